@@ -0,0 +1,141 @@
+//! Reply encoding and pipelining: allocations and time to encode a large array reply on
+//! its own and into a reused buffer, and the rate of pipelined commands against a server
+//! process, which answers each batch it reads with one write.
+//! Run with `cargo bench --bench encode`.
+use codecrafters_redis::resp::Frame;
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::hint::black_box;
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::process::{Child, Command, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+/// The system allocator, counting the allocations made through it
+struct Counting;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        unsafe { System.alloc(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { System.dealloc(ptr, layout) }
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        unsafe { System.realloc(ptr, layout, new_size) }
+    }
+}
+
+#[global_allocator]
+static GLOBAL: Counting = Counting;
+
+/// Run f repeatedly and report its time and allocations per run
+fn measure(name: &str, iterations: usize, mut f: impl FnMut()) {
+    for _ in 0..iterations / 10 {
+        f();
+    }
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed);
+    let started = Instant::now();
+    for _ in 0..iterations {
+        f();
+    }
+    let elapsed = started.elapsed();
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed) - allocations;
+    println!(
+        "{:<40} {:>10.1} µs/iter {:>10.1} allocations/iter",
+        name,
+        elapsed.as_secs_f64() * 1e6 / iterations as f64,
+        allocations as f64 / iterations as f64
+    );
+}
+
+/// A server process on a free port, killed when dropped
+struct Server(Child, u16);
+
+impl Server {
+    fn start() -> Server {
+        let port = TcpListener::bind("127.0.0.1:0")
+            .and_then(|listener| listener.local_addr())
+            .unwrap()
+            .port();
+        let child = Command::new(env!("CARGO_BIN_EXE_codecrafters-redis"))
+            .args(["--port", &port.to_string(), "--save", ""])
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .unwrap();
+        let started = Instant::now();
+        while TcpStream::connect(("127.0.0.1", port)).is_err() {
+            assert!(
+                started.elapsed() < Duration::from_secs(10),
+                "server didn't start"
+            );
+            std::thread::sleep(Duration::from_millis(20));
+        }
+        Server(child, port)
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = self.0.kill();
+        let _ = self.0.wait();
+    }
+}
+
+/// Send SETs in batches of depth and report how many are answered per second
+fn pipeline(server: &Server, depth: usize, total: usize) {
+    let mut stream = TcpStream::connect(("127.0.0.1", server.1)).unwrap();
+    stream.set_nodelay(true).unwrap();
+    let mut batch = Vec::new();
+    for i in 0..depth {
+        batch.extend(
+            Frame::Array(Some(vec![
+                Frame::BulkString(Some(b"SET".to_vec())),
+                Frame::BulkString(Some(format!("bench:{}", i).into_bytes())),
+                Frame::BulkString(Some(b"value".to_vec())),
+            ]))
+            .encode(),
+        );
+    }
+    let mut replies = vec![0u8; depth * b"+OK\r\n".len()];
+    let started = Instant::now();
+    for _ in 0..total / depth {
+        stream.write_all(&batch).unwrap();
+        stream.read_exact(&mut replies).unwrap();
+    }
+    let elapsed = started.elapsed();
+    println!(
+        "{:<40} {:>10.0} commands/s",
+        format!("pipelined SET, {} per batch", depth),
+        (total / depth * depth) as f64 / elapsed.as_secs_f64()
+    );
+}
+
+fn main() {
+    let reply = Frame::Array(Some(
+        (0..10_000)
+            .map(|i| Frame::BulkString(Some(format!("element:{}", i).into_bytes())))
+            .collect(),
+    ));
+    measure("encode a 10k element array", 200, || {
+        black_box(reply.encode());
+    });
+    let mut out = Vec::new();
+    measure("encode_into a reused buffer", 200, || {
+        out.clear();
+        reply.encode_into(&mut out);
+        black_box(&out);
+    });
+
+    let server = Server::start();
+    for depth in [1, 16, 128] {
+        pipeline(&server, depth, 50_000);
+    }
+}
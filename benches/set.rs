@@ -0,0 +1,57 @@
+//! SET throughput under contention: tasks on a multi-threaded runtime each run SETs,
+//! with and without a TTL, through their own socketless session, so they contend on
+//! the keyspace locks rather than on sockets. With appendonly off and no replicas,
+//! writes only share the commands' WRITES lock, so what more tasks show is how long
+//! db::set holds the keyspace; the one-task figure is the baseline they compare against.
+//! Run with `cargo bench --bench set`.
+use codecrafters_redis::Session;
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use std::time::{Duration, Instant};
+
+/// Run `sets` SETs from each of `tasks` tasks at once, and return how long they took
+async fn contend(tasks: usize, ttl: bool, sets: u64) -> Duration {
+    let started = Instant::now();
+    let handles: Vec<_> = (0..tasks)
+        .map(|task| {
+            tokio::spawn(async move {
+                let mut session = Session::new();
+                for i in 0..sets {
+                    let key = format!("bench:{}:{}", task, i % 1_000);
+                    let reply = if ttl {
+                        session.call(&["set", &key, "value", "PX", "60000"]).await
+                    } else {
+                        session.call(&["set", &key, "value"]).await
+                    };
+                    assert_eq!(reply, b"+OK\r\n");
+                }
+            })
+        })
+        .collect();
+    for handle in handles {
+        handle.await.unwrap();
+    }
+    started.elapsed()
+}
+
+fn set_under_contention(c: &mut Criterion) {
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .worker_threads(4)
+        .enable_all()
+        .build()
+        .unwrap();
+    let mut group = c.benchmark_group("set");
+    for ttl in [false, true] {
+        for tasks in [1, 4, 16] {
+            // An iteration is one SET from every task
+            group.throughput(Throughput::Elements(tasks as u64));
+            let name = if ttl { "with PX" } else { "plain" };
+            group.bench_with_input(BenchmarkId::new(name, tasks), &tasks, |b, &tasks| {
+                b.iter_custom(|iters| runtime.block_on(contend(tasks, ttl, iters)))
+            });
+        }
+    }
+    group.finish();
+}
+
+criterion_group!(benches, set_under_contention);
+criterion_main!(benches);
@@ -0,0 +1,268 @@
+//! The append-only file: while `appendonly` is enabled, every write that changed the
+//! dataset is appended to `appendfilename` in `dir`, in the form replicas get it,
+//! so replaying the file in order rebuilds the same dataset. The file starts with an
+//! RDB image of the dataset as it was when the file was created or appendonly was
+//! enabled, like Redis's aof-use-rdb-preamble, so it holds the writes made before as
+//! well. Writes are handed over in the order they were applied to a thread that owns
+//! the file, so the async workers never wait on the disk; at startup the file is
+//! replayed instead of loading the RDB file.
+use crate::commands;
+use crate::config;
+use crate::db;
+use crate::model::client_state::ClientState;
+use crate::rdb;
+use crate::resp::parser::FrameParser;
+use crate::resp::Frame;
+use once_cell::sync::Lazy;
+use std::fs::{File, OpenOptions};
+use std::io::{Cursor, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::Mutex;
+use tokio::sync::oneshot;
+
+/// What the writer thread is asked to do, in order
+enum Job {
+    Append {
+        path: PathBuf,
+        db: usize, // database the command ran in
+        command: Vec<u8>,
+    },
+    Close,
+    Sync(oneshot::Sender<()>), // reply once everything before it is on disk
+    Rewrite {
+        path: PathBuf,
+        base: Vec<u8>, // RDB image of the dataset, which the file starts over with
+        done: oneshot::Sender<Result<(), String>>,
+    },
+}
+
+/// The file being appended to, created by a rewrite when appendonly is enabled
+struct AppendFile {
+    path: PathBuf,
+    file: File,
+    db: usize, // database of the last command written, so SELECT is only written when it changes
+}
+
+/// Where jobs go, with whether the writer has a file open, so a disabled appendonly
+/// only costs a Close once
+static WRITER: Lazy<Mutex<(Sender<Job>, bool)>> = Lazy::new(|| {
+    let (tx, rx) = mpsc::channel();
+    std::thread::Builder::new()
+        .name("aof-writer".to_string())
+        .spawn(move || write_jobs(rx))
+        .expect("failed to start the append only file writer");
+    Mutex::new((tx, false))
+});
+
+/// Set while the file is being replayed, whose commands mustn't be appended to it again
+static LOADING: AtomicBool = AtomicBool::new(false);
+
+/// Append a write command, run in the current database, if appendonly is enabled.
+/// Callers hold WRITES exclusively, so commands are queued in the order they were applied.
+pub fn feed(command: &[Frame]) {
+    if LOADING.load(Ordering::Relaxed) {
+        return;
+    }
+    let mut writer = WRITER.lock().unwrap();
+    let (jobs, open) = &mut *writer;
+    if !config::get_appendonly() {
+        // Close the file, so enabling appendonly again picks up dir and appendfilename
+        if std::mem::take(open) {
+            let _ = jobs.send(Job::Close);
+        }
+        return;
+    }
+    let mut bytes = Vec::new();
+    Frame::Array(Some(command.to_vec())).encode_into(&mut bytes);
+    *open = true;
+    let _ = jobs.send(Job::Append {
+        path: config::get_aof_path(),
+        db: db::current_db(),
+        command: bytes,
+    });
+}
+
+/// Start the file over with an RDB image of the dataset, for when appendonly is
+/// enabled or the file doesn't exist yet. Writes are held off until the image is
+/// queued, so each one is either in it or appended after it.
+pub async fn rewrite() -> Result<(), String> {
+    let _writes = commands::hold_off_writes().await;
+    let (base, _) = rdb::snapshot().await?;
+    let (tx, rx) = oneshot::channel();
+    {
+        let mut writer = WRITER.lock().unwrap();
+        writer.1 = true;
+        let _ = writer.0.send(Job::Rewrite {
+            path: config::get_aof_path(),
+            base,
+            done: tx,
+        });
+    }
+    rx.await
+        .unwrap_or_else(|_| Err("the append only file writer stopped".to_string()))
+}
+
+/// Wait until every write appended so far has been written and synced to disk,
+/// for WAITAOF
+pub async fn synced() {
+    let (tx, rx) = oneshot::channel();
+    let _ = WRITER.lock().unwrap().0.send(Job::Sync(tx));
+    let _ = rx.await;
+}
+
+/// The writer thread: appends commands to the file, opening it, or another one when
+/// dir or appendfilename change, as needed
+fn write_jobs(jobs: Receiver<Job>) {
+    let mut aof: Option<AppendFile> = None;
+    for job in jobs {
+        match job {
+            Job::Append { path, db, command } => append(&mut aof, path, db, &command),
+            Job::Close => aof = None,
+            Job::Rewrite { path, base, done } => {
+                let result = start_over(&path, &base);
+                aof = match &result {
+                    Ok(file) => file.try_clone().ok().map(|file| AppendFile {
+                        path,
+                        file,
+                        db: usize::MAX,
+                    }),
+                    Err(_) => None,
+                };
+                let _ = done.send(result.map(|_| ()));
+            }
+            Job::Sync(done) => {
+                if let Some(open) = &aof {
+                    if let Err(e) = open.file.sync_data() {
+                        warn!(
+                            "Failed to sync the append only file {}: {}",
+                            open.path.display(),
+                            e
+                        );
+                    }
+                }
+                let _ = done.send(());
+            }
+        }
+    }
+}
+
+/// Replace the file at `path` with one holding just `base`, through a temporary file so
+/// a crash midway leaves the old one, and open it for appending
+fn start_over(path: &Path, base: &[u8]) -> Result<File, String> {
+    let temp = path.with_extension("tmp");
+    let failed = |e: std::io::Error| {
+        format!(
+            "Failed to rewrite the append only file {}: {}",
+            path.display(),
+            e
+        )
+    };
+    let mut file = File::create(&temp).map_err(failed)?;
+    file.write_all(base).map_err(failed)?;
+    file.sync_data().map_err(failed)?;
+    std::fs::rename(&temp, path).map_err(failed)?;
+    OpenOptions::new().append(true).open(path).map_err(failed)
+}
+
+fn append(aof: &mut Option<AppendFile>, path: PathBuf, db: usize, command: &[u8]) {
+    if aof.as_ref().is_none_or(|open| open.path != path) {
+        match OpenOptions::new().create(true).append(true).open(&path) {
+            Ok(file) => {
+                *aof = Some(AppendFile {
+                    path,
+                    file,
+                    db: usize::MAX,
+                })
+            }
+            Err(e) => {
+                warn!(
+                    "Failed to open the append only file {}: {}",
+                    path.display(),
+                    e
+                );
+                *aof = None;
+                return;
+            }
+        }
+    }
+    let Some(open) = aof.as_mut() else {
+        return;
+    };
+    let mut bytes = Vec::new();
+    if open.db != db {
+        Frame::Array(Some(vec![
+            Frame::BulkString(Some(b"SELECT".to_vec())),
+            Frame::BulkString(Some(db.to_string().into_bytes())),
+        ]))
+        .encode_into(&mut bytes);
+    }
+    bytes.extend_from_slice(command);
+    match open.file.write_all(&bytes) {
+        Ok(()) => open.db = db,
+        Err(e) => {
+            // Start over with a fresh handle, and a SELECT, on the next write
+            warn!(
+                "Failed to write to the append only file {}: {}",
+                open.path.display(),
+                e
+            );
+            *aof = None;
+        }
+    }
+}
+
+/// Rebuild the dataset by loading the RDB image the append only file starts with, if it
+/// has one, and running the commands after it, returning how many there were.
+/// A command cut short at the end, as a crash mid-write leaves it, is left out,
+/// like Redis does with aof-load-truncated.
+pub async fn load() -> Result<usize, String> {
+    let path = config::get_aof_path();
+    let bytes = tokio::fs::read(&path).await.map_err(|e| e.to_string())?;
+    let mut commands_start = 0;
+    if bytes.starts_with(b"REDIS") {
+        let mut reader = Cursor::new(&bytes[..]);
+        let base = rdb::RdbParser::load_from_reader(&mut reader)
+            .map_err(|e| format!("Bad RDB preamble: {}", e))?;
+        let keys = base.data.len();
+        db::load_from_rdb(base).await?;
+        info!("Loaded {} keys from the RDB preamble", keys);
+        commands_start = reader.position() as usize;
+    }
+    let mut parser = FrameParser::new();
+    parser.feed(&bytes[commands_start..]);
+    let mut state = ClientState::new();
+    state.addr = "aof".to_string();
+    state.authenticated = true;
+    // Applied like a master's stream, which a replica doesn't refuse or pass on
+    state.master_link = true;
+
+    LOADING.store(true, Ordering::Relaxed);
+    let mut loaded = 0;
+    let result = loop {
+        match parser.parse() {
+            Ok(Some(frame)) => {
+                let reply = commands::dispatch(frame, &mut state).await.reply;
+                if reply.starts_with(b"-") {
+                    warn!(
+                        "Command {} of the append only file failed: {}",
+                        loaded + 1,
+                        String::from_utf8_lossy(&reply).trim_end()
+                    );
+                }
+                loaded += 1;
+            }
+            Ok(None) => break Ok(loaded),
+            Err(e) => break Err(format!("Bad file format after {} commands: {}", loaded, e)),
+        }
+    };
+    LOADING.store(false, Ordering::Relaxed);
+    if parser.buffered() > 0 {
+        warn!(
+            "The append only file {} ends with a truncated command, which was left out",
+            path.display()
+        );
+    }
+    result
+}
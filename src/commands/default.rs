@@ -1,11 +1,40 @@
+use super::registry;
+use super::{hold_off_writes, prevent_propagation, propagate, serialise};
+use crate::aof;
 use crate::config;
 use crate::db;
+use crate::geo;
+use crate::model::client_state::ClientState;
+use crate::model::redis_value::{RedisValue, StreamEntry, StreamId};
+use crate::monitor;
+use crate::pubsub;
 use crate::rdb;
+use crate::replication;
+use crate::resp::types::format_double;
 use crate::resp::Frame;
+use crate::server;
+use crate::sha256;
+use crate::slowlog;
+use crate::stats;
 
-/// Ping command just returns "PONG" as a simple string.
-pub async fn ping(_args: Vec<Frame>) -> Vec<u8> {
-    Frame::SimpleString("PONG".into()).encode()
+/// Redis version this server reports itself as compatible with
+const REDIS_VERSION: &str = "7.2.0";
+
+/// Ping command returns "PONG" as a simple string, or echoes back
+/// its single optional argument as a bulk string.
+/// A subscribed RESP2 connection gets a ["pong", message] array instead.
+pub async fn ping(args: Vec<Frame>, state: &mut ClientState) -> Vec<u8> {
+    let subscribed = state.subscriber_count() > 0 && !state.resp3();
+    match args.as_slice() {
+        [] if subscribed => bulk_array(vec![b"pong".to_vec(), Vec::new()]),
+        [Frame::BulkString(Some(msg))] if subscribed => {
+            bulk_array(vec![b"pong".to_vec(), msg.clone()])
+        }
+        [] => Frame::SimpleString("PONG".into()).encode(),
+        [Frame::BulkString(Some(msg))] => Frame::BulkString(Some(msg.clone())).encode(),
+        [_] => Frame::Error("ERR invalid argument for 'ping'".into()).encode(),
+        _ => Frame::Error("ERR wrong number of arguments for 'ping'".into()).encode(),
+    }
 }
 
 /// Echo command returns the same bulk string passed to it
@@ -26,6 +55,7 @@ pub async fn echo(args: Vec<Frame>) -> Vec<u8> {
 
 /// Set command parses arguments and performs error checking before delegating to db::set.
 /// It expects at least 2 arguments: key and value (both BulkString).
+/// Replies nil when NX or XX kept the key from being set.
 pub async fn set(args: Vec<Frame>) -> Vec<u8> {
     if args.len() < 2 {
         return Frame::Error("ERR wrong number of arguments for 'set'".into()).encode();
@@ -44,61 +74,39 @@ pub async fn set(args: Vec<Frame>) -> Vec<u8> {
     };
 
     // Parse options
-    let mut ex: Option<u64> = None;
-    let mut px: Option<u64> = None;
+    let mut expire_at: Option<i64> = None;
+    let mut keep_ttl = false;
     let mut nx = false;
     let mut xx = false;
     let mut i = 2;
     while i < args.len() {
+        let unit = match &args[i] {
+            Frame::BulkString(Some(opt)) => expire_unit(opt),
+            _ => None,
+        };
         match &args[i] {
-            Frame::BulkString(Some(opt)) if opt.eq_ignore_ascii_case(b"EX") => {
-                if i + 1 >= args.len() {
-                    return Frame::Error("ERR syntax error: EX requires seconds".into()).encode();
-                }
-                match &args[i + 1] {
-                    Frame::BulkString(Some(sec)) => {
-                        let sec_str = String::from_utf8_lossy(sec);
-                        match sec_str.parse::<u64>() {
-                            Ok(sec_val) if sec_val > 0 => ex = Some(sec_val),
-                            _ => {
-                                return Frame::Error(
-                                    "ERR EX value must be a positive integer".into(),
-                                )
-                                .encode();
-                            }
-                        }
-                    }
-                    _ => {
-                        return Frame::Error("ERR EX value must be a positive integer".into())
-                            .encode();
-                    }
+            // EX, PX, EXAT or PXAT, of which only one may be given
+            _ if unit.is_some() => {
+                let Some(time) = args.get(i + 1).filter(|_| !keep_ttl && expire_at.is_none())
+                else {
+                    return Frame::Error("ERR syntax error".into()).encode();
+                };
+                let time = match parse_integer(time) {
+                    Ok(time) => time,
+                    Err(e) => return e,
+                };
+                match db::validate_expire("set", time, unit.unwrap()) {
+                    Ok(at) => expire_at = Some(at),
+                    Err(e) => return Frame::Error(e).encode(),
                 }
                 i += 2;
             }
-            Frame::BulkString(Some(opt)) if opt.eq_ignore_ascii_case(b"PX") => {
-                if i + 1 >= args.len() {
-                    return Frame::Error("ERR syntax error: PX requires milliseconds".into())
-                        .encode();
-                }
-                match &args[i + 1] {
-                    Frame::BulkString(Some(ms)) => {
-                        let ms_str = String::from_utf8_lossy(ms);
-                        match ms_str.parse::<u64>() {
-                            Ok(ms_val) if ms_val > 0 => px = Some(ms_val),
-                            _ => {
-                                return Frame::Error(
-                                    "ERR PX value must be a positive integer".into(),
-                                )
-                                .encode();
-                            }
-                        }
-                    }
-                    _ => {
-                        return Frame::Error("ERR PX value must be a positive integer".into())
-                            .encode();
-                    }
+            Frame::BulkString(Some(opt)) if opt.eq_ignore_ascii_case(b"KEEPTTL") => {
+                if expire_at.is_some() {
+                    return Frame::Error("ERR syntax error".into()).encode();
                 }
-                i += 2;
+                keep_ttl = true;
+                i += 1;
             }
             Frame::BulkString(Some(opt)) if opt.eq_ignore_ascii_case(b"NX") => {
                 nx = true;
@@ -115,12 +123,405 @@ pub async fn set(args: Vec<Frame>) -> Vec<u8> {
     }
 
     // Delegate to db::set with options
-    match db::set(key, value, ex, px, nx, xx).await {
-        Ok(()) => Frame::SimpleString("OK".into()).encode(),
+    if expire_at.is_some() {
+        prevent_propagation();
+    }
+    match db::set(key.clone(), value.clone(), expire_at, keep_ttl, nx, xx).await {
+        Ok(true) => {
+            // A relative TTL is sent as the time it came to, so replaying it later
+            // doesn't push the expiry back
+            if let Some(at) = expire_at {
+                let mut command = vec![arg("SET"), arg(key), arg(value), arg("PXAT")];
+                command.push(arg(at.to_string()));
+                command.extend(nx.then(|| arg("NX")));
+                command.extend(xx.then(|| arg("XX")));
+                propagate(command);
+            }
+            Frame::SimpleString("OK".into()).encode()
+        }
+        Ok(false) => {
+            prevent_propagation();
+            Frame::BulkString(None).encode()
+        }
+        Err(e) => Frame::Error(format!("ERR {}", e)).encode(),
+    }
+}
+
+/// DEL command deletes keys of any type and returns how many existed.
+/// UNLINK is the same, as values are always freed synchronously here.
+/// It expects one or more keys.
+pub async fn del(args: Vec<Frame>, cmd: &str) -> Vec<u8> {
+    let keys = match bulk_args(args, cmd) {
+        Ok(parts) if !parts.is_empty() => parts,
+        Ok(_) => {
+            return Frame::Error(format!("ERR wrong number of arguments for '{}'", cmd)).encode()
+        }
+        Err(e) => return e,
+    };
+    let keys: Vec<String> = keys
+        .iter()
+        .map(|k| String::from_utf8_lossy(k).to_string())
+        .collect();
+    let deleted = db::del(&keys).await;
+    if deleted == 0 {
+        prevent_propagation();
+    }
+    Frame::Integer(deleted).encode()
+}
+
+/// GETDEL command returns the string at a key and deletes the key.
+/// It expects a single argument which is the key.
+pub async fn getdel(args: Vec<Frame>) -> Vec<u8> {
+    let [Frame::BulkString(Some(key))] = args.as_slice() else {
+        return Frame::Error("ERR wrong number of arguments for 'getdel'".into()).encode();
+    };
+    match db::getdel(&String::from_utf8_lossy(key)).await {
+        Ok(None) => {
+            prevent_propagation();
+            Frame::BulkString(None).encode()
+        }
+        Ok(value) => Frame::BulkString(value).encode(),
+        Err(e) => Frame::Error(e).encode(),
+    }
+}
+
+/// GETSET command sets a key to a string and returns the previous string, or nil.
+/// It expects a key and a value.
+pub async fn getset(args: Vec<Frame>) -> Vec<u8> {
+    let [Frame::BulkString(Some(key)), Frame::BulkString(Some(value))] = args.as_slice() else {
+        return Frame::Error("ERR wrong number of arguments for 'getset'".into()).encode();
+    };
+    match db::getset(&String::from_utf8_lossy(key), value.clone()).await {
+        Ok(previous) => Frame::BulkString(previous).encode(),
+        Err(e) => Frame::Error(e).encode(),
+    }
+}
+
+/// GETEX command returns the string at a key and optionally changes its TTL.
+/// It expects a key and at most one of EX/PX/EXAT/PXAT <time> or PERSIST.
+pub async fn getex(args: Vec<Frame>) -> Vec<u8> {
+    let key = match args.first() {
+        Some(Frame::BulkString(Some(bs))) => String::from_utf8_lossy(bs).to_string(),
+        Some(_) => return Frame::Error("ERR invalid key for 'getex'".into()).encode(),
+        None => return Frame::Error("ERR wrong number of arguments for 'getex'".into()).encode(),
+    };
+    let expiry = match &args[1..] {
+        [] => db::GetexExpiry::Keep,
+        [Frame::BulkString(Some(opt))] if opt.eq_ignore_ascii_case(b"PERSIST") => {
+            db::GetexExpiry::Persist
+        }
+        [Frame::BulkString(Some(opt)), time] => {
+            let Some(unit) = expire_unit(opt) else {
+                return Frame::Error("ERR syntax error".into()).encode();
+            };
+            let time = match parse_integer(time) {
+                Ok(time) => time,
+                Err(e) => return e,
+            };
+            match db::validate_expire("getex", time, unit) {
+                Ok(at) => db::GetexExpiry::AtUnixMs(at),
+                Err(e) => return Frame::Error(e).encode(),
+            }
+        }
+        _ => return Frame::Error("ERR syntax error".into()).encode(),
+    };
+    // Without an option GETEX is a read, and a new TTL is sent as the time it came to
+    if expiry != db::GetexExpiry::Persist {
+        prevent_propagation();
+    }
+    match db::getex(&key, expiry).await {
+        Ok(value) => {
+            match expiry {
+                db::GetexExpiry::AtUnixMs(at) if value.is_some() => {
+                    propagate(vec![arg("PEXPIREAT"), arg(key), arg(at.to_string())]);
+                }
+                db::GetexExpiry::Persist if value.is_none() => prevent_propagation(),
+                _ => {}
+            }
+            Frame::BulkString(value).encode()
+        }
+        Err(e) => Frame::Error(e).encode(),
+    }
+}
+
+/// SETEX and PSETEX commands set a string with a TTL in seconds or milliseconds.
+/// They expect a key, a positive TTL and a value.
+pub async fn setex(args: Vec<Frame>, cmd: &str) -> Vec<u8> {
+    let [Frame::BulkString(Some(key)), time, Frame::BulkString(Some(value))] = args.as_slice()
+    else {
+        return Frame::Error(format!("ERR wrong number of arguments for '{}'", cmd)).encode();
+    };
+    let unit = if cmd == "setex" {
+        db::ExpireUnit::Seconds
+    } else {
+        db::ExpireUnit::Millis
+    };
+    let expire_at = match parse_integer(time).map(|time| db::validate_expire(cmd, time, unit)) {
+        Ok(Ok(at)) => at,
+        Ok(Err(e)) => return Frame::Error(e).encode(),
+        Err(e) => return e,
+    };
+    prevent_propagation();
+    match db::set(
+        key.clone(),
+        value.clone(),
+        Some(expire_at),
+        false,
+        false,
+        false,
+    )
+    .await
+    {
+        Ok(_) => {
+            propagate(vec![
+                arg("SET"),
+                arg(key.clone()),
+                arg(value.clone()),
+                arg("PXAT"),
+                arg(expire_at.to_string()),
+            ]);
+            Frame::SimpleString("OK".into()).encode()
+        }
         Err(e) => Frame::Error(format!("ERR {}", e)).encode(),
     }
 }
 
+/// EXPIRE, PEXPIRE, EXPIREAT and PEXPIREAT commands set the TTL of a key, relative in
+/// seconds or milliseconds or as a Unix time. A time in the past deletes the key.
+/// They expect a key, a time and optional NX/XX/GT/LT conditions, and return 1 if
+/// the TTL was set, or 0 if the key doesn't exist or the condition wasn't met.
+pub async fn expire(args: Vec<Frame>, cmd: &str) -> Vec<u8> {
+    let (key, time, options) = match args.as_slice() {
+        [Frame::BulkString(Some(key)), time, options @ ..] => (key, time, options),
+        _ => return Frame::Error(format!("ERR wrong number of arguments for '{}'", cmd)).encode(),
+    };
+
+    // Parse options, which may be repeated and combined as long as they agree
+    let (mut nx, mut xx, mut gt, mut lt) = (false, false, false, false);
+    for option in options {
+        let Frame::BulkString(Some(option)) = option else {
+            return Frame::Error("ERR syntax error".into()).encode();
+        };
+        match option.to_ascii_lowercase().as_slice() {
+            b"nx" => nx = true,
+            b"xx" => xx = true,
+            b"gt" => gt = true,
+            b"lt" => lt = true,
+            _ => {
+                return Frame::Error(format!(
+                    "ERR Unsupported option {}",
+                    String::from_utf8_lossy(option)
+                ))
+                .encode()
+            }
+        }
+    }
+    if nx && (xx || gt || lt) {
+        return Frame::Error(
+            "ERR NX and XX, GT or LT options at the same time are not compatible".into(),
+        )
+        .encode();
+    }
+    if gt && lt {
+        return Frame::Error("ERR GT and LT options at the same time are not compatible".into())
+            .encode();
+    }
+    let condition = match (nx, xx, gt, lt) {
+        (true, ..) => db::ExpireCondition::Nx,
+        // GT already needs a TTL to compare against
+        (_, _, true, _) => db::ExpireCondition::Gt,
+        (_, true, _, true) => db::ExpireCondition::XxLt,
+        (_, _, _, true) => db::ExpireCondition::Lt,
+        (_, true, ..) => db::ExpireCondition::Xx,
+        _ => db::ExpireCondition::Always,
+    };
+    let unit = match cmd {
+        "expire" => db::ExpireUnit::Seconds,
+        "pexpire" => db::ExpireUnit::Millis,
+        "expireat" => db::ExpireUnit::UnixSeconds,
+        _ => db::ExpireUnit::UnixMillis,
+    };
+    let expire_at = match parse_integer(time).map(|time| db::expire_deadline(cmd, time, unit)) {
+        Ok(Ok(at)) => at,
+        Ok(Err(e)) => return Frame::Error(e).encode(),
+        Err(e) => return e,
+    };
+    // Sent as the time the TTL came to, which deletes the key again if it has passed
+    prevent_propagation();
+    let updated = db::expire(&String::from_utf8_lossy(key), expire_at, condition).await;
+    if updated {
+        propagate(vec![
+            arg("PEXPIREAT"),
+            arg(key.clone()),
+            arg(expire_at.to_string()),
+        ]);
+    }
+    Frame::Integer(updated as i64).encode()
+}
+
+/// INCR/DECR/INCRBY/DECRBY commands add to the integer stored at a key and return the result.
+/// INCR and DECR expect a key; INCRBY and DECRBY also expect the amount.
+pub async fn incr(args: Vec<Frame>, cmd: &str) -> Vec<u8> {
+    let by_amount = cmd.ends_with("by");
+    if args.len() != if by_amount { 2 } else { 1 } {
+        return Frame::Error(format!("ERR wrong number of arguments for '{}'", cmd)).encode();
+    }
+    let key = match &args[0] {
+        Frame::BulkString(Some(bs)) => String::from_utf8_lossy(bs).to_string(),
+        _ => return Frame::Error(format!("ERR invalid key for '{}'", cmd)).encode(),
+    };
+    let amount = if by_amount {
+        match parse_integer(&args[1]) {
+            Ok(n) => n,
+            Err(e) => return e,
+        }
+    } else {
+        1
+    };
+    let delta = if cmd.starts_with("decr") {
+        match amount.checked_neg() {
+            Some(delta) => delta,
+            None => {
+                return Frame::Error("ERR decrement would overflow".into()).encode();
+            }
+        }
+    } else {
+        amount
+    };
+    match db::incr_by(&key, delta).await {
+        Ok(value) => Frame::Integer(value).encode(),
+        Err(e) => Frame::Error(e).encode(),
+    }
+}
+
+/// INCRBYFLOAT command adds a floating point increment to the number stored at
+/// a key, creating it at 0 if needed, and returns the new value as a bulk string.
+/// It expects a key and an increment, which may use exponent notation.
+pub async fn incrbyfloat(args: Vec<Frame>) -> Vec<u8> {
+    let [Frame::BulkString(Some(key)), increment] = args.as_slice() else {
+        return Frame::Error("ERR wrong number of arguments for 'incrbyfloat'".into()).encode();
+    };
+    let increment = match parse_score(increment) {
+        Ok(increment) => increment,
+        Err(e) => return e,
+    };
+    // Sent as the value it came to, as float formatting and rounding may differ elsewhere
+    prevent_propagation();
+    match db::incr_by_float(&String::from_utf8_lossy(key), increment).await {
+        Ok(value) => {
+            propagate(vec![
+                arg("SET"),
+                arg(key.clone()),
+                arg(value.clone()),
+                arg("KEEPTTL"),
+            ]);
+            Frame::BulkString(Some(value)).encode()
+        }
+        Err(e) => Frame::Error(e).encode(),
+    }
+}
+
+/// PFADD command adds elements to a HyperLogLog, creating it if needed, and
+/// returns 1 if its estimated cardinality may have changed, 0 otherwise.
+/// It expects a key followed by zero or more elements.
+pub async fn pfadd(args: Vec<Frame>) -> Vec<u8> {
+    let mut parts = match bulk_args(args, "pfadd") {
+        Ok(parts) => parts,
+        Err(e) => return e,
+    };
+    if parts.is_empty() {
+        return Frame::Error("ERR wrong number of arguments for 'pfadd'".into()).encode();
+    }
+    let key = String::from_utf8_lossy(&parts.remove(0)).to_string();
+    match db::pfadd(&key, &parts).await {
+        Ok(changed) => {
+            if !changed {
+                prevent_propagation();
+            }
+            Frame::Integer(i64::from(changed)).encode()
+        }
+        Err(e) => Frame::Error(e).encode(),
+    }
+}
+
+/// PFCOUNT command returns the estimated cardinality of a HyperLogLog, or of the
+/// union of several. It expects one or more keys.
+pub async fn pfcount(args: Vec<Frame>) -> Vec<u8> {
+    let keys = match bulk_args(args, "pfcount") {
+        Ok(parts) if !parts.is_empty() => parts,
+        Ok(_) => {
+            return Frame::Error("ERR wrong number of arguments for 'pfcount'".into()).encode()
+        }
+        Err(e) => return e,
+    };
+    let keys: Vec<String> = keys
+        .iter()
+        .map(|k| String::from_utf8_lossy(k).to_string())
+        .collect();
+    match db::pfcount(&keys).await {
+        Ok(count) => Frame::Integer(count as i64).encode(),
+        Err(e) => Frame::Error(e).encode(),
+    }
+}
+
+/// PFMERGE command stores the union of several HyperLogLogs, including the
+/// destination's own, in the destination. It expects a destination and source keys.
+pub async fn pfmerge(args: Vec<Frame>) -> Vec<u8> {
+    let keys = match bulk_args(args, "pfmerge") {
+        Ok(parts) if !parts.is_empty() => parts,
+        Ok(_) => {
+            return Frame::Error("ERR wrong number of arguments for 'pfmerge'".into()).encode()
+        }
+        Err(e) => return e,
+    };
+    let mut keys: Vec<String> = keys
+        .iter()
+        .map(|k| String::from_utf8_lossy(k).to_string())
+        .collect();
+    let dest = keys.remove(0);
+    match db::pfmerge(&dest, &keys).await {
+        Ok(()) => Frame::SimpleString("OK".into()).encode(),
+        Err(e) => Frame::Error(e).encode(),
+    }
+}
+
+/// MSET command sets several keys to string values in one step.
+/// It expects key/value pairs.
+pub async fn mset(args: Vec<Frame>) -> Vec<u8> {
+    if args.len() < 2 || !args.len().is_multiple_of(2) {
+        return Frame::Error("ERR wrong number of arguments for 'mset'".into()).encode();
+    }
+    let mut pairs = Vec::with_capacity(args.len() / 2);
+    let mut args = args.into_iter();
+    while let (Some(key), Some(value)) = (args.next(), args.next()) {
+        match (key, value) {
+            (Frame::BulkString(Some(key)), Frame::BulkString(Some(value))) => {
+                pairs.push((String::from_utf8_lossy(&key).to_string(), value))
+            }
+            _ => return Frame::Error("ERR invalid argument for 'mset'".into()).encode(),
+        }
+    }
+    db::mset(pairs).await;
+    Frame::SimpleString("OK".into()).encode()
+}
+
+/// MGET command returns the values of several keys, in order, with nil for every key
+/// that is missing, expired or not a string.
+/// It expects one or more keys.
+pub async fn mget(args: Vec<Frame>) -> Vec<u8> {
+    let keys = match bulk_args(args, "mget") {
+        Ok(keys) if !keys.is_empty() => keys,
+        Ok(_) => return Frame::Error("ERR wrong number of arguments for 'mget'".into()).encode(),
+        Err(e) => return e,
+    };
+    let mut values = Vec::with_capacity(keys.len());
+    for key in keys {
+        let value = db::get_string_or_none(&String::from_utf8_lossy(&key)).await;
+        values.push(Frame::BulkString(value));
+    }
+    Frame::Array(Some(values)).encode()
+}
+
 /// Get command retrieves a value by key, checking for expiration.
 /// It expects a single argument which is the key (BulkString).
 pub async fn get(args: Vec<Frame>) -> Vec<u8> {
@@ -134,24 +535,49 @@ pub async fn get(args: Vec<Frame>) -> Vec<u8> {
     db::get(key).await
 }
 
+/// EXPIRETIME/PEXPIRETIME commands return the Unix time (in seconds, or milliseconds
+/// when `millis` is set) at which a key expires, -1 if it has no TTL or -2 if it doesn't exist.
+/// It expects a single argument which is the key.
+pub async fn expiretime(args: Vec<Frame>, millis: bool) -> Vec<u8> {
+    let cmd = if millis { "pexpiretime" } else { "expiretime" };
+    if args.len() != 1 {
+        return Frame::Error(format!("ERR wrong number of arguments for '{}'", cmd)).encode();
+    }
+    let key = match &args[0] {
+        Frame::BulkString(Some(bs)) => String::from_utf8_lossy(bs).to_string(),
+        _ => return Frame::Error(format!("ERR invalid key for '{}'", cmd)).encode(),
+    };
+    let at = db::expiretime_ms(&key).await;
+    if at < 0 || millis {
+        Frame::Integer(at).encode()
+    } else {
+        Frame::Integer(at / 1000).encode()
+    }
+}
+
 /// KEYS command returns all the keys that match a given pattern, as a RESP array.
 /// It expects the pattern as a single argument.
+/// KEYS still walks the whole keyspace in one go; SCAN is the way to do it without
+/// building one huge reply.
 pub async fn keys(args: Vec<Frame>) -> Vec<u8> {
     if args.len() != 1 {
         return Frame::Error("ERR wrong number of arguments for 'keys'".into()).encode();
     }
     let pattern = match &args[0] {
-        Frame::BulkString(Some(bs)) => String::from_utf8_lossy(bs).to_string(),
+        Frame::BulkString(Some(bs)) => bs,
         _ => return Frame::Error("ERR invalid pattern for 'keys'".into()).encode(),
     };
 
-    let keys = db::get_keys_matching_pattern(&pattern).await;
-    let resp = Frame::Array(Some(
-        keys.into_iter()
-            .map(|k| Frame::BulkString(Some(k.into_bytes())))
-            .collect(),
-    ));
-    resp.encode()
+    let keys = db::get_keys_matching_pattern(pattern).await;
+    // Encode straight into a buffer sized for the reply rather than through an array frame:
+    // each key takes its bytes plus a `$<len>\r\n` header and a trailing `\r\n`
+    let size = keys.iter().map(|k| k.len() + 16).sum::<usize>() + 16;
+    let mut out = Vec::with_capacity(size);
+    out.extend_from_slice(format!("*{}\r\n", keys.len()).as_bytes());
+    for key in keys {
+        Frame::BulkString(Some(key.into_bytes())).encode_into(&mut out);
+    }
+    out
 }
 
 /// SAVE command synchronously saves the dataset to disk.
@@ -160,6 +586,9 @@ pub async fn save(args: Vec<Frame>) -> Vec<u8> {
     if !args.is_empty() {
         return Frame::Error("ERR wrong number of arguments for 'save'".into()).encode();
     }
+    if rdb::bgsave_in_progress() {
+        return Frame::Error("ERR Background save already in progress".into()).encode();
+    }
 
     match rdb::save().await {
         Ok(()) => Frame::SimpleString("OK".into()).encode(),
@@ -167,51 +596,3627 @@ pub async fn save(args: Vec<Frame>) -> Vec<u8> {
     }
 }
 
-/// CONFIG GET command returns config values as RESP array
-/// It expects a single argument which is the parameter name.
-pub async fn config_get(args: Vec<Frame>) -> Vec<u8> {
+/// BGSAVE command starts saving the dataset to the RDB file in the background.
+/// It expects no arguments, or SCHEDULE, which is accepted and ignored.
+pub async fn bgsave(args: Vec<Frame>) -> Vec<u8> {
+    match args.as_slice() {
+        [] => {}
+        [Frame::BulkString(Some(flag))] if flag.eq_ignore_ascii_case(b"schedule") => {}
+        _ => return Frame::Error("ERR syntax error".into()).encode(),
+    }
+    match rdb::bgsave() {
+        Ok(()) => Frame::SimpleString("Background saving started".into()).encode(),
+        Err(e) => Frame::Error(format!("ERR {}", e)).encode(),
+    }
+}
+
+/// DUMP command returns the value at key serialized in the RDB-based DUMP format,
+/// or nil if the key does not exist.
+/// It expects a single argument which is the key.
+pub async fn dump(args: Vec<Frame>) -> Vec<u8> {
     if args.len() != 1 {
-        return Frame::Error("ERR wrong number of arguments for 'config get'".into()).encode();
+        return Frame::Error("ERR wrong number of arguments for 'dump'".into()).encode();
     }
-    let param = match &args[0] {
-        Frame::BulkString(Some(bs)) => String::from_utf8_lossy(bs).to_lowercase(),
-        _ => return Frame::Error("ERR invalid argument for 'config get'".into()).encode(),
+    let key = match &args[0] {
+        Frame::BulkString(Some(bs)) => String::from_utf8_lossy(bs).to_string(),
+        _ => return Frame::Error("ERR invalid key for 'dump'".into()).encode(),
     };
-    let config = config::get_config();
-    let value = match param.as_str() {
-        "dir" => config.dir.to_string_lossy().to_string(),
-        "dbfilename" => config.dbfilename,
-        _ => String::new(),
+    let Some(value) = db::get_value(&key).await else {
+        return Frame::BulkString(None).encode();
     };
-    let resp = Frame::Array(Some(vec![
-        Frame::BulkString(Some(param.into_bytes())),
-        Frame::BulkString(Some(value.into_bytes())),
-    ]));
-    resp.encode()
+    match rdb::dump_value(&value) {
+        Ok(dump) => Frame::BulkString(dump).encode(),
+        Err(e) => Frame::Error(format!("ERR {}", e)).encode(),
+    }
 }
 
-/// CONFIG SET command allows setting configuration parameters
-/// It expects two arguments: the parameter name and the value.
-pub async fn config_set(args: Vec<Frame>) -> Vec<u8> {
-    if args.len() != 2 {
-        return Frame::Error("ERR wrong number of arguments for 'config set'".into()).encode();
+/// RESTORE command recreates a key from a DUMP payload.
+/// It expects the key, a TTL in milliseconds (0 for none) and the payload,
+/// optionally followed by REPLACE and/or ABSTTL.
+pub async fn restore(args: Vec<Frame>) -> Vec<u8> {
+    if args.len() < 3 {
+        return Frame::Error("ERR wrong number of arguments for 'restore'".into()).encode();
     }
-    let param = match &args[0] {
-        Frame::BulkString(Some(bs)) => String::from_utf8_lossy(bs).to_lowercase(),
-        _ => return Frame::Error("ERR invalid argument for 'config set'".into()).encode(),
+    let key = match &args[0] {
+        Frame::BulkString(Some(bs)) => String::from_utf8_lossy(bs).to_string(),
+        _ => return Frame::Error("ERR invalid key for 'restore'".into()).encode(),
     };
-    let value = match &args[1] {
+    let ttl = match &args[1] {
+        Frame::BulkString(Some(bs)) => match String::from_utf8_lossy(bs).parse::<i64>() {
+            Ok(ttl) if ttl >= 0 => ttl as u64,
+            Ok(_) => {
+                return Frame::Error("ERR Invalid TTL value, must be >= 0".into()).encode();
+            }
+            Err(_) => {
+                return Frame::Error("ERR value is not an integer or out of range".into()).encode();
+            }
+        },
+        _ => return Frame::Error("ERR invalid TTL for 'restore'".into()).encode(),
+    };
+    let payload = match &args[2] {
+        Frame::BulkString(Some(bs)) => bs,
+        _ => return Frame::Error("ERR invalid payload for 'restore'".into()).encode(),
+    };
+
+    let mut replace = false;
+    let mut absttl = false;
+    for opt in &args[3..] {
+        match opt {
+            Frame::BulkString(Some(o)) if o.eq_ignore_ascii_case(b"REPLACE") => replace = true,
+            Frame::BulkString(Some(o)) if o.eq_ignore_ascii_case(b"ABSTTL") => absttl = true,
+            _ => return Frame::Error("ERR syntax error".into()).encode(),
+        }
+    }
+
+    let value = match rdb::restore_value(payload) {
+        Ok(value) => value,
+        Err(e) => return Frame::Error(format!("ERR {}", e)).encode(),
+    };
+
+    let now_ms = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0);
+    // A deadline that has passed restores nothing, though REPLACE still deletes the key
+    let ttl_ms = match (ttl, absttl) {
+        (0, _) => None,
+        (at, true) => Some(at.saturating_sub(now_ms)),
+        (ms, false) => Some(ms),
+    };
+
+    // A relative TTL is sent as the time it came to, so replaying it later doesn't
+    // push the expiry back
+    let relative = ttl_ms.is_some() && !absttl;
+    let expired = ttl_ms == Some(0);
+    if relative || expired {
+        prevent_propagation();
+    }
+    match db::restore(key.clone(), value, ttl_ms, replace).await {
+        Ok(replaced) => {
+            if expired {
+                if replaced {
+                    propagate(vec![arg("DEL"), arg(key)]);
+                }
+            } else if relative {
+                let mut command = vec![
+                    arg("RESTORE"),
+                    arg(key),
+                    arg(now_ms.saturating_add(ttl).to_string()),
+                    args[2].clone(),
+                ];
+                command.extend(replace.then(|| arg("REPLACE")));
+                command.push(arg("ABSTTL"));
+                propagate(command);
+            }
+            Frame::SimpleString("OK".into()).encode()
+        }
+        Err(e) => Frame::Error(e).encode(),
+    }
+}
+
+/// Refcount Redis reports for its shared integer objects
+const OBJ_SHARED_REFCOUNT: i64 = i32::MAX as i64;
+/// Integers below this are served from Redis' shared object pool
+const OBJ_SHARED_INTEGERS: i64 = 10000;
+
+/// Parse the single key argument shared by the OBJECT subcommands
+fn object_key(args: &[Frame], subcmd: &str) -> Result<String, Vec<u8>> {
+    if args.len() != 1 {
+        return Err(Frame::Error(format!(
+            "ERR wrong number of arguments for 'object|{}'",
+            subcmd
+        ))
+        .encode());
+    }
+    match &args[0] {
+        Frame::BulkString(Some(bs)) => Ok(String::from_utf8_lossy(bs).to_string()),
+        _ => Err(Frame::Error(format!("ERR invalid key for 'object|{}'", subcmd)).encode()),
+    }
+}
+
+/// OBJECT ENCODING command returns the internal encoding of the value stored at a key.
+/// It expects a single argument which is the key.
+pub async fn object_encoding(args: Vec<Frame>) -> Vec<u8> {
+    let key = match object_key(&args, "encoding") {
+        Ok(key) => key,
+        Err(e) => return e,
+    };
+    match db::peek_value(&key).await {
+        Some(value) => Frame::BulkString(Some(db::encoding(&value).as_bytes().to_vec())).encode(),
+        None => Frame::Error("ERR no such key".into()).encode(),
+    }
+}
+
+/// OBJECT REFCOUNT command returns the reference count of the value at key.
+/// Values are never shared here, except small integers which Redis keeps in a shared pool.
+pub async fn object_refcount(args: Vec<Frame>) -> Vec<u8> {
+    let key = match object_key(&args, "refcount") {
+        Ok(key) => key,
+        Err(e) => return e,
+    };
+    match db::peek_value(&key).await {
+        Some(value) => Frame::Integer(refcount(&value)).encode(),
+        None => Frame::Error("ERR no such key".into()).encode(),
+    }
+}
+
+/// Reference count Redis would report for a value: small integers come from the shared pool
+fn refcount(value: &RedisValue) -> i64 {
+    match value {
+        RedisValue::Integer(i) if (0..OBJ_SHARED_INTEGERS).contains(i) => OBJ_SHARED_REFCOUNT,
+        RedisValue::String(s)
+            if std::str::from_utf8(s)
+                .ok()
+                .and_then(|s| s.parse::<i64>().ok().filter(|i| i.to_string() == s))
+                .is_some_and(|i| (0..OBJ_SHARED_INTEGERS).contains(&i)) =>
+        {
+            OBJ_SHARED_REFCOUNT
+        }
+        _ => 1,
+    }
+}
+
+/// OBJECT IDLETIME command returns the seconds since the key was last accessed.
+/// Idle time isn't tracked under an LFU policy, matching Redis.
+pub async fn object_idletime(args: Vec<Frame>) -> Vec<u8> {
+    let key = match object_key(&args, "idletime") {
+        Ok(key) => key,
+        Err(e) => return e,
+    };
+    if config::get_config().maxmemory_policy.ends_with("-lfu") {
+        return Frame::Error("ERR An LFU maxmemory policy is selected, idle time not tracked. Please note that when switching between policies at runtime LRU and LFU data will take some time to adjust.".into()).encode();
+    }
+    match db::access_meta(&key).await {
+        Some(meta) => Frame::Integer(meta.last_access.elapsed().as_secs() as i64).encode(),
+        None => Frame::Error("ERR no such key".into()).encode(),
+    }
+}
+
+/// TYPE command returns the name of the type of the value at key, or "none".
+/// It expects a key.
+pub async fn type_of(args: Vec<Frame>) -> Vec<u8> {
+    let [Frame::BulkString(Some(key))] = args.as_slice() else {
+        return Frame::Error("ERR wrong number of arguments for 'type'".into()).encode();
+    };
+    let type_name = match db::peek_value(&String::from_utf8_lossy(key)).await {
+        Some(value) => value.type_name(),
+        None => "none",
+    };
+    Frame::SimpleString(type_name.into()).encode()
+}
+
+/// OBJECT FREQ command returns the logarithmic access frequency counter of the key.
+/// It is only available under an LFU maxmemory policy.
+pub async fn object_freq(args: Vec<Frame>) -> Vec<u8> {
+    let key = match object_key(&args, "freq") {
+        Ok(key) => key,
+        Err(e) => return e,
+    };
+    if !config::get_config().maxmemory_policy.ends_with("-lfu") {
+        return Frame::Error("ERR An LFU maxmemory policy is not selected, access frequency not tracked. Please note that when switching between policies at runtime LRU and LFU data will take some time to adjust.".into()).encode();
+    }
+    match db::access_meta(&key).await {
+        Some(meta) => Frame::Integer(meta.decayed_counter() as i64).encode(),
+        None => Frame::Error("ERR no such key".into()).encode(),
+    }
+}
+
+/// MEMORY USAGE command estimates the bytes a key and its value take, or returns nil
+/// if the key doesn't exist.
+/// It expects a key and optionally SAMPLES n, how many elements of a container to
+/// measure (default 5, 0 for all of them).
+pub async fn memory_usage(args: Vec<Frame>) -> Vec<u8> {
+    let args = match bulk_args(args, "memory usage") {
+        Ok(args) if !args.is_empty() => args,
+        Ok(_) => {
+            return Frame::Error("ERR wrong number of arguments for 'memory usage'".into()).encode()
+        }
+        Err(e) => return e,
+    };
+    let samples = match &args[1..] {
+        [] => 5,
+        [option, samples] if option.eq_ignore_ascii_case(b"samples") => {
+            match String::from_utf8_lossy(samples).parse::<i64>() {
+                Ok(samples) if samples >= 0 => samples as usize,
+                Ok(_) => return Frame::Error("ERR syntax error".into()).encode(),
+                Err(_) => {
+                    return Frame::Error("ERR value is not an integer or out of range".into())
+                        .encode()
+                }
+            }
+        }
+        _ => return Frame::Error("ERR syntax error".into()).encode(),
+    };
+    match db::memory_usage(&String::from_utf8_lossy(&args[0]), samples).await {
+        Some(bytes) => Frame::Integer(bytes as i64).encode(),
+        None => Frame::BulkString(None).encode(),
+    }
+}
+
+/// MEMORY DOCTOR command reports on memory problems, in the words Redis uses.
+/// There is no memory accounting to diagnose, so it only tells an empty server apart.
+/// It expects no arguments.
+pub async fn memory_doctor(args: Vec<Frame>) -> Vec<u8> {
+    if !args.is_empty() {
+        return Frame::Error("ERR wrong number of arguments for 'memory doctor'".into()).encode();
+    }
+    let report = if db::total_keys().await == 0 {
+        "Hi Sam, this instance is empty or is using very little memory, my issues detector can't be used in these conditions. Please, leave for your mission on Earth and fill it with some data. The new Sam and I will be back to our programming as soon as I finished rebooting."
+    } else {
+        "Hi Sam, I can't find any memory issue in your instance. I can only account for what occurs on this base."
+    };
+    Frame::BulkString(Some(report.as_bytes().to_vec())).encode()
+}
+
+/// Parse an integer argument, producing the standard Redis error reply otherwise
+fn parse_integer(frame: &Frame) -> Result<i64, Vec<u8>> {
+    match frame {
+        Frame::BulkString(Some(bs)) => String::from_utf8_lossy(bs).parse::<i64>().map_err(|_| {
+            Frame::Error("ERR value is not an integer or out of range".into()).encode()
+        }),
+        _ => Err(Frame::Error("ERR value is not an integer or out of range".into()).encode()),
+    }
+}
+
+/// Collect arguments that must all be bulk strings
+fn bulk_args(args: Vec<Frame>, cmd: &str) -> Result<Vec<Vec<u8>>, Vec<u8>> {
+    args.into_iter()
+        .map(|arg| match arg {
+            Frame::BulkString(Some(bs)) => Ok(bs),
+            _ => Err(Frame::Error(format!("ERR invalid argument for '{}'", cmd)).encode()),
+        })
+        .collect()
+}
+
+/// The unit of an EX, PX, EXAT or PXAT option, or None for any other argument
+fn expire_unit(opt: &[u8]) -> Option<db::ExpireUnit> {
+    match opt.to_ascii_lowercase().as_slice() {
+        b"ex" => Some(db::ExpireUnit::Seconds),
+        b"px" => Some(db::ExpireUnit::Millis),
+        b"exat" => Some(db::ExpireUnit::UnixSeconds),
+        b"pxat" => Some(db::ExpireUnit::UnixMillis),
+        _ => None,
+    }
+}
+
+/// An argument of a command a handler propagates in place of its own
+fn arg(bytes: impl Into<Vec<u8>>) -> Frame {
+    Frame::BulkString(Some(bytes.into()))
+}
+
+/// Encode a list of members as an array of bulk strings
+fn bulk_array(items: Vec<Vec<u8>>) -> Vec<u8> {
+    Frame::Array(Some(
+        items
+            .into_iter()
+            .map(|item| Frame::BulkString(Some(item)))
+            .collect(),
+    ))
+    .encode()
+}
+
+/// SADD command adds members to a set, creating it if needed, and returns
+/// how many were newly added.
+/// It expects a key followed by one or more members.
+pub async fn sadd(args: Vec<Frame>) -> Vec<u8> {
+    if args.len() < 2 {
+        return Frame::Error("ERR wrong number of arguments for 'sadd'".into()).encode();
+    }
+    let mut parts = Vec::with_capacity(args.len());
+    for arg in args {
+        match arg {
+            Frame::BulkString(Some(bs)) => parts.push(bs),
+            _ => return Frame::Error("ERR invalid argument for 'sadd'".into()).encode(),
+        }
+    }
+    let key = String::from_utf8_lossy(&parts.remove(0)).to_string();
+    match db::sadd(&key, parts).await {
+        Ok(added) => {
+            if added == 0 {
+                prevent_propagation();
+            }
+            Frame::Integer(added).encode()
+        }
+        Err(e) => Frame::Error(e).encode(),
+    }
+}
+
+/// SPOP command removes and returns a random member of a set (nil if empty), or,
+/// with a count, an array of up to count members.
+/// It expects a key and an optional non-negative count.
+pub async fn spop(args: Vec<Frame>) -> Vec<u8> {
+    if args.is_empty() || args.len() > 2 {
+        return Frame::Error("ERR wrong number of arguments for 'spop'".into()).encode();
+    }
+    let key = match &args[0] {
         Frame::BulkString(Some(bs)) => String::from_utf8_lossy(bs).to_string(),
-        _ => return Frame::Error("ERR invalid value for 'config set'".into()).encode(),
+        _ => return Frame::Error("ERR invalid key for 'spop'".into()).encode(),
+    };
+    let count = match args.get(1).map(parse_integer) {
+        None => None,
+        Some(Ok(n)) if n >= 0 => Some(n as usize),
+        Some(Ok(_)) => {
+            return Frame::Error("ERR value is out of range, must be positive".into()).encode();
+        }
+        Some(Err(e)) => return e,
     };
 
-    match param.as_str() {
-        "dir" => config::set_dir(value),
-        "dbfilename" => config::set_dbfilename(value),
-        _ => return Frame::Error("ERR unknown configuration parameter".into()).encode(),
+    // Sent as the members it removed, rather than another random pick
+    prevent_propagation();
+    match db::spop(&key, count.unwrap_or(1)).await {
+        Ok(members) => {
+            if !members.is_empty() {
+                let removed = members.iter().map(|member| arg(member.as_slice()));
+                propagate([arg("SREM"), arg(key)].into_iter().chain(removed).collect());
+            }
+            match count {
+                Some(_) => bulk_array(members),
+                None => Frame::BulkString(members.into_iter().next()).encode(),
+            }
+        }
+        Err(e) => Frame::Error(e).encode(),
     }
+}
 
-    Frame::SimpleString("OK".into()).encode()
+/// SREM command removes members from a set, deleting the key once it is empty,
+/// and returns how many were removed.
+/// It expects a key followed by one or more members.
+pub async fn srem(args: Vec<Frame>) -> Vec<u8> {
+    let mut parts = match bulk_args(args, "srem") {
+        Ok(parts) if parts.len() >= 2 => parts,
+        Ok(_) => return Frame::Error("ERR wrong number of arguments for 'srem'".into()).encode(),
+        Err(e) => return e,
+    };
+    let key = String::from_utf8_lossy(&parts.remove(0)).to_string();
+    match db::srem(&key, &parts).await {
+        Ok(removed) => {
+            if removed == 0 {
+                prevent_propagation();
+            }
+            Frame::Integer(removed).encode()
+        }
+        Err(e) => Frame::Error(e).encode(),
+    }
+}
+
+/// SRANDMEMBER command returns a random member of a set without removing it.
+/// With a positive count it returns up to count distinct members;
+/// with a negative count it returns |count| members that may repeat.
+pub async fn srandmember(args: Vec<Frame>) -> Vec<u8> {
+    if args.is_empty() || args.len() > 2 {
+        return Frame::Error("ERR wrong number of arguments for 'srandmember'".into()).encode();
+    }
+    let key = match &args[0] {
+        Frame::BulkString(Some(bs)) => String::from_utf8_lossy(bs).to_string(),
+        _ => return Frame::Error("ERR invalid key for 'srandmember'".into()).encode(),
+    };
+    let count = match args.get(1).map(parse_integer) {
+        None => None,
+        // |count| must fit a long, as in Redis
+        Some(Ok(i64::MIN)) => return Frame::Error(db::OUT_OF_RANGE.into()).encode(),
+        Some(Ok(n)) => Some(n),
+        Some(Err(e)) => return e,
+    };
+
+    match db::srandmember(&key, count.unwrap_or(1)).await {
+        Ok(members) if count.is_some() => bulk_array(members),
+        Ok(members) => Frame::BulkString(members.into_iter().next()).encode(),
+        Err(e) => Frame::Error(e).encode(),
+    }
+}
+
+/// SINTERCARD/ZINTERCARD commands return the size of the intersection of the
+/// given sets (or sorted sets), stopping early once LIMIT is reached.
+/// It expects numkeys, that many keys and an optional LIMIT count.
+pub async fn intercard(args: Vec<Frame>, zset: bool) -> Vec<u8> {
+    let cmd = if zset { "zintercard" } else { "sintercard" };
+    if args.len() < 2 {
+        return Frame::Error(format!("ERR wrong number of arguments for '{}'", cmd)).encode();
+    }
+    let numkeys = match parse_integer(&args[0]) {
+        Ok(n) if n > 0 => n as usize,
+        Ok(_) => return Frame::Error("ERR numkeys should be greater than 0".into()).encode(),
+        Err(e) => return e,
+    };
+    if numkeys > args.len() - 1 {
+        return Frame::Error("ERR Number of keys can't be greater than number of args".into())
+            .encode();
+    }
+    let mut keys = Vec::with_capacity(numkeys);
+    for arg in &args[1..=numkeys] {
+        match arg {
+            Frame::BulkString(Some(bs)) => keys.push(String::from_utf8_lossy(bs).to_string()),
+            _ => return Frame::Error(format!("ERR invalid key for '{}'", cmd)).encode(),
+        }
+    }
+    let limit = match &args[numkeys + 1..] {
+        [] => 0,
+        [Frame::BulkString(Some(opt)), value] if opt.eq_ignore_ascii_case(b"LIMIT") => {
+            match parse_integer(value) {
+                Ok(n) if n >= 0 => n as usize,
+                Ok(_) => return Frame::Error("ERR LIMIT can't be negative".into()).encode(),
+                Err(e) => return e,
+            }
+        }
+        _ => return Frame::Error("ERR syntax error".into()).encode(),
+    };
+
+    match db::intercard(&keys, limit, zset).await {
+        Ok(count) => Frame::Integer(count).encode(),
+        Err(e) => Frame::Error(e).encode(),
+    }
+}
+
+/// TIME command returns the server's Unix time as [seconds, microseconds].
+/// It expects no arguments.
+pub async fn time(args: Vec<Frame>) -> Vec<u8> {
+    if !args.is_empty() {
+        return Frame::Error("ERR wrong number of arguments for 'time'".into()).encode();
+    }
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default();
+    bulk_array(vec![
+        now.as_secs().to_string().into_bytes(),
+        now.subsec_micros().to_string().into_bytes(),
+    ])
+}
+
+/// DBSIZE command returns the number of keys in the selected database.
+/// It expects no arguments.
+pub async fn dbsize(args: Vec<Frame>) -> Vec<u8> {
+    if !args.is_empty() {
+        return Frame::Error("ERR wrong number of arguments for 'dbsize'".into()).encode();
+    }
+    Frame::Integer(db::dbsize().await as i64).encode()
+}
+
+/// Parse a SETBIT/GETBIT offset, which must address a bit inside proto-max-bulk-len bytes
+fn parse_bit_offset(frame: &Frame) -> Result<u64, Vec<u8>> {
+    let max_bits = config::get_config().proto_max_bulk_len.saturating_mul(8);
+    match frame {
+        Frame::BulkString(Some(bs)) => String::from_utf8_lossy(bs)
+            .parse::<u64>()
+            .ok()
+            .filter(|&offset| offset < max_bits),
+        _ => None,
+    }
+    .ok_or_else(|| Frame::Error("ERR bit offset is not an integer or out of range".into()).encode())
+}
+
+/// SETBIT command sets or clears the bit at an offset of a string, growing it as needed,
+/// and returns the previous bit.
+/// It expects a key, an offset and a bit value (0 or 1).
+pub async fn setbit(args: Vec<Frame>) -> Vec<u8> {
+    if args.len() != 3 {
+        return Frame::Error("ERR wrong number of arguments for 'setbit'".into()).encode();
+    }
+    let key = match &args[0] {
+        Frame::BulkString(Some(bs)) => String::from_utf8_lossy(bs).to_string(),
+        _ => return Frame::Error("ERR invalid key for 'setbit'".into()).encode(),
+    };
+    let offset = match parse_bit_offset(&args[1]) {
+        Ok(offset) => offset,
+        Err(e) => return e,
+    };
+    let bit = match &args[2] {
+        Frame::BulkString(Some(bs)) if bs.as_slice() == b"0" => false,
+        Frame::BulkString(Some(bs)) if bs.as_slice() == b"1" => true,
+        _ => return Frame::Error("ERR bit is not an integer or out of range".into()).encode(),
+    };
+    match db::setbit(&key, offset, bit).await {
+        Ok(previous) => Frame::Integer(previous as i64).encode(),
+        Err(e) => Frame::Error(e).encode(),
+    }
+}
+
+/// GETBIT command returns the bit at an offset of a string (0 past the end).
+/// It expects a key and an offset.
+pub async fn getbit(args: Vec<Frame>) -> Vec<u8> {
+    if args.len() != 2 {
+        return Frame::Error("ERR wrong number of arguments for 'getbit'".into()).encode();
+    }
+    let key = match &args[0] {
+        Frame::BulkString(Some(bs)) => String::from_utf8_lossy(bs).to_string(),
+        _ => return Frame::Error("ERR invalid key for 'getbit'".into()).encode(),
+    };
+    let offset = match parse_bit_offset(&args[1]) {
+        Ok(offset) => offset,
+        Err(e) => return e,
+    };
+    match db::getbit(&key, offset).await {
+        Ok(bit) => Frame::Integer(bit as i64).encode(),
+        Err(e) => Frame::Error(e).encode(),
+    }
+}
+
+/// BITCOUNT command counts the set bits of a string.
+/// It expects a key and an optional start and end, in bytes by default or in bits with BIT.
+pub async fn bitcount(args: Vec<Frame>) -> Vec<u8> {
+    if args.is_empty() || args.len() > 4 {
+        return Frame::Error("ERR wrong number of arguments for 'bitcount'".into()).encode();
+    }
+    let key = match &args[0] {
+        Frame::BulkString(Some(bs)) => String::from_utf8_lossy(bs).to_string(),
+        _ => return Frame::Error("ERR invalid key for 'bitcount'".into()).encode(),
+    };
+    let range = match &args[1..] {
+        [] => None,
+        [start, end, ..] => match (parse_integer(start), parse_integer(end)) {
+            (Ok(start), Ok(end)) => Some((start, end)),
+            (Err(e), _) | (_, Err(e)) => return e,
+        },
+        [_] => return Frame::Error("ERR syntax error".into()).encode(),
+    };
+    let bit_unit = match args.get(3) {
+        None => false,
+        Some(Frame::BulkString(Some(unit))) => match unit.to_ascii_lowercase().as_slice() {
+            b"bit" => true,
+            b"byte" => false,
+            _ => return Frame::Error("ERR syntax error".into()).encode(),
+        },
+        Some(_) => return Frame::Error("ERR syntax error".into()).encode(),
+    };
+    match db::bitcount(&key, range, bit_unit).await {
+        Ok(count) => Frame::Integer(count).encode(),
+        Err(e) => Frame::Error(e).encode(),
+    }
+}
+
+/// GETRANGE command (and its old name SUBSTR) returns the substring of a string
+/// between two inclusive byte offsets, which may be negative to count from the end.
+/// It expects a key, a start and an end.
+pub async fn getrange(args: Vec<Frame>, cmd: &str) -> Vec<u8> {
+    let [Frame::BulkString(Some(key)), start, end] = args.as_slice() else {
+        return Frame::Error(format!("ERR wrong number of arguments for '{}'", cmd)).encode();
+    };
+    let (start, end) = match (parse_integer(start), parse_integer(end)) {
+        (Ok(start), Ok(end)) => (start, end),
+        (Err(e), _) | (_, Err(e)) => return e,
+    };
+    match db::getrange(&String::from_utf8_lossy(key), start, end).await {
+        Ok(bytes) => Frame::BulkString(Some(bytes)).encode(),
+        Err(e) => Frame::Error(e).encode(),
+    }
+}
+
+/// STRLEN command returns the length of the string at key, 0 if it doesn't exist.
+/// Integers count the digits of their decimal form, as Redis measures int-encoded strings.
+/// It expects a key.
+pub async fn strlen(args: Vec<Frame>) -> Vec<u8> {
+    let [Frame::BulkString(Some(key))] = args.as_slice() else {
+        return Frame::Error("ERR wrong number of arguments for 'strlen'".into()).encode();
+    };
+    match db::get_string(&String::from_utf8_lossy(key)).await {
+        Ok(bytes) => Frame::Integer(bytes.map_or(0, |b| b.len()) as i64).encode(),
+        Err(e) => Frame::Error(e).encode(),
+    }
+}
+
+/// APPEND command appends a value to a string, creating it if needed, and returns its new length.
+/// It expects a key and a value.
+pub async fn append(args: Vec<Frame>) -> Vec<u8> {
+    let [Frame::BulkString(Some(key)), Frame::BulkString(Some(value))] = args.as_slice() else {
+        return Frame::Error("ERR wrong number of arguments for 'append'".into()).encode();
+    };
+    match db::append(&String::from_utf8_lossy(key), value).await {
+        Ok(len) => Frame::Integer(len).encode(),
+        Err(e) => Frame::Error(e).encode(),
+    }
+}
+
+/// SETRANGE command overwrites part of a string from an offset, zero-padding it as needed,
+/// and returns its new length.
+/// It expects a key, an offset and a value.
+pub async fn setrange(args: Vec<Frame>) -> Vec<u8> {
+    let [Frame::BulkString(Some(key)), offset, Frame::BulkString(Some(value))] = args.as_slice()
+    else {
+        return Frame::Error("ERR wrong number of arguments for 'setrange'".into()).encode();
+    };
+    let offset = match parse_integer(offset) {
+        Ok(offset) if offset >= 0 => offset as u64,
+        Ok(_) => return Frame::Error("ERR offset is out of range".into()).encode(),
+        Err(e) => return e,
+    };
+    // An empty value leaves the string as it was, and doesn't create a missing key
+    if value.is_empty() {
+        prevent_propagation();
+    }
+    match db::setrange(&String::from_utf8_lossy(key), offset, value).await {
+        Ok(len) => Frame::Integer(len).encode(),
+        Err(e) => Frame::Error(e).encode(),
+    }
+}
+
+/// BITOP command applies AND, OR, XOR or NOT to the source strings and stores
+/// the result in destkey, returning its length.
+/// It expects an operation, a destination key and one or more source keys (exactly one for NOT).
+pub async fn bitop(args: Vec<Frame>) -> Vec<u8> {
+    if args.len() < 3 {
+        return Frame::Error("ERR wrong number of arguments for 'bitop'".into()).encode();
+    }
+    let mut parts = Vec::with_capacity(args.len());
+    for arg in args {
+        match arg {
+            Frame::BulkString(Some(bs)) => parts.push(String::from_utf8_lossy(&bs).to_string()),
+            _ => return Frame::Error("ERR invalid argument for 'bitop'".into()).encode(),
+        }
+    }
+    let op = match parts[0].to_lowercase().as_str() {
+        "and" => db::BitOp::And,
+        "or" => db::BitOp::Or,
+        "xor" => db::BitOp::Xor,
+        "not" => db::BitOp::Not,
+        _ => return Frame::Error("ERR syntax error".into()).encode(),
+    };
+    if matches!(op, db::BitOp::Not) && parts.len() != 3 {
+        return Frame::Error("ERR BITOP NOT must be called with a single source key.".into())
+            .encode();
+    }
+    match db::bitop(op, &parts[1], &parts[2..]).await {
+        Ok(len) => Frame::Integer(len).encode(),
+        Err(e) => Frame::Error(e).encode(),
+    }
+}
+
+/// HSET command sets one or more fields of a hash, creating it if needed,
+/// and returns the number of fields that were added.
+/// HMSET, its deprecated form, replies OK instead.
+/// It expects a key followed by field/value pairs.
+pub async fn hset(args: Vec<Frame>, cmd: &str) -> Vec<u8> {
+    if args.len() < 3 || args.len().is_multiple_of(2) {
+        return Frame::Error(format!("ERR wrong number of arguments for '{}'", cmd)).encode();
+    }
+    let mut parts = Vec::with_capacity(args.len());
+    for arg in args {
+        match arg {
+            Frame::BulkString(Some(bs)) => parts.push(bs),
+            _ => return Frame::Error(format!("ERR invalid argument for '{}'", cmd)).encode(),
+        }
+    }
+    let key = String::from_utf8_lossy(&parts.remove(0)).to_string();
+    let mut pairs = Vec::with_capacity(parts.len() / 2);
+    let mut parts = parts.into_iter();
+    while let (Some(field), Some(value)) = (parts.next(), parts.next()) {
+        pairs.push((field, value));
+    }
+    match db::hset(&key, pairs).await {
+        Ok(_) if cmd == "hmset" => Frame::SimpleString("OK".into()).encode(),
+        Ok(added) => Frame::Integer(added).encode(),
+        Err(e) => Frame::Error(e).encode(),
+    }
+}
+
+/// HSETNX command sets a hash field only if it doesn't exist yet, creating the
+/// hash if needed, and returns 1 if it was set or 0 if it already existed.
+/// It expects a key, a field and a value.
+pub async fn hsetnx(args: Vec<Frame>) -> Vec<u8> {
+    let [Frame::BulkString(Some(key)), Frame::BulkString(Some(field)), Frame::BulkString(Some(value))] =
+        args.as_slice()
+    else {
+        return Frame::Error("ERR wrong number of arguments for 'hsetnx'".into()).encode();
+    };
+    match db::hsetnx(&String::from_utf8_lossy(key), field.clone(), value.clone()).await {
+        Ok(set) => {
+            if !set {
+                prevent_propagation();
+            }
+            Frame::Integer(i64::from(set)).encode()
+        }
+        Err(e) => Frame::Error(e).encode(),
+    }
+}
+
+/// HGET command returns the value of a hash field, or nil if it doesn't exist.
+/// It expects a key and a field.
+pub async fn hget(args: Vec<Frame>) -> Vec<u8> {
+    let (key, field) = match args.as_slice() {
+        [Frame::BulkString(Some(key)), Frame::BulkString(Some(field))] => (key, field),
+        [_, _] => return Frame::Error("ERR invalid argument for 'hget'".into()).encode(),
+        _ => return Frame::Error("ERR wrong number of arguments for 'hget'".into()).encode(),
+    };
+    match db::hget(&String::from_utf8_lossy(key), field).await {
+        Ok(value) => Frame::BulkString(value).encode(),
+        Err(e) => Frame::Error(e).encode(),
+    }
+}
+
+/// HGETALL command returns every field and value of a hash,
+/// as a map under RESP3 and a flat array under RESP2.
+/// It expects a single argument which is the key.
+pub async fn hgetall(args: Vec<Frame>, state: &mut ClientState) -> Vec<u8> {
+    if args.len() != 1 {
+        return Frame::Error("ERR wrong number of arguments for 'hgetall'".into()).encode();
+    }
+    let key = match &args[0] {
+        Frame::BulkString(Some(bs)) => String::from_utf8_lossy(bs).to_string(),
+        _ => return Frame::Error("ERR invalid key for 'hgetall'".into()).encode(),
+    };
+    match db::hgetall(&key).await {
+        Ok(hash) => db::encode_hash(hash, state.resp3()),
+        Err(e) => Frame::Error(e).encode(),
+    }
+}
+
+/// Parse the `key [count [WITHVALUES|WITHSCORES]]` arguments shared by HRANDFIELD and ZRANDMEMBER
+fn parse_random_args(
+    args: &[Frame],
+    cmd: &str,
+    with_flag: &[u8],
+) -> Result<(String, Option<i64>, bool), Vec<u8>> {
+    if args.is_empty() || args.len() > 3 {
+        return Err(Frame::Error(format!("ERR wrong number of arguments for '{}'", cmd)).encode());
+    }
+    let key = match &args[0] {
+        Frame::BulkString(Some(bs)) => String::from_utf8_lossy(bs).to_string(),
+        _ => return Err(Frame::Error(format!("ERR invalid key for '{}'", cmd)).encode()),
+    };
+    let count = args.get(1).map(parse_integer).transpose()?;
+    // Half a long either way, as in Redis, so a count of pairs still fits one
+    if count.is_some_and(|n| !(-i64::MAX / 2..=i64::MAX / 2).contains(&n)) {
+        return Err(Frame::Error(db::OUT_OF_RANGE.into()).encode());
+    }
+    let with = match args.get(2) {
+        None => false,
+        Some(Frame::BulkString(Some(bs))) if bs.eq_ignore_ascii_case(with_flag) => true,
+        Some(_) => return Err(Frame::Error("ERR syntax error".into()).encode()),
+    };
+    Ok((key, count, with))
+}
+
+/// Encode (member, value) pairs as a flat array, or as an array of pairs over RESP3
+fn pairs_array(pairs: Vec<(Frame, Frame)>, resp3: bool) -> Vec<u8> {
+    let items = if resp3 {
+        pairs
+            .into_iter()
+            .map(|(k, v)| Frame::Array(Some(vec![k, v])))
+            .collect()
+    } else {
+        pairs.into_iter().flat_map(|(k, v)| [k, v]).collect()
+    };
+    Frame::Array(Some(items)).encode()
+}
+
+/// HRANDFIELD command returns random fields of a hash, like SRANDMEMBER does for sets.
+/// It expects a key, an optional count and, after the count, WITHVALUES.
+pub async fn hrandfield(args: Vec<Frame>, state: &mut ClientState) -> Vec<u8> {
+    let (key, count, with_values) = match parse_random_args(&args, "hrandfield", b"WITHVALUES") {
+        Ok(parsed) => parsed,
+        Err(e) => return e,
+    };
+    let fields = match db::hrandfield(&key, count.unwrap_or(1)).await {
+        Ok(fields) => fields,
+        Err(e) => return Frame::Error(e).encode(),
+    };
+    if count.is_none() {
+        return Frame::BulkString(fields.into_iter().next().map(|(f, _)| f)).encode();
+    }
+    if !with_values {
+        return bulk_array(fields.into_iter().map(|(f, _)| f).collect());
+    }
+    let pairs = fields
+        .into_iter()
+        .map(|(f, v)| (Frame::BulkString(Some(f)), Frame::BulkString(Some(v))))
+        .collect();
+    pairs_array(pairs, state.resp3())
+}
+
+/// Latest expire time a hash field may have, 2^48 milliseconds since the epoch as in Redis
+const MAX_FIELD_EXPIRE_MS: i64 = 1 << 48;
+
+/// HEXPIRE, HPEXPIRE, HEXPIREAT and HPEXPIREAT commands set the TTL of fields of a hash,
+/// relative in seconds or milliseconds or as a Unix time, returning a status per field:
+/// -2 no such field, 0 condition not met, 1 TTL set, 2 field deleted by a time that has passed.
+/// They expect a key, a time, an optional NX/XX/GT/LT condition and FIELDS numfields field...
+pub async fn hexpire(args: Vec<Frame>, cmd: &str) -> Vec<u8> {
+    if args.len() < 5 {
+        return Frame::Error(format!("ERR wrong number of arguments for '{}'", cmd)).encode();
+    }
+    let unit = match cmd {
+        "hexpire" => db::ExpireUnit::Seconds,
+        "hpexpire" => db::ExpireUnit::Millis,
+        "hexpireat" => db::ExpireUnit::UnixSeconds,
+        _ => db::ExpireUnit::UnixMillis,
+    };
+    let expire_at = match parse_integer(&args[1]) {
+        Ok(time) if time >= 0 => match db::expire_deadline(cmd, time, unit) {
+            Ok(at) if at <= MAX_FIELD_EXPIRE_MS => at,
+            _ => {
+                return Frame::Error(format!("ERR invalid expire time in '{}' command", cmd))
+                    .encode()
+            }
+        },
+        Ok(_) => {
+            return Frame::Error(format!("ERR invalid expire time in '{}' command", cmd)).encode()
+        }
+        Err(e) => return e,
+    };
+    let mut args = match bulk_args(args, cmd) {
+        Ok(args) => args,
+        Err(e) => return e,
+    };
+    let key = String::from_utf8_lossy(&args.remove(0)).to_string();
+    args.remove(0);
+
+    let condition = match args[0].to_ascii_lowercase().as_slice() {
+        b"nx" => db::ExpireCondition::Nx,
+        b"xx" => db::ExpireCondition::Xx,
+        b"gt" => db::ExpireCondition::Gt,
+        b"lt" => db::ExpireCondition::Lt,
+        _ => db::ExpireCondition::Always,
+    };
+    if condition != db::ExpireCondition::Always {
+        args.remove(0);
+    }
+    let fields = match hash_fields(args) {
+        Ok(fields) => fields,
+        Err(e) => return e,
+    };
+    // Sent as the time the TTL came to, for just the fields it was set on
+    prevent_propagation();
+    match db::hexpire(&key, expire_at, condition, &fields).await {
+        Ok(statuses) => {
+            let updated: Vec<Frame> = fields
+                .iter()
+                .zip(&statuses)
+                .filter(|(_, &status)| status == 1 || status == 2)
+                .map(|(field, _)| arg(field.as_slice()))
+                .collect();
+            if !updated.is_empty() {
+                let mut command = vec![arg("HPEXPIREAT"), arg(key), arg(expire_at.to_string())];
+                command.push(arg("FIELDS"));
+                command.push(arg(updated.len().to_string()));
+                command.extend(updated);
+                propagate(command);
+            }
+            integer_array(statuses)
+        }
+        Err(e) => Frame::Error(e).encode(),
+    }
+}
+
+/// HTTL command returns the remaining TTL in seconds of fields of a hash:
+/// -2 for a missing field and -1 for a field without a TTL.
+/// It expects a key and FIELDS numfields field...
+pub async fn httl(args: Vec<Frame>) -> Vec<u8> {
+    let mut args = match bulk_args(args, "httl") {
+        Ok(args) if args.len() >= 4 => args,
+        Ok(_) => return Frame::Error("ERR wrong number of arguments for 'httl'".into()).encode(),
+        Err(e) => return e,
+    };
+    let key = String::from_utf8_lossy(&args.remove(0)).to_string();
+    let fields = match hash_fields(args) {
+        Ok(fields) => fields,
+        Err(e) => return e,
+    };
+    match db::httl(&key, &fields).await {
+        Ok(ttls) => integer_array(ttls),
+        Err(e) => Frame::Error(e).encode(),
+    }
+}
+
+/// HGETEX command returns the values of fields of a hash (nil for missing ones) and
+/// optionally sets or clears their TTL.
+/// It expects a key, at most one of EX/PX/EXAT/PXAT <time> or PERSIST, and FIELDS numfields field...
+pub async fn hgetex(args: Vec<Frame>) -> Vec<u8> {
+    let mut args = match bulk_args(args, "hgetex") {
+        Ok(args) if args.len() >= 4 => args,
+        Ok(_) => return Frame::Error("ERR wrong number of arguments for 'hgetex'".into()).encode(),
+        Err(e) => return e,
+    };
+    let key = String::from_utf8_lossy(&args.remove(0)).to_string();
+    let expiry = if let Some(unit) = expire_unit(&args[0]) {
+        let time = match std::str::from_utf8(&args[1])
+            .ok()
+            .and_then(|t| t.parse().ok())
+        {
+            Some(time) => time,
+            None => {
+                return Frame::Error("ERR value is not an integer or out of range".into()).encode()
+            }
+        };
+        args.drain(..2);
+        match db::validate_expire("hgetex", time, unit) {
+            Ok(at) if at <= MAX_FIELD_EXPIRE_MS => db::GetexExpiry::AtUnixMs(at),
+            Ok(_) => {
+                return Frame::Error("ERR invalid expire time in 'hgetex' command".into()).encode()
+            }
+            Err(e) => return Frame::Error(e).encode(),
+        }
+    } else if args[0].eq_ignore_ascii_case(b"persist") {
+        args.remove(0);
+        db::GetexExpiry::Persist
+    } else {
+        db::GetexExpiry::Keep
+    };
+    let fields = match hash_fields(args) {
+        Ok(fields) => fields,
+        Err(e) => return e,
+    };
+    // Without an option HGETEX is a read, and a new TTL is sent as the time it came to,
+    // for just the fields that exist
+    if expiry != db::GetexExpiry::Persist {
+        prevent_propagation();
+    }
+    match db::hgetex(&key, expiry, &fields).await {
+        Ok(values) => {
+            let found: Vec<Frame> = fields
+                .iter()
+                .zip(&values)
+                .filter(|(_, value)| value.is_some())
+                .map(|(field, _)| arg(field.as_slice()))
+                .collect();
+            match expiry {
+                db::GetexExpiry::AtUnixMs(at) if !found.is_empty() => {
+                    let mut command = vec![arg("HGETEX"), arg(key), arg("PXAT")];
+                    command.push(arg(at.to_string()));
+                    command.push(arg("FIELDS"));
+                    command.push(arg(found.len().to_string()));
+                    command.extend(found);
+                    propagate(command);
+                }
+                db::GetexExpiry::Persist if found.is_empty() => prevent_propagation(),
+                _ => {}
+            }
+            Frame::Array(Some(values.into_iter().map(Frame::BulkString).collect())).encode()
+        }
+        Err(e) => Frame::Error(e).encode(),
+    }
+}
+
+/// HGETDEL command returns the values of fields of a hash (nil for missing ones) and
+/// deletes them, deleting the key once the hash is empty.
+/// It expects a key and FIELDS numfields field...
+pub async fn hgetdel(args: Vec<Frame>) -> Vec<u8> {
+    let mut args = match bulk_args(args, "hgetdel") {
+        Ok(args) if args.len() >= 4 => args,
+        Ok(_) => {
+            return Frame::Error("ERR wrong number of arguments for 'hgetdel'".into()).encode()
+        }
+        Err(e) => return e,
+    };
+    let key = String::from_utf8_lossy(&args.remove(0)).to_string();
+    let fields = match hash_fields(args) {
+        Ok(fields) => fields,
+        Err(e) => return e,
+    };
+    match db::hgetdel(&key, &fields).await {
+        Ok(values) => {
+            if values.iter().all(Option::is_none) {
+                prevent_propagation();
+            }
+            Frame::Array(Some(values.into_iter().map(Frame::BulkString).collect())).encode()
+        }
+        Err(e) => Frame::Error(e).encode(),
+    }
+}
+
+/// Parse the `FIELDS numfields field...` arguments that end the hash field TTL commands
+fn hash_fields(mut args: Vec<Vec<u8>>) -> Result<Vec<Vec<u8>>, Vec<u8>> {
+    if args.len() < 2 || !args[0].eq_ignore_ascii_case(b"fields") {
+        return Err(Frame::Error(
+            "ERR Mandatory argument FIELDS is missing or not at the right position".into(),
+        )
+        .encode());
+    }
+    let numfields = std::str::from_utf8(&args[1])
+        .ok()
+        .and_then(|n| n.parse::<usize>().ok())
+        .filter(|&n| n > 0)
+        .ok_or_else(|| {
+            Frame::Error("ERR Parameter `numFields` should be greater than 0".into()).encode()
+        })?;
+    let fields = args.split_off(2);
+    if fields.len() != numfields {
+        return Err(Frame::Error(
+            "ERR The `numfields` parameter must match the number of arguments".into(),
+        )
+        .encode());
+    }
+    Ok(fields)
+}
+
+const INVALID_STREAM_ID: &str = "ERR Invalid stream ID specified as stream command argument";
+
+/// Parse a stream ID given as `ms-seq`, or as `ms` alone, which takes missing_seq
+fn parse_stream_id(arg: &[u8], missing_seq: u64) -> Option<StreamId> {
+    let arg = std::str::from_utf8(arg).ok()?;
+    match arg.split_once('-') {
+        Some((ms, seq)) => Some((ms.parse().ok()?, seq.parse().ok()?)),
+        None => Some((arg.parse().ok()?, missing_seq)),
+    }
+}
+
+/// Format a stream ID the way replies spell it, `ms-seq`
+fn stream_id_bytes((ms, seq): StreamId) -> Vec<u8> {
+    format!("{}-{}", ms, seq).into_bytes()
+}
+
+/// XADD command appends an entry to a stream, creating the stream if needed,
+/// and returns the new entry's ID.
+/// It expects a key, an ID (`*` to generate one, `ms-*` to generate only the sequence
+/// number, or `ms-seq`) and one or more field/value pairs.
+pub async fn xadd(args: Vec<Frame>) -> Vec<u8> {
+    let mut args = match bulk_args(args, "xadd") {
+        Ok(args) if args.len() >= 4 && args.len() % 2 == 0 => args,
+        Ok(_) => return Frame::Error("ERR wrong number of arguments for 'xadd'".into()).encode(),
+        Err(e) => return e,
+    };
+    let key = String::from_utf8_lossy(&args.remove(0)).to_string();
+    let id = args.remove(0);
+    let fields: Vec<Frame> = args.iter().map(|arg| self::arg(arg.as_slice())).collect();
+    let id = if id.as_slice() == b"*" {
+        db::StreamIdSpec::Auto
+    } else if let Some(ms) = id.strip_suffix(b"-*") {
+        match std::str::from_utf8(ms).ok().and_then(|ms| ms.parse().ok()) {
+            Some(ms) => db::StreamIdSpec::AutoSeq(ms),
+            None => return Frame::Error(INVALID_STREAM_ID.into()).encode(),
+        }
+    } else {
+        match parse_stream_id(&id, 0) {
+            Some(id) => db::StreamIdSpec::Explicit(id),
+            None => return Frame::Error(INVALID_STREAM_ID.into()).encode(),
+        }
+    };
+    let mut pairs = Vec::with_capacity(args.len() / 2);
+    let mut args = args.into_iter();
+    while let (Some(field), Some(value)) = (args.next(), args.next()) {
+        pairs.push((field, value));
+    }
+    // Sent with the ID the entry was given, rather than one generated from the clock
+    prevent_propagation();
+    match db::xadd(&key, id, pairs).await {
+        Ok(id) => {
+            let id = stream_id_bytes(id);
+            let command = [arg("XADD"), arg(key), arg(id.as_slice())];
+            propagate(command.into_iter().chain(fields).collect());
+            Frame::BulkString(Some(id)).encode()
+        }
+        Err(e) => Frame::Error(e).encode(),
+    }
+}
+
+/// XLEN command returns the number of entries in a stream.
+/// It expects a key.
+pub async fn xlen(args: Vec<Frame>) -> Vec<u8> {
+    let [Frame::BulkString(Some(key))] = args.as_slice() else {
+        return Frame::Error("ERR wrong number of arguments for 'xlen'".into()).encode();
+    };
+    match db::xlen(&String::from_utf8_lossy(key)).await {
+        Ok(len) => Frame::Integer(len).encode(),
+        Err(e) => Frame::Error(e).encode(),
+    }
+}
+
+/// XRANGE command returns the entries of a stream whose IDs fall in a range,
+/// each as its ID and an array of its fields and values.
+/// It expects a key, a start and an end ID and optionally COUNT n. `-` and `+` stand for
+/// the smallest and largest IDs, a bare `ms` for its first or last sequence number,
+/// and a `(` in front of an ID excludes it.
+pub async fn xrange(args: Vec<Frame>) -> Vec<u8> {
+    let args = match bulk_args(args, "xrange") {
+        Ok(args) if args.len() >= 3 => args,
+        Ok(_) => return Frame::Error("ERR wrong number of arguments for 'xrange'".into()).encode(),
+        Err(e) => return e,
+    };
+    let count = match &args[3..] {
+        [] => None,
+        [option, count] if option.eq_ignore_ascii_case(b"count") => {
+            match String::from_utf8_lossy(count).parse::<i64>() {
+                Ok(count) => Some(count.max(0) as usize),
+                Err(_) => {
+                    return Frame::Error("ERR value is not an integer or out of range".into())
+                        .encode()
+                }
+            }
+        }
+        _ => return Frame::Error("ERR syntax error".into()).encode(),
+    };
+    let bound = |arg: &[u8], is_start: bool| -> Result<StreamId, Vec<u8>> {
+        match arg {
+            b"-" => return Ok((0, 0)),
+            b"+" => return Ok((u64::MAX, u64::MAX)),
+            _ => {}
+        }
+        let missing_seq = if is_start { 0 } else { u64::MAX };
+        let (exclusive, arg) = match arg.strip_prefix(b"(") {
+            Some(rest) if !rest.is_empty() => (true, rest),
+            _ => (false, arg),
+        };
+        let id = parse_stream_id(arg, missing_seq)
+            .ok_or_else(|| Frame::Error(INVALID_STREAM_ID.into()).encode())?;
+        if !exclusive {
+            return Ok(id);
+        }
+        // An excluded ID is the range starting just after it, or ending just before it
+        let (ms, seq) = id;
+        let next = if is_start {
+            seq.checked_add(1)
+                .map(|seq| (ms, seq))
+                .or_else(|| ms.checked_add(1).map(|ms| (ms, 0)))
+        } else {
+            seq.checked_sub(1)
+                .map(|seq| (ms, seq))
+                .or_else(|| ms.checked_sub(1).map(|ms| (ms, u64::MAX)))
+        };
+        next.ok_or_else(|| {
+            let which = if is_start { "start" } else { "end" };
+            Frame::Error(format!("ERR invalid {} ID for the interval", which)).encode()
+        })
+    };
+    let start = match bound(&args[1], true) {
+        Ok(id) => id,
+        Err(e) => return e,
+    };
+    let end = match bound(&args[2], false) {
+        Ok(id) => id,
+        Err(e) => return e,
+    };
+    let key = String::from_utf8_lossy(&args[0]).to_string();
+    match db::xrange(&key, start, end, count).await {
+        Ok(entries) => {
+            Frame::Array(Some(entries.into_iter().map(stream_entry_frame).collect())).encode()
+        }
+        Err(e) => Frame::Error(e).encode(),
+    }
+}
+
+/// A stream entry as replies carry it: its ID and an array of its fields and values
+fn stream_entry_frame((id, fields): StreamEntry) -> Frame {
+    let fields = fields
+        .into_iter()
+        .flat_map(|(field, value)| [field, value])
+        .map(|bytes| Frame::BulkString(Some(bytes)))
+        .collect();
+    Frame::Array(Some(vec![
+        Frame::BulkString(Some(stream_id_bytes(id))),
+        Frame::Array(Some(fields)),
+    ]))
+}
+
+/// XINFO STREAM command describes a stream: its length, the last ID it handed out
+/// and its first and last entries (nil while it is empty).
+/// It expects a key.
+pub async fn xinfo_stream(args: Vec<Frame>, state: &mut ClientState) -> Vec<u8> {
+    let [Frame::BulkString(Some(key))] = args.as_slice() else {
+        return Frame::Error("ERR wrong number of arguments for 'xinfo stream'".into()).encode();
+    };
+    let stream = match db::stream(&String::from_utf8_lossy(key)).await {
+        Ok(Some(stream)) => stream,
+        Ok(None) => return Frame::Error("ERR no such key".into()).encode(),
+        Err(e) => return Frame::Error(e).encode(),
+    };
+    let bulk = |s: &str| Frame::BulkString(Some(s.as_bytes().to_vec()));
+    let entry = |entry: Option<&StreamEntry>| {
+        entry.map_or(Frame::BulkString(None), |entry| {
+            stream_entry_frame(entry.clone())
+        })
+    };
+    let fields = vec![
+        (bulk("length"), Frame::Integer(stream.entries.len() as i64)),
+        (
+            bulk("last-generated-id"),
+            Frame::BulkString(Some(stream_id_bytes(stream.last_id))),
+        ),
+        (bulk("first-entry"), entry(stream.entries.first())),
+        (bulk("last-entry"), entry(stream.entries.last())),
+    ];
+    map_frame(fields, state.resp3()).encode()
+}
+
+/// Encode a list of integers as an array
+fn integer_array(values: Vec<i64>) -> Vec<u8> {
+    Frame::Array(Some(values.into_iter().map(Frame::Integer).collect())).encode()
+}
+
+/// ZRANDMEMBER command returns random members of a sorted set, like SRANDMEMBER does for sets.
+/// It expects a key, an optional count and, after the count, WITHSCORES.
+pub async fn zrandmember(args: Vec<Frame>, state: &mut ClientState) -> Vec<u8> {
+    let (key, count, with_scores) = match parse_random_args(&args, "zrandmember", b"WITHSCORES") {
+        Ok(parsed) => parsed,
+        Err(e) => return e,
+    };
+    let members = match db::zrandmember(&key, count.unwrap_or(1)).await {
+        Ok(members) => members,
+        Err(e) => return Frame::Error(e).encode(),
+    };
+    if count.is_none() {
+        return Frame::BulkString(members.into_iter().next().map(|(m, _)| m)).encode();
+    }
+    if !with_scores {
+        return bulk_array(members.into_iter().map(|(m, _)| m).collect());
+    }
+    let resp3 = state.resp3();
+    let pairs = members
+        .into_iter()
+        .map(|(m, score)| (Frame::BulkString(Some(m)), score_frame(score, resp3)))
+        .collect();
+    pairs_array(pairs, resp3)
+}
+
+/// A sorted set score as a RESP3 double, or as a bulk string over RESP2
+fn score_frame(score: f64, resp3: bool) -> Frame {
+    if resp3 {
+        Frame::Double(score)
+    } else {
+        Frame::BulkString(Some(format_double(score).into_bytes()))
+    }
+}
+
+/// Parse a sorted set score or float increment argument, see db::parse_score
+fn parse_score(frame: &Frame) -> Result<f64, Vec<u8>> {
+    match frame {
+        Frame::BulkString(Some(bs)) => db::parse_score(bs).map_err(|e| Frame::Error(e).encode()),
+        _ => Err(Frame::Error("ERR value is not a valid float".into()).encode()),
+    }
+}
+
+/// ZADD command adds members with scores to a sorted set, updating the score
+/// of existing members, and returns the number of members added.
+/// It expects a key, optional NX/XX/GT/LT/CH/INCR flags and score/member pairs.
+/// With INCR it returns the new score instead, or nil if the flags blocked the update.
+pub async fn zadd(args: Vec<Frame>, state: &mut ClientState) -> Vec<u8> {
+    if args.len() < 3 {
+        return Frame::Error("ERR wrong number of arguments for 'zadd'".into()).encode();
+    }
+    let key = match &args[0] {
+        Frame::BulkString(Some(bs)) => String::from_utf8_lossy(bs).to_string(),
+        _ => return Frame::Error("ERR invalid key for 'zadd'".into()).encode(),
+    };
+
+    let mut opts = db::ZaddOptions::default();
+    let mut ch = false; // count changed members as well as added ones
+    let mut i = 1;
+    while let Some(Frame::BulkString(Some(flag))) = args.get(i) {
+        match flag.to_ascii_lowercase().as_slice() {
+            b"nx" => opts.nx = true,
+            b"xx" => opts.xx = true,
+            b"gt" => opts.gt = true,
+            b"lt" => opts.lt = true,
+            b"ch" => ch = true,
+            b"incr" => opts.incr = true,
+            _ => break,
+        }
+        i += 1;
+    }
+    let pairs = &args[i..];
+    if pairs.is_empty() || !pairs.len().is_multiple_of(2) {
+        return Frame::Error("ERR syntax error".into()).encode();
+    }
+    if opts.nx && opts.xx {
+        return Frame::Error("ERR XX and NX options at the same time are not compatible".into())
+            .encode();
+    }
+    if (opts.gt && opts.lt) || (opts.nx && (opts.gt || opts.lt)) {
+        return Frame::Error(
+            "ERR GT, LT, and/or NX options at the same time are not compatible".into(),
+        )
+        .encode();
+    }
+    if opts.incr && pairs.len() > 2 {
+        return Frame::Error("ERR INCR option supports a single increment-element pair".into())
+            .encode();
+    }
+
+    let mut members = Vec::with_capacity(pairs.len() / 2);
+    for pair in pairs.chunks(2) {
+        let score = match parse_score(&pair[0]) {
+            Ok(score) => score,
+            Err(e) => return e,
+        };
+        match &pair[1] {
+            Frame::BulkString(Some(member)) => members.push((score, member.clone())),
+            _ => return Frame::Error("ERR invalid member for 'zadd'".into()).encode(),
+        }
+    }
+    let (added, updated, score) = match db::zadd(&key, members, opts).await {
+        Ok(result) => result,
+        Err(e) => return Frame::Error(e).encode(),
+    };
+    if added + updated == 0 {
+        prevent_propagation();
+    }
+    match score {
+        _ if !opts.incr => Frame::Integer(if ch { added + updated } else { added }).encode(),
+        Some(score) => score_frame(score, state.resp3()).encode(),
+        None => Frame::BulkString(None).encode(),
+    }
+}
+
+/// ZSCORE command returns the score of a member of a sorted set, or nil.
+/// It expects a key and a member.
+pub async fn zscore(args: Vec<Frame>, state: &mut ClientState) -> Vec<u8> {
+    let [Frame::BulkString(Some(key)), Frame::BulkString(Some(member))] = args.as_slice() else {
+        return Frame::Error("ERR wrong number of arguments for 'zscore'".into()).encode();
+    };
+    match db::zscore(&String::from_utf8_lossy(key), member).await {
+        Ok(Some(score)) => score_frame(score, state.resp3()).encode(),
+        Ok(None) => Frame::BulkString(None).encode(),
+        Err(e) => Frame::Error(e).encode(),
+    }
+}
+
+/// ZINCRBY command adds an increment to the score of a sorted set member, adding
+/// the member at the increment if it is missing, and returns the new score.
+/// It expects a key, an increment and a member.
+pub async fn zincrby(args: Vec<Frame>, state: &mut ClientState) -> Vec<u8> {
+    let [Frame::BulkString(Some(key)), increment, Frame::BulkString(Some(member))] =
+        args.as_slice()
+    else {
+        return Frame::Error("ERR wrong number of arguments for 'zincrby'".into()).encode();
+    };
+    let increment = match parse_score(increment) {
+        Ok(increment) => increment,
+        Err(e) => return e,
+    };
+    let opts = db::ZaddOptions {
+        incr: true,
+        ..Default::default()
+    };
+    match db::zadd(
+        &String::from_utf8_lossy(key),
+        vec![(increment, member.clone())],
+        opts,
+    )
+    .await
+    {
+        Ok((added, updated, Some(score))) => {
+            if added + updated == 0 {
+                prevent_propagation();
+            }
+            score_frame(score, state.resp3()).encode()
+        }
+        Ok((.., None)) => Frame::BulkString(None).encode(),
+        Err(e) => Frame::Error(e).encode(),
+    }
+}
+
+/// ZMSCORE command returns the scores of several sorted set members, in argument
+/// order, with nil for members (or a key) that don't exist.
+/// It expects a key followed by one or more members.
+pub async fn zmscore(args: Vec<Frame>, state: &mut ClientState) -> Vec<u8> {
+    if args.len() < 2 {
+        return Frame::Error("ERR wrong number of arguments for 'zmscore'".into()).encode();
+    }
+    let mut names = Vec::with_capacity(args.len());
+    for arg in args {
+        match arg {
+            Frame::BulkString(Some(bs)) => names.push(bs),
+            _ => return Frame::Error("ERR invalid argument for 'zmscore'".into()).encode(),
+        }
+    }
+    let key = String::from_utf8_lossy(&names.remove(0)).to_string();
+    let resp3 = state.resp3();
+    match db::zmscore(&key, &names).await {
+        Ok(scores) => Frame::Array(Some(
+            scores
+                .into_iter()
+                .map(|score| match score {
+                    Some(score) => score_frame(score, resp3),
+                    None => Frame::BulkString(None),
+                })
+                .collect(),
+        ))
+        .encode(),
+        Err(e) => Frame::Error(e).encode(),
+    }
+}
+
+/// ZRANGEBYSCORE command returns the members of a sorted set with a score between
+/// min and max, lowest first. Bounds may be -inf/+inf and are excluded with a '(' prefix.
+/// It expects a key, min and max, then optional WITHSCORES and LIMIT offset count.
+pub async fn zrangebyscore(args: Vec<Frame>, state: &mut ClientState) -> Vec<u8> {
+    if args.len() < 3 {
+        return Frame::Error("ERR wrong number of arguments for 'zrangebyscore'".into()).encode();
+    }
+    let bulk = |frame: &Frame| match frame {
+        Frame::BulkString(Some(bs)) => Some(bs.clone()),
+        _ => None,
+    };
+    let (Some(key), Some(min), Some(max)) = (bulk(&args[0]), bulk(&args[1]), bulk(&args[2])) else {
+        return Frame::Error("ERR syntax error".into()).encode();
+    };
+    let (min, max) = match (db::ScoreBound::parse(&min), db::ScoreBound::parse(&max)) {
+        (Ok(min), Ok(max)) => (min, max),
+        (Err(e), _) | (_, Err(e)) => return Frame::Error(e).encode(),
+    };
+
+    let mut with_scores = false;
+    let mut offset = 0;
+    let mut count = None;
+    let mut i = 3;
+    while i < args.len() {
+        let option = bulk(&args[i]).unwrap_or_default().to_ascii_lowercase();
+        match option.as_slice() {
+            b"withscores" => with_scores = true,
+            b"limit" if i + 2 < args.len() => {
+                let (start, n) = match (parse_integer(&args[i + 1]), parse_integer(&args[i + 2])) {
+                    (Ok(start), Ok(n)) => (start, n),
+                    (Err(e), _) | (_, Err(e)) => return e,
+                };
+                if start < 0 {
+                    // A negative offset matches nothing, as in Redis
+                    return bulk_array(Vec::new());
+                }
+                offset = start as usize;
+                // A negative count means no limit
+                count = usize::try_from(n).ok();
+                i += 2;
+            }
+            _ => return Frame::Error("ERR syntax error".into()).encode(),
+        }
+        i += 1;
+    }
+
+    let key = String::from_utf8_lossy(&key);
+    let members = match db::zrange_by_score(&key, min, max, offset, count).await {
+        Ok(members) => members,
+        Err(e) => return Frame::Error(e).encode(),
+    };
+    if !with_scores {
+        return bulk_array(members.into_iter().map(|(m, _)| m).collect());
+    }
+    let resp3 = state.resp3();
+    let pairs = members
+        .into_iter()
+        .map(|(m, score)| (Frame::BulkString(Some(m)), score_frame(score, resp3)))
+        .collect();
+    pairs_array(pairs, resp3)
+}
+
+/// GEOADD command adds members at longitude/latitude positions to a geo set, a
+/// sorted set scored by geohash, and returns the number of members added.
+/// It expects a key, optional NX/XX/CH flags and longitude/latitude/member triples.
+pub async fn geoadd(args: Vec<Frame>) -> Vec<u8> {
+    let mut args = match bulk_args(args, "geoadd") {
+        Ok(args) if args.len() >= 4 => args,
+        Ok(_) => return Frame::Error("ERR wrong number of arguments for 'geoadd'".into()).encode(),
+        Err(e) => return e,
+    };
+    let key = String::from_utf8_lossy(&args.remove(0)).to_string();
+
+    let mut opts = db::ZaddOptions::default();
+    let mut ch = false;
+    let mut i = 0;
+    while let Some(flag) = args.get(i) {
+        match flag.to_ascii_lowercase().as_slice() {
+            b"nx" => opts.nx = true,
+            b"xx" => opts.xx = true,
+            b"ch" => ch = true,
+            _ => break,
+        }
+        i += 1;
+    }
+    let triples = &args[i..];
+    if triples.is_empty() || !triples.len().is_multiple_of(3) {
+        return Frame::Error(
+            "ERR syntax error. Try GEOADD key [x1] [y1] [name1] [x2] [y2] [name2] ... ".into(),
+        )
+        .encode();
+    }
+    if opts.nx && opts.xx {
+        return Frame::Error("ERR XX and NX options at the same time are not compatible".into())
+            .encode();
+    }
+
+    let mut members = Vec::with_capacity(triples.len() / 3);
+    for triple in triples.chunks(3) {
+        let (lon, lat) = match (db::parse_score(&triple[0]), db::parse_score(&triple[1])) {
+            (Ok(lon), Ok(lat)) => (lon, lat),
+            (Err(e), _) | (_, Err(e)) => return Frame::Error(e).encode(),
+        };
+        if !geo::valid(lon, lat) {
+            return Frame::Error(format!(
+                "ERR invalid longitude,latitude pair {:.6},{:.6}",
+                lon, lat
+            ))
+            .encode();
+        }
+        members.push((geo::encode(lon, lat), triple[2].clone()));
+    }
+    match db::zadd(&key, members, opts).await {
+        Ok((added, updated, _)) => {
+            if added + updated == 0 {
+                prevent_propagation();
+            }
+            Frame::Integer(if ch { added + updated } else { added }).encode()
+        }
+        Err(e) => Frame::Error(e).encode(),
+    }
+}
+
+/// GEOPOS command returns the longitude and latitude of members of a geo set,
+/// as two-element arrays, or nil for missing members.
+/// It expects a key followed by zero or more members.
+pub async fn geopos(args: Vec<Frame>, state: &mut ClientState) -> Vec<u8> {
+    let mut args = match bulk_args(args, "geopos") {
+        Ok(args) if !args.is_empty() => args,
+        Ok(_) => return Frame::Error("ERR wrong number of arguments for 'geopos'".into()).encode(),
+        Err(e) => return e,
+    };
+    let key = String::from_utf8_lossy(&args.remove(0)).to_string();
+    let resp3 = state.resp3();
+    match db::zmscore(&key, &args).await {
+        Ok(scores) => Frame::Array(Some(
+            scores
+                .into_iter()
+                .map(|score| match score {
+                    Some(score) => {
+                        let (lon, lat) = geo::decode(score);
+                        Frame::Array(Some(vec![score_frame(lon, resp3), score_frame(lat, resp3)]))
+                    }
+                    None => Frame::Array(None),
+                })
+                .collect(),
+        ))
+        .encode(),
+        Err(e) => Frame::Error(e).encode(),
+    }
+}
+
+/// GEODIST command returns the distance between two members of a geo set with
+/// four decimals, or nil if either is missing.
+/// It expects a key, two members and an optional unit: m (default), km, ft or mi.
+pub async fn geodist(args: Vec<Frame>) -> Vec<u8> {
+    let args = match bulk_args(args, "geodist") {
+        Ok(args) if (3..=4).contains(&args.len()) => args,
+        Ok(_) => return Frame::Error("ERR syntax error".into()).encode(),
+        Err(e) => return e,
+    };
+    let factor = match args.get(3) {
+        Some(unit) => match geo::unit_factor(unit) {
+            Some(factor) => factor,
+            None => {
+                return Frame::Error(
+                    "ERR unsupported unit provided. please use M, KM, FT, MI".into(),
+                )
+                .encode()
+            }
+        },
+        None => 1.0,
+    };
+    let key = String::from_utf8_lossy(&args[0]).to_string();
+    match db::zmscore(&key, &args[1..3]).await {
+        Ok(scores) => match scores.as_slice() {
+            [Some(a), Some(b)] => {
+                let (lon1, lat1) = geo::decode(*a);
+                let (lon2, lat2) = geo::decode(*b);
+                let meters = geo::distance(lon1, lat1, lon2, lat2);
+                Frame::BulkString(Some(format!("{:.4}", meters / factor).into_bytes())).encode()
+            }
+            _ => Frame::BulkString(None).encode(),
+        },
+        Err(e) => Frame::Error(e).encode(),
+    }
+}
+
+/// LPUSH/RPUSH commands push values onto the head or tail of a list,
+/// creating it if needed, and return the new length.
+/// It expects a key followed by one or more values.
+pub async fn push(args: Vec<Frame>, head: bool) -> Vec<u8> {
+    let cmd = if head { "lpush" } else { "rpush" };
+    if args.len() < 2 {
+        return Frame::Error(format!("ERR wrong number of arguments for '{}'", cmd)).encode();
+    }
+    let mut parts = Vec::with_capacity(args.len());
+    for arg in args {
+        match arg {
+            Frame::BulkString(Some(bs)) => parts.push(bs),
+            _ => return Frame::Error(format!("ERR invalid argument for '{}'", cmd)).encode(),
+        }
+    }
+    let key = String::from_utf8_lossy(&parts.remove(0)).to_string();
+    match db::push(&key, parts, head).await {
+        Ok(len) => Frame::Integer(len).encode(),
+        Err(e) => Frame::Error(e).encode(),
+    }
+}
+
+/// LPOP/RPOP commands remove and return the first or last element of a list
+/// (nil if empty), or, with a count, an array of up to count elements.
+/// It expects a key and an optional non-negative count.
+pub async fn pop(args: Vec<Frame>, head: bool) -> Vec<u8> {
+    let cmd = if head { "lpop" } else { "rpop" };
+    if args.is_empty() || args.len() > 2 {
+        return Frame::Error(format!("ERR wrong number of arguments for '{}'", cmd)).encode();
+    }
+    let key = match &args[0] {
+        Frame::BulkString(Some(bs)) => String::from_utf8_lossy(bs).to_string(),
+        _ => return Frame::Error(format!("ERR invalid key for '{}'", cmd)).encode(),
+    };
+    let count = match args.get(1).map(parse_integer) {
+        None => None,
+        Some(Ok(n)) if n >= 0 => Some(n as usize),
+        Some(Ok(_)) => {
+            return Frame::Error("ERR value is out of range, must be positive".into()).encode();
+        }
+        Some(Err(e)) => return e,
+    };
+
+    let items = match db::pop(&key, count.unwrap_or(1), head).await {
+        Ok(items) => items,
+        Err(e) => return Frame::Error(e).encode(),
+    };
+    if items.is_empty() {
+        prevent_propagation();
+    }
+    match items {
+        // Like Redis, a count against a missing key is a nil array rather than an empty one
+        items if count.is_some() && items.is_empty() => Frame::Array(None).encode(),
+        items if count.is_some() => bulk_array(items),
+        items => Frame::BulkString(items.into_iter().next()).encode(),
+    }
+}
+
+/// BLPOP/BRPOP commands pop from the first non-empty list among the keys,
+/// blocking until a push arrives if they are all empty.
+/// It expects one or more keys followed by a timeout in seconds (0 blocks forever),
+/// and returns [key, element], or a nil array on timeout.
+pub async fn blocking_pop(args: Vec<Frame>, head: bool, state: &ClientState) -> Vec<u8> {
+    let cmd = if head { "blpop" } else { "brpop" };
+    if args.len() < 2 {
+        return Frame::Error(format!("ERR wrong number of arguments for '{}'", cmd)).encode();
+    }
+    let mut parts = Vec::with_capacity(args.len());
+    for arg in args {
+        match arg {
+            Frame::BulkString(Some(bs)) => parts.push(bs),
+            _ => return Frame::Error(format!("ERR invalid argument for '{}'", cmd)).encode(),
+        }
+    }
+    let timeout = match parse_timeout(&parts.pop().unwrap_or_default()) {
+        Ok(timeout) => timeout,
+        Err(e) => return e,
+    };
+    let keys: Vec<String> = parts
+        .iter()
+        .map(|k| String::from_utf8_lossy(k).to_string())
+        .collect();
+
+    // A timeout too far in the future to represent just blocks forever
+    let deadline = timeout.and_then(|t| tokio::time::Instant::now().checked_add(t));
+    // Register before the first check so a push in between still wakes us.
+    // Inside a transaction there is no waiting: an empty key times out at once.
+    let waiter = (!state.in_exec).then(|| db::ListWaiter::new(&keys));
+    // Sent as the plain pop that served it, since a replica must never block
+    prevent_propagation();
+    loop {
+        let writes = serialise().await;
+        match db::pop_first(&keys, 1, head).await {
+            Ok(Some((key, values))) => {
+                let pop = if head { "LPOP" } else { "RPOP" };
+                propagate(vec![arg(pop), arg(key.as_str())]);
+                return bulk_array([vec![key.into_bytes()], values].concat());
+            }
+            Ok(None) => {}
+            Err(e) => return Frame::Error(e).encode(),
+        }
+        drop(writes);
+        let Some(waiter) = &waiter else {
+            return Frame::Array(None).encode();
+        };
+        match deadline {
+            Some(deadline) => {
+                if tokio::time::timeout_at(deadline, waiter.wait())
+                    .await
+                    .is_err()
+                {
+                    return Frame::Array(None).encode();
+                }
+            }
+            None => waiter.wait().await,
+        }
+    }
+}
+
+/// LMPOP and ZMPOP commands pop up to a count of elements (1 by default) from the first
+/// non-empty key among numkeys keys: from the LEFT or RIGHT of a list, or the members with
+/// the MIN or MAX scores of a sorted set. They reply [key, [elements...]], or
+/// [key, [[member, score]...]] for ZMPOP, or a nil array if every key is empty.
+/// BLMPOP and BZMPOP take a timeout ahead of numkeys and block until an element arrives.
+pub async fn mpop(args: Vec<Frame>, cmd: &str, state: &mut ClientState) -> Vec<u8> {
+    let blocking = cmd.starts_with('b');
+    let zset = cmd.ends_with("zmpop");
+    let wrong_args = || Frame::Error(format!("ERR wrong number of arguments for '{}'", cmd));
+    let (deadline, args) = if blocking {
+        let Some(Frame::BulkString(Some(timeout))) = args.first() else {
+            return wrong_args().encode();
+        };
+        match parse_timeout(timeout) {
+            // A timeout too far in the future to represent just blocks forever
+            Ok(timeout) => (
+                timeout.and_then(|t| tokio::time::Instant::now().checked_add(t)),
+                &args[1..],
+            ),
+            Err(e) => return e,
+        }
+    } else {
+        (None, &args[..])
+    };
+    let Some(numkeys) = args.first() else {
+        return wrong_args().encode();
+    };
+    let numkeys = match parse_integer(numkeys) {
+        Ok(n) if n > 0 => n as usize,
+        Ok(_) => return Frame::Error("ERR numkeys should be greater than 0".into()).encode(),
+        Err(e) => return e,
+    };
+    let Some(options) = args
+        .get(numkeys + 1..)
+        .filter(|options| !options.is_empty())
+    else {
+        return Frame::Error("ERR syntax error".into()).encode();
+    };
+    let mut keys = Vec::with_capacity(numkeys);
+    for arg in &args[1..=numkeys] {
+        match arg {
+            Frame::BulkString(Some(bs)) => keys.push(String::from_utf8_lossy(bs).to_string()),
+            _ => return Frame::Error(format!("ERR invalid key for '{}'", cmd)).encode(),
+        }
+    }
+    let ends: [&[u8]; 2] = if zset {
+        [b"MIN", b"MAX"]
+    } else {
+        [b"LEFT", b"RIGHT"]
+    };
+    let first_end = match &options[0] {
+        Frame::BulkString(Some(end)) if end.eq_ignore_ascii_case(ends[0]) => true,
+        Frame::BulkString(Some(end)) if end.eq_ignore_ascii_case(ends[1]) => false,
+        _ => return Frame::Error("ERR syntax error".into()).encode(),
+    };
+    let count = match &options[1..] {
+        [] => 1,
+        [Frame::BulkString(Some(opt)), count] if opt.eq_ignore_ascii_case(b"COUNT") => {
+            match parse_integer(count) {
+                Ok(n) if n > 0 => n as usize,
+                Ok(_) => return Frame::Error("ERR count should be greater than 0".into()).encode(),
+                Err(e) => return e,
+            }
+        }
+        _ => return Frame::Error("ERR syntax error".into()).encode(),
+    };
+
+    // Register before the first check so a push in between still wakes us.
+    // Inside a transaction the blocking forms don't wait, like the plain ones.
+    let waiter = (blocking && !state.in_exec).then(|| db::ListWaiter::new(&keys));
+    // Sent as a pop from the key it was served from, of how many it popped, so a replay
+    // can't pick another key or block
+    prevent_propagation();
+    loop {
+        let writes = serialise().await;
+        let popped = if zset {
+            db::zpop_first(&keys, count, first_end).await.map(|popped| {
+                popped.map(|(key, members)| {
+                    let resp3 = state.resp3();
+                    let members: Vec<Frame> = members
+                        .into_iter()
+                        .map(|(member, score)| {
+                            Frame::Array(Some(vec![
+                                Frame::BulkString(Some(member)),
+                                score_frame(score, resp3),
+                            ]))
+                        })
+                        .collect();
+                    (key, members)
+                })
+            })
+        } else {
+            db::pop_first(&keys, count, first_end).await.map(|popped| {
+                popped.map(|(key, values)| {
+                    let values = values
+                        .into_iter()
+                        .map(|value| Frame::BulkString(Some(value)))
+                        .collect();
+                    (key, values)
+                })
+            })
+        };
+        match popped {
+            Ok(Some((key, elements))) => {
+                propagate(vec![
+                    arg(if zset { "ZMPOP" } else { "LMPOP" }),
+                    arg("1"),
+                    arg(key.as_str()),
+                    options[0].clone(),
+                    arg("COUNT"),
+                    arg(elements.len().to_string()),
+                ]);
+                return Frame::Array(Some(vec![
+                    Frame::BulkString(Some(key.into_bytes())),
+                    Frame::Array(Some(elements)),
+                ]))
+                .encode();
+            }
+            Ok(None) => {}
+            Err(e) => return Frame::Error(e).encode(),
+        }
+        drop(writes);
+        let Some(waiter) = &waiter else {
+            return Frame::Array(None).encode();
+        };
+        match deadline {
+            Some(deadline) => {
+                if tokio::time::timeout_at(deadline, waiter.wait())
+                    .await
+                    .is_err()
+                {
+                    return Frame::Array(None).encode();
+                }
+            }
+            None => waiter.wait().await,
+        }
+    }
+}
+
+/// LMOVE, RPOPLPUSH, BLMOVE and BRPOPLPUSH commands pop an element from one end of a
+/// list and push it onto one end of another, returning it. The blocking forms wait up to
+/// a timeout for the source to receive a push, and return nil if it doesn't.
+/// LMOVE expects source, destination, LEFT|RIGHT and LEFT|RIGHT, RPOPLPUSH just source
+/// and destination (right to left); the blocking forms take a timeout after those.
+pub async fn lmove(args: Vec<Frame>, cmd: &str, state: &ClientState) -> Vec<u8> {
+    let parts = match bulk_args(args, cmd) {
+        Ok(parts) => parts,
+        Err(e) => return e,
+    };
+    let blocking = cmd.starts_with('b');
+    let with_ends = cmd.ends_with("lmove");
+    let expected = 2 + if with_ends { 2 } else { 0 } + if blocking { 1 } else { 0 };
+    if parts.len() != expected {
+        return Frame::Error(format!("ERR wrong number of arguments for '{}'", cmd)).encode();
+    }
+    let source = String::from_utf8_lossy(&parts[0]).to_string();
+    let destination = String::from_utf8_lossy(&parts[1]).to_string();
+    let (from_head, to_head) = if with_ends {
+        let end = |raw: &[u8]| match raw.to_ascii_lowercase().as_slice() {
+            b"left" => Some(true),
+            b"right" => Some(false),
+            _ => None,
+        };
+        match (end(&parts[2]), end(&parts[3])) {
+            (Some(from), Some(to)) => (from, to),
+            _ => return Frame::Error("ERR syntax error".into()).encode(),
+        }
+    } else {
+        (false, true)
+    };
+    let deadline = if blocking {
+        match parse_timeout(&parts[expected - 1]) {
+            // A timeout too far in the future to represent just blocks forever
+            Ok(timeout) => timeout.and_then(|t| tokio::time::Instant::now().checked_add(t)),
+            Err(e) => return e,
+        }
+    } else {
+        None
+    };
+
+    // Register before the first check so a push in between still wakes us.
+    // Inside a transaction the blocking forms don't wait, like the plain ones.
+    let waiter =
+        (blocking && !state.in_exec).then(|| db::ListWaiter::new(std::slice::from_ref(&source)));
+    // The blocking forms are sent as the plain move that served them
+    if blocking {
+        prevent_propagation();
+    }
+    loop {
+        // Each attempt re-checks the source under the lock, since another
+        // client woken by the same push may have emptied it first
+        let writes = serialise().await;
+        match db::lmove(&source, &destination, from_head, to_head).await {
+            Ok(Some(element)) => {
+                if blocking {
+                    let mut command = vec![arg(if with_ends { "LMOVE" } else { "RPOPLPUSH" })];
+                    command.extend(
+                        parts[..expected - 1]
+                            .iter()
+                            .map(|part| arg(part.as_slice())),
+                    );
+                    propagate(command);
+                }
+                return Frame::BulkString(Some(element)).encode();
+            }
+            Ok(None) => {}
+            Err(e) => return Frame::Error(e).encode(),
+        }
+        drop(writes);
+        let Some(waiter) = &waiter else {
+            // Nothing was moved
+            prevent_propagation();
+            return Frame::BulkString(None).encode();
+        };
+        match deadline {
+            Some(deadline) => {
+                if tokio::time::timeout_at(deadline, waiter.wait())
+                    .await
+                    .is_err()
+                {
+                    return Frame::BulkString(None).encode();
+                }
+            }
+            None => waiter.wait().await,
+        }
+    }
+}
+
+/// Parse a blocking command timeout in (possibly fractional) seconds.
+/// Zero means block forever and is returned as None.
+fn parse_timeout(raw: &[u8]) -> Result<Option<std::time::Duration>, Vec<u8>> {
+    let secs = String::from_utf8_lossy(raw)
+        .parse::<f64>()
+        .ok()
+        .filter(|s| s.is_finite())
+        .ok_or_else(|| {
+            Frame::Error("ERR timeout is not a float or out of range".into()).encode()
+        })?;
+    if secs < 0.0 {
+        return Err(Frame::Error("ERR timeout is negative".into()).encode());
+    }
+    if secs == 0.0 {
+        return Ok(None);
+    }
+    std::time::Duration::try_from_secs_f64(secs)
+        .map(Some)
+        .map_err(|_| Frame::Error("ERR timeout is out of range".into()).encode())
+}
+
+/// LRANGE command returns the elements of a list between start and stop (inclusive).
+/// It expects a key, a start index and a stop index; negative indices count from the tail.
+pub async fn lrange(args: Vec<Frame>) -> Vec<u8> {
+    if args.len() != 3 {
+        return Frame::Error("ERR wrong number of arguments for 'lrange'".into()).encode();
+    }
+    let key = match &args[0] {
+        Frame::BulkString(Some(bs)) => String::from_utf8_lossy(bs).to_string(),
+        _ => return Frame::Error("ERR invalid key for 'lrange'".into()).encode(),
+    };
+    let (start, stop) = match (parse_integer(&args[1]), parse_integer(&args[2])) {
+        (Ok(start), Ok(stop)) => (start, stop),
+        (Err(e), _) | (_, Err(e)) => return e,
+    };
+    match db::lrange(&key, start, stop).await {
+        Ok(items) => bulk_array(items),
+        Err(e) => Frame::Error(e).encode(),
+    }
+}
+
+/// LPOS command returns the index of the first element of a list equal to a value,
+/// or, with COUNT, an array of up to that many matching indices (0 means all).
+/// It expects a key and an element, optionally followed by RANK, COUNT and MAXLEN.
+pub async fn lpos(args: Vec<Frame>) -> Vec<u8> {
+    if args.len() < 2 {
+        return Frame::Error("ERR wrong number of arguments for 'lpos'".into()).encode();
+    }
+    let (key, element) = match (&args[0], &args[1]) {
+        (Frame::BulkString(Some(key)), Frame::BulkString(Some(element))) => {
+            (String::from_utf8_lossy(key).to_string(), element)
+        }
+        _ => return Frame::Error("ERR invalid argument for 'lpos'".into()).encode(),
+    };
+
+    let mut rank = 1;
+    let mut count = None;
+    let mut maxlen = 0;
+    let mut options = args[2..].iter();
+    while let Some(option) = options.next() {
+        let Frame::BulkString(Some(name)) = option else {
+            return Frame::Error("ERR syntax error".into()).encode();
+        };
+        let Some(value) = options.next() else {
+            return Frame::Error("ERR syntax error".into()).encode();
+        };
+        let value = match parse_integer(value) {
+            Ok(n) => n,
+            Err(e) => return e,
+        };
+        match name.to_ascii_lowercase().as_slice() {
+            b"rank" if value == 0 || value == i64::MIN => {
+                return Frame::Error(
+                    "ERR RANK can't be zero: use 1 to start from the first match, 2 from the second ... or use negative to start from the end of the list".into(),
+                )
+                .encode();
+            }
+            b"rank" => rank = value,
+            b"count" if value < 0 => {
+                return Frame::Error("ERR COUNT can't be negative".into()).encode();
+            }
+            b"count" => count = Some(value as usize),
+            b"maxlen" if value < 0 => {
+                return Frame::Error("ERR MAXLEN can't be negative".into()).encode();
+            }
+            b"maxlen" => maxlen = value as usize,
+            _ => return Frame::Error("ERR syntax error".into()).encode(),
+        }
+    }
+
+    match db::lpos(&key, element, rank, count.unwrap_or(1), maxlen).await {
+        Ok(indices) if count.is_some() => {
+            Frame::Array(Some(indices.into_iter().map(Frame::Integer).collect())).encode()
+        }
+        Ok(indices) => match indices.first() {
+            Some(&index) => Frame::Integer(index).encode(),
+            None => Frame::BulkString(None).encode(),
+        },
+        Err(e) => Frame::Error(e).encode(),
+    }
+}
+
+/// LREM command removes up to count occurrences of a value from a list:
+/// from the head if count > 0, from the tail if count < 0, and all if count is 0.
+/// It expects a key, a count and a value, and returns how many were removed.
+pub async fn lrem(args: Vec<Frame>) -> Vec<u8> {
+    if args.len() != 3 {
+        return Frame::Error("ERR wrong number of arguments for 'lrem'".into()).encode();
+    }
+    let key = match &args[0] {
+        Frame::BulkString(Some(bs)) => String::from_utf8_lossy(bs).to_string(),
+        _ => return Frame::Error("ERR invalid key for 'lrem'".into()).encode(),
+    };
+    let count = match parse_integer(&args[1]) {
+        Ok(n) => n,
+        Err(e) => return e,
+    };
+    let value = match &args[2] {
+        Frame::BulkString(Some(bs)) => bs,
+        _ => return Frame::Error("ERR invalid value for 'lrem'".into()).encode(),
+    };
+    match db::lrem(&key, count, value).await {
+        Ok(removed) => {
+            if removed == 0 {
+                prevent_propagation();
+            }
+            Frame::Integer(removed).encode()
+        }
+        Err(e) => Frame::Error(e).encode(),
+    }
+}
+
+/// LTRIM command trims a list so it only holds the inclusive start..stop range.
+/// It expects a key, a start index and a stop index; negative indices count from the tail.
+pub async fn ltrim(args: Vec<Frame>) -> Vec<u8> {
+    if args.len() != 3 {
+        return Frame::Error("ERR wrong number of arguments for 'ltrim'".into()).encode();
+    }
+    let key = match &args[0] {
+        Frame::BulkString(Some(bs)) => String::from_utf8_lossy(bs).to_string(),
+        _ => return Frame::Error("ERR invalid key for 'ltrim'".into()).encode(),
+    };
+    let (start, stop) = match (parse_integer(&args[1]), parse_integer(&args[2])) {
+        (Ok(start), Ok(stop)) => (start, stop),
+        (Err(e), _) | (_, Err(e)) => return e,
+    };
+    match db::ltrim(&key, start, stop).await {
+        Ok(removed) => {
+            if removed == 0 {
+                prevent_propagation();
+            }
+            Frame::SimpleString("OK".into()).encode()
+        }
+        Err(e) => Frame::Error(e).encode(),
+    }
+}
+
+/// LINSERT command inserts a value before or after a pivot element of a list.
+/// It expects a key, BEFORE or AFTER, the pivot and the value, and returns the new
+/// length, -1 if the pivot wasn't found, or 0 if the key doesn't exist.
+pub async fn linsert(args: Vec<Frame>) -> Vec<u8> {
+    let [key, position, pivot, value] = match <[Frame; 4]>::try_from(args) {
+        Ok(args) => args,
+        Err(_) => {
+            return Frame::Error("ERR wrong number of arguments for 'linsert'".into()).encode()
+        }
+    };
+    let (
+        Frame::BulkString(Some(key)),
+        Frame::BulkString(Some(position)),
+        Frame::BulkString(Some(pivot)),
+        Frame::BulkString(Some(value)),
+    ) = (key, position, pivot, value)
+    else {
+        return Frame::Error("ERR invalid argument for 'linsert'".into()).encode();
+    };
+    let before = match String::from_utf8_lossy(&position).to_lowercase().as_str() {
+        "before" => true,
+        "after" => false,
+        _ => return Frame::Error("ERR syntax error".into()).encode(),
+    };
+    let key = String::from_utf8_lossy(&key).to_string();
+    match db::linsert(&key, before, &pivot, value).await {
+        Ok(len) => {
+            // A missing key or pivot leaves the list as it was
+            if len <= 0 {
+                prevent_propagation();
+            }
+            Frame::Integer(len).encode()
+        }
+        Err(e) => Frame::Error(e).encode(),
+    }
+}
+
+/// Options shared by the SCAN family of commands
+struct ScanOptions {
+    cursor: u64,
+    count: usize,
+    pattern: Option<String>,
+    type_filter: Option<String>,
+    novalues: bool,
+}
+
+/// Parse "cursor [MATCH pattern] [COUNT count] [TYPE type] [NOVALUES]" for cmd;
+/// TYPE is only valid for SCAN and NOVALUES only for HSCAN.
+fn parse_scan_options(args: &[Frame], cmd: &str) -> Result<ScanOptions, Vec<u8>> {
+    let mut parts = Vec::with_capacity(args.len());
+    for arg in args {
+        match arg {
+            Frame::BulkString(Some(bs)) => parts.push(String::from_utf8_lossy(bs).to_string()),
+            _ => return Err(Frame::Error("ERR syntax error".into()).encode()),
+        }
+    }
+    let Some(cursor) = parts.first() else {
+        return Err(Frame::Error("ERR syntax error".into()).encode());
+    };
+    let cursor = cursor
+        .parse::<u64>()
+        .map_err(|_| Frame::Error("ERR invalid cursor".into()).encode())?;
+
+    let mut options = ScanOptions {
+        cursor,
+        count: 10,
+        pattern: None,
+        type_filter: None,
+        novalues: false,
+    };
+    let mut rest = parts[1..].iter();
+    while let Some(option) = rest.next() {
+        if cmd == "hscan" && option.eq_ignore_ascii_case("novalues") {
+            options.novalues = true;
+            continue;
+        }
+        let Some(value) = rest.next() else {
+            return Err(Frame::Error("ERR syntax error".into()).encode());
+        };
+        match option.to_lowercase().as_str() {
+            "match" => options.pattern = Some(value.clone()),
+            "count" => {
+                options.count = match value.parse::<i64>() {
+                    Ok(n) if n >= 1 => n as usize,
+                    Ok(_) => return Err(Frame::Error("ERR syntax error".into()).encode()),
+                    Err(_) => {
+                        return Err(Frame::Error(
+                            "ERR value is not an integer or out of range".into(),
+                        )
+                        .encode())
+                    }
+                }
+            }
+            "type" if cmd == "scan" => options.type_filter = Some(value.to_lowercase()),
+            _ => return Err(Frame::Error("ERR syntax error".into()).encode()),
+        }
+    }
+    Ok(options)
+}
+
+/// Encode the [cursor, elements] reply shared by the SCAN family
+fn scan_reply(cursor: u64, items: Vec<Vec<u8>>) -> Vec<u8> {
+    Frame::Array(Some(vec![
+        Frame::BulkString(Some(cursor.to_string().into_bytes())),
+        Frame::Array(Some(
+            items
+                .into_iter()
+                .map(|item| Frame::BulkString(Some(item)))
+                .collect(),
+        )),
+    ]))
+    .encode()
+}
+
+/// SCAN command incrementally iterates the keyspace.
+/// It expects a cursor and optional MATCH pattern, COUNT hint and TYPE filter.
+pub async fn scan(args: Vec<Frame>) -> Vec<u8> {
+    if args.is_empty() {
+        return Frame::Error("ERR wrong number of arguments for 'scan'".into()).encode();
+    }
+    let options = match parse_scan_options(&args, "scan") {
+        Ok(options) => options,
+        Err(e) => return e,
+    };
+    let (next, keys) = db::scan(
+        options.cursor,
+        options.count,
+        options.pattern.as_deref(),
+        options.type_filter.as_deref(),
+    )
+    .await;
+    scan_reply(next, keys.into_iter().map(String::into_bytes).collect())
+}
+
+/// HSCAN/SSCAN/ZSCAN commands incrementally iterate a hash's fields and values,
+/// a set's members, or a sorted set's members and scores.
+/// It expects a key, a cursor and optional MATCH pattern and COUNT hint; HSCAN also
+/// takes NOVALUES, to return the fields alone.
+pub async fn scan_members(args: Vec<Frame>, type_name: &str) -> Vec<u8> {
+    let cmd = match type_name {
+        "hash" => "hscan",
+        "set" => "sscan",
+        _ => "zscan",
+    };
+    if args.len() < 2 {
+        return Frame::Error(format!("ERR wrong number of arguments for '{}'", cmd)).encode();
+    }
+    let key = match &args[0] {
+        Frame::BulkString(Some(bs)) => String::from_utf8_lossy(bs).to_string(),
+        _ => return Frame::Error(format!("ERR invalid key for '{}'", cmd)).encode(),
+    };
+    let options = match parse_scan_options(&args[1..], cmd) {
+        Ok(options) => options,
+        Err(e) => return e,
+    };
+    match db::scan_members(
+        &key,
+        type_name,
+        options.cursor,
+        options.count,
+        options.pattern.as_deref(),
+    )
+    .await
+    {
+        Ok((next, items)) if options.novalues => {
+            scan_reply(next, items.into_iter().step_by(2).collect())
+        }
+        Ok((next, items)) => scan_reply(next, items),
+        Err(e) => Frame::Error(e).encode(),
+    }
+}
+
+/// SUBSCRIBE/PSUBSCRIBE commands subscribe the connection to channels or patterns,
+/// replying with one confirmation per argument carrying the running subscription count.
+/// It expects one or more channel names or glob patterns.
+pub async fn subscribe(args: Vec<Frame>, state: &mut ClientState, pattern: bool) -> Vec<u8> {
+    let cmd = if pattern { "psubscribe" } else { "subscribe" };
+    if args.is_empty() {
+        return Frame::Error(format!("ERR wrong number of arguments for '{}'", cmd)).encode();
+    }
+    let mut reply = Vec::new();
+    for arg in args {
+        let Frame::BulkString(Some(name)) = arg else {
+            return Frame::Error(format!("ERR invalid argument for '{}'", cmd)).encode();
+        };
+        pubsub::subscribe(state, &name, pattern);
+        reply.extend(subscription_reply(cmd, Some(name), state));
+    }
+    reply
+}
+
+/// UNSUBSCRIBE/PUNSUBSCRIBE commands drop channel or pattern subscriptions,
+/// replying with one confirmation per name carrying the remaining subscription count.
+/// It expects zero or more names; with none, every subscription of that kind is dropped.
+pub async fn unsubscribe(args: Vec<Frame>, state: &mut ClientState, pattern: bool) -> Vec<u8> {
+    let cmd = if pattern {
+        "punsubscribe"
+    } else {
+        "unsubscribe"
+    };
+    let mut names = Vec::with_capacity(args.len());
+    for arg in args {
+        match arg {
+            Frame::BulkString(Some(name)) => names.push(name),
+            _ => return Frame::Error(format!("ERR invalid argument for '{}'", cmd)).encode(),
+        }
+    }
+    if names.is_empty() {
+        let current = if pattern {
+            &state.patterns
+        } else {
+            &state.channels
+        };
+        names = current.iter().cloned().collect();
+        if names.is_empty() {
+            return subscription_reply(cmd, None, state);
+        }
+    }
+    let mut reply = Vec::new();
+    for name in names {
+        pubsub::unsubscribe(state, &name, pattern);
+        reply.extend(subscription_reply(cmd, Some(name), state));
+    }
+    reply
+}
+
+/// Encode a [kind, name, count] (un)subscribe confirmation, framed like a message
+fn subscription_reply(kind: &str, name: Option<Vec<u8>>, state: &ClientState) -> Vec<u8> {
+    pubsub::message_frame(
+        vec![
+            Frame::BulkString(Some(kind.as_bytes().to_vec())),
+            Frame::BulkString(name),
+            Frame::Integer(state.subscriber_count() as i64),
+        ],
+        state.resp3(),
+    )
+    .encode()
+}
+
+/// PUBLISH command sends a message to a channel and returns how many subscribers got it.
+/// It expects a channel and a message.
+pub async fn publish(args: Vec<Frame>) -> Vec<u8> {
+    match args.as_slice() {
+        [Frame::BulkString(Some(channel)), Frame::BulkString(Some(message))] => {
+            Frame::Integer(pubsub::publish(channel, message)).encode()
+        }
+        [_, _] => Frame::Error("ERR invalid argument for 'publish'".into()).encode(),
+        _ => Frame::Error("ERR wrong number of arguments for 'publish'".into()).encode(),
+    }
+}
+
+/// PUBSUB CHANNELS command lists the channels that have subscribers.
+/// It expects an optional glob pattern to filter them by.
+pub async fn pubsub_channels(args: Vec<Frame>) -> Vec<u8> {
+    let pattern = match args.as_slice() {
+        [] => None,
+        [Frame::BulkString(Some(bs))] => Some(String::from_utf8_lossy(bs).to_string()),
+        [_] => return Frame::Error("ERR invalid argument for 'pubsub channels'".into()).encode(),
+        _ => {
+            return Frame::Error("ERR wrong number of arguments for 'pubsub channels'".into())
+                .encode()
+        }
+    };
+    bulk_array(pubsub::channels(pattern.as_deref()))
+}
+
+/// PUBSUB NUMSUB command returns channel/subscriber-count pairs for the given channels.
+/// It expects zero or more channel names.
+pub async fn pubsub_numsub(args: Vec<Frame>) -> Vec<u8> {
+    let mut reply = Vec::with_capacity(args.len() * 2);
+    for arg in args {
+        let Frame::BulkString(Some(channel)) = arg else {
+            return Frame::Error("ERR invalid argument for 'pubsub numsub'".into()).encode();
+        };
+        let count = pubsub::numsub(&channel) as i64;
+        reply.push(Frame::BulkString(Some(channel)));
+        reply.push(Frame::Integer(count));
+    }
+    Frame::Array(Some(reply)).encode()
+}
+
+/// PUBSUB NUMPAT command returns the number of patterns with subscribers.
+/// It expects no arguments.
+pub async fn pubsub_numpat(args: Vec<Frame>) -> Vec<u8> {
+    if !args.is_empty() {
+        return Frame::Error("ERR wrong number of arguments for 'pubsub numpat'".into()).encode();
+    }
+    Frame::Integer(pubsub::numpat() as i64).encode()
+}
+
+/// DEBUG RELOAD command saves the dataset to the RDB file and loads it back,
+/// verifying that everything survives a persistence round trip.
+/// It expects no arguments.
+pub async fn debug_reload(args: Vec<Frame>) -> Vec<u8> {
+    if !args.is_empty() {
+        return Frame::Error("ERR wrong number of arguments for 'debug reload'".into()).encode();
+    }
+    if let Err(e) = rdb::save().await {
+        return Frame::Error(format!("ERR {}", e)).encode();
+    }
+    let path = config::get_dir().join(config::get_dbfilename());
+    let loaded = match rdb::RdbParser::load(&path) {
+        Ok(loaded) => loaded,
+        Err(e) => {
+            return Frame::Error(format!("ERR Error trying to load the RDB dump: {}", e)).encode()
+        }
+    };
+    match db::load_from_rdb(loaded).await {
+        Ok(()) => Frame::SimpleString("OK".into()).encode(),
+        Err(e) => Frame::Error(format!("ERR {}", e)).encode(),
+    }
+}
+
+/// DEBUG CHANGE-REPL-ID command gives the server a new random replication ID.
+/// It expects no arguments.
+pub async fn debug_change_repl_id(args: Vec<Frame>) -> Vec<u8> {
+    if !args.is_empty() {
+        return Frame::Error("ERR wrong number of arguments for 'debug change-repl-id'".into())
+            .encode();
+    }
+    replication::change_replid();
+    Frame::SimpleString("OK".into()).encode()
+}
+
+/// DEBUG OBJECT command describes the value stored at a key: its refcount, encoding,
+/// RDB serialized length and LRU state, and for a quicklist, the nodes Redis would
+/// split it into. There is no real object address to show.
+pub async fn debug_object(args: Vec<Frame>) -> Vec<u8> {
+    let [Frame::BulkString(Some(key))] = args.as_slice() else {
+        return Frame::Error("ERR wrong number of arguments for 'debug object'".into()).encode();
+    };
+    let key = String::from_utf8_lossy(key);
+    let (Some(value), Some(meta)) = (db::peek_value(&key).await, db::access_meta(&key).await)
+    else {
+        return Frame::Error("ERR no such key".into()).encode();
+    };
+    let idle = meta.last_access.elapsed().as_secs();
+    // Redis' LRU clock counts seconds in 24 bits
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let lru = now.saturating_sub(idle) & ((1 << 24) - 1);
+    let encoding = db::encoding(&value);
+    let mut description = format!(
+        "Value at:0x0 refcount:{} encoding:{} serializedlength:{} lru:{} lru_seconds_idle:{}",
+        refcount(&value),
+        encoding,
+        db::serialized_length(&value),
+        lru,
+        idle
+    );
+    if let (RedisValue::List(items), "quicklist") = (&value, encoding) {
+        let nodes = db::quicklist_nodes(items);
+        // Nodes are never compressed, list-compress-depth being 0
+        description.push_str(&format!(
+            " ql_nodes:{} ql_avg_node:{:.2} ql_listpack_max:{} ql_compressed:0 ql_uncompressed_size:{}",
+            nodes.len(),
+            items.len() as f64 / nodes.len() as f64,
+            config::get_config().list_max_listpack_size,
+            nodes.iter().map(|(_, bytes)| bytes).sum::<usize>()
+        ));
+    }
+    Frame::SimpleString(description).encode()
+}
+
+/// DEBUG QUICKLIST command prints the quicklist nodes a list is split into on stdout,
+/// with every node's entries if the optional level is 1 (0 by default).
+/// It expects a key and optionally the level, and fails for lists kept in one listpack.
+pub async fn debug_quicklist(args: Vec<Frame>) -> Vec<u8> {
+    let (key, full) = match args.as_slice() {
+        [Frame::BulkString(Some(key))] => (key, false),
+        // Read as atoi reads it, anything but a number being 0
+        [Frame::BulkString(Some(key)), Frame::BulkString(Some(level))] => (
+            key,
+            String::from_utf8_lossy(level)
+                .trim()
+                .parse::<i64>()
+                .unwrap_or(0)
+                != 0,
+        ),
+        _ => {
+            return Frame::Error("ERR wrong number of arguments for 'debug quicklist'".into())
+                .encode()
+        }
+    };
+    let Some(value) = db::peek_value(&String::from_utf8_lossy(key)).await else {
+        return Frame::Error("ERR no such key".into()).encode();
+    };
+    match (&value, db::encoding(&value)) {
+        (RedisValue::List(items), "quicklist") => {
+            print!("{}", db::quicklist_repr(items, full));
+            Frame::SimpleString("Quicklist structure printed on stdout".into()).encode()
+        }
+        _ => Frame::Error("ERR Not a quicklist encoded object.".into()).encode(),
+    }
+}
+
+/// DEBUG SLEEP command delays the reply by the given number of seconds.
+/// With emulate-single-thread, dispatch holds every other command back meanwhile.
+/// It expects a single argument which is the (possibly fractional) number of seconds.
+pub async fn debug_sleep(args: Vec<Frame>) -> Vec<u8> {
+    let [Frame::BulkString(Some(secs))] = args.as_slice() else {
+        return Frame::Error("ERR wrong number of arguments for 'debug sleep'".into()).encode();
+    };
+    let delay = String::from_utf8_lossy(secs)
+        .parse::<f64>()
+        .ok()
+        .and_then(|s| tokio::time::Duration::try_from_secs_f64(s).ok());
+    let Some(delay) = delay else {
+        return Frame::Error("ERR value is not a valid float".into()).encode();
+    };
+    tokio::time::sleep(delay).await;
+    Frame::SimpleString("OK".into()).encode()
+}
+
+/// DEBUG SET-ACTIVE-EXPIRE command turns the background purge of expired keys off (0)
+/// or back on (any other integer); keys still expire when a command reads them.
+/// It expects a single integer argument.
+pub async fn debug_set_active_expire(args: Vec<Frame>) -> Vec<u8> {
+    let [flag] = args.as_slice() else {
+        return Frame::Error("ERR wrong number of arguments for 'debug set-active-expire'".into())
+            .encode();
+    };
+    match parse_integer(flag) {
+        Ok(flag) => {
+            db::set_active_expire(flag != 0);
+            Frame::SimpleString("OK".into()).encode()
+        }
+        Err(e) => e,
+    }
+}
+
+/// DEBUG STRINGMATCH-LEN command reports whether a string matches a glob-style
+/// pattern, using the matcher of KEYS, SCAN and PSUBSCRIBE.
+/// It expects a pattern and a string, and returns 1 or 0.
+pub async fn debug_stringmatch_len(args: Vec<Frame>) -> Vec<u8> {
+    let [Frame::BulkString(Some(pattern)), Frame::BulkString(Some(string))] = args.as_slice()
+    else {
+        return Frame::Error("ERR wrong number of arguments for 'debug stringmatch-len'".into())
+            .encode();
+    };
+    Frame::Integer(db::glob_match(pattern, string) as i64).encode()
+}
+
+/// DEBUG DUMPKEYS command returns the whole keyspace as a JSON object of
+/// key -> {"type", "value"}, for tests to assert against.
+/// It expects no arguments.
+pub async fn debug_dumpkeys(args: Vec<Frame>) -> Vec<u8> {
+    if !args.is_empty() {
+        return Frame::Error("ERR wrong number of arguments for 'debug dumpkeys'".into()).encode();
+    }
+    Frame::BulkString(Some(db::dump_keys_json().await.into_bytes())).encode()
+}
+
+/// COMMAND COUNT returns the number of commands the server knows about.
+pub async fn command_count(args: Vec<Frame>) -> Vec<u8> {
+    if !args.is_empty() {
+        return Frame::Error("ERR wrong number of arguments for 'command count'".into()).encode();
+    }
+    Frame::Integer(registry::COMMANDS.len() as i64).encode()
+}
+
+/// COMMAND INFO returns the arity, flags and key positions of the named commands,
+/// or of every command if none are named. Unknown commands get a nil in their place.
+pub async fn command_info(args: Vec<Frame>) -> Vec<u8> {
+    let names = match bulk_args(args, "command info") {
+        Ok(names) => names,
+        Err(e) => return e,
+    };
+    let specs: Vec<Option<&registry::CommandSpec>> = if names.is_empty() {
+        registry::COMMANDS.iter().map(Some).collect()
+    } else {
+        names
+            .iter()
+            .map(|name| registry::lookup(&String::from_utf8_lossy(name)))
+            .collect()
+    };
+    let entries = specs
+        .into_iter()
+        .map(|spec| match spec {
+            None => Frame::Array(None),
+            Some(spec) => Frame::Array(Some(vec![
+                Frame::BulkString(Some(spec.name.as_bytes().to_vec())),
+                Frame::Integer(spec.arity as i64),
+                Frame::Array(Some(
+                    spec.flags
+                        .split_whitespace()
+                        .map(|flag| Frame::SimpleString(flag.to_string()))
+                        .collect(),
+                )),
+                Frame::Integer(spec.first_key as i64),
+                Frame::Integer(spec.last_key as i64),
+                Frame::Integer(spec.step as i64),
+            ])),
+        })
+        .collect();
+    Frame::Array(Some(entries)).encode()
+}
+
+/// COMMAND DOCS returns a map from command name to its summary, version and group,
+/// for the named commands or for every command if none are named.
+/// Unknown commands are left out.
+pub async fn command_docs(args: Vec<Frame>, state: &mut ClientState) -> Vec<u8> {
+    let names = match bulk_args(args, "command docs") {
+        Ok(names) => names,
+        Err(e) => return e,
+    };
+    let docs: Vec<&registry::CommandDoc> = if names.is_empty() {
+        registry::DOCS.iter().collect()
+    } else {
+        names
+            .iter()
+            .filter_map(|name| registry::lookup_doc(&String::from_utf8_lossy(name)))
+            .collect()
+    };
+    let resp3 = state.resp3();
+    let bulk = |s: &str| Frame::BulkString(Some(s.as_bytes().to_vec()));
+    let entries = docs
+        .into_iter()
+        .map(|doc| {
+            let fields = vec![
+                (bulk("summary"), bulk(doc.summary)),
+                (bulk("since"), bulk(doc.since)),
+                (bulk("group"), bulk(doc.group)),
+            ];
+            (bulk(doc.name), map_frame(fields, resp3))
+        })
+        .collect();
+    map_frame(entries, resp3).encode()
+}
+
+/// COMMAND GETKEYS returns the key names of a full command line, using the
+/// key positions from the command registry.
+/// It expects the command name followed by its arguments.
+pub async fn command_getkeys(args: Vec<Frame>) -> Vec<u8> {
+    if args.is_empty() {
+        return Frame::Error("ERR wrong number of arguments for 'command getkeys'".into()).encode();
+    }
+    let mut argv = Vec::with_capacity(args.len());
+    for arg in args {
+        match arg {
+            Frame::BulkString(Some(bs)) => argv.push(bs),
+            _ => return Frame::Error("ERR invalid argument for 'command getkeys'".into()).encode(),
+        }
+    }
+    let Some(spec) = registry::lookup(&String::from_utf8_lossy(&argv[0])) else {
+        return Frame::Error("ERR Invalid command specified".into()).encode();
+    };
+    match spec.keys(&argv) {
+        Ok(keys) => bulk_array(keys.into_iter().map(<[u8]>::to_vec).collect()),
+        Err(e) => Frame::Error(e).encode(),
+    }
+}
+
+/// REPLICAOF host port starts replicating from a master; REPLICAOF NO ONE turns
+/// the server back into a master, keeping the data it has
+pub async fn replicaof(args: Vec<Frame>, cmd: &str) -> Vec<u8> {
+    let [Frame::BulkString(Some(host)), Frame::BulkString(Some(port))] = args.as_slice() else {
+        return Frame::Error(format!("ERR wrong number of arguments for '{}'", cmd)).encode();
+    };
+    let host = String::from_utf8_lossy(host).to_string();
+    let port = String::from_utf8_lossy(port).to_string();
+    if host.eq_ignore_ascii_case("no") && port.eq_ignore_ascii_case("one") {
+        if replication::is_replica() {
+            replication::promote();
+            info!("MASTER MODE enabled (user request)");
+        }
+        return Frame::SimpleString("OK".into()).encode();
+    }
+    let Ok(port) = port.parse::<u16>() else {
+        return Frame::Error("ERR Invalid master port".into()).encode();
+    };
+    replication::replicate_from(host.clone(), port);
+    info!("REPLICAOF {}:{} enabled (user request)", host, port);
+    Frame::SimpleString("OK Background replication started".into()).encode()
+}
+
+/// REPLCONF, sent by replicas while handshaking and, with ACK, to report the
+/// offset they processed. ACK gets no reply.
+pub async fn replconf(args: Vec<Frame>, state: &mut ClientState) -> Vec<u8> {
+    if !args.len().is_multiple_of(2) {
+        return Frame::Error("ERR syntax error".into()).encode();
+    }
+    for pair in args.chunks(2) {
+        let (Frame::BulkString(Some(option)), Frame::BulkString(Some(value))) =
+            (&pair[0], &pair[1])
+        else {
+            return Frame::Error("ERR syntax error".into()).encode();
+        };
+        let value = String::from_utf8_lossy(value);
+        match String::from_utf8_lossy(option).to_lowercase().as_str() {
+            "listening-port" => match value.parse::<u16>() {
+                Ok(port) => state.replica_listening_port = port,
+                Err(_) => {
+                    return Frame::Error("ERR value is not an integer or out of range".into())
+                        .encode()
+                }
+            },
+            // Capabilities only matter for partial resyncs and diskless loading, which aren't supported
+            "capa" | "ip-address" => {}
+            "ack" => {
+                if let Ok(offset) = value.parse::<u64>() {
+                    replication::record_ack(state.id, offset);
+                }
+                return Vec::new();
+            }
+            "getack" => return Vec::new(),
+            other => {
+                return Frame::Error(format!("ERR Unrecognized REPLCONF option: {}", other))
+                    .encode()
+            }
+        }
+    }
+    Frame::SimpleString("OK".into()).encode()
+}
+
+/// PSYNC replid offset. Partial resyncs aren't supported, so every replica gets a
+/// full resync, written to its output queue ahead of the propagated commands.
+pub async fn psync(args: Vec<Frame>, state: &mut ClientState) -> Vec<u8> {
+    if args.len() < 2 {
+        return Frame::Error("ERR wrong number of arguments for 'psync'".into()).encode();
+    }
+    if replication::is_replica() {
+        return Frame::Error("ERR Chained replication is not supported".into()).encode();
+    }
+    let Some(tx) = state.push_tx.clone() else {
+        return Frame::Error("ERR PSYNC is only supported over a network connection".into())
+            .encode();
+    };
+    // Writes wait until the replica is registered, so each one is either in the snapshot
+    // or in the stream that follows it
+    let _writes = hold_off_writes().await;
+    let rdb = match rdb::snapshot().await {
+        Ok((rdb, _)) => rdb,
+        Err(e) => return Frame::Error(format!("ERR {}", e)).encode(),
+    };
+    let ip = state
+        .addr
+        .rsplit_once(':')
+        .map_or(state.addr.clone(), |(ip, _)| ip.to_string());
+    info!(
+        "Replica {}:{} asks for synchronization",
+        ip, state.replica_listening_port
+    );
+    state.replica = true;
+    replication::full_resync(state.id, ip, state.replica_listening_port, tx, rdb);
+    // Everything was queued by full_resync
+    Vec::new()
+}
+
+/// WAITAOF command waits for writes to be fsynced to the local AOF and replicas.
+/// With appendonly enabled it waits for the AOF writer to sync everything appended so far
+/// and answers [1, 0], as replicas don't report their AOF; otherwise [0, 0] when numlocal is 0.
+/// It expects numlocal, numreplicas and a timeout.
+pub async fn waitaof(args: Vec<Frame>) -> Vec<u8> {
+    if args.len() != 3 {
+        return Frame::Error("ERR wrong number of arguments for 'waitaof'".into()).encode();
+    }
+    let mut numbers = [0i64; 3];
+    for (n, arg) in numbers.iter_mut().zip(&args) {
+        match parse_integer(arg) {
+            Ok(value) if value >= 0 => *n = value,
+            Ok(_) => {
+                return Frame::Error("ERR value is out of range, must be positive".into()).encode()
+            }
+            Err(e) => return e,
+        }
+    }
+    let appendonly = config::get_config().appendonly;
+    if numbers[0] > 0 && !appendonly {
+        return Frame::Error(
+            "ERR WAITAOF cannot be used when numlocal is set but appendonly is disabled.".into(),
+        )
+        .encode();
+    }
+    if appendonly {
+        aof::synced().await;
+    }
+    Frame::Array(Some(vec![
+        Frame::Integer(i64::from(appendonly)),
+        Frame::Integer(0),
+    ]))
+    .encode()
+}
+
+/// SLOWLOG GET command returns the most recent slow log entries, newest first, each as
+/// [id, timestamp, duration in microseconds, [args...], client address, client name].
+/// It expects an optional count (default 10, -1 for all entries).
+/// RESP3 connections get the slowlog-max-len setting as an attribute ahead of the entries.
+pub async fn slowlog_get(args: Vec<Frame>, state: &mut ClientState) -> Vec<u8> {
+    if args.len() > 1 {
+        return Frame::Error("ERR wrong number of arguments for 'slowlog get'".into()).encode();
+    }
+    let count = match args.first().map(parse_integer) {
+        None => Some(10),
+        Some(Ok(-1)) => None,
+        Some(Ok(n)) if n >= 0 => Some(n as usize),
+        Some(Ok(_)) => {
+            return Frame::Error("ERR count should be greater than or equal to -1".into()).encode()
+        }
+        Some(Err(e)) => return e,
+    };
+    let bulk = |b: Vec<u8>| Frame::BulkString(Some(b));
+    let entries = slowlog::get(count)
+        .into_iter()
+        .map(|entry| {
+            Frame::Array(Some(vec![
+                Frame::Integer(entry.id as i64),
+                Frame::Integer(entry.timestamp as i64),
+                Frame::Integer(entry.duration_us as i64),
+                Frame::Array(Some(entry.args.into_iter().map(bulk).collect())),
+                bulk(entry.client_addr.into_bytes()),
+                bulk(entry.client_name.into_bytes()),
+            ]))
+        })
+        .collect();
+    let mut reply = Vec::new();
+    if state.resp3() {
+        // RESP3 clients also learn how many entries the log keeps, as an attribute
+        let max_len = config::get_config().slowlog_max_len as i64;
+        Frame::Attribute(Some(vec![(
+            bulk(b"slowlog-max-len".to_vec()),
+            Frame::Integer(max_len),
+        )]))
+        .encode_into(&mut reply);
+    }
+    Frame::Array(Some(entries)).encode_into(&mut reply);
+    reply
+}
+
+/// CLUSTER INFO command describes the cluster state. Cluster mode isn't supported,
+/// so it reports a standalone node, which is enough for cluster-aware clients.
+pub async fn cluster_info(args: Vec<Frame>) -> Vec<u8> {
+    if !args.is_empty() {
+        return Frame::Error("ERR wrong number of arguments for 'cluster info'".into()).encode();
+    }
+    let fields = [
+        ("cluster_enabled", 0),
+        ("cluster_slots_assigned", 0),
+        ("cluster_slots_ok", 0),
+        ("cluster_slots_pfail", 0),
+        ("cluster_slots_fail", 0),
+        ("cluster_known_nodes", 1),
+        ("cluster_size", 0),
+        ("cluster_current_epoch", 0),
+        ("cluster_my_epoch", 0),
+    ];
+    let mut info = String::from("cluster_state:ok\r\n");
+    for (name, value) in fields {
+        info.push_str(&format!("{}:{}\r\n", name, value));
+    }
+    Frame::BulkString(Some(info.into_bytes())).encode()
+}
+
+/// CLUSTER SLOTS command returns the slot ranges served by each node: none here.
+pub async fn cluster_slots(args: Vec<Frame>) -> Vec<u8> {
+    if !args.is_empty() {
+        return Frame::Error("ERR wrong number of arguments for 'cluster slots'".into()).encode();
+    }
+    Frame::Array(Some(Vec::new())).encode()
+}
+
+/// CLUSTER NODES command returns the cluster configuration: empty without a cluster.
+pub async fn cluster_nodes(args: Vec<Frame>) -> Vec<u8> {
+    if !args.is_empty() {
+        return Frame::Error("ERR wrong number of arguments for 'cluster nodes'".into()).encode();
+    }
+    Frame::BulkString(Some(Vec::new())).encode()
+}
+
+/// CLUSTER MYID command returns the id of this node, its run id.
+pub async fn cluster_myid(args: Vec<Frame>) -> Vec<u8> {
+    if !args.is_empty() {
+        return Frame::Error("ERR wrong number of arguments for 'cluster myid'".into()).encode();
+    }
+    Frame::BulkString(Some(server::RUN_ID.as_bytes().to_vec())).encode()
+}
+
+/// SLOWLOG LEN command returns the number of entries in the slow log.
+pub async fn slowlog_len(args: Vec<Frame>) -> Vec<u8> {
+    if !args.is_empty() {
+        return Frame::Error("ERR wrong number of arguments for 'slowlog len'".into()).encode();
+    }
+    Frame::Integer(slowlog::len() as i64).encode()
+}
+
+/// SLOWLOG RESET command clears the slow log.
+pub async fn slowlog_reset(args: Vec<Frame>) -> Vec<u8> {
+    if !args.is_empty() {
+        return Frame::Error("ERR wrong number of arguments for 'slowlog reset'".into()).encode();
+    }
+    slowlog::reset();
+    Frame::SimpleString("OK".into()).encode()
+}
+
+/// HELP subcommand of CLIENT, COMMAND, CONFIG, DEBUG, OBJECT, PUBSUB and SLOWLOG returns usage lines,
+/// formatted like Redis: a header, then each subcommand followed by its description.
+/// It expects no arguments.
+pub async fn help(command: &str, args: Vec<Frame>) -> Vec<u8> {
+    if !args.is_empty() {
+        return Frame::Error(format!(
+            "ERR wrong number of arguments for '{} help'",
+            command
+        ))
+        .encode();
+    }
+    let subcommands: &[(&str, &str)] = match command {
+        "client" => &[
+            ("GETNAME", "Return the name of the current connection."),
+            (
+                "NO-EVICT (ON|OFF)",
+                "Protect current client connection from eviction.",
+            ),
+            (
+                "NO-TOUCH (ON|OFF)",
+                "Will not touch LRU/LFU stats when this mode is on.",
+            ),
+            (
+                "SETNAME <name>",
+                "Assign the name <name> to the current connection.",
+            ),
+        ],
+        "config" => &[
+            (
+                "GET <pattern>",
+                "Return parameters matching the glob-like <pattern> and their values.",
+            ),
+            (
+                "SET <directive> <value>",
+                "Set the configuration <directive> to <value>.",
+            ),
+            (
+                "RESETSTAT",
+                "Reset statistics reported by the INFO command.",
+            ),
+        ],
+        "acl" => &[
+            (
+                "CAT [<category>]",
+                "List all commands that belong to <category>, or all command categories when no category is specified.",
+            ),
+            (
+                "GETUSER <username>",
+                "Get the user's details.",
+            ),
+            ("LIST", "Show users details in config file format."),
+            ("USERS", "List all the registered usernames."),
+            (
+                "WHOAMI",
+                "Return the current connection username.",
+            ),
+        ],
+        "cluster" => &[
+            ("INFO", "Return information about the cluster."),
+            (
+                "MYID",
+                "Return the node id.",
+            ),
+            ("NODES", "Return cluster configuration seen by node."),
+            (
+                "SLOTS",
+                "Return information about slots range mappings.",
+            ),
+        ],
+        "command" => &[
+            (
+                "COUNT",
+                "Return the total number of commands in this server.",
+            ),
+            (
+                "DOCS [<command-name> ...]",
+                "Return documentation details about multiple Redis commands.",
+            ),
+            (
+                "GETKEYS <full-command>",
+                "Return the keys from a full command.",
+            ),
+            (
+                "INFO [<command-name> ...]",
+                "Return details about multiple commands.",
+            ),
+        ],
+        "debug" => &[
+            (
+                "RELOAD",
+                "Save the RDB on disk and reload it back to memory.",
+            ),
+            (
+                "CHANGE-REPL-ID",
+                "Change the replication IDs of the instance. Dangerous: should be used only for testing the replication subsystem.",
+            ),
+            ("DUMPKEYS", "Return the keyspace serialized as JSON."),
+            (
+                "OBJECT <key>",
+                "Show low level info about the <key> and associated value.",
+            ),
+            (
+                "QUICKLIST <key> [<0|1>]",
+                "Show low level info about the quicklist encoding of <key>. The optional argument (0 by default) sets the level of detail",
+            ),
+            ("SLEEP <seconds>", "Stop the server for <seconds>. Decimals allowed."),
+            (
+                "SET-ACTIVE-EXPIRE <0|1>",
+                "Setting it to 0 disables expiring keys in background when they are not accessed (otherwise the Redis behavior). Setting it to 1 reenables back the default.",
+            ),
+            (
+                "STRINGMATCH-LEN <pattern> <string>",
+                "Return 1 if the string matches the glob-style pattern, 0 otherwise.",
+            ),
+        ],
+        "memory" => &[
+            (
+                "DOCTOR",
+                "Return memory problems reports.",
+            ),
+            (
+                "USAGE <key> [SAMPLES <count>]",
+                "Return memory in bytes used by <key> and its value. Nested values are sampled up to <count> times (default: 5, 0 means sample all).",
+            ),
+        ],
+        "object" => &[
+            (
+                "ENCODING <key>",
+                "Return the kind of internal representation used in order to store the value associated with a <key>.",
+            ),
+            (
+                "FREQ <key>",
+                "Return the access frequency index of the key <key>.",
+            ),
+            ("IDLETIME <key>", "Return the idle time of the key <key>."),
+            (
+                "REFCOUNT <key>",
+                "Return the reference count of the object stored at <key>.",
+            ),
+        ],
+        "xinfo" => &[("STREAM <key>", "Show information about the stream.")],
+        "slowlog" => &[
+            (
+                "GET [<count>]",
+                "Return top <count> entries from the slowlog (default: 10, -1 mean all).",
+            ),
+            ("LEN", "Return the length of the slowlog."),
+            ("RESET", "Reset the slowlog."),
+        ],
+        "pubsub" => &[
+            (
+                "CHANNELS [<pattern>]",
+                "Return the currently active channels matching a <pattern> (default: '*').",
+            ),
+            ("NUMPAT", "Return number of subscriptions to patterns."),
+            (
+                "NUMSUB [<channel> ...]",
+                "Return the number of subscribers for the specified channels.",
+            ),
+        ],
+        _ => &[],
+    };
+
+    let mut lines = vec![format!(
+        "{} <subcommand> [<arg> [value] [opt] ...]. Subcommands are:",
+        command.to_uppercase()
+    )];
+    for (usage, description) in subcommands.iter().chain(&[("HELP", "Print this help.")]) {
+        lines.push(usage.to_string());
+        lines.push(format!("    {}", description));
+    }
+    Frame::Array(Some(lines.into_iter().map(Frame::SimpleString).collect())).encode()
+}
+
+/// CONFIG GET command returns config values as RESP array
+/// It expects one or more parameter names or glob-style patterns, and replies with
+/// every parameter they match, each once; unknown ones match nothing.
+pub async fn config_get(args: Vec<Frame>) -> Vec<u8> {
+    if args.is_empty() {
+        return Frame::Error("ERR wrong number of arguments for 'config get'".into()).encode();
+    }
+    let mut names: Vec<String> = Vec::new();
+    for arg in &args {
+        let pattern = match arg {
+            Frame::BulkString(Some(bs)) => String::from_utf8_lossy(bs).to_lowercase(),
+            _ => return Frame::Error("ERR invalid argument for 'config get'".into()).encode(),
+        };
+        // A plain name is looked up, so aliases are found as well
+        if !pattern.contains(['*', '?', '[']) {
+            names.push(pattern);
+            continue;
+        }
+        names.extend(
+            config::PARAMS
+                .iter()
+                .filter(|name| db::glob_match(pattern.as_bytes(), name.as_bytes()))
+                .map(|name| name.to_string()),
+        );
+    }
+    let mut pairs = Vec::new();
+    for (i, name) in names.iter().enumerate() {
+        if names[..i].contains(name) {
+            continue;
+        }
+        if let Some(value) = config::get_param(name) {
+            pairs.push(Frame::BulkString(Some(name.clone().into_bytes())));
+            pairs.push(Frame::BulkString(Some(value.into_bytes())));
+        }
+    }
+    Frame::Array(Some(pairs)).encode()
+}
+
+/// CONFIG SET command allows setting configuration parameters
+/// It expects two arguments: the parameter name and the value.
+pub async fn config_set(args: Vec<Frame>) -> Vec<u8> {
+    if args.len() != 2 {
+        return Frame::Error("ERR wrong number of arguments for 'config set'".into()).encode();
+    }
+    let param = match &args[0] {
+        Frame::BulkString(Some(bs)) => String::from_utf8_lossy(bs).to_lowercase(),
+        _ => return Frame::Error("ERR invalid argument for 'config set'".into()).encode(),
+    };
+    let value = match &args[1] {
+        Frame::BulkString(Some(bs)) => String::from_utf8_lossy(bs).to_string(),
+        _ => return Frame::Error("ERR invalid value for 'config set'".into()).encode(),
+    };
+
+    let appended = config::get_config().appendonly.then(config::get_aof_path);
+    if let Err(e) = config::set_param(&param, &value) {
+        return Frame::Error(format!("ERR {}", e)).encode();
+    }
+    // An append only file started now, or moved elsewhere, starts with the dataset so far
+    if config::get_config().appendonly && appended != Some(config::get_aof_path()) {
+        if let Err(e) = aof::rewrite().await {
+            // An append only file missing the dataset is no use, so leave it off
+            warn!("{}", e);
+            let _ = config::set_param("appendonly", "no");
+            return Frame::Error(format!("ERR {}", e)).encode();
+        }
+    }
+    Frame::SimpleString("OK".into()).encode()
+}
+
+/// CONFIG RESETSTAT command zeroes the counters reported by INFO.
+/// It expects no arguments.
+pub async fn config_resetstat(args: Vec<Frame>) -> Vec<u8> {
+    if !args.is_empty() {
+        return Frame::Error("ERR wrong number of arguments for 'config resetstat'".into())
+            .encode();
+    }
+    stats::reset();
+    Frame::SimpleString("OK".into()).encode()
+}
+
+/// INFO command returns server information as a bulk string of "field:value" lines.
+/// It accepts an optional section name; unknown sections yield an empty reply.
+pub async fn info(args: Vec<Frame>) -> Vec<u8> {
+    if args.len() > 1 {
+        return Frame::Error("ERR wrong number of arguments for 'info'".into()).encode();
+    }
+    let section = match args.first() {
+        Some(Frame::BulkString(Some(bs))) => String::from_utf8_lossy(bs).to_lowercase(),
+        Some(_) => return Frame::Error("ERR invalid section for 'info'".into()).encode(),
+        None => "default".to_string(),
+    };
+    let body = match section.as_str() {
+        "default" | "all" | "everything" => {
+            format!(
+                "{}\r\n{}\r\n{}\r\n{}",
+                server::info_section(),
+                rdb::info_section(),
+                stats::info_section(),
+                replication::info_section()
+            )
+        }
+        "clients" => server::info_section(),
+        "persistence" => rdb::info_section(),
+        "stats" => stats::info_section(),
+        "replication" => replication::info_section(),
+        _ => String::new(),
+    };
+    Frame::BulkString(Some(body.into_bytes())).encode()
+}
+
+/// MONITOR command streams every command run by other connections to this one,
+/// until it disconnects or RESETs.
+/// It expects no arguments.
+pub async fn monitor(args: Vec<Frame>, state: &mut ClientState) -> Vec<u8> {
+    if !args.is_empty() {
+        return Frame::Error("ERR wrong number of arguments for 'monitor'".into()).encode();
+    }
+    monitor::add(state);
+    Frame::SimpleString("OK".into()).encode()
+}
+
+/// MULTI command starts a transaction; subsequent commands are queued until EXEC.
+/// It expects no arguments.
+pub async fn multi(args: Vec<Frame>, state: &mut ClientState) -> Vec<u8> {
+    if !args.is_empty() {
+        return Frame::Error("ERR wrong number of arguments for 'multi'".into()).encode();
+    }
+    if state.multi.is_some() {
+        return Frame::Error("ERR MULTI calls can not be nested".into()).encode();
+    }
+    state.multi = Some(Vec::new());
+    Frame::SimpleString("OK".into()).encode()
+}
+
+/// DISCARD command throws away the commands queued since MULTI.
+/// It expects no arguments.
+pub async fn discard(args: Vec<Frame>, state: &mut ClientState) -> Vec<u8> {
+    if !args.is_empty() {
+        return Frame::Error("ERR wrong number of arguments for 'discard'".into()).encode();
+    }
+    match state.multi.take() {
+        Some(_) => Frame::SimpleString("OK".into()).encode(),
+        None => Frame::Error("ERR DISCARD without MULTI".into()).encode(),
+    }
+}
+
+/// RESET command returns the connection to its initial state: it discards any
+/// MULTI queue, clears the client name and de-authenticates.
+/// It expects no arguments.
+pub async fn reset(args: Vec<Frame>, state: &mut ClientState) -> Vec<u8> {
+    if !args.is_empty() {
+        return Frame::Error("ERR wrong number of arguments for 'reset'".into()).encode();
+    }
+    state.reset();
+    Frame::SimpleString("RESET".into()).encode()
+}
+
+/// QUIT command replies OK and asks the server to close the connection.
+/// It expects no arguments, though like Redis any are ignored.
+pub async fn quit(_args: Vec<Frame>) -> Vec<u8> {
+    Frame::SimpleString("OK".into()).encode()
+}
+
+/// AUTH command authenticates the connection against requirepass.
+/// It expects either a password or a username ("default") and a password.
+pub async fn auth(args: Vec<Frame>, state: &mut ClientState) -> Vec<u8> {
+    let (username, password) = match args.as_slice() {
+        [Frame::BulkString(Some(pass))] => (b"default".to_vec(), pass.clone()),
+        [Frame::BulkString(Some(user)), Frame::BulkString(Some(pass))] => {
+            (user.clone(), pass.clone())
+        }
+        [_] | [_, _] => return Frame::Error("ERR invalid argument for 'auth'".into()).encode(),
+        _ => return Frame::Error("ERR wrong number of arguments for 'auth'".into()).encode(),
+    };
+
+    if let Err(e) = check_credentials(&username, &password) {
+        return e;
+    }
+    state.authenticated = true;
+    Frame::SimpleString("OK".into()).encode()
+}
+
+/// Command categories reported by ACL CAT, with the command flag that selects
+/// a category's commands where one does. "slow" is every command not flagged fast.
+const ACL_CATEGORIES: &[(&str, Option<&str>)] = &[
+    ("keyspace", None),
+    ("read", Some("readonly")),
+    ("write", Some("write")),
+    ("set", None),
+    ("sortedset", None),
+    ("list", None),
+    ("hash", None),
+    ("string", None),
+    ("bitmap", None),
+    ("hyperloglog", None),
+    ("geo", None),
+    ("stream", None),
+    ("pubsub", Some("pubsub")),
+    ("admin", Some("admin")),
+    ("fast", Some("fast")),
+    ("slow", None),
+    ("blocking", Some("blocking")),
+    ("dangerous", None),
+    ("connection", None),
+    ("transaction", None),
+    ("scripting", None),
+];
+
+/// The password rule of the default user, the only user there is:
+/// the SHA-256 of requirepass if one is set, like Redis shows it, or nopass
+fn default_user_password() -> Option<String> {
+    config::get_requirepass().map(|pass| format!("#{}", sha256::hex_digest(pass.as_bytes())))
+}
+
+/// ACL WHOAMI command returns the user of the connection, always "default".
+pub async fn acl_whoami(args: Vec<Frame>) -> Vec<u8> {
+    if !args.is_empty() {
+        return Frame::Error("ERR wrong number of arguments for 'acl whoami'".into()).encode();
+    }
+    Frame::BulkString(Some(b"default".to_vec())).encode()
+}
+
+/// ACL LIST command returns the rules of every user, that is of the default user,
+/// which may run any command on any key and channel.
+pub async fn acl_list(args: Vec<Frame>) -> Vec<u8> {
+    if !args.is_empty() {
+        return Frame::Error("ERR wrong number of arguments for 'acl list'".into()).encode();
+    }
+    let password = default_user_password().unwrap_or_else(|| "nopass".to_string());
+    bulk_array(vec![
+        format!("user default on {} ~* &* +@all", password).into_bytes()
+    ])
+}
+
+/// ACL USERS command returns the names of every user, just "default".
+pub async fn acl_users(args: Vec<Frame>) -> Vec<u8> {
+    if !args.is_empty() {
+        return Frame::Error("ERR wrong number of arguments for 'acl users'".into()).encode();
+    }
+    bulk_array(vec![b"default".to_vec()])
+}
+
+/// ACL CAT command returns the command categories or, given one, the commands in it.
+/// Categories that no command flag selects have no commands here.
+pub async fn acl_cat(args: Vec<Frame>) -> Vec<u8> {
+    let category = match args.as_slice() {
+        [] => {
+            return bulk_array(
+                ACL_CATEGORIES
+                    .iter()
+                    .map(|(name, _)| name.as_bytes().to_vec())
+                    .collect(),
+            )
+        }
+        [Frame::BulkString(Some(category))] => String::from_utf8_lossy(category).to_lowercase(),
+        _ => return Frame::Error("ERR wrong number of arguments for 'acl cat'".into()).encode(),
+    };
+    let Some((_, flag)) = ACL_CATEGORIES.iter().find(|(name, _)| *name == category) else {
+        return Frame::Error(format!("ERR Unknown category '{}'", category)).encode();
+    };
+    let commands = registry::COMMANDS.iter().filter(|spec| match flag {
+        Some(flag) => spec.has_flag(flag),
+        None => category == "slow" && !spec.has_flag("fast"),
+    });
+    bulk_array(commands.map(|spec| spec.name.as_bytes().to_vec()).collect())
+}
+
+/// ACL GETUSER command describes a user's flags, passwords and permissions,
+/// or returns nil for any user but "default".
+/// It expects a username.
+pub async fn acl_getuser(args: Vec<Frame>, state: &mut ClientState) -> Vec<u8> {
+    let [Frame::BulkString(Some(username))] = args.as_slice() else {
+        return Frame::Error("ERR wrong number of arguments for 'acl getuser'".into()).encode();
+    };
+    if username.as_slice() != b"default" {
+        return Frame::BulkString(None).encode();
+    }
+    let bulk = |s: &str| Frame::BulkString(Some(s.as_bytes().to_vec()));
+    let password = default_user_password();
+    let mut flags = vec![bulk("on")];
+    if password.is_none() {
+        flags.push(bulk("nopass"));
+    }
+    // Passwords are listed by their hash, without the '#' of the rule
+    let passwords = password.iter().map(|p| bulk(&p[1..])).collect();
+    let fields = vec![
+        (bulk("flags"), Frame::Array(Some(flags))),
+        (bulk("passwords"), Frame::Array(Some(passwords))),
+        (bulk("commands"), bulk("+@all")),
+        (bulk("keys"), bulk("~*")),
+        (bulk("channels"), bulk("&*")),
+        (bulk("selectors"), Frame::Array(Some(Vec::new()))),
+    ];
+    map_frame(fields, state.resp3()).encode()
+}
+
+/// Frame key/value pairs as a map under RESP3, or as a flat array under RESP2
+fn map_frame(fields: Vec<(Frame, Frame)>, resp3: bool) -> Frame {
+    if resp3 {
+        Frame::Map(Some(fields))
+    } else {
+        Frame::Array(Some(fields.into_iter().flat_map(|(k, v)| [k, v]).collect()))
+    }
+}
+
+/// Check a username/password pair against requirepass, as done by AUTH and HELLO
+fn check_credentials(username: &[u8], password: &[u8]) -> Result<(), Vec<u8>> {
+    let Some(requirepass) = config::get_requirepass() else {
+        return Err(Frame::Error(
+            "ERR AUTH <password> called without any password configured for the default user. Are you sure your configuration is correct?".into(),
+        )
+        .encode());
+    };
+    if username != b"default" || password != requirepass.as_bytes() {
+        return Err(Frame::Error(
+            "WRONGPASS invalid username-password pair or user is disabled.".into(),
+        )
+        .encode());
+    }
+    Ok(())
+}
+
+/// Whether a client name is acceptable to CLIENT SETNAME and HELLO SETNAME
+fn valid_client_name(name: &str) -> bool {
+    !name.chars().any(|c| c <= ' ' || c > '~')
+}
+
+/// HELLO command switches the connection's protocol version and returns
+/// information about the server in the newly selected protocol.
+/// It expects an optional protocol version (2 or 3), optionally followed by
+/// AUTH username password and SETNAME clientname.
+pub async fn hello(args: Vec<Frame>, state: &mut ClientState) -> Vec<u8> {
+    let mut parts = Vec::with_capacity(args.len());
+    for arg in args {
+        match arg {
+            Frame::BulkString(Some(bs)) => parts.push(bs),
+            _ => return Frame::Error("ERR invalid argument for 'hello'".into()).encode(),
+        }
+    }
+    let mut parts = parts.into_iter();
+
+    let protocol = match parts.next() {
+        None => state.protocol,
+        Some(raw) => match String::from_utf8_lossy(&raw).parse::<i64>() {
+            Ok(v @ 2..=3) => v as u8,
+            Ok(_) => return Frame::Error("NOPROTO unsupported protocol version".into()).encode(),
+            Err(_) => {
+                return Frame::Error(
+                    "ERR Protocol version is not an integer or out of range".into(),
+                )
+                .encode()
+            }
+        },
+    };
+
+    let mut credentials = None;
+    let mut name = None;
+    while let Some(option) = parts.next() {
+        let option = String::from_utf8_lossy(&option).to_string();
+        match option.to_lowercase().as_str() {
+            "auth" => match (parts.next(), parts.next()) {
+                (Some(user), Some(pass)) => credentials = Some((user, pass)),
+                _ => {
+                    return Frame::Error(format!("ERR Syntax error in HELLO option '{}'", option))
+                        .encode()
+                }
+            },
+            "setname" => match parts.next() {
+                Some(raw) => name = Some(String::from_utf8_lossy(&raw).to_string()),
+                None => {
+                    return Frame::Error(format!("ERR Syntax error in HELLO option '{}'", option))
+                        .encode()
+                }
+            },
+            _ => {
+                return Frame::Error(format!("ERR Syntax error in HELLO option '{}'", option))
+                    .encode()
+            }
+        }
+    }
+
+    if let Some((user, pass)) = credentials {
+        if let Err(e) = check_credentials(&user, &pass) {
+            return e;
+        }
+        state.authenticated = true;
+    }
+    if config::get_requirepass().is_some() && !state.authenticated {
+        return Frame::Error(
+            "NOAUTH HELLO must be called with the client already authenticated, otherwise the HELLO <proto> AUTH <user> <pass> option can be used to authenticate the client and select the RESP protocol version at the same time".into(),
+        )
+        .encode();
+    }
+    if let Some(name) = name {
+        if !valid_client_name(&name) {
+            return Frame::Error(
+                "ERR Client names cannot contain spaces, newlines or special characters.".into(),
+            )
+            .encode();
+        }
+        state.name = if name.is_empty() { None } else { Some(name) };
+    }
+    state.protocol = protocol;
+    pubsub::update_protocol(state);
+
+    let bulk = |s: &str| Frame::BulkString(Some(s.as_bytes().to_vec()));
+    let fields = vec![
+        (bulk("server"), bulk("redis")),
+        (bulk("version"), bulk(REDIS_VERSION)),
+        (bulk("proto"), Frame::Integer(protocol as i64)),
+        (bulk("id"), Frame::Integer(state.id as i64)),
+        (bulk("mode"), bulk("standalone")),
+        (bulk("role"), bulk("master")),
+        (bulk("modules"), Frame::Array(Some(Vec::new()))),
+    ];
+    map_frame(fields, state.resp3()).encode()
+}
+
+/// Parse a database index argument, as used by SELECT and MOVE
+fn parse_db_index(frame: &Frame) -> Result<usize, Vec<u8>> {
+    let index = parse_integer(frame)?;
+    if index < 0 || index as usize >= db::database_count() {
+        return Err(Frame::Error("ERR DB index is out of range".into()).encode());
+    }
+    Ok(index as usize)
+}
+
+/// SELECT command switches the connection to another database.
+/// It expects a single argument which is the database index.
+pub async fn select(args: Vec<Frame>, state: &mut ClientState) -> Vec<u8> {
+    if args.len() != 1 {
+        return Frame::Error("ERR wrong number of arguments for 'select'".into()).encode();
+    }
+    match parse_db_index(&args[0]) {
+        Ok(index) => {
+            state.db_index = index;
+            Frame::SimpleString("OK".into()).encode()
+        }
+        Err(e) => e,
+    }
+}
+
+/// MOVE command moves a key, with its TTL, from the selected database to another one.
+/// Returns 1 if the key was moved, 0 if it doesn't exist or already exists in the target.
+pub async fn move_key(args: Vec<Frame>) -> Vec<u8> {
+    if args.len() != 2 {
+        return Frame::Error("ERR wrong number of arguments for 'move'".into()).encode();
+    }
+    let key = match &args[0] {
+        Frame::BulkString(Some(bs)) => String::from_utf8_lossy(bs).to_string(),
+        _ => return Frame::Error("ERR invalid key for 'move'".into()).encode(),
+    };
+    let target = match parse_db_index(&args[1]) {
+        Ok(index) => index,
+        Err(e) => return e,
+    };
+    match db::move_key(&key, db::current_db(), target).await {
+        Ok(moved) => {
+            if !moved {
+                prevent_propagation();
+            }
+            Frame::Integer(moved as i64).encode()
+        }
+        Err(e) => Frame::Error(e).encode(),
+    }
+}
+
+/// SWAPDB command exchanges the contents of two databases.
+/// It expects the two database indexes.
+pub async fn swapdb(args: Vec<Frame>) -> Vec<u8> {
+    let [first, second] = args.as_slice() else {
+        return Frame::Error("ERR wrong number of arguments for 'swapdb'".into()).encode();
+    };
+    let (first, second) = match (parse_db_index(first), parse_db_index(second)) {
+        (Ok(first), Ok(second)) => (first, second),
+        (Err(e), _) | (_, Err(e)) => return e,
+    };
+    db::swapdb(first, second).await;
+    Frame::SimpleString("OK".into()).encode()
+}
+
+/// CLIENT SETNAME command assigns a name to the connection; an empty name clears it.
+/// It expects a single argument which is the name.
+pub async fn client_setname(args: Vec<Frame>, state: &mut ClientState) -> Vec<u8> {
+    if args.len() != 1 {
+        return Frame::Error("ERR wrong number of arguments for 'client setname'".into()).encode();
+    }
+    let name = match &args[0] {
+        Frame::BulkString(Some(bs)) => String::from_utf8_lossy(bs).to_string(),
+        _ => return Frame::Error("ERR invalid argument for 'client setname'".into()).encode(),
+    };
+    if !valid_client_name(&name) {
+        return Frame::Error(
+            "ERR Client names cannot contain spaces, newlines or special characters.".into(),
+        )
+        .encode();
+    }
+    state.name = if name.is_empty() { None } else { Some(name) };
+    Frame::SimpleString("OK".into()).encode()
+}
+
+/// CLIENT NO-EVICT and CLIENT NO-TOUCH commands turn a flag of the connection on or off.
+/// With NO-TOUCH on, its commands leave the LRU/LFU access data of keys unchanged.
+/// NO-EVICT is only recorded, since eviction doesn't account for clients.
+/// It expects ON or OFF.
+pub async fn client_flag(args: Vec<Frame>, state: &mut ClientState, subcmd: &str) -> Vec<u8> {
+    let [Frame::BulkString(Some(mode))] = args.as_slice() else {
+        return Frame::Error(format!(
+            "ERR wrong number of arguments for 'client {}'",
+            subcmd
+        ))
+        .encode();
+    };
+    let enabled = match mode.to_ascii_lowercase().as_slice() {
+        b"on" => true,
+        b"off" => false,
+        _ => return Frame::Error("ERR syntax error".into()).encode(),
+    };
+    if subcmd == "no-evict" {
+        state.no_evict = enabled;
+    } else {
+        state.no_touch = enabled;
+    }
+    Frame::SimpleString("OK".into()).encode()
+}
+
+/// CLIENT GETNAME command returns the connection name, or nil if none is set.
+/// It expects no arguments.
+pub async fn client_getname(args: Vec<Frame>, state: &mut ClientState) -> Vec<u8> {
+    if !args.is_empty() {
+        return Frame::Error("ERR wrong number of arguments for 'client getname'".into()).encode();
+    }
+    Frame::BulkString(state.name.clone().map(String::into_bytes)).encode()
 }
 
 pub async fn unknown() -> Vec<u8> {
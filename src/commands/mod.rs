@@ -1,44 +1,715 @@
+use crate::aof;
+use crate::config;
+use crate::db;
+use crate::model::client_state::ClientState;
+use crate::monitor;
+use crate::rdb;
+use crate::replication;
 use crate::resp::Frame;
+use crate::slowlog;
+use crate::stats;
+use once_cell::sync::Lazy;
+use std::cell::Cell;
+use tokio::sync::{RwLock, RwLockReadGuard, RwLockWriteGuard};
 mod default;
+mod registry;
+
+/// Commands that still run immediately while a MULTI transaction is open
+const TRANSACTION_CONTROL: &[&str] = &["multi", "exec", "discard", "quit", "reset"];
+
+/// Commands an unauthenticated client may issue when requirepass is set
+const NO_AUTH_ALLOWED: &[&str] = &["auth", "hello", "quit", "reset"];
+
+/// Commands a RESP2 connection may issue while subscribed to channels or patterns
+const SUBSCRIBER_ALLOWED: &[&str] = &[
+    "subscribe",
+    "unsubscribe",
+    "psubscribe",
+    "punsubscribe",
+    "ping",
+    "quit",
+    "reset",
+];
+
+/// What the connection handler should do once a reply has been written
+#[derive(Debug, PartialEq)]
+pub enum ConnAction {
+    None,
+    Close,           // QUIT
+    EnterSubscriber, // the connection went from zero to at least one subscription
+    ExitSubscriber,  // the connection dropped its last subscription
+}
+
+/// Outcome of a dispatched command: the encoded reply and any follow-up for the connection
+#[derive(Debug)]
+pub struct DispatchResult {
+    pub reply: Vec<u8>,
+    pub action: ConnAction,
+}
+
+impl From<Vec<u8>> for DispatchResult {
+    fn from(reply: Vec<u8>) -> Self {
+        DispatchResult {
+            reply,
+            action: ConnAction::None,
+        }
+    }
+}
 
 /// Dispatch function to handle commands based on the RESP protocol.
 /// It expects a command in the form of an array where the first element is the command name.
-pub async fn dispatch(frame: Frame) -> Vec<u8> {
+pub async fn dispatch(frame: Frame, state: &mut ClientState) -> DispatchResult {
+    let _turn = take_turn(&frame, state).await;
+    let was_subscribed = state.subscriber_count() > 0;
+    let slowlog_args = slowlog::enabled().then(|| slowlog::capture_args(&frame));
+    let started = std::time::Instant::now();
+    let mut result = route(frame, state).await;
+    if let Some(args) = slowlog_args {
+        let name = state.name.as_deref().unwrap_or("");
+        slowlog::record(args, started.elapsed(), &state.addr, name);
+    }
+    if result.action == ConnAction::None {
+        result.action = match (was_subscribed, state.subscriber_count() > 0) {
+            (false, true) => ConnAction::EnterSubscriber,
+            (true, false) => ConnAction::ExitSubscriber,
+            _ => ConnAction::None,
+        };
+    }
+    result
+}
+
+/// Held by commands for the whole of their run when emulate-single-thread is on
+static SERVER_LOCK: Lazy<RwLock<()>> = Lazy::new(|| RwLock::new(()));
+
+/// A command's hold on SERVER_LOCK
+enum Turn {
+    Shared {
+        _guard: RwLockReadGuard<'static, ()>,
+    },
+    Exclusive {
+        _guard: RwLockWriteGuard<'static, ()>,
+    },
+}
+
+/// With emulate-single-thread, make DEBUG SLEEP stall the server the way it does
+/// single-threaded Redis: it takes SERVER_LOCK exclusively, while every other command
+/// takes it shared. Blocking commands don't take it, since waiting for data doesn't
+/// stall Redis, and holding it while they wait would stall the sleep instead. Inside
+/// MULTI commands are only queued, so the lock is decided by what EXEC runs.
+async fn take_turn(frame: &Frame, state: &ClientState) -> Option<Turn> {
+    if !config::get_emulate_single_thread() {
+        return None;
+    }
+    let cmd = command_name(frame, 0);
+    let exclusive = match &state.multi {
+        Some(queue) if cmd == "exec" => queue.iter().any(|(cmd, args)| {
+            let sub = match args.first() {
+                Some(Frame::BulkString(Some(sub))) => sub.as_slice(),
+                _ => b"",
+            };
+            cmd == "debug" && sub.eq_ignore_ascii_case(b"sleep")
+        }),
+        Some(_) if !TRANSACTION_CONTROL.contains(&cmd.as_str()) => return None,
+        _ => cmd == "debug" && command_name(frame, 1) == "sleep",
+    };
+    if exclusive {
+        return Some(Turn::Exclusive {
+            _guard: SERVER_LOCK.write().await,
+        });
+    }
+    if registry::lookup(&cmd).is_some_and(|spec| spec.has_flag("blocking")) {
+        return None;
+    }
+    Some(Turn::Shared {
+        _guard: SERVER_LOCK.read().await,
+    })
+}
+
+/// The lowercased word at index of a command frame: the command name at 0, and the
+/// subcommand of container commands such as DEBUG at 1. Empty if there is none.
+fn command_name(frame: &Frame, index: usize) -> String {
+    match frame {
+        Frame::Array(Some(items)) => match items.get(index) {
+            Some(Frame::BulkString(Some(name))) => String::from_utf8_lossy(name).to_lowercase(),
+            _ => String::new(),
+        },
+        _ => String::new(),
+    }
+}
+
+/// Whether a command can wait before it replies: the blocking commands, WAITAOF and
+/// DEBUG SLEEP. A connection sends the replies it has gathered before running one.
+pub fn may_wait(frame: &Frame) -> bool {
+    let cmd = command_name(frame, 0);
+    (cmd == "debug" && command_name(frame, 1) == "sleep")
+        || cmd == "waitaof"
+        || registry::lookup(&cmd).is_some_and(|spec| spec.has_flag("blocking"))
+}
+
+/// Apply the connection-level checks (auth, subscriber mode, MULTI queueing)
+/// and run the command.
+async fn route(frame: Frame, state: &mut ClientState) -> DispatchResult {
     match frame {
         Frame::Array(Some(mut v)) if !v.is_empty() => {
             if let Frame::BulkString(Some(cmd)) = v.remove(0) {
+                stats::incr(&stats::TOTAL_COMMANDS_PROCESSED);
                 let cmd_str = String::from_utf8_lossy(&cmd).to_lowercase();
 
+                if config::get_requirepass().is_some()
+                    && !state.authenticated
+                    && !NO_AUTH_ALLOWED.contains(&cmd_str.as_str())
+                {
+                    return default::error("NOAUTH Authentication required.")
+                        .await
+                        .into();
+                }
+
+                if registry::is_write(&cmd_str) && replication::is_replica() && !state.master_link {
+                    return default::error("READONLY You can't write against a read only replica.")
+                        .await
+                        .into();
+                }
+
+                // RESP3 can interleave pushes with replies, so only RESP2 subscribers are restricted
+                if state.subscriber_count() > 0
+                    && !state.resp3()
+                    && !SUBSCRIBER_ALLOWED.contains(&cmd_str.as_str())
+                {
+                    return default::error(&format!(
+                        "ERR Can't execute '{}': only (P)SUBSCRIBE / (P)UNSUBSCRIBE / PING / QUIT / RESET are allowed in this context",
+                        cmd_str
+                    ))
+                    .await.into();
+                }
+
+                // Inside MULTI, everything except the transaction commands is queued
+                if let Some(queue) = state.multi.as_mut() {
+                    if !TRANSACTION_CONTROL.contains(&cmd_str.as_str()) {
+                        queue.push((cmd_str, v));
+                        return Frame::SimpleString("QUEUED".into()).encode().into();
+                    }
+                }
+
                 match cmd_str.as_str() {
-                    "ping" => default::ping(v).await,
-                    "echo" => default::echo(v).await,
-                    "set" => default::set(v).await,
-                    "get" => default::get(v).await,
-                    "save" => default::save(v).await,
-                    "keys" => default::keys(v).await,
-                    "config" => {
-                        if v.is_empty() {
-                            return default::error("ERR wrong number of arguments for 'config'")
-                                .await;
+                    "multi" => default::multi(v, state).await.into(),
+                    "quit" => DispatchResult {
+                        reply: default::quit(v).await,
+                        action: ConnAction::Close,
+                    },
+                    "exec" => {
+                        if !v.is_empty() {
+                            return default::error("ERR wrong number of arguments for 'exec'")
+                                .await
+                                .into();
                         }
-                        // First argument is subcommand (e.g., GET)
-                        if let Frame::BulkString(Some(subcmd)) = v.remove(0) {
-                            let subcmd_str = String::from_utf8_lossy(&subcmd).to_lowercase();
-                            match subcmd_str.as_str() {
-                                "get" => default::config_get(v).await,
-                                "set" => default::config_set(v).await,
-                                _ => default::error("ERR unknown subcommand for 'config'").await,
-                            }
-                        } else {
-                            default::error("ERR invalid subcommand for 'config'").await
+                        let Some(queue) = state.multi.take() else {
+                            return default::error("ERR EXEC without MULTI").await.into();
+                        };
+                        // Replies are already encoded, so build the array header by hand
+                        let mut reply = format!("*{}\r\n", queue.len()).into_bytes();
+                        // Like the rest of the transaction, its writes are logged without
+                        // any other write in between
+                        let _writes = WRITES.write().await;
+                        state.in_exec = true;
+                        for (cmd, args) in queue {
+                            // Re-read the index for each command, since the queue may SELECT
+                            let (db_index, no_touch) = (state.db_index, state.no_touch);
+                            let run = db::with_no_touch(no_touch, execute(&cmd, args, state));
+                            reply.extend(db::with_db(db_index, run).await);
                         }
+                        state.in_exec = false;
+                        reply.into()
+                    }
+                    _ => {
+                        let (db_index, no_touch) = (state.db_index, state.no_touch);
+                        let run = db::with_no_touch(no_touch, execute(&cmd_str, v, state));
+                        db::with_db(db_index, run).await.into()
                     }
-                    _ => default::unknown().await,
                 }
             } else {
-                default::error("Protocol error: invalid command").await
+                default::error("Protocol error: invalid command")
+                    .await
+                    .into()
+            }
+        }
+        _ => default::error("Protocol error: expected array")
+            .await
+            .into(),
+    }
+}
+
+/// Held by a write from applying its effect until it has been propagated, so the AOF
+/// and replicas get writes in the order they were applied. Writes only take it
+/// exclusively while there is somewhere to propagate them, and otherwise share it, so
+/// that whatever has to see every write at once, like PSYNC, can hold them all off.
+static WRITES: RwLock<()> = RwLock::const_new(());
+
+/// A write's hold on WRITES
+pub enum WriteTurn {
+    Shared {
+        _guard: RwLockReadGuard<'static, ()>,
+    },
+    Exclusive {
+        _guard: RwLockWriteGuard<'static, ()>,
+    },
+}
+
+/// Take WRITES for a write, exclusively if it has to be appended to the AOF or sent to
+/// replicas. Registering a replica and starting the AOF over hold off every write first,
+/// so a write that shared WRITES is in the snapshot they start from.
+async fn write_turn() -> WriteTurn {
+    if config::get_appendonly() || replication::has_replicas() {
+        return WriteTurn::Exclusive {
+            _guard: WRITES.write().await,
+        };
+    }
+    WriteTurn::Shared {
+        _guard: WRITES.read().await,
+    }
+}
+
+/// How the write that is running reaches the AOF and replicas
+struct Propagation {
+    replicate: bool, // a replica counts its master's stream as it reads it, in the replication link
+    done: Cell<bool>, // the handler took over propagating the write's effect
+    changed: Cell<bool>, // something was propagated, so the write changed the dataset
+    serialised: bool, // the command already holds WRITES
+}
+
+tokio::task_local! {
+    /// The write whose command is running
+    static PROPAGATION: Propagation;
+}
+
+/// Append a command to the AOF and send it to replicas as the effect of the running
+/// write, instead of the write itself. Handlers call this when replaying their command
+/// could have another effect: blocking pops are sent as the plain pop that served them,
+/// since a replica must never block on its master's stream, and commands that depend
+/// on randomness or the clock as what they did, so replaying them anywhere, at any
+/// time, gives the same dataset.
+pub fn propagate(command: Vec<Frame>) {
+    let replicate = PROPAGATION
+        .try_with(|propagation| {
+            propagation.done.set(true);
+            propagation.changed.set(true);
+            propagation.replicate
+        })
+        .unwrap_or(true);
+    aof::feed(&command);
+    if replicate {
+        replication::propagate(command);
+    }
+}
+
+/// Take WRITES, for writes that don't get it for their whole run: blocking commands take
+/// it for each attempt, as waiting with it would keep out the push they wait for.
+/// Returns None if the running command already holds it.
+pub async fn serialise() -> Option<WriteTurn> {
+    if PROPAGATION.try_with(|propagation| propagation.serialised) == Ok(true) {
+        return None;
+    }
+    Some(write_turn().await)
+}
+
+/// Hold off every write, for what has to see the dataset and where writes go change
+/// together: registering a replica, or starting the AOF over. Returns None inside EXEC,
+/// which already holds WRITES exclusively.
+pub async fn hold_off_writes() -> Option<RwLockWriteGuard<'static, ()>> {
+    if PROPAGATION.try_with(|propagation| propagation.serialised) == Ok(true) {
+        return None;
+    }
+    Some(WRITES.write().await)
+}
+
+/// Keep the running write from being propagated as it was given. Handlers call this when
+/// the write left the dataset as it was, like a DEL of missing keys, and before running
+/// one whose effect they propagate themselves, so the paths that change nothing send nothing.
+/// A write only counts as a change for the next save if something was propagated.
+pub fn prevent_propagation() {
+    let _ = PROPAGATION.try_with(|propagation| propagation.done.set(true));
+}
+
+/// Run a single, already-parsed command against the connection's state,
+/// appending it to the AOF and propagating it to replicas if it is a write.
+async fn execute(cmd_str: &str, v: Vec<Frame>, state: &mut ClientState) -> Vec<u8> {
+    let write = registry::is_write(cmd_str);
+    let verbatim = write.then(|| v.clone());
+    // Like Redis, monitors don't see admin commands or passwords
+    if registry::lookup(cmd_str).is_some_and(|spec| !spec.has_flag("admin")) && cmd_str != "auth" {
+        monitor::feed(state.id, db::current_db(), &state.addr, cmd_str, &v);
+    }
+    let blocking = registry::lookup(cmd_str).is_some_and(|spec| spec.has_flag("blocking"));
+    // Inside EXEC, which holds WRITES across the transaction, nothing blocks
+    let _writes = if write && !blocking && !state.in_exec {
+        Some(write_turn().await)
+    } else {
+        None
+    };
+    let propagation = Propagation {
+        replicate: !state.master_link,
+        done: Cell::new(false),
+        changed: Cell::new(false),
+        serialised: _writes.is_some() || state.in_exec,
+    };
+    let (reply, changed) = PROPAGATION
+        .scope(propagation, async {
+            let reply = run(cmd_str, v, state).await;
+            let done = PROPAGATION.with(|propagation| propagation.done.get());
+            if let Some(args) = verbatim {
+                if !done && !reply.starts_with(b"-") {
+                    let mut command = vec![Frame::BulkString(Some(cmd_str.as_bytes().to_vec()))];
+                    command.extend(args);
+                    propagate(command);
+                }
+            }
+            (
+                reply,
+                PROPAGATION.with(|propagation| propagation.changed.get()),
+            )
+        })
+        .await;
+    if changed {
+        rdb::record_changes(1);
+    }
+    reply
+}
+
+/// Run a command and return its encoded reply
+async fn run(cmd_str: &str, mut v: Vec<Frame>, state: &mut ClientState) -> Vec<u8> {
+    match cmd_str {
+        "ping" => default::ping(v, state).await,
+        "echo" => default::echo(v).await,
+        "time" => default::time(v).await,
+        "dbsize" => default::dbsize(v).await,
+        "set" => default::set(v).await,
+        "mget" => default::mget(v).await,
+        "mset" => default::mset(v).await,
+        "pfadd" => default::pfadd(v).await,
+        "pfcount" => default::pfcount(v).await,
+        "pfmerge" => default::pfmerge(v).await,
+        "get" => default::get(v).await,
+        "type" => default::type_of(v).await,
+        "del" | "unlink" => default::del(v, cmd_str).await,
+        "getdel" => default::getdel(v).await,
+        "getset" => default::getset(v).await,
+        "getex" => default::getex(v).await,
+        "setex" | "psetex" => default::setex(v, cmd_str).await,
+        "expire" | "pexpire" | "expireat" | "pexpireat" => default::expire(v, cmd_str).await,
+        "incr" | "decr" | "incrby" | "decrby" => default::incr(v, cmd_str).await,
+        "incrbyfloat" => default::incrbyfloat(v).await,
+        "setbit" => default::setbit(v).await,
+        "getbit" => default::getbit(v).await,
+        "bitcount" => default::bitcount(v).await,
+        "getrange" | "substr" => default::getrange(v, cmd_str).await,
+        "strlen" => default::strlen(v).await,
+        "append" => default::append(v).await,
+        "setrange" => default::setrange(v).await,
+        "bitop" => default::bitop(v).await,
+        "save" => default::save(v).await,
+        "bgsave" => default::bgsave(v).await,
+        "waitaof" => default::waitaof(v).await,
+        "replicaof" | "slaveof" => default::replicaof(v, cmd_str).await,
+        "replconf" => default::replconf(v, state).await,
+        "psync" => default::psync(v, state).await,
+        "keys" => default::keys(v).await,
+        "expiretime" => default::expiretime(v, false).await,
+        "pexpiretime" => default::expiretime(v, true).await,
+        "scan" => default::scan(v).await,
+        "hscan" => default::scan_members(v, "hash").await,
+        "sscan" => default::scan_members(v, "set").await,
+        "zscan" => default::scan_members(v, "zset").await,
+        "info" => default::info(v).await,
+        "dump" => default::dump(v).await,
+        "restore" => default::restore(v).await,
+        "sadd" => default::sadd(v).await,
+        "srem" => default::srem(v).await,
+        "spop" => default::spop(v).await,
+        "srandmember" => default::srandmember(v).await,
+        "sintercard" => default::intercard(v, false).await,
+        "zintercard" => default::intercard(v, true).await,
+        "hset" | "hmset" => default::hset(v, cmd_str).await,
+        "hsetnx" => default::hsetnx(v).await,
+        "hget" => default::hget(v).await,
+        "hgetall" => default::hgetall(v, state).await,
+        "hrandfield" => default::hrandfield(v, state).await,
+        "hexpire" | "hpexpire" | "hexpireat" | "hpexpireat" => default::hexpire(v, cmd_str).await,
+        "httl" => default::httl(v).await,
+        "hgetex" => default::hgetex(v).await,
+        "hgetdel" => default::hgetdel(v).await,
+        "xadd" => default::xadd(v).await,
+        "xlen" => default::xlen(v).await,
+        "xrange" => default::xrange(v).await,
+        "xinfo" => {
+            if v.is_empty() {
+                return default::error("ERR wrong number of arguments for 'xinfo'").await;
+            }
+            if let Frame::BulkString(Some(subcmd)) = v.remove(0) {
+                let subcmd_str = String::from_utf8_lossy(&subcmd).to_lowercase();
+                match subcmd_str.as_str() {
+                    "stream" => default::xinfo_stream(v, state).await,
+                    "help" => default::help("xinfo", v).await,
+                    _ => default::error("ERR unknown subcommand for 'xinfo'").await,
+                }
+            } else {
+                default::error("ERR invalid subcommand for 'xinfo'").await
+            }
+        }
+        "zadd" => default::zadd(v, state).await,
+        "zscore" => default::zscore(v, state).await,
+        "zmscore" => default::zmscore(v, state).await,
+        "zincrby" => default::zincrby(v, state).await,
+        "zrangebyscore" => default::zrangebyscore(v, state).await,
+        "zrandmember" => default::zrandmember(v, state).await,
+        "geoadd" => default::geoadd(v).await,
+        "geopos" => default::geopos(v, state).await,
+        "geodist" => default::geodist(v).await,
+        "lpush" => default::push(v, true).await,
+        "rpush" => default::push(v, false).await,
+        "lpop" => default::pop(v, true).await,
+        "rpop" => default::pop(v, false).await,
+        "blpop" => default::blocking_pop(v, true, state).await,
+        "lmpop" | "zmpop" | "blmpop" | "bzmpop" => default::mpop(v, cmd_str, state).await,
+        "brpop" => default::blocking_pop(v, false, state).await,
+        "lmove" | "rpoplpush" | "blmove" | "brpoplpush" => default::lmove(v, cmd_str, state).await,
+        "lrange" => default::lrange(v).await,
+        "lpos" => default::lpos(v).await,
+        "lrem" => default::lrem(v).await,
+        "ltrim" => default::ltrim(v).await,
+        "linsert" => default::linsert(v).await,
+        "auth" => default::auth(v, state).await,
+        "hello" => default::hello(v, state).await,
+        "select" => default::select(v, state).await,
+        "move" => default::move_key(v).await,
+        "swapdb" => default::swapdb(v).await,
+        "subscribe" => default::subscribe(v, state, false).await,
+        "unsubscribe" => default::unsubscribe(v, state, false).await,
+        "psubscribe" => default::subscribe(v, state, true).await,
+        "punsubscribe" => default::unsubscribe(v, state, true).await,
+        "publish" => default::publish(v).await,
+        "discard" => default::discard(v, state).await,
+        "reset" => default::reset(v, state).await,
+        "monitor" => default::monitor(v, state).await,
+        "client" => {
+            if v.is_empty() {
+                return default::error("ERR wrong number of arguments for 'client'").await;
+            }
+            if let Frame::BulkString(Some(subcmd)) = v.remove(0) {
+                let subcmd_str = String::from_utf8_lossy(&subcmd).to_lowercase();
+                match subcmd_str.as_str() {
+                    "setname" => default::client_setname(v, state).await,
+                    "getname" => default::client_getname(v, state).await,
+                    "no-evict" | "no-touch" => default::client_flag(v, state, &subcmd_str).await,
+                    "help" => default::help("client", v).await,
+                    _ => default::error("ERR unknown subcommand for 'client'").await,
+                }
+            } else {
+                default::error("ERR invalid subcommand for 'client'").await
+            }
+        }
+        "acl" => {
+            if v.is_empty() {
+                return default::error("ERR wrong number of arguments for 'acl'").await;
+            }
+            if let Frame::BulkString(Some(subcmd)) = v.remove(0) {
+                let subcmd_str = String::from_utf8_lossy(&subcmd).to_lowercase();
+                match subcmd_str.as_str() {
+                    "whoami" => default::acl_whoami(v).await,
+                    "list" => default::acl_list(v).await,
+                    "users" => default::acl_users(v).await,
+                    "cat" => default::acl_cat(v).await,
+                    "getuser" => default::acl_getuser(v, state).await,
+                    "help" => default::help("acl", v).await,
+                    _ => default::error("ERR unknown subcommand for 'acl'").await,
+                }
+            } else {
+                default::error("ERR invalid subcommand for 'acl'").await
             }
         }
-        _ => default::error("Protocol error: expected array").await,
+        "memory" => {
+            if v.is_empty() {
+                return default::error("ERR wrong number of arguments for 'memory'").await;
+            }
+            if let Frame::BulkString(Some(subcmd)) = v.remove(0) {
+                let subcmd_str = String::from_utf8_lossy(&subcmd).to_lowercase();
+                match subcmd_str.as_str() {
+                    "usage" => default::memory_usage(v).await,
+                    "doctor" => default::memory_doctor(v).await,
+                    "help" => default::help("memory", v).await,
+                    _ => default::error("ERR unknown subcommand for 'memory'").await,
+                }
+            } else {
+                default::error("ERR invalid subcommand for 'memory'").await
+            }
+        }
+        "object" => {
+            if v.is_empty() {
+                return default::error("ERR wrong number of arguments for 'object'").await;
+            }
+            if let Frame::BulkString(Some(subcmd)) = v.remove(0) {
+                let subcmd_str = String::from_utf8_lossy(&subcmd).to_lowercase();
+                match subcmd_str.as_str() {
+                    "encoding" => default::object_encoding(v).await,
+                    "refcount" => default::object_refcount(v).await,
+                    "idletime" => default::object_idletime(v).await,
+                    "freq" => default::object_freq(v).await,
+                    "help" => default::help("object", v).await,
+                    _ => default::error("ERR unknown subcommand for 'object'").await,
+                }
+            } else {
+                default::error("ERR invalid subcommand for 'object'").await
+            }
+        }
+        "pubsub" => {
+            if v.is_empty() {
+                return default::error("ERR wrong number of arguments for 'pubsub'").await;
+            }
+            if let Frame::BulkString(Some(subcmd)) = v.remove(0) {
+                let subcmd_str = String::from_utf8_lossy(&subcmd).to_lowercase();
+                match subcmd_str.as_str() {
+                    "channels" => default::pubsub_channels(v).await,
+                    "numsub" => default::pubsub_numsub(v).await,
+                    "numpat" => default::pubsub_numpat(v).await,
+                    "help" => default::help("pubsub", v).await,
+                    _ => default::error("ERR unknown subcommand for 'pubsub'").await,
+                }
+            } else {
+                default::error("ERR invalid subcommand for 'pubsub'").await
+            }
+        }
+        "debug" => {
+            if v.is_empty() {
+                return default::error("ERR wrong number of arguments for 'debug'").await;
+            }
+            if let Frame::BulkString(Some(subcmd)) = v.remove(0) {
+                let subcmd_str = String::from_utf8_lossy(&subcmd).to_lowercase();
+                match subcmd_str.as_str() {
+                    "reload" => default::debug_reload(v).await,
+                    "dumpkeys" => default::debug_dumpkeys(v).await,
+                    "sleep" => default::debug_sleep(v).await,
+                    "set-active-expire" => default::debug_set_active_expire(v).await,
+                    "stringmatch-len" => default::debug_stringmatch_len(v).await,
+                    "object" => default::debug_object(v).await,
+                    "quicklist" => default::debug_quicklist(v).await,
+                    "change-repl-id" => default::debug_change_repl_id(v).await,
+                    "help" => default::help("debug", v).await,
+                    _ => default::error("ERR unknown subcommand for 'debug'").await,
+                }
+            } else {
+                default::error("ERR invalid subcommand for 'debug'").await
+            }
+        }
+        "slowlog" => {
+            if v.is_empty() {
+                return default::error("ERR wrong number of arguments for 'slowlog'").await;
+            }
+            if let Frame::BulkString(Some(subcmd)) = v.remove(0) {
+                let subcmd_str = String::from_utf8_lossy(&subcmd).to_lowercase();
+                match subcmd_str.as_str() {
+                    "get" => default::slowlog_get(v, state).await,
+                    "len" => default::slowlog_len(v).await,
+                    "reset" => default::slowlog_reset(v).await,
+                    "help" => default::help("slowlog", v).await,
+                    _ => default::error("ERR unknown subcommand for 'slowlog'").await,
+                }
+            } else {
+                default::error("ERR invalid subcommand for 'slowlog'").await
+            }
+        }
+        "cluster" => {
+            if v.is_empty() {
+                return default::error("ERR wrong number of arguments for 'cluster'").await;
+            }
+            if let Frame::BulkString(Some(subcmd)) = v.remove(0) {
+                let subcmd_str = String::from_utf8_lossy(&subcmd).to_lowercase();
+                match subcmd_str.as_str() {
+                    "info" => default::cluster_info(v).await,
+                    "slots" => default::cluster_slots(v).await,
+                    "nodes" => default::cluster_nodes(v).await,
+                    "myid" => default::cluster_myid(v).await,
+                    "help" => default::help("cluster", v).await,
+                    _ => default::error("ERR unknown subcommand for 'cluster'").await,
+                }
+            } else {
+                default::error("ERR invalid subcommand for 'cluster'").await
+            }
+        }
+        "command" => {
+            if v.is_empty() {
+                return default::error("ERR wrong number of arguments for 'command'").await;
+            }
+            if let Frame::BulkString(Some(subcmd)) = v.remove(0) {
+                let subcmd_str = String::from_utf8_lossy(&subcmd).to_lowercase();
+                match subcmd_str.as_str() {
+                    "count" => default::command_count(v).await,
+                    "docs" => default::command_docs(v, state).await,
+                    "getkeys" => default::command_getkeys(v).await,
+                    "info" => default::command_info(v).await,
+                    "help" => default::help("command", v).await,
+                    _ => default::error("ERR unknown subcommand for 'command'").await,
+                }
+            } else {
+                default::error("ERR invalid subcommand for 'command'").await
+            }
+        }
+        "config" => {
+            if v.is_empty() {
+                return default::error("ERR wrong number of arguments for 'config'").await;
+            }
+            // First argument is subcommand (e.g., GET)
+            if let Frame::BulkString(Some(subcmd)) = v.remove(0) {
+                let subcmd_str = String::from_utf8_lossy(&subcmd).to_lowercase();
+                match subcmd_str.as_str() {
+                    "get" => default::config_get(v).await,
+                    "set" => default::config_set(v).await,
+                    "resetstat" => default::config_resetstat(v).await,
+                    "help" => default::help("config", v).await,
+                    _ => default::error("ERR unknown subcommand for 'config'").await,
+                }
+            } else {
+                default::error("ERR invalid subcommand for 'config'").await
+            }
+        }
+        _ => default::unknown().await,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn command(args: &[&str]) -> Frame {
+        Frame::Array(Some(
+            args.iter()
+                .map(|arg| Frame::BulkString(Some(arg.as_bytes().to_vec())))
+                .collect(),
+        ))
+    }
+
+    #[tokio::test]
+    async fn dispatch_reports_what_the_connection_should_do_next() {
+        let mut state = ClientState::new();
+        state.authenticated = true;
+        // Subscribing needs somewhere to push messages, as a connection has
+        let (push_tx, _push_rx) = crate::output::channel();
+        state.push_tx = Some(push_tx);
+
+        let ping = dispatch(command(&["ping"]), &mut state).await;
+        assert_eq!(
+            (ping.reply, ping.action),
+            (b"+PONG\r\n".to_vec(), ConnAction::None)
+        );
+
+        let first = dispatch(command(&["subscribe", "dispatch:a"]), &mut state).await;
+        assert_eq!(first.action, ConnAction::EnterSubscriber);
+        let second = dispatch(command(&["subscribe", "dispatch:b"]), &mut state).await;
+        assert_eq!(second.action, ConnAction::None);
+        let last = dispatch(command(&["unsubscribe"]), &mut state).await;
+        assert_eq!(last.action, ConnAction::ExitSubscriber);
+
+        let quit = dispatch(command(&["quit"]), &mut state).await;
+        assert_eq!(
+            (quit.reply, quit.action),
+            (b"+OK\r\n".to_vec(), ConnAction::Close)
+        );
     }
 }
@@ -0,0 +1,491 @@
+//! Static metadata about every command the server understands
+
+/// Arity, flags and key positions of a command, following Redis' COMMAND conventions:
+/// a negative arity is a minimum, and positions count the command name as 0.
+#[derive(Debug)]
+pub struct CommandSpec {
+    pub name: &'static str,
+    pub arity: i32,
+    pub flags: &'static str, // space-separated, as COMMAND INFO names them
+    pub first_key: i32,      // 0 if the command takes no keys
+    pub last_key: i32,       // negative values count from the end of the arguments
+    pub step: i32,
+    pub numkeys_at: Option<usize>, // for movable keys: position of the numkeys argument
+}
+
+const fn spec(
+    name: &'static str,
+    arity: i32,
+    flags: &'static str,
+    first_key: i32,
+    last_key: i32,
+    step: i32,
+) -> CommandSpec {
+    CommandSpec {
+        name,
+        arity,
+        flags,
+        first_key,
+        last_key,
+        step,
+        numkeys_at: None,
+    }
+}
+
+/// A command whose keys follow a numkeys argument at position `numkeys_at`
+const fn numkeys_spec(
+    name: &'static str,
+    arity: i32,
+    flags: &'static str,
+    numkeys_at: usize,
+) -> CommandSpec {
+    CommandSpec {
+        name,
+        arity,
+        flags,
+        first_key: 0,
+        last_key: 0,
+        step: 0,
+        numkeys_at: Some(numkeys_at),
+    }
+}
+
+pub const COMMANDS: &[CommandSpec] = &[
+    spec("acl", -2, "", 0, 0, 0),
+    spec("append", 3, "write denyoom fast", 1, 1, 1),
+    spec(
+        "auth",
+        -2,
+        "noscript loading stale fast no-auth allow-busy",
+        0,
+        0,
+        0,
+    ),
+    spec("bgsave", -1, "admin noscript no-async-loading", 0, 0, 0),
+    spec("bitcount", -2, "readonly", 1, 1, 1),
+    spec("bitop", -4, "write denyoom", 2, -1, 1),
+    spec("blmove", 6, "write denyoom noscript blocking", 1, 2, 1),
+    numkeys_spec("blmpop", -5, "write noscript blocking", 2),
+    spec("blpop", -3, "write noscript blocking", 1, -2, 1),
+    spec("brpop", -3, "write noscript blocking", 1, -2, 1),
+    spec("brpoplpush", 4, "write denyoom noscript blocking", 1, 2, 1),
+    numkeys_spec("bzmpop", -5, "write noscript blocking", 2),
+    spec("client", -2, "", 0, 0, 0),
+    spec("cluster", -2, "", 0, 0, 0),
+    spec("command", -1, "loading stale", 0, 0, 0),
+    spec("config", -2, "admin noscript loading stale", 0, 0, 0),
+    spec("dbsize", 1, "readonly fast", 0, 0, 0),
+    spec("debug", -2, "admin noscript loading stale", 0, 0, 0),
+    spec("decr", 2, "write denyoom fast", 1, 1, 1),
+    spec("decrby", 3, "write denyoom fast", 1, 1, 1),
+    spec("del", -2, "write", 1, -1, 1),
+    spec(
+        "discard",
+        1,
+        "noscript loading stale fast allow-busy",
+        0,
+        0,
+        0,
+    ),
+    spec("dump", 2, "readonly", 1, 1, 1),
+    spec("echo", 2, "fast", 0, 0, 0),
+    spec("exec", 1, "noscript loading stale skip-slowlog", 0, 0, 0),
+    spec("expire", -3, "write fast", 1, 1, 1),
+    spec("expireat", -3, "write fast", 1, 1, 1),
+    spec("expiretime", 2, "readonly fast", 1, 1, 1),
+    spec("geoadd", -5, "write denyoom", 1, 1, 1),
+    spec("geodist", -4, "readonly", 1, 1, 1),
+    spec("geopos", -2, "readonly", 1, 1, 1),
+    spec("get", 2, "readonly fast", 1, 1, 1),
+    spec("getbit", 3, "readonly fast", 1, 1, 1),
+    spec("getdel", 2, "write fast", 1, 1, 1),
+    spec("getex", -2, "write fast", 1, 1, 1),
+    spec("getrange", 4, "readonly", 1, 1, 1),
+    spec("getset", 3, "write denyoom fast", 1, 1, 1),
+    spec(
+        "hello",
+        -1,
+        "noscript loading stale fast no-auth allow-busy",
+        0,
+        0,
+        0,
+    ),
+    spec("hexpire", -6, "write denyoom fast", 1, 1, 1),
+    spec("hexpireat", -6, "write denyoom fast", 1, 1, 1),
+    spec("hget", 3, "readonly fast", 1, 1, 1),
+    spec("hgetall", 2, "readonly", 1, 1, 1),
+    spec("hgetdel", -5, "write fast", 1, 1, 1),
+    spec("hgetex", -5, "write fast", 1, 1, 1),
+    spec("hmset", -4, "write denyoom fast", 1, 1, 1),
+    spec("hpexpire", -6, "write denyoom fast", 1, 1, 1),
+    spec("hpexpireat", -6, "write denyoom fast", 1, 1, 1),
+    spec("hrandfield", -2, "readonly", 1, 1, 1),
+    spec("hscan", -3, "readonly", 1, 1, 1),
+    spec("hset", -4, "write denyoom fast", 1, 1, 1),
+    spec("hsetnx", 4, "write denyoom fast", 1, 1, 1),
+    spec("httl", -5, "readonly fast", 1, 1, 1),
+    spec("incr", 2, "write denyoom fast", 1, 1, 1),
+    spec("incrby", 3, "write denyoom fast", 1, 1, 1),
+    spec("incrbyfloat", 3, "write denyoom fast", 1, 1, 1),
+    spec("info", -1, "loading stale", 0, 0, 0),
+    spec("keys", 2, "readonly", 0, 0, 0),
+    spec("linsert", 5, "write denyoom", 1, 1, 1),
+    spec("lmove", 5, "write denyoom", 1, 2, 1),
+    numkeys_spec("lmpop", -4, "write", 1),
+    spec("lpop", -2, "write fast", 1, 1, 1),
+    spec("lpos", -3, "readonly", 1, 1, 1),
+    spec("lpush", -3, "write denyoom fast", 1, 1, 1),
+    spec("lrange", 4, "readonly", 1, 1, 1),
+    spec("lrem", 4, "write", 1, 1, 1),
+    spec("ltrim", 4, "write", 1, 1, 1),
+    spec("memory", -2, "", 0, 0, 0),
+    spec("mget", -2, "readonly fast", 1, -1, 1),
+    spec("monitor", 1, "admin noscript loading stale", 0, 0, 0),
+    spec("move", 3, "write fast", 1, 1, 1),
+    spec("mset", -3, "write denyoom", 1, -1, 2),
+    spec(
+        "multi",
+        1,
+        "noscript loading stale fast allow-busy",
+        0,
+        0,
+        0,
+    ),
+    spec("object", -2, "", 0, 0, 0),
+    spec("pexpire", -3, "write fast", 1, 1, 1),
+    spec("pexpireat", -3, "write fast", 1, 1, 1),
+    spec("pexpiretime", 2, "readonly fast", 1, 1, 1),
+    spec("pfadd", -2, "write denyoom fast", 1, 1, 1),
+    spec("pfcount", -2, "readonly", 1, -1, 1),
+    spec("pfmerge", -2, "write denyoom", 1, -1, 1),
+    spec("ping", -1, "fast", 0, 0, 0),
+    spec("psetex", 4, "write denyoom", 1, 1, 1),
+    spec("psubscribe", -2, "pubsub noscript loading stale", 0, 0, 0),
+    spec(
+        "psync",
+        -3,
+        "admin noscript no-async-loading no-multi",
+        0,
+        0,
+        0,
+    ),
+    spec("publish", 3, "pubsub loading stale fast", 0, 0, 0),
+    spec("pubsub", -2, "", 0, 0, 0),
+    spec("punsubscribe", -1, "pubsub noscript loading stale", 0, 0, 0),
+    spec(
+        "quit",
+        -1,
+        "noscript loading stale fast no-auth allow-busy",
+        0,
+        0,
+        0,
+    ),
+    spec(
+        "replconf",
+        -1,
+        "admin noscript loading stale allow-busy",
+        0,
+        0,
+        0,
+    ),
+    spec(
+        "replicaof",
+        3,
+        "admin noscript stale no-async-loading",
+        0,
+        0,
+        0,
+    ),
+    spec(
+        "reset",
+        1,
+        "noscript loading stale fast no-auth allow-busy",
+        0,
+        0,
+        0,
+    ),
+    spec("restore", -4, "write denyoom", 1, 1, 1),
+    spec("rpop", -2, "write fast", 1, 1, 1),
+    spec("rpoplpush", 3, "write denyoom", 1, 2, 1),
+    spec("rpush", -3, "write denyoom fast", 1, 1, 1),
+    spec("sadd", -3, "write denyoom fast", 1, 1, 1),
+    spec(
+        "save",
+        1,
+        "admin noscript no-async-loading no-multi",
+        0,
+        0,
+        0,
+    ),
+    spec("scan", -2, "readonly", 0, 0, 0),
+    spec("select", 2, "loading stale fast", 0, 0, 0),
+    spec("set", -3, "write denyoom", 1, 1, 1),
+    spec("setbit", 4, "write denyoom", 1, 1, 1),
+    spec("setex", 4, "write denyoom", 1, 1, 1),
+    spec("setrange", 4, "write denyoom", 1, 1, 1),
+    numkeys_spec("sintercard", -3, "readonly", 1),
+    spec(
+        "slaveof",
+        3,
+        "admin noscript stale no-async-loading",
+        0,
+        0,
+        0,
+    ),
+    spec("slowlog", -2, "", 0, 0, 0),
+    spec("spop", -2, "write fast", 1, 1, 1),
+    spec("srandmember", -2, "readonly", 1, 1, 1),
+    spec("srem", -3, "write fast", 1, 1, 1),
+    spec("sscan", -3, "readonly", 1, 1, 1),
+    spec("strlen", 2, "readonly fast", 1, 1, 1),
+    spec("subscribe", -2, "pubsub noscript loading stale", 0, 0, 0),
+    spec("substr", 4, "readonly", 1, 1, 1),
+    spec("swapdb", 3, "write fast", 0, 0, 0),
+    spec("time", 1, "loading stale fast", 0, 0, 0),
+    spec("type", 2, "readonly fast", 1, 1, 1),
+    spec("unlink", -2, "write fast", 1, -1, 1),
+    spec("unsubscribe", -1, "pubsub noscript loading stale", 0, 0, 0),
+    spec("waitaof", 4, "noscript", 0, 0, 0),
+    spec("xadd", -5, "write denyoom fast", 1, 1, 1),
+    spec("xinfo", -2, "", 0, 0, 0),
+    spec("xlen", 2, "readonly fast", 1, 1, 1),
+    spec("xrange", -4, "readonly", 1, 1, 1),
+    spec("zadd", -4, "write denyoom fast", 1, 1, 1),
+    spec("zincrby", 4, "write denyoom fast", 1, 1, 1),
+    numkeys_spec("zintercard", -3, "readonly", 1),
+    numkeys_spec("zmpop", -4, "write", 1),
+    spec("zmscore", -3, "readonly fast", 1, 1, 1),
+    spec("zrandmember", -2, "readonly", 1, 1, 1),
+    spec("zrangebyscore", -4, "readonly", 1, 1, 1),
+    spec("zscan", -3, "readonly", 1, 1, 1),
+    spec("zscore", 3, "readonly fast", 1, 1, 1),
+];
+
+/// What COMMAND DOCS tells about a command
+#[derive(Debug)]
+pub struct CommandDoc {
+    pub name: &'static str,
+    pub group: &'static str, // generic, string, list, set, sorted-set, hash, ...
+    pub since: &'static str, // Redis version that introduced the command
+    pub summary: &'static str,
+}
+
+const fn doc(
+    name: &'static str,
+    group: &'static str,
+    since: &'static str,
+    summary: &'static str,
+) -> CommandDoc {
+    CommandDoc {
+        name,
+        group,
+        since,
+        summary,
+    }
+}
+
+/// Documentation of every command in COMMANDS, in the same order
+pub const DOCS: &[CommandDoc] = &[
+    doc("acl", "server", "6.0.0", "A container for Access List Control commands."),
+    doc("append", "string", "2.0.0", "Appends a string to the value of a key. Creates the key if it doesn't exist."),
+    doc("auth", "connection", "1.0.0", "Authenticates the connection."),
+    doc("bgsave", "server", "1.0.0", "Asynchronously saves the database(s) to disk."),
+    doc("bitcount", "bitmap", "2.6.0", "Counts the number of set bits (population counting) in a string."),
+    doc("bitop", "bitmap", "2.6.0", "Performs bitwise operations on multiple strings, and stores the result."),
+    doc("blmove", "list", "6.2.0", "Pops an element from a list, pushes it to another list and returns it. Blocks until an element is available otherwise. Deletes the list if the last element was moved."),
+    doc("blmpop", "list", "7.0.0", "Pops the first element from one of multiple lists. Blocks until an element is available otherwise. Deletes the list if the last element was popped."),
+    doc("blpop", "list", "2.0.0", "Removes and returns the first element in a list. Blocks until an element is available otherwise. Deletes the list if the last element was popped."),
+    doc("brpop", "list", "2.0.0", "Removes and returns the last element in a list. Blocks until an element is available otherwise. Deletes the list if the last element was popped."),
+    doc("brpoplpush", "list", "2.2.0", "Pops an element from a list, pushes it to another list and returns it. Block until an element is available otherwise. Deletes the list if the last element was popped."),
+    doc("bzmpop", "sorted-set", "7.0.0", "Removes and returns a member by score from one or more sorted sets. Blocks until a member is available otherwise. Deletes the sorted set if the last element was popped."),
+    doc("client", "connection", "2.4.0", "A container for client connection commands."),
+    doc("cluster", "cluster", "3.0.0", "A container for Redis Cluster commands."),
+    doc("command", "server", "2.8.13", "Returns detailed information about all commands."),
+    doc("config", "server", "2.0.0", "A container for server configuration commands."),
+    doc("dbsize", "server", "1.0.0", "Returns the number of keys in the database."),
+    doc("debug", "server", "1.0.0", "A container for debugging commands."),
+    doc("decr", "string", "1.0.0", "Decrements the integer value of a key by one. Uses 0 as initial value if the key doesn't exist."),
+    doc("decrby", "string", "1.0.0", "Decrements a number from the integer value of a key. Uses 0 as initial value if the key doesn't exist."),
+    doc("del", "generic", "1.0.0", "Deletes one or more keys."),
+    doc("discard", "transactions", "2.0.0", "Discards a transaction."),
+    doc("dump", "generic", "2.6.0", "Returns a serialized representation of the value stored at a key."),
+    doc("echo", "connection", "1.0.0", "Returns the given string."),
+    doc("exec", "transactions", "1.2.0", "Executes all commands in a transaction."),
+    doc("expire", "generic", "1.0.0", "Sets the expiration time of a key in seconds."),
+    doc("expireat", "generic", "1.2.0", "Sets the expiration time of a key to a Unix timestamp."),
+    doc("expiretime", "generic", "7.0.0", "Returns the expiration time of a key as a Unix timestamp."),
+    doc("geoadd", "geo", "3.2.0", "Adds one or more members to a geospatial index. The key is created if it doesn't exist."),
+    doc("geodist", "geo", "3.2.0", "Returns the distance between two members of a geospatial index."),
+    doc("geopos", "geo", "3.2.0", "Returns the longitude and latitude of members from a geospatial index."),
+    doc("get", "string", "1.0.0", "Returns the string value of a key."),
+    doc("getbit", "bitmap", "2.2.0", "Returns a bit value by offset."),
+    doc("getdel", "string", "6.2.0", "Returns the string value of a key after deleting the key."),
+    doc("getex", "string", "6.2.0", "Returns the string value of a key after setting its expiration time."),
+    doc("getrange", "string", "2.4.0", "Returns a substring of the string stored at a key."),
+    doc("getset", "string", "1.0.0", "Returns the previous string value of a key after setting it to a new value."),
+    doc("hello", "connection", "6.0.0", "Handshakes with the Redis server."),
+    doc("hexpire", "hash", "7.4.0", "Set expiry for hash field using relative time to expire (seconds)"),
+    doc("hexpireat", "hash", "7.4.0", "Set expiry for hash field using an absolute Unix timestamp (seconds)"),
+    doc("hget", "hash", "2.0.0", "Returns the value of a field in a hash."),
+    doc("hgetall", "hash", "2.0.0", "Returns all fields and values in a hash."),
+    doc(
+        "hgetdel",
+        "hash",
+        "8.0.0",
+        "Returns the value of a field and deletes it from the hash.",
+    ),
+    doc(
+        "hgetex",
+        "hash",
+        "8.0.0",
+        "Get the value of one or more fields of a given hash key, and optionally set their expiration.",
+    ),
+    doc("hmset", "hash", "2.0.0", "Sets the values of multiple fields."),
+    doc("hpexpire", "hash", "7.4.0", "Set expiry for hash field using relative time to expire (milliseconds)"),
+    doc("hpexpireat", "hash", "7.4.0", "Set expiry for hash field using an absolute Unix timestamp (milliseconds)"),
+    doc("hrandfield", "hash", "6.2.0", "Returns one or more random fields from a hash."),
+    doc("hscan", "hash", "2.8.0", "Iterates over fields and values of a hash."),
+    doc("hset", "hash", "2.0.0", "Creates or modifies the value of a field in a hash."),
+    doc("hsetnx", "hash", "2.0.0", "Sets the value of a field in a hash only when the field doesn't exist."),
+    doc("httl", "hash", "7.4.0", "Returns the TTL in seconds of a hash field."),
+    doc("incr", "string", "1.0.0", "Increments the integer value of a key by one. Uses 0 as initial value if the key doesn't exist."),
+    doc("incrby", "string", "1.0.0", "Increments the integer value of a key by a number. Uses 0 as initial value if the key doesn't exist."),
+    doc("incrbyfloat", "string", "2.6.0", "Increment the floating point value of a key by a number. Uses 0 as initial value if the key doesn't exist."),
+    doc("info", "server", "1.0.0", "Returns information and statistics about the server."),
+    doc("keys", "generic", "1.0.0", "Returns all key names that match a pattern."),
+    doc("linsert", "list", "2.2.0", "Inserts an element before or after another element in a list."),
+    doc("lmove", "list", "6.2.0", "Returns an element after popping it from one list and pushing it to another. Deletes the list if the last element was moved."),
+    doc("lmpop", "list", "7.0.0", "Returns multiple elements from a list after removing them. Deletes the list if the last element was popped."),
+    doc("lpop", "list", "1.0.0", "Returns the first elements in a list after removing it. Deletes the list if the last element was popped."),
+    doc("lpos", "list", "6.0.6", "Returns the index of matching elements in a list."),
+    doc("lpush", "list", "1.0.0", "Prepends one or more elements to a list. Creates the key if it doesn't exist."),
+    doc("lrange", "list", "1.0.0", "Returns a range of elements from a list."),
+    doc("lrem", "list", "1.0.0", "Removes elements from a list. Deletes the list if the last element was removed."),
+    doc("ltrim", "list", "1.0.0", "Removes elements from both ends a list. Deletes the list if all elements were trimmed."),
+    doc("memory", "server", "4.0.0", "A container for memory diagnostics commands."),
+    doc("mget", "string", "1.0.0", "Atomically returns the string values of one or more keys."),
+    doc("monitor", "server", "1.0.0", "Listens for all requests received by the server in real-time."),
+    doc("move", "generic", "1.0.0", "Moves a key to another database."),
+    doc("mset", "string", "1.0.1", "Atomically creates or modifies the string values of one or more keys."),
+    doc("multi", "transactions", "1.2.0", "Starts a transaction."),
+    doc("object", "generic", "2.2.3", "A container for object introspection commands."),
+    doc("pexpire", "generic", "2.6.0", "Sets the expiration time of a key in milliseconds."),
+    doc("pexpireat", "generic", "2.6.0", "Sets the expiration time of a key to a Unix milliseconds timestamp."),
+    doc("pexpiretime", "generic", "7.0.0", "Returns the expiration time of a key as a Unix milliseconds timestamp."),
+    doc("pfadd", "hyperloglog", "2.8.9", "Adds elements to a HyperLogLog key. Creates the key if it doesn't exist."),
+    doc("pfcount", "hyperloglog", "2.8.9", "Returns the approximated cardinality of the set(s) observed by the HyperLogLog key(s)."),
+    doc("pfmerge", "hyperloglog", "2.8.9", "Merges one or more HyperLogLog values into a single key."),
+    doc("ping", "connection", "1.0.0", "Returns the server's liveliness response."),
+    doc("psetex", "string", "2.6.0", "Sets both string value and expiration time in milliseconds of a key. The key is created if it doesn't exist."),
+    doc("psubscribe", "pubsub", "2.0.0", "Listens for messages published to channels that match one or more patterns."),
+    doc("psync", "server", "2.8.0", "An internal command used in replication."),
+    doc("publish", "pubsub", "2.0.0", "Posts a message to a channel."),
+    doc("pubsub", "pubsub", "2.8.0", "A container for Pub/Sub commands."),
+    doc("punsubscribe", "pubsub", "2.0.0", "Stops listening to messages published to channels that match one or more patterns."),
+    doc("quit", "connection", "1.0.0", "Closes the connection."),
+    doc("replconf", "server", "3.0.0", "An internal command for configuring the replication stream."),
+    doc("replicaof", "server", "5.0.0", "Configures a server as replica of another, or promotes it to a master."),
+    doc("reset", "connection", "6.2.0", "Resets the connection."),
+    doc("restore", "generic", "2.6.0", "Creates a key from the serialized representation of a value."),
+    doc("rpop", "list", "1.0.0", "Returns and removes the last elements of a list. Deletes the list if the last element was popped."),
+    doc("rpoplpush", "list", "1.2.0", "Returns the last element of a list after removing and pushing it to another list. Deletes the list if the last element was popped."),
+    doc("rpush", "list", "1.0.0", "Appends one or more elements to a list. Creates the key if it doesn't exist."),
+    doc("sadd", "set", "1.0.0", "Adds one or more members to a set. Creates the key if it doesn't exist."),
+    doc("save", "server", "1.0.0", "Synchronously saves the database(s) to disk."),
+    doc("scan", "generic", "2.8.0", "Iterates over the key names in the database."),
+    doc("select", "connection", "1.0.0", "Changes the selected database."),
+    doc("set", "string", "1.0.0", "Sets the string value of a key, ignoring its type. The key is created if it doesn't exist."),
+    doc("setbit", "bitmap", "2.2.0", "Sets or clears the bit at offset of the string value. Creates the key if it doesn't exist."),
+    doc("setex", "string", "2.0.0", "Sets the string value and expiration time of a key. Creates the key if it doesn't exist."),
+    doc("setrange", "string", "2.2.0", "Overwrites a part of a string value with another by an offset. Creates the key if it doesn't exist."),
+    doc("sintercard", "set", "7.0.0", "Returns the number of members of the intersect of multiple sets."),
+    doc("slaveof", "server", "1.0.0", "Sets a Redis server as a replica of another, or promotes it to being a master."),
+    doc("slowlog", "server", "2.2.12", "A container for slow log commands."),
+    doc("spop", "set", "1.0.0", "Returns one or more random members from a set after removing them. Deletes the set if the last member was popped."),
+    doc("srandmember", "set", "1.0.0", "Get one or multiple random members from a set"),
+    doc("srem", "set", "1.0.0", "Removes one or more members from a set. Deletes the set if the last member was removed."),
+    doc("sscan", "set", "2.8.0", "Iterates over members of a set."),
+    doc("strlen", "string", "2.2.0", "Returns the length of a string value."),
+    doc("subscribe", "pubsub", "2.0.0", "Listens for messages published to channels."),
+    doc("substr", "string", "1.0.0", "Returns a substring from a string value."),
+    doc("swapdb", "server", "4.0.0", "Swaps two Redis databases."),
+    doc("time", "server", "2.6.0", "Returns the server time."),
+    doc("type", "generic", "1.0.0", "Determines the type of value stored at a key."),
+    doc("unlink", "generic", "4.0.0", "Asynchronously deletes one or more keys."),
+    doc("unsubscribe", "pubsub", "2.0.0", "Stops listening to messages posted to channels."),
+    doc("waitaof", "generic", "7.2.0", "Blocks until all of the preceding write commands sent by the connection are written to the append-only file of the master and/or replicas."),
+    doc("xadd", "stream", "5.0.0", "Appends a new message to a stream. Creates the key if it doesn't exist."),
+    doc("xinfo", "stream", "5.0.0", "A container for stream introspection commands."),
+    doc("xlen", "stream", "5.0.0", "Return the number of messages in a stream."),
+    doc("xrange", "stream", "5.0.0", "Returns the messages from a stream within a range of IDs."),
+    doc("zadd", "sorted-set", "1.2.0", "Adds one or more members to a sorted set, or updates their scores. Creates the key if it doesn't exist."),
+    doc("zincrby", "sorted-set", "1.2.0", "Increments the score of a member in a sorted set."),
+    doc("zintercard", "sorted-set", "7.0.0", "Returns the number of members of the intersect of multiple sorted sets."),
+    doc("zmpop", "sorted-set", "7.0.0", "Returns the highest- or lowest-scoring members from one or more sorted sets after removing them. Deletes the sorted set if the last member was popped."),
+    doc("zmscore", "sorted-set", "6.2.0", "Returns the score of one or more members in a sorted set."),
+    doc("zrandmember", "sorted-set", "6.2.0", "Returns one or more random members from a sorted set."),
+    doc("zrangebyscore", "sorted-set", "1.0.5", "Returns members in a sorted set within a range of scores."),
+    doc("zscan", "sorted-set", "2.8.0", "Iterates over members and scores of a sorted set."),
+    doc("zscore", "sorted-set", "1.2.0", "Returns the score of a member in a sorted set."),
+];
+
+/// Whether a command may modify the dataset, and so would be propagated to replicas
+pub fn is_write(name: &str) -> bool {
+    lookup(name).is_some_and(|spec| spec.has_flag("write"))
+}
+
+/// Look up a command by name, case-insensitively
+pub fn lookup(name: &str) -> Option<&'static CommandSpec> {
+    COMMANDS.iter().find(|c| c.name.eq_ignore_ascii_case(name))
+}
+
+/// Look up the documentation of a command by name, case-insensitively
+pub fn lookup_doc(name: &str) -> Option<&'static CommandDoc> {
+    DOCS.iter().find(|d| d.name.eq_ignore_ascii_case(name))
+}
+
+impl CommandSpec {
+    pub fn has_flag(&self, flag: &str) -> bool {
+        self.flags.split_whitespace().any(|f| f == flag)
+    }
+
+    /// Whether `argc` arguments (including the command name) satisfy the arity
+    pub fn accepts(&self, argc: usize) -> bool {
+        if self.arity >= 0 {
+            argc == self.arity as usize
+        } else {
+            argc >= self.arity.unsigned_abs() as usize
+        }
+    }
+
+    /// Extract the key names from a full command line (name included)
+    pub fn keys<'a>(&self, argv: &'a [Vec<u8>]) -> Result<Vec<&'a [u8]>, String> {
+        if !self.accepts(argv.len()) {
+            return Err("ERR Invalid number of arguments specified for command".to_string());
+        }
+        if let Some(at) = self.numkeys_at {
+            let numkeys = std::str::from_utf8(&argv[at])
+                .ok()
+                .and_then(|n| n.parse::<usize>().ok())
+                .filter(|&n| n > 0 && at + n < argv.len())
+                .ok_or_else(|| "ERR Invalid arguments specified for command".to_string())?;
+            return Ok(argv[at + 1..=at + numkeys]
+                .iter()
+                .map(Vec::as_slice)
+                .collect());
+        }
+        if self.first_key == 0 {
+            return Err("ERR The command has no key arguments".to_string());
+        }
+        let argc = argv.len() as i32;
+        let last = if self.last_key < 0 {
+            argc + self.last_key
+        } else {
+            self.last_key.min(argc - 1)
+        };
+        Ok((self.first_key..=last)
+            .step_by(self.step as usize)
+            .map(|i| argv[i as usize].as_slice())
+            .collect())
+    }
+}
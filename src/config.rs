@@ -1,19 +1,166 @@
+use crate::log::Level;
+use crate::notify;
 use once_cell::sync::Lazy;
 use std::env;
+use std::fs;
 use std::path::PathBuf;
 use std::sync::RwLock;
 
+/// Eviction policies accepted by `maxmemory-policy`.
+const MAXMEMORY_POLICIES: &[&str] = &[
+    "volatile-lru",
+    "allkeys-lru",
+    "volatile-lfu",
+    "allkeys-lfu",
+    "volatile-random",
+    "allkeys-random",
+    "volatile-ttl",
+    "noeviction",
+];
+
+/// Classes of clients that each get their own `client-output-buffer-limit`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClientClass {
+    Normal,
+    Replica,
+    Pubsub,
+}
+
+impl ClientClass {
+    const ALL: [ClientClass; 3] = [
+        ClientClass::Normal,
+        ClientClass::Replica,
+        ClientClass::Pubsub,
+    ];
+
+    pub fn from_u8(value: u8) -> ClientClass {
+        ClientClass::ALL
+            .get(value as usize)
+            .copied()
+            .unwrap_or(ClientClass::Normal)
+    }
+
+    /// Name used by `client-output-buffer-limit`, which also accepts "slave" for replicas
+    fn name(self) -> &'static str {
+        match self {
+            ClientClass::Normal => "normal",
+            ClientClass::Replica => "slave",
+            ClientClass::Pubsub => "pubsub",
+        }
+    }
+
+    fn parse(name: &str) -> Option<ClientClass> {
+        match name.to_lowercase().as_str() {
+            "normal" => Some(ClientClass::Normal),
+            "replica" | "slave" => Some(ClientClass::Replica),
+            "pubsub" => Some(ClientClass::Pubsub),
+            _ => None,
+        }
+    }
+}
+
+/// Output buffer limit of one class of clients, in bytes; 0 disables a limit
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OutputBufferLimit {
+    pub hard: u64,
+    pub soft: u64,
+    pub soft_seconds: u64, // how long the soft limit may be exceeded
+}
+
 #[derive(Debug, Clone)]
 pub struct Config {
+    pub port: u16,
     pub dir: PathBuf,
     pub dbfilename: String,
+    pub maxmemory: u64,
+    pub maxmemory_policy: String,
+    pub appendonly: bool,
+    pub appendfilename: String, // in dir, like dbfilename
+    pub requirepass: Option<String>,
+    pub save: Vec<(u64, u64)>, // (seconds, changes)
+    pub proto_max_bulk_len: u64,
+    pub proto_max_inline_len: usize, // longest line buffered while waiting for its newline
+    pub databases: usize,
+    pub timeout: u64,       // idle client timeout in seconds, 0 = disabled
+    pub tcp_keepalive: u64, // seconds, 0 = disabled
+    pub maxclients: usize,
+    pub rdb_load_lenient: bool, // keep the readable keys of a truncated RDB file
+    pub proto_max_nesting: usize, // deepest aggregate nesting accepted from clients
+    pub loglevel: Level,
+    pub set_max_intset_entries: usize,
+    pub set_max_listpack_entries: usize,
+    pub set_max_listpack_value: usize,
+    pub hash_max_listpack_entries: usize,
+    pub hash_max_listpack_value: usize,
+    pub zset_max_listpack_entries: usize,
+    pub zset_max_listpack_value: usize,
+    pub list_max_listpack_size: i64, // entries if positive, -1..-5 is a 4kb..64kb byte limit
+    pub slowlog_log_slower_than: i64, // microseconds, negative disables the slow log
+    pub slowlog_max_len: usize,
+    pub notify_keyspace_events: u32, // flags from the notify module
+    pub client_output_buffer_limit: [OutputBufferLimit; 3], // indexed by ClientClass
+    pub emulate_single_thread: bool, // DEBUG SLEEP stalls every other command, as in Redis
+    pub tls_port: u16,               // 0 = no TLS listener
+    pub tls_cert_file: String,       // PEM certificate chain the TLS listener presents
+    pub tls_key_file: String,        // PEM private key of the certificate
 }
 
 impl Default for Config {
     fn default() -> Self {
         let dir = env::current_dir().unwrap_or_else(|_| PathBuf::from("."));
         let dbfilename = "dump.rdb".to_string();
-        Config { dir, dbfilename }
+        Config {
+            port: 6379,
+            dir,
+            dbfilename,
+            maxmemory: 0,
+            maxmemory_policy: "noeviction".to_string(),
+            appendonly: false,
+            appendfilename: "appendonly.aof".to_string(),
+            requirepass: None,
+            save: vec![(3600, 1), (300, 100), (60, 10000)],
+            proto_max_bulk_len: 512 * 1024 * 1024,
+            proto_max_inline_len: 64 * 1024,
+            databases: 16,
+            timeout: 0,
+            tcp_keepalive: 300,
+            maxclients: 10000,
+            rdb_load_lenient: false,
+            proto_max_nesting: 128,
+            loglevel: Level::Notice,
+            set_max_intset_entries: 512,
+            set_max_listpack_entries: 128,
+            set_max_listpack_value: 64,
+            hash_max_listpack_entries: 128,
+            hash_max_listpack_value: 64,
+            zset_max_listpack_entries: 128,
+            zset_max_listpack_value: 64,
+            list_max_listpack_size: -2,
+            slowlog_log_slower_than: 10000,
+            slowlog_max_len: 128,
+            notify_keyspace_events: 0,
+            client_output_buffer_limit: [
+                OutputBufferLimit {
+                    hard: 0,
+                    soft: 0,
+                    soft_seconds: 0,
+                },
+                OutputBufferLimit {
+                    hard: 256 * 1024 * 1024,
+                    soft: 64 * 1024 * 1024,
+                    soft_seconds: 60,
+                },
+                OutputBufferLimit {
+                    hard: 32 * 1024 * 1024,
+                    soft: 8 * 1024 * 1024,
+                    soft_seconds: 60,
+                },
+            ],
+            emulate_single_thread: false,
+            tls_port: 0,
+            tls_cert_file: String::new(),
+            tls_key_file: String::new(),
+        }
     }
 }
 
@@ -41,25 +188,617 @@ pub fn set_dbfilename<S: Into<String>>(filename: S) {
     config.dbfilename = filename.into();
 }
 
+/// Path of the append-only file, which lives in dir like the RDB file
+pub fn get_aof_path() -> PathBuf {
+    let config = CONFIG.read().unwrap();
+    config.dir.join(&config.appendfilename)
+}
+
+pub fn get_timeout() -> u64 {
+    CONFIG.read().unwrap().timeout
+}
+
+pub fn get_proto_max_bulk_len() -> u64 {
+    CONFIG.read().unwrap().proto_max_bulk_len
+}
+
+pub fn get_proto_max_inline_len() -> usize {
+    CONFIG.read().unwrap().proto_max_inline_len
+}
+
+pub fn get_proto_max_nesting() -> usize {
+    CONFIG.read().unwrap().proto_max_nesting
+}
+
+pub fn get_notify_keyspace_events() -> u32 {
+    CONFIG.read().unwrap().notify_keyspace_events
+}
+
+pub fn get_output_buffer_limit(class: ClientClass) -> OutputBufferLimit {
+    CONFIG.read().unwrap().client_output_buffer_limit[class as usize]
+}
+
+pub fn get_appendonly() -> bool {
+    CONFIG.read().unwrap().appendonly
+}
+
+pub fn get_emulate_single_thread() -> bool {
+    CONFIG.read().unwrap().emulate_single_thread
+}
+
+pub fn get_loglevel() -> Level {
+    CONFIG.read().unwrap().loglevel
+}
+
+pub fn get_requirepass() -> Option<String> {
+    CONFIG.read().unwrap().requirepass.clone()
+}
+
+/// Every parameter get_param knows, aliases included, in the order CONFIG GET lists
+/// the ones a pattern matches
+pub const PARAMS: &[&str] = &[
+    "dir",
+    "dbfilename",
+    "port",
+    "maxmemory",
+    "maxmemory-policy",
+    "appendonly",
+    "appendfilename",
+    "requirepass",
+    "save",
+    "proto-max-bulk-len",
+    "proto-max-inline-len",
+    "proto-max-nesting",
+    "databases",
+    "timeout",
+    "tcp-keepalive",
+    "maxclients",
+    "loglevel",
+    "set-max-intset-entries",
+    "set-max-listpack-entries",
+    "set-max-listpack-value",
+    "hash-max-listpack-entries",
+    "hash-max-ziplist-entries",
+    "hash-max-listpack-value",
+    "hash-max-ziplist-value",
+    "zset-max-listpack-entries",
+    "zset-max-ziplist-entries",
+    "zset-max-listpack-value",
+    "zset-max-ziplist-value",
+    "list-max-listpack-size",
+    "list-max-ziplist-size",
+    "slowlog-log-slower-than",
+    "slowlog-max-len",
+    "notify-keyspace-events",
+    "client-output-buffer-limit",
+    "emulate-single-thread",
+    "tls-port",
+    "tls-cert-file",
+    "tls-key-file",
+];
+
+/// The value of a configuration parameter by name as CONFIG GET reports it, in canonical
+/// form: sizes in bytes, booleans as yes/no and dir as an absolute path.
+/// Returns None for unknown parameters.
+pub fn get_param(name: &str) -> Option<String> {
+    let config = get_config();
+    let yes_no = |enabled: bool| if enabled { "yes" } else { "no" }.to_string();
+    Some(match name {
+        "dir" => std::path::absolute(&config.dir)
+            .unwrap_or(config.dir)
+            .to_string_lossy()
+            .to_string(),
+        "dbfilename" => config.dbfilename,
+        "port" => config.port.to_string(),
+        "maxmemory" => config.maxmemory.to_string(),
+        "maxmemory-policy" => config.maxmemory_policy,
+        "appendonly" => yes_no(config.appendonly),
+        "appendfilename" => config.appendfilename,
+        "requirepass" => config.requirepass.unwrap_or_default(),
+        "save" => config
+            .save
+            .iter()
+            .map(|(seconds, changes)| format!("{} {}", seconds, changes))
+            .collect::<Vec<_>>()
+            .join(" "),
+        "proto-max-bulk-len" => config.proto_max_bulk_len.to_string(),
+        "proto-max-inline-len" => config.proto_max_inline_len.to_string(),
+        "proto-max-nesting" => config.proto_max_nesting.to_string(),
+        "databases" => config.databases.to_string(),
+        "timeout" => config.timeout.to_string(),
+        "tcp-keepalive" => config.tcp_keepalive.to_string(),
+        "maxclients" => config.maxclients.to_string(),
+        "loglevel" => config.loglevel.as_str().to_string(),
+        "set-max-intset-entries" => config.set_max_intset_entries.to_string(),
+        "set-max-listpack-entries" => config.set_max_listpack_entries.to_string(),
+        "set-max-listpack-value" => config.set_max_listpack_value.to_string(),
+        "hash-max-listpack-entries" | "hash-max-ziplist-entries" => {
+            config.hash_max_listpack_entries.to_string()
+        }
+        "hash-max-listpack-value" | "hash-max-ziplist-value" => {
+            config.hash_max_listpack_value.to_string()
+        }
+        "zset-max-listpack-entries" | "zset-max-ziplist-entries" => {
+            config.zset_max_listpack_entries.to_string()
+        }
+        "zset-max-listpack-value" | "zset-max-ziplist-value" => {
+            config.zset_max_listpack_value.to_string()
+        }
+        "list-max-listpack-size" | "list-max-ziplist-size" => {
+            config.list_max_listpack_size.to_string()
+        }
+        "slowlog-log-slower-than" => config.slowlog_log_slower_than.to_string(),
+        "slowlog-max-len" => config.slowlog_max_len.to_string(),
+        "notify-keyspace-events" => notify::flags_to_string(config.notify_keyspace_events),
+        "client-output-buffer-limit" => {
+            format_output_buffer_limits(&config.client_output_buffer_limit)
+        }
+        "emulate-single-thread" => yes_no(config.emulate_single_thread),
+        "tls-port" => config.tls_port.to_string(),
+        "tls-cert-file" => config.tls_cert_file,
+        "tls-key-file" => config.tls_key_file,
+        _ => return None,
+    })
+}
+
+/// Set a configuration parameter by name, validating the value first.
+/// Nothing is changed if the value is rejected.
+pub fn set_param(name: &str, value: &str) -> Result<(), String> {
+    let failed = |reason: &str| {
+        format!(
+            "CONFIG SET failed (possibly related to argument '{}') - {}",
+            name, reason
+        )
+    };
+
+    match name {
+        "dir" => {
+            // Like the chdir Redis does, the directory has to exist. It's stored
+            // canonicalized, so it doesn't depend on the working directory later.
+            let dir = fs::canonicalize(value).map_err(|e| {
+                let reason = e.to_string();
+                failed(reason.split(" (os error").next().unwrap_or(&reason))
+            })?;
+            if !dir.is_dir() {
+                return Err(failed("Not a directory"));
+            }
+            set_dir(dir);
+        }
+        "dbfilename" => set_dbfilename(value),
+        "maxmemory" => {
+            let bytes = parse_memory_size(value)
+                .ok_or_else(|| failed("argument must be a memory value"))?;
+            CONFIG.write().unwrap().maxmemory = bytes;
+        }
+        "maxmemory-policy" => {
+            let policy = value.to_lowercase();
+            if !MAXMEMORY_POLICIES.contains(&policy.as_str()) {
+                return Err(failed(&format!(
+                    "argument(s) must be one of the following: {}",
+                    MAXMEMORY_POLICIES.join(", ")
+                )));
+            }
+            CONFIG.write().unwrap().maxmemory_policy = policy;
+        }
+        "appendonly" => {
+            let enabled =
+                parse_bool(value).ok_or_else(|| failed("argument must be 'yes' or 'no'"))?;
+            CONFIG.write().unwrap().appendonly = enabled;
+        }
+        "appendfilename" => {
+            if value.is_empty() || value.contains('/') {
+                return Err(failed("appendfilename can't be a path, just a filename"));
+            }
+            CONFIG.write().unwrap().appendfilename = value.to_string();
+        }
+        "requirepass" => {
+            let pass = if value.is_empty() {
+                None
+            } else {
+                Some(value.to_string())
+            };
+            CONFIG.write().unwrap().requirepass = pass;
+        }
+        "save" => {
+            let rules = parse_save_rules(value).ok_or_else(|| failed("Invalid save parameters"))?;
+            CONFIG.write().unwrap().save = rules;
+        }
+        "proto-max-bulk-len" => {
+            let bytes = parse_memory_size(value)
+                .filter(|&b| b > 0)
+                .ok_or_else(|| failed("argument must be a memory value"))?;
+            CONFIG.write().unwrap().proto_max_bulk_len = bytes;
+        }
+        "proto-max-inline-len" => {
+            let bytes = parse_memory_size(value)
+                .and_then(|b| usize::try_from(b).ok())
+                .filter(|&b| b > 0)
+                .ok_or_else(|| failed("argument must be a memory value"))?;
+            CONFIG.write().unwrap().proto_max_inline_len = bytes;
+        }
+        "proto-max-nesting" => {
+            let depth = value
+                .parse::<usize>()
+                .ok()
+                .filter(|&n| n > 0)
+                .ok_or_else(|| failed("argument must be a positive integer"))?;
+            CONFIG.write().unwrap().proto_max_nesting = depth;
+        }
+        "loglevel" => {
+            let level = Level::parse(value).ok_or_else(|| {
+                failed("argument(s) must be one of the following: debug, verbose, notice, warning")
+            })?;
+            CONFIG.write().unwrap().loglevel = level;
+        }
+        "timeout" => {
+            let secs = value
+                .parse::<u64>()
+                .map_err(|_| failed("argument couldn't be parsed into an integer"))?;
+            CONFIG.write().unwrap().timeout = secs;
+        }
+        "tcp-keepalive" => {
+            let secs = value
+                .parse::<u64>()
+                .map_err(|_| failed("argument couldn't be parsed into an integer"))?;
+            CONFIG.write().unwrap().tcp_keepalive = secs;
+        }
+        "maxclients" => {
+            let count = value
+                .parse::<usize>()
+                .ok()
+                .filter(|&n| n > 0)
+                .ok_or_else(|| failed("argument must be a positive integer"))?;
+            CONFIG.write().unwrap().maxclients = count;
+        }
+        "set-max-intset-entries" | "set-max-listpack-entries" | "set-max-listpack-value" => {
+            let limit = value
+                .parse::<usize>()
+                .map_err(|_| failed("argument couldn't be parsed into an integer"))?;
+            let mut config = CONFIG.write().unwrap();
+            match name {
+                "set-max-intset-entries" => config.set_max_intset_entries = limit,
+                "set-max-listpack-entries" => config.set_max_listpack_entries = limit,
+                _ => config.set_max_listpack_value = limit,
+            }
+        }
+        "hash-max-listpack-entries"
+        | "hash-max-ziplist-entries"
+        | "hash-max-listpack-value"
+        | "hash-max-ziplist-value" => {
+            let limit = value
+                .parse::<usize>()
+                .map_err(|_| failed("argument couldn't be parsed into an integer"))?;
+            let mut config = CONFIG.write().unwrap();
+            if name.ends_with("-entries") {
+                config.hash_max_listpack_entries = limit;
+            } else {
+                config.hash_max_listpack_value = limit;
+            }
+        }
+        "zset-max-listpack-entries"
+        | "zset-max-ziplist-entries"
+        | "zset-max-listpack-value"
+        | "zset-max-ziplist-value" => {
+            let limit = value
+                .parse::<usize>()
+                .map_err(|_| failed("argument couldn't be parsed into an integer"))?;
+            let mut config = CONFIG.write().unwrap();
+            if name.ends_with("-entries") {
+                config.zset_max_listpack_entries = limit;
+            } else {
+                config.zset_max_listpack_value = limit;
+            }
+        }
+        "list-max-listpack-size" | "list-max-ziplist-size" => {
+            let size = value
+                .parse::<i64>()
+                .map_err(|_| failed("argument couldn't be parsed into an integer"))?;
+            CONFIG.write().unwrap().list_max_listpack_size = size;
+        }
+        "slowlog-log-slower-than" => {
+            let micros = value
+                .parse::<i64>()
+                .map_err(|_| failed("argument couldn't be parsed into an integer"))?;
+            CONFIG.write().unwrap().slowlog_log_slower_than = micros;
+        }
+        "slowlog-max-len" => {
+            let len = value
+                .parse::<usize>()
+                .map_err(|_| failed("argument couldn't be parsed into an integer"))?;
+            CONFIG.write().unwrap().slowlog_max_len = len;
+        }
+        "notify-keyspace-events" => {
+            let flags = notify::parse_flags(value)
+                .ok_or_else(|| failed("Invalid event class character. Use 'Ag$shlzxeKEtmn'."))?;
+            CONFIG.write().unwrap().notify_keyspace_events = flags;
+        }
+        "emulate-single-thread" => {
+            let enabled =
+                parse_bool(value).ok_or_else(|| failed("argument must be 'yes' or 'no'"))?;
+            CONFIG.write().unwrap().emulate_single_thread = enabled;
+        }
+        "client-output-buffer-limit" => {
+            let mut limits = CONFIG.read().unwrap().client_output_buffer_limit;
+            parse_output_buffer_limits(value, &mut limits)
+                .ok_or_else(|| failed("Invalid client-output-buffer-limit parameters"))?;
+            CONFIG.write().unwrap().client_output_buffer_limit = limits;
+        }
+        // The databases are allocated and the TLS listener is set up once, at startup
+        "databases" | "tls-port" | "tls-cert-file" | "tls-key-file" => {
+            return Err(failed("can't set immutable config"))
+        }
+        _ => {
+            return Err(format!(
+                "Unknown option or number of arguments for CONFIG SET - '{}'",
+                name
+            ))
+        }
+    }
+    Ok(())
+}
+
+/// Parse a memory size such as "1024", "100kb", "512mb" or "1gb" into bytes.
+/// Like Redis, "k"/"m"/"g" are powers of 1000 and "kb"/"mb"/"gb" powers of 1024.
+pub fn parse_memory_size(value: &str) -> Option<u64> {
+    let lower = value.trim().to_lowercase();
+    let split = lower
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(lower.len());
+    let (digits, unit) = lower.split_at(split);
+    if digits.is_empty() {
+        return None;
+    }
+    let multiplier: u64 = match unit {
+        "" | "b" => 1,
+        "k" => 1000,
+        "kb" => 1024,
+        "m" => 1000 * 1000,
+        "mb" => 1024 * 1024,
+        "g" => 1000 * 1000 * 1000,
+        "gb" => 1024 * 1024 * 1024,
+        _ => return None,
+    };
+    digits.parse::<u64>().ok()?.checked_mul(multiplier)
+}
+
+/// Parse a Redis-style "yes"/"no" boolean.
+fn parse_bool(value: &str) -> Option<bool> {
+    match value.to_lowercase().as_str() {
+        "yes" => Some(true),
+        "no" => Some(false),
+        _ => None,
+    }
+}
+
+/// Parse save rules of the form "900 1 300 10" into (seconds, changes) pairs.
+/// An empty string disables snapshotting.
+fn parse_save_rules(value: &str) -> Option<Vec<(u64, u64)>> {
+    let parts: Vec<&str> = value.split_whitespace().collect();
+    if !parts.len().is_multiple_of(2) {
+        return None;
+    }
+    parts
+        .chunks(2)
+        .map(|pair| Some((pair[0].parse().ok()?, pair[1].parse().ok()?)))
+        .collect()
+}
+
+/// Parse `client-output-buffer-limit` groups of "<class> <hard> <soft> <soft seconds>"
+/// into limits. Classes left out keep their current limit.
+fn parse_output_buffer_limits(value: &str, limits: &mut [OutputBufferLimit; 3]) -> Option<()> {
+    let parts: Vec<&str> = value.split_whitespace().collect();
+    if parts.is_empty() || !parts.len().is_multiple_of(4) {
+        return None;
+    }
+    for group in parts.chunks(4) {
+        let class = ClientClass::parse(group[0])?;
+        limits[class as usize] = OutputBufferLimit {
+            hard: parse_memory_size(group[1])?,
+            soft: parse_memory_size(group[2])?,
+            soft_seconds: group[3].parse().ok()?,
+        };
+    }
+    Some(())
+}
+
+/// Render the limits the way CONFIG GET client-output-buffer-limit reports them
+pub fn format_output_buffer_limits(limits: &[OutputBufferLimit; 3]) -> String {
+    ClientClass::ALL
+        .iter()
+        .map(|&class| {
+            let limit = limits[class as usize];
+            format!(
+                "{} {} {} {}",
+                class.name(),
+                limit.hard,
+                limit.soft,
+                limit.soft_seconds
+            )
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Read a configuration file of "<directive> <arguments>" lines, as Redis does.
+/// Each `save` line adds a rule and `save ""` drops the rules before it; other
+/// directives are applied like CONFIG SET. Bad lines are logged and skipped.
+fn load_config_file(path: &str) {
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(e) => {
+            error!("Can't read the config file {}: {}", path, e);
+            return;
+        }
+    };
+    let mut save = None;
+    for (number, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (name, value) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+        let name = name.to_lowercase();
+        let value = value.trim();
+        let value = value
+            .strip_prefix('"')
+            .and_then(|v| v.strip_suffix('"'))
+            .unwrap_or(value);
+        let result = if name == "save" {
+            parse_save_rules(value)
+                .map(|rules| {
+                    let save = save.get_or_insert_with(Vec::new);
+                    if rules.is_empty() {
+                        save.clear();
+                    }
+                    save.extend(rules);
+                })
+                .ok_or_else(|| "Invalid save parameters".to_string())
+        } else {
+            set_param(&name, value)
+        };
+        if let Err(e) = result {
+            error!("Bad directive at line {} of {}: {}", number + 1, path, e);
+        }
+    }
+    // Save lines replace the default rules rather than adding to them
+    if let Some(rules) = save {
+        CONFIG.write().unwrap().save = rules;
+    }
+}
+
+/// Configure the server from its arguments: an optional configuration file first,
+/// then options that override it
 pub fn parse_args_and_set_config() {
     let args: Vec<String> = env::args().collect();
+    if let Some(path) = args.get(1).filter(|arg| !arg.starts_with("--")) {
+        load_config_file(path);
+    }
     for i in 1..args.len() {
         match args[i].as_str() {
             "--dir" => {
                 if i + 1 < args.len() {
                     set_dir(&args[i + 1]);
                 } else {
-                    eprintln!("Error: --dir requires a path argument");
+                    error!("--dir requires a path argument");
                 }
             }
             "--dbfilename" => {
                 if i + 1 < args.len() {
                     set_dbfilename(&args[i + 1]);
                 } else {
-                    eprintln!("Error: --dbfilename requires a filename argument");
+                    error!("--dbfilename requires a filename argument");
                 }
             }
+            "--port" => match args.get(i + 1).and_then(|v| v.parse::<u16>().ok()) {
+                Some(port) => CONFIG.write().unwrap().port = port,
+                None => error!("--port requires a port number"),
+            },
+            "--save" => match args.get(i + 1).and_then(|v| parse_save_rules(v)) {
+                Some(rules) => CONFIG.write().unwrap().save = rules,
+                None => error!("--save requires rules like \"3600 1 300 100\", or \"\" to disable"),
+            },
+            "--appendonly" => match args.get(i + 1).and_then(|v| parse_bool(v)) {
+                Some(enabled) => CONFIG.write().unwrap().appendonly = enabled,
+                None => error!("--appendonly requires yes or no"),
+            },
+            "--appendfilename" => match args.get(i + 1) {
+                Some(filename) => CONFIG.write().unwrap().appendfilename = filename.clone(),
+                None => error!("--appendfilename requires a filename argument"),
+            },
+            "--databases" => match args.get(i + 1).and_then(|v| v.parse::<usize>().ok()) {
+                Some(count) if count > 0 => CONFIG.write().unwrap().databases = count,
+                _ => error!("--databases requires a positive number"),
+            },
+            "--rdb-load-lenient" => CONFIG.write().unwrap().rdb_load_lenient = true,
+            "--loglevel" => match args.get(i + 1).and_then(|v| Level::parse(v)) {
+                Some(level) => CONFIG.write().unwrap().loglevel = level,
+                None => error!("--loglevel requires one of debug, verbose, notice or warning"),
+            },
+            "--tls-port" => match args.get(i + 1).and_then(|v| v.parse::<u16>().ok()) {
+                Some(port) => CONFIG.write().unwrap().tls_port = port,
+                None => error!("--tls-port requires a port number"),
+            },
+            "--tls-cert-file" => match args.get(i + 1) {
+                Some(file) => CONFIG.write().unwrap().tls_cert_file = file.clone(),
+                None => error!("--tls-cert-file requires a path argument"),
+            },
+            "--tls-key-file" => match args.get(i + 1) {
+                Some(file) => CONFIG.write().unwrap().tls_key_file = file.clone(),
+                None => error!("--tls-key-file requires a path argument"),
+            },
             _ => {}
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_listed_parameter_has_a_value() {
+        for name in PARAMS {
+            assert!(get_param(name).is_some(), "{}", name);
+        }
+        assert_eq!(get_param("no-such-parameter"), None);
+        assert_eq!(
+            set_param("no-such-parameter", "1"),
+            Err(
+                "Unknown option or number of arguments for CONFIG SET - 'no-such-parameter'"
+                    .to_string()
+            )
+        );
+    }
+
+    #[test]
+    fn memory_sizes_are_parsed_into_bytes() {
+        assert_eq!(parse_memory_size("1024"), Some(1024));
+        assert_eq!(parse_memory_size("1gb"), Some(1024 * 1024 * 1024));
+        assert_eq!(parse_memory_size("512mb"), Some(512 * 1024 * 1024));
+        assert_eq!(parse_memory_size("100kb"), Some(100 * 1024));
+        assert_eq!(parse_memory_size("100k"), Some(100_000));
+        assert_eq!(parse_memory_size("2m"), Some(2_000_000));
+        assert_eq!(parse_memory_size("1G"), Some(1_000_000_000));
+        assert_eq!(parse_memory_size("10b"), Some(10));
+    }
+
+    #[test]
+    fn malformed_memory_sizes_are_rejected() {
+        for value in [
+            "",
+            "mb",
+            "-1",
+            "1.5mb",
+            "12tb",
+            "1 gb x",
+            "99999999999999999999",
+        ] {
+            assert_eq!(parse_memory_size(value), None, "{:?}", value);
+        }
+        // Sizes that overflow once multiplied out
+        assert_eq!(parse_memory_size("18446744073709551615kb"), None);
+    }
+
+    #[test]
+    fn rejected_values_leave_the_setting_alone() {
+        let policy = get_param("maxmemory-policy");
+        let error = set_param("maxmemory-policy", "most-recent").unwrap_err();
+        assert!(error
+            .starts_with("CONFIG SET failed (possibly related to argument 'maxmemory-policy')"));
+        assert_eq!(get_param("maxmemory-policy"), policy);
+
+        let maxmemory = get_param("maxmemory");
+        assert!(set_param("maxmemory", "lots").is_err());
+        assert_eq!(get_param("maxmemory"), maxmemory);
+    }
+
+    #[test]
+    fn databases_cant_be_changed_at_runtime() {
+        let databases = get_param("databases");
+        assert_eq!(
+            set_param("databases", "4"),
+            Err("CONFIG SET failed (possibly related to argument 'databases') - can't set immutable config".to_string())
+        );
+        assert_eq!(get_param("databases"), databases);
+    }
+}
@@ -1,23 +1,133 @@
-use crate::model::redis_value::RedisValue;
-use crate::rdb::RdbDatabase;
+use crate::config;
+use crate::hll::{self, Hll};
+use crate::model::redis_value::{HashValue, RedisValue, StreamEntry, StreamId, StreamValue};
+use crate::notify;
+use crate::rdb::{self, RdbDatabase};
 use crate::resp::types::Frame;
+use crate::stats;
 use once_cell::sync::Lazy;
-use std::collections::HashMap;
-use tokio::sync::RwLock;
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, HashSet};
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::sync::{Notify, RwLock};
 use tokio::time::{Duration, Instant};
 
-pub static KV: Lazy<RwLock<HashMap<String, RedisValue>>> =
-    Lazy::new(|| RwLock::new(HashMap::new()));
-pub static EXP: Lazy<RwLock<HashMap<String, Instant>>> = Lazy::new(|| RwLock::new(HashMap::new()));
+/// A map per database, sized by the `databases` setting when first used
+type Databases<T> = Lazy<Vec<RwLock<HashMap<String, T>>>>;
+
+pub static KV: Databases<RedisValue> = Lazy::new(new_databases);
+pub static EXP: Databases<Instant> = Lazy::new(new_databases);
+pub static ACCESS: Databases<AccessMeta> = Lazy::new(new_databases);
+/// A list or sorted set key within a specific database
+type DbKey = (usize, String);
+/// Clients blocked on list keys (BLPOP, BRPOP, BLMPOP) or sorted set keys (BZMPOP),
+/// woken when a push or ZADD lands on one of them
+static LIST_WAITERS: Lazy<Mutex<HashMap<DbKey, Vec<Arc<Notify>>>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// Whether the background task purges expired keys. DEBUG SET-ACTIVE-EXPIRE 0 turns
+/// it off, leaving keys to expire lazily when a command accesses them.
+static ACTIVE_EXPIRE: AtomicBool = AtomicBool::new(true);
+
+tokio::task_local! {
+    /// Database selected by the connection whose command is running
+    static CURRENT_DB: usize;
+    /// Whether the connection whose command is running has CLIENT NO-TOUCH on
+    static NO_TOUCH: bool;
+}
+
+fn new_databases<T>() -> Vec<RwLock<HashMap<String, T>>> {
+    (0..config::get_config().databases)
+        .map(|_| RwLock::new(HashMap::new()))
+        .collect()
+}
+
+/// Number of databases available to SELECT
+pub fn database_count() -> usize {
+    KV.len()
+}
+
+/// Run a command with `index` as the database every db function operates on
+pub async fn with_db<F: Future>(index: usize, fut: F) -> F::Output {
+    CURRENT_DB.scope(index, fut).await
+}
+
+/// Run a command without refreshing the access time and LFU counter of the keys it uses
+pub async fn with_no_touch<F: Future>(no_touch: bool, fut: F) -> F::Output {
+    NO_TOUCH.scope(no_touch, fut).await
+}
+
+/// Database of the running command, or 0 outside of a command
+pub fn current_db() -> usize {
+    CURRENT_DB.try_with(|db| *db).unwrap_or(0)
+}
+
+fn kv() -> &'static RwLock<HashMap<String, RedisValue>> {
+    &KV[current_db()]
+}
+
+fn exp() -> &'static RwLock<HashMap<String, Instant>> {
+    &EXP[current_db()]
+}
+
+fn access() -> &'static RwLock<HashMap<String, AccessMeta>> {
+    &ACCESS[current_db()]
+}
+
+/// Initial LFU counter of a new key, so fresh keys aren't evicted straight away
+const LFU_INIT_VAL: u8 = 5;
+/// How hard it is to grow the logarithmic LFU counter (Redis' lfu-log-factor)
+const LFU_LOG_FACTOR: f64 = 10.0;
+/// Minutes of inactivity that decrement the LFU counter by one (Redis' lfu-decay-time)
+const LFU_DECAY_MINUTES: u64 = 1;
+
+/// Per-key access metadata, used by OBJECT IDLETIME/FREQ and LRU/LFU eviction
+#[derive(Debug, Clone, Copy)]
+pub struct AccessMeta {
+    pub last_access: Instant,
+    pub lfu_counter: u8,
+}
+
+impl AccessMeta {
+    fn new() -> Self {
+        AccessMeta {
+            last_access: Instant::now(),
+            lfu_counter: LFU_INIT_VAL,
+        }
+    }
+
+    /// LFU counter after applying the decay for the time the key has been idle
+    pub fn decayed_counter(&self) -> u8 {
+        let idle_minutes = self.last_access.elapsed().as_secs() / 60;
+        let decay = (idle_minutes / LFU_DECAY_MINUTES).min(u8::MAX as u64) as u8;
+        self.lfu_counter.saturating_sub(decay)
+    }
+}
 
 /// Load data from RDB file into the in-memory database
 pub async fn load_from_rdb(rdb_db: RdbDatabase) -> Result<(), String> {
-    let mut kv = KV.write().await;
-    let mut exp = EXP.write().await;
+    // Take every database's locks, in index order and KV before EXP before ACCESS
+    let mut kvs = Vec::with_capacity(KV.len());
+    for db in KV.iter() {
+        kvs.push(db.write().await);
+    }
+    let mut exps = Vec::with_capacity(EXP.len());
+    for db in EXP.iter() {
+        exps.push(db.write().await);
+    }
+    let mut accesses = Vec::with_capacity(ACCESS.len());
+    for db in ACCESS.iter() {
+        accesses.push(db.write().await);
+    }
 
     // Clear existing data
-    kv.clear();
-    exp.clear();
+    for ((kv, exp), access) in kvs.iter_mut().zip(exps.iter_mut()).zip(accesses.iter_mut()) {
+        kv.clear();
+        exp.clear();
+        access.clear();
+    }
 
     let now = Instant::now();
     // Get current Unix timestamp in milliseconds
@@ -27,7 +137,14 @@ pub async fn load_from_rdb(rdb_db: RdbDatabase) -> Result<(), String> {
         .as_millis() as u64;
 
     // Load all data from RDB
+    let mut loaded = 0;
     for (key, redis_entry) in rdb_db.data {
+        let db = redis_entry.db;
+        if db >= kvs.len() {
+            warn!("Skipping key in database {} which is out of range", db);
+            continue;
+        }
+
         // Check if the key has expired
         if let Some(expiry_timestamp) = redis_entry.expiry {
             if expiry_timestamp <= current_timestamp {
@@ -38,133 +155,2863 @@ pub async fn load_from_rdb(rdb_db: RdbDatabase) -> Result<(), String> {
             // Calculate when the key should expire relative to tokio::time::Instant::now()
             let remaining_ms = expiry_timestamp - current_timestamp;
             let expiry_instant = now + Duration::from_millis(remaining_ms);
-            exp.insert(key.clone(), expiry_instant);
+            exps[db].insert(key.clone(), expiry_instant);
+        }
+
+        // A hash whose fields all expired while saved is gone
+        if matches!(&redis_entry.value, RedisValue::Hash(hash) if hash.fields.is_empty()) {
+            continue;
         }
 
         // Insert the value
-        kv.insert(key, redis_entry.value);
+        accesses[db].insert(key.clone(), AccessMeta::new());
+        if kvs[db].insert(key, redis_entry.value).is_none() {
+            loaded += 1;
+        }
     }
 
-    println!("Loaded {} keys from RDB file", kv.len());
+    info!("Loaded {} keys from RDB file", loaded);
     Ok(())
 }
 
-/// Set a key with optional expiration and NX/XX options
+/// How an expire time given to a command is expressed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExpireUnit {
+    Seconds,     // EX, EXPIRE, SETEX
+    Millis,      // PX, PEXPIRE, PSETEX
+    UnixSeconds, // EXAT, EXPIREAT
+    UnixMillis,  // PXAT, PEXPIREAT
+}
+
+/// Check an expire time for SET, SETEX or GETEX, which unlike EXPIRE refuse
+/// times that aren't positive. Returns the absolute time, see expire_deadline.
+pub fn validate_expire(cmd: &str, value: i64, unit: ExpireUnit) -> Result<i64, String> {
+    if value <= 0 {
+        return Err(invalid_expire(cmd));
+    }
+    expire_deadline(cmd, value, unit)
+}
+
+/// Absolute Unix time in milliseconds of an expire time given to cmd.
+/// Like Redis, a time that overflows once converted is an error rather than wrapping.
+pub fn expire_deadline(cmd: &str, value: i64, unit: ExpireUnit) -> Result<i64, String> {
+    let ms = match unit {
+        ExpireUnit::Seconds | ExpireUnit::UnixSeconds => value.checked_mul(1000),
+        ExpireUnit::Millis | ExpireUnit::UnixMillis => Some(value),
+    };
+    let at = match unit {
+        ExpireUnit::Seconds | ExpireUnit::Millis => ms.and_then(|ms| ms.checked_add(unix_now_ms())),
+        ExpireUnit::UnixSeconds | ExpireUnit::UnixMillis => ms,
+    };
+    at.ok_or_else(|| invalid_expire(cmd))
+}
+
+fn invalid_expire(cmd: &str) -> String {
+    format!("ERR invalid expire time in '{}' command", cmd)
+}
+
+fn unix_now_ms() -> i64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as i64
+}
+
+/// The instant at which EXP should expire a key due at Unix time at_ms, or None if
+/// that time has passed. Times too far off for Instant are clamped to a century from now.
+fn instant_at(at_ms: i64) -> Option<Instant> {
+    let remaining = at_ms.checked_sub(unix_now_ms()).filter(|&ms| ms > 0)?;
+    let remaining =
+        Duration::from_millis(remaining as u64).min(Duration::from_secs(100 * 365 * 86400));
+    Some(Instant::now() + remaining)
+}
+
+/// Set a key with an optional expire time (absolute, in Unix milliseconds) and NX/XX options.
+/// Without an expire time the key loses any TTL it had, unless keep_ttl is set.
+/// Returns false if NX or XX kept the key from being set.
 pub async fn set(
     key: Vec<u8>,
     value: Vec<u8>,
-    ex: Option<u64>,
-    px: Option<u64>,
+    expire_at_ms: Option<i64>,
+    keep_ttl: bool,
     nx: bool,
     xx: bool,
-) -> Result<(), String> {
+) -> Result<bool, String> {
+    // Everything that doesn't need the keyspace is worked out before taking its locks
     let key_str = String::from_utf8_lossy(&key).into_owned();
-    let mut kv = KV.write().await;
-    let mut exp = EXP.write().await;
+    let value = RedisValue::String(value);
+    let expire_at = expire_at_ms.map(|at| instant_at(at).unwrap_or_else(Instant::now));
 
+    let mut kv = kv().write().await;
+    let mut exp = exp().write().await;
+    // A key past its TTL doesn't exist for NX and XX, nor is its TTL kept
+    expire_if_needed(&mut kv, &mut exp, &key_str).await;
     let exists = kv.contains_key(&key_str);
     if nx && exists {
         // NX: only set if key does not exist
-        return Ok(());
+        return Ok(false);
     }
     if xx && !exists {
         // XX: only set if key exists
-        return Ok(());
+        return Ok(false);
+    }
+
+    if !keep_ttl {
+        match expire_at {
+            Some(at) => exp.insert(key_str.clone(), at),
+            None => exp.remove(&key_str),
+        };
+    }
+    kv.insert(key_str.clone(), value);
+    touch(&key_str).await;
+    let db = current_db();
+    if !exists {
+        notify::notify(notify::NEW, "new", &key_str, db);
+    }
+    notify::notify(notify::STRING, "set", &key_str, db);
+    if expire_at.is_some() && !keep_ttl {
+        notify::notify(notify::GENERIC, "expire", &key_str, db);
     }
 
-    kv.insert(key_str.clone(), RedisValue::String(value));
+    Ok(true)
+}
 
-    // Handle expiration
-    if let Some(ms) = px {
-        exp.insert(key_str.clone(), Instant::now() + Duration::from_millis(ms));
-    } else if let Some(sec) = ex {
-        exp.insert(key_str.clone(), Instant::now() + Duration::from_secs(sec));
-    } else {
-        exp.remove(&key_str);
+/// Set several string keys at once, clearing any TTL they had
+pub async fn mset(pairs: Vec<(String, Vec<u8>)>) {
+    let mut kv = kv().write().await;
+    let mut exp = exp().write().await;
+    for (key, value) in pairs {
+        exp.remove(&key);
+        if kv.insert(key.clone(), RedisValue::String(value)).is_none() {
+            notify::notify(notify::NEW, "new", &key, current_db());
+        }
+        touch(&key).await;
+        notify::notify(notify::STRING, "set", &key, current_db());
     }
+}
 
-    Ok(())
+/// What GETEX does to the TTL of the key it reads, or HGETEX to the TTL of fields
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GetexExpiry {
+    Keep,
+    Persist,
+    AtUnixMs(i64), // from EX/PX/EXAT/PXAT, see expire_deadline
+}
+
+/// Delete keys, returning how many existed. Expired keys don't count.
+pub async fn del(keys: &[String]) -> i64 {
+    let mut kv = kv().write().await;
+    let mut exp = exp().write().await;
+    let mut deleted = 0;
+    for key in keys {
+        expire_if_needed(&mut kv, &mut exp, key).await;
+        if remove_key(current_db(), &mut kv, &mut exp, key)
+            .await
+            .is_some()
+        {
+            notify::notify(notify::GENERIC, "del", key, current_db());
+            deleted += 1;
+        }
+    }
+    deleted
+}
+
+/// Read the string at key and delete the key, under a single write lock
+pub async fn getdel(key: &str) -> Result<Option<Vec<u8>>, String> {
+    let mut kv = kv().write().await;
+    let mut exp = exp().write().await;
+    expire_if_needed(&mut kv, &mut exp, key).await;
+
+    let Some(value) = kv.get(key) else {
+        return Ok(None);
+    };
+    let bytes = value.string_bytes().ok_or_else(|| WRONGTYPE.to_string())?;
+    remove_key(current_db(), &mut kv, &mut exp, key).await;
+    Ok(Some(bytes))
+}
+
+/// Replace the string at key and return the previous one, clearing any TTL
+pub async fn getset(key: &str, value: Vec<u8>) -> Result<Option<Vec<u8>>, String> {
+    let mut kv = kv().write().await;
+    let mut exp = exp().write().await;
+    expire_if_needed(&mut kv, &mut exp, key).await;
+
+    let previous = match kv.get(key) {
+        None => None,
+        Some(old) => Some(old.string_bytes().ok_or_else(|| WRONGTYPE.to_string())?),
+    };
+    kv.insert(key.to_string(), RedisValue::String(value));
+    exp.remove(key);
+    touch(key).await;
+    Ok(previous)
+}
+
+/// Read the string at key and update its TTL, under a single write lock.
+/// An absolute expiry in the past deletes the key after reading it.
+pub async fn getex(key: &str, expiry: GetexExpiry) -> Result<Option<Vec<u8>>, String> {
+    let mut kv = kv().write().await;
+    let mut exp = exp().write().await;
+    expire_if_needed(&mut kv, &mut exp, key).await;
+
+    let Some(value) = kv.get(key) else {
+        return Ok(None);
+    };
+    let bytes = value.string_bytes().ok_or_else(|| WRONGTYPE.to_string())?;
+    match expiry {
+        GetexExpiry::Keep => {}
+        GetexExpiry::Persist => {
+            exp.remove(key);
+        }
+        GetexExpiry::AtUnixMs(at) => {
+            let Some(at) = instant_at(at) else {
+                remove_key(current_db(), &mut kv, &mut exp, key).await;
+                return Ok(Some(bytes));
+            };
+            exp.insert(key.to_string(), at);
+        }
+    }
+    touch(key).await;
+    Ok(Some(bytes))
+}
+
+/// Set the expire time of key to Unix time at_ms if condition allows it, as EXPIRE does.
+/// A time in the past deletes the key. Returns whether the key exists and was updated.
+pub async fn expire(key: &str, at_ms: i64, condition: ExpireCondition) -> bool {
+    let mut kv = kv().write().await;
+    let mut exp = exp().write().await;
+    expire_if_needed(&mut kv, &mut exp, key).await;
+
+    if !kv.contains_key(key) {
+        return false;
+    }
+    let at = instant_at(at_ms);
+    let current = exp.get(key).copied();
+    if !condition.allows(current, at) {
+        return false;
+    }
+    match at {
+        Some(at) => {
+            exp.insert(key.to_string(), at);
+            touch(key).await;
+            notify::notify(notify::GENERIC, "expire", key, current_db());
+        }
+        None => {
+            remove_key(current_db(), &mut kv, &mut exp, key).await;
+            notify::notify(notify::GENERIC, "del", key, current_db());
+        }
+    }
+    true
+}
+
+/// Add delta to the integer stored at key (0 if missing), keeping its TTL.
+/// Read and write happen under a single write lock, so concurrent increments never get lost.
+pub async fn incr_by(key: &str, delta: i64) -> Result<i64, String> {
+    let mut kv = kv().write().await;
+    let mut exp = exp().write().await;
+    expire_if_needed(&mut kv, &mut exp, key).await;
+
+    let current = match kv.get(key) {
+        None => 0,
+        Some(value) => {
+            let bytes = value.string_bytes().ok_or_else(|| WRONGTYPE.to_string())?;
+            std::str::from_utf8(&bytes)
+                .ok()
+                .and_then(|s| s.parse::<i64>().ok())
+                .ok_or_else(|| "ERR value is not an integer or out of range".to_string())?
+        }
+    };
+    let updated = current
+        .checked_add(delta)
+        .ok_or_else(|| "ERR increment or decrement would overflow".to_string())?;
+    kv.insert(
+        key.to_string(),
+        RedisValue::String(updated.to_string().into_bytes()),
+    );
+    touch(key).await;
+    Ok(updated)
+}
+
+/// Add a float increment to the number stored at key (0 if it doesn't exist),
+/// all under one lock, and return the new value as it was stored
+pub async fn incr_by_float(key: &str, delta: f64) -> Result<Vec<u8>, String> {
+    let mut kv = kv().write().await;
+    let mut exp = exp().write().await;
+    expire_if_needed(&mut kv, &mut exp, key).await;
+
+    let current = match kv.get(key) {
+        None => 0.0,
+        Some(value) => {
+            let bytes = value.string_bytes().ok_or_else(|| WRONGTYPE.to_string())?;
+            parse_score(&bytes)?
+        }
+    };
+    let updated = current + delta;
+    if !updated.is_finite() {
+        return Err("ERR increment would produce NaN or Infinity".to_string());
+    }
+    let updated = format_float(updated);
+    kv.insert(key.to_string(), RedisValue::String(updated.clone()));
+    touch(key).await;
+    Ok(updated)
+}
+
+/// A float as INCRBYFLOAT stores it: in fixed point like Redis' %.17Lf, with the
+/// shortest digits that read back as the same number, at most 17 decimals of them
+/// and no trailing zeros
+fn format_float(value: f64) -> Vec<u8> {
+    let mut formatted = value.to_string();
+    if formatted
+        .split_once('.')
+        .is_some_and(|(_, decimals)| decimals.len() > 17)
+    {
+        formatted = format!("{:.17}", value);
+        formatted.truncate(formatted.trim_end_matches('0').trim_end_matches('.').len());
+    }
+    if formatted == "-0" {
+        formatted.remove(0);
+    }
+    formatted.into_bytes()
 }
 
 /// Get a key, checking for expiration
 pub async fn get(key: Vec<u8>) -> Vec<u8> {
     let k = String::from_utf8_lossy(&key);
-    if let Some(expiry) = EXP.read().await.get(&*k) {
-        if Instant::now() > *expiry {
-            return Frame::BulkString(None).encode();
-        }
-    }
-    match KV.read().await.get(&*k) {
-        Some(val) => match val {
-            RedisValue::String(s) => Frame::BulkString(Some(s.clone())).encode(),
-            RedisValue::Integer(i) => Frame::Integer(*i).encode(),
-            // Add more conversions as needed
-            RedisValue::Float(f) => Frame::BulkString(Some(f.to_string().into_bytes())).encode(),
-            RedisValue::Boolean(b) => Frame::BulkString(Some(b.to_string().into_bytes())).encode(),
-            RedisValue::Null => Frame::Null.encode(),
-            RedisValue::List(l) => Frame::Array(Some(
-                l.iter()
-                    .map(|v| Frame::BulkString(Some(v.clone())))
-                    .collect(),
-            ))
-            .encode(),
-            RedisValue::Set(s) => Frame::Array(Some(
-                s.iter()
-                    .map(|v| Frame::BulkString(Some(v.clone())))
-                    .collect(),
-            ))
-            .encode(),
-            RedisValue::SortedSet(ss) => Frame::Array(Some(
-                ss.iter()
-                    .map(|(member, score)| {
-                        Frame::Array(Some(vec![
-                            Frame::BulkString(Some(member.clone())),
-                            Frame::BulkString(Some(score.to_string().into_bytes())),
-                        ]))
-                    })
-                    .collect(),
-            ))
-            .encode(),
-            RedisValue::Hash(h) => Frame::Array(Some(
-                h.iter()
-                    .map(|(k, v)| {
-                        Frame::Array(Some(vec![
-                            Frame::BulkString(Some(k.clone())),
-                            Frame::BulkString(Some(v.clone())),
-                        ]))
-                    })
-                    .collect(),
-            ))
-            .encode(),
-            RedisValue::Zipmap(z) => Frame::BulkString(Some(z.clone())).encode(),
-            RedisValue::Ziplist(z) => Frame::BulkString(Some(z.clone())).encode(),
-            RedisValue::Intset(i) => Frame::BulkString(Some(i.clone())).encode(),
-            RedisValue::Quicklist(q) => Frame::BulkString(Some(q.clone())).encode(),
-        },
-        None => Frame::BulkString(None).encode(),
-    }
-}
-
-/// Get all keys matching a  glob-style pattern
-pub async fn get_keys_matching_pattern(pattern: &str) -> Vec<String> {
-    let kv = KV.read().await;
-    kv.keys()
-        .filter(|k| glob::Pattern::new(pattern).map_or(false, |p| p.matches(k)))
-        .cloned()
-        .collect()
+    match read_value(&k, encode_string).await {
+        Some(reply) => {
+            stats::incr(&stats::KEYSPACE_HITS);
+            reply
+        }
+        None => {
+            stats::incr(&stats::KEYSPACE_MISSES);
+            Frame::BulkString(None).encode()
+        }
+    }
 }
 
-/// Purge expired keys from KV and EXP
-pub async fn purge_expired_keys() {
-    let now = Instant::now();
-    let mut exp = EXP.write().await;
-    let mut kv = KV.write().await;
-    let expired_keys: Vec<String> = exp
-        .iter()
-        .filter_map(|(k, &v)| if now > v { Some(k.clone()) } else { None })
-        .collect();
-    for k in expired_keys {
-        exp.remove(&k);
-        kv.remove(&k);
+/// The bytes of the string at key, or None if the key is missing, expired or holds
+/// another type, as MGET reads keys without ever replying WRONGTYPE
+pub async fn get_string_or_none(key: &str) -> Option<Vec<u8>> {
+    match read_value(key, RedisValue::string_bytes).await {
+        Some(bytes) => {
+            stats::incr(&stats::KEYSPACE_HITS);
+            bytes
+        }
+        None => {
+            stats::incr(&stats::KEYSPACE_MISSES);
+            None
+        }
+    }
+}
+
+/// Encode a value for a string command such as GET; any other type is a WRONGTYPE error
+pub fn encode_string(value: &RedisValue) -> Vec<u8> {
+    match value {
+        RedisValue::String(s) | RedisValue::RawString(s) => {
+            Frame::BulkString(Some(s.clone())).encode()
+        }
+        RedisValue::Integer(i) => Frame::BulkString(Some(i.to_string().into_bytes())).encode(),
+        RedisValue::Float(f) => Frame::BulkString(Some(f.to_string().into_bytes())).encode(),
+        RedisValue::Boolean(b) => Frame::BulkString(Some(b.to_string().into_bytes())).encode(),
+        RedisValue::Null => Frame::BulkString(None).encode(),
+        _ => Frame::Error(WRONGTYPE.to_string()).encode(),
+    }
+}
+
+/// Encode a hash for HGETALL: a map under RESP3, a flat field/value array under RESP2
+pub fn encode_hash(hash: HashMap<Vec<u8>, Vec<u8>>, resp3: bool) -> Vec<u8> {
+    let pairs = hash.into_iter().map(|(field, value)| {
+        (
+            Frame::BulkString(Some(field)),
+            Frame::BulkString(Some(value)),
+        )
+    });
+    if resp3 {
+        Frame::Map(Some(pairs.collect())).encode()
+    } else {
+        Frame::Array(Some(pairs.flat_map(|(f, v)| [f, v]).collect())).encode()
+    }
+}
+
+/// Check whether a key has a TTL that has already passed
+fn is_expired(exp: &HashMap<String, Instant>, key: &str) -> bool {
+    exp.get(key).is_some_and(|expiry| Instant::now() > *expiry)
+}
+
+/// Get a copy of the value stored at key, deleting it if it has expired
+pub async fn get_value(key: &str) -> Option<RedisValue> {
+    let value = peek_value(key).await;
+    if value.is_some() {
+        touch(key).await;
+    } else {
+        remove_if_expired(key).await;
+        notify::notify(notify::KEY_MISS, "keymiss", key, current_db());
+    }
+    value
+}
+
+/// Run f on the value stored at key under the read lock, counting as an access as
+/// get_value does, for reads such as GET that need only part of the value rather than
+/// a copy of all of it
+async fn read_value<T>(key: &str, f: impl FnOnce(&RedisValue) -> T) -> Option<T> {
+    let result = if is_expired(&*exp().read().await, key) {
+        None
+    } else {
+        let kv = kv().read().await;
+        // A hash whose fields have all passed their TTL is as good as missing
+        kv.get(key)
+            .filter(|value| !matches!(value, RedisValue::Hash(hash) if hash.all_expired()))
+            .map(f)
+    };
+    if result.is_some() {
+        touch(key).await;
+    } else {
+        remove_if_expired(key).await;
+        notify::notify(notify::KEY_MISS, "keymiss", key, current_db());
+    }
+    result
+}
+
+/// Delete key if its TTL has passed, as reads do rather than wait for the purge task
+async fn remove_if_expired(key: &str) {
+    if !is_expired(&*exp().read().await, key) {
+        return;
+    }
+    let mut kv = kv().write().await;
+    let mut exp = exp().write().await;
+    expire_if_needed(&mut kv, &mut exp, key).await;
+}
+
+/// Number of keys in the current database, including expired ones not yet purged
+pub async fn dbsize() -> usize {
+    kv().read().await.len()
+}
+
+/// Number of keys across every database, including expired ones not yet purged
+pub async fn total_keys() -> usize {
+    let mut total = 0;
+    for db in KV.iter() {
+        total += db.read().await.len();
+    }
+    total
+}
+
+/// Bytes Redis spends on every key beyond its name and value: the dictionary entry
+/// and the value's object header
+const KEY_OVERHEAD: usize = 24 + 16;
+
+/// Bytes of bookkeeping a container spends per element, on top of the element itself
+const ELEMENT_OVERHEAD: usize = 16;
+
+/// Estimate the bytes the key and its value take, as MEMORY USAGE reports, or None if
+/// the key doesn't exist. Containers are sized from their first `samples` elements
+/// (all of them for 0), extrapolated to the whole.
+pub async fn memory_usage(key: &str, samples: usize) -> Option<usize> {
+    let value = peek_value(key).await?;
+    // A string's bytes carry a small header and a terminator, like an sds
+    let string = |bytes: &[u8]| bytes.len() + 4;
+    let value_size = match &value {
+        RedisValue::String(s) | RedisValue::RawString(s) => string(s),
+        RedisValue::Integer(_) => 0, // kept in the object header itself
+        RedisValue::Float(_) | RedisValue::Boolean(_) => {
+            string(&value.string_bytes().unwrap_or_default())
+        }
+        RedisValue::Null => 0,
+        RedisValue::List(items) | RedisValue::Set(items) => {
+            sampled_size(items.iter(), samples, |item| {
+                string(item) + ELEMENT_OVERHEAD
+            })
+        }
+        RedisValue::SortedSet(members) => sampled_size(members.iter(), samples, |(member, _)| {
+            string(member) + 8 + ELEMENT_OVERHEAD
+        }),
+        RedisValue::Hash(hash) => {
+            sampled_size(hash.fields.iter(), samples, |(field, value)| {
+                string(field) + string(value) + ELEMENT_OVERHEAD
+            }) + hash.expiries.len() * ELEMENT_OVERHEAD
+        }
+        RedisValue::Stream(stream) => {
+            sampled_size(stream.entries.iter(), samples, |(_, fields)| {
+                16 + fields
+                    .iter()
+                    .map(|(field, value)| string(field) + string(value))
+                    .sum::<usize>()
+            })
+        }
+        RedisValue::Zipmap(blob)
+        | RedisValue::Ziplist(blob)
+        | RedisValue::Intset(blob)
+        | RedisValue::Quicklist(blob) => blob.len(),
+    };
+    Some(KEY_OVERHEAD + string(key.as_bytes()) + value_size)
+}
+
+/// Total size of a container's elements, measuring only its first `samples` (all for 0)
+/// and scaling their average up to the whole container
+fn sampled_size<T>(
+    elements: impl ExactSizeIterator<Item = T>,
+    samples: usize,
+    size: impl Fn(T) -> usize,
+) -> usize {
+    let len = elements.len();
+    let sampled = if samples == 0 { len } else { samples.min(len) };
+    if sampled == 0 {
+        return 0;
+    }
+    let total: usize = elements.take(sampled).map(size).sum();
+    (total as u128 * len as u128 / sampled as u128) as usize
+}
+
+/// Name of the internal encoding Redis would use for a value, as reported by OBJECT ENCODING.
+/// Sets, hashes and sorted sets are classified from their content and the set-max-*,
+/// hash-max-* and zset-max-* thresholds, strings from their content and length, see
+/// string_encoding. The thresholds are read on every call, so setting one to 0 makes
+/// every collection of that type report its large encoding.
+pub fn encoding(value: &RedisValue) -> &'static str {
+    match value {
+        RedisValue::Set(members) => {
+            let config = config::get_config();
+            let is_integer = |m: &Vec<u8>| {
+                std::str::from_utf8(m)
+                    .ok()
+                    .and_then(|s| s.parse::<i64>().ok().filter(|i| i.to_string() == s))
+                    .is_some()
+            };
+            if members.len() <= config.set_max_intset_entries && members.iter().all(is_integer) {
+                "intset"
+            } else if members.len() <= config.set_max_listpack_entries
+                && members
+                    .iter()
+                    .all(|m| m.len() <= config.set_max_listpack_value)
+            {
+                "listpack"
+            } else {
+                "hashtable"
+            }
+        }
+        RedisValue::String(s) => string_encoding(s),
+        RedisValue::Float(_) | RedisValue::Boolean(_) => {
+            string_encoding(&value.string_bytes().unwrap_or_default())
+        }
+        RedisValue::RawString(_) => "raw",
+        RedisValue::Integer(_) => "int",
+        RedisValue::List(items) => {
+            // Redis keeps a list in a single listpack until it outgrows one quicklist node
+            let size = config::get_config().list_max_listpack_size;
+            let bytes =
+                LISTPACK_HEADER + items.iter().map(|item| listpack_entry(item)).sum::<usize>();
+            if within_listpack_size(size, items.len(), bytes) {
+                "listpack"
+            } else {
+                "quicklist"
+            }
+        }
+        RedisValue::Quicklist(_) => "quicklist",
+        RedisValue::Hash(hash) => {
+            let config = config::get_config();
+            if hash.fields.len() <= config.hash_max_listpack_entries
+                && hash.fields.iter().all(|(field, value)| {
+                    field.len() <= config.hash_max_listpack_value
+                        && value.len() <= config.hash_max_listpack_value
+                })
+            {
+                "listpack"
+            } else {
+                "hashtable"
+            }
+        }
+        RedisValue::SortedSet(members) => {
+            let config = config::get_config();
+            // Scores are stored in the listpack too, but only the members count towards the limit
+            if members.len() <= config.zset_max_listpack_entries
+                && members
+                    .iter()
+                    .all(|(member, _)| member.len() <= config.zset_max_listpack_value)
+            {
+                "listpack"
+            } else {
+                "skiplist"
+            }
+        }
+        RedisValue::Stream(_) => "stream",
+        RedisValue::Zipmap(_) => "zipmap",
+        RedisValue::Ziplist(_) => "ziplist",
+        RedisValue::Intset(_) => "intset",
+        RedisValue::Null => "none",
+    }
+}
+
+/// Bytes of a listpack's header and terminator
+const LISTPACK_HEADER: usize = 7;
+
+/// Approximate bytes an entry takes in a listpack: its length plus about two bytes of header
+fn listpack_entry(item: &[u8]) -> usize {
+    item.len() + 2
+}
+
+/// Whether a listpack of `entries` entries taking `bytes` bytes stays within
+/// list-max-listpack-size: a number of entries if zero or more, so 0 makes every list
+/// a quicklist, otherwise a byte limit where -1 is 4kb, -2 8kb, and so on up to -5, 64kb
+fn within_listpack_size(size: i64, entries: usize, bytes: usize) -> bool {
+    if size >= 0 {
+        entries <= size as usize
+    } else {
+        bytes <= 4096usize << (size.unsigned_abs().clamp(1, 5) - 1)
+    }
+}
+
+/// How Redis would split a list into quicklist nodes under list-max-listpack-size,
+/// filling each in turn: the number of entries and bytes of every node.
+/// Every node holds at least one entry, however large.
+pub fn quicklist_nodes(items: &[Vec<u8>]) -> Vec<(usize, usize)> {
+    let size = config::get_config().list_max_listpack_size;
+    let mut nodes = Vec::new();
+    let (mut entries, mut bytes) = (0, LISTPACK_HEADER);
+    for item in items {
+        let entry = listpack_entry(item);
+        if entries > 0 && !within_listpack_size(size, entries + 1, bytes + entry) {
+            nodes.push((entries, bytes));
+            (entries, bytes) = (0, LISTPACK_HEADER);
+        }
+        entries += 1;
+        bytes += entry;
+    }
+    if entries > 0 {
+        nodes.push((entries, bytes));
+    }
+    nodes
+}
+
+/// The quicklist a list would be, described as Redis' quicklistRepr prints it, with
+/// every node's entries too if full. Nodes are always packed and never compressed.
+pub fn quicklist_repr(items: &[Vec<u8>], full: bool) -> String {
+    let nodes = quicklist_nodes(items);
+    let mut repr = format!(
+        "{{count : {}}}\n{{len : {}}}\n{{fill : {}}}\n{{compress : 0}}\n{{bookmark_count : 0}}\n",
+        items.len(),
+        nodes.len(),
+        config::get_config().list_max_listpack_size
+    );
+    let mut entries = items.iter();
+    for (i, (count, bytes)) in nodes.into_iter().enumerate() {
+        repr.push_str(&format!(
+            "{{quicklist node({})\n{{container : PACKED, encoding: RAW, size: {}, count: {}, recompress: 0, attempted_compress: 0}}\n",
+            i, bytes, count
+        ));
+        if full {
+            repr.push_str("{ listpack:\n");
+            for (index, entry) in entries.by_ref().take(count).enumerate() {
+                repr.push_str(&format!(
+                    "\t[{}] {}\n",
+                    index,
+                    String::from_utf8_lossy(entry)
+                ));
+            }
+            repr.push_str("}\n");
+        }
+        repr.push_str("}\n");
+    }
+    repr
+}
+
+/// Longest string Redis stores embedded in its object header (OBJ_ENCODING_EMBSTR_SIZE_LIMIT)
+const OBJ_ENCODING_EMBSTR_SIZE_LIMIT: usize = 44;
+
+/// Encoding of a string set as a whole: "int" if it is the canonical form of an i64,
+/// "embstr" if it fits the embedded limit and "raw" otherwise
+fn string_encoding(bytes: &[u8]) -> &'static str {
+    let is_integer = bytes.len() <= 20
+        && std::str::from_utf8(bytes)
+            .ok()
+            .and_then(|s| s.parse::<i64>().ok().filter(|i| i.to_string() == s))
+            .is_some();
+    if is_integer {
+        "int"
+    } else if bytes.len() <= OBJ_ENCODING_EMBSTR_SIZE_LIMIT {
+        "embstr"
+    } else {
+        "raw"
+    }
+}
+
+/// Bytes the value takes in an RDB file after its type byte and key, as reported by
+/// DEBUG OBJECT's serializedlength. Values with no RDB form take none.
+pub fn serialized_length(value: &RedisValue) -> usize {
+    match rdb::encode_value(value) {
+        Ok(Some((_, payload))) => payload.len(),
+        _ => 0,
+    }
+}
+
+/// Absolute Unix time in milliseconds at which key expires,
+/// -1 if it has no TTL or -2 if it doesn't exist
+pub async fn expiretime_ms(key: &str) -> i64 {
+    let kv = kv().read().await;
+    let exp = exp().read().await;
+    if !kv.contains_key(key) || is_expired(&exp, key) {
+        return -2;
+    }
+    let Some(expiry) = exp.get(key) else {
+        return -1;
+    };
+    // EXP holds monotonic instants, so anchor the remaining time to the wall clock
+    let now_ms = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as i64;
+    now_ms + expiry.saturating_duration_since(Instant::now()).as_millis() as i64
+}
+
+/// Like get_value, but without counting as an access (for introspection commands)
+pub async fn peek_value(key: &str) -> Option<RedisValue> {
+    if is_expired(&*exp().read().await, key) {
+        return None;
+    }
+    let mut value = kv().read().await.get(key).cloned()?;
+    // Hash fields past their TTL linger until a writer or the expirer drops them
+    if let RedisValue::Hash(hash) = &mut value {
+        hash.remove_expired();
+        if hash.fields.is_empty() {
+            return None;
+        }
+    }
+    Some(value)
+}
+
+/// Record an access to key: refresh its last-access time and bump its LFU counter
+async fn touch(key: &str) {
+    let mut access = access().write().await;
+    let meta = match access.entry(key.to_string()) {
+        // A new key starts at LFU_INIT_VAL, as a new object does in Redis
+        Entry::Vacant(entry) => {
+            entry.insert(AccessMeta::new());
+            return;
+        }
+        Entry::Occupied(entry) => entry.into_mut(),
+    };
+    if NO_TOUCH.try_with(|no_touch| *no_touch).unwrap_or(false) {
+        return;
+    }
+    let mut counter = meta.decayed_counter();
+    if counter < u8::MAX {
+        // Logarithmic increment: the higher the counter, the less likely it grows
+        let base = counter.saturating_sub(LFU_INIT_VAL) as f64;
+        let probability = 1.0 / (base * LFU_LOG_FACTOR + 1.0);
+        if random_f64() < probability {
+            counter += 1;
+        }
+    }
+    meta.lfu_counter = counter;
+    meta.last_access = Instant::now();
+}
+
+/// Get the access metadata of an existing key without counting it as an access
+pub async fn access_meta(key: &str) -> Option<AccessMeta> {
+    if is_expired(&*exp().read().await, key) || !kv().read().await.contains_key(key) {
+        return None;
+    }
+    Some(
+        access()
+            .read()
+            .await
+            .get(key)
+            .copied()
+            .unwrap_or_else(AccessMeta::new),
+    )
+}
+
+/// Return a random 64-bit number.
+/// std's RandomState is seeded randomly per instance, which is plenty for picking keys.
+pub fn random_u64() -> u64 {
+    use std::collections::hash_map::RandomState;
+    use std::hash::{BuildHasher, Hasher};
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u128(
+        std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_nanos())
+            .unwrap_or(0),
+    );
+    hasher.finish()
+}
+
+/// Return a random number in [0, 1)
+pub fn random_f64() -> f64 {
+    (random_u64() >> 11) as f64 / (1u64 << 53) as f64
+}
+
+/// Store a value with an optional TTL in milliseconds, as done by RESTORE. A TTL of 0
+/// has already passed, so the value is dropped and only the key it replaces goes.
+/// Returns whether an existing key was replaced, or the BUSYKEY error without touching
+/// anything if the key exists and `replace` is not set.
+pub async fn restore(
+    key: String,
+    value: RedisValue,
+    ttl_ms: Option<u64>,
+    replace: bool,
+) -> Result<bool, String> {
+    let mut kv = kv().write().await;
+    let mut exp = exp().write().await;
+    expire_if_needed(&mut kv, &mut exp, &key).await;
+
+    if kv.contains_key(&key) && !replace {
+        return Err("BUSYKEY Target key name already exists.".to_string());
+    }
+    if ttl_ms == Some(0) {
+        let replaced = remove_key(current_db(), &mut kv, &mut exp, &key)
+            .await
+            .is_some();
+        if replaced {
+            notify::notify(notify::GENERIC, "del", &key, current_db());
+        }
+        return Ok(replaced);
+    }
+    let replaced = kv.insert(key.clone(), value).is_some();
+    access()
+        .write()
+        .await
+        .insert(key.clone(), AccessMeta::new());
+    match ttl_ms {
+        Some(ms) => exp.insert(key, Instant::now() + Duration::from_millis(ms)),
+        None => exp.remove(&key),
+    };
+    Ok(replaced)
+}
+
+/// Error returned when a command is used against a key of the wrong type
+pub const WRONGTYPE: &str = "WRONGTYPE Operation against a key holding the wrong kind of value";
+
+/// Error returned when a count is outside the range a command can serve
+pub const OUT_OF_RANGE: &str = "ERR value is out of range";
+
+/// Drop key if its TTL has passed, so writers see it as missing.
+/// Must be called with the KV and EXP write locks of the current database held.
+async fn expire_if_needed(
+    kv: &mut HashMap<String, RedisValue>,
+    exp: &mut HashMap<String, Instant>,
+    key: &str,
+) {
+    expire_in_db(current_db(), kv, exp, key).await;
+}
+
+/// expire_if_needed for a database other than the current one
+async fn expire_in_db(
+    db: usize,
+    kv: &mut HashMap<String, RedisValue>,
+    exp: &mut HashMap<String, Instant>,
+    key: &str,
+) {
+    if is_expired(exp, key) && remove_key(db, kv, exp, key).await.is_some() {
+        stats::incr(&stats::EXPIRED_KEYS);
+        notify::notify(notify::EXPIRED, "expired", key, db);
+    }
+}
+
+/// Delete a key along with everything kept beside its value: the TTL and the
+/// access metadata (hash field TTLs live in the value). Every deletion goes through
+/// here so none of them outlives the key. Must be called with the KV and EXP write
+/// locks of database `db` held, and without its ACCESS lock, which is taken here.
+async fn remove_key(
+    db: usize,
+    kv: &mut HashMap<String, RedisValue>,
+    exp: &mut HashMap<String, Instant>,
+    key: &str,
+) -> Option<RedisValue> {
+    let value = kv.remove(key)?;
+    exp.remove(key);
+    ACCESS[db].write().await.remove(key);
+    Some(value)
+}
+
+/// Resolve the inclusive start/stop indices of a range command (LRANGE, LTRIM,
+/// GETRANGE, BITCOUNT) over `len` elements. Negative indices count from the end,
+/// both ends are clamped to the elements, and None means the range is empty.
+pub fn normalize_range(start: i64, stop: i64, len: usize) -> Option<(usize, usize)> {
+    let len = i64::try_from(len).unwrap_or(i64::MAX);
+    let start = if start < 0 {
+        start.saturating_add(len).max(0)
+    } else {
+        start
+    };
+    let stop = if stop < 0 {
+        stop.saturating_add(len)
+    } else {
+        stop.min(len - 1)
+    };
+    if start > stop || start >= len {
+        return None;
+    }
+    Some((start as usize, stop as usize))
+}
+
+/// Random index in 0..len
+fn random_index(len: usize) -> usize {
+    (random_u64() % len as u64) as usize
+}
+
+/// Add members to the set at key, creating it if needed.
+/// Returns the number of members that were not already present.
+pub async fn sadd(key: &str, members: Vec<Vec<u8>>) -> Result<i64, String> {
+    let mut kv = kv().write().await;
+    let mut exp = exp().write().await;
+    expire_if_needed(&mut kv, &mut exp, key).await;
+
+    let created = !kv.contains_key(key);
+    let entry = kv
+        .entry(key.to_string())
+        .or_insert_with(|| RedisValue::Set(Vec::new()));
+    let RedisValue::Set(set) = entry else {
+        return Err(WRONGTYPE.to_string());
+    };
+    let mut added = 0;
+    for member in members {
+        if !set.contains(&member) {
+            set.push(member);
+            added += 1;
+        }
+    }
+    touch(key).await;
+    if created {
+        notify::notify(notify::NEW, "new", key, current_db());
+    }
+    if added > 0 {
+        notify::notify(notify::SET, "sadd", key, current_db());
+    }
+    Ok(added)
+}
+
+/// Remove members from the set at key, deleting the key once it is empty.
+/// Returns how many of them were in the set.
+pub async fn srem(key: &str, members: &[Vec<u8>]) -> Result<i64, String> {
+    let mut kv = kv().write().await;
+    let mut exp = exp().write().await;
+    expire_if_needed(&mut kv, &mut exp, key).await;
+
+    let Some(value) = kv.get_mut(key) else {
+        return Ok(0);
+    };
+    let RedisValue::Set(set) = value else {
+        return Err(WRONGTYPE.to_string());
+    };
+    let before = set.len();
+    set.retain(|member| !members.contains(member));
+    let removed = (before - set.len()) as i64;
+    if set.is_empty() {
+        remove_key(current_db(), &mut kv, &mut exp, key).await;
+    } else if removed > 0 {
+        touch(key).await;
+    }
+    Ok(removed)
+}
+
+/// Remove and return up to `count` random members of the set at key,
+/// deleting the key once it is empty.
+pub async fn spop(key: &str, count: usize) -> Result<Vec<Vec<u8>>, String> {
+    let mut kv = kv().write().await;
+    let mut exp = exp().write().await;
+    expire_if_needed(&mut kv, &mut exp, key).await;
+
+    let popped = match kv.get_mut(key) {
+        None => return Ok(Vec::new()),
+        Some(RedisValue::Set(set)) => {
+            let mut popped = Vec::with_capacity(count.min(set.len()));
+            while popped.len() < count && !set.is_empty() {
+                let idx = random_index(set.len());
+                popped.push(set.swap_remove(idx));
+            }
+            popped
+        }
+        Some(_) => return Err(WRONGTYPE.to_string()),
+    };
+    if matches!(kv.get(key), Some(RedisValue::Set(set)) if set.is_empty()) {
+        remove_key(current_db(), &mut kv, &mut exp, key).await;
+    } else {
+        touch(key).await;
+    }
+    Ok(popped)
+}
+
+/// Return random members of the set at key without removing them.
+/// A positive count yields distinct members (at most the set size),
+/// a negative count yields exactly |count| members that may repeat.
+pub async fn srandmember(key: &str, count: i64) -> Result<Vec<Vec<u8>>, String> {
+    match get_value(key).await {
+        None => Ok(Vec::new()),
+        Some(RedisValue::Set(set)) => random_sample(set, count),
+        Some(_) => Err(WRONGTYPE.to_string()),
+    }
+}
+
+/// Return random field/value pairs of the hash at key, with SRANDMEMBER's count semantics
+pub async fn hrandfield(key: &str, count: i64) -> Result<Vec<(Vec<u8>, Vec<u8>)>, String> {
+    let hash = hgetall(key).await?;
+    random_sample(hash.into_iter().collect(), count)
+}
+
+/// Return random member/score pairs of the sorted set at key, with SRANDMEMBER's count semantics
+pub async fn zrandmember(key: &str, count: i64) -> Result<Vec<(Vec<u8>, f64)>, String> {
+    match get_value(key).await {
+        None => Ok(Vec::new()),
+        Some(RedisValue::SortedSet(members)) => random_sample(members, count),
+        Some(_) => Err(WRONGTYPE.to_string()),
+    }
+}
+
+/// Pick random items: a positive count yields distinct items (at most all of them),
+/// a negative count yields exactly |count| items that may repeat.
+/// A negative count too large to reserve room for is an out of range error
+/// rather than an allocation failure that would abort the server.
+fn random_sample<T: Clone>(mut items: Vec<T>, count: i64) -> Result<Vec<T>, String> {
+    if items.is_empty() {
+        return Ok(Vec::new());
+    }
+    if count < 0 {
+        let n = usize::try_from(count.unsigned_abs()).map_err(|_| OUT_OF_RANGE.to_string())?;
+        let mut picked = Vec::new();
+        picked
+            .try_reserve_exact(n)
+            .map_err(|_| OUT_OF_RANGE.to_string())?;
+        picked.extend((0..n).map(|_| items[random_index(items.len())].clone()));
+        return Ok(picked);
+    }
+
+    // Partial Fisher-Yates shuffle picks `count` distinct items
+    let n = (count as usize).min(items.len());
+    for i in 0..n {
+        let j = i + random_index(items.len() - i);
+        items.swap(i, j);
+    }
+    items.truncate(n);
+    Ok(items)
+}
+
+/// Count the members shared by the sets (or, with `zset`, the sorted sets and sets)
+/// at keys, stopping early once `limit` is reached (0 means no limit).
+/// A missing key makes the intersection empty.
+pub async fn intercard(keys: &[String], limit: usize, zset: bool) -> Result<i64, String> {
+    let kv = kv().read().await;
+    let exp = exp().read().await;
+
+    let mut members: Vec<HashSet<&[u8]>> = Vec::with_capacity(keys.len());
+    let mut missing = false;
+    for key in keys {
+        let value = if is_expired(&exp, key) {
+            None
+        } else {
+            kv.get(key)
+        };
+        let set: HashSet<&[u8]> = match value {
+            None => {
+                missing = true;
+                continue;
+            }
+            Some(RedisValue::Set(set)) => set.iter().map(Vec::as_slice).collect(),
+            Some(RedisValue::SortedSet(zset_members)) if zset => {
+                zset_members.iter().map(|(m, _)| m.as_slice()).collect()
+            }
+            Some(_) => return Err(WRONGTYPE.to_string()),
+        };
+        members.push(set);
+    }
+    if missing {
+        return Ok(0);
+    }
+
+    // Walk the smallest set and probe the others
+    members.sort_by_key(HashSet::len);
+    let Some((smallest, others)) = members.split_first() else {
+        return Ok(0);
+    };
+    let mut count = 0;
+    for member in smallest {
+        if others.iter().all(|set| set.contains(member)) {
+            count += 1;
+            if count == limit {
+                break;
+            }
+        }
+    }
+    Ok(count as i64)
+}
+
+/// Flags accepted by ZADD
+#[derive(Debug, Default, Clone, Copy)]
+pub struct ZaddOptions {
+    pub nx: bool,   // only add new members
+    pub xx: bool,   // only update existing members
+    pub gt: bool,   // only update when the new score is greater
+    pub lt: bool,   // only update when the new score is less
+    pub incr: bool, // add the score to the current one, like ZINCRBY
+}
+
+/// Add members with their scores to the sorted set at key, creating it if needed.
+/// Returns the number of added members, the number of existing members whose score
+/// changed, and the resulting score of the last member, which is None if the options
+/// prevented the change.
+pub async fn zadd(
+    key: &str,
+    members: Vec<(f64, Vec<u8>)>,
+    opts: ZaddOptions,
+) -> Result<(i64, i64, Option<f64>), String> {
+    let mut kv = kv().write().await;
+    let mut exp = exp().write().await;
+    expire_if_needed(&mut kv, &mut exp, key).await;
+
+    let created = !kv.contains_key(key);
+    let entry = kv
+        .entry(key.to_string())
+        .or_insert_with(|| RedisValue::SortedSet(Vec::new()));
+    let RedisValue::SortedSet(zset) = entry else {
+        return Err(WRONGTYPE.to_string());
+    };
+    let (mut added, mut updated) = (0, 0);
+    let mut last_score = None;
+    for (score, member) in members {
+        last_score = None;
+        match zset.iter_mut().find(|(m, _)| *m == member) {
+            Some(existing) => {
+                if opts.nx {
+                    continue;
+                }
+                let new_score = if opts.incr { existing.1 + score } else { score };
+                if new_score.is_nan() {
+                    return Err("ERR resulting score is not a number (NaN)".to_string());
+                }
+                if (opts.gt && new_score <= existing.1) || (opts.lt && new_score >= existing.1) {
+                    continue;
+                }
+                if new_score != existing.1 {
+                    existing.1 = new_score;
+                    updated += 1;
+                }
+                last_score = Some(new_score);
+            }
+            None => {
+                if opts.xx {
+                    continue;
+                }
+                zset.push((member, score));
+                added += 1;
+                last_score = Some(score);
+            }
+        }
+    }
+    if zset.is_empty() {
+        // XX on a missing key must not leave an empty sorted set behind
+        remove_key(current_db(), &mut kv, &mut exp, key).await;
+        return Ok((0, 0, last_score));
+    }
+    // Keep members ordered by score, then lexicographically
+    zset.sort_by(|a, b| a.1.total_cmp(&b.1).then_with(|| a.0.cmp(&b.0)));
+    touch(key).await;
+    if created {
+        notify::notify(notify::NEW, "new", key, current_db());
+    }
+    if added + updated > 0 {
+        let event = if opts.incr { "zincr" } else { "zadd" };
+        notify::notify(notify::ZSET, event, key, current_db());
+    }
+    wake_list_waiters(key);
+    Ok((added, updated, last_score))
+}
+
+/// Pop up to count members with the lowest (min) or highest scores from the sorted set
+/// at key, in the order they were popped. The key is deleted once the set is empty.
+pub async fn zpop(key: &str, count: usize, min: bool) -> Result<Vec<(Vec<u8>, f64)>, String> {
+    let mut kv = kv().write().await;
+    let mut exp = exp().write().await;
+    expire_if_needed(&mut kv, &mut exp, key).await;
+
+    let zset = match kv.get_mut(key) {
+        None => return Ok(Vec::new()),
+        Some(RedisValue::SortedSet(zset)) => zset,
+        Some(_) => return Err(WRONGTYPE.to_string()),
+    };
+    let n = count.min(zset.len());
+    let popped = if min {
+        zset.drain(..n).collect()
+    } else {
+        zset.split_off(zset.len() - n).into_iter().rev().collect()
+    };
+    if zset.is_empty() {
+        remove_key(current_db(), &mut kv, &mut exp, key).await;
+    } else {
+        touch(key).await;
+    }
+    Ok(popped)
+}
+
+/// Pop up to count members from the first non-empty sorted set among keys,
+/// returning the key they came from.
+pub async fn zpop_first(
+    keys: &[String],
+    count: usize,
+    min: bool,
+) -> Result<Option<(String, Vec<(Vec<u8>, f64)>)>, String> {
+    for key in keys {
+        let popped = zpop(key, count, min).await?;
+        if !popped.is_empty() {
+            return Ok(Some((key.clone(), popped)));
+        }
+    }
+    Ok(None)
+}
+
+/// Parse a sorted set score or float increment the way Redis does: integers,
+/// decimals, exponent notation and inf/+inf/-inf are accepted, NaN is not
+pub fn parse_score(bytes: &[u8]) -> Result<f64, String> {
+    std::str::from_utf8(bytes)
+        .ok()
+        .and_then(|s| s.parse::<f64>().ok())
+        .filter(|score| !score.is_nan())
+        .ok_or_else(|| "ERR value is not a valid float".to_string())
+}
+
+/// One end of a ZRANGEBYSCORE range
+#[derive(Debug, Clone, Copy)]
+pub struct ScoreBound {
+    pub score: f64,
+    pub exclusive: bool,
+}
+
+impl ScoreBound {
+    /// Parse a bound: a score, optionally prefixed by '(' to exclude it
+    pub fn parse(bytes: &[u8]) -> Result<ScoreBound, String> {
+        let (exclusive, score) = match bytes.strip_prefix(b"(") {
+            Some(rest) => (true, rest),
+            None => (false, bytes),
+        };
+        let score = parse_score(score).map_err(|_| "ERR min or max is not a float".to_string())?;
+        Ok(ScoreBound { score, exclusive })
+    }
+
+    fn below(&self, score: f64) -> bool {
+        if self.exclusive {
+            self.score < score
+        } else {
+            self.score <= score
+        }
+    }
+
+    fn above(&self, score: f64) -> bool {
+        if self.exclusive {
+            score < self.score
+        } else {
+            score <= self.score
+        }
+    }
+}
+
+/// Members of the sorted set at key with a score between min and max, in order,
+/// skipping `offset` of them and returning at most `count` (all if None)
+pub async fn zrange_by_score(
+    key: &str,
+    min: ScoreBound,
+    max: ScoreBound,
+    offset: usize,
+    count: Option<usize>,
+) -> Result<Vec<(Vec<u8>, f64)>, String> {
+    match get_value(key).await {
+        None => Ok(Vec::new()),
+        Some(RedisValue::SortedSet(members)) => Ok(members
+            .into_iter()
+            .filter(|(_, score)| min.below(*score) && max.above(*score))
+            .skip(offset)
+            .take(count.unwrap_or(usize::MAX))
+            .collect()),
+        Some(_) => Err(WRONGTYPE.to_string()),
+    }
+}
+
+/// Return the score of a member of the sorted set at key
+pub async fn zscore(key: &str, member: &[u8]) -> Result<Option<f64>, String> {
+    match get_value(key).await {
+        None => Ok(None),
+        Some(RedisValue::SortedSet(members)) => Ok(members
+            .into_iter()
+            .find(|(m, _)| m == member)
+            .map(|(_, score)| score)),
+        Some(_) => Err(WRONGTYPE.to_string()),
+    }
+}
+
+/// Return the scores of several members of the sorted set at key, None for missing ones
+pub async fn zmscore(key: &str, members: &[Vec<u8>]) -> Result<Vec<Option<f64>>, String> {
+    match get_value(key).await {
+        None => Ok(vec![None; members.len()]),
+        Some(RedisValue::SortedSet(zset)) => Ok(members
+            .iter()
+            .map(|member| zset.iter().find(|(m, _)| m == member).map(|(_, s)| *s))
+            .collect()),
+        Some(_) => Err(WRONGTYPE.to_string()),
+    }
+}
+
+/// The HyperLogLog stored at key in a locked map, None if the key doesn't exist
+fn read_hll(kv: &HashMap<String, RedisValue>, key: &str) -> Result<Option<Hll>, String> {
+    let Some(value) = kv.get(key) else {
+        return Ok(None);
+    };
+    let bytes = value.string_bytes().ok_or_else(|| WRONGTYPE.to_string())?;
+    Hll::from_bytes(&bytes)
+        .map(Some)
+        .ok_or_else(|| hll::WRONGTYPE_HLL.to_string())
+}
+
+/// Add elements to the HyperLogLog at key, creating it if needed.
+/// Returns whether the key was created or its estimate may have changed.
+pub async fn pfadd(key: &str, elements: &[Vec<u8>]) -> Result<bool, String> {
+    let mut kv = kv().write().await;
+    let mut exp = exp().write().await;
+    expire_if_needed(&mut kv, &mut exp, key).await;
+
+    let (mut hll, mut changed) = match read_hll(&kv, key)? {
+        Some(hll) => (hll, false),
+        None => (Hll::new(), true),
+    };
+    for element in elements {
+        changed |= hll.add(element);
+    }
+    if changed {
+        kv.insert(key.to_string(), RedisValue::String(hll.to_bytes()));
+        touch(key).await;
+    }
+    Ok(changed)
+}
+
+/// Estimate the cardinality of the union of the HyperLogLogs at keys; missing keys are empty
+pub async fn pfcount(keys: &[String]) -> Result<u64, String> {
+    let kv = kv().read().await;
+    let exp = exp().read().await;
+    let mut union = Hll::new();
+    for key in keys {
+        if is_expired(&exp, key) {
+            continue;
+        }
+        if let Some(hll) = read_hll(&kv, key)? {
+            union.merge(&hll);
+        }
+    }
+    Ok(union.count())
+}
+
+/// Store the union of the HyperLogLogs at dest and sources in dest, keeping dest's TTL
+pub async fn pfmerge(dest: &str, sources: &[String]) -> Result<(), String> {
+    let mut kv = kv().write().await;
+    let mut exp = exp().write().await;
+    expire_if_needed(&mut kv, &mut exp, dest).await;
+
+    let mut union = read_hll(&kv, dest)?.unwrap_or_else(Hll::new);
+    for source in sources {
+        expire_if_needed(&mut kv, &mut exp, source).await;
+        if let Some(hll) = read_hll(&kv, source)? {
+            union.merge(&hll);
+        }
+    }
+    kv.insert(dest.to_string(), RedisValue::String(union.to_bytes()));
+    touch(dest).await;
+    Ok(())
+}
+
+/// Return the string stored at key, None if it doesn't exist, or WRONGTYPE for other types
+pub async fn get_string(key: &str) -> Result<Option<Vec<u8>>, String> {
+    read_value(key, |value| {
+        value.string_bytes().ok_or_else(|| WRONGTYPE.to_string())
+    })
+    .await
+    .transpose()
+}
+
+/// Set or clear the bit at offset in the string at key, growing it with zero bytes as needed.
+/// Bit 0 is the most significant bit of the first byte. Returns the previous bit.
+pub async fn setbit(key: &str, offset: u64, bit: bool) -> Result<u8, String> {
+    let mut kv = kv().write().await;
+    let mut exp = exp().write().await;
+    expire_if_needed(&mut kv, &mut exp, key).await;
+
+    let mut bytes = match kv.get(key) {
+        None => Vec::new(),
+        Some(value) => value.string_bytes().ok_or_else(|| WRONGTYPE.to_string())?,
+    };
+    let byte = (offset / 8) as usize;
+    let mask = 0x80u8 >> (offset % 8);
+    if bytes.len() <= byte {
+        bytes.resize(byte + 1, 0);
+    }
+    let previous = u8::from(bytes[byte] & mask != 0);
+    if bit {
+        bytes[byte] |= mask;
+    } else {
+        bytes[byte] &= !mask;
+    }
+    kv.insert(key.to_string(), RedisValue::String(bytes));
+    touch(key).await;
+    Ok(previous)
+}
+
+/// Return the bit at offset in the string at key; bits past the end are 0
+pub async fn getbit(key: &str, offset: u64) -> Result<u8, String> {
+    let bytes = get_string(key).await?.unwrap_or_default();
+    let bit = bytes
+        .get((offset / 8) as usize)
+        .is_some_and(|b| b & (0x80 >> (offset % 8)) != 0);
+    Ok(u8::from(bit))
+}
+
+/// Count the set bits in the string at key, optionally limited to an inclusive
+/// start..end range of bytes, or of bits when `bit_unit` is set.
+/// Negative indices count from the end of the string.
+pub async fn bitcount(key: &str, range: Option<(i64, i64)>, bit_unit: bool) -> Result<i64, String> {
+    let bytes = get_string(key).await?.unwrap_or_default();
+    let Some((start, end)) = range else {
+        return Ok(bytes.iter().map(|b| b.count_ones() as i64).sum());
+    };
+
+    let len = if bit_unit {
+        bytes.len() * 8
+    } else {
+        bytes.len()
+    };
+    let Some((start, end)) = normalize_range(start, end, len) else {
+        return Ok(0);
+    };
+    if bit_unit {
+        Ok((start..=end)
+            .filter(|&i| bytes[i / 8] & (0x80 >> (i % 8)) != 0)
+            .count() as i64)
+    } else {
+        Ok(bytes[start..=end]
+            .iter()
+            .map(|b| b.count_ones() as i64)
+            .sum())
+    }
+}
+
+/// Return the bytes of the string at key between two inclusive offsets, see normalize_range
+pub async fn getrange(key: &str, start: i64, end: i64) -> Result<Vec<u8>, String> {
+    let bytes = get_string(key).await?.unwrap_or_default();
+    Ok(match normalize_range(start, end, bytes.len()) {
+        Some((start, end)) => bytes[start..=end].to_vec(),
+        None => Vec::new(),
+    })
+}
+
+/// Check that a string APPEND or SETRANGE would grow to current + extra bytes stays within
+/// proto-max-bulk-len (and so within the i64 its length is replied as), before any of it
+/// is allocated. Returns the new length.
+fn checked_string_len(current: u64, extra: u64) -> Result<i64, String> {
+    current
+        .checked_add(extra)
+        .filter(|&len| len <= config::get_proto_max_bulk_len())
+        .and_then(|len| i64::try_from(len).ok())
+        .ok_or_else(|| "ERR string exceeds maximum allowed size (proto-max-bulk-len)".to_string())
+}
+
+/// Append value to the string at key, creating it if missing. Like in Redis the result
+/// is kept in the raw encoding. Returns the new length.
+pub async fn append(key: &str, value: &[u8]) -> Result<i64, String> {
+    let mut kv = kv().write().await;
+    let mut exp = exp().write().await;
+    expire_if_needed(&mut kv, &mut exp, key).await;
+
+    let mut bytes = match kv.get(key) {
+        None => Vec::new(),
+        Some(value) => value.string_bytes().ok_or_else(|| WRONGTYPE.to_string())?,
+    };
+    let len = checked_string_len(bytes.len() as u64, value.len() as u64)?;
+    bytes.extend_from_slice(value);
+    kv.insert(key.to_string(), RedisValue::RawString(bytes));
+    touch(key).await;
+    Ok(len)
+}
+
+/// Overwrite the string at key from offset with value, padding it with zero bytes as needed.
+/// A missing key is created unless value is empty. Returns the new length.
+pub async fn setrange(key: &str, offset: u64, value: &[u8]) -> Result<i64, String> {
+    let mut kv = kv().write().await;
+    let mut exp = exp().write().await;
+    expire_if_needed(&mut kv, &mut exp, key).await;
+
+    let mut bytes = match kv.get(key) {
+        None if value.is_empty() => return Ok(0),
+        None => Vec::new(),
+        Some(existing) => existing
+            .string_bytes()
+            .ok_or_else(|| WRONGTYPE.to_string())?,
+    };
+    if value.is_empty() {
+        return Ok(bytes.len() as i64);
+    }
+    let end = checked_string_len(offset, value.len() as u64)? as usize;
+    let offset = offset as usize;
+    if bytes.len() < end {
+        bytes.resize(end, 0);
+    }
+    bytes[offset..end].copy_from_slice(value);
+    let len = bytes.len() as i64;
+    kv.insert(key.to_string(), RedisValue::RawString(bytes));
+    touch(key).await;
+    Ok(len)
+}
+
+/// Bitwise operation applied by BITOP
+#[derive(Debug, Clone, Copy)]
+pub enum BitOp {
+    And,
+    Or,
+    Xor,
+    Not,
+}
+
+/// Combine the strings at `sources` byte by byte and store the result at dest.
+/// Shorter (or missing) sources are zero-extended to the longest one; an empty
+/// result deletes dest. Returns the length of the stored string.
+pub async fn bitop(op: BitOp, dest: &str, sources: &[String]) -> Result<i64, String> {
+    let mut kv = kv().write().await;
+    let mut exp = exp().write().await;
+
+    let mut operands = Vec::with_capacity(sources.len());
+    for key in sources {
+        expire_if_needed(&mut kv, &mut exp, key).await;
+        match kv.get(key) {
+            None => operands.push(Vec::new()),
+            Some(value) => {
+                operands.push(value.string_bytes().ok_or_else(|| WRONGTYPE.to_string())?)
+            }
+        }
+    }
+
+    let len = operands.iter().map(Vec::len).max().unwrap_or(0);
+    let byte_at = |operand: &Vec<u8>, i: usize| operand.get(i).copied().unwrap_or(0);
+    let result: Vec<u8> = (0..len)
+        .map(|i| {
+            let mut bytes = operands.iter().map(|o| byte_at(o, i));
+            let first = bytes.next().unwrap_or(0);
+            match op {
+                BitOp::And => bytes.fold(first, |acc, b| acc & b),
+                BitOp::Or => bytes.fold(first, |acc, b| acc | b),
+                BitOp::Xor => bytes.fold(first, |acc, b| acc ^ b),
+                BitOp::Not => !first,
+            }
+        })
+        .collect();
+
+    // The destination is overwritten, so any TTL it had no longer applies
+    exp.remove(dest);
+    if result.is_empty() {
+        remove_key(current_db(), &mut kv, &mut exp, dest).await;
+        return Ok(0);
+    }
+    kv.insert(dest.to_string(), RedisValue::String(result));
+    touch(dest).await;
+    Ok(len as i64)
+}
+
+/// Set field/value pairs in the hash at key, creating it if needed.
+/// Returns the number of fields that were newly added.
+pub async fn hset(key: &str, pairs: Vec<(Vec<u8>, Vec<u8>)>) -> Result<i64, String> {
+    let mut kv = kv().write().await;
+    let mut exp = exp().write().await;
+    expire_if_needed(&mut kv, &mut exp, key).await;
+
+    let created = !kv.contains_key(key);
+    let entry = kv
+        .entry(key.to_string())
+        .or_insert_with(|| RedisValue::Hash(HashValue::default()));
+    let RedisValue::Hash(hash) = entry else {
+        return Err(WRONGTYPE.to_string());
+    };
+    expire_fields(hash);
+    let mut added = 0;
+    for (field, value) in pairs {
+        if hash.insert(field, value) {
+            added += 1;
+        }
+    }
+    touch(key).await;
+    if created {
+        notify::notify(notify::NEW, "new", key, current_db());
+    }
+    notify::notify(notify::HASH, "hset", key, current_db());
+    Ok(added)
+}
+
+/// Set a hash field unless it already exists; returns whether it was set.
+/// Like HSET, an existing key keeps its TTL.
+pub async fn hsetnx(key: &str, field: Vec<u8>, value: Vec<u8>) -> Result<bool, String> {
+    let mut kv = kv().write().await;
+    let mut exp = exp().write().await;
+    expire_if_needed(&mut kv, &mut exp, key).await;
+
+    let entry = kv
+        .entry(key.to_string())
+        .or_insert_with(|| RedisValue::Hash(HashValue::default()));
+    let RedisValue::Hash(hash) = entry else {
+        return Err(WRONGTYPE.to_string());
+    };
+    expire_fields(hash);
+    if hash.fields.contains_key(&field) {
+        return Ok(false);
+    }
+    hash.insert(field, value);
+    touch(key).await;
+    Ok(true)
+}
+
+/// Drop the fields of a hash whose TTL has passed, counting them as expired.
+/// Writers call this with the KV write lock held, before reading the fields.
+fn expire_fields(hash: &mut HashValue) {
+    let expired = hash.remove_expired();
+    stats::incr_by(&stats::EXPIRED_SUBKEYS, expired as u64);
+}
+
+/// Which keys EXPIRE, or fields HEXPIRE, may update, by whether and how they already expire
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExpireCondition {
+    Always,
+    Nx,   // only keys or fields without a TTL
+    Xx,   // only keys or fields with a TTL
+    Gt,   // only when the new expiry is later (no TTL counts as never expiring)
+    Lt,   // only when the new expiry is sooner
+    XxLt, // only keys or fields with a TTL, when the new expiry is sooner
+}
+
+impl ExpireCondition {
+    /// Whether a TTL of current may become at, None being no TTL and a time that has passed
+    fn allows(self, current: Option<Instant>, at: Option<Instant>) -> bool {
+        match self {
+            ExpireCondition::Always => true,
+            ExpireCondition::Nx => current.is_none(),
+            ExpireCondition::Xx => current.is_some(),
+            // A time in the past is sooner than any TTL
+            ExpireCondition::Gt => current.is_some_and(|current| at.is_some_and(|at| at > current)),
+            ExpireCondition::Lt => current.is_none_or(|current| at.is_none_or(|at| at < current)),
+            ExpireCondition::XxLt => {
+                current.is_some_and(|current| at.is_none_or(|at| at < current))
+            }
+        }
+    }
+}
+
+/// Set the expire time of fields of the hash at key to Unix time at_ms. Returns a status
+/// per field, as HEXPIRE does: -2 no such field or key, 0 condition not met, 1 TTL set,
+/// 2 field deleted (a time that has passed).
+pub async fn hexpire(
+    key: &str,
+    at_ms: i64,
+    condition: ExpireCondition,
+    fields: &[Vec<u8>],
+) -> Result<Vec<i64>, String> {
+    let mut kv = kv().write().await;
+    let mut exp = exp().write().await;
+    expire_if_needed(&mut kv, &mut exp, key).await;
+
+    let hash = match kv.get_mut(key) {
+        None => return Ok(vec![-2; fields.len()]),
+        Some(RedisValue::Hash(hash)) => hash,
+        Some(_) => return Err(WRONGTYPE.to_string()),
+    };
+    expire_fields(hash);
+    let at = instant_at(at_ms);
+    let statuses = fields
+        .iter()
+        .map(|field| {
+            if !hash.fields.contains_key(field) {
+                return -2;
+            }
+            let current = hash.expiries.get(field).copied();
+            match at {
+                _ if !condition.allows(current, at) => 0,
+                Some(at) => {
+                    hash.expiries.insert(field.clone(), at);
+                    1
+                }
+                None => {
+                    hash.remove(field);
+                    2
+                }
+            }
+        })
+        .collect();
+
+    if hash.fields.is_empty() {
+        remove_key(current_db(), &mut kv, &mut exp, key).await;
+    } else {
+        touch(key).await;
+    }
+    Ok(statuses)
+}
+
+/// Remaining TTL in seconds, rounded up, of fields of the hash at key.
+/// Returns -2 for missing fields (all of them if the key doesn't exist) and -1 for fields without a TTL.
+pub async fn httl(key: &str, fields: &[Vec<u8>]) -> Result<Vec<i64>, String> {
+    let hash = match get_value(key).await {
+        None => return Ok(vec![-2; fields.len()]),
+        Some(RedisValue::Hash(hash)) => hash,
+        Some(_) => return Err(WRONGTYPE.to_string()),
+    };
+    let now = Instant::now();
+    Ok(fields
+        .iter()
+        .map(|field| match hash.expiries.get(field) {
+            _ if !hash.fields.contains_key(field) => -2,
+            None => -1,
+            Some(&at) => at.saturating_duration_since(now).as_millis().div_ceil(1000) as i64,
+        })
+        .collect())
+}
+
+/// Return the values of fields of the hash at key (None for missing ones) and update their
+/// TTL, as HGETEX does. An expiry in the past deletes the fields, and the key if it empties.
+pub async fn hgetex(
+    key: &str,
+    expiry: GetexExpiry,
+    fields: &[Vec<u8>],
+) -> Result<Vec<Option<Vec<u8>>>, String> {
+    let mut kv = kv().write().await;
+    let mut exp = exp().write().await;
+    expire_if_needed(&mut kv, &mut exp, key).await;
+
+    let hash = match kv.get_mut(key) {
+        None => return Ok(vec![None; fields.len()]),
+        Some(RedisValue::Hash(hash)) => hash,
+        Some(_) => return Err(WRONGTYPE.to_string()),
+    };
+    expire_fields(hash);
+    let values: Vec<Option<Vec<u8>>> = fields
+        .iter()
+        .map(|field| hash.fields.get(field).cloned())
+        .collect();
+    for (field, _) in fields
+        .iter()
+        .zip(&values)
+        .filter(|(_, value)| value.is_some())
+    {
+        match expiry {
+            GetexExpiry::Keep => {}
+            GetexExpiry::Persist => {
+                hash.expiries.remove(field);
+            }
+            GetexExpiry::AtUnixMs(at) => match instant_at(at) {
+                Some(at) => {
+                    hash.expiries.insert(field.clone(), at);
+                }
+                None => {
+                    hash.remove(field);
+                }
+            },
+        }
+    }
+
+    if hash.fields.is_empty() {
+        remove_key(current_db(), &mut kv, &mut exp, key).await;
+    } else {
+        touch(key).await;
+    }
+    Ok(values)
+}
+
+/// Return the values of fields of the hash at key (None for missing ones) and delete
+/// the fields, and the key if it empties, as HGETDEL does
+pub async fn hgetdel(key: &str, fields: &[Vec<u8>]) -> Result<Vec<Option<Vec<u8>>>, String> {
+    let mut kv = kv().write().await;
+    let mut exp = exp().write().await;
+    expire_if_needed(&mut kv, &mut exp, key).await;
+
+    let hash = match kv.get_mut(key) {
+        None => return Ok(vec![None; fields.len()]),
+        Some(RedisValue::Hash(hash)) => hash,
+        Some(_) => return Err(WRONGTYPE.to_string()),
+    };
+    expire_fields(hash);
+    // A field named twice is only returned the first time, like in Redis
+    let values = fields
+        .iter()
+        .map(|field| {
+            let value = hash.fields.get(field).cloned();
+            hash.remove(field);
+            value
+        })
+        .collect();
+
+    if hash.fields.is_empty() {
+        remove_key(current_db(), &mut kv, &mut exp, key).await;
+    } else {
+        touch(key).await;
+    }
+    Ok(values)
+}
+
+/// How XADD picks the ID of a new stream entry
+pub enum StreamIdSpec {
+    Auto,         // *: the current time, or after the last ID if the clock is behind it
+    AutoSeq(u64), // <ms>-*: the next sequence number within the given milliseconds
+    Explicit(StreamId),
+}
+
+/// The ID a new entry gets after last under spec, or the error XADD replies with
+fn next_stream_id(last: StreamId, spec: StreamIdSpec) -> Result<StreamId, String> {
+    const NOT_GREATER: &str =
+        "ERR The ID specified in XADD is equal or smaller than the target stream top item";
+    match spec {
+        StreamIdSpec::Auto => {
+            let ms = (unix_now_ms().max(0) as u64).max(last.0);
+            if ms > last.0 {
+                Ok((ms, 0))
+            } else if let Some(seq) = last.1.checked_add(1) {
+                Ok((ms, seq))
+            } else if let Some(ms) = ms.checked_add(1) {
+                Ok((ms, 0))
+            } else {
+                Err(
+                    "ERR The stream has exhausted the last possible ID, unable to add more items"
+                        .to_string(),
+                )
+            }
+        }
+        StreamIdSpec::AutoSeq(ms) if ms > last.0 => Ok((ms, 0)),
+        StreamIdSpec::AutoSeq(ms) if ms == last.0 => last
+            .1
+            .checked_add(1)
+            .map(|seq| (ms, seq))
+            .ok_or_else(|| NOT_GREATER.to_string()),
+        StreamIdSpec::AutoSeq(_) => Err(NOT_GREATER.to_string()),
+        StreamIdSpec::Explicit((0, 0)) => {
+            Err("ERR The ID specified in XADD must be greater than 0-0".to_string())
+        }
+        StreamIdSpec::Explicit(id) if id > last => Ok(id),
+        StreamIdSpec::Explicit(_) => Err(NOT_GREATER.to_string()),
+    }
+}
+
+/// Append an entry to the stream at key, creating it if needed. Returns the new entry's ID.
+pub async fn xadd(
+    key: &str,
+    id: StreamIdSpec,
+    fields: Vec<(Vec<u8>, Vec<u8>)>,
+) -> Result<StreamId, String> {
+    let mut kv = kv().write().await;
+    let mut exp = exp().write().await;
+    expire_if_needed(&mut kv, &mut exp, key).await;
+
+    let last = match kv.get(key) {
+        None => (0, 0),
+        Some(RedisValue::Stream(stream)) => stream.last_id,
+        Some(_) => return Err(WRONGTYPE.to_string()),
+    };
+    let id = next_stream_id(last, id)?;
+    let created = !kv.contains_key(key);
+    let entry = kv
+        .entry(key.to_string())
+        .or_insert_with(|| RedisValue::Stream(StreamValue::default()));
+    if let RedisValue::Stream(stream) = entry {
+        stream.entries.push((id, fields));
+        stream.last_id = id;
+    }
+    touch(key).await;
+    if created {
+        notify::notify(notify::NEW, "new", key, current_db());
+    }
+    notify::notify(notify::STREAM, "xadd", key, current_db());
+    Ok(id)
+}
+
+/// The stream stored at key, or None if the key doesn't exist
+pub async fn stream(key: &str) -> Result<Option<StreamValue>, String> {
+    match get_value(key).await {
+        None => Ok(None),
+        Some(RedisValue::Stream(stream)) => Ok(Some(stream)),
+        Some(_) => Err(WRONGTYPE.to_string()),
+    }
+}
+
+/// Number of entries in the stream at key, 0 if the key doesn't exist
+pub async fn xlen(key: &str) -> Result<i64, String> {
+    match get_value(key).await {
+        None => Ok(0),
+        Some(RedisValue::Stream(stream)) => Ok(stream.entries.len() as i64),
+        Some(_) => Err(WRONGTYPE.to_string()),
+    }
+}
+
+/// The entries of the stream at key with IDs from start to end, both included,
+/// at most count of them if given
+pub async fn xrange(
+    key: &str,
+    start: StreamId,
+    end: StreamId,
+    count: Option<usize>,
+) -> Result<Vec<StreamEntry>, String> {
+    let stream = match get_value(key).await {
+        None => return Ok(Vec::new()),
+        Some(RedisValue::Stream(stream)) => stream,
+        Some(_) => return Err(WRONGTYPE.to_string()),
+    };
+    let from = stream.entries.partition_point(|(id, _)| *id < start);
+    let to = stream.entries.partition_point(|(id, _)| *id <= end);
+    if from >= to {
+        return Ok(Vec::new());
+    }
+    let to = count.map_or(to, |count| to.min(from.saturating_add(count)));
+    Ok(stream.entries[from..to].to_vec())
+}
+
+/// Return the hash stored at key, or an empty one if the key doesn't exist
+pub async fn hgetall(key: &str) -> Result<HashMap<Vec<u8>, Vec<u8>>, String> {
+    match get_value(key).await {
+        None => Ok(HashMap::new()),
+        Some(RedisValue::Hash(hash)) => Ok(hash.fields),
+        Some(_) => Err(WRONGTYPE.to_string()),
+    }
+}
+
+/// Return the value of a field in the hash at key
+pub async fn hget(key: &str, field: &[u8]) -> Result<Option<Vec<u8>>, String> {
+    Ok(hgetall(key).await?.remove(field))
+}
+
+/// Run `f` on the list stored at key while holding the write locks,
+/// deleting the key if the list ends up empty.
+/// Returns Ok(None) if the key does not exist.
+async fn with_list<T>(
+    key: &str,
+    f: impl FnOnce(&mut Vec<Vec<u8>>) -> T,
+) -> Result<Option<T>, String> {
+    let mut kv = kv().write().await;
+    let mut exp = exp().write().await;
+    expire_if_needed(&mut kv, &mut exp, key).await;
+
+    let result = match kv.get_mut(key) {
+        None => return Ok(None),
+        Some(RedisValue::List(list)) => f(list),
+        Some(_) => return Err(WRONGTYPE.to_string()),
+    };
+    if matches!(kv.get(key), Some(RedisValue::List(list)) if list.is_empty()) {
+        remove_key(current_db(), &mut kv, &mut exp, key).await;
+    } else {
+        touch(key).await;
+    }
+    Ok(Some(result))
+}
+
+/// Longest list a push may leave behind: lengths are replied as RESP integers
+const LIST_MAX_LEN: usize = i64::MAX as usize;
+
+/// Push values onto the head (LPUSH) or tail (RPUSH) of the list at key,
+/// creating it if needed. Returns the length of the list after the push.
+/// Any other type at key, including a string, is a WRONGTYPE error and is left untouched.
+pub async fn push(key: &str, values: Vec<Vec<u8>>, head: bool) -> Result<i64, String> {
+    let mut kv = kv().write().await;
+    let mut exp = exp().write().await;
+    expire_if_needed(&mut kv, &mut exp, key).await;
+
+    let created = !kv.contains_key(key);
+    let entry = kv
+        .entry(key.to_string())
+        .or_insert_with(|| RedisValue::List(Vec::new()));
+    let RedisValue::List(list) = entry else {
+        return Err(WRONGTYPE.to_string());
+    };
+    if list
+        .len()
+        .checked_add(values.len())
+        .is_none_or(|len| len > LIST_MAX_LEN)
+    {
+        return Err("ERR list would exceed the maximum length".to_string());
+    }
+    if head {
+        // Each value goes to the head in turn, so LPUSH a b c yields [c, b, a];
+        // splicing them in reversed does that with a single shift of the list
+        list.splice(0..0, values.into_iter().rev());
+    } else {
+        list.extend(values);
+    }
+    let len = list.len() as i64;
+    touch(key).await;
+    if created {
+        notify::notify(notify::NEW, "new", key, current_db());
+    }
+    let event = if head { "lpush" } else { "rpush" };
+    notify::notify(notify::LIST, event, key, current_db());
+    wake_list_waiters(key);
+    Ok(len)
+}
+
+/// Pop up to count elements from the head or tail of the list at key.
+/// The key is deleted once the list is empty.
+pub async fn pop(key: &str, count: usize, head: bool) -> Result<Vec<Vec<u8>>, String> {
+    let popped = with_list(key, |list| {
+        let n = count.min(list.len());
+        if head {
+            list.drain(..n).collect()
+        } else {
+            list.split_off(list.len() - n).into_iter().rev().collect()
+        }
+    })
+    .await?;
+    Ok(popped.unwrap_or_default())
+}
+
+/// Pop up to count elements from the first non-empty list among keys,
+/// returning the key they came from.
+pub async fn pop_first(
+    keys: &[String],
+    count: usize,
+    head: bool,
+) -> Result<Option<(String, Vec<Vec<u8>>)>, String> {
+    for key in keys {
+        let popped = pop(key, count, head).await?;
+        if !popped.is_empty() {
+            return Ok(Some((key.clone(), popped)));
+        }
+    }
+    Ok(None)
+}
+
+/// Pop an element from one end of the source list and push it onto one end of the
+/// destination, atomically, as LMOVE does. Returns the element, or None if the
+/// source is empty. Both keys must hold lists (or not exist); source and destination
+/// may be the same list, which then rotates.
+pub async fn lmove(
+    source: &str,
+    destination: &str,
+    from_head: bool,
+    to_head: bool,
+) -> Result<Option<Vec<u8>>, String> {
+    let mut kv = kv().write().await;
+    let mut exp = exp().write().await;
+    expire_if_needed(&mut kv, &mut exp, source).await;
+    expire_if_needed(&mut kv, &mut exp, destination).await;
+
+    let Some(value) = kv.get(source) else {
+        return Ok(None);
+    };
+    if !matches!(value, RedisValue::List(_))
+        || kv
+            .get(destination)
+            .is_some_and(|v| !matches!(v, RedisValue::List(_)))
+    {
+        return Err(WRONGTYPE.to_string());
+    }
+    let Some(RedisValue::List(list)) = kv.get_mut(source) else {
+        return Ok(None);
+    };
+    let element = if from_head && !list.is_empty() {
+        Some(list.remove(0))
+    } else {
+        list.pop()
+    };
+    let Some(element) = element else {
+        return Ok(None);
+    };
+    if list.is_empty() {
+        remove_key(current_db(), &mut kv, &mut exp, source).await;
+    } else {
+        touch(source).await;
+    }
+
+    let entry = kv
+        .entry(destination.to_string())
+        .or_insert_with(|| RedisValue::List(Vec::new()));
+    if let RedisValue::List(list) = entry {
+        if to_head {
+            list.insert(0, element.clone());
+        } else {
+            list.push(element.clone());
+        }
+    }
+    touch(destination).await;
+    wake_list_waiters(destination);
+    Ok(Some(element))
+}
+
+/// Registration of a blocked client on a set of list or sorted set keys.
+/// It is created before checking the lists so no push can slip in unnoticed,
+/// and unregisters itself when dropped.
+pub struct ListWaiter {
+    db: usize,
+    keys: Vec<String>,
+    notify: Arc<Notify>,
+}
+
+impl ListWaiter {
+    pub fn new(keys: &[String]) -> Self {
+        let notify = Arc::new(Notify::new());
+        let db = current_db();
+        let mut waiters = LIST_WAITERS.lock().unwrap();
+        for key in keys {
+            waiters
+                .entry((db, key.clone()))
+                .or_default()
+                .push(Arc::clone(&notify));
+        }
+        ListWaiter {
+            db,
+            keys: keys.to_vec(),
+            notify,
+        }
+    }
+
+    /// Wait until one of the keys receives a push, or a ZADD for sorted sets
+    pub async fn wait(&self) {
+        self.notify.notified().await;
+    }
+}
+
+impl Drop for ListWaiter {
+    fn drop(&mut self) {
+        let mut waiters = LIST_WAITERS.lock().unwrap();
+        for key in &self.keys {
+            let entry = (self.db, key.clone());
+            if let Some(list) = waiters.get_mut(&entry) {
+                list.retain(|n| !Arc::ptr_eq(n, &self.notify));
+                if list.is_empty() {
+                    waiters.remove(&entry);
+                }
+            }
+        }
+    }
+}
+
+/// Wake every client blocked on key; each one re-checks its keys itself.
+fn wake_list_waiters(key: &str) {
+    let entry = (current_db(), key.to_string());
+    if let Some(list) = LIST_WAITERS.lock().unwrap().get(&entry) {
+        for notify in list {
+            // notify_one stores a permit, so a client between checks doesn't miss it
+            notify.notify_one();
+        }
+    }
+}
+
+/// Return the elements of the list at key between start and stop (inclusive),
+/// where negative indices count from the tail.
+pub async fn lrange(key: &str, start: i64, stop: i64) -> Result<Vec<Vec<u8>>, String> {
+    let list = match get_value(key).await {
+        None => return Ok(Vec::new()),
+        Some(RedisValue::List(list)) => list,
+        Some(_) => return Err(WRONGTYPE.to_string()),
+    };
+    Ok(match normalize_range(start, stop, list.len()) {
+        Some((start, stop)) => list[start..=stop].to_vec(),
+        None => Vec::new(),
+    })
+}
+
+/// Find the indices of element in the list at key. A positive rank skips to the
+/// rank-th match from the head, a negative one scans from the tail instead.
+/// At most count matches are returned (0 means all) and at most maxlen
+/// entries are examined (0 means the whole list).
+pub async fn lpos(
+    key: &str,
+    element: &[u8],
+    rank: i64,
+    count: usize,
+    maxlen: usize,
+) -> Result<Vec<i64>, String> {
+    let list = match get_value(key).await {
+        None => return Ok(Vec::new()),
+        Some(RedisValue::List(list)) => list,
+        Some(_) => return Err(WRONGTYPE.to_string()),
+    };
+    let limit = if maxlen == 0 { list.len() } else { maxlen };
+    let count = if count == 0 { usize::MAX } else { count };
+    let skip = (rank.unsigned_abs() - 1) as usize;
+
+    let indices: Box<dyn Iterator<Item = usize>> = if rank > 0 {
+        Box::new(0..list.len())
+    } else {
+        Box::new((0..list.len()).rev())
+    };
+    Ok(indices
+        .take(limit)
+        .filter(|&i| list[i] == element)
+        .skip(skip)
+        .take(count)
+        .map(|i| i as i64)
+        .collect())
+}
+
+/// Remove up to |count| occurrences of value from the list at key: from the head
+/// when count > 0, from the tail when count < 0, and all of them when count is 0.
+/// Returns how many elements were removed.
+pub async fn lrem(key: &str, count: i64, value: &[u8]) -> Result<i64, String> {
+    let removed = with_list(key, |list| {
+        let limit = if count == 0 {
+            usize::MAX
+        } else {
+            count.unsigned_abs() as usize
+        };
+        let mut removed = 0;
+        if count >= 0 {
+            let mut i = 0;
+            while i < list.len() && removed < limit {
+                if list[i] == value {
+                    list.remove(i);
+                    removed += 1;
+                } else {
+                    i += 1;
+                }
+            }
+        } else {
+            let mut i = list.len();
+            while i > 0 && removed < limit {
+                i -= 1;
+                if list[i] == value {
+                    list.remove(i);
+                    removed += 1;
+                }
+            }
+        }
+        removed as i64
+    })
+    .await?;
+    Ok(removed.unwrap_or(0))
+}
+
+/// Trim the list at key so it only contains the inclusive start..stop range,
+/// where negative indices count from the tail. The key is deleted if nothing remains.
+/// Returns how many elements were removed.
+pub async fn ltrim(key: &str, start: i64, stop: i64) -> Result<usize, String> {
+    let removed = with_list(key, |list| {
+        let len = list.len();
+        match normalize_range(start, stop, len) {
+            Some((start, stop)) => {
+                list.truncate(stop + 1);
+                list.drain(..start);
+            }
+            None => list.clear(),
+        }
+        len - list.len()
+    })
+    .await?;
+    Ok(removed.unwrap_or(0))
+}
+
+/// Insert value before or after the first occurrence of pivot in the list at key.
+/// Returns the new length, -1 if the pivot wasn't found, or 0 if the key doesn't exist.
+pub async fn linsert(key: &str, before: bool, pivot: &[u8], value: Vec<u8>) -> Result<i64, String> {
+    let len = with_list(key, |list| {
+        match list.iter().position(|item| item == pivot) {
+            Some(idx) => {
+                list.insert(if before { idx } else { idx + 1 }, value);
+                list.len() as i64
+            }
+            None => -1,
+        }
+    })
+    .await?;
+    Ok(len.unwrap_or(0))
+}
+
+/// Move key, with its TTL, from database `from` to database `to`.
+/// Returns false if the key doesn't exist in the source or already exists in the target.
+pub async fn move_key(key: &str, from: usize, to: usize) -> Result<bool, String> {
+    if from == to {
+        return Err("ERR source and destination objects are the same".to_string());
+    }
+    let (low, high) = (from.min(to), from.max(to));
+    // Lock the lower-numbered database first, and every KV before any EXP
+    let mut kv_low = KV[low].write().await;
+    let mut kv_high = KV[high].write().await;
+    let mut exp_low = EXP[low].write().await;
+    let mut exp_high = EXP[high].write().await;
+    let (src_kv, dst_kv, src_exp, dst_exp) = if from < to {
+        (&mut *kv_low, &mut *kv_high, &mut *exp_low, &mut *exp_high)
+    } else {
+        (&mut *kv_high, &mut *kv_low, &mut *exp_high, &mut *exp_low)
+    };
+    expire_in_db(from, src_kv, src_exp, key).await;
+    expire_in_db(to, dst_kv, dst_exp, key).await;
+    if !src_kv.contains_key(key) || dst_kv.contains_key(key) {
+        return Ok(false);
+    }
+
+    if let Some(value) = src_kv.remove(key) {
+        dst_kv.insert(key.to_string(), value);
+    }
+    if let Some(expiry) = src_exp.remove(key) {
+        dst_exp.insert(key.to_string(), expiry);
+    }
+    let meta = ACCESS[from].write().await.remove(key);
+    ACCESS[to]
+        .write()
+        .await
+        .insert(key.to_string(), meta.unwrap_or_else(AccessMeta::new));
+    Ok(true)
+}
+
+/// Exchange the contents of two databases, keys, TTLs and access times alike.
+/// Connections keep their selected index, so they see the other dataset from now on.
+/// Every task finds a database through its index each time (the expirer on each pass,
+/// blocked clients when they look at their keys again), so none is left holding the old one.
+pub async fn swapdb(a: usize, b: usize) {
+    if a == b {
+        return;
+    }
+    let (low, high) = (a.min(b), a.max(b));
+    // Lock the lower-numbered database first, and every KV before any EXP and ACCESS
+    let mut kv_low = KV[low].write().await;
+    let mut kv_high = KV[high].write().await;
+    let mut exp_low = EXP[low].write().await;
+    let mut exp_high = EXP[high].write().await;
+    let mut access_low = ACCESS[low].write().await;
+    let mut access_high = ACCESS[high].write().await;
+    std::mem::swap(&mut *kv_low, &mut *kv_high);
+    std::mem::swap(&mut *exp_low, &mut *exp_high);
+    std::mem::swap(&mut *access_low, &mut *access_high);
+
+    // Clients blocked on a list in either database check whether one arrived with the swap
+    for ((db, _), list) in LIST_WAITERS.lock().unwrap().iter() {
+        if *db == low || *db == high {
+            for notify in list {
+                notify.notify_one();
+            }
+        }
+    }
+}
+
+/// Get all keys matching a glob-style pattern.
+/// The locks are only held to snapshot the live keys, so writers aren't kept waiting
+/// while a pattern is tried against every key of a large database.
+pub async fn get_keys_matching_pattern(pattern: &[u8]) -> Vec<String> {
+    let keys: Vec<String> = {
+        let kv = kv().read().await;
+        let exp = exp().read().await;
+        kv.keys()
+            .filter(|k| !is_expired(&exp, k))
+            .cloned()
+            .collect()
+    };
+    keys.into_iter()
+        .filter(|k| glob_match(pattern, k.as_bytes()))
+        .collect()
+}
+
+/// Match a string against a glob-style pattern the way Redis does (stringmatchlen),
+/// on raw bytes: `*` matches any run of bytes, `/` included, `?` any single byte,
+/// `[...]` a class of bytes and ranges (negated by a leading `^`), and `\` escapes
+/// the next byte. Malformed patterns such as an unclosed `[` still match something.
+pub fn glob_match(pattern: &[u8], string: &[u8]) -> bool {
+    let (mut p, mut s) = (0, 0);
+    // Where to resume after the latest `*`: the pattern past it and the string
+    // position it has consumed up to. Backtracking to only the latest one is enough,
+    // since it can absorb anything an earlier star would have.
+    let mut star: Option<(usize, usize)> = None;
+    while s < string.len() {
+        if pattern.get(p) == Some(&b'*') {
+            while pattern.get(p) == Some(&b'*') {
+                p += 1;
+            }
+            if p == pattern.len() {
+                return true;
+            }
+            star = Some((p, s));
+            continue;
+        }
+        if p < pattern.len() {
+            let (matched, next) = match_glob_token(pattern, p, string[s]);
+            if matched {
+                p = next;
+                s += 1;
+                continue;
+            }
+        }
+        match star {
+            Some((after_star, consumed)) => {
+                star = Some((after_star, consumed + 1));
+                p = after_star;
+                s = consumed + 1;
+            }
+            None => return false,
+        }
+    }
+    while pattern.get(p) == Some(&b'*') {
+        p += 1;
+    }
+    p == pattern.len()
+}
+
+/// Match one byte against the pattern token at `p` (anything but `*`).
+/// Returns whether it matched and where the next token starts.
+fn match_glob_token(pattern: &[u8], p: usize, c: u8) -> (bool, usize) {
+    match pattern[p] {
+        b'?' => (true, p + 1),
+        b'\\' if p + 1 < pattern.len() => (pattern[p + 1] == c, p + 2),
+        b'[' => {
+            let mut i = p + 1;
+            let negate = pattern.get(i) == Some(&b'^');
+            if negate {
+                i += 1;
+            }
+            let mut matched = false;
+            // An unclosed class runs to the end of the pattern
+            while i < pattern.len() {
+                if pattern[i] == b'\\' && i + 1 < pattern.len() {
+                    matched |= pattern[i + 1] == c;
+                    i += 2;
+                } else if pattern[i] == b']' {
+                    i += 1;
+                    break;
+                } else if i + 2 < pattern.len() && pattern[i + 1] == b'-' {
+                    let (low, high) = (
+                        pattern[i].min(pattern[i + 2]),
+                        pattern[i].max(pattern[i + 2]),
+                    );
+                    matched |= (low..=high).contains(&c);
+                    i += 3;
+                } else {
+                    matched |= pattern[i] == c;
+                    i += 1;
+                }
+            }
+            (matched != negate, i)
+        }
+        b => (b == c, p + 1),
+    }
+}
+
+/// Position of an element in a SCAN iteration. Elements are visited in hash order
+/// and a cursor is the hash to resume from, so it stays meaningful however the
+/// collection changes between calls.
+fn scan_hash(name: &[u8]) -> u64 {
+    use std::hash::{Hash, Hasher};
+    let mut hasher = std::collections::hash_map::DefaultHasher::new();
+    name.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Take the page of a scan from (hash, element) pairs: `count` elements with a hash
+/// of at least `cursor`, in hash order, plus any sharing the last one's hash so the
+/// next cursor never splits them. Returns that next cursor, 0 once nothing is left.
+///
+/// An element present for the whole iteration is returned exactly once. Elements
+/// added or removed meanwhile may or may not be returned.
+fn scan_page<T>(items: Vec<(u64, T)>, cursor: u64, count: usize) -> (u64, Vec<T>) {
+    let mut items: Vec<(u64, T)> = items.into_iter().filter(|(h, _)| *h >= cursor).collect();
+    let mut next = 0;
+    if items.len() > count {
+        // Partition the count lowest hashes off the rest, so only the page gets sorted
+        items.select_nth_unstable_by_key(count, |(h, _)| *h);
+        let last = items[..count].iter().map(|(h, _)| *h).max();
+        let (ties, rest): (Vec<_>, Vec<_>) = items
+            .split_off(count)
+            .into_iter()
+            .partition(|(h, _)| Some(*h) == last);
+        items.extend(ties);
+        // The next hash is greater than every returned one, so it can't be 0
+        next = rest.iter().map(|(h, _)| *h).min().unwrap_or(0);
+    }
+    items.sort_by_key(|(h, _)| *h);
+    (next, items.into_iter().map(|(_, item)| item).collect())
+}
+
+/// Iterate the keyspace from cursor, examining count keys (see scan_page).
+/// MATCH and TYPE filters are applied to the examined keys, so a page may come back empty.
+/// Returns the next cursor (0 once the iteration is complete) and the matching keys.
+pub async fn scan(
+    cursor: u64,
+    count: usize,
+    pattern: Option<&str>,
+    type_filter: Option<&str>,
+) -> (u64, Vec<String>) {
+    let kv = kv().read().await;
+    let exp = exp().read().await;
+    let keys: Vec<(u64, &String)> = kv
+        .keys()
+        .filter(|k| !is_expired(&exp, k))
+        .map(|k| (scan_hash(k.as_bytes()), k))
+        .collect();
+    let (next, page) = scan_page(keys, cursor, count);
+
+    let page = page
+        .into_iter()
+        .filter(|k| pattern.is_none_or(|p| glob_match(p.as_bytes(), k.as_bytes())))
+        .filter(|k| type_filter.is_none_or(|t| kv[k.as_str()].type_name() == t))
+        .cloned()
+        .collect();
+    (next, page)
+}
+
+/// Iterate the fields of a hash, or the members of a set or sorted set, like scan.
+/// Hashes yield field/value pairs and sorted sets member/score pairs, flattened.
+/// Returns the next cursor (0 once complete) and the elements; a missing key is an empty scan.
+pub async fn scan_members(
+    key: &str,
+    type_name: &str,
+    cursor: u64,
+    count: usize,
+    pattern: Option<&str>,
+) -> Result<(u64, Vec<Vec<u8>>), String> {
+    let entries: Vec<(Vec<u8>, Option<Vec<u8>>)> = match get_value(key).await {
+        None => return Ok((0, Vec::new())),
+        Some(value) if value.type_name() != type_name => return Err(WRONGTYPE.to_string()),
+        Some(RedisValue::Hash(hash)) => {
+            hash.fields.into_iter().map(|(f, v)| (f, Some(v))).collect()
+        }
+        Some(RedisValue::Set(members)) => members.into_iter().map(|m| (m, None)).collect(),
+        Some(RedisValue::SortedSet(members)) => members
+            .into_iter()
+            .map(|(m, score)| (m, Some(score.to_string().into_bytes())))
+            .collect(),
+        Some(_) => return Err("ERR unsupported encoding for scan".to_string()),
+    };
+    let entries = entries
+        .into_iter()
+        .map(|entry| (scan_hash(&entry.0), entry))
+        .collect();
+    let (next, entries) = scan_page(entries, cursor, count);
+
+    let mut page = Vec::new();
+    for (name, value) in entries {
+        if pattern.is_none_or(|p| glob_match(p.as_bytes(), &name)) {
+            page.push(name);
+            page.extend(value);
+        }
+    }
+    Ok((next, page))
+}
+
+/// Serialize the keyspace as a JSON object of key -> {"type", "value"}, sorted by key.
+/// Values kept in a raw RDB encoding are summarized as null.
+pub async fn dump_keys_json() -> String {
+    let kv = kv().read().await;
+    let exp = exp().read().await;
+    let mut keys: Vec<&String> = kv.keys().filter(|k| !is_expired(&exp, k)).collect();
+    keys.sort();
+
+    let entries: Vec<String> = keys
+        .into_iter()
+        .map(|key| {
+            let value = &kv[key];
+            let json = match value {
+                RedisValue::List(items) | RedisValue::Set(items) => json_array(items),
+                RedisValue::Hash(hash) => {
+                    let now = Instant::now();
+                    let mut fields: Vec<_> = hash
+                        .fields
+                        .iter()
+                        .filter(|(f, _)| hash.expiries.get(*f).is_none_or(|&at| at >= now))
+                        .collect();
+                    fields.sort();
+                    let fields: Vec<String> = fields
+                        .into_iter()
+                        .map(|(f, v)| format!("{}:{}", json_string(f), json_string(v)))
+                        .collect();
+                    format!("{{{}}}", fields.join(","))
+                }
+                RedisValue::SortedSet(members) => {
+                    let members: Vec<String> = members
+                        .iter()
+                        .map(|(m, score)| format!("[{},{}]", json_string(m), json_number(*score)))
+                        .collect();
+                    format!("[{}]", members.join(","))
+                }
+                other => other
+                    .string_bytes()
+                    .map_or_else(|| "null".to_string(), |b| json_string(&b)),
+            };
+            format!(
+                "{}:{{\"type\":\"{}\",\"value\":{}}}",
+                json_string(key.as_bytes()),
+                value.type_name(),
+                json
+            )
+        })
+        .collect();
+    format!("{{{}}}", entries.join(","))
+}
+
+/// Encode bytes as a JSON string, replacing invalid UTF-8
+fn json_string(bytes: &[u8]) -> String {
+    let mut out = String::from("\"");
+    for c in String::from_utf8_lossy(bytes).chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+fn json_array(items: &[Vec<u8>]) -> String {
+    let items: Vec<String> = items.iter().map(|i| json_string(i)).collect();
+    format!("[{}]", items.join(","))
+}
+
+/// JSON has no infinities or NaN, so those scores are written as strings
+fn json_number(n: f64) -> String {
+    if n.is_finite() {
+        n.to_string()
+    } else {
+        format!("\"{}\"", n)
+    }
+}
+
+pub fn set_active_expire(enabled: bool) {
+    ACTIVE_EXPIRE.store(enabled, Ordering::SeqCst);
+}
+
+pub fn active_expire_enabled() -> bool {
+    ACTIVE_EXPIRE.load(Ordering::SeqCst)
+}
+
+/// Purge expired keys from KV and EXP in every database
+pub async fn purge_expired_keys() {
+    let now = Instant::now();
+    for db in 0..database_count() {
+        // Lock in the same order as every other writer (KV, then EXP) to avoid deadlocks
+        let mut kv = KV[db].write().await;
+        let mut exp = EXP[db].write().await;
+        let expired_keys: Vec<String> = exp
+            .iter()
+            .filter_map(|(k, &v)| if now > v { Some(k.clone()) } else { None })
+            .collect();
+        for k in expired_keys {
+            if remove_key(db, &mut kv, &mut exp, &k).await.is_some() {
+                stats::incr(&stats::EXPIRED_KEYS);
+                notify::notify(notify::EXPIRED, "expired", &k, db);
+            }
+        }
+
+        // Then the hash fields past their TTL, deleting hashes they leave empty
+        let mut emptied = Vec::new();
+        for (k, value) in kv.iter_mut() {
+            if let RedisValue::Hash(hash) = value {
+                expire_fields(hash);
+                if hash.fields.is_empty() {
+                    emptied.push(k.clone());
+                }
+            }
+        }
+        for k in emptied {
+            remove_key(db, &mut kv, &mut exp, &k).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quicklist_repr_describes_every_node() {
+        let items: Vec<Vec<u8>> = ["a", "bb", "ccc"]
+            .iter()
+            .map(|i| i.as_bytes().to_vec())
+            .collect();
+        let size = config::get_config().list_max_listpack_size;
+        let header = format!(
+            "{{count : 3}}\n{{len : 1}}\n{{fill : {}}}\n{{compress : 0}}\n{{bookmark_count : 0}}\n\
+             {{quicklist node(0)\n{{container : PACKED, encoding: RAW, size: {}, count: 3, recompress: 0, attempted_compress: 0}}\n",
+            size,
+            LISTPACK_HEADER + 3 + 4 + 5
+        );
+        assert_eq!(quicklist_repr(&items, false), format!("{}}}\n", header));
+        assert_eq!(
+            quicklist_repr(&items, true),
+            format!(
+                "{}{{ listpack:\n\t[0] a\n\t[1] bb\n\t[2] ccc\n}}\n}}\n",
+                header
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn string_reads_see_only_live_strings() {
+        let value = |v: &str| v.as_bytes().to_vec();
+        set(value("db:read:s"), value("text"), None, false, false, false)
+            .await
+            .unwrap();
+        assert_eq!(get_string("db:read:s").await, Ok(Some(value("text"))));
+        assert_eq!(get_string_or_none("db:read:s").await, Some(value("text")));
+
+        hset("db:read:h", vec![(value("f"), value("v"))])
+            .await
+            .unwrap();
+        assert_eq!(get_string("db:read:h").await, Err(WRONGTYPE.to_string()));
+        assert_eq!(get_string_or_none("db:read:h").await, None);
+        // Once every field has passed its TTL the hash reads as missing
+        let passed = Instant::now() - Duration::from_millis(1);
+        if let Some(RedisValue::Hash(hash)) = kv().write().await.get_mut("db:read:h") {
+            hash.expiries.insert(value("f"), passed);
+        }
+        assert_eq!(get_string("db:read:h").await, Ok(None));
+        assert_eq!(get("db:read:h".into()).await, b"$-1\r\n");
+    }
+
+    #[tokio::test]
+    async fn srem_removes_members_and_the_emptied_key() {
+        let members = |names: &[&str]| names.iter().map(|m| m.as_bytes().to_vec()).collect();
+        sadd("db:srem", members(&["a", "b", "c"])).await.unwrap();
+        assert_eq!(srem("db:srem", &members(&["a", "x", "a"])).await, Ok(1));
+        assert_eq!(srem("db:srem", &members(&["b", "c"])).await, Ok(2));
+        assert!(get_value("db:srem").await.is_none());
+        assert_eq!(srem("db:srem", &members(&["a"])).await, Ok(0));
+    }
+
+    #[tokio::test]
+    async fn hexpire_at_a_passed_time_deletes_the_fields() {
+        let field = |name: &str| name.as_bytes().to_vec();
+        let pairs = vec![(field("a"), field("v")), (field("b"), field("v"))];
+        hset("db:hexpire", pairs).await.unwrap();
+        let (a, b, missing) = (field("a"), field("b"), field("x"));
+
+        let later = unix_now_ms() + 60_000;
+        let fields = [a.clone(), missing];
+        let statuses = hexpire("db:hexpire", later, ExpireCondition::Always, &fields).await;
+        assert_eq!(statuses, Ok(vec![1, -2]));
+        // A passed time is sooner than any TTL, so LT allows it and GT doesn't
+        let passed = unix_now_ms() - 1;
+        let fields = [a.clone()];
+        let statuses = hexpire("db:hexpire", passed, ExpireCondition::Gt, &fields).await;
+        assert_eq!(statuses, Ok(vec![0]));
+        let fields = [a, b];
+        let statuses = hexpire("db:hexpire", passed, ExpireCondition::Lt, &fields).await;
+        assert_eq!(statuses, Ok(vec![2, 2]));
+        assert!(get_value("db:hexpire").await.is_none());
+    }
+
+    #[test]
+    fn random_sample_follows_count_semantics() {
+        let items: Vec<u32> = (0..10).collect();
+
+        let mut distinct = random_sample(items.clone(), 4).unwrap();
+        assert_eq!(distinct.len(), 4);
+        distinct.sort();
+        distinct.dedup();
+        assert_eq!(distinct.len(), 4);
+
+        assert_eq!(random_sample(items.clone(), 100).unwrap().len(), 10);
+        assert_eq!(random_sample(items.clone(), -25).unwrap().len(), 25);
+        assert!(random_sample(items.clone(), -25)
+            .unwrap()
+            .iter()
+            .all(|i| items.contains(i)));
+        assert!(random_sample(Vec::<u32>::new(), -5).unwrap().is_empty());
+    }
+
+    #[test]
+    fn random_sample_rejects_counts_it_cannot_hold() {
+        let items = vec![b"member".to_vec()];
+        assert_eq!(
+            random_sample(items.clone(), -i64::MAX).unwrap_err(),
+            OUT_OF_RANGE
+        );
+        assert_eq!(
+            random_sample(items, -10_000_000_000).unwrap_err(),
+            OUT_OF_RANGE
+        );
+    }
+
+    #[test]
+    fn ranges_count_from_the_end_and_clamp_to_the_elements() {
+        assert_eq!(normalize_range(0, -1, 5), Some((0, 4)));
+        assert_eq!(normalize_range(1, 3, 5), Some((1, 3)));
+        assert_eq!(normalize_range(-3, -2, 5), Some((2, 3)));
+        assert_eq!(normalize_range(2, 2, 5), Some((2, 2)));
+        // Ends past either side are clamped
+        assert_eq!(normalize_range(-100, 1, 5), Some((0, 1)));
+        assert_eq!(normalize_range(3, 100, 5), Some((3, 4)));
+        assert_eq!(normalize_range(i64::MIN, i64::MAX, 5), Some((0, 4)));
+        // Empty ranges
+        assert_eq!(normalize_range(3, 1, 5), None);
+        assert_eq!(normalize_range(-1, -2, 5), None);
+        assert_eq!(normalize_range(5, 10, 5), None);
+        assert_eq!(normalize_range(0, -6, 5), None);
+        assert_eq!(normalize_range(0, -1, 0), None);
+        assert_eq!(normalize_range(-1, 0, 0), None);
+        assert_eq!(normalize_range(i64::MIN, i64::MIN, 5), None);
+    }
+
+    #[test]
+    fn glob_patterns_match_like_stringmatchlen() {
+        for (pattern, string, expected) in [
+            (&b"h[a-c]llo"[..], &b"hallo"[..], true),
+            (b"h[a-c]llo", b"hcllo", true),
+            (b"h[a-c]llo", b"hello", false),
+            (b"h[c-a]llo", b"hbllo", true),
+            (b"h[^e]llo", b"hallo", true),
+            (b"h[^e]llo", b"hello", false),
+            (b"h[ae]llo", b"hello", true),
+            (b"h?llo", b"hxllo", true),
+            (b"h?llo", b"hllo", false),
+            (b"h*llo", b"hllo", true),
+            (b"h*llo", b"heeeello", true),
+            (b"h\\*llo", b"h*llo", true),
+            (b"h\\*llo", b"hello", false),
+            (b"h[\\]]llo", b"h]llo", true),
+            // Slashes are ordinary bytes, unlike in filesystem globs
+            (b"users/*", b"users/1/profile", true),
+            (b"users/?/profile", b"users/1/profile", true),
+            (b"*/profile", b"users/1/profile", true),
+            (b"users/*/name", b"users/1/profile", false),
+            // Raw bytes, not necessarily UTF-8
+            (b"\xff*", b"\xff\xfe", true),
+            (b"[\x80-\xff]", b"\xc0", true),
+            (b"*", b"", true),
+            (b"", b"", true),
+            (b"", b"a", false),
+            (b"a*b*c", b"aXbYbZc", true),
+            (b"a*b*c", b"aXbYbZ", false),
+            // An unclosed class runs to the end of the pattern
+            (b"h[el", b"he", true),
+        ] {
+            assert_eq!(
+                glob_match(pattern, string),
+                expected,
+                "{} against {}",
+                String::from_utf8_lossy(pattern),
+                String::from_utf8_lossy(string)
+            );
+        }
+    }
+
+    #[test]
+    fn scores_are_parsed_in_every_format_redis_accepts() {
+        for (input, score) in [
+            ("3", 3.0),
+            ("-2", -2.0),
+            ("1.5", 1.5),
+            (".5", 0.5),
+            ("3.0e3", 3000.0),
+            ("1E-2", 0.01),
+            ("inf", f64::INFINITY),
+            ("+inf", f64::INFINITY),
+            ("-inf", f64::NEG_INFINITY),
+        ] {
+            assert_eq!(parse_score(input.as_bytes()), Ok(score), "{}", input);
+        }
+        for input in ["", "nan", "-nan", "abc", "1.5x", " 1"] {
+            assert_eq!(
+                parse_score(input.as_bytes()),
+                Err("ERR value is not a valid float".to_string()),
+                "{:?}",
+                input
+            );
+        }
+    }
+
+    #[test]
+    fn scan_pages_keep_equal_hashes_together_and_resume_after_them() {
+        let page = |cursor, count| {
+            let items = vec![(9, 'e'), (3, 'b'), (7, 'c'), (1, 'a'), (7, 'd'), (12, 'f')];
+            let (next, mut page) = scan_page(items, cursor, count);
+            // Elements sharing a hash come back in no particular order
+            page.sort();
+            (next, page)
+        };
+        assert_eq!(page(0, 2), (7, vec!['a', 'b']));
+        assert_eq!(page(7, 1), (9, vec!['c', 'd']));
+        assert_eq!(page(0, 3), (9, vec!['a', 'b', 'c', 'd']));
+        assert_eq!(page(4, 10), (0, vec!['c', 'd', 'e', 'f']));
+        assert_eq!(page(10, 1), (0, vec!['f']));
+        assert_eq!(page(13, 5), (0, vec![]));
+        assert_eq!(page(0, 0), (1, vec![]));
     }
 }
@@ -0,0 +1,83 @@
+//! Geohash encoding for the GEO commands, which keep locations in sorted sets.
+//! Each score is a 52-bit integer interleaving 26 bits of longitude and latitude,
+//! the same encoding Redis uses, so geo sets are interchangeable with Redis'.
+
+const STEP: u32 = 26; // bits per coordinate
+const LON_MIN: f64 = -180.0;
+const LON_MAX: f64 = 180.0;
+/// The limits of EPSG:3857 (Web Mercator), beyond which positions can't be indexed
+const LAT_MIN: f64 = -85.05112878;
+const LAT_MAX: f64 = 85.05112878;
+/// Earth's quadratic mean radius in meters, as in Redis
+const EARTH_RADIUS: f64 = 6372797.560856;
+
+/// Whether a longitude/latitude pair can be stored
+pub fn valid(lon: f64, lat: f64) -> bool {
+    (LON_MIN..=LON_MAX).contains(&lon) && (LAT_MIN..=LAT_MAX).contains(&lat)
+}
+
+/// Encode a valid position as a sorted set score
+pub fn encode(lon: f64, lat: f64) -> f64 {
+    let scale = (1u64 << STEP) as f64;
+    let lat_bits = ((lat - LAT_MIN) / (LAT_MAX - LAT_MIN) * scale) as u64;
+    let lon_bits = ((lon - LON_MIN) / (LON_MAX - LON_MIN) * scale) as u64;
+    // Latitude takes the even bits and longitude the odd ones
+    (spread(lat_bits) | (spread(lon_bits) << 1)) as f64
+}
+
+/// Decode a score to the longitude/latitude at the center of its cell
+pub fn decode(score: f64) -> (f64, f64) {
+    let bits = score as u64;
+    let lat_bits = squash(bits);
+    let lon_bits = squash(bits >> 1);
+    let scale = (1u64 << STEP) as f64;
+    let center = |bits: u64, min: f64, max: f64| {
+        let low = min + bits as f64 / scale * (max - min);
+        let high = min + (bits + 1) as f64 / scale * (max - min);
+        ((low + high) / 2.0).clamp(min, max)
+    };
+    (
+        center(lon_bits, LON_MIN, LON_MAX),
+        center(lat_bits, LAT_MIN, LAT_MAX),
+    )
+}
+
+/// The great-circle distance in meters between two positions, by the haversine formula
+pub fn distance(lon1: f64, lat1: f64, lon2: f64, lat2: f64) -> f64 {
+    let (lat1, lat2) = (lat1.to_radians(), lat2.to_radians());
+    let u = ((lat2 - lat1) / 2.0).sin();
+    let v = ((lon2 - lon1).to_radians() / 2.0).sin();
+    let a = u * u + lat1.cos() * lat2.cos() * v * v;
+    2.0 * EARTH_RADIUS * a.sqrt().asin()
+}
+
+/// Meters per unit for the units GEO commands accept, None for unknown ones
+pub fn unit_factor(unit: &[u8]) -> Option<f64> {
+    match unit.to_ascii_lowercase().as_slice() {
+        b"m" => Some(1.0),
+        b"km" => Some(1000.0),
+        b"ft" => Some(0.3048),
+        b"mi" => Some(1609.34),
+        _ => None,
+    }
+}
+
+/// Move the low 32 bits of x to the even bit positions
+fn spread(x: u64) -> u64 {
+    let mut x = x & 0xffff_ffff;
+    x = (x | (x << 16)) & 0x0000_ffff_0000_ffff;
+    x = (x | (x << 8)) & 0x00ff_00ff_00ff_00ff;
+    x = (x | (x << 4)) & 0x0f0f_0f0f_0f0f_0f0f;
+    x = (x | (x << 2)) & 0x3333_3333_3333_3333;
+    (x | (x << 1)) & 0x5555_5555_5555_5555
+}
+
+/// The inverse of spread: gather the even bits of x
+fn squash(x: u64) -> u64 {
+    let mut x = x & 0x5555_5555_5555_5555;
+    x = (x | (x >> 1)) & 0x3333_3333_3333_3333;
+    x = (x | (x >> 2)) & 0x0f0f_0f0f_0f0f_0f0f;
+    x = (x | (x >> 4)) & 0x00ff_00ff_00ff_00ff;
+    x = (x | (x >> 8)) & 0x0000_ffff_0000_ffff;
+    (x | (x >> 16)) & 0x0000_0000_ffff_ffff
+}
@@ -0,0 +1,211 @@
+//! HyperLogLog cardinality estimation for PFADD/PFCOUNT/PFMERGE, stored in strings
+//! using Redis' layout so values survive DUMP/RESTORE and RDB files from Redis.
+//! This server always writes the dense encoding, but reads sparse ones as well.
+
+const MAGIC: &[u8; 4] = b"HYLL";
+const HEADER_LEN: usize = 16; // magic, encoding, 3 unused bytes, 8 byte cached cardinality
+const ENCODING_DENSE: u8 = 0;
+const ENCODING_SPARSE: u8 = 1;
+
+const P: u32 = 14; // bits of the hash that select a register
+const REGISTERS: usize = 1 << P;
+const REGISTER_BITS: usize = 6;
+const REGISTER_MAX: u8 = (1 << REGISTER_BITS) - 1;
+const Q: usize = 64 - P as usize; // bits left to count leading zeros in
+const DENSE_LEN: usize = HEADER_LEN + (REGISTERS * REGISTER_BITS).div_ceil(8);
+const ALPHA_INF: f64 = 0.721_347_520_444_481_7; // 1 / (2 ln 2)
+
+pub const WRONGTYPE_HLL: &str = "WRONGTYPE Key is not a valid HyperLogLog string value.";
+
+/// The registers of a HyperLogLog, one byte each while in memory
+pub struct Hll {
+    registers: Vec<u8>,
+}
+
+impl Hll {
+    pub fn new() -> Self {
+        Hll {
+            registers: vec![0; REGISTERS],
+        }
+    }
+
+    /// Decode a dense or sparse HyperLogLog string, or None if it isn't one
+    pub fn from_bytes(bytes: &[u8]) -> Option<Hll> {
+        if bytes.len() < HEADER_LEN || &bytes[..4] != MAGIC {
+            return None;
+        }
+        let data = &bytes[HEADER_LEN..];
+        match bytes[4] {
+            ENCODING_DENSE if bytes.len() == DENSE_LEN => {
+                let registers = (0..REGISTERS).map(|i| dense_get(data, i)).collect();
+                Some(Hll { registers })
+            }
+            ENCODING_SPARSE => decode_sparse(data),
+            _ => None,
+        }
+    }
+
+    /// Encode as a dense HyperLogLog with its cached cardinality marked stale
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = vec![0u8; DENSE_LEN];
+        bytes[..4].copy_from_slice(MAGIC);
+        bytes[4] = ENCODING_DENSE;
+        bytes[15] = 0x80; // the most significant bit of the cache invalidates it
+        let data = &mut bytes[HEADER_LEN..];
+        for (i, &value) in self.registers.iter().enumerate() {
+            dense_set(data, i, value);
+        }
+        bytes
+    }
+
+    /// Count an element; returns whether a register changed, and so maybe the estimate
+    pub fn add(&mut self, element: &[u8]) -> bool {
+        let hash = murmurhash64a(element, 0xadc83b19);
+        let index = (hash & (REGISTERS as u64 - 1)) as usize;
+        // Setting bit Q bounds the run of zeros, so the count fits a register
+        let rest = (hash >> P) | (1 << Q);
+        let count = rest.trailing_zeros() as u8 + 1;
+        if count > self.registers[index] {
+            self.registers[index] = count;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Fold another HyperLogLog into this one, which then estimates their union
+    pub fn merge(&mut self, other: &Hll) {
+        for (mine, theirs) in self.registers.iter_mut().zip(&other.registers) {
+            *mine = (*mine).max(*theirs);
+        }
+    }
+
+    /// Estimate the cardinality with the improved estimator from Otmar Ertl's
+    /// "New cardinality estimation algorithms for HyperLogLog sketches", as Redis does
+    pub fn count(&self) -> u64 {
+        let mut histogram = [0u32; 64];
+        for &value in &self.registers {
+            histogram[value as usize] += 1;
+        }
+        let m = REGISTERS as f64;
+        let mut z = m * tau((m - histogram[Q + 1] as f64) / m);
+        for j in (1..=Q).rev() {
+            z += histogram[j] as f64;
+            z *= 0.5;
+        }
+        z += m * sigma(histogram[0] as f64 / m);
+        (ALPHA_INF * m * m / z).round() as u64
+    }
+}
+
+/// Read register `index` from 6-bit registers packed least significant bit first
+fn dense_get(data: &[u8], index: usize) -> u8 {
+    let byte = index * REGISTER_BITS / 8;
+    let shift = (index * REGISTER_BITS) % 8;
+    let low = data[byte] >> shift;
+    let high = data
+        .get(byte + 1)
+        .map_or(0, |b| b.checked_shl(8 - shift as u32).unwrap_or(0));
+    (low | high) & REGISTER_MAX
+}
+
+fn dense_set(data: &mut [u8], index: usize, value: u8) {
+    let byte = index * REGISTER_BITS / 8;
+    let shift = (index * REGISTER_BITS) % 8;
+    data[byte] &= !(REGISTER_MAX << shift);
+    data[byte] |= value << shift;
+    if shift > 8 - REGISTER_BITS {
+        let spill = 8 - shift;
+        data[byte + 1] &= !(REGISTER_MAX >> spill);
+        data[byte + 1] |= value >> spill;
+    }
+}
+
+/// Expand the sparse encoding: runs of zero registers (ZERO, XZERO opcodes)
+/// and runs of registers sharing a small value (VAL opcode)
+fn decode_sparse(data: &[u8]) -> Option<Hll> {
+    let mut registers = Vec::with_capacity(REGISTERS);
+    let mut i = 0;
+    while i < data.len() {
+        let op = data[i];
+        let (value, run) = if op & 0xc0 == 0 {
+            (0, (op & 0x3f) as usize + 1)
+        } else if op & 0xc0 == 0x40 {
+            let low = *data.get(i + 1)?;
+            i += 1;
+            (0, ((((op & 0x3f) as usize) << 8) | low as usize) + 1)
+        } else {
+            (((op >> 2) & 0x1f) + 1, (op & 0x03) as usize + 1)
+        };
+        if registers.len() + run > REGISTERS {
+            return None;
+        }
+        registers.extend(std::iter::repeat_n(value, run));
+        i += 1;
+    }
+    (registers.len() == REGISTERS).then_some(Hll { registers })
+}
+
+fn sigma(mut x: f64) -> f64 {
+    if x == 1.0 {
+        return f64::INFINITY;
+    }
+    let mut y = 1.0;
+    let mut z = x;
+    loop {
+        x *= x;
+        let previous = z;
+        z += x * y;
+        y += y;
+        if previous == z {
+            return z;
+        }
+    }
+}
+
+fn tau(mut x: f64) -> f64 {
+    if x == 0.0 || x == 1.0 {
+        return 0.0;
+    }
+    let mut y = 1.0;
+    let mut z = 1.0 - x;
+    loop {
+        x = x.sqrt();
+        let previous = z;
+        y *= 0.5;
+        z -= (1.0 - x).powi(2) * y;
+        if previous == z {
+            return z / 3.0;
+        }
+    }
+}
+
+/// MurmurHash64A, the hash Redis uses to spread elements over registers
+fn murmurhash64a(key: &[u8], seed: u64) -> u64 {
+    const M: u64 = 0xc6a4a7935bd1e995;
+    const R: u32 = 47;
+    let mut h = seed ^ (key.len() as u64).wrapping_mul(M);
+
+    let mut chunks = key.chunks_exact(8);
+    for chunk in &mut chunks {
+        let mut k = u64::from_le_bytes(chunk.try_into().unwrap());
+        k = k.wrapping_mul(M);
+        k ^= k >> R;
+        k = k.wrapping_mul(M);
+        h ^= k;
+        h = h.wrapping_mul(M);
+    }
+
+    let tail = chunks.remainder();
+    if !tail.is_empty() {
+        for (i, &b) in tail.iter().enumerate() {
+            h ^= (b as u64) << (8 * i);
+        }
+        h = h.wrapping_mul(M);
+    }
+
+    h ^= h >> R;
+    h = h.wrapping_mul(M);
+    h ^= h >> R;
+    h
+}
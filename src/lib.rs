@@ -0,0 +1,101 @@
+//! A Redis server. `run` serves clients as the binary does; `Session` runs commands
+//! without a socket, for tests and fuzzing.
+use std::error::Error;
+#[macro_use]
+mod log;
+mod aof;
+mod commands;
+mod config;
+mod db;
+mod geo;
+mod hll;
+mod model;
+mod monitor;
+mod notify;
+mod output;
+mod pubsub;
+mod rdb;
+mod replication;
+pub mod resp;
+mod server;
+mod session;
+mod sha256;
+mod slowlog;
+mod stats;
+#[cfg(feature = "tls")]
+mod tls;
+
+pub use session::Session;
+
+/// Configure the server from the command line, load the dataset and serve clients
+pub async fn run() -> Result<(), Box<dyn Error>> {
+    config::parse_args_and_set_config();
+
+    // Like Redis, the append only file is the more complete record when there is one
+    let aof_path = config::get_aof_path();
+    if config::get_config().appendonly && aof_path.exists() {
+        info!("Loading append only file from: {}", aof_path.display());
+        match aof::load().await {
+            Ok(commands) => info!("Replayed {} commands from the append only file", commands),
+            Err(e) => warn!("Failed to load the append only file: {}", e),
+        }
+    } else {
+        if let Err(e) = load_rdb_file().await {
+            warn!("Failed to load RDB file: {}", e);
+            // Continue running even if RDB loading fails
+        }
+        // A new append only file starts with the dataset it is to add to
+        if config::get_config().appendonly {
+            if let Err(e) = aof::rewrite().await {
+                warn!("{}", e);
+            }
+        }
+    }
+
+    tokio::spawn(async {
+        // spawn background purging task
+        loop {
+            if db::active_expire_enabled() {
+                db::purge_expired_keys().await;
+            }
+            tokio::time::sleep(tokio::time::Duration::from_secs(60)).await;
+        }
+    });
+
+    tokio::spawn(async {
+        // check the save rules once a second, for automatic snapshots
+        let mut interval = tokio::time::interval(tokio::time::Duration::from_secs(1));
+        loop {
+            interval.tick().await;
+            rdb::bgsave_if_due();
+        }
+    });
+
+    let addr = format!("127.0.0.1:{}", config::get_config().port);
+    server::start(&addr).await
+}
+
+async fn load_rdb_file() -> Result<(), Box<dyn Error>> {
+    let rdb_path = config::get_dir().join(config::get_dbfilename());
+
+    if !std::path::Path::new(&rdb_path).exists() {
+        info!(
+            "No RDB file found at {}, starting with empty database",
+            rdb_path.display()
+        );
+        return Ok(());
+    }
+
+    info!("Loading RDB file from: {}", rdb_path.display());
+
+    // Parse the RDB file
+    let rdb_db = rdb::RdbParser::load(&rdb_path)?;
+    let keys_count = rdb_db.data.len();
+
+    // Load the data into your in-memory database
+    db::load_from_rdb(rdb_db).await?;
+
+    info!("Successfully loaded {} keys from RDB file", keys_count);
+
+    Ok(())
+}
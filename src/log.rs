@@ -0,0 +1,87 @@
+//! Leveled logging, filtered by the `loglevel` setting.
+//! Use the `debug!`, `verbose!`, `info!`, `warn!` and `error!` macros rather than calling `write`.
+use crate::config;
+use std::fmt;
+
+/// Log levels, from the most to the least chatty, as accepted by `loglevel`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    Debug,
+    Verbose,
+    Notice,
+    Warning,
+}
+
+impl Level {
+    pub fn parse(value: &str) -> Option<Level> {
+        match value.to_lowercase().as_str() {
+            "debug" => Some(Level::Debug),
+            "verbose" => Some(Level::Verbose),
+            "notice" => Some(Level::Notice),
+            "warning" => Some(Level::Warning),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Level::Debug => "debug",
+            Level::Verbose => "verbose",
+            Level::Notice => "notice",
+            Level::Warning => "warning",
+        }
+    }
+
+    /// Marker Redis puts in front of each line of this level
+    fn marker(self) -> char {
+        match self {
+            Level::Debug => '.',
+            Level::Verbose => '-',
+            Level::Notice => '*',
+            Level::Warning => '#',
+        }
+    }
+}
+
+/// Whether messages of this level are currently printed
+pub fn enabled(level: Level) -> bool {
+    level >= config::get_loglevel()
+}
+
+/// Print a message if its level is enabled; warnings go to stderr, everything else to stdout
+pub fn write(level: Level, args: fmt::Arguments) {
+    if !enabled(level) {
+        return;
+    }
+    if level == Level::Warning {
+        eprintln!("{} {}", level.marker(), args);
+    } else {
+        println!("{} {}", level.marker(), args);
+    }
+}
+
+#[macro_export]
+macro_rules! debug {
+    ($($arg:tt)*) => { $crate::log::write($crate::log::Level::Debug, format_args!($($arg)*)) };
+}
+
+#[macro_export]
+macro_rules! verbose {
+    ($($arg:tt)*) => { $crate::log::write($crate::log::Level::Verbose, format_args!($($arg)*)) };
+}
+
+#[macro_export]
+macro_rules! info {
+    ($($arg:tt)*) => { $crate::log::write($crate::log::Level::Notice, format_args!($($arg)*)) };
+}
+
+#[macro_export]
+macro_rules! warn {
+    ($($arg:tt)*) => { $crate::log::write($crate::log::Level::Warning, format_args!($($arg)*)) };
+}
+
+/// Errors are logged at the warning level, the highest one there is
+#[macro_export]
+macro_rules! error {
+    ($($arg:tt)*) => { $crate::log::write($crate::log::Level::Warning, format_args!($($arg)*)) };
+}
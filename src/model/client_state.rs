@@ -0,0 +1,62 @@
+use crate::monitor;
+use crate::output::OutputSender;
+use crate::pubsub;
+use crate::resp::Frame;
+use std::collections::HashSet;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+static NEXT_CLIENT_ID: AtomicU64 = AtomicU64::new(1);
+
+/// Per-connection state, owned by the connection handler and passed to every command.
+#[derive(Debug, Default)]
+pub struct ClientState {
+    pub multi: Option<Vec<(String, Vec<Frame>)>>, // queued (command, args) while in MULTI
+    pub in_exec: bool, // running the commands MULTI queued, during which nothing blocks
+    pub name: Option<String>,
+    pub authenticated: bool,
+    pub id: u64,
+    pub addr: String,                // peer address, as shown by SLOWLOG
+    pub protocol: u8,                // RESP version negotiated with HELLO (2 or 3)
+    pub db_index: usize,             // database chosen with SELECT
+    pub master_link: bool, // the connection a replica applies its master's commands through
+    pub replica: bool,     // the peer is a replica of ours, set once it PSYNCs
+    pub replica_listening_port: u16, // announced with REPLCONF listening-port
+    pub no_evict: bool,    // CLIENT NO-EVICT, recorded for when eviction accounts for clients
+    pub no_touch: bool,    // CLIENT NO-TOUCH: commands don't update the access time of keys
+    pub monitor: bool,     // MONITOR: receives every command the server runs
+    pub channels: HashSet<Vec<u8>>,
+    pub patterns: HashSet<Vec<u8>>,
+    pub push_tx: Option<OutputSender>, // output queue of the connection, for pub/sub messages
+}
+
+impl ClientState {
+    pub fn new() -> Self {
+        ClientState {
+            id: NEXT_CLIENT_ID.fetch_add(1, Ordering::Relaxed),
+            protocol: 2,
+            ..ClientState::default()
+        }
+    }
+
+    /// Return the connection to the state it had right after connecting, keeping its id
+    pub fn reset(&mut self) {
+        pubsub::unsubscribe_all(self);
+        monitor::remove(self);
+        *self = ClientState {
+            id: self.id,
+            addr: std::mem::take(&mut self.addr),
+            protocol: 2,
+            push_tx: self.push_tx.take(),
+            ..ClientState::default()
+        };
+    }
+
+    /// Number of channels and patterns the connection is subscribed to
+    pub fn subscriber_count(&self) -> usize {
+        self.channels.len() + self.patterns.len()
+    }
+
+    pub fn resp3(&self) -> bool {
+        self.protocol == 3
+    }
+}
@@ -1 +1,2 @@
+pub mod client_state;
 pub mod redis_value;
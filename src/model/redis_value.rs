@@ -1,9 +1,11 @@
 use std::collections::HashMap;
+use tokio::time::Instant;
 
 #[derive(Debug, Clone)]
 #[allow(dead_code)]
 pub enum RedisValue {
     String(Vec<u8>),
+    RawString(Vec<u8>), // A string edited in place (APPEND, SETRANGE), always raw-encoded
     Integer(i64),
     Float(f64),
     Boolean(bool),
@@ -11,9 +13,110 @@ pub enum RedisValue {
     List(Vec<Vec<u8>>),
     Set(Vec<Vec<u8>>),
     SortedSet(Vec<(Vec<u8>, f64)>), // (member, score)
-    Hash(HashMap<Vec<u8>, Vec<u8>>),
+    Hash(HashValue),
+    Stream(StreamValue),
     Zipmap(Vec<u8>),    // Raw zipmap encoding
     Ziplist(Vec<u8>),   // Raw ziplist encoding
     Intset(Vec<u8>),    // Raw intset encoding
     Quicklist(Vec<u8>), // Raw quicklist encoding
 }
+
+/// The fields of a hash, alongside the expiry times of the fields given one by HEXPIRE
+#[derive(Debug, Clone, Default)]
+pub struct HashValue {
+    pub fields: HashMap<Vec<u8>, Vec<u8>>,
+    pub expiries: HashMap<Vec<u8>, Instant>,
+}
+
+/// A stream entry ID: a Unix time in milliseconds and a sequence number within it
+pub type StreamId = (u64, u64);
+
+/// A stream entry: its ID and its field/value pairs, in the order they were given
+pub type StreamEntry = (StreamId, Vec<(Vec<u8>, Vec<u8>)>);
+
+/// The entries of a stream in ID order, alongside the last ID it handed out,
+/// which new entries must exceed even once the entry that had it is gone
+#[derive(Debug, Clone, Default)]
+pub struct StreamValue {
+    pub entries: Vec<StreamEntry>,
+    pub last_id: StreamId,
+}
+
+impl From<HashMap<Vec<u8>, Vec<u8>>> for HashValue {
+    fn from(fields: HashMap<Vec<u8>, Vec<u8>>) -> Self {
+        HashValue {
+            fields,
+            expiries: HashMap::new(),
+        }
+    }
+}
+
+impl HashValue {
+    /// Set a field, clearing any TTL it had; returns whether the field is new
+    pub fn insert(&mut self, field: Vec<u8>, value: Vec<u8>) -> bool {
+        self.expiries.remove(&field);
+        self.fields.insert(field, value).is_none()
+    }
+
+    /// Remove a field and its TTL; returns whether it existed
+    pub fn remove(&mut self, field: &[u8]) -> bool {
+        self.expiries.remove(field);
+        self.fields.remove(field).is_some()
+    }
+
+    /// Drop the fields whose TTL has passed, returning how many there were
+    pub fn remove_expired(&mut self) -> usize {
+        if self.expiries.is_empty() {
+            return 0;
+        }
+        let now = Instant::now();
+        let expired: Vec<Vec<u8>> = self
+            .expiries
+            .iter()
+            .filter(|(_, &at)| now > at)
+            .map(|(field, _)| field.clone())
+            .collect();
+        for field in &expired {
+            self.remove(field);
+        }
+        expired.len()
+    }
+
+    /// Whether every field's TTL has passed, leaving the hash as good as missing
+    pub fn all_expired(&self) -> bool {
+        let now = Instant::now();
+        self.fields
+            .keys()
+            .all(|field| self.expiries.get(field).is_some_and(|&at| now > at))
+    }
+}
+
+impl RedisValue {
+    /// The raw bytes of a string value, or None for any other type
+    pub fn string_bytes(&self) -> Option<Vec<u8>> {
+        match self {
+            RedisValue::String(s) | RedisValue::RawString(s) => Some(s.clone()),
+            RedisValue::Integer(i) => Some(i.to_string().into_bytes()),
+            RedisValue::Float(f) => Some(f.to_string().into_bytes()),
+            RedisValue::Boolean(b) => Some(b.to_string().into_bytes()),
+            _ => None,
+        }
+    }
+
+    /// Name of the value's type as reported by TYPE and matched by SCAN's TYPE option
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            RedisValue::String(_)
+            | RedisValue::RawString(_)
+            | RedisValue::Integer(_)
+            | RedisValue::Float(_)
+            | RedisValue::Boolean(_) => "string",
+            RedisValue::Null => "none",
+            RedisValue::List(_) | RedisValue::Quicklist(_) | RedisValue::Ziplist(_) => "list",
+            RedisValue::Set(_) | RedisValue::Intset(_) => "set",
+            RedisValue::SortedSet(_) => "zset",
+            RedisValue::Hash(_) | RedisValue::Zipmap(_) => "hash",
+            RedisValue::Stream(_) => "stream",
+        }
+    }
+}
@@ -0,0 +1,89 @@
+//! Connections in MONITOR mode, which receive a line for every command the server runs
+use crate::model::client_state::ClientState;
+use crate::output::OutputSender;
+use crate::resp::Frame;
+use once_cell::sync::Lazy;
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+/// Output queues of the monitoring connections, keyed by client id
+static MONITORS: Lazy<Mutex<HashMap<u64, OutputSender>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// Put the connection in monitor mode. Returns false if it already was.
+pub fn add(state: &mut ClientState) -> bool {
+    let Some(tx) = state.push_tx.clone() else {
+        return false;
+    };
+    if state.monitor {
+        return false;
+    }
+    state.monitor = true;
+    MONITORS.lock().unwrap().insert(state.id, tx);
+    true
+}
+
+/// Take the connection out of monitor mode, when it disconnects or RESETs
+pub fn remove(state: &mut ClientState) {
+    if std::mem::take(&mut state.monitor) {
+        MONITORS.lock().unwrap().remove(&state.id);
+    }
+}
+
+/// Number of connections in monitor mode
+pub fn count() -> usize {
+    MONITORS.lock().unwrap().len()
+}
+
+/// Send a command run by client `origin` to every monitor but the client itself, as
+/// `<unix time> [<db> <addr>] "<command>" "<arg>" ...`
+pub fn feed(origin: u64, db: usize, addr: &str, cmd: &str, args: &[Frame]) {
+    let monitors = MONITORS.lock().unwrap();
+    if monitors.keys().all(|&id| id == origin) {
+        return;
+    }
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default();
+    let mut line = format!(
+        "{}.{:06} [{} {}] {}",
+        now.as_secs(),
+        now.subsec_micros(),
+        db,
+        addr,
+        quote(cmd.as_bytes())
+    );
+    for arg in args {
+        if let Frame::BulkString(Some(bytes)) = arg {
+            line.push(' ');
+            line.push_str(&quote(bytes));
+        }
+    }
+    let message = Frame::SimpleString(line).encode();
+    for (&id, tx) in monitors.iter() {
+        // A closed receiver means the client is disconnecting and will remove itself
+        if id != origin {
+            let _ = tx.send(message.clone());
+        }
+    }
+}
+
+/// Quote an argument the way Redis prints them, escaping anything unprintable
+fn quote(bytes: &[u8]) -> String {
+    let mut out = String::with_capacity(bytes.len() + 2);
+    out.push('"');
+    for &b in bytes {
+        match b {
+            b'\\' => out.push_str("\\\\"),
+            b'"' => out.push_str("\\\""),
+            b'\n' => out.push_str("\\n"),
+            b'\r' => out.push_str("\\r"),
+            b'\t' => out.push_str("\\t"),
+            0x07 => out.push_str("\\a"),
+            0x08 => out.push_str("\\b"),
+            b if b.is_ascii_graphic() || b == b' ' => out.push(b as char),
+            b => out.push_str(&format!("\\x{:02x}", b)),
+        }
+    }
+    out.push('"');
+    out
+}
@@ -0,0 +1,80 @@
+//! Keyspace notifications: pub/sub messages announcing changes to keys, enabled by
+//! the classes of events listed in `notify-keyspace-events`, using Redis' flag letters.
+use crate::config;
+use crate::pubsub;
+
+pub const KEYSPACE: u32 = 1 << 0; // K: publish to __keyspace@<db>__:<key>
+pub const KEYEVENT: u32 = 1 << 1; // E: publish to __keyevent@<db>__:<event>
+pub const GENERIC: u32 = 1 << 2; // g: DEL, EXPIRE, RENAME, ...
+pub const STRING: u32 = 1 << 3; // $
+pub const LIST: u32 = 1 << 4; // l
+pub const SET: u32 = 1 << 5; // s
+pub const HASH: u32 = 1 << 6; // h
+pub const ZSET: u32 = 1 << 7; // z
+pub const EXPIRED: u32 = 1 << 8; // x: a key was deleted because its TTL passed
+pub const EVICTED: u32 = 1 << 9; // e: a key was evicted for maxmemory, which none are yet
+pub const STREAM: u32 = 1 << 10; // t
+pub const KEY_MISS: u32 = 1 << 11; // m: a command read a missing key
+pub const NEW: u32 = 1 << 12; // n: a key was created
+/// The classes "A" enables, which leave out the noisy key-miss and new-key events
+const ALL: u32 = GENERIC | STRING | LIST | SET | HASH | ZSET | EXPIRED | EVICTED | STREAM;
+
+const FLAG_LETTERS: &[(char, u32)] = &[
+    ('g', GENERIC),
+    ('$', STRING),
+    ('l', LIST),
+    ('s', SET),
+    ('h', HASH),
+    ('z', ZSET),
+    ('x', EXPIRED),
+    ('e', EVICTED),
+    ('t', STREAM),
+    ('K', KEYSPACE),
+    ('E', KEYEVENT),
+    ('m', KEY_MISS),
+    ('n', NEW),
+];
+
+/// Parse a `notify-keyspace-events` value, or None if it has an unknown letter
+pub fn parse_flags(value: &str) -> Option<u32> {
+    value.chars().try_fold(0, |flags, c| {
+        if c == 'A' {
+            return Some(flags | ALL);
+        }
+        let (_, flag) = FLAG_LETTERS.iter().find(|(letter, _)| *letter == c)?;
+        Some(flags | flag)
+    })
+}
+
+/// Render flags back to letters, as CONFIG GET reports them
+pub fn flags_to_string(flags: u32) -> String {
+    let mut out = String::new();
+    let mut rest = flags;
+    if flags & ALL == ALL {
+        out.push('A');
+        rest &= !ALL;
+    }
+    for &(letter, flag) in FLAG_LETTERS {
+        if rest & flag != 0 {
+            out.push(letter);
+        }
+    }
+    out
+}
+
+/// Announce that `event` happened to `key` in database `db`, if notifications
+/// for the event's class are enabled
+pub fn notify(class: u32, event: &str, key: &str, db: usize) {
+    let flags = config::get_notify_keyspace_events();
+    if flags & class == 0 {
+        return;
+    }
+    if flags & KEYSPACE != 0 {
+        let channel = format!("__keyspace@{}__:{}", db, key);
+        pubsub::publish(channel.as_bytes(), event.as_bytes());
+    }
+    if flags & KEYEVENT != 0 {
+        let channel = format!("__keyevent@{}__:{}", db, event);
+        pubsub::publish(channel.as_bytes(), key.as_bytes());
+    }
+}
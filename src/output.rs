@@ -0,0 +1,118 @@
+//! The queue of output waiting to be written to a connection: replies, pub/sub messages,
+//! MONITOR lines and the replication stream. The bytes queued are counted so the
+//! connection can be closed once they pass its `client-output-buffer-limit`.
+use crate::config::{self, ClientClass};
+use std::sync::atomic::{AtomicBool, AtomicU8, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::sync::mpsc::error::SendError;
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use tokio::sync::Notify;
+use tokio::time::Instant;
+
+/// State shared by both ends of a connection's queue
+#[derive(Debug)]
+struct Shared {
+    pending: AtomicUsize, // bytes queued but not yet taken by the connection
+    class: AtomicU8,      // ClientClass whose limit applies
+    soft_since: Mutex<Option<Instant>>, // when pending last went over the soft limit
+    over_limit: AtomicBool,
+    limit_reached: Notify,
+}
+
+/// The sending half, cloned into everything that writes to the connection
+#[derive(Debug, Clone)]
+pub struct OutputSender {
+    tx: UnboundedSender<Vec<u8>>,
+    shared: Arc<Shared>,
+}
+
+/// The receiving half, drained by the connection task alone
+#[derive(Debug)]
+pub struct OutputReceiver {
+    rx: UnboundedReceiver<Vec<u8>>,
+    shared: Arc<Shared>,
+}
+
+pub fn channel() -> (OutputSender, OutputReceiver) {
+    let (tx, rx) = mpsc::unbounded_channel();
+    let shared = Arc::new(Shared {
+        pending: AtomicUsize::new(0),
+        class: AtomicU8::new(ClientClass::Normal as u8),
+        soft_since: Mutex::new(None),
+        over_limit: AtomicBool::new(false),
+        limit_reached: Notify::new(),
+    });
+    (
+        OutputSender {
+            tx,
+            shared: shared.clone(),
+        },
+        OutputReceiver { rx, shared },
+    )
+}
+
+impl OutputSender {
+    /// Queue bytes for the connection. Fails if the connection is gone or the bytes
+    /// take it over its output buffer limit, in which case it is about to be closed.
+    pub fn send(&self, bytes: Vec<u8>) -> Result<(), SendError<Vec<u8>>> {
+        if self.shared.over_limit.load(Ordering::SeqCst) {
+            return Err(SendError(bytes));
+        }
+        let pending = self.shared.pending.fetch_add(bytes.len(), Ordering::SeqCst) + bytes.len();
+        if self.exceeds_limit(pending as u64) {
+            self.shared.over_limit.store(true, Ordering::SeqCst);
+            self.shared.limit_reached.notify_one();
+            return Err(SendError(bytes));
+        }
+        self.tx.send(bytes)
+    }
+
+    /// Choose which class of limits applies, as the connection subscribes or becomes a replica
+    pub fn set_class(&self, class: ClientClass) {
+        self.shared.class.store(class as u8, Ordering::SeqCst);
+    }
+
+    /// Resolves once the connection has gone over its output buffer limit
+    pub async fn limit_reached(&self) {
+        while !self.shared.over_limit.load(Ordering::SeqCst) {
+            self.shared.limit_reached.notified().await;
+        }
+    }
+
+    /// Like Redis, the hard limit closes the connection at once, while the soft
+    /// limit only does when it stays exceeded for longer than its number of seconds
+    fn exceeds_limit(&self, pending: u64) -> bool {
+        let class = ClientClass::from_u8(self.shared.class.load(Ordering::SeqCst));
+        let limit = config::get_output_buffer_limit(class);
+        if limit.hard > 0 && pending >= limit.hard {
+            return true;
+        }
+        let mut soft_since = self.shared.soft_since.lock().unwrap();
+        if limit.soft == 0 || pending < limit.soft {
+            *soft_since = None;
+            return false;
+        }
+        match *soft_since {
+            None => {
+                *soft_since = Some(Instant::now());
+                false
+            }
+            Some(since) => since.elapsed().as_secs() > limit.soft_seconds,
+        }
+    }
+}
+
+impl OutputReceiver {
+    pub async fn recv(&mut self) -> Option<Vec<u8>> {
+        let bytes = self.rx.recv().await?;
+        self.shared.pending.fetch_sub(bytes.len(), Ordering::SeqCst);
+        Some(bytes)
+    }
+
+    /// Take the next queued bytes without waiting
+    pub fn try_recv(&mut self) -> Option<Vec<u8>> {
+        let bytes = self.rx.try_recv().ok()?;
+        self.shared.pending.fetch_sub(bytes.len(), Ordering::SeqCst);
+        Some(bytes)
+    }
+}
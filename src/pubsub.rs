@@ -0,0 +1,182 @@
+//! Channel and pattern subscriptions shared by every connection
+use crate::db;
+use crate::model::client_state::ClientState;
+use crate::output::OutputSender;
+use crate::resp::Frame;
+use crate::stats;
+use once_cell::sync::Lazy;
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+/// A subscribed connection: the sender half of its message channel, and the RESP
+/// version it negotiated, which decides how messages are framed
+struct Subscriber {
+    tx: OutputSender,
+    protocol: u8,
+}
+
+/// Subscribers of a channel or pattern, keyed by client id
+type Subscribers = HashMap<u64, Subscriber>;
+
+static CHANNELS: Lazy<Mutex<HashMap<Vec<u8>, Subscribers>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+static PATTERNS: Lazy<Mutex<HashMap<Vec<u8>, Subscribers>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// Subscribe the connection to a channel (or a pattern if `pattern` is set).
+/// Returns false if it was already subscribed.
+pub fn subscribe(state: &mut ClientState, name: &[u8], pattern: bool) -> bool {
+    let Some(tx) = state.push_tx.clone() else {
+        return false;
+    };
+    let (subscriptions, registry) = if pattern {
+        (&mut state.patterns, &PATTERNS)
+    } else {
+        (&mut state.channels, &CHANNELS)
+    };
+    if !subscriptions.insert(name.to_vec()) {
+        return false;
+    }
+    registry
+        .lock()
+        .unwrap()
+        .entry(name.to_vec())
+        .or_default()
+        .insert(
+            state.id,
+            Subscriber {
+                tx,
+                protocol: state.protocol,
+            },
+        );
+    true
+}
+
+/// Record a protocol change (HELLO) for the connection's existing subscriptions
+pub fn update_protocol(state: &ClientState) {
+    for (names, registry) in [(&state.channels, &CHANNELS), (&state.patterns, &PATTERNS)] {
+        let mut registry = registry.lock().unwrap();
+        for name in names {
+            if let Some(subscriber) = registry.get_mut(name).and_then(|s| s.get_mut(&state.id)) {
+                subscriber.protocol = state.protocol;
+            }
+        }
+    }
+}
+
+/// Frame a pub/sub message or confirmation: a push in RESP3, a plain array in RESP2
+pub fn message_frame(items: Vec<Frame>, resp3: bool) -> Frame {
+    if resp3 {
+        Frame::Push(Some(items))
+    } else {
+        Frame::Array(Some(items))
+    }
+}
+
+/// Send a message to every subscriber, encoded for its protocol.
+/// Returns how many received it.
+fn deliver(subscribers: &Subscribers, items: Vec<Frame>) -> i64 {
+    let resp2 = message_frame(items.clone(), false).encode();
+    let resp3 = message_frame(items, true).encode();
+    let mut receivers = 0;
+    for subscriber in subscribers.values() {
+        let payload = if subscriber.protocol == 3 {
+            &resp3
+        } else {
+            &resp2
+        };
+        // A closed receiver means the client is disconnecting and will unsubscribe itself
+        if subscriber.tx.send(payload.clone()).is_ok() {
+            receivers += 1;
+        }
+    }
+    receivers
+}
+
+/// Unsubscribe the connection from a channel (or a pattern if `pattern` is set).
+/// Returns false if it wasn't subscribed.
+pub fn unsubscribe(state: &mut ClientState, name: &[u8], pattern: bool) -> bool {
+    let (subscriptions, registry) = if pattern {
+        (&mut state.patterns, &PATTERNS)
+    } else {
+        (&mut state.channels, &CHANNELS)
+    };
+    if !subscriptions.remove(name) {
+        return false;
+    }
+    let mut registry = registry.lock().unwrap();
+    if let Some(subscribers) = registry.get_mut(name) {
+        subscribers.remove(&state.id);
+        if subscribers.is_empty() {
+            registry.remove(name);
+        }
+    }
+    true
+}
+
+/// Drop every subscription of the connection, e.g. when it disconnects or runs RESET
+pub fn unsubscribe_all(state: &mut ClientState) {
+    for channel in state.channels.clone() {
+        unsubscribe(state, &channel, false);
+    }
+    for pattern in state.patterns.clone() {
+        unsubscribe(state, &pattern, true);
+    }
+}
+
+/// Deliver a message to every subscriber of the channel and of any matching pattern.
+/// Returns the number of subscriptions that received it.
+pub fn publish(channel: &[u8], message: &[u8]) -> i64 {
+    let bulk = |b: &[u8]| Frame::BulkString(Some(b.to_vec()));
+    let mut receivers = 0;
+
+    if let Some(subscribers) = CHANNELS.lock().unwrap().get(channel) {
+        receivers += deliver(
+            subscribers,
+            vec![bulk(b"message"), bulk(channel), bulk(message)],
+        );
+    }
+
+    for (pattern, subscribers) in PATTERNS.lock().unwrap().iter() {
+        if !db::glob_match(pattern, channel) {
+            continue;
+        }
+        receivers += deliver(
+            subscribers,
+            vec![
+                bulk(b"pmessage"),
+                bulk(pattern),
+                bulk(channel),
+                bulk(message),
+            ],
+        );
+    }
+    stats::incr_by(&stats::TOTAL_PUBSUB_MESSAGES, receivers as u64);
+    receivers
+}
+
+/// Channels with at least one subscriber, optionally filtered by a glob pattern
+pub fn channels(pattern: Option<&str>) -> Vec<Vec<u8>> {
+    CHANNELS
+        .lock()
+        .unwrap()
+        .keys()
+        .filter(|channel| pattern.is_none_or(|p| db::glob_match(p.as_bytes(), channel)))
+        .cloned()
+        .collect()
+}
+
+/// Number of subscribers of a channel, not counting pattern subscriptions
+pub fn numsub(channel: &[u8]) -> usize {
+    CHANNELS.lock().unwrap().get(channel).map_or(0, |s| s.len())
+}
+
+/// Number of channels with at least one subscriber
+pub fn numchannels() -> usize {
+    CHANNELS.lock().unwrap().len()
+}
+
+/// Number of distinct patterns with at least one subscriber
+pub fn numpat() -> usize {
+    PATTERNS.lock().unwrap().len()
+}
@@ -1,40 +1,70 @@
-use crate::model::redis_value::RedisValue;
+use crate::model::redis_value::{HashValue, RedisValue, StreamEntry, StreamId, StreamValue};
 use crate::{config, db};
 use crc64::crc64;
+use once_cell::sync::Lazy;
+use std::cell::Cell;
 use std::collections::HashMap;
 use std::fs::File;
 use std::io::{self, BufReader, Read, Write};
 use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use tokio::time::Instant;
 
 #[derive(Debug)]
 pub struct RedisEntry {
     pub value: RedisValue,
     pub expiry: Option<u64>, // Unix timestamp in milliseconds
+    pub db: usize,           // database selected by the preceding 0xFE opcode
 }
 
 #[derive(Debug)]
 pub struct RdbDatabase {
-    pub data: HashMap<String, RedisEntry>,
+    pub data: Vec<(String, RedisEntry)>,
 }
 
 pub struct RdbParser;
 
+/// Newest RDB version the loader understands (Redis 7.4)
+const RDB_MAX_VERSION: u16 = 12;
+/// RDB version stamped into DUMP payloads, matching the version written by save
+const DUMP_RDB_VERSION: u16 = 11;
+/// Version bytes of the files this server wrote before it used Redis's layout: binary
+/// rather than four ASCII digits, with 32-bit lengths little-endian rather than big-endian
+const LEGACY_VERSION: [u8; 4] = [0, 0, 0, 11];
+
+thread_local! {
+    /// Set while such a file is being parsed
+    static LEGACY_LENGTHS: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Writes since the last successful save, compared against the `save` rules
+static CHANGES_SINCE_SAVE: AtomicU64 = AtomicU64::new(0);
+/// Unix time in seconds of the last successful save, or of startup
+static LAST_SAVE: Lazy<AtomicU64> = Lazy::new(|| AtomicU64::new(unix_secs()));
+static BGSAVE_IN_PROGRESS: AtomicBool = AtomicBool::new(false);
+static LAST_BGSAVE_OK: AtomicBool = AtomicBool::new(true);
+static LAST_BGSAVE_TRY: AtomicU64 = AtomicU64::new(0);
+/// Seconds to wait after a failed background save before a rule may trigger another
+const BGSAVE_RETRY_DELAY: u64 = 5;
+
 impl RdbParser {
     pub fn load<P: AsRef<Path>>(path: P) -> io::Result<RdbDatabase> {
         let file = match File::open(&path) {
             Ok(f) => f,
-            Err(_) => {
-                return Ok(RdbDatabase {
-                    data: HashMap::new(),
-                })
-            }
+            Err(_) => return Ok(RdbDatabase { data: Vec::new() }),
         };
         let mut reader = BufReader::new(file);
-        Self::parse(&mut reader)
+        Self::parse(&mut reader, config::get_config().rdb_load_lenient)
+    }
+
+    /// Load an RDB payload from any reader, such as the one a master sends to its replicas
+    pub fn load_from_reader<R: Read>(reader: &mut R) -> io::Result<RdbDatabase> {
+        Self::parse(reader, config::get_config().rdb_load_lenient)
     }
 
-    fn parse<R: Read>(reader: &mut R) -> io::Result<RdbDatabase> {
+    /// Parse an RDB stream. In lenient mode a file that ends in the middle of an
+    /// entry yields the keys read so far instead of an error.
+    fn parse<R: Read>(reader: &mut R, lenient: bool) -> io::Result<RdbDatabase> {
         let mut magic = [0u8; 5];
         reader.read_exact(&mut magic)?;
         if &magic != b"REDIS" {
@@ -46,211 +76,36 @@ impl RdbParser {
 
         let mut version = [0u8; 4];
         reader.read_exact(&mut version)?;
-        if version != [48, 48, 49, 49] {
+        // Accept every version up to Redis 7.4, which covers the listpack encodings,
+        // and the files this server wrote before it used Redis's layout
+        let legacy = version == LEGACY_VERSION;
+        let version_number = std::str::from_utf8(&version)
+            .ok()
+            .and_then(|v| v.parse::<u16>().ok());
+        if !legacy && !version_number.is_some_and(|v| (1..=RDB_MAX_VERSION).contains(&v)) {
             return Err(io::Error::new(
                 io::ErrorKind::InvalidData,
                 "Unsupported RDB version",
             ));
         }
 
-        let mut data = HashMap::new();
-        let mut buf = [0u8; 1];
-        let mut current_expiry: Option<u64> = None;
-
+        let mut data = Vec::new();
         let mut file_bytes: Vec<u8> = Vec::new();
         file_bytes.extend_from_slice(&magic);
         file_bytes.extend_from_slice(&version);
 
-        loop {
-            match reader.read_exact(&mut buf) {
-                Ok(_) => {
-                    file_bytes.push(buf[0]);
-                    let opcode = buf[0];
-                    match opcode {
-                        0xFA => {
-                            let _key = read_length_prefixed_string(reader, &mut file_bytes)?;
-                            let _value = read_length_prefixed_string(reader, &mut file_bytes)?;
-                            // Metadata - don't reset expiry
-                        }
-                        0xFB => {
-                            let _ht_size = read_rdb_length(reader, &mut file_bytes)?;
-                            let _expire_ht_size = read_rdb_length(reader, &mut file_bytes)?;
-                            // Resize hint - don't reset expiry
-                        }
-                        0xFE => {
-                            let _db_number = read_rdb_length(reader, &mut file_bytes)?;
-                            // Database selector - don't reset expiry
-                        }
-                        0xFD => {
-                            // Expiry in seconds
-                            let mut expiry_buf = [0u8; 4];
-                            reader.read_exact(&mut expiry_buf)?;
-                            file_bytes.extend_from_slice(&expiry_buf);
-                            let expiry_seconds = u32::from_le_bytes(expiry_buf) as u64;
-                            current_expiry = Some(expiry_seconds * 1000); // Convert to milliseconds
-                        }
-                        0xFC => {
-                            // Expiry in milliseconds
-                            let mut expiry_buf = [0u8; 8];
-                            reader.read_exact(&mut expiry_buf)?;
-                            file_bytes.extend_from_slice(&expiry_buf);
-                            current_expiry = Some(u64::from_le_bytes(expiry_buf));
-                        }
-                        0xFF => {
-                            // End of RDB file
-                            break;
-                        }
-                        // Value types
-                        0x00 => {
-                            // String Encoding
-                            let key = read_length_prefixed_string(reader, &mut file_bytes)?;
-                            let value = read_length_prefixed_bytes(reader, &mut file_bytes)?;
-                            data.insert(
-                                key,
-                                RedisEntry {
-                                    value: RedisValue::String(value),
-                                    expiry: current_expiry,
-                                },
-                            );
-                            current_expiry = None;
-                        }
-                        0x01 => {
-                            // List Encoding
-                            let key = read_length_prefixed_string(reader, &mut file_bytes)?;
-                            let len = read_rdb_length(reader, &mut file_bytes)?;
-                            let mut items = Vec::with_capacity(len as usize);
-                            for _ in 0..len {
-                                let item = read_length_prefixed_bytes(reader, &mut file_bytes)?;
-                                items.push(item);
-                            }
-                            data.insert(
-                                key,
-                                RedisEntry {
-                                    value: RedisValue::List(items),
-                                    expiry: current_expiry,
-                                },
-                            );
-                            current_expiry = None;
-                        }
-                        0x02 => {
-                            // Set Encoding
-                            let key = read_length_prefixed_string(reader, &mut file_bytes)?;
-                            let len = read_rdb_length(reader, &mut file_bytes)?;
-                            let mut items = Vec::with_capacity(len as usize);
-                            for _ in 0..len {
-                                let item = read_length_prefixed_bytes(reader, &mut file_bytes)?;
-                                items.push(item);
-                            }
-                            data.insert(
-                                key,
-                                RedisEntry {
-                                    value: RedisValue::Set(items),
-                                    expiry: current_expiry,
-                                },
-                            );
-                            current_expiry = None;
-                        }
-                        0x03 => {
-                            // Sorted Set in Ziplist Encoding
-                            let key = read_length_prefixed_string(reader, &mut file_bytes)?;
-                            let ziplist = read_length_prefixed_bytes(reader, &mut file_bytes)?;
-                            data.insert(
-                                key,
-                                RedisEntry {
-                                    value: RedisValue::Ziplist(ziplist),
-                                    expiry: current_expiry,
-                                },
-                            );
-                            current_expiry = None;
-                        }
-                        0x04 => {
-                            // Hash in Zipmap Encoding
-                            let key = read_length_prefixed_string(reader, &mut file_bytes)?;
-                            let zipmap = read_length_prefixed_bytes(reader, &mut file_bytes)?;
-                            data.insert(
-                                key,
-                                RedisEntry {
-                                    value: RedisValue::Zipmap(zipmap),
-                                    expiry: current_expiry,
-                                },
-                            );
-                            current_expiry = None;
-                        }
-                        0x09 => {
-                            // Hashmap in Ziplist Encoding
-                            let key = read_length_prefixed_string(reader, &mut file_bytes)?;
-                            let ziplist = read_length_prefixed_bytes(reader, &mut file_bytes)?;
-                            data.insert(
-                                key,
-                                RedisEntry {
-                                    value: RedisValue::Ziplist(ziplist),
-                                    expiry: current_expiry,
-                                },
-                            );
-                            current_expiry = None;
-                        }
-                        0x0A => {
-                            // List in Ziplist Encoding
-                            let key = read_length_prefixed_string(reader, &mut file_bytes)?;
-                            let ziplist = read_length_prefixed_bytes(reader, &mut file_bytes)?;
-                            data.insert(
-                                key,
-                                RedisEntry {
-                                    value: RedisValue::Ziplist(ziplist),
-                                    expiry: current_expiry,
-                                },
-                            );
-                            current_expiry = None;
-                        }
-                        0x0B => {
-                            // Set in Intset Encoding
-                            let key = read_length_prefixed_string(reader, &mut file_bytes)?;
-                            let intset = read_length_prefixed_bytes(reader, &mut file_bytes)?;
-                            data.insert(
-                                key,
-                                RedisEntry {
-                                    value: RedisValue::Intset(intset),
-                                    expiry: current_expiry,
-                                },
-                            );
-                            current_expiry = None;
-                        }
-                        0x0C => {
-                            // Sorted Set in Intset Encoding
-                            let key = read_length_prefixed_string(reader, &mut file_bytes)?;
-                            let intset = read_length_prefixed_bytes(reader, &mut file_bytes)?;
-                            data.insert(
-                                key,
-                                RedisEntry {
-                                    value: RedisValue::Intset(intset),
-                                    expiry: current_expiry,
-                                },
-                            );
-                            current_expiry = None;
-                        }
-                        0x0D => {
-                            // List in Quicklist Encoding
-                            let key = read_length_prefixed_string(reader, &mut file_bytes)?;
-                            let quicklist = read_length_prefixed_bytes(reader, &mut file_bytes)?;
-                            data.insert(
-                                key,
-                                RedisEntry {
-                                    value: RedisValue::Quicklist(quicklist),
-                                    expiry: current_expiry,
-                                },
-                            );
-                            current_expiry = None;
-                        }
-                        _ => {
-                            return Err(io::Error::new(
-                                io::ErrorKind::InvalidData,
-                                format!("Unsupported RDB value type: {:#X}", opcode),
-                            ));
-                        }
-                    }
-                }
-                Err(_) => break,
+        LEGACY_LENGTHS.set(legacy);
+        let read = read_entries(reader, &mut data, &mut file_bytes);
+        LEGACY_LENGTHS.set(false);
+        if let Err(e) = read {
+            if e.kind() == io::ErrorKind::UnexpectedEof && lenient {
+                warn!(
+                    "RDB file is truncated, keeping the {} keys read before the cut",
+                    data.len()
+                );
+                return Ok(RdbDatabase { data });
             }
+            return Err(e);
         }
 
         // Read checksum (8 bytes)
@@ -263,8 +118,8 @@ impl RdbParser {
 
                 if expected != actual {
                     // For debugging purposes, you might want to make this a warning instead of an error
-                    eprintln!(
-                        "Warning: RDB checksum mismatch: expected {:016x}, got {:016x}",
+                    warn!(
+                        "RDB checksum mismatch: expected {:016x}, got {:016x}",
                         expected, actual
                     );
                     // Uncomment the next line if you want to enforce checksum validation
@@ -273,7 +128,7 @@ impl RdbParser {
             }
             Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => {
                 // Some RDB files might not have a checksum, especially older versions
-                eprintln!("Warning: No checksum found in RDB file, skipping validation");
+                warn!("No checksum found in RDB file, skipping validation");
             }
             Err(e) => return Err(e),
         }
@@ -282,13 +137,308 @@ impl RdbParser {
     }
 }
 
+/// Read opcodes and key/value entries up to the end-of-file marker (or the end of the stream)
+fn read_entries<R: Read>(
+    reader: &mut R,
+    data: &mut Vec<(String, RedisEntry)>,
+    file_bytes: &mut Vec<u8>,
+) -> io::Result<()> {
+    let mut buf = [0u8; 1];
+    let mut current_expiry: Option<u64> = None;
+    let mut current_db = 0;
+
+    while reader.read_exact(&mut buf).is_ok() {
+        file_bytes.push(buf[0]);
+        let opcode = buf[0];
+        match opcode {
+            0xFA => {
+                let _key = read_length_prefixed_string(reader, file_bytes)?;
+                let _value = read_length_prefixed_string(reader, file_bytes)?;
+                // Metadata - don't reset expiry
+            }
+            0xFB => {
+                let _ht_size = read_rdb_length(reader, file_bytes)?;
+                let _expire_ht_size = read_rdb_length(reader, file_bytes)?;
+                // Resize hint - don't reset expiry
+            }
+            0xFE => {
+                current_db = read_rdb_length(reader, file_bytes)? as usize;
+                // Database selector - don't reset expiry
+            }
+            0xFD => {
+                // Expiry in seconds
+                let mut expiry_buf = [0u8; 4];
+                reader.read_exact(&mut expiry_buf)?;
+                file_bytes.extend_from_slice(&expiry_buf);
+                let expiry_seconds = u32::from_le_bytes(expiry_buf) as u64;
+                current_expiry = Some(expiry_seconds * 1000); // Convert to milliseconds
+            }
+            0xFC => {
+                // Expiry in milliseconds
+                let mut expiry_buf = [0u8; 8];
+                reader.read_exact(&mut expiry_buf)?;
+                file_bytes.extend_from_slice(&expiry_buf);
+                current_expiry = Some(u64::from_le_bytes(expiry_buf));
+            }
+            0xFF => {
+                // End of RDB file
+                break;
+            }
+            // Value types
+            value_type => {
+                let key = read_length_prefixed_string(reader, file_bytes)?;
+                let value = read_value(value_type, reader, file_bytes)?;
+                data.push((
+                    key,
+                    RedisEntry {
+                        value,
+                        expiry: current_expiry,
+                        db: current_db,
+                    },
+                ));
+                current_expiry = None;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Type bytes of streams saved as listpacks, as Redis 5, 7.0 and 7.2 write them. Each
+/// version adds bookkeeping to the one before; streams are saved as the newest.
+const RDB_TYPE_STREAM_LISTPACKS: u8 = 15;
+const RDB_TYPE_STREAM_LISTPACKS_2: u8 = 19;
+const RDB_TYPE_STREAM_LISTPACKS_3: u8 = 21;
+
+/// Entries per listpack node when saving, as Redis's stream-node-max-entries default
+const STREAM_NODE_MAX_ENTRIES: usize = 100;
+
+/// Flags stored with each entry of a stream listpack node
+const STREAM_ITEM_FLAG_DELETED: i64 = 1;
+const STREAM_ITEM_FLAG_SAMEFIELDS: i64 = 2; // only values stored, the node's fields assumed
+
+/// Read a single value of the given RDB type (the part that follows the key)
+fn read_value<R: Read>(
+    value_type: u8,
+    reader: &mut R,
+    file_bytes: &mut Vec<u8>,
+) -> io::Result<RedisValue> {
+    match value_type {
+        0x00 => {
+            // String Encoding
+            let value = read_length_prefixed_bytes(reader, file_bytes)?;
+            Ok(RedisValue::String(value))
+        }
+        0x01 | 0x02 => {
+            // List / Set Encoding
+            let len = read_rdb_length(reader, file_bytes)?;
+            let mut items = Vec::with_capacity(prealloc(len));
+            for _ in 0..len {
+                let item = read_length_prefixed_bytes(reader, file_bytes)?;
+                items.push(item);
+            }
+            if value_type == 0x01 {
+                Ok(RedisValue::List(items))
+            } else {
+                Ok(RedisValue::Set(items))
+            }
+        }
+        0x03 | 0x05 => {
+            // Sorted Set, with scores as strings (0x03) or binary doubles (0x05)
+            let len = read_rdb_length(reader, file_bytes)?;
+            let mut members = Vec::with_capacity(prealloc(len));
+            for _ in 0..len {
+                let member = read_length_prefixed_bytes(reader, file_bytes)?;
+                let score = if value_type == 0x05 {
+                    let mut buf = [0u8; 8];
+                    reader.read_exact(&mut buf)?;
+                    file_bytes.extend_from_slice(&buf);
+                    f64::from_le_bytes(buf)
+                } else {
+                    read_double_string(reader, file_bytes)?
+                };
+                members.push((member, score));
+            }
+            Ok(RedisValue::SortedSet(members))
+        }
+        0x04 => {
+            // Hash Encoding: a length followed by field/value pairs
+            let len = read_rdb_length(reader, file_bytes)?;
+            let mut hash = HashMap::with_capacity(prealloc(len));
+            for _ in 0..len {
+                let field = read_length_prefixed_bytes(reader, file_bytes)?;
+                let value = read_length_prefixed_bytes(reader, file_bytes)?;
+                hash.insert(field, value);
+            }
+            Ok(RedisValue::Hash(hash.into()))
+        }
+        0x18 => {
+            // Hash with field TTLs (Redis 7.4): the soonest field expiry in Unix ms, then
+            // a length and TTL/field/value triples. A zero TTL means the field has none,
+            // otherwise it is the field's expiry relative to the soonest one, plus one.
+            let mut min_expiry = [0u8; 8];
+            reader.read_exact(&mut min_expiry)?;
+            file_bytes.extend_from_slice(&min_expiry);
+            let min_expiry = u64::from_le_bytes(min_expiry);
+            let len = read_rdb_length(reader, file_bytes)?;
+            let mut hash = HashValue::default();
+            for _ in 0..len {
+                let ttl = read_rdb_length(reader, file_bytes)?;
+                let field = read_length_prefixed_bytes(reader, file_bytes)?;
+                let value = read_length_prefixed_bytes(reader, file_bytes)?;
+                if ttl == 0 {
+                    hash.fields.insert(field, value);
+                } else if let Some(at) = instant_at(min_expiry.saturating_add(ttl - 1)) {
+                    hash.expiries.insert(field.clone(), at);
+                    hash.fields.insert(field, value);
+                }
+            }
+            Ok(RedisValue::Hash(hash))
+        }
+        0x09 => {
+            // Hash in Zipmap Encoding
+            let zipmap = read_length_prefixed_bytes(reader, file_bytes)?;
+            Ok(RedisValue::Zipmap(zipmap))
+        }
+        0x0A | 0x0C | 0x0D => {
+            // List / Sorted Set / Hash in Ziplist Encoding
+            let ziplist = read_length_prefixed_bytes(reader, file_bytes)?;
+            Ok(RedisValue::Ziplist(ziplist))
+        }
+        0x0B => {
+            // Set in Intset Encoding
+            let intset = read_length_prefixed_bytes(reader, file_bytes)?;
+            Ok(RedisValue::Intset(intset))
+        }
+        0x0E => {
+            // List in Quicklist Encoding
+            let quicklist = read_length_prefixed_bytes(reader, file_bytes)?;
+            Ok(RedisValue::Quicklist(quicklist))
+        }
+        0x10 => {
+            // Hash in Listpack Encoding
+            let listpack = read_length_prefixed_bytes(reader, file_bytes)?;
+            let entries = decode_listpack(&listpack)?;
+            if entries.len() % 2 != 0 {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "Hash listpack has an odd number of entries",
+                ));
+            }
+            let mut hash = HashMap::with_capacity(entries.len() / 2);
+            let mut entries = entries.into_iter();
+            while let (Some(field), Some(value)) = (entries.next(), entries.next()) {
+                hash.insert(field, value);
+            }
+            Ok(RedisValue::Hash(hash.into()))
+        }
+        0x11 => {
+            // Sorted Set in Listpack Encoding: member, score, member, score...
+            let listpack = read_length_prefixed_bytes(reader, file_bytes)?;
+            let entries = decode_listpack(&listpack)?;
+            let mut members = Vec::with_capacity(entries.len() / 2);
+            let mut entries = entries.into_iter();
+            while let (Some(member), Some(score)) = (entries.next(), entries.next()) {
+                let score = String::from_utf8_lossy(&score)
+                    .parse::<f64>()
+                    .map_err(|_| {
+                        io::Error::new(io::ErrorKind::InvalidData, "Invalid sorted set score")
+                    })?;
+                members.push((member, score));
+            }
+            Ok(RedisValue::SortedSet(members))
+        }
+        0x12 => {
+            // List in Quicklist 2 Encoding (a sequence of listpack or plain nodes)
+            let node_count = read_rdb_length(reader, file_bytes)?;
+            let mut items = Vec::new();
+            for _ in 0..node_count {
+                let container = read_rdb_length(reader, file_bytes)?;
+                let node = read_length_prefixed_bytes(reader, file_bytes)?;
+                match container {
+                    QUICKLIST_NODE_PLAIN => items.push(node),
+                    QUICKLIST_NODE_PACKED => items.extend(decode_listpack(&node)?),
+                    _ => {
+                        return Err(io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            format!("Unknown quicklist container type: {}", container),
+                        ));
+                    }
+                }
+            }
+            Ok(RedisValue::List(items))
+        }
+        RDB_TYPE_STREAM_LISTPACKS | RDB_TYPE_STREAM_LISTPACKS_2 | RDB_TYPE_STREAM_LISTPACKS_3 => {
+            read_stream(value_type, reader, file_bytes).map(RedisValue::Stream)
+        }
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Unsupported RDB value type: {:#X}", value_type),
+        )),
+    }
+}
+
+/// Read a score saved as a length-prefixed decimal string, where the
+/// lengths 253, 254 and 255 stand for NaN, +inf and -inf
+fn read_double_string<R: Read>(reader: &mut R, file_bytes: &mut Vec<u8>) -> io::Result<f64> {
+    let mut len = [0u8; 1];
+    reader.read_exact(&mut len)?;
+    file_bytes.push(len[0]);
+    match len[0] {
+        253 => Ok(f64::NAN),
+        254 => Ok(f64::INFINITY),
+        255 => Ok(f64::NEG_INFINITY),
+        n => {
+            let buf = read_exact_len(reader, n as u64, file_bytes)?;
+            String::from_utf8_lossy(&buf)
+                .parse::<f64>()
+                .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "Invalid double value"))
+        }
+    }
+}
+
+/// Most elements or bytes set aside up front for a length read from the input.
+/// Lengths come from files and RESTORE payloads that may be corrupt or hostile,
+/// so past this a value only grows as its data actually turns up.
+const MAX_PREALLOC: u64 = 4096;
+
+fn prealloc(len: u64) -> usize {
+    len.min(MAX_PREALLOC) as usize
+}
+
+/// Read exactly len bytes, failing with UnexpectedEof if the input holds fewer
+fn read_exact_len<R: Read>(
+    reader: &mut R,
+    len: u64,
+    file_bytes: &mut Vec<u8>,
+) -> io::Result<Vec<u8>> {
+    let mut buf = Vec::with_capacity(prealloc(len));
+    reader.take(len).read_to_end(&mut buf)?;
+    if (buf.len() as u64) < len {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "RDB length runs past the end of the input",
+        ));
+    }
+    file_bytes.extend_from_slice(&buf);
+    Ok(buf)
+}
+
 // Replace your read_rdb_length function with this enhanced version
 fn read_rdb_length<R: Read>(reader: &mut R, file_bytes: &mut Vec<u8>) -> io::Result<u64> {
     let mut first = [0u8; 1];
     reader.read_exact(&mut first)?;
     file_bytes.push(first[0]);
-    let enc_type = first[0] >> 6;
-    let len = (first[0] & 0x3F) as u64;
+    decode_rdb_length(first[0], reader, file_bytes)
+}
+
+/// Decode a length whose first byte has already been consumed from the reader
+fn decode_rdb_length<R: Read>(
+    first: u8,
+    reader: &mut R,
+    file_bytes: &mut Vec<u8>,
+) -> io::Result<u64> {
+    let enc_type = first >> 6;
+    let len = (first & 0x3F) as u64;
 
     match enc_type {
         0 => Ok(len), // 6-bit length
@@ -296,14 +446,25 @@ fn read_rdb_length<R: Read>(reader: &mut R, file_bytes: &mut Vec<u8>) -> io::Res
             let mut second = [0u8; 1];
             reader.read_exact(&mut second)?;
             file_bytes.push(second[0]);
-            let combined = ((len << 8) | second[0] as u64) as u64;
+            let combined = (len << 8) | second[0] as u64;
             Ok(combined)
         }
+        2 if first == 0x81 => {
+            // 64-bit big-endian length
+            let mut buf = [0u8; 8];
+            reader.read_exact(&mut buf)?;
+            file_bytes.extend_from_slice(&buf);
+            Ok(u64::from_be_bytes(buf))
+        }
         2 => {
+            // 32-bit big-endian length
             let mut buf = [0u8; 4];
             reader.read_exact(&mut buf)?;
             file_bytes.extend_from_slice(&buf);
-            Ok(u32::from_le_bytes(buf) as u64)
+            if LEGACY_LENGTHS.get() {
+                return Ok(u32::from_le_bytes(buf) as u64);
+            }
+            Ok(u32::from_be_bytes(buf) as u64)
         }
         3 => {
             // Special encoding - the lower 6 bits indicate the format
@@ -377,14 +538,10 @@ fn read_string_with_encoding<R: Read>(
     let len = (first[0] & 0x3F) as u64;
 
     match enc_type {
-        0 | 1 | 2 => {
-            // Normal length encoding - put the byte back and read normally
-            file_bytes.pop(); // Remove the byte we just added
-            let actual_len = read_rdb_length(reader, file_bytes)?;
-            let mut buf = vec![0u8; actual_len as usize];
-            reader.read_exact(&mut buf)?;
-            file_bytes.extend_from_slice(&buf);
-            Ok(buf)
+        0..=2 => {
+            // Normal length encoding - the byte we just read is the start of the length
+            let actual_len = decode_rdb_length(first[0], reader, file_bytes)?;
+            read_exact_len(reader, actual_len, file_bytes)
         }
         3 => {
             // Special encoding
@@ -417,12 +574,9 @@ fn read_string_with_encoding<R: Read>(
                     // LZF compressed string
                     let compressed_len = read_rdb_length(reader, file_bytes)?;
                     let uncompressed_len = read_rdb_length(reader, file_bytes)?;
-                    let mut compressed_data = vec![0u8; compressed_len as usize];
-                    reader.read_exact(&mut compressed_data)?;
-                    file_bytes.extend_from_slice(&compressed_data);
+                    let compressed_data = read_exact_len(reader, compressed_len, file_bytes)?;
 
-                    // Use our fallback LZF decompression implementation
-                    match lzf_decompress_fallback(&compressed_data, uncompressed_len as usize) {
+                    match lzf_decompress(&compressed_data, uncompressed_len as usize) {
                         Ok(data) => Ok(data),
                         Err(_) => Err(io::Error::new(
                             io::ErrorKind::InvalidData,
@@ -443,21 +597,374 @@ fn read_string_with_encoding<R: Read>(
     }
 }
 
-// Fallback LZF decompression implementation for compatibility
-fn lzf_decompress_fallback(
-    compressed: &[u8],
-    expected_len: usize,
-) -> Result<Vec<u8>, &'static str> {
-    let mut output = Vec::with_capacity(expected_len);
+/// Quicklist 2 node holding a single element stored as-is
+const QUICKLIST_NODE_PLAIN: u64 = 1;
+/// Quicklist 2 node holding a listpack of elements
+const QUICKLIST_NODE_PACKED: u64 = 2;
+
+/// Decode a listpack blob into its elements.
+/// Integer-encoded elements are returned as their decimal string representation,
+/// which is how Redis exposes them to clients.
+fn decode_listpack(lp: &[u8]) -> io::Result<Vec<Vec<u8>>> {
+    let invalid = |msg: &str| io::Error::new(io::ErrorKind::InvalidData, msg.to_string());
+    // Header: 4 bytes total size + 2 bytes element count
+    if lp.len() < 7 {
+        return Err(invalid("Listpack too short"));
+    }
+    let mut items = Vec::with_capacity(u16::from_le_bytes([lp[4], lp[5]]) as usize);
+    let mut pos = 6;
+
+    loop {
+        let take = |start: usize, len: usize| {
+            lp.get(start..start + len)
+                .ok_or_else(|| invalid("Listpack entry extends beyond blob"))
+        };
+        let b0 = *lp
+            .get(pos)
+            .ok_or_else(|| invalid("Listpack missing terminator"))?;
+        if b0 == 0xFF {
+            break;
+        }
+
+        // (encoding + data length, decoded element)
+        let (entry_len, item) = if b0 & 0x80 == 0 {
+            // 7-bit unsigned integer
+            (1, ((b0 & 0x7F) as i64).to_string().into_bytes())
+        } else if b0 & 0xC0 == 0x80 {
+            // 6-bit string length
+            let len = (b0 & 0x3F) as usize;
+            (1 + len, take(pos + 1, len)?.to_vec())
+        } else if b0 & 0xE0 == 0xC0 {
+            // 13-bit signed integer
+            let b1 = take(pos + 1, 1)?[0];
+            let uval = (((b0 & 0x1F) as i64) << 8) | b1 as i64;
+            let val = if uval >= 1 << 12 {
+                uval - (1 << 13)
+            } else {
+                uval
+            };
+            (2, val.to_string().into_bytes())
+        } else if b0 & 0xF0 == 0xE0 {
+            // 12-bit string length
+            let b1 = take(pos + 1, 1)?[0];
+            let len = (((b0 & 0x0F) as usize) << 8) | b1 as usize;
+            (2 + len, take(pos + 2, len)?.to_vec())
+        } else {
+            match b0 {
+                0xF0 => {
+                    // 32-bit string length
+                    let raw = take(pos + 1, 4)?;
+                    let len = u32::from_le_bytes([raw[0], raw[1], raw[2], raw[3]]) as usize;
+                    (5 + len, take(pos + 5, len)?.to_vec())
+                }
+                0xF1 => {
+                    let raw = take(pos + 1, 2)?;
+                    let val = i16::from_le_bytes([raw[0], raw[1]]);
+                    (3, val.to_string().into_bytes())
+                }
+                0xF2 => {
+                    // 24-bit signed integer, sign-extended from bit 23
+                    let raw = take(pos + 1, 3)?;
+                    let val = i32::from_le_bytes([0, raw[0], raw[1], raw[2]]) >> 8;
+                    (4, val.to_string().into_bytes())
+                }
+                0xF3 => {
+                    let raw = take(pos + 1, 4)?;
+                    let val = i32::from_le_bytes([raw[0], raw[1], raw[2], raw[3]]);
+                    (5, val.to_string().into_bytes())
+                }
+                0xF4 => {
+                    let raw = take(pos + 1, 8)?;
+                    let mut buf = [0u8; 8];
+                    buf.copy_from_slice(raw);
+                    (9, i64::from_le_bytes(buf).to_string().into_bytes())
+                }
+                _ => return Err(invalid("Unknown listpack entry encoding")),
+            }
+        };
+
+        // Every entry is followed by a backlen whose size depends on the entry length
+        let backlen_size = match entry_len {
+            0..=127 => 1,
+            128..=16382 => 2,
+            16383..=2097150 => 3,
+            2097151..=268435454 => 4,
+            _ => 5,
+        };
+        pos += entry_len + backlen_size;
+        items.push(item);
+    }
+
+    Ok(items)
+}
+
+/// Read a stream saved as listpack nodes, each keyed by the ID its entries are stored
+/// relative to. Consumer groups are read past, as streams here don't have them.
+fn read_stream<R: Read>(
+    value_type: u8,
+    reader: &mut R,
+    file_bytes: &mut Vec<u8>,
+) -> io::Result<StreamValue> {
+    let invalid = |msg: &str| io::Error::new(io::ErrorKind::InvalidData, msg.to_string());
+    let mut stream = StreamValue::default();
+    let nodes = read_rdb_length(reader, file_bytes)?;
+    for _ in 0..nodes {
+        let key = read_length_prefixed_bytes(reader, file_bytes)?;
+        let master = decode_stream_id(&key).ok_or_else(|| invalid("Bad stream node key"))?;
+        let listpack = read_length_prefixed_bytes(reader, file_bytes)?;
+        read_stream_node(master, &decode_listpack(&listpack)?, &mut stream.entries)
+            .ok_or_else(|| invalid("Bad stream listpack node"))?;
+    }
+
+    let _length = read_rdb_length(reader, file_bytes)?;
+    stream.last_id = (
+        read_rdb_length(reader, file_bytes)?,
+        read_rdb_length(reader, file_bytes)?,
+    );
+    if value_type >= RDB_TYPE_STREAM_LISTPACKS_2 {
+        // First ID, largest deleted ID and entries ever added, which XADD works out
+        for _ in 0..5 {
+            read_rdb_length(reader, file_bytes)?;
+        }
+    }
+
+    let groups = read_rdb_length(reader, file_bytes)?;
+    for _ in 0..groups {
+        read_length_prefixed_bytes(reader, file_bytes)?; // name
+        read_rdb_length(reader, file_bytes)?; // last delivered ID
+        read_rdb_length(reader, file_bytes)?;
+        if value_type >= RDB_TYPE_STREAM_LISTPACKS_2 {
+            read_rdb_length(reader, file_bytes)?; // entries read
+        }
+        // Pending entries: a raw ID, a delivery time and a delivery count each
+        let pending = read_rdb_length(reader, file_bytes)?;
+        for _ in 0..pending {
+            read_exact_len(reader, 16 + 8, file_bytes)?;
+            read_rdb_length(reader, file_bytes)?;
+        }
+        let consumers = read_rdb_length(reader, file_bytes)?;
+        for _ in 0..consumers {
+            read_length_prefixed_bytes(reader, file_bytes)?; // name
+            let times = if value_type >= RDB_TYPE_STREAM_LISTPACKS_3 {
+                2
+            } else {
+                1
+            };
+            read_exact_len(reader, 8 * times, file_bytes)?;
+            let pending = read_rdb_length(reader, file_bytes)?;
+            read_exact_len(reader, 16 * pending, file_bytes)?;
+        }
+    }
+    Ok(stream)
+}
+
+/// A stream ID as a node key: milliseconds then sequence, 64 bits each, big-endian
+fn decode_stream_id(key: &[u8]) -> Option<StreamId> {
+    let key: [u8; 16] = key.try_into().ok()?;
+    let ms = u64::from_be_bytes(key[..8].try_into().ok()?);
+    let seq = u64::from_be_bytes(key[8..].try_into().ok()?);
+    Some((ms, seq))
+}
+
+/// Append the live entries of a listpack node to entries. The node starts with its
+/// entry counts and the fields of its first entry; each entry then has flags, its ID
+/// as a difference from the master ID, its fields or only its values, and a count of
+/// the listpack elements it took.
+fn read_stream_node(
+    master: StreamId,
+    items: &[Vec<u8>],
+    entries: &mut Vec<StreamEntry>,
+) -> Option<()> {
+    let int = |item: &Vec<u8>| std::str::from_utf8(item).ok()?.parse::<i64>().ok();
+    let mut items = items.iter();
+    let mut next_int = || int(items.next()?);
+    let _live = next_int()?;
+    let _deleted = next_int()?;
+    let master_field_count = usize::try_from(next_int()?).ok()?;
+    let mut items = items.as_slice().iter();
+    let master_fields: Vec<&Vec<u8>> = (0..master_field_count)
+        .map(|_| items.next())
+        .collect::<Option<_>>()?;
+    if int(items.next()?)? != 0 {
+        return None; // the master entry ends with a 0
+    }
+
+    while let Some(flags) = items.next() {
+        let flags = int(flags)?;
+        let ms = master.0.checked_add_signed(int(items.next()?)?)?;
+        let seq = master.1.checked_add_signed(int(items.next()?)?)?;
+        let mut fields = Vec::new();
+        if flags & STREAM_ITEM_FLAG_SAMEFIELDS != 0 {
+            for field in &master_fields {
+                fields.push(((*field).clone(), items.next()?.clone()));
+            }
+        } else {
+            let count = usize::try_from(int(items.next()?)?).ok()?;
+            for _ in 0..count {
+                fields.push((items.next()?.clone(), items.next()?.clone()));
+            }
+        }
+        let _lp_count = items.next()?;
+        if flags & STREAM_ITEM_FLAG_DELETED == 0 {
+            entries.push(((ms, seq), fields));
+        }
+    }
+    Some(())
+}
+
+/// Write a stream as listpack nodes of up to STREAM_NODE_MAX_ENTRIES entries each,
+/// followed by its IDs and counts and no consumer groups
+fn write_stream(payload: &mut Vec<u8>, stream: &StreamValue) -> Result<(), String> {
+    let nodes = stream.entries.chunks(STREAM_NODE_MAX_ENTRIES);
+    write_rdb_length(payload, nodes.len() as u64)?;
+    for node in nodes {
+        let (master, master_fields) = &node[0];
+        let mut key = master.0.to_be_bytes().to_vec();
+        key.extend_from_slice(&master.1.to_be_bytes());
+        write_length_prefixed_bytes(payload, &key)?;
+
+        let mut items = vec![
+            ListpackItem::Int(node.len() as i64),
+            ListpackItem::Int(0),
+            ListpackItem::Int(master_fields.len() as i64),
+        ];
+        items.extend(master_fields.iter().map(|(f, _)| ListpackItem::Str(f)));
+        items.push(ListpackItem::Int(0));
+        for ((ms, seq), fields) in node {
+            let same_fields = fields.len() == master_fields.len()
+                && fields.iter().zip(master_fields).all(|(a, b)| a.0 == b.0);
+            let flags = if same_fields {
+                STREAM_ITEM_FLAG_SAMEFIELDS
+            } else {
+                0
+            };
+            items.push(ListpackItem::Int(flags));
+            items.push(ListpackItem::Int(ms.wrapping_sub(master.0) as i64));
+            items.push(ListpackItem::Int(seq.wrapping_sub(master.1) as i64));
+            let mut lp_count = fields.len() + 3;
+            if same_fields {
+                items.extend(fields.iter().map(|(_, v)| ListpackItem::Str(v)));
+            } else {
+                items.push(ListpackItem::Int(fields.len() as i64));
+                for (field, value) in fields {
+                    items.push(ListpackItem::Str(field));
+                    items.push(ListpackItem::Str(value));
+                }
+                lp_count += fields.len() + 1;
+            }
+            items.push(ListpackItem::Int(lp_count as i64));
+        }
+        write_length_prefixed_bytes(payload, &encode_listpack(&items))?;
+    }
+
+    let first_id = stream.entries.first().map_or((0, 0), |(id, _)| *id);
+    for n in [
+        stream.entries.len() as u64,
+        stream.last_id.0,
+        stream.last_id.1,
+        first_id.0,
+        first_id.1,
+        0, // largest deleted ID, which isn't kept
+        0,
+        stream.entries.len() as u64, // entries added, counting only those left
+        0,                           // consumer groups
+    ] {
+        write_rdb_length(payload, n)?;
+    }
+    Ok(())
+}
+
+/// An element to encode into a listpack
+enum ListpackItem<'a> {
+    Int(i64),
+    Str(&'a [u8]),
+}
+
+/// Encode elements as a listpack: a header with the total size and element count,
+/// each element's encoding and data followed by its length for walking backwards,
+/// and an 0xFF terminator
+fn encode_listpack(items: &[ListpackItem]) -> Vec<u8> {
+    let mut lp = vec![0u8; 6];
+    for item in items {
+        let start = lp.len();
+        match *item {
+            ListpackItem::Int(v @ 0..=127) => lp.push(v as u8),
+            ListpackItem::Int(v @ -4096..=4095) => {
+                let v = (v as u16) & 0x1FFF;
+                lp.push(0xC0 | (v >> 8) as u8);
+                lp.push(v as u8);
+            }
+            ListpackItem::Int(v @ -32768..=32767) => {
+                lp.push(0xF1);
+                lp.extend_from_slice(&(v as i16).to_le_bytes());
+            }
+            ListpackItem::Int(v @ -8388608..=8388607) => {
+                lp.push(0xF2);
+                lp.extend_from_slice(&(v as i32).to_le_bytes()[..3]);
+            }
+            ListpackItem::Int(v) if i32::try_from(v).is_ok() => {
+                lp.push(0xF3);
+                lp.extend_from_slice(&(v as i32).to_le_bytes());
+            }
+            ListpackItem::Int(v) => {
+                lp.push(0xF4);
+                lp.extend_from_slice(&v.to_le_bytes());
+            }
+            ListpackItem::Str(s) => {
+                if s.len() < 64 {
+                    lp.push(0x80 | s.len() as u8);
+                } else if s.len() < 4096 {
+                    lp.push(0xE0 | (s.len() >> 8) as u8);
+                    lp.push(s.len() as u8);
+                } else {
+                    lp.push(0xF0);
+                    lp.extend_from_slice(&(s.len() as u32).to_le_bytes());
+                }
+                lp.extend_from_slice(s);
+            }
+        }
+        // The entry length again, 7 bits per byte from the most significant group,
+        // with the high bit set on all but the first byte written
+        let len = lp.len() - start;
+        let groups = match len {
+            0..=127 => 1,
+            128..=16382 => 2,
+            16383..=2097150 => 3,
+            2097151..=268435454 => 4,
+            _ => 5,
+        };
+        for group in (0..groups).rev() {
+            let bits = ((len >> (7 * group)) & 127) as u8;
+            lp.push(if group == groups - 1 {
+                bits
+            } else {
+                bits | 128
+            });
+        }
+    }
+    lp.push(0xFF);
+    let total = lp.len() as u32;
+    lp[..4].copy_from_slice(&total.to_le_bytes());
+    let count = items.len().min(u16::MAX as usize) as u16;
+    lp[4..6].copy_from_slice(&count.to_le_bytes());
+    lp
+}
+
+/// Decompress an LZF block, the way Redis stores long strings, which must come out
+/// exactly expected_len bytes long. Each control byte starts either a run of up to 32
+/// literal bytes or a back reference: 3 bits of length (7 meaning another length byte
+/// follows) and 13 bits of distance back into the output, spread over the next byte.
+fn lzf_decompress(compressed: &[u8], expected_len: usize) -> Result<Vec<u8>, &'static str> {
+    let mut output = Vec::with_capacity(prealloc(expected_len as u64));
     let mut i = 0;
 
     while i < compressed.len() {
-        let ctrl = compressed[i];
+        let ctrl = compressed[i] as usize;
         i += 1;
 
         if ctrl < 32 {
             // Literal run
-            let len = ctrl as usize + 1;
+            let len = ctrl + 1;
             if i + len > compressed.len() {
                 return Err("Invalid LZF data: literal run extends beyond input");
             }
@@ -465,53 +972,83 @@ fn lzf_decompress_fallback(
             i += len;
         } else {
             // Back reference
-            let len = (ctrl >> 5) as usize;
-            let _offset = if len == 7 {
-                // Extended length
-                if i >= compressed.len() {
+            let mut len = ctrl >> 5;
+            if len == 7 {
+                let Some(&extra) = compressed.get(i) else {
                     return Err("Invalid LZF data: missing extended length");
-                }
-                let extra_len = compressed[i] as usize;
+                };
+                len += extra as usize;
                 i += 1;
-                7 + extra_len
-            } else {
-                len
-            };
-
-            if i + 1 >= compressed.len() {
-                return Err("Invalid LZF data: missing offset bytes");
             }
-
-            let offset_bytes = ((ctrl & 0x1F) as usize) << 8 | compressed[i] as usize;
+            let Some(&low) = compressed.get(i) else {
+                return Err("Invalid LZF data: missing offset byte");
+            };
             i += 1;
 
-            let back_ref_len = len + 2;
-            let back_ref_offset = offset_bytes + 1;
-
-            if back_ref_offset > output.len() {
+            let distance = ((ctrl & 0x1F) << 8 | low as usize) + 1;
+            if distance > output.len() {
                 return Err("Invalid LZF data: back reference beyond output");
             }
-
-            let start_pos = output.len() - back_ref_offset;
-
-            // Handle overlapping copies
-            for j in 0..back_ref_len {
-                if start_pos + j >= output.len() {
-                    break;
-                }
-                let byte = output[start_pos + j];
-                output.push(byte);
+            // The copy may overlap what it produces, which repeats the referenced bytes
+            let start = output.len() - distance;
+            for j in 0..len + 2 {
+                output.push(output[start + j]);
             }
         }
+        if output.len() > expected_len {
+            return Err("Invalid LZF data: longer than its stated length");
+        }
     }
 
+    if output.len() != expected_len {
+        return Err("Invalid LZF data: shorter than its stated length");
+    }
     Ok(output)
 }
 
+/// Serialize a single value in the DUMP format: the RDB type byte and payload,
+/// followed by a 2-byte RDB version and an 8-byte CRC64 of everything before it.
+/// Returns None for values that have no RDB representation.
+pub fn dump_value(value: &RedisValue) -> Result<Option<Vec<u8>>, String> {
+    let Some((value_type, payload)) = encode_value(value)? else {
+        return Ok(None);
+    };
+    let mut dump = Vec::with_capacity(1 + payload.len() + 10);
+    dump.push(value_type);
+    dump.extend_from_slice(&payload);
+    dump.extend_from_slice(&DUMP_RDB_VERSION.to_le_bytes());
+    let checksum = crc64(0, &dump);
+    dump.extend_from_slice(&checksum.to_le_bytes());
+    Ok(Some(dump))
+}
+
+/// Deserialize a DUMP payload produced by `dump_value`, validating its version and CRC64.
+pub fn restore_value(dump: &[u8]) -> Result<RedisValue, String> {
+    let bad_payload = || "DUMP payload version or checksum are wrong".to_string();
+    if dump.len() < 11 {
+        return Err(bad_payload());
+    }
+    let (body, checksum) = dump.split_at(dump.len() - 8);
+    let version = u16::from_le_bytes([body[body.len() - 2], body[body.len() - 1]]);
+    let mut checksum_bytes = [0u8; 8];
+    checksum_bytes.copy_from_slice(checksum);
+    if version > RDB_MAX_VERSION || u64::from_le_bytes(checksum_bytes) != crc64(0, body) {
+        return Err(bad_payload());
+    }
+
+    let mut reader = &body[1..body.len() - 2];
+    let value = read_value(body[0], &mut reader, &mut Vec::new())
+        .map_err(|_| "Bad data format".to_string())?;
+    if !reader.is_empty() {
+        return Err("Bad data format".to_string());
+    }
+    Ok(value)
+}
+
 /// Save the current database state to RDB file
 pub async fn save() -> Result<(), String> {
-    // First purge any expired keys
-    db::purge_expired_keys().await;
+    let changes = CHANGES_SINCE_SAVE.load(Ordering::SeqCst);
+    let (file_bytes, saved_keys) = snapshot().await?;
 
     let config = config::get_config();
     let rdb_path = config.dir.join(&config.dbfilename);
@@ -521,6 +1058,117 @@ pub async fn save() -> Result<(), String> {
     let mut file =
         File::create(&temp_path).map_err(|e| format!("Failed to create RDB file: {}", e))?;
 
+    // Write all data to file
+    file.write_all(&file_bytes)
+        .map_err(|e| format!("Failed to write RDB file: {}", e))?;
+
+    file.flush()
+        .map_err(|e| format!("Failed to flush RDB file: {}", e))?;
+
+    // Atomically replace the old file with the new one
+    std::fs::rename(temp_path, rdb_path)
+        .map_err(|e| format!("Failed to rename RDB file: {}", e))?;
+
+    // Writes made while saving still count towards the next save
+    CHANGES_SINCE_SAVE.fetch_sub(changes, Ordering::SeqCst);
+    LAST_SAVE.store(unix_secs(), Ordering::SeqCst);
+    info!("Saved {} keys to RDB file", saved_keys);
+    Ok(())
+}
+
+/// Count writes towards the `save` rules
+pub fn record_changes(n: u64) {
+    CHANGES_SINCE_SAVE.fetch_add(n, Ordering::SeqCst);
+}
+
+pub fn bgsave_in_progress() -> bool {
+    BGSAVE_IN_PROGRESS.load(Ordering::SeqCst)
+}
+
+/// Save in a background task, unless a background save is already running
+pub fn bgsave() -> Result<(), String> {
+    if BGSAVE_IN_PROGRESS.swap(true, Ordering::SeqCst) {
+        return Err("Background save already in progress".to_string());
+    }
+    LAST_BGSAVE_TRY.store(unix_secs(), Ordering::SeqCst);
+    tokio::spawn(async {
+        let result = save().await;
+        if let Err(e) = &result {
+            warn!("Background saving error: {}", e);
+        }
+        LAST_BGSAVE_OK.store(result.is_ok(), Ordering::SeqCst);
+        BGSAVE_IN_PROGRESS.store(false, Ordering::SeqCst);
+    });
+    Ok(())
+}
+
+/// Start a background save if a `save <seconds> <changes>` rule is met: at least
+/// `changes` writes and more than `seconds` since the last save. Called every second.
+pub fn bgsave_if_due() {
+    let now = unix_secs();
+    let changes = CHANGES_SINCE_SAVE.load(Ordering::SeqCst);
+    let elapsed = now.saturating_sub(LAST_SAVE.load(Ordering::SeqCst));
+    let Some((seconds, _)) = config::get_config()
+        .save
+        .into_iter()
+        .find(|&(seconds, min_changes)| changes >= min_changes && elapsed > seconds)
+    else {
+        return;
+    };
+    // Don't retry a failing save every second
+    if !LAST_BGSAVE_OK.load(Ordering::SeqCst)
+        && now.saturating_sub(LAST_BGSAVE_TRY.load(Ordering::SeqCst)) <= BGSAVE_RETRY_DELAY
+    {
+        return;
+    }
+    if bgsave().is_ok() {
+        info!("{} changes in {} seconds. Saving...", changes, seconds);
+    }
+}
+
+/// Render the `# Persistence` section of INFO
+pub fn info_section() -> String {
+    let status = if LAST_BGSAVE_OK.load(Ordering::SeqCst) {
+        "ok"
+    } else {
+        "err"
+    };
+    let fields = [
+        ("loading", "0".to_string()),
+        (
+            "rdb_changes_since_last_save",
+            CHANGES_SINCE_SAVE.load(Ordering::SeqCst).to_string(),
+        ),
+        (
+            "rdb_bgsave_in_progress",
+            u8::from(bgsave_in_progress()).to_string(),
+        ),
+        (
+            "rdb_last_save_time",
+            LAST_SAVE.load(Ordering::SeqCst).to_string(),
+        ),
+        ("rdb_last_bgsave_status", status.to_string()),
+    ];
+    let mut out = String::from("# Persistence\r\n");
+    for (name, value) in fields {
+        out.push_str(&format!("{}:{}\r\n", name, value));
+    }
+    out
+}
+
+fn unix_secs() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// Serialize every database as an RDB image, returning it with the number of keys it holds.
+/// Used both for the RDB file and for the full resync of a replica.
+pub async fn snapshot() -> Result<(Vec<u8>, usize), String> {
+    // First purge any expired keys
+    db::purge_expired_keys().await;
+
     // Get current timestamp for calculating expiry
     let current_timestamp = std::time::SystemTime::now()
         .duration_since(std::time::UNIX_EPOCH)
@@ -531,129 +1179,53 @@ pub async fn save() -> Result<(), String> {
 
     // Write RDB header
     file_bytes.extend_from_slice(b"REDIS");
-    file_bytes.extend_from_slice(&[0, 0, 0, 11]); // Version 0011
-
-    // Write database selector (database 0)
-    file_bytes.push(0xFE);
-    write_rdb_length(&mut file_bytes, 0)?;
-
-    // Get hash table size hint
-    let kv = db::KV.read().await;
-    let exp = db::EXP.read().await;
-
-    // Write resize hint
-    file_bytes.push(0xFB);
-    write_rdb_length(&mut file_bytes, kv.len() as u64)?;
-    write_rdb_length(&mut file_bytes, exp.len() as u64)?;
-
-    // Write all key-value pairs
-    for (key, value) in kv.iter() {
-        // Check if key has expiry
-        if let Some(expiry_instant) = exp.get(key) {
-            // Calculate expiry timestamp in milliseconds
-            let now = Instant::now();
-            if *expiry_instant > now {
-                let remaining_duration = *expiry_instant - now;
-                let expiry_timestamp = current_timestamp + remaining_duration.as_millis() as u64;
-
-                // Write expiry in milliseconds
-                file_bytes.push(0xFC);
-                file_bytes.extend_from_slice(&expiry_timestamp.to_le_bytes());
-            }
-        }
-
-        // Write the key-value pair based on value type
-        match value {
-            RedisValue::String(s) => {
-                file_bytes.push(0x00); // String encoding
-                write_length_prefixed_string(&mut file_bytes, key)?;
-                write_length_prefixed_bytes(&mut file_bytes, s)?;
-            }
-            RedisValue::List(items) => {
-                file_bytes.push(0x01); // List encoding
-                write_length_prefixed_string(&mut file_bytes, key)?;
-                write_rdb_length(&mut file_bytes, items.len() as u64)?;
-                for item in items {
-                    write_length_prefixed_bytes(&mut file_bytes, item)?;
-                }
-            }
-            RedisValue::Set(items) => {
-                file_bytes.push(0x02); // Set encoding
-                write_length_prefixed_string(&mut file_bytes, key)?;
-                write_rdb_length(&mut file_bytes, items.len() as u64)?;
-                for item in items {
-                    write_length_prefixed_bytes(&mut file_bytes, item)?;
-                }
-            }
-            RedisValue::Ziplist(data) => {
-                file_bytes.push(0x0A); // List in Ziplist encoding
-                write_length_prefixed_string(&mut file_bytes, key)?;
-                write_length_prefixed_bytes(&mut file_bytes, data)?;
-            }
-            RedisValue::Zipmap(data) => {
-                file_bytes.push(0x04); // Hash in Zipmap encoding
-                write_length_prefixed_string(&mut file_bytes, key)?;
-                write_length_prefixed_bytes(&mut file_bytes, data)?;
-            }
-            RedisValue::Intset(data) => {
-                file_bytes.push(0x0B); // Set in Intset encoding
-                write_length_prefixed_string(&mut file_bytes, key)?;
-                write_length_prefixed_bytes(&mut file_bytes, data)?;
-            }
-            RedisValue::Quicklist(data) => {
-                file_bytes.push(0x0D); // List in Quicklist encoding
-                write_length_prefixed_string(&mut file_bytes, key)?;
-                write_length_prefixed_bytes(&mut file_bytes, data)?;
-            }
-            // For complex types, we'll serialize them as strings for now
-            RedisValue::Integer(i) => {
-                file_bytes.push(0x00); // String encoding
-                write_length_prefixed_string(&mut file_bytes, key)?;
-                let value_bytes = i.to_string().into_bytes();
-                write_length_prefixed_bytes(&mut file_bytes, &value_bytes)?;
-            }
-            RedisValue::Float(f) => {
-                file_bytes.push(0x00); // String encoding
-                write_length_prefixed_string(&mut file_bytes, key)?;
-                let value_bytes = f.to_string().into_bytes();
-                write_length_prefixed_bytes(&mut file_bytes, &value_bytes)?;
-            }
-            RedisValue::Boolean(b) => {
-                file_bytes.push(0x00); // String encoding
-                write_length_prefixed_string(&mut file_bytes, key)?;
-                let value_bytes = b.to_string().into_bytes();
-                write_length_prefixed_bytes(&mut file_bytes, &value_bytes)?;
-            }
-            RedisValue::Hash(hash) => {
-                file_bytes.push(0x04); // Hash in Zipmap encoding (simplified)
-                write_length_prefixed_string(&mut file_bytes, key)?;
-                // Serialize hash as a simple format for now
-                let mut hash_data = Vec::new();
-                for (k, v) in hash {
-                    hash_data.extend_from_slice(k);
-                    hash_data.push(0); // separator
-                    hash_data.extend_from_slice(v);
-                    hash_data.push(0); // separator
-                }
-                write_length_prefixed_bytes(&mut file_bytes, &hash_data)?;
-            }
-            RedisValue::SortedSet(sorted_set) => {
-                file_bytes.push(0x03); // Sorted Set in Ziplist encoding (simplified)
-                write_length_prefixed_string(&mut file_bytes, key)?;
-                let mut ss_data = Vec::new();
-                for (member, score) in sorted_set {
-                    ss_data.extend_from_slice(member);
-                    ss_data.push(0); // separator
-                    ss_data.extend_from_slice(&score.to_string().into_bytes());
-                    ss_data.push(0); // separator
-                }
-                write_length_prefixed_bytes(&mut file_bytes, &ss_data)?;
-            }
-            RedisValue::Null => {
-                // Skip null values
+    // The version is four ASCII digits, as Redis writes it and the loader expects
+    file_bytes.extend_from_slice(b"0011");
+
+    let mut saved_keys = 0;
+    for (index, (kv, exp)) in db::KV.iter().zip(db::EXP.iter()).enumerate() {
+        let kv = kv.read().await;
+        let exp = exp.read().await;
+        if kv.is_empty() {
+            continue;
+        }
+
+        // Write database selector
+        file_bytes.push(0xFE);
+        write_rdb_length(&mut file_bytes, index as u64)?;
+
+        // Write resize hint
+        file_bytes.push(0xFB);
+        write_rdb_length(&mut file_bytes, kv.len() as u64)?;
+        write_rdb_length(&mut file_bytes, exp.len() as u64)?;
+
+        // Write all key-value pairs
+        for (key, value) in kv.iter() {
+            let Some((value_type, payload)) = encode_value(value)? else {
+                // Skip values that have no RDB representation
                 continue;
+            };
+
+            // Check if key has expiry
+            if let Some(expiry_instant) = exp.get(key) {
+                // Calculate expiry timestamp in milliseconds
+                let now = Instant::now();
+                if *expiry_instant > now {
+                    let remaining_duration = *expiry_instant - now;
+                    let expiry_timestamp =
+                        current_timestamp + remaining_duration.as_millis() as u64;
+
+                    // Write expiry in milliseconds
+                    file_bytes.push(0xFC);
+                    file_bytes.extend_from_slice(&expiry_timestamp.to_le_bytes());
+                }
             }
+
+            file_bytes.push(value_type);
+            write_length_prefixed_string(&mut file_bytes, key)?;
+            file_bytes.extend_from_slice(&payload);
         }
+        saved_keys += kv.len();
     }
 
     // Write end of file marker
@@ -663,22 +1235,112 @@ pub async fn save() -> Result<(), String> {
     let checksum = crc64(0, &file_bytes);
     file_bytes.extend_from_slice(&checksum.to_le_bytes());
 
-    // Write all data to file
-    file.write_all(&file_bytes)
-        .map_err(|e| format!("Failed to write RDB file: {}", e))?;
-
-    file.flush()
-        .map_err(|e| format!("Failed to flush RDB file: {}", e))?;
-
-    // Atomically replace the old file with the new one
-    std::fs::rename(temp_path, rdb_path)
-        .map_err(|e| format!("Failed to rename RDB file: {}", e))?;
+    Ok((file_bytes, saved_keys))
+}
 
-    println!("Saved {} keys to RDB file", kv.len());
-    Ok(())
+/// Encode a value as its RDB type byte and payload (everything after the key).
+/// Returns None for values that have no RDB representation.
+pub fn encode_value(value: &RedisValue) -> Result<Option<(u8, Vec<u8>)>, String> {
+    let mut payload = Vec::new();
+    let value_type;
+    match value {
+        RedisValue::String(s) | RedisValue::RawString(s) => {
+            value_type = 0x00; // String encoding
+            write_length_prefixed_bytes(&mut payload, s)?;
+        }
+        RedisValue::List(items) => {
+            value_type = 0x01; // List encoding
+            write_rdb_length(&mut payload, items.len() as u64)?;
+            for item in items {
+                write_length_prefixed_bytes(&mut payload, item)?;
+            }
+        }
+        RedisValue::Set(items) => {
+            value_type = 0x02; // Set encoding
+            write_rdb_length(&mut payload, items.len() as u64)?;
+            for item in items {
+                write_length_prefixed_bytes(&mut payload, item)?;
+            }
+        }
+        RedisValue::Ziplist(data) => {
+            value_type = 0x0A; // List in Ziplist encoding
+            write_length_prefixed_bytes(&mut payload, data)?;
+        }
+        RedisValue::Zipmap(data) => {
+            value_type = 0x09; // Hash in Zipmap encoding
+            write_length_prefixed_bytes(&mut payload, data)?;
+        }
+        RedisValue::Intset(data) => {
+            value_type = 0x0B; // Set in Intset encoding
+            write_length_prefixed_bytes(&mut payload, data)?;
+        }
+        RedisValue::Quicklist(data) => {
+            value_type = 0x0E; // List in Quicklist encoding
+            write_length_prefixed_bytes(&mut payload, data)?;
+        }
+        // For complex types, we'll serialize them as strings for now
+        RedisValue::Integer(i) => {
+            value_type = 0x00; // String encoding
+            let value_bytes = i.to_string().into_bytes();
+            write_length_prefixed_bytes(&mut payload, &value_bytes)?;
+        }
+        RedisValue::Float(f) => {
+            value_type = 0x00; // String encoding
+            let value_bytes = f.to_string().into_bytes();
+            write_length_prefixed_bytes(&mut payload, &value_bytes)?;
+        }
+        RedisValue::Boolean(b) => {
+            value_type = 0x00; // String encoding
+            let value_bytes = b.to_string().into_bytes();
+            write_length_prefixed_bytes(&mut payload, &value_bytes)?;
+        }
+        RedisValue::Hash(hash) if hash.expiries.is_empty() => {
+            value_type = 0x04; // Hash encoding
+            write_rdb_length(&mut payload, hash.fields.len() as u64)?;
+            for (field, value) in &hash.fields {
+                write_length_prefixed_bytes(&mut payload, field)?;
+                write_length_prefixed_bytes(&mut payload, value)?;
+            }
+        }
+        RedisValue::Hash(hash) => {
+            value_type = 0x18; // Hash with field TTLs, see read_value
+            let expiries: HashMap<&[u8], u64> = hash
+                .expiries
+                .iter()
+                .map(|(field, &at)| (field.as_slice(), unix_ms(at)))
+                .collect();
+            let min_expiry = expiries.values().copied().min().unwrap_or_default();
+            payload.extend_from_slice(&min_expiry.to_le_bytes());
+            write_rdb_length(&mut payload, hash.fields.len() as u64)?;
+            for (field, value) in &hash.fields {
+                let ttl = expiries
+                    .get(field.as_slice())
+                    .map_or(0, |expiry| expiry - min_expiry + 1);
+                write_rdb_length(&mut payload, ttl)?;
+                write_length_prefixed_bytes(&mut payload, field)?;
+                write_length_prefixed_bytes(&mut payload, value)?;
+            }
+        }
+        RedisValue::SortedSet(sorted_set) => {
+            value_type = 0x05; // Sorted Set encoding with binary double scores
+            write_rdb_length(&mut payload, sorted_set.len() as u64)?;
+            for (member, score) in sorted_set {
+                write_length_prefixed_bytes(&mut payload, member)?;
+                payload.extend_from_slice(&score.to_le_bytes());
+            }
+        }
+        RedisValue::Stream(stream) => {
+            value_type = RDB_TYPE_STREAM_LISTPACKS_3;
+            write_stream(&mut payload, stream)?;
+        }
+        RedisValue::Null => return Ok(None),
+    }
+    Ok(Some((value_type, payload)))
 }
 
-/// Helper function to write RDB length encoding
+/// Helper function to write RDB length encoding.
+/// Lengths of 2^14 and up follow a 0x80 (32-bit) or 0x81 (64-bit) marker in big-endian
+/// byte order, unlike the rest of the format: Redis writes them with htonl.
 fn write_rdb_length(buf: &mut Vec<u8>, len: u64) -> Result<(), String> {
     if len < 64 {
         // 6-bit length
@@ -690,13 +1352,33 @@ fn write_rdb_length(buf: &mut Vec<u8>, len: u64) -> Result<(), String> {
     } else if len < 4294967296 {
         // 32-bit length
         buf.push(0x80);
-        buf.extend_from_slice(&(len as u32).to_le_bytes());
+        buf.extend_from_slice(&(len as u32).to_be_bytes());
     } else {
-        return Err("Length too large for RDB format".to_string());
+        // 64-bit length
+        buf.push(0x81);
+        buf.extend_from_slice(&len.to_be_bytes());
     }
     Ok(())
 }
 
+/// Unix time in milliseconds of a monotonic instant, as RDB files store expiry times
+fn unix_ms(at: Instant) -> u64 {
+    let now_ms = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64;
+    now_ms + at.saturating_duration_since(Instant::now()).as_millis() as u64
+}
+
+/// The instant of a Unix time in milliseconds, or None if it has already passed
+fn instant_at(unix_ms: u64) -> Option<Instant> {
+    let now_ms = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64;
+    (unix_ms > now_ms).then(|| Instant::now() + std::time::Duration::from_millis(unix_ms - now_ms))
+}
+
 /// Helper function to write length-prefixed string
 fn write_length_prefixed_string(buf: &mut Vec<u8>, s: &str) -> Result<(), String> {
     let bytes = s.as_bytes();
@@ -707,7 +1389,464 @@ fn write_length_prefixed_string(buf: &mut Vec<u8>, s: &str) -> Result<(), String
 
 /// Helper function to write length-prefixed bytes
 fn write_length_prefixed_bytes(buf: &mut Vec<u8>, bytes: &[u8]) -> Result<(), String> {
+    // Like Redis, a string that is the canonical form of a 32-bit integer is stored as one
+    if bytes.len() <= 11 {
+        let value = std::str::from_utf8(bytes)
+            .ok()
+            .and_then(|s| s.parse::<i32>().ok().filter(|v| v.to_string() == s));
+        if let Some(value) = value {
+            if let Ok(v) = i8::try_from(value) {
+                buf.extend_from_slice(&[0xC0, v as u8]);
+            } else if let Ok(v) = i16::try_from(value) {
+                buf.push(0xC1);
+                buf.extend_from_slice(&v.to_le_bytes());
+            } else {
+                buf.push(0xC2);
+                buf.extend_from_slice(&value.to_le_bytes());
+            }
+            return Ok(());
+        }
+    }
     write_rdb_length(buf, bytes.len() as u64)?;
     buf.extend_from_slice(bytes);
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(value: &RedisValue) -> RedisValue {
+        let dump = dump_value(value).unwrap().unwrap();
+        restore_value(&dump).unwrap()
+    }
+
+    /// A stream of one entry, 1-1 field=value, in the bytes Redis 7.2 writes for it
+    fn redis_stream_payload() -> Vec<u8> {
+        let listpack = [
+            &[0x25, 0, 0, 0, 10, 0][..],
+            &[0x01, 0x01, 0x00, 0x01, 0x01, 0x01], // 1 live, 0 deleted, 1 master field
+            &[0x85],
+            b"field",
+            &[0x06, 0x00, 0x01], // the field, then the master entry's end
+            &[0x02, 0x01, 0x00, 0x01, 0x00, 0x01], // same fields, at the master ID
+            &[0x85],
+            b"value",
+            &[0x06, 0x04, 0x01, 0xFF], // the value, then 4 listpack elements taken
+        ]
+        .concat();
+        let mut payload = vec![0x01, 0x10, 0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 1];
+        payload.push(listpack.len() as u8);
+        payload.extend_from_slice(&listpack);
+        // Length, last ID, first ID, largest deleted ID, entries added, groups
+        payload.extend_from_slice(&[1, 1, 1, 1, 1, 0, 0, 1, 0]);
+        payload
+    }
+
+    #[test]
+    fn streams_are_written_as_redis_writes_them() {
+        let stream = StreamValue {
+            entries: vec![((1, 1), vec![(b"field".to_vec(), b"value".to_vec())])],
+            last_id: (1, 1),
+        };
+        let (value_type, payload) = encode_value(&RedisValue::Stream(stream)).unwrap().unwrap();
+        assert_eq!(value_type, RDB_TYPE_STREAM_LISTPACKS_3);
+        assert_eq!(payload, redis_stream_payload());
+    }
+
+    #[test]
+    fn streams_round_trip_across_nodes() {
+        // Enough entries for several nodes, with fields that change along the way,
+        // sequence numbers that go down as the milliseconds go up, and long values
+        let entries: Vec<StreamEntry> = (0..250u64)
+            .map(|i| {
+                let id = (1_700_000_000_000 + i / 3, 1000 - i % 3);
+                let mut fields = vec![(b"n".to_vec(), i.to_string().into_bytes())];
+                if i % 7 == 0 {
+                    fields.push((b"extra".to_vec(), vec![b'e'; 5000]));
+                }
+                (id, fields)
+            })
+            .collect();
+        let stream = StreamValue {
+            entries: entries.clone(),
+            last_id: (1_800_000_000_000, 5),
+        };
+        let RedisValue::Stream(loaded) = round_trip(&RedisValue::Stream(stream)) else {
+            panic!("expected a stream");
+        };
+        assert_eq!(loaded.entries, entries);
+        assert_eq!(loaded.last_id, (1_800_000_000_000, 5));
+
+        let RedisValue::Stream(empty) = round_trip(&RedisValue::Stream(StreamValue {
+            entries: Vec::new(),
+            last_id: (7, 7),
+        })) else {
+            panic!("expected a stream");
+        };
+        assert!(empty.entries.is_empty());
+        assert_eq!(empty.last_id, (7, 7));
+    }
+
+    #[test]
+    fn older_stream_types_and_consumer_groups_load() {
+        // Redis 5's layout: no first ID, deleted ID or entries added
+        let mut payload = redis_stream_payload();
+        payload.truncate(payload.len() - 6);
+        // One group with a pending entry and a consumer that has it
+        payload.extend_from_slice(&[1, 0x05]);
+        payload.extend_from_slice(b"group");
+        payload.extend_from_slice(&[1, 1, 1]); // last delivered ID, 1 pending entry
+        let raw_id = [&[0u8; 15][..], &[1]].concat();
+        payload.extend_from_slice(&raw_id);
+        payload.extend_from_slice(&[0u8; 8]); // delivery time
+        payload.push(1); // delivery count
+        payload.extend_from_slice(&[1, 0x01, b'c']);
+        payload.extend_from_slice(&[0u8; 8]); // seen time
+        payload.push(1);
+        payload.extend_from_slice(&raw_id);
+        let mut reader = &payload[..];
+        let value = read_value(RDB_TYPE_STREAM_LISTPACKS, &mut reader, &mut Vec::new()).unwrap();
+        assert!(reader.is_empty());
+        let RedisValue::Stream(stream) = value else {
+            panic!("expected a stream");
+        };
+        assert_eq!(
+            stream.entries,
+            vec![((1, 1), vec![(b"field".to_vec(), b"value".to_vec())])]
+        );
+    }
+
+    #[test]
+    fn deleted_stream_entries_are_left_out() {
+        let mut payload = redis_stream_payload();
+        // Mark the entry deleted: its flags element follows the master entry
+        let flags = payload.windows(2).position(|w| w == [0x02, 0x01]).unwrap();
+        payload[flags] = 0x03;
+        let RedisValue::Stream(stream) = read_value(
+            RDB_TYPE_STREAM_LISTPACKS_3,
+            &mut &payload[..],
+            &mut Vec::new(),
+        )
+        .unwrap() else {
+            panic!("expected a stream");
+        };
+        assert!(stream.entries.is_empty());
+        assert_eq!(stream.last_id, (1, 1));
+    }
+
+    #[test]
+    fn listpack_integers_and_strings_decode_as_encoded() {
+        let numbers = [
+            0,
+            127,
+            128,
+            -1,
+            -4096,
+            4095,
+            4096,
+            -32768,
+            32767,
+            -8388608,
+            8388607,
+            8388608,
+            i32::MIN as i64,
+            i32::MAX as i64 + 1,
+            i64::MIN,
+            i64::MAX,
+        ];
+        let long = vec![b's'; 70_000];
+        let medium = vec![b'm'; 200];
+        let mut items: Vec<ListpackItem> = numbers.iter().map(|n| ListpackItem::Int(*n)).collect();
+        items.push(ListpackItem::Str(b"short"));
+        items.push(ListpackItem::Str(&medium));
+        items.push(ListpackItem::Str(&long));
+        let decoded = decode_listpack(&encode_listpack(&items)).unwrap();
+        let mut expected: Vec<Vec<u8>> =
+            numbers.iter().map(|n| n.to_string().into_bytes()).collect();
+        expected.extend([b"short".to_vec(), medium, long]);
+        assert_eq!(decoded, expected);
+    }
+
+    /// A DUMP payload around raw RDB value bytes, with a valid version and checksum
+    fn payload(value_type: u8, body: &[u8]) -> Vec<u8> {
+        let mut dump = vec![value_type];
+        dump.extend_from_slice(body);
+        dump.extend_from_slice(&DUMP_RDB_VERSION.to_le_bytes());
+        let checksum = crc64(0, &dump);
+        dump.extend_from_slice(&checksum.to_le_bytes());
+        dump
+    }
+
+    #[test]
+    fn dump_and_restore_round_trip() {
+        let RedisValue::String(s) = round_trip(&RedisValue::String(b"hello".to_vec())) else {
+            panic!("expected a string");
+        };
+        assert_eq!(s, b"hello");
+
+        let items = vec![b"a".to_vec(), b"b".to_vec(), vec![b'x'; 20000]];
+        let RedisValue::List(list) = round_trip(&RedisValue::List(items.clone())) else {
+            panic!("expected a list");
+        };
+        assert_eq!(list, items);
+
+        let members = vec![(b"m".to_vec(), 1.5), (b"n".to_vec(), f64::INFINITY)];
+        let RedisValue::SortedSet(zset) = round_trip(&RedisValue::SortedSet(members.clone()))
+        else {
+            panic!("expected a sorted set");
+        };
+        assert_eq!(zset, members);
+
+        let fields = HashMap::from([(b"f".to_vec(), b"v".to_vec())]);
+        let RedisValue::Hash(hash) = round_trip(&RedisValue::Hash(fields.clone().into())) else {
+            panic!("expected a hash");
+        };
+        assert_eq!(hash.fields, fields);
+    }
+
+    #[test]
+    fn integer_strings_are_written_int_encoded() {
+        for (value, encoded) in [
+            ("-1", &[0xC0, 0xFF][..]),
+            ("12345", &[0xC1, 0x39, 0x30]),
+            ("2147483647", &[0xC2, 0xFF, 0xFF, 0xFF, 0x7F]),
+            ("2147483648", b"\x0a2147483648"),
+            ("007", b"\x03007"),
+        ] {
+            let string = RedisValue::String(value.as_bytes().to_vec());
+            let (_, payload) = encode_value(&string).unwrap().unwrap();
+            assert_eq!(payload, encoded, "{}", value);
+            let RedisValue::String(s) = round_trip(&string) else {
+                panic!("expected a string");
+            };
+            assert_eq!(s, value.as_bytes());
+        }
+    }
+
+    #[test]
+    fn restore_rejects_a_bad_checksum_or_version() {
+        let mut dump = dump_value(&RedisValue::String(b"v".to_vec()))
+            .unwrap()
+            .unwrap();
+        let last = dump.len() - 1;
+        dump[last] ^= 1;
+        assert_eq!(
+            restore_value(&dump).unwrap_err(),
+            "DUMP payload version or checksum are wrong"
+        );
+        assert!(restore_value(b"short").is_err());
+    }
+
+    #[test]
+    fn restore_rejects_lengths_past_the_payload() {
+        // A list claiming 2^40 elements, a string claiming 2^40 bytes and a compressed
+        // string claiming 2^40 bytes once decompressed, all in a few bytes of payload
+        let huge = [0x81, 0, 0, 1, 0, 0, 0, 0, 0];
+        for (value_type, body) in [
+            (0x01, huge.to_vec()),
+            (0x00, huge.to_vec()),
+            (0x00, [&[0xC3, 0x01][..], &huge, &[0x00]].concat()),
+        ] {
+            assert_eq!(
+                restore_value(&payload(value_type, &body)).unwrap_err(),
+                "Bad data format"
+            );
+        }
+    }
+
+    #[test]
+    fn restore_rejects_trailing_bytes() {
+        assert_eq!(
+            restore_value(&payload(0x00, &[0x01, b'a', b'b'])).unwrap_err(),
+            "Bad data format"
+        );
+    }
+
+    /// "abc" then one back reference, of extended length, that repeats it to 22 bytes
+    const LZF_ABC: &[u8] = &[0x02, b'a', b'b', b'c', 0xE0, 0x0A, 0x02];
+
+    #[test]
+    fn lzf_back_references_use_the_extended_length() {
+        let expected = b"abcabcabcabcabcabcabca".to_vec();
+        assert_eq!(lzf_decompress(LZF_ABC, expected.len()), Ok(expected));
+        // A short back reference, ending the input
+        assert_eq!(
+            lzf_decompress(&[0x01, b'x', b'y', 0x20, 0x01], 5),
+            Ok(b"xyxyx".to_vec())
+        );
+    }
+
+    #[test]
+    fn lzf_rejects_data_that_doesnt_match_its_length() {
+        assert!(lzf_decompress(LZF_ABC, 21).is_err());
+        assert!(lzf_decompress(LZF_ABC, 23).is_err());
+        // Missing the distance byte, or reaching back before the start
+        assert!(lzf_decompress(&[0x00, b'x', 0x20], 3).is_err());
+        assert!(lzf_decompress(&[0x00, b'x', 0x20, 0x05], 3).is_err());
+    }
+
+    #[test]
+    fn restore_decompresses_lzf_strings() {
+        let body = [&[0xC3, LZF_ABC.len() as u8, 22][..], LZF_ABC].concat();
+        let RedisValue::String(s) = restore_value(&payload(0x00, &body)).unwrap() else {
+            panic!("expected a string");
+        };
+        assert_eq!(s, b"abcabcabcabcabcabcabca");
+    }
+
+    #[test]
+    fn long_lengths_are_big_endian() {
+        let mut buf = Vec::new();
+        write_rdb_length(&mut buf, 0x12345678).unwrap();
+        assert_eq!(buf, [0x80, 0x12, 0x34, 0x56, 0x78]);
+        buf.clear();
+        write_rdb_length(&mut buf, 1 << 40).unwrap();
+        assert_eq!(buf, [0x81, 0, 0, 1, 0, 0, 0, 0, 0]);
+        for len in [0, 63, 64, 16383, 16384, 0x12345678, 1 << 40] {
+            let mut buf = Vec::new();
+            write_rdb_length(&mut buf, len).unwrap();
+            assert_eq!(
+                read_rdb_length(&mut &buf[..], &mut Vec::new()).unwrap(),
+                len
+            );
+        }
+    }
+
+    /// An RDB file laid out by hand the way Redis writes one: ASCII version, a 32-bit
+    /// big-endian string length and a millisecond expiry
+    fn redis_file() -> Vec<u8> {
+        let mut file = b"REDIS0011".to_vec();
+        file.extend_from_slice(&[0xFE, 0x00, 0xFB, 0x02, 0x01]);
+        file.extend_from_slice(&[0x00, 0x05]);
+        file.extend_from_slice(b"large");
+        file.extend_from_slice(&[0x80, 0x00, 0x00, 0x40, 0x00]);
+        file.extend_from_slice(&[b'x'; 16384]);
+        file.push(0xFC);
+        file.extend_from_slice(&4102444800000u64.to_le_bytes());
+        file.extend_from_slice(&[0x00, 0x03]);
+        file.extend_from_slice(b"due");
+        file.extend_from_slice(&[0x01, b'1']);
+        file.push(0xFF);
+        let checksum = crc64(0, &file);
+        file.extend_from_slice(&checksum.to_le_bytes());
+        file
+    }
+
+    #[test]
+    fn a_redis_layout_file_loads() {
+        let loaded = RdbParser::parse(&mut &redis_file()[..], false).unwrap();
+        assert_eq!(loaded.data.len(), 2);
+        let (key, large) = &loaded.data[0];
+        assert_eq!(key, "large");
+        let RedisValue::String(s) = &large.value else {
+            panic!("expected a string");
+        };
+        assert_eq!(s.len(), 16384);
+        let (key, due) = &loaded.data[1];
+        assert_eq!(key, "due");
+        assert_eq!(due.expiry, Some(4102444800000));
+    }
+
+    #[test]
+    fn a_truncated_file_keeps_the_keys_before_the_cut_when_lenient() {
+        // Cut inside the value of "due", the second key
+        let file = redis_file();
+        let cut = &file[..file.len() - 11];
+        let error = RdbParser::parse(&mut &cut[..], false).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::UnexpectedEof);
+        let loaded = RdbParser::parse(&mut &cut[..], true).unwrap();
+        assert_eq!(loaded.data.len(), 1);
+        assert_eq!(loaded.data[0].0, "large");
+
+        // A cut inside the first value leaves nothing to keep
+        let loaded = RdbParser::parse(&mut &file[..1000], true).unwrap();
+        assert!(loaded.data.is_empty());
+        // The header itself must be whole, though
+        assert!(RdbParser::parse(&mut &file[..7], true).is_err());
+    }
+
+    #[test]
+    fn versions_past_the_newest_known_are_refused() {
+        let mut file = redis_file();
+        file[5..9].copy_from_slice(b"0013");
+        assert!(RdbParser::parse(&mut &file[..], false).is_err());
+        file[5..9].copy_from_slice(&[0, 0, 0, 12]);
+        assert!(RdbParser::parse(&mut &file[..], false).is_err());
+    }
+
+    #[test]
+    fn files_in_the_old_layout_still_load() {
+        let sample = Path::new(env!("CARGO_MANIFEST_DIR")).join("rdb/database.rdb");
+        let loaded = RdbParser::parse(&mut &std::fs::read(sample).unwrap()[..], false).unwrap();
+        let (key, entry) = &loaded.data[0];
+        assert_eq!(key, "foo");
+        assert!(matches!(&entry.value, RedisValue::String(s) if s == b"bar"));
+
+        // Binary version, and the 32-bit length in little-endian order
+        let mut file = redis_file();
+        file[5..9].copy_from_slice(&LEGACY_VERSION);
+        let at = file
+            .windows(5)
+            .position(|w| w == [0x80, 0, 0, 0x40, 0])
+            .unwrap();
+        file[at + 1..at + 5].copy_from_slice(&16384u32.to_le_bytes());
+        let body = file.len() - 8;
+        let checksum = crc64(0, &file[..body]);
+        file[body..].copy_from_slice(&checksum.to_le_bytes());
+        let loaded = RdbParser::parse(&mut &file[..], false).unwrap();
+        assert!(matches!(&loaded.data[0].1.value, RedisValue::String(s) if s.len() == 16384));
+    }
+
+    #[tokio::test]
+    async fn a_snapshot_loads_back() {
+        let large = vec![b'v'; 70_000];
+        db::set(
+            b"rdb:string".to_vec(),
+            large.clone(),
+            None,
+            false,
+            false,
+            false,
+        )
+        .await
+        .unwrap();
+        let at = 4102444800000;
+        db::set(
+            b"rdb:due".to_vec(),
+            b"1".to_vec(),
+            Some(at),
+            false,
+            false,
+            false,
+        )
+        .await
+        .unwrap();
+        let items: Vec<Vec<u8>> = (0..1000).map(|i| i.to_string().into_bytes()).collect();
+        db::push("rdb:list", items.clone(), false).await.unwrap();
+
+        let (image, _) = snapshot().await.unwrap();
+        assert_eq!(&image[..9], b"REDIS0011");
+        let loaded = RdbParser::parse(&mut &image[..], false).unwrap();
+        let entry = |key: &str| {
+            &loaded
+                .data
+                .iter()
+                .find(|(k, _)| k == key)
+                .unwrap_or_else(|| panic!("{} is missing", key))
+                .1
+        };
+        let RedisValue::String(s) = &entry("rdb:string").value else {
+            panic!("expected a string");
+        };
+        assert_eq!(s, &large);
+        assert_eq!(entry("rdb:string").expiry, None);
+        // Expiry times go through Instant, which rounds to the millisecond
+        let expiry = entry("rdb:due").expiry.unwrap();
+        assert!(expiry.abs_diff(at as u64) <= 1, "{}", expiry);
+        let RedisValue::List(list) = &entry("rdb:list").value else {
+            panic!("expected a list");
+        };
+        assert_eq!(list, &items);
+    }
+}
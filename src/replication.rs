@@ -0,0 +1,392 @@
+//! Replication identity and offset, reported in the INFO `# Replication` section,
+//! and both sides of replication: as a master, replicas that PSYNC receive an RDB
+//! snapshot followed by every write command; as a replica, REPLICAOF connects to a
+//! master, loads its snapshot and then applies the commands it streams.
+use crate::commands::dispatch;
+use crate::config;
+use crate::db;
+use crate::model::client_state::ClientState;
+use crate::output::OutputSender;
+use crate::rdb;
+use crate::resp::parser::FrameParser;
+use crate::resp::Frame;
+use once_cell::sync::Lazy;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::Mutex;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::task::AbortHandle;
+use tokio::time::Duration;
+
+/// Replication ID of the current dataset history, 40 hex characters like Redis'
+static REPLID: Lazy<Mutex<String>> = Lazy::new(|| Mutex::new(random_id()));
+/// Bytes of write commands propagated to replicas,
+/// or, on a replica, bytes of the replication stream processed so far
+static REPL_OFFSET: AtomicU64 = AtomicU64::new(0);
+
+/// A replica fed through the output queue of the connection it PSYNCed on
+struct Replica {
+    client_id: u64,
+    ip: String,
+    port: u16, // listening port announced with REPLCONF, 0 if none
+    tx: OutputSender,
+    ack_offset: u64,
+}
+
+static REPLICAS: Lazy<Mutex<Vec<Replica>>> = Lazy::new(|| Mutex::new(Vec::new()));
+/// Database of the last propagated command, so SELECT is only sent when it changes
+static PROPAGATED_DB: AtomicUsize = AtomicUsize::new(usize::MAX);
+
+/// Master this server replicates from, as (host, port); None when it is a master itself
+static MASTER: Lazy<Mutex<Option<(String, u16)>>> = Lazy::new(|| Mutex::new(None));
+/// Whether the replica is connected to its master and in sync
+static LINK_UP: AtomicBool = AtomicBool::new(false);
+/// Task maintaining the link to the master, aborted when the master changes
+static LINK_TASK: Lazy<Mutex<Option<AbortHandle>>> = Lazy::new(|| Mutex::new(None));
+
+/// 40 random hex characters, the format of both replication ids and the server's run id
+pub fn random_id() -> String {
+    let mut id = String::with_capacity(48);
+    for _ in 0..3 {
+        id.push_str(&format!("{:016x}", db::random_u64()));
+    }
+    id.truncate(40);
+    id
+}
+
+pub fn replid() -> String {
+    REPLID.lock().unwrap().clone()
+}
+
+/// Start a new replication history, as done by DEBUG CHANGE-REPL-ID
+pub fn change_replid() {
+    *REPLID.lock().unwrap() = random_id();
+}
+
+pub fn offset() -> u64 {
+    REPL_OFFSET.load(Ordering::SeqCst)
+}
+
+/// Account for `bytes` bytes of the replication stream
+pub fn advance(bytes: u64) {
+    REPL_OFFSET.fetch_add(bytes, Ordering::SeqCst);
+}
+
+/// Send a write command, run in the current database, to every replica
+pub fn propagate(command: Vec<Frame>) {
+    let mut replicas = REPLICAS.lock().unwrap();
+    let db = db::current_db();
+    let mut bytes = Vec::new();
+    if PROPAGATED_DB.swap(db, Ordering::SeqCst) != db {
+        Frame::Array(Some(vec![
+            Frame::BulkString(Some(b"SELECT".to_vec())),
+            Frame::BulkString(Some(db.to_string().into_bytes())),
+        ]))
+        .encode_into(&mut bytes);
+    }
+    Frame::Array(Some(command)).encode_into(&mut bytes);
+    advance(bytes.len() as u64);
+    // A failed send means the replica's connection is gone
+    replicas.retain(|replica| replica.tx.send(bytes.clone()).is_ok());
+}
+
+/// Answer a PSYNC with a full resynchronization: the replication id and offset,
+/// then the RDB snapshot, after which the replica gets every propagated command
+pub fn full_resync(client_id: u64, ip: String, port: u16, tx: OutputSender, rdb: Vec<u8>) {
+    let mut replicas = REPLICAS.lock().unwrap();
+    let mut payload = format!("+FULLRESYNC {} {}\r\n", replid(), offset()).into_bytes();
+    // Unlike a bulk string, the snapshot is not followed by CRLF
+    payload.extend(format!("${}\r\n", rdb.len()).into_bytes());
+    payload.extend(rdb);
+    if tx.send(payload).is_err() {
+        return;
+    }
+    replicas.retain(|replica| replica.client_id != client_id);
+    replicas.push(Replica {
+        client_id,
+        ip,
+        port,
+        tx,
+        ack_offset: 0,
+    });
+    // The replica's stream starts without a database selected
+    PROPAGATED_DB.store(usize::MAX, Ordering::SeqCst);
+}
+
+/// Record the offset a replica reported with REPLCONF ACK
+pub fn record_ack(client_id: u64, offset: u64) {
+    let mut replicas = REPLICAS.lock().unwrap();
+    if let Some(replica) = replicas.iter_mut().find(|r| r.client_id == client_id) {
+        replica.ack_offset = offset;
+    }
+}
+
+/// Forget a replica whose connection closed
+pub fn remove_replica(client_id: u64) {
+    REPLICAS
+        .lock()
+        .unwrap()
+        .retain(|replica| replica.client_id != client_id);
+}
+
+/// Whether any replica is attached, so writes have to be propagated
+pub fn has_replicas() -> bool {
+    !REPLICAS.lock().unwrap().is_empty()
+}
+
+/// Whether this server is replicating from a master
+pub fn is_replica() -> bool {
+    MASTER.lock().unwrap().is_some()
+}
+
+/// Start replicating from host:port, dropping the link to any previous master
+pub fn replicate_from(host: String, port: u16) {
+    *MASTER.lock().unwrap() = Some((host.clone(), port));
+    LINK_UP.store(false, Ordering::SeqCst);
+    let task = tokio::spawn(maintain_link(host, port));
+    if let Some(previous) = LINK_TASK.lock().unwrap().replace(task.abort_handle()) {
+        previous.abort();
+    }
+}
+
+/// Stop replicating and become a master with a history of its own, as done by REPLICAOF NO ONE
+pub fn promote() {
+    if MASTER.lock().unwrap().take().is_none() {
+        return;
+    }
+    if let Some(task) = LINK_TASK.lock().unwrap().take() {
+        task.abort();
+    }
+    LINK_UP.store(false, Ordering::SeqCst);
+    change_replid();
+}
+
+/// Keep a link to the master up, reconnecting after a second whenever it drops
+async fn maintain_link(host: String, port: u16) {
+    loop {
+        if let Err(e) = run_link(&host, port).await {
+            warn!("Replication link with {}:{} failed: {}", host, port, e);
+        }
+        LINK_UP.store(false, Ordering::SeqCst);
+        tokio::time::sleep(Duration::from_secs(1)).await;
+    }
+}
+
+/// Bytes read from the master that haven't been consumed yet
+struct MasterStream {
+    socket: TcpStream,
+    pending: Vec<u8>,
+}
+
+impl MasterStream {
+    async fn fill(&mut self) -> Result<(), String> {
+        let mut buf = [0u8; 4096];
+        let n = self
+            .socket
+            .read(&mut buf)
+            .await
+            .map_err(|e| e.to_string())?;
+        if n == 0 {
+            return Err("connection closed by master".to_string());
+        }
+        self.pending.extend_from_slice(&buf[..n]);
+        Ok(())
+    }
+
+    /// Read one CRLF-terminated line, without the CRLF
+    async fn read_line(&mut self) -> Result<String, String> {
+        loop {
+            if let Some(end) = self.pending.windows(2).position(|w| w == b"\r\n") {
+                let line: Vec<u8> = self.pending.drain(..end + 2).take(end).collect();
+                return Ok(String::from_utf8_lossy(&line).into_owned());
+            }
+            self.fill().await?;
+        }
+    }
+
+    async fn read_exact(&mut self, len: usize) -> Result<Vec<u8>, String> {
+        while self.pending.len() < len {
+            self.fill().await?;
+        }
+        Ok(self.pending.drain(..len).collect())
+    }
+
+    /// Send a command and return the master's single-line reply
+    async fn command(&mut self, args: &[&str]) -> Result<String, String> {
+        let frame = Frame::Array(Some(
+            args.iter()
+                .map(|a| Frame::BulkString(Some(a.as_bytes().to_vec())))
+                .collect(),
+        ));
+        self.socket
+            .write_all(&frame.encode())
+            .await
+            .map_err(|e| e.to_string())?;
+        let reply = self.read_line().await?;
+        if reply.starts_with('-') {
+            return Err(format!("master replied {}", reply));
+        }
+        Ok(reply)
+    }
+
+    /// Read the RDB snapshot that follows +FULLRESYNC, either length-prefixed
+    /// or, for diskless sync, delimited by an EOF:<mark> marker
+    async fn read_rdb(&mut self) -> Result<Vec<u8>, String> {
+        // The master may send bare newlines to keep the link alive while it prepares the snapshot
+        loop {
+            let blank = self.pending.iter().take_while(|&&b| b == b'\n').count();
+            self.pending.drain(..blank);
+            if !self.pending.is_empty() {
+                break;
+            }
+            self.fill().await?;
+        }
+        let header = self.read_line().await?;
+        let Some(spec) = header.strip_prefix('$') else {
+            return Err(format!("unexpected RDB header {:?}", header));
+        };
+        if let Some(mark) = spec.strip_prefix("EOF:") {
+            let mark = mark.as_bytes().to_vec();
+            loop {
+                if let Some(end) = self.pending.windows(mark.len()).position(|w| w == mark) {
+                    let payload = self.pending.drain(..end).collect();
+                    self.pending.drain(..mark.len());
+                    return Ok(payload);
+                }
+                self.fill().await?;
+            }
+        }
+        let len = spec
+            .parse::<usize>()
+            .map_err(|_| format!("invalid RDB length {:?}", spec))?;
+        self.read_exact(len).await
+    }
+}
+
+/// Handshake with the master, load its snapshot, then apply its command stream until it drops
+async fn run_link(host: &str, port: u16) -> Result<(), String> {
+    let socket = TcpStream::connect((host, port))
+        .await
+        .map_err(|e| e.to_string())?;
+    let mut master = MasterStream {
+        socket,
+        pending: Vec::new(),
+    };
+
+    master.command(&["PING"]).await?;
+    let listening_port = config::get_config().port.to_string();
+    master
+        .command(&["REPLCONF", "listening-port", &listening_port])
+        .await?;
+    master
+        .command(&["REPLCONF", "capa", "eof", "capa", "psync2"])
+        .await?;
+    let reply = master.command(&["PSYNC", "?", "-1"]).await?;
+    let mut parts = reply.split_whitespace();
+    let (Some("+FULLRESYNC"), Some(master_replid), Some(master_offset)) =
+        (parts.next(), parts.next(), parts.next())
+    else {
+        return Err(format!("unexpected PSYNC reply {:?}", reply));
+    };
+    let master_offset = master_offset
+        .parse::<u64>()
+        .map_err(|_| format!("invalid offset in {:?}", reply))?;
+
+    let payload = master.read_rdb().await?;
+    let snapshot = rdb::RdbParser::load_from_reader(&mut payload.as_slice())
+        .map_err(|e| format!("failed to parse the master's RDB: {}", e))?;
+    db::load_from_rdb(snapshot).await?;
+    *REPLID.lock().unwrap() = master_replid.to_string();
+    REPL_OFFSET.store(master_offset, Ordering::SeqCst);
+    LINK_UP.store(true, Ordering::SeqCst);
+    info!("Synchronized with master {}:{}", host, port);
+
+    // Apply the propagated commands; their replies are not sent back
+    let mut state = ClientState::new();
+    state.authenticated = true;
+    state.master_link = true;
+    state.addr = format!("{}:{}", host, port);
+    let mut parser = FrameParser::new();
+    parser.feed(&std::mem::take(&mut master.pending));
+    loop {
+        loop {
+            let before = parser.buffered();
+            let frame = match parser.parse() {
+                Ok(Some(frame)) => frame,
+                Ok(None) => break,
+                Err(e) => return Err(format!("protocol error in the replication stream: {}", e)),
+            };
+            let consumed = (before - parser.buffered()) as u64;
+            if is_getack(&frame) {
+                // Report the offset processed before this GETACK
+                let ack = Frame::Array(Some(vec![
+                    Frame::BulkString(Some(b"REPLCONF".to_vec())),
+                    Frame::BulkString(Some(b"ACK".to_vec())),
+                    Frame::BulkString(Some(offset().to_string().into_bytes())),
+                ]));
+                master
+                    .socket
+                    .write_all(&ack.encode())
+                    .await
+                    .map_err(|e| e.to_string())?;
+            } else {
+                dispatch(frame, &mut state).await;
+            }
+            advance(consumed);
+        }
+        master.fill().await?;
+        parser.feed(&std::mem::take(&mut master.pending));
+    }
+}
+
+/// Whether a frame is REPLCONF GETACK, the master asking for our offset
+fn is_getack(frame: &Frame) -> bool {
+    match frame {
+        Frame::Array(Some(items)) => matches!(
+            items.as_slice(),
+            [Frame::BulkString(Some(cmd)), Frame::BulkString(Some(sub)), ..]
+                if cmd.eq_ignore_ascii_case(b"REPLCONF") && sub.eq_ignore_ascii_case(b"GETACK")
+        ),
+        _ => false,
+    }
+}
+
+/// Render the `# Replication` section of INFO
+pub fn info_section() -> String {
+    let mut out = String::from("# Replication\r\n");
+    let mut field = |name: &str, value: String| out.push_str(&format!("{}:{}\r\n", name, value));
+    match MASTER.lock().unwrap().clone() {
+        Some((host, port)) => {
+            let link = if LINK_UP.load(Ordering::SeqCst) {
+                "up"
+            } else {
+                "down"
+            };
+            field("role", "slave".to_string());
+            field("master_host", host);
+            field("master_port", port.to_string());
+            field("master_link_status", link.to_string());
+            field("slave_repl_offset", offset().to_string());
+            field("slave_read_only", "1".to_string());
+        }
+        None => field("role", "master".to_string()),
+    }
+    let replicas = REPLICAS.lock().unwrap();
+    field("connected_slaves", replicas.len().to_string());
+    for (i, r) in replicas.iter().enumerate() {
+        field(
+            &format!("slave{}", i),
+            format!(
+                "ip={},port={},state=online,offset={},lag=0",
+                r.ip, r.port, r.ack_offset
+            ),
+        );
+    }
+    drop(replicas);
+    field("master_failover_state", "no-failover".to_string());
+    field("master_replid", replid());
+    field("master_replid2", "0".repeat(40));
+    field("master_repl_offset", offset().to_string());
+    field("second_repl_offset", "-1".to_string());
+    out
+}
@@ -1,11 +1,46 @@
+use crate::config;
 use crate::resp::types::Frame;
 use bytes::Buf;
 use bytes::BytesMut;
 
 /// RESP parser for parsing RESP frames from a byte stream.
 /// It supports both RESP2 and RESP3 protocols.
+/// Parsing resumes where the last call stopped: each line and payload is consumed
+/// as soon as it is complete, and aggregates still waiting for elements are kept
+/// on a stack, so a frame split over many reads is never parsed again from its start.
 pub struct FrameParser {
     buf: BytesMut,
+    scanned: usize, // leading bytes of buf already searched for a newline, none found
+    open: Vec<Aggregate>, // aggregates still missing elements, the innermost last
+    payload: Option<Payload>, // bulk payload whose header has been read
+    in_frame: usize, // bytes already consumed into the frame being parsed
+    discarding: bool, // dropping the rest of a line a protocol error was found in
+    failed: bool,   // a protocol error left the stream unparseable, so input is dropped
+}
+
+/// An aggregate whose header has been read, with the elements parsed for it so far
+struct Aggregate {
+    kind: u8,         // its type byte: '*', '~', '>', '%' or '|'
+    remaining: usize, // elements still to come, two per pair for maps and attributes
+    items: Vec<Frame>,
+}
+
+/// A bulk payload of `len` bytes, taken once they and their CRLF have all arrived
+struct Payload {
+    kind: PayloadKind,
+    len: usize,
+}
+
+enum PayloadKind {
+    String,
+    Error,
+    Verbatim(String), // with its subtype, such as txt
+}
+
+impl Default for FrameParser {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl FrameParser {
@@ -13,333 +48,593 @@ impl FrameParser {
     pub fn new() -> Self {
         FrameParser {
             buf: BytesMut::with_capacity(4096),
+            scanned: 0,
+            open: Vec::new(),
+            payload: None,
+            in_frame: 0,
+            discarding: false,
+            failed: false,
         }
     }
 
     /// Feeds data into the parser's buffer.
+    /// While a large bulk payload is arriving, room for all of it is reserved at once
+    /// (its length was checked against proto-max-bulk-len), rather than the buffer
+    /// reallocating over and over as the chunks come in.
     pub fn feed(&mut self, data: &[u8]) {
+        if self.failed {
+            return;
+        }
+        self.buf.reserve(self.pending().max(data.len()));
         self.buf.extend_from_slice(data);
     }
 
+    /// Bytes of a bulk payload still to arrive, so the connection can read them in
+    /// larger chunks
+    pub fn pending(&self) -> usize {
+        self.payload.as_ref().map_or(0, |payload| {
+            (payload.len + 2).saturating_sub(self.buf.len())
+        })
+    }
+
+    /// Number of bytes fed but not returned as part of a frame yet
+    pub fn buffered(&self) -> usize {
+        self.buf.len() + self.in_frame
+    }
+
     /// Parses the buffer and returns a Frame if available.
-    /// Returns None if the buffer is empty or if no complete frame can be parsed.
-    /// Returns an error if the buffer contains invalid RESP data.
+    /// Returns None if the buffer is empty or if no complete frame can be parsed;
+    /// what was parsed of an incomplete frame is kept until the rest is fed.
+    /// Returns an error if the buffer contains invalid RESP data, nests aggregates
+    /// deeper than proto-max-nesting or holds a line longer than proto-max-inline-len.
+    /// After an over-long inline command, the rest of its line is dropped, however
+    /// much of it is still to arrive, and parsing resumes at the next line. Any other
+    /// error leaves no telling where the next command starts, since the bytes after it
+    /// may be the payload of a bulk string, so everything after it is dropped and
+    /// `failed` reports that the connection has to be closed, as Redis does.
     pub fn parse(&mut self) -> Result<Option<Frame>, String> {
-        if self.buf.is_empty() {
+        loop {
+            if self.discarding && !self.skip_line() {
+                return Ok(None);
+            }
+            if let Some(payload) = &self.payload {
+                let needed = payload.len + 2;
+                if self.buf.len() < needed {
+                    return Ok(None);
+                }
+                let payload = self.payload.take().expect("checked above");
+                let data = self.buf.split_to(payload.len).to_vec();
+                self.buf.advance(2); // the CRLF after the payload
+                self.in_frame += needed;
+                let frame = match payload.kind {
+                    PayloadKind::String => Frame::BulkString(Some(data)),
+                    PayloadKind::Error => {
+                        Frame::BulkError(String::from_utf8_lossy(&data).into_owned())
+                    }
+                    PayloadKind::Verbatim(subtype) => Frame::VerbatimString { subtype, data },
+                };
+                if let Some(frame) = self.complete(frame) {
+                    return Ok(Some(frame));
+                }
+                continue;
+            }
+
+            let Some(&kind) = self.buf.first() else {
+                return Ok(None);
+            };
+            if self.open.len() > config::get_proto_max_nesting() {
+                return Err(self.fail("too deep nesting", false));
+            }
+            // Anything but a type byte at the top level is an inline command,
+            // as typed into telnet
+            let inline = self.open.is_empty() && !is_type_byte(kind);
+            let line = match self.take_line(kind, inline) {
+                Ok(Some(line)) => line,
+                Ok(None) => return Ok(None),
+                Err(reason) => return Err(self.fail(reason, inline)),
+            };
+            let frame = if inline {
+                match parse_inline(&line) {
+                    Some(frame) => frame,
+                    // Redis ignores the blank lines clients may send between commands
+                    None => continue,
+                }
+            } else {
+                match self.parse_header(kind, &line) {
+                    Ok(Some(frame)) => frame,
+                    Ok(None) => continue, // an aggregate or a payload has started
+                    Err(reason) => return Err(self.fail(reason, false)),
+                }
+            };
+            if let Some(frame) = self.complete(frame) {
+                return Ok(Some(frame));
+            }
+        }
+    }
+
+    /// Take the next line off the buffer, without its line ending, or None if its
+    /// newline hasn't arrived. Only the bytes fed since the last search are scanned
+    /// for it, so a long line arriving in small reads isn't searched again each time.
+    /// Lines are capped at proto-max-inline-len whether or not their newline has
+    /// arrived, at any nesting depth, so a header can't buffer without bound.
+    fn take_line(&mut self, kind: u8, inline: bool) -> Result<Option<BytesMut>, String> {
+        let limit = config::get_proto_max_inline_len();
+        let Some(i) = self.buf[self.scanned..].iter().position(|&b| b == b'\n') else {
+            self.scanned = self.buf.len();
+            if self.buf.len() > limit {
+                self.skip_line();
+                return Err(too_big(kind, inline).into());
+            }
             return Ok(None);
+        };
+        let end = self.scanned + i;
+        if end > limit {
+            self.skip_line();
+            return Err(too_big(kind, inline).into());
+        }
+        let mut line = self.buf.split_to(end + 1);
+        self.scanned = 0;
+        self.in_frame += line.len();
+        line.truncate(end);
+        // Like Redis, a bare LF ends a line as well as CRLF, for clients such as netcat
+        if line.last() == Some(&b'\r') {
+            line.truncate(end - 1);
         }
-        let b0 = self.buf[0];
-        match b0 {
+        Ok(Some(line))
+    }
+
+    /// Parse a header line whose type byte is `kind`. Returns the frame if the line
+    /// is all of it, or None once an aggregate or a bulk payload has been started.
+    fn parse_header(&mut self, kind: u8, line: &[u8]) -> Result<Option<Frame>, String> {
+        let text = || String::from_utf8_lossy(&line[1..]).into_owned();
+        let frame = match kind {
             // RESP2:
-            b'+' => parse_simple(&mut self.buf).map(Some),
-            b'-' => parse_error(&mut self.buf).map(Some),
-            b':' => parse_integer(&mut self.buf).map(Some),
-            b'$' => parse_bulk(&mut self.buf).map(Some),
-            b'*' => parse_array(&mut self.buf).map(Some),
+            b'+' => Frame::SimpleString(text()),
+            b'-' => Frame::Error(text()),
+            b':' => Frame::Integer(parse_number(&line[1..]).ok_or("invalid integer")?),
+            b'$' if parse_number(&line[1..]).is_some_and(|len| len < 0) => Frame::BulkString(None),
+            b'$' => return self.start_payload(PayloadKind::String, &line[1..]),
+            b'*' | b'~' | b'>' | b'%' | b'|' => return self.open_aggregate(kind, &line[1..]),
 
             // RESP3:
-            b'_' => parse_null(&mut self.buf).map(Some),
-            b'#' => parse_boolean(&mut self.buf).map(Some),
-            b',' => parse_double(&mut self.buf).map(Some),
-            b'(' => parse_bignumber(&mut self.buf).map(Some),
-            b'!' => parse_bulk_error(&mut self.buf).map(Some),
-            b'=' => parse_verbatim_string(&mut self.buf).map(Some),
-            b'%' => parse_map(&mut self.buf).map(Some),
-            b'~' => parse_set(&mut self.buf).map(Some),
-            b'|' => parse_attribute(&mut self.buf).map(Some),
-            b'>' => parse_push(&mut self.buf).map(Some),
-
-            _ => Err(format!("Unexpected byte: {}", b0)),
+            b'_' => Frame::Null,
+            b'#' => match &line[1..] {
+                b"t" => Frame::Boolean(true),
+                b"f" => Frame::Boolean(false),
+                _ => return Err("invalid boolean".into()),
+            },
+            b',' => Frame::Double(
+                std::str::from_utf8(&line[1..])
+                    .ok()
+                    .and_then(|d| d.parse().ok())
+                    .ok_or("invalid double")?,
+            ),
+            b'(' => Frame::BigNumber(text()),
+            b'!' => return self.start_payload(PayloadKind::Error, &line[1..]),
+            b'=' => {
+                let Some(space) = line.iter().position(|&b| b == b' ') else {
+                    return Err("invalid verbatim string".into());
+                };
+                let subtype = String::from_utf8_lossy(&line[1..space]).into_owned();
+                return self.start_payload(PayloadKind::Verbatim(subtype), &line[space + 1..]);
+            }
+
+            _ => return Err(format!("expected '$', got '{}'", kind as char)),
+        };
+        Ok(Some(frame))
+    }
+
+    /// Start waiting for a bulk payload, rejecting lengths over proto-max-bulk-len
+    /// before anything is allocated for them
+    fn start_payload(&mut self, kind: PayloadKind, len: &[u8]) -> Result<Option<Frame>, String> {
+        let len = match parse_number(len) {
+            Some(len) if len >= 0 && len as u64 <= config::get_proto_max_bulk_len() => len as usize,
+            _ => return Err("invalid bulk length".into()),
+        };
+        self.payload = Some(Payload { kind, len });
+        Ok(None)
+    }
+
+    /// Open an aggregate of the element count in `count`, or return it straight
+    /// away if it is nil or empty
+    fn open_aggregate(&mut self, kind: u8, count: &[u8]) -> Result<Option<Frame>, String> {
+        let count = parse_number(count).ok_or("invalid multibulk length")?;
+        if count < 0 {
+            return Ok(Some(nil_aggregate(kind)));
+        }
+        let pairs = matches!(kind, b'%' | b'|');
+        let remaining = usize::try_from(count)
+            .ok()
+            .and_then(|count| count.checked_mul(if pairs { 2 } else { 1 }))
+            .ok_or("invalid multibulk length")?;
+        let aggregate = Aggregate {
+            kind,
+            remaining,
+            // Every element takes at least a byte, so a count larger than the buffer
+            // can't be trusted for allocation
+            items: Vec::with_capacity(remaining.min(self.buf.len())),
+        };
+        if remaining == 0 {
+            return Ok(Some(aggregate.into_frame()));
         }
+        self.open.push(aggregate);
+        Ok(None)
     }
-}
 
-/// Parses a line from the buffer, expecting it to end with CRLF.
-/// Returns the line as a String if found, or None if the buffer does not contain a complete line.
-/// The line is expected to start with a RESP type indicator (e.g., '+', '-', ':', etc.).
-/// The CRLF is consumed from the buffer.
-fn parse_line(buf: &mut BytesMut) -> Option<String> {
-    for i in 0..buf.len() - 1 {
-        if &buf[i..i + 2] == b"\r\n" {
-            let line = buf.split_to(i);
-            buf.advance(2); // Remove the CRLF
-            return Some(String::from_utf8(line.to_vec()).unwrap());
+    /// Add a parsed frame to the innermost open aggregate, closing every aggregate it
+    /// completes. Returns the frame they add up to once nothing is left open.
+    fn complete(&mut self, mut frame: Frame) -> Option<Frame> {
+        while let Some(aggregate) = self.open.last_mut() {
+            aggregate.items.push(frame);
+            aggregate.remaining -= 1;
+            if aggregate.remaining > 0 {
+                return None;
+            }
+            frame = self.open.pop().expect("checked above").into_frame();
         }
+        self.in_frame = 0;
+        Some(frame)
     }
-    None
-}
 
-/// Parses a simple string from the buffer.
-/// It expects the string to start with a '+' character and end with CRLF.
-/// Returns a Frame::SimpleString if successful, or an error message if the buffer is incomplete.
-fn parse_simple(buf: &mut BytesMut) -> Result<Frame, String> {
-    if let Some(line) = parse_line(buf) {
-        Ok(Frame::SimpleString(line[1..].to_string()))
-    } else {
-        Err("Incomplete".into())
+    /// Whether a protocol error has made the rest of the stream unparseable
+    pub fn failed(&self) -> bool {
+        self.failed
     }
-}
 
-/// Parses an error frame from the buffer.
-/// It expects the error to start with a '-' character and end with CRLF.
-/// Returns a Frame::Error if successful, or an error message if the buffer is incomplete.
-fn parse_error(buf: &mut BytesMut) -> Result<Frame, String> {
-    if let Some(line) = parse_line(buf) {
-        Ok(Frame::Error(line[1..].to_string()))
-    } else {
-        Err("Incomplete".into())
+    /// Drop what was parsed of the current frame, returning the error to report.
+    /// If `resumable`, bytes after the offending line stay buffered, to be parsed
+    /// afresh; otherwise they are dropped along with any fed later.
+    fn fail(&mut self, reason: impl std::fmt::Display, resumable: bool) -> String {
+        self.open.clear();
+        self.payload = None;
+        self.in_frame = 0;
+        if !resumable {
+            self.buf.clear();
+            self.scanned = 0;
+            self.discarding = false;
+            self.failed = true;
+        }
+        format!("ERR Protocol error: {}", reason)
     }
-}
 
-/// Parses an integer from the buffer.
-/// It expects the integer to start with a ':' character and end with CRLF.
-/// Returns a Frame::Integer if successful, or an error message if the buffer is incomplete.
-fn parse_integer(buf: &mut BytesMut) -> Result<Frame, String> {
-    if let Some(line) = parse_line(buf) {
-        let num = line[1..].parse::<i64>().map_err(|e| e.to_string())?;
-        Ok(Frame::Integer(num))
-    } else {
-        Err("Incomplete".into())
+    /// Drop the buffer up to and including the next newline.
+    /// Returns false if it hasn't arrived, having dropped everything buffered;
+    /// bytes fed later are then dropped until it does.
+    fn skip_line(&mut self) -> bool {
+        match self.buf[self.scanned..].iter().position(|&b| b == b'\n') {
+            Some(i) => {
+                self.buf.advance(self.scanned + i + 1);
+                self.scanned = 0;
+                self.discarding = false;
+                true
+            }
+            None => {
+                self.buf.clear();
+                self.scanned = 0;
+                self.discarding = true;
+                false
+            }
+        }
     }
 }
 
-/// Parses a bulk string from the buffer.
-/// It expects the bulk string to start with a '$' character, followed by the length of the string,
-/// and then the string itself, ending with CRLF.
-/// Returns a Frame::BulkString if successful, or an error message if the buffer is incomplete
-fn parse_bulk(buf: &mut BytesMut) -> Result<Frame, String> {
-    if let Some(line) = parse_line(buf) {
-        let len = line[1..].parse::<isize>().map_err(|e| e.to_string())?;
-        if len < 0 {
-            Ok(Frame::BulkString(None))
-        } else if buf.len() >= (len as usize + 2) {
-            let data = buf.split_to(len as usize).to_vec();
-            buf.advance(2);
-            Ok(Frame::BulkString(Some(data)))
-        } else {
-            Err("Incomplete".into())
+impl Aggregate {
+    fn into_frame(self) -> Frame {
+        match self.kind {
+            b'~' => Frame::Set(Some(self.items)),
+            b'>' => Frame::Push(Some(self.items)),
+            b'%' => Frame::Map(Some(into_pairs(self.items))),
+            b'|' => Frame::Attribute(Some(into_pairs(self.items))),
+            _ => Frame::Array(Some(self.items)),
         }
-    } else {
-        Err("Incomplete".into())
     }
 }
 
-/// Parses an array from the buffer.
-/// It expects the array to start with a '*' character, followed by the number of elements,
-/// and then the elements themselves, each ending with CRLF.
-/// Returns a Frame::Array if successful, or an error message if the buffer is incomplete.
-fn parse_array(buf: &mut BytesMut) -> Result<Frame, String> {
-    if let Some(line) = parse_line(buf) {
-        let count = line[1..].parse::<isize>().map_err(|e| e.to_string())?;
-        if count < 0 {
-            Ok(Frame::Array(None))
-        } else {
-            let mut items = Vec::with_capacity(count as usize);
-            for _ in 0..count {
-                // Parse each item in-place, updating the buffer as we go
-                let mut parser = FrameParser {
-                    buf: BytesMut::new(),
-                };
-                // Move the buffer content to the parser's buffer
-                parser.buf = buf.split();
-                match parser.parse()? {
-                    Some(frame) => {
-                        items.push(frame);
-                        // Move back the remaining buffer to the original buf
-                        buf.unsplit(parser.buf);
-                    }
-                    None => return Err("Incomplete array item".into()),
-                }
-            }
-            Ok(Frame::Array(Some(items)))
-        }
-    } else {
-        Err("Incomplete".into())
+/// Pair up the alternating keys and values of a map or attribute
+fn into_pairs(items: Vec<Frame>) -> Vec<(Frame, Frame)> {
+    let mut pairs = Vec::with_capacity(items.len() / 2);
+    let mut items = items.into_iter();
+    while let (Some(key), Some(value)) = (items.next(), items.next()) {
+        pairs.push((key, value));
+    }
+    pairs
+}
+
+/// The nil form of the aggregate type `kind`
+fn nil_aggregate(kind: u8) -> Frame {
+    match kind {
+        b'~' => Frame::Set(None),
+        b'>' => Frame::Push(None),
+        b'%' => Frame::Map(None),
+        b'|' => Frame::Attribute(None),
+        _ => Frame::Array(None),
     }
 }
 
-/// Parses a null frame from the buffer.
-/// It expects the null frame to start with a '_' character and end with CRLF.
-/// Returns a Frame::Null if successful, or an error message if the buffer is incomplete.
-fn parse_null(buf: &mut BytesMut) -> Result<Frame, String> {
-    let _ = parse_line(buf).ok_or("Incomplete")?;
-    Ok(Frame::Null)
+fn is_type_byte(b: u8) -> bool {
+    b"+-:$*_#,(!=%~|>".contains(&b)
 }
 
-/// Parses a boolean frame from the buffer.
-/// It expects the boolean to start with a '#' character, followed by 't' for true
-/// or 'f' for false, and ending with CRLF.
-/// Returns a Frame::Boolean if successful, or an error message if the buffer is incomplete.
-fn parse_boolean(buf: &mut BytesMut) -> Result<Frame, String> {
-    let line = parse_line(buf).ok_or("Incomplete")?;
-    let b = match &line[1..] {
-        "t" => true,
-        "f" => false,
-        _ => return Err("Invalid boolean".into()),
-    };
-    Ok(Frame::Boolean(b))
+/// The error for a line of type `kind` longer than proto-max-inline-len
+fn too_big(kind: u8, inline: bool) -> &'static str {
+    match kind {
+        _ if inline => "too big inline request",
+        b'*' | b'~' | b'>' | b'%' | b'|' => "too big mbulk count string",
+        b'$' | b'!' | b'=' => "too big bulk count string",
+        _ => "too big line",
+    }
 }
 
-/// Parses a double from the buffer.
-/// It expects the double to start with a ',' character, followed by the double value,
-/// and ending with CRLF.
-/// Returns a Frame::Double if successful, or an error message if the buffer is incomplete.
-fn parse_double(buf: &mut BytesMut) -> Result<Frame, String> {
-    let line = parse_line(buf).ok_or("Incomplete")?;
-    let d = line[1..].parse::<f64>().map_err(|e| e.to_string())?;
-    Ok(Frame::Double(d))
+/// Parse a decimal integer, as found in headers and integer frames
+fn parse_number(digits: &[u8]) -> Option<i64> {
+    std::str::from_utf8(digits).ok()?.parse().ok()
 }
 
-/// Parses a bignumber from the buffer.
-/// It expects the bignumber to start with a '(' character, followed by the number,
-/// and ending with CRLF.
-/// Returns a Frame::BigNumber if successful, or an error message if the buffer is incomplete
-fn parse_bignumber(buf: &mut BytesMut) -> Result<Frame, String> {
-    let line = parse_line(buf).ok_or("Incomplete")?;
-    Ok(Frame::BigNumber(line[1..].to_string()))
+/// Parses an inline command: a line of space-separated arguments, returned as an
+/// array of bulk strings like a regular command, or None for a blank line
+fn parse_inline(line: &[u8]) -> Option<Frame> {
+    let args: Vec<Frame> = line
+        .split(|b| b.is_ascii_whitespace())
+        .filter(|arg| !arg.is_empty())
+        .map(|arg| Frame::BulkString(Some(arg.to_vec())))
+        .collect();
+    (!args.is_empty()).then_some(Frame::Array(Some(args)))
 }
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-/// Parses a bulk error from the buffer.
-/// It expects the bulk error to start with a '!' character, followed by the length of the error message,
-/// and then the error message itself, ending with CRLF.
-/// Returns a Frame::BulkError if successful, or an error message if the buffer is incomplete
-fn parse_bulk_error(buf: &mut BytesMut) -> Result<Frame, String> {
-    if let Some(line) = parse_line(buf) {
-        let len = line[1..].parse::<usize>().map_err(|e| e.to_string())?;
-        if buf.len() < len + 2 {
-            return Err("Incomplete".into());
-        }
-        let data = buf.split_to(len).to_vec();
-        buf.advance(2);
-        Ok(Frame::BulkError(String::from_utf8_lossy(&data).into()))
-    } else {
-        Err("Incomplete".into())
+    fn parse_all(input: &[u8]) -> Result<Option<Frame>, String> {
+        let mut parser = FrameParser::new();
+        parser.feed(input);
+        parser.parse()
     }
-}
 
-/// Parses a verbatim string from the buffer.
-/// It expects the verbatim string to start with a '=' character, followed by the subtype and length,
-/// and then the string itself, ending with CRLF.
-/// Returns a Frame::VerbatimString if successful, or an error message if the buffer is
-fn parse_verbatim_string(buf: &mut BytesMut) -> Result<Frame, String> {
-    if let Some(line) = parse_line(buf) {
-        let mut parts = line[1..].splitn(2, ' ');
-        let subtype = parts.next().unwrap().to_string();
-        let len = parts
-            .next()
-            .unwrap()
-            .parse::<usize>()
-            .map_err(|e| e.to_string())?;
-        if buf.len() < len + 2 {
-            return Err("Incomplete".into());
+    #[test]
+    fn doubles_spell_their_special_values_as_resp3_does() {
+        for (value, encoded) in [
+            (1.5, &b",1.5\r\n"[..]),
+            (-2.0, b",-2\r\n"),
+            (0.1, b",0.1\r\n"),
+            (1e16, b",10000000000000000\r\n"),
+            (1e17, b",1e+17\r\n"),
+            (-1.5e300, b",-1.5e+300\r\n"),
+            (0.0001, b",0.0001\r\n"),
+            (1.25e-5, b",1.25e-05\r\n"),
+            (f64::INFINITY, b",inf\r\n"),
+            (f64::NEG_INFINITY, b",-inf\r\n"),
+            (f64::NAN, b",nan\r\n"),
+        ] {
+            assert_eq!(Frame::Double(value).encode(), encoded);
+            let Ok(Some(Frame::Array(Some(items)))) = parse_all(&[b"*1\r\n", encoded].concat())
+            else {
+                panic!("{:?} didn't parse", String::from_utf8_lossy(encoded));
+            };
+            match items[0] {
+                Frame::Double(parsed) if value.is_nan() => assert!(parsed.is_nan()),
+                Frame::Double(parsed) => assert_eq!(parsed, value),
+                ref other => panic!("{:?} parsed as {:?}", encoded, other),
+            }
         }
-        let data = buf.split_to(len).to_vec();
-        buf.advance(2);
-        Ok(Frame::VerbatimString { subtype, data })
-    } else {
-        Err("Incomplete".into())
     }
-}
 
-/// Parses a set from the buffer.
-/// It expects the set to start with a '~' character, followed by the number of elements,
-/// and then the elements themselves, each ending with CRLF.
-/// Returns a Frame::Set if successful, or an error message if the buffer is incomplete.
-fn parse_set(buf: &mut BytesMut) -> Result<Frame, String> {
-    parse_aggregate(buf, Frame::Set(None), |n| {
-        Frame::Set(Some(Vec::with_capacity(n)))
-    })
-}
+    #[test]
+    fn a_large_bulk_payload_is_buffered_without_reallocating() {
+        const LEN: usize = 10 * 1024 * 1024;
+        let mut parser = FrameParser::new();
+        parser.feed(format!("*3\r\n$3\r\nSET\r\n$1\r\nk\r\n${}\r\n", LEN).as_bytes());
+        assert!(parser.parse().unwrap().is_none());
+        assert_eq!(parser.pending(), LEN + 2);
 
-/// Parses a push frame from the buffer.
-/// It expects the push frame to start with a '>' character, followed by the number of elements,
-/// and then the elements themselves, each ending with CRLF.
-/// Returns a Frame::Push if successful, or an error message if the buffer is incomplete.
-fn parse_push(buf: &mut BytesMut) -> Result<Frame, String> {
-    parse_aggregate(buf, Frame::Push(None), |n| {
-        Frame::Push(Some(Vec::with_capacity(n)))
-    })
-}
+        let chunk = vec![b'x'; 16 * 1024];
+        parser.feed(&chunk);
+        let buffer = parser.buf.as_ptr();
+        for _ in 1..LEN / chunk.len() {
+            parser.feed(&chunk);
+            assert_eq!(parser.buf.as_ptr(), buffer, "the buffer was reallocated");
+        }
+        assert_eq!(parser.pending(), 2);
+        parser.feed(b"\r\n");
+        let Some(Frame::Array(Some(items))) = parser.parse().unwrap() else {
+            panic!("the command didn't parse");
+        };
+        assert_eq!(items[2], Frame::BulkString(Some(vec![b'x'; LEN])));
+        assert_eq!(parser.buffered(), 0);
+    }
 
-/// Parses an attribute frame from the buffer.
-/// It expects the attribute to start with a '|' character, followed by a map of attributes,
-/// and ending with CRLF.
-/// Returns a Frame::Attribute if successful, or an error message if the buffer is incomplete.
-fn parse_attribute(buf: &mut BytesMut) -> Result<Frame, String> {
-    // parse_map returns Frame::Attribute
-    match parse_map(buf)? {
-        Frame::Attribute(attr) => Ok(Frame::Attribute(attr)),
-        _ => Err("Expected attribute frame".into()),
+    #[test]
+    fn deep_nesting_is_an_error_not_a_crash() {
+        let input = b"*1\r\n".repeat(200);
+        assert_eq!(
+            parse_all(&input).unwrap_err(),
+            "ERR Protocol error: too deep nesting"
+        );
     }
-}
 
-/// Parses an aggregate frame from the buffer.
-/// It expects the aggregate to start with a '*' character, followed by the number of elements,
-/// and then the elements themselves, each ending with CRLF.
-/// Returns the appropriate Frame type (Array, Set, Push) based on the provided `make_some` function.
-fn parse_aggregate(
-    buf: &mut BytesMut,
-    nil_frame: Frame,
-    make_some: impl FnOnce(usize) -> Frame,
-) -> Result<Frame, String> {
-    let line = parse_line(buf).ok_or("Incomplete")?;
-    let count = line[1..].parse::<isize>().map_err(|e| e.to_string())?;
-    if count < 0 {
-        return Ok(nil_frame);
-    }
-    let count = count as usize;
-    let mut frame = make_some(count);
-    let mut items = Vec::with_capacity(count);
-    for _ in 0..count {
-        match (FrameParser { buf: buf.clone() }).parse()? {
-            Some(f) => {
-                items.push(f);
-                buf.unsplit(FrameParser { buf: buf.clone() }.buf);
+    #[test]
+    fn malformed_frames_are_protocol_errors() {
+        for (input, error) in [
+            (&b"*x\r\n"[..], "invalid multibulk length"),
+            (b"%1a\r\n", "invalid multibulk length"),
+            (b"*1\r\n$abc\r\n", "invalid bulk length"),
+            (b"*1\r\n$-\r\n", "invalid bulk length"),
+            (b"*1\r\n:12z\r\n", "invalid integer"),
+            (b"*1\r\n,one\r\n", "invalid double"),
+            (b"*1\r\n#x\r\n", "invalid boolean"),
+            (b"*1\r\n=5\r\n", "invalid verbatim string"),
+            (b"*1\r\nGET\r\n", "expected '$', got 'G'"),
+        ] {
+            assert_eq!(
+                parse_all(input).unwrap_err(),
+                format!("ERR Protocol error: {}", error),
+                "{:?}",
+                String::from_utf8_lossy(input)
+            );
+        }
+    }
+
+    /// Feed `input` in pieces of `chunk` bytes, collecting every frame parsed
+    fn parse_in_chunks(input: &[u8], chunk: usize) -> Vec<Vec<u8>> {
+        let mut parser = FrameParser::new();
+        let mut frames = Vec::new();
+        for piece in input.chunks(chunk) {
+            parser.feed(piece);
+            while let Some(frame) = parser.parse().unwrap() {
+                frames.push(frame.encode());
             }
-            None => return Err("Incomplete aggregate item".into()),
         }
+        assert_eq!(parser.buffered(), 0);
+        frames
     }
-    match &mut frame {
-        Frame::Array(Some(vec)) => *vec = items,
-        Frame::Set(Some(vec)) => *vec = items,
-        Frame::Push(Some(vec)) => *vec = items,
-        _ => {}
+
+    #[test]
+    fn frames_split_anywhere_parse_the_same() {
+        let input = b"*3\r\n$3\r\nSET\r\n$1\r\nk\r\n$5\r\nva\r\nl\r\n\
+            %1\r\n+key\r\n*2\r\n:-7\r\n,1.5\r\n\
+            ~1\r\n=txt 3\r\nabc\r\n\
+            PING now\r\n";
+        let whole = parse_in_chunks(input, input.len());
+        assert_eq!(whole.len(), 4);
+        for chunk in 1..input.len() {
+            assert_eq!(parse_in_chunks(input, chunk), whole, "chunks of {}", chunk);
+        }
+    }
+
+    #[test]
+    fn inline_commands_accept_a_bare_newline_and_skip_blank_lines() {
+        assert_eq!(
+            parse_in_chunks(b"\r\n  \nGET  key\n\nPING\r\n", 1),
+            vec![
+                b"*2\r\n$3\r\nGET\r\n$3\r\nkey\r\n".to_vec(),
+                b"*1\r\n$4\r\nPING\r\n".to_vec()
+            ]
+        );
+    }
+
+    #[test]
+    fn buffered_counts_the_bytes_of_a_frame_until_it_is_returned() {
+        let mut parser = FrameParser::new();
+        parser.feed(b"*2\r\n$3\r\nGET\r\n$1");
+        assert!(parser.parse().unwrap().is_none());
+        assert_eq!(parser.buffered(), 15);
+        parser.feed(b"\r\nk\r\n*1\r\n");
+        assert!(parser.parse().unwrap().is_some());
+        assert_eq!(parser.buffered(), 4);
     }
-    Ok(frame)
-}
 
-/// Parses a map from the buffer.
-/// It expects the map to start with a '%' character, followed by the number of key-value pairs,
-/// and then the pairs themselves, each ending with CRLF.
-/// Returns a Frame::Map if successful, or a Frame::Attribute if the map is an
-fn parse_map(buf: &mut BytesMut) -> Result<Frame, String> {
-    let line = parse_line(buf).ok_or("Incomplete")?;
-    let count = line[1..].parse::<isize>().map_err(|e| e.to_string())?;
-    if count < 0 {
-        if line.starts_with('%') {
-            return Ok(Frame::Map(None));
-        } else {
-            return Ok(Frame::Attribute(None));
+    #[test]
+    fn over_long_lines_are_rejected_at_any_depth() {
+        let limit = config::get_proto_max_inline_len();
+        for (header, error) in [
+            (&b""[..], "too big inline request"),
+            (b"*", "too big mbulk count string"),
+            (b"*1\r\n$", "too big bulk count string"),
+            (b"*2\r\n*1\r\n*", "too big mbulk count string"),
+            (b"*1\r\n+", "too big line"),
+        ] {
+            let mut parser = FrameParser::new();
+            parser.feed(header);
+            let mut fed = header.len();
+            // Digits arriving 4KiB at a time, with no newline ever coming
+            let result = loop {
+                parser.feed(&[b'9'; 4096]);
+                fed += 4096;
+                match parser.parse() {
+                    Ok(None) if fed < 2 * limit => continue,
+                    result => break result,
+                }
+            };
+            assert_eq!(
+                result.unwrap_err(),
+                format!("ERR Protocol error: {}", error)
+            );
+            assert!(fed <= limit + 4096 + header.len());
+            assert_eq!(parser.buffered(), 0);
         }
+
+        // A line over the limit is rejected even when its newline comes with it
+        let mut line = vec![b'*'];
+        line.resize(limit + 1, b'9');
+        line.extend_from_slice(b"\r\n");
+        assert_eq!(
+            parse_all(&line).unwrap_err(),
+            "ERR Protocol error: too big mbulk count string"
+        );
     }
-    let count = count as usize;
-    let mut pairs = Vec::with_capacity(count);
-    for _ in 0..count {
-        let key = FrameParser { buf: buf.clone() }
-            .parse()?
-            .ok_or("Incomplete map key")?;
-        let leftover = FrameParser { buf: buf.clone() }.buf;
-        buf.unsplit(leftover);
-        let value = FrameParser { buf: buf.clone() }
-            .parse()?
-            .ok_or("Incomplete map value")?;
-        let leftover = FrameParser { buf: buf.clone() }.buf;
-        buf.unsplit(leftover);
-        pairs.push((key, value));
+
+    #[test]
+    fn oversized_bulk_lengths_are_rejected_before_allocating() {
+        assert_eq!(
+            parse_all(b"*1\r\n$99999999999999\r\n").unwrap_err(),
+            "ERR Protocol error: invalid bulk length"
+        );
+    }
+
+    #[test]
+    fn pending_reports_the_rest_of_a_bulk_payload() {
+        let mut parser = FrameParser::new();
+        parser.feed(b"*1\r\n$100\r\n0123456789");
+        assert!(parser.parse().unwrap().is_none());
+        assert_eq!(parser.pending(), 92);
+    }
+
+    #[test]
+    fn a_long_multibulk_arriving_slowly_is_parsed_once() {
+        // Reparsing from the first element on every read would take minutes here
+        let count = 200_000;
+        let mut input = format!("*{}\r\n", count).into_bytes();
+        for _ in 0..count {
+            input.extend_from_slice(b"$1\r\nx\r\n");
+        }
+        let frames = parse_in_chunks(&input, 16);
+        assert_eq!(frames, vec![input]);
     }
-    if line.starts_with('%') {
-        Ok(Frame::Map(Some(pairs)))
-    } else {
-        Ok(Frame::Attribute(Some(pairs)))
+
+    #[test]
+    fn nothing_is_parsed_after_an_error_in_a_multibulk() {
+        let mut parser = FrameParser::new();
+        parser.feed(b"*2\r\n$3\r\nGET\r\n$x\r\n*1\r\n$4\r\nPING\r\n");
+        assert_eq!(
+            parser.parse().unwrap_err(),
+            "ERR Protocol error: invalid bulk length"
+        );
+        assert!(parser.failed());
+        assert_eq!(parser.buffered(), 0);
+        parser.feed(b"PING\r\n");
+        assert!(parser.parse().unwrap().is_none());
+    }
+
+    #[test]
+    fn the_rest_of_an_over_long_line_is_dropped_as_it_arrives() {
+        let limit = config::get_proto_max_inline_len();
+        let mut parser = FrameParser::new();
+        parser.feed(&vec![b'a'; limit + 1]);
+        assert_eq!(
+            parser.parse().unwrap_err(),
+            "ERR Protocol error: too big inline request"
+        );
+        assert_eq!(parser.buffered(), 0);
+        // More of the same line, then its end and a good command
+        parser.feed(&vec![b'a'; limit * 2]);
+        assert!(parser.parse().unwrap().is_none());
+        assert_eq!(parser.buffered(), 0);
+        parser.feed(b"aaaa\r\nPING\r\n");
+        assert_eq!(
+            parser.parse().unwrap().unwrap().encode(),
+            b"*1\r\n$4\r\nPING\r\n"
+        );
+    }
+
+    #[test]
+    fn too_deep_nesting_drops_the_rest_of_the_stream() {
+        let mut parser = FrameParser::new();
+        let depth = config::get_proto_max_nesting() + 1;
+        parser.feed(&b"*1\r\n".repeat(depth + 1));
+        assert_eq!(
+            parser.parse().unwrap_err(),
+            "ERR Protocol error: too deep nesting"
+        );
+        assert!(parser.failed());
+        assert_eq!(parser.buffered(), 0);
     }
 }
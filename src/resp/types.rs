@@ -1,5 +1,7 @@
+use std::fmt::Write;
+
 /// RESP (REdis Serialization Protocol) data types
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum Frame {
     // RESP2 classics:
     SimpleString(String),        // +
@@ -28,75 +30,149 @@ pub enum Frame {
 impl Frame {
     /// Serialize frame back into RESP bytes
     pub fn encode(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        self.encode_into(&mut out);
+        out
+    }
+
+    /// Serialize frame into the end of `out`, so a caller encoding many frames
+    /// (or the elements of a large aggregate) can reuse one buffer for all of them
+    pub fn encode_into(&self, out: &mut Vec<u8>) {
         match self {
-            Frame::SimpleString(s) => format!("+{}\r\n", s).into_bytes(),
-            Frame::Error(s) => format!("-{}\r\n", s).into_bytes(),
-            Frame::Integer(i) => format!(":{}\r\n", i).into_bytes(),
-            Frame::BulkString(Some(bs)) => {
-                let mut v = format!("${}\r\n", bs.len()).into_bytes();
-                v.extend(bs);
-                v.extend(b"\r\n");
-                v
-            }
-            Frame::BulkString(None) => b"$-1\r\n".to_vec(),
-            Frame::Array(Some(arr)) => {
-                let mut v = format!("*{}\r\n", arr.len()).into_bytes();
-                for f in arr {
-                    v.extend(f.encode());
-                }
-                v
-            }
-            Frame::Array(None) => b"*-1\r\n".to_vec(),
-            Frame::Null => b"_\r\n".to_vec(),
-            Frame::Boolean(b) => format!("#{}\r\n", if *b { "t" } else { "f" }).into_bytes(),
-            Frame::Double(d) => format!(",{}\r\n", d).into_bytes(),
-            Frame::BigNumber(s) => format!("({}\r\n", s).into_bytes(),
-            Frame::BulkError(msg) => {
-                let mut v = format!("!{}\r\n", msg.len()).into_bytes();
-                v.extend(msg.as_bytes());
-                v.extend(b"\r\n");
-                v
-            }
+            Frame::SimpleString(s) => push_line(out, b'+', s.as_bytes()),
+            Frame::Error(s) => push_line(out, b'-', s.as_bytes()),
+            Frame::Integer(i) => push_header(out, b':', *i),
+            Frame::BulkString(Some(bs)) => push_blob(out, b'$', bs),
+            Frame::BulkString(None) => out.extend_from_slice(b"$-1\r\n"),
+            Frame::Array(Some(arr)) => push_aggregate(out, b'*', arr),
+            Frame::Array(None) => out.extend_from_slice(b"*-1\r\n"),
+            Frame::Null => out.extend_from_slice(b"_\r\n"),
+            Frame::Boolean(b) => out.extend_from_slice(if *b { b"#t\r\n" } else { b"#f\r\n" }),
+            Frame::Double(d) => push_line(out, b',', format_double(*d).as_bytes()),
+            Frame::BigNumber(s) => push_line(out, b'(', s.as_bytes()),
+            Frame::BulkError(msg) => push_blob(out, b'!', msg.as_bytes()),
             Frame::VerbatimString { subtype, data } => {
-                let mut v = format!("={} {}\r\n", subtype, data.len()).into_bytes();
-                v.extend(data);
-                v.extend(b"\r\n");
-                v
-            }
-            Frame::Map(None) => b"%-1\r\n".to_vec(),
-            Frame::Map(Some(pairs)) => {
-                let mut v = format!("%{}\r\n", pairs.len()).into_bytes();
-                for (k, val) in pairs {
-                    v.extend(k.encode());
-                    v.extend(val.encode());
-                }
-                v
-            }
-            Frame::Set(None) => b"~-1\r\n".to_vec(),
-            Frame::Set(Some(items)) => {
-                let mut v = format!("~{}\r\n", items.len()).into_bytes();
-                for it in items {
-                    v.extend(it.encode());
-                }
-                v
-            }
-            Frame::Attribute(None) => b"|-1\r\n".to_vec(),
-            Frame::Attribute(Some(pairs)) => {
-                let mut v = format!("|{}\r\n", pairs.len()).into_bytes();
-                for (k, val) in pairs {
-                    v.extend(k.encode());
-                    v.extend(val.encode());
-                }
-                v
-            }
-            Frame::Push(None) => b">-1\r\n".to_vec(),
-            Frame::Push(Some(items)) => {
-                let mut v = format!(">{}\r\n", items.len()).into_bytes();
-                for it in items {
-                    v.extend(it.encode());
-                }
-                v
+                out.push(b'=');
+                out.extend_from_slice(subtype.as_bytes());
+                push_header(out, b' ', data.len() as i64);
+                out.extend_from_slice(data);
+                out.extend_from_slice(b"\r\n");
             }
+            Frame::Map(None) => out.extend_from_slice(b"%-1\r\n"),
+            Frame::Map(Some(pairs)) => push_pairs(out, b'%', pairs),
+            Frame::Set(None) => out.extend_from_slice(b"~-1\r\n"),
+            Frame::Set(Some(items)) => push_aggregate(out, b'~', items),
+            Frame::Attribute(None) => out.extend_from_slice(b"|-1\r\n"),
+            Frame::Attribute(Some(pairs)) => push_pairs(out, b'|', pairs),
+            Frame::Push(None) => out.extend_from_slice(b">-1\r\n"),
+            Frame::Push(Some(items)) => push_aggregate(out, b'>', items),
+        }
+    }
+}
+
+/// A double as Redis writes it, in RESP3 doubles and RESP2 bulk strings alike: the
+/// shortest digits that read back as the same number, laid out as printf's %.17g
+/// would, so 1e300 is 1e+300 rather than 301 digits. RESP3 spells the special values
+/// inf, -inf and nan.
+pub fn format_double(d: f64) -> String {
+    if d.is_nan() {
+        return "nan".to_string();
+    }
+    if d.is_infinite() {
+        return if d > 0.0 { "inf" } else { "-inf" }.to_string();
+    }
+    // {:e} gives the shortest digits, as d.ddde-x
+    let scientific = format!("{:e}", d.abs());
+    let (mantissa, exponent) = scientific.split_once('e').unwrap();
+    let exponent: i32 = exponent.parse().unwrap();
+    let digits = mantissa.replace('.', "");
+
+    let mut out = String::new();
+    if d.is_sign_negative() {
+        out.push('-');
+    }
+    if !(-4..17).contains(&exponent) {
+        out.push_str(&digits[..1]);
+        if digits.len() > 1 {
+            out.push('.');
+            out.push_str(&digits[1..]);
+        }
+        let _ = write!(
+            out,
+            "e{}{:02}",
+            if exponent < 0 { '-' } else { '+' },
+            exponent.abs()
+        );
+    } else if exponent < 0 {
+        out.push_str("0.");
+        out.extend(std::iter::repeat_n('0', (-exponent - 1) as usize));
+        out.push_str(&digits);
+    } else {
+        let whole = exponent as usize + 1;
+        if digits.len() <= whole {
+            out.push_str(&digits);
+            out.extend(std::iter::repeat_n('0', whole - digits.len()));
+        } else {
+            out.push_str(&digits[..whole]);
+            out.push('.');
+            out.push_str(&digits[whole..]);
+        }
+    }
+    out
+}
+
+/// A type byte followed by a line of text. Like Redis, line breaks in it, as an error
+/// quoting its argument may hold, are sent as spaces, so the line can't end early.
+fn push_line(out: &mut Vec<u8>, kind: u8, line: &[u8]) {
+    out.push(kind);
+    out.extend(line.iter().map(|&b| match b {
+        b'\r' | b'\n' => b' ',
+        b => b,
+    }));
+    out.extend_from_slice(b"\r\n");
+}
+
+/// A type byte followed by a number: an integer, or the length of what follows
+fn push_header(out: &mut Vec<u8>, kind: u8, n: i64) {
+    out.push(kind);
+    if n < 0 {
+        out.push(b'-');
+    }
+    // Digits come out least significant first, so fill a scratch buffer from its end
+    let mut digits = [0u8; 20];
+    let mut at = digits.len();
+    let mut n = n.unsigned_abs();
+    loop {
+        at -= 1;
+        digits[at] = b'0' + (n % 10) as u8;
+        n /= 10;
+        if n == 0 {
+            break;
         }
     }
+    out.extend_from_slice(&digits[at..]);
+    out.extend_from_slice(b"\r\n");
+}
+
+/// A length-prefixed payload, as bulk strings and bulk errors are sent
+fn push_blob(out: &mut Vec<u8>, kind: u8, data: &[u8]) {
+    out.reserve(data.len() + 16);
+    push_header(out, kind, data.len() as i64);
+    out.extend_from_slice(data);
+    out.extend_from_slice(b"\r\n");
+}
+
+fn push_aggregate(out: &mut Vec<u8>, kind: u8, items: &[Frame]) {
+    push_header(out, kind, items.len() as i64);
+    for item in items {
+        item.encode_into(out);
+    }
+}
+
+fn push_pairs(out: &mut Vec<u8>, kind: u8, pairs: &[(Frame, Frame)]) {
+    push_header(out, kind, pairs.len() as i64);
+    for (key, value) in pairs {
+        key.encode_into(out);
+        value.encode_into(out);
+    }
 }
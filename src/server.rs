@@ -1,54 +1,693 @@
-use crate::commands::dispatch;
+use crate::commands::{dispatch, may_wait, ConnAction, DispatchResult};
+use crate::config::{self, ClientClass};
+use crate::model::client_state::ClientState;
+use crate::monitor;
+use crate::output::{self, OutputReceiver, OutputSender};
+use crate::pubsub;
+use crate::replication;
+use crate::resp::parser::FrameParser;
+use crate::resp::Frame;
+use crate::stats;
+#[cfg(feature = "tls")]
+use crate::tls;
+use once_cell::sync::Lazy;
 use std::error::Error;
-use std::net::SocketAddr;
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
+#[cfg(feature = "tls")]
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+#[cfg(feature = "tls")]
+use std::sync::Arc;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
 use tokio::net::{TcpListener, TcpStream};
+use tokio::time::Duration;
+
+/// Identifies this run of the server, as reported by CLUSTER MYID
+pub static RUN_ID: Lazy<String> = Lazy::new(replication::random_id);
+
+/// Number of clients currently connected
+pub static CONNECTED_CLIENTS: AtomicUsize = AtomicUsize::new(0);
+
+/// Releases a slot in CONNECTED_CLIENTS when the connection ends, however it ends
+struct ClientSlot;
+
+impl Drop for ClientSlot {
+    fn drop(&mut self) {
+        CONNECTED_CLIENTS.fetch_sub(1, Ordering::SeqCst);
+    }
+}
 
 /// Start the Redis server on the specified address
 /// This function listens for incoming connections and spawns a handler for each client.
+/// With tls-port set, TLS clients are accepted on that port at the same time,
+/// and served by the same handler once the handshake is done.
 pub async fn start(addr: &str) -> Result<(), Box<dyn Error>> {
     let listener = TcpListener::bind(addr).await?;
-    println!("Listening on {}", addr);
+    info!("Listening on {}", addr);
+    let config = config::get_config();
+    if config.tls_port > 0 {
+        listen_tls(&config).await?;
+    }
     loop {
         let (socket, peer) = listener.accept().await?;
-        println!("New client: {}", peer);
-        tokio::spawn(handle(socket, peer));
+        match admit(&socket, &peer.to_string()) {
+            Ok(slot) => tokio::spawn(handle(socket, peer.to_string(), slot)),
+            Err(slot) => tokio::spawn(refuse(socket, slot)),
+        };
+    }
+}
+
+/// Start accepting TLS clients on tls-port, presenting tls-cert-file and tls-key-file
+#[cfg(feature = "tls")]
+async fn listen_tls(config: &config::Config) -> Result<(), Box<dyn Error>> {
+    let context = tls::Context::server(
+        Path::new(&config.tls_cert_file),
+        Path::new(&config.tls_key_file),
+    )?;
+    let tls_addr = format!("127.0.0.1:{}", config.tls_port);
+    let tls_listener = TcpListener::bind(&tls_addr).await?;
+    info!("Listening for TLS connections on {}", tls_addr);
+    tokio::spawn(accept_tls(tls_listener, Arc::new(context)));
+    Ok(())
+}
+
+#[cfg(not(feature = "tls"))]
+async fn listen_tls(_config: &config::Config) -> Result<(), Box<dyn Error>> {
+    Err("tls-port is set, but this build has no TLS support (the tls feature)".into())
+}
+
+/// Most time a TLS client gets to complete its handshake before it is dropped
+#[cfg(feature = "tls")]
+const TLS_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Accept TLS clients, serving each one once its handshake completes
+#[cfg(feature = "tls")]
+async fn accept_tls(listener: TcpListener, context: Arc<tls::Context>) {
+    loop {
+        let (socket, peer) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(e) => {
+                warn!("Failed to accept a TLS connection: {}", e);
+                continue;
+            }
+        };
+        let peer = peer.to_string();
+        set_keepalive(&socket, &peer);
+        let context = context.clone();
+        // The handshake runs in the client's task, so a slow one holds up no one else.
+        // It only counts towards maxclients once the handshake is done, so connections
+        // that never complete one can't keep other clients out.
+        tokio::spawn(async move {
+            let stream =
+                match tokio::time::timeout(TLS_HANDSHAKE_TIMEOUT, tls::accept(&context, socket))
+                    .await
+                {
+                    Ok(Ok(stream)) => stream,
+                    Ok(Err(e)) => {
+                        verbose!("TLS handshake with {} failed: {}", peer, e);
+                        return;
+                    }
+                    Err(_) => {
+                        verbose!("TLS handshake with {} timed out", peer);
+                        return;
+                    }
+                };
+            match take_slot(&peer) {
+                Ok(slot) => handle(stream, peer, slot).await,
+                Err(slot) => refuse(stream, slot).await,
+            }
+        });
     }
 }
 
+/// Set up a newly accepted connection and take a client slot for it.
+/// Returns Err, still holding the slot, for a client over maxclients.
+fn admit(socket: &TcpStream, peer: &str) -> Result<ClientSlot, ClientSlot> {
+    set_keepalive(socket, peer);
+    take_slot(peer)
+}
+
+/// Turn on TCP keepalive for a new connection, if tcp-keepalive is set
+fn set_keepalive(socket: &TcpStream, peer: &str) {
+    let tcp_keepalive = config::get_config().tcp_keepalive;
+    if tcp_keepalive > 0 {
+        let keepalive = socket2::TcpKeepalive::new().with_time(Duration::from_secs(tcp_keepalive));
+        if let Err(e) = socket2::SockRef::from(socket).set_tcp_keepalive(&keepalive) {
+            warn!("Failed to set keepalive for {}: {}", peer, e);
+        }
+    }
+}
+
+/// Take a client slot for a new client.
+/// Returns Err, still holding the slot, for a client over maxclients.
+fn take_slot(peer: &str) -> Result<ClientSlot, ClientSlot> {
+    verbose!("New client: {}", peer);
+    let slot = ClientSlot;
+    if CONNECTED_CLIENTS.fetch_add(1, Ordering::SeqCst) >= config::get_config().maxclients {
+        warn!("Refusing client {}: max number of clients reached", peer);
+        return Err(slot);
+    }
+    Ok(slot)
+}
+
+/// Tell a client over the maxclients limit why it is being disconnected
+async fn refuse<S: AsyncWrite + Unpin>(mut socket: S, _slot: ClientSlot) {
+    let _ = socket
+        .write_all(b"-ERR max number of clients reached\r\n")
+        .await;
+    let _ = socket.flush().await;
+}
+
+/// Most reply bytes gathered before they are written, even in the middle of a pipeline
+const MAX_GATHERED: usize = 64 * 1024;
+
+/// Bytes read from a client at a time, as Redis reads them, unless a large bulk payload
+/// is arriving
+const READ_CHUNK: usize = 16 * 1024;
+
+/// Most bytes read at a time while a large bulk payload is arriving
+const MAX_READ_CHUNK: usize = 1024 * 1024;
+
 /// Handle a single client connection
 /// This function reads commands from the client, processes them, and sends responses back.
 /// It runs in its own task to allow multiple clients to be handled concurrently.
-async fn handle(mut socket: TcpStream, peer: SocketAddr) {
-    use crate::resp::parser::FrameParser;
+/// Replies and pub/sub messages all go through one channel, which only this task drains,
+/// so every frame reaches the socket whole and in the order it was produced.
+/// Any byte stream will do as the socket, and peer is only the address to report for it,
+/// so a TLS session, a Unix socket or an in-memory pipe can be served the same way.
+async fn handle<S: AsyncRead + AsyncWrite + Unpin>(mut socket: S, peer: String, _slot: ClientSlot) {
+    stats::incr(&stats::TOTAL_CONNECTIONS_RECEIVED);
     let mut parser = FrameParser::new();
-    let mut buf = [0u8; 1024];
+    let mut state = ClientState::new();
+    state.addr = peer.clone();
+    let (out_tx, mut out_rx) = output::channel();
+    state.push_tx = Some(out_tx.clone());
+    let mut buf = vec![0u8; READ_CHUNK];
+    let mut out = Vec::new();
+    let mut subscribed = false;
 
-    loop {
-        match socket.read(&mut buf).await {
-            Ok(0) => {
-                println!("Client {} disconnected", peer);
-                return;
+    'conn: loop {
+        // An idle timeout of 0 means clients may stay connected forever;
+        // like Redis, subscribed clients, monitors and replicas are never considered idle
+        let idle_timeout = if subscribed || state.monitor || state.replica {
+            0
+        } else {
+            config::get_timeout()
+        };
+        // Read a payload the parser knows is coming in chunks as large as it,
+        // within reason, then go back to small reads once it has arrived
+        let wanted = parser.pending().clamp(READ_CHUNK, MAX_READ_CHUNK);
+        if wanted > buf.len() || (parser.pending() == 0 && buf.len() > READ_CHUNK) {
+            buf = vec![0u8; wanted];
+        }
+        out_tx.set_class(if state.replica {
+            ClientClass::Replica
+        } else if state.subscriber_count() > 0 {
+            ClientClass::Pubsub
+        } else {
+            ClientClass::Normal
+        });
+        let event = tokio::select! {
+            read = read_with_timeout(&mut socket, &mut buf, idle_timeout) => match read {
+                Some(read) => Event::Read(read),
+                None => Event::TimedOut,
+            },
+            Some(message) = out_rx.recv() => Event::Push(message),
+            _ = out_tx.limit_reached() => Event::OutputLimit,
+        };
+
+        match event {
+            Event::TimedOut => {
+                verbose!("Closing idle client {}", peer);
+                break 'conn;
             }
-            Ok(n) => {
+            Event::OutputLimit => {
+                warn!(
+                    "Closing client {}: client reached output buffer limit",
+                    peer
+                );
+                break 'conn;
+            }
+            Event::Push(message) => {
+                if !write(&mut socket, &out_tx, &peer, &message).await {
+                    break 'conn;
+                }
+            }
+            Event::Read(Ok(0)) => {
+                verbose!("Client {} disconnected", peer);
+                break 'conn;
+            }
+            Event::Read(Ok(n)) => {
                 parser.feed(&buf[..n]);
-                while let Some(frame) = parser.parse().unwrap() {
-                    // Process command frame
-                    let response = dispatch(frame).await;
-                    if let Err(e) = socket.write_all(&response).await {
-                        eprintln!("Write error {}: {}", peer, e);
-                        return;
+                // Replies are gathered in out and written once the commands read have
+                // run, so a pipeline takes one write rather than one per command
+                out.clear();
+                loop {
+                    let (reply, action) = match parser.parse() {
+                        Ok(Some(frame)) => {
+                            // Don't hold earlier replies back while this one waits
+                            if may_wait(&frame)
+                                && !write_gathered(
+                                    &mut socket,
+                                    &out_tx,
+                                    &mut out_rx,
+                                    &peer,
+                                    &mut out,
+                                )
+                                .await
+                            {
+                                break 'conn;
+                            }
+                            let result = if may_wait(&frame) {
+                                match dispatch_watched(&mut socket, &mut parser, frame, &mut state)
+                                    .await
+                                {
+                                    Some(result) => result,
+                                    None => {
+                                        verbose!("Client {} disconnected while blocked", peer);
+                                        break 'conn;
+                                    }
+                                }
+                            } else {
+                                dispatch(frame, &mut state).await
+                            };
+                            (result.reply, result.action)
+                        }
+                        Ok(None) => break,
+                        Err(e) => {
+                            // Past a bad inline command the parser carries on at the next
+                            // line, but any other error leaves the stream unparseable
+                            verbose!("Protocol error from {}: {}", peer, e);
+                            let action = if parser.failed() {
+                                ConnAction::Close
+                            } else {
+                                ConnAction::None
+                            };
+                            (Frame::Error(e).encode(), action)
+                        }
+                    };
+                    // Anything published while the command ran goes ahead of its reply
+                    take_queued(&mut out_rx, &mut out);
+                    out.extend_from_slice(&reply);
+                    if (out.len() >= MAX_GATHERED || action == ConnAction::Close)
+                        && !write_gathered(&mut socket, &out_tx, &mut out_rx, &peer, &mut out).await
+                    {
+                        break 'conn;
                     }
+                    match action {
+                        ConnAction::None => {}
+                        ConnAction::Close => {
+                            verbose!("Closing client {}", peer);
+                            break 'conn;
+                        }
+                        ConnAction::EnterSubscriber => subscribed = true,
+                        ConnAction::ExitSubscriber => subscribed = false,
+                    }
+                }
+                if !write_gathered(&mut socket, &out_tx, &mut out_rx, &peer, &mut out).await {
+                    break 'conn;
                 }
             }
-            Err(e) if e.kind() == std::io::ErrorKind::ConnectionReset => {
-                println!("Client {} disconnected", peer);
-                return;
+            Event::Read(Err(e)) if e.kind() == std::io::ErrorKind::ConnectionReset => {
+                verbose!("Client {} disconnected", peer);
+                break 'conn;
             }
+            Event::Read(Err(e)) => {
+                warn!("Unexpected read error {}: {}", peer, e);
+                break 'conn;
+            }
+        }
+    }
+    pubsub::unsubscribe_all(&mut state);
+    monitor::remove(&mut state);
+    if state.replica {
+        replication::remove_replica(state.id);
+    }
+}
+
+/// Run a command that may wait, watching the socket meanwhile: what the client sends
+/// is buffered for after it, and if the client goes away the command is dropped,
+/// unregistering it from the keys it waits on, so it doesn't go on to take an element
+/// nobody will receive. Returns None if the client disconnected.
+async fn dispatch_watched<S: AsyncRead + Unpin>(
+    socket: &mut S,
+    parser: &mut FrameParser,
+    frame: Frame,
+    state: &mut ClientState,
+) -> Option<DispatchResult> {
+    let command = dispatch(frame, state);
+    tokio::pin!(command);
+    let mut buf = vec![0u8; READ_CHUNK];
+    loop {
+        tokio::select! {
+            result = &mut command => return Some(result),
+            read = socket.read(&mut buf) => match read {
+                Ok(0) | Err(_) => return None,
+                Ok(n) => parser.feed(&buf[..n]),
+            },
+        }
+    }
+}
+
+/// Render the `# Clients` section of INFO
+pub fn info_section() -> String {
+    format!(
+        "# Clients\r\nconnected_clients:{}\r\nmonitor_clients:{}\r\n",
+        CONNECTED_CLIENTS.load(Ordering::SeqCst),
+        monitor::count()
+    )
+}
+
+/// Move everything queued for the connection, such as pub/sub messages, onto the end
+/// of out, in the order it was queued
+fn take_queued(out_rx: &mut OutputReceiver, out: &mut Vec<u8>) {
+    while let Some(bytes) = out_rx.try_recv() {
+        out.extend_from_slice(&bytes);
+    }
+}
+
+/// Write the replies gathered in out, and anything queued since, in one write,
+/// leaving out empty for the next ones
+async fn write_gathered<S: AsyncWrite + Unpin>(
+    socket: &mut S,
+    out_tx: &OutputSender,
+    out_rx: &mut OutputReceiver,
+    peer: &str,
+    out: &mut Vec<u8>,
+) -> bool {
+    take_queued(out_rx, out);
+    if out.is_empty() {
+        return true;
+    }
+    let written = write(socket, out_tx, peer, out).await;
+    out.clear();
+    written
+}
+
+/// Write bytes to the client, unless it goes over its output buffer limit first:
+/// a client that doesn't read would otherwise leave the write, and everything
+/// queued behind it, waiting forever.
+/// Returns false, having logged why, if the connection should be closed.
+async fn write<S: AsyncWrite + Unpin>(
+    socket: &mut S,
+    out_tx: &OutputSender,
+    peer: &str,
+    bytes: &[u8],
+) -> bool {
+    tokio::select! {
+        // Flushed too, for streams such as TLS sessions that buffer what they send
+        written = async { socket.write_all(bytes).await?; socket.flush().await } => match written {
+            Ok(()) => true,
             Err(e) => {
-                eprintln!("Unexpected read error {}: {}", peer, e);
-                return;
+                verbose!("Write error {}: {}", peer, e);
+                false
+            }
+        },
+        _ = out_tx.limit_reached() => {
+            warn!("Closing client {}: client reached output buffer limit", peer);
+            false
+        }
+    }
+}
+
+/// What woke the connection loop up
+enum Event {
+    Read(std::io::Result<usize>),
+    TimedOut,
+    Push(Vec<u8>),
+    OutputLimit,
+}
+
+/// Read from the socket, giving up after idle_timeout seconds (0 waits forever).
+/// Returns None on timeout.
+async fn read_with_timeout<S: AsyncRead + Unpin>(
+    socket: &mut S,
+    buf: &mut [u8],
+    idle_timeout: u64,
+) -> Option<std::io::Result<usize>> {
+    if idle_timeout == 0 {
+        return Some(socket.read(buf).await);
+    }
+    tokio::time::timeout(Duration::from_secs(idle_timeout), socket.read(buf))
+        .await
+        .ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::pin::Pin;
+    use std::sync::Arc;
+    use std::task::{Context, Poll};
+    use tokio::io::{DuplexStream, ReadBuf};
+
+    /// Serve the far end of an in-memory pipe as a client connection
+    fn connect() -> DuplexStream {
+        connect_counted(Arc::new(AtomicUsize::new(0)))
+    }
+
+    /// Like connect, counting the writes the connection makes
+    fn connect_counted(writes: Arc<AtomicUsize>) -> DuplexStream {
+        let (client, server) = tokio::io::duplex(64 * 1024);
+        CONNECTED_CLIENTS.fetch_add(1, Ordering::SeqCst);
+        let server = CountWrites {
+            inner: server,
+            writes,
+        };
+        tokio::spawn(handle(server, "duplex".to_string(), ClientSlot));
+        client
+    }
+
+    struct CountWrites {
+        inner: DuplexStream,
+        writes: Arc<AtomicUsize>,
+    }
+
+    impl AsyncRead for CountWrites {
+        fn poll_read(
+            mut self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buf: &mut ReadBuf<'_>,
+        ) -> Poll<std::io::Result<()>> {
+            Pin::new(&mut self.inner).poll_read(cx, buf)
+        }
+    }
+
+    impl AsyncWrite for CountWrites {
+        fn poll_write(
+            mut self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buf: &[u8],
+        ) -> Poll<std::io::Result<usize>> {
+            self.writes.fetch_add(1, Ordering::SeqCst);
+            Pin::new(&mut self.inner).poll_write(cx, buf)
+        }
+
+        fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+            Pin::new(&mut self.inner).poll_flush(cx)
+        }
+
+        fn poll_shutdown(
+            mut self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+        ) -> Poll<std::io::Result<()>> {
+            Pin::new(&mut self.inner).poll_shutdown(cx)
+        }
+    }
+
+    /// Send bytes and read until exactly the expected reply has arrived
+    async fn exchange(client: &mut DuplexStream, request: &[u8], expected: &[u8]) {
+        client.write_all(request).await.unwrap();
+        let mut reply = vec![0u8; expected.len()];
+        tokio::time::timeout(Duration::from_secs(5), client.read_exact(&mut reply))
+            .await
+            .expect("no reply in time")
+            .unwrap();
+        assert_eq!(
+            String::from_utf8_lossy(&reply),
+            String::from_utf8_lossy(expected)
+        );
+    }
+
+    #[tokio::test]
+    async fn commands_through_a_pipe_get_their_replies() {
+        let mut client = connect();
+        exchange(&mut client, b"*1\r\n$4\r\nPING\r\n", b"+PONG\r\n").await;
+        exchange(
+            &mut client,
+            b"*3\r\n$3\r\nSET\r\n$11\r\nserver:pipe\r\n$5\r\nvalue\r\n",
+            b"+OK\r\n",
+        )
+        .await;
+        exchange(
+            &mut client,
+            b"*2\r\n$3\r\nGET\r\n$11\r\nserver:pipe\r\n",
+            b"$5\r\nvalue\r\n",
+        )
+        .await;
+        // Inline commands, and a command split across writes
+        exchange(&mut client, b"ECHO hi\r\n", b"$2\r\nhi\r\n").await;
+        client.write_all(b"*2\r\n$4\r\nEC").await.unwrap();
+        exchange(&mut client, b"HO\r\n$3\r\nabc\r\n", b"$3\r\nabc\r\n").await;
+    }
+
+    #[tokio::test]
+    async fn pipelined_commands_are_answered_in_order() {
+        let mut client = connect();
+        exchange(
+            &mut client,
+            b"*2\r\n$4\r\nINCR\r\n$14\r\nserver:counter\r\n*2\r\n$4\r\nINCR\r\n$14\r\nserver:counter\r\n*1\r\n$4\r\nPING\r\n",
+            b":1\r\n:2\r\n+PONG\r\n",
+        )
+        .await;
+    }
+
+    #[tokio::test]
+    async fn inline_pings_in_one_packet_each_get_a_reply() {
+        let mut client = connect();
+        exchange(
+            &mut client,
+            b"PING\r\nPING\r\nPING\r\n",
+            b"+PONG\r\n+PONG\r\n+PONG\r\n",
+        )
+        .await;
+    }
+
+    #[tokio::test]
+    async fn a_protocol_error_in_a_multibulk_closes_the_connection() {
+        let mut client = connect();
+        exchange(
+            &mut client,
+            b"*1\r\n$x\r\n*1\r\n$4\r\nPING\r\n",
+            b"-ERR Protocol error: invalid bulk length\r\n",
+        )
+        .await;
+        let mut rest = Vec::new();
+        client.read_to_end(&mut rest).await.unwrap();
+        assert!(rest.is_empty());
+    }
+
+    #[tokio::test]
+    async fn quit_closes_the_connection() {
+        let mut client = connect();
+        exchange(&mut client, b"*1\r\n$4\r\nQUIT\r\n", b"+OK\r\n").await;
+        let mut rest = Vec::new();
+        client.read_to_end(&mut rest).await.unwrap();
+        assert!(rest.is_empty());
+    }
+
+    #[tokio::test]
+    async fn a_pipeline_is_answered_in_one_write() {
+        let writes = Arc::new(AtomicUsize::new(0));
+        let mut client = connect_counted(writes.clone());
+        let mut pipeline = Vec::new();
+        let mut expected = Vec::new();
+        for i in 0..50 {
+            let value = i.to_string();
+            pipeline.extend(
+                Frame::Array(Some(vec![
+                    Frame::BulkString(Some(b"ECHO".to_vec())),
+                    Frame::BulkString(Some(value.clone().into_bytes())),
+                ]))
+                .encode(),
+            );
+            expected.extend(Frame::BulkString(Some(value.into_bytes())).encode());
+        }
+        exchange(&mut client, &pipeline, &expected).await;
+        assert_eq!(writes.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn replies_before_a_blocking_command_are_not_held_back() {
+        let mut client = connect();
+        exchange(
+            &mut client,
+            b"*1\r\n$4\r\nPING\r\n*3\r\n$5\r\nBLPOP\r\n$12\r\nserver:block\r\n$1\r\n0\r\n",
+            b"+PONG\r\n",
+        )
+        .await;
+        let mut other = connect();
+        exchange(
+            &mut other,
+            b"*3\r\n$5\r\nRPUSH\r\n$12\r\nserver:block\r\n$1\r\nv\r\n",
+            b":1\r\n",
+        )
+        .await;
+        let mut reply = vec![0u8; b"*2\r\n$12\r\nserver:block\r\n$1\r\nv\r\n".len()];
+        tokio::time::timeout(Duration::from_secs(5), client.read_exact(&mut reply))
+            .await
+            .expect("BLPOP was not served")
+            .unwrap();
+        assert_eq!(reply, b"*2\r\n$12\r\nserver:block\r\n$1\r\nv\r\n");
+    }
+
+    fn command(args: &[&[u8]]) -> Vec<u8> {
+        Frame::Array(Some(
+            args.iter()
+                .map(|arg| Frame::BulkString(Some(arg.to_vec())))
+                .collect(),
+        ))
+        .encode()
+    }
+
+    #[tokio::test]
+    async fn messages_and_replies_reach_a_subscriber_whole_and_in_order() {
+        let mut subscriber = connect();
+        exchange(
+            &mut subscriber,
+            &command(&[b"SUBSCRIBE", b"server:a", b"server:b", b"server:c"]),
+            b"*3\r\n$9\r\nsubscribe\r\n$8\r\nserver:a\r\n:1\r\n\
+              *3\r\n$9\r\nsubscribe\r\n$8\r\nserver:b\r\n:2\r\n\
+              *3\r\n$9\r\nsubscribe\r\n$8\r\nserver:c\r\n:3\r\n",
+        )
+        .await;
+
+        // Large messages published while the subscriber's own PINGs are answered
+        let mut publisher = connect();
+        let payload = "x".repeat(10_000);
+        let publishing = tokio::spawn(async move {
+            for i in 0..100 {
+                let message = format!("{:03}{}", i, payload);
+                publisher
+                    .write_all(&command(&[b"PUBLISH", b"server:b", message.as_bytes()]))
+                    .await
+                    .unwrap();
+                let mut reply = [0u8; 4];
+                publisher.read_exact(&mut reply).await.unwrap();
+                assert_eq!(&reply, b":1\r\n");
+            }
+        });
+        for _ in 0..100 {
+            subscriber.write_all(&command(&[b"PING"])).await.unwrap();
+        }
+
+        let mut parser = crate::resp::parser::FrameParser::new();
+        let (mut messages, mut pongs) = (0, 0);
+        let mut buf = vec![0u8; 64 * 1024];
+        while messages + pongs < 200 {
+            let n = tokio::time::timeout(Duration::from_secs(5), subscriber.read(&mut buf))
+                .await
+                .expect("no reply in time")
+                .unwrap();
+            parser.feed(&buf[..n]);
+            while let Some(frame) = parser.parse().unwrap() {
+                let Frame::Array(Some(items)) = frame else {
+                    panic!("expected an array, got {:?}", frame);
+                };
+                match &items[0] {
+                    Frame::BulkString(Some(kind)) if kind == b"message" => {
+                        let Frame::BulkString(Some(message)) = &items[2] else {
+                            panic!("expected a message, got {:?}", items);
+                        };
+                        assert_eq!(message.len(), 10_003);
+                        assert_eq!(&message[..3], format!("{:03}", messages).as_bytes());
+                        messages += 1;
+                    }
+                    Frame::BulkString(Some(kind)) if kind == b"pong" => pongs += 1,
+                    other => panic!("unexpected frame {:?}", other),
+                }
             }
         }
+        publishing.await.unwrap();
+        assert_eq!((messages, pongs), (100, 100));
     }
 }
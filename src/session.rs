@@ -0,0 +1,77 @@
+//! A client with no socket behind it: commands go straight to dispatch, with the
+//! same per-connection state a network client has, and the reply comes back as
+//! the bytes that would have been written. Tests and fuzzers drive the server
+//! through it.
+use crate::commands::dispatch;
+use crate::model::client_state::ClientState;
+use crate::output::{self, OutputReceiver};
+use crate::resp::parser::FrameParser;
+use crate::resp::Frame;
+
+pub struct Session {
+    state: ClientState,
+    parser: FrameParser,
+    out_rx: OutputReceiver,
+}
+
+impl Default for Session {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Session {
+    pub fn new() -> Self {
+        let mut state = ClientState::new();
+        state.addr = "session".to_string();
+        let (out_tx, out_rx) = output::channel();
+        state.push_tx = Some(out_tx);
+        Session {
+            state,
+            parser: FrameParser::new(),
+            out_rx,
+        }
+    }
+
+    /// Run one command, given as its name and arguments, and return the encoded reply,
+    /// after anything pushed to the client while it ran as a connection would
+    pub async fn call<A: AsRef<[u8]>>(&mut self, args: &[A]) -> Vec<u8> {
+        let frame = Frame::Array(Some(
+            args.iter()
+                .map(|arg| Frame::BulkString(Some(arg.as_ref().to_vec())))
+                .collect(),
+        ));
+        self.run(frame).await
+    }
+
+    /// Feed raw bytes, as if read from a socket, and return everything written back
+    /// for the commands they complete, protocol errors included. Nothing more is
+    /// read after an error a connection would be closed for.
+    pub async fn feed(&mut self, bytes: &[u8]) -> Vec<u8> {
+        self.parser.feed(bytes);
+        let mut written = Vec::new();
+        loop {
+            match self.parser.parse() {
+                Ok(Some(frame)) => written.extend(self.run(frame).await),
+                Ok(None) => break,
+                Err(e) => {
+                    Frame::Error(e).encode_into(&mut written);
+                    if self.parser.failed() {
+                        break;
+                    }
+                }
+            }
+        }
+        written
+    }
+
+    async fn run(&mut self, frame: Frame) -> Vec<u8> {
+        let reply = dispatch(frame, &mut self.state).await.reply;
+        let mut written = Vec::new();
+        while let Some(pushed) = self.out_rx.try_recv() {
+            written.extend(pushed);
+        }
+        written.extend(reply);
+        written
+    }
+}
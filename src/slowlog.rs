@@ -0,0 +1,102 @@
+//! Recent commands that took longer than slowlog-log-slower-than to execute
+use crate::config;
+use crate::resp::Frame;
+use once_cell::sync::Lazy;
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// Like Redis, only the first arguments of a command, truncated, are kept
+const MAX_ARGS: usize = 32;
+const MAX_ARG_LEN: usize = 128;
+
+#[derive(Debug, Clone)]
+pub struct Entry {
+    pub id: u64,
+    pub timestamp: u64, // Unix time in seconds
+    pub duration_us: u64,
+    pub args: Vec<Vec<u8>>,
+    pub client_addr: String,
+    pub client_name: String,
+}
+
+#[derive(Default)]
+struct SlowLog {
+    entries: VecDeque<Entry>, // newest first
+    next_id: u64,
+}
+
+static SLOWLOG: Lazy<Mutex<SlowLog>> = Lazy::new(|| Mutex::new(SlowLog::default()));
+
+/// Whether commands should be timed at all (a negative threshold disables the log)
+pub fn enabled() -> bool {
+    config::get_config().slowlog_log_slower_than >= 0
+}
+
+/// Copy the arguments of a command the way they are kept in the log
+pub fn capture_args(frame: &Frame) -> Vec<Vec<u8>> {
+    let Frame::Array(Some(items)) = frame else {
+        return Vec::new();
+    };
+    let mut args: Vec<Vec<u8>> = items
+        .iter()
+        .take(MAX_ARGS)
+        .map(|item| match item {
+            Frame::BulkString(Some(bs)) if bs.len() > MAX_ARG_LEN => {
+                let mut arg = bs[..MAX_ARG_LEN].to_vec();
+                arg.extend_from_slice(
+                    format!("... ({} more bytes)", bs.len() - MAX_ARG_LEN).as_bytes(),
+                );
+                arg
+            }
+            Frame::BulkString(Some(bs)) => bs.clone(),
+            _ => Vec::new(),
+        })
+        .collect();
+    if items.len() > MAX_ARGS {
+        args[MAX_ARGS - 1] =
+            format!("... ({} more arguments)", items.len() - MAX_ARGS + 1).into_bytes();
+    }
+    args
+}
+
+/// Record a command if it ran for longer than the configured threshold
+pub fn record(args: Vec<Vec<u8>>, duration: Duration, client_addr: &str, client_name: &str) {
+    let config = config::get_config();
+    let duration_us = duration.as_micros() as u64;
+    if config.slowlog_log_slower_than < 0 || duration_us < config.slowlog_log_slower_than as u64 {
+        return;
+    }
+    let timestamp = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+
+    let mut log = SLOWLOG.lock().unwrap();
+    let id = log.next_id;
+    log.next_id += 1;
+    log.entries.push_front(Entry {
+        id,
+        timestamp,
+        duration_us,
+        args,
+        client_addr: client_addr.to_string(),
+        client_name: client_name.to_string(),
+    });
+    log.entries.truncate(config.slowlog_max_len);
+}
+
+/// The `count` most recent entries, newest first (all of them if count is None)
+pub fn get(count: Option<usize>) -> Vec<Entry> {
+    let log = SLOWLOG.lock().unwrap();
+    let count = count.unwrap_or(log.entries.len());
+    log.entries.iter().take(count).cloned().collect()
+}
+
+pub fn len() -> usize {
+    SLOWLOG.lock().unwrap().entries.len()
+}
+
+pub fn reset() {
+    SLOWLOG.lock().unwrap().entries.clear();
+}
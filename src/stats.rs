@@ -0,0 +1,56 @@
+//! Server-wide statistics reported in the INFO `# Stats` section
+use crate::pubsub;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+pub static TOTAL_COMMANDS_PROCESSED: AtomicU64 = AtomicU64::new(0);
+pub static TOTAL_CONNECTIONS_RECEIVED: AtomicU64 = AtomicU64::new(0);
+pub static EXPIRED_KEYS: AtomicU64 = AtomicU64::new(0);
+pub static EXPIRED_SUBKEYS: AtomicU64 = AtomicU64::new(0); // hash fields past their TTL
+pub static KEYSPACE_HITS: AtomicU64 = AtomicU64::new(0);
+pub static KEYSPACE_MISSES: AtomicU64 = AtomicU64::new(0);
+pub static TOTAL_PUBSUB_MESSAGES: AtomicU64 = AtomicU64::new(0); // deliveries to subscribers
+
+/// Increment a counter by one
+pub fn incr(counter: &AtomicU64) {
+    counter.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Increment a counter by an arbitrary amount
+pub fn incr_by(counter: &AtomicU64, n: u64) {
+    counter.fetch_add(n, Ordering::Relaxed);
+}
+
+/// Zero every counter, as done by CONFIG RESETSTAT
+pub fn reset() {
+    for counter in [
+        &TOTAL_COMMANDS_PROCESSED,
+        &TOTAL_CONNECTIONS_RECEIVED,
+        &EXPIRED_KEYS,
+        &EXPIRED_SUBKEYS,
+        &KEYSPACE_HITS,
+        &KEYSPACE_MISSES,
+        &TOTAL_PUBSUB_MESSAGES,
+    ] {
+        counter.store(0, Ordering::Relaxed);
+    }
+}
+
+/// Render the `# Stats` section of INFO
+pub fn info_section() -> String {
+    let fields = [
+        ("total_connections_received", &TOTAL_CONNECTIONS_RECEIVED),
+        ("total_commands_processed", &TOTAL_COMMANDS_PROCESSED),
+        ("expired_keys", &EXPIRED_KEYS),
+        ("expired_subkeys", &EXPIRED_SUBKEYS),
+        ("keyspace_hits", &KEYSPACE_HITS),
+        ("keyspace_misses", &KEYSPACE_MISSES),
+        ("total_pub_sub_messages", &TOTAL_PUBSUB_MESSAGES),
+    ];
+    let mut out = String::from("# Stats\r\n");
+    for (name, counter) in fields {
+        out.push_str(&format!("{}:{}\r\n", name, counter.load(Ordering::Relaxed)));
+    }
+    out.push_str(&format!("pubsub_channels:{}\r\n", pubsub::numchannels()));
+    out.push_str(&format!("pubsub_patterns:{}\r\n", pubsub::numpat()));
+    out
+}
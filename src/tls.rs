@@ -0,0 +1,89 @@
+//! TLS sessions over any byte stream, through rustls. A `Context` holds what the server
+//! presents to clients, and `accept` runs the handshake over a stream, returning a
+//! `TlsStream` that is read and written as plaintext, so TLS clients go through the
+//! async connection loop like a plain `TcpStream` does.
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio_rustls::rustls::crypto::ring;
+use tokio_rustls::rustls::pki_types::pem::PemObject;
+use tokio_rustls::rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use tokio_rustls::rustls::ServerConfig;
+use tokio_rustls::TlsAcceptor;
+
+pub use tokio_rustls::server::TlsStream;
+
+/// Settings shared by the sessions of one listener: the certificate and key it presents
+pub struct Context {
+    acceptor: TlsAcceptor,
+}
+
+impl Context {
+    /// A server context presenting the PEM certificate chain and private key in the
+    /// given files
+    pub fn server(cert_file: &Path, key_file: &Path) -> Result<Context, String> {
+        let certs = CertificateDer::pem_file_iter(cert_file)
+            .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+            .map_err(|e| format!("Failed to load certificate {}: {}", cert_file.display(), e))?;
+        let key = PrivateKeyDer::from_pem_file(key_file)
+            .map_err(|e| format!("Failed to load private key {}: {}", key_file.display(), e))?;
+        let config = ServerConfig::builder_with_provider(Arc::new(ring::default_provider()))
+            .with_safe_default_protocol_versions()
+            .map_err(|e| format!("Failed to create a TLS context: {}", e))?
+            .with_no_client_auth()
+            .with_single_cert(certs, key)
+            .map_err(|e| format!("Invalid certificate or private key: {}", e))?;
+        Ok(Context {
+            acceptor: TlsAcceptor::from(Arc::new(config)),
+        })
+    }
+}
+
+/// Perform the server side of the handshake over stream
+pub async fn accept<S: AsyncRead + AsyncWrite + Unpin>(
+    context: &Context,
+    stream: S,
+) -> io::Result<TlsStream<S>> {
+    context.acceptor.accept(stream).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::AsyncWriteExt;
+
+    fn fixture(name: &str) -> std::path::PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/fixtures/tls")
+            .join(name)
+    }
+
+    fn server_context() -> Context {
+        Context::server(&fixture("cert.pem"), &fixture("key.pem")).unwrap()
+    }
+
+    #[tokio::test]
+    async fn plaintext_fails_the_handshake() {
+        let server = server_context();
+        let (server_end, mut client_end) = tokio::io::duplex(1024);
+        client_end.write_all(b"*1\r\n$4\r\nPING\r\n").await.unwrap();
+        assert!(accept(&server, server_end).await.is_err());
+    }
+
+    #[test]
+    fn a_missing_certificate_is_reported() {
+        let error = Context::server(&fixture("missing.pem"), &fixture("key.pem"))
+            .err()
+            .unwrap();
+        assert!(error.contains("missing.pem"), "{}", error);
+    }
+
+    #[test]
+    fn a_key_file_without_a_key_is_reported() {
+        let error = Context::server(&fixture("cert.pem"), &fixture("cert.pem"))
+            .err()
+            .unwrap();
+        assert!(error.contains("private key"), "{}", error);
+    }
+}
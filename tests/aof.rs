@@ -0,0 +1,149 @@
+//! The append only file of a server process: what is written to it, and the dataset
+//! a restart rebuilds from it
+mod common;
+
+use codecrafters_redis::resp::Frame;
+use common::{bulk, ok, path_arg, temp_dir, Server};
+use std::io::Write;
+
+#[test]
+fn get_is_never_logged_but_set_is() {
+    let dir = temp_dir("aof-reads");
+    let server = Server::start(&["--dir", path_arg(&dir), "--appendonly", "yes"]);
+    let mut client = server.client();
+    assert_eq!(client.call(&["set", "key", "value"]), ok());
+    assert_eq!(client.call(&["get", "key"]), bulk("value"));
+    assert_eq!(client.call(&["get", "missing"]), Frame::BulkString(None));
+    assert_eq!(client.call(&["strlen", "key"]), Frame::Integer(5));
+    assert_eq!(
+        client.call(&["waitaof", "1", "0", "0"]),
+        Frame::Array(Some(vec![Frame::Integer(1), Frame::Integer(0)]))
+    );
+    // The commands follow the RDB image of the empty dataset the file was created
+    // with, after its EOF opcode and checksum
+    let log = std::fs::read(dir.join("appendonly.aof")).unwrap();
+    assert!(log.starts_with(b"REDIS"));
+    let commands =
+        b"*2\r\n$6\r\nSELECT\r\n$1\r\n0\r\n*3\r\n$3\r\nset\r\n$3\r\nkey\r\n$5\r\nvalue\r\n";
+    assert_eq!(&log[log.len() - commands.len()..], commands);
+    assert_eq!(log[log.len() - commands.len() - 9], 0xFF);
+}
+
+#[test]
+fn a_restart_replays_the_log() {
+    let dir = temp_dir("aof-replay");
+    let args = ["--dir", path_arg(&dir), "--appendonly", "yes"];
+    let server = Server::start(&args);
+    let mut client = server.client();
+    for command in [
+        &["set", "string", "v"][..],
+        &["incrby", "counter", "5"],
+        &["incrbyfloat", "float", "1.5"],
+        &["rpush", "list", "a", "b", "c"],
+        &["lpop", "list"],
+        &["sadd", "set", "x", "y"],
+        &["spop", "set"],
+        &["set", "volatile", "v", "EX", "1000"],
+        &["set", "gone", "v", "PX", "1"],
+        &["select", "1"],
+        &["set", "string", "in db 1"],
+    ] {
+        assert!(
+            !matches!(client.call(command), Frame::Error(_)),
+            "{:?}",
+            command
+        );
+    }
+    client.call(&["select", "0"]);
+    let expires = client.call(&["expiretime", "volatile"]);
+    let left = client.call(&["srandmember", "set", "10"]);
+    client.call(&["waitaof", "1", "0", "0"]);
+    let port = server.port;
+    drop(server);
+
+    // A write cut short by a crash is left out
+    let mut log = std::fs::OpenOptions::new()
+        .append(true)
+        .open(dir.join("appendonly.aof"))
+        .unwrap();
+    log.write_all(b"*3\r\n$3\r\nset\r\n$6\r\nstr").unwrap();
+    drop(log);
+
+    let server = Server::start_on(port, &args);
+    let mut client = server.client();
+    assert_eq!(client.call(&["get", "string"]), bulk("v"));
+    assert_eq!(client.call(&["get", "counter"]), bulk("5"));
+    assert_eq!(client.call(&["get", "float"]), bulk("1.5"));
+    assert_eq!(
+        client.call(&["lrange", "list", "0", "-1"]),
+        Frame::Array(Some(vec![bulk("b"), bulk("c")]))
+    );
+    assert_eq!(client.call(&["srandmember", "set", "10"]), left);
+    assert_eq!(client.call(&["expiretime", "volatile"]), expires);
+    assert_eq!(client.call(&["get", "gone"]), Frame::BulkString(None));
+    assert_eq!(client.call(&["select", "1"]), ok());
+    assert_eq!(client.call(&["get", "string"]), bulk("in db 1"));
+}
+
+#[test]
+fn waitaof_needs_appendonly_for_a_local_sync() {
+    let server = Server::start(&["--appendonly", "no"]);
+    let mut client = server.client();
+    assert_eq!(
+        client.call(&["waitaof", "1", "0", "0"]),
+        Frame::Error(
+            "ERR WAITAOF cannot be used when numlocal is set but appendonly is disabled.".into()
+        )
+    );
+    assert_eq!(
+        client.call(&["waitaof", "0", "0", "0"]),
+        Frame::Array(Some(vec![Frame::Integer(0), Frame::Integer(0)]))
+    );
+    assert_eq!(
+        client.call(&["waitaof", "0", "-1", "0"]),
+        Frame::Error("ERR value is out of range, must be positive".into())
+    );
+}
+
+#[test]
+fn turning_appendonly_on_keeps_the_dataset_loaded_from_the_rdb_file() {
+    let dir = temp_dir("aof-preamble");
+    let server = Server::start(&["--dir", path_arg(&dir)]);
+    let port = server.port;
+    let mut client = server.client();
+    assert_eq!(client.call(&["set", "a", "from rdb"]), ok());
+    assert_eq!(client.call(&["save"]), ok());
+    drop(server);
+
+    // The file is created at startup, with what the RDB file held
+    let args = ["--dir", path_arg(&dir), "--appendonly", "yes"];
+    let server = Server::start_on(port, &args);
+    let mut client = server.client();
+    assert_eq!(client.call(&["set", "b", "from aof"]), ok());
+    client.call(&["waitaof", "1", "0", "0"]);
+    drop(server);
+
+    let server = Server::start_on(port, &args);
+    let mut client = server.client();
+    assert_eq!(client.call(&["get", "a"]), bulk("from rdb"));
+    assert_eq!(client.call(&["get", "b"]), bulk("from aof"));
+
+    // Enabled at runtime, the file starts over with the dataset so far
+    assert_eq!(client.call(&["config", "set", "appendonly", "no"]), ok());
+    assert_eq!(client.call(&["set", "c", "unlogged"]), ok());
+    assert_eq!(client.call(&["config", "set", "appendonly", "yes"]), ok());
+    assert_eq!(client.call(&["set", "d", "logged"]), ok());
+    client.call(&["waitaof", "1", "0", "0"]);
+    drop(server);
+
+    let server = Server::start_on(port, &args);
+    let mut client = server.client();
+    for (key, value) in [
+        ("a", "from rdb"),
+        ("b", "from aof"),
+        ("c", "unlogged"),
+        ("d", "logged"),
+    ] {
+        assert_eq!(client.call(&["get", key]), bulk(value), "{}", key);
+    }
+}
@@ -0,0 +1,191 @@
+//! Blocking list and sorted set pops, in and out of transactions, and clients that
+//! disconnect from a server process while blocked
+mod common;
+
+use codecrafters_redis::resp::Frame;
+use codecrafters_redis::Session;
+use common::{bulk, eventually, info_field, Server};
+use std::time::Duration;
+use tokio::time::timeout;
+
+/// Run a command, failing the test if it is still blocked after a second
+async fn call(session: &mut Session, args: &[&str]) -> Vec<u8> {
+    timeout(Duration::from_secs(1), session.call(args))
+        .await
+        .unwrap_or_else(|_| panic!("{:?} blocked", args))
+}
+
+#[tokio::test]
+async fn blocking_commands_in_a_transaction_do_not_block() {
+    let mut session = Session::new();
+    assert_eq!(call(&mut session, &["multi"]).await, b"+OK\r\n");
+    for command in [
+        &["blpop", "block:none", "0"][..],
+        &["brpop", "block:none", "0"],
+        &["blmove", "block:none", "block:dest", "LEFT", "RIGHT", "0"],
+        &["brpoplpush", "block:none", "block:dest", "0"],
+        &["blmpop", "0", "1", "block:none", "LEFT"],
+        &["bzmpop", "0", "1", "block:none", "MIN"],
+    ] {
+        assert_eq!(call(&mut session, command).await, b"+QUEUED\r\n");
+    }
+    assert_eq!(
+        call(&mut session, &["exec"]).await,
+        b"*6\r\n*-1\r\n*-1\r\n$-1\r\n$-1\r\n*-1\r\n*-1\r\n"
+    );
+}
+
+#[tokio::test]
+async fn blocking_commands_in_a_transaction_still_pop() {
+    let mut session = Session::new();
+    call(&mut session, &["rpush", "block:tx", "a", "b", "c"]).await;
+    call(&mut session, &["multi"]).await;
+    call(&mut session, &["blpop", "block:tx", "0"]).await;
+    call(
+        &mut session,
+        &["blmpop", "0", "1", "block:tx", "RIGHT", "COUNT", "5"],
+    )
+    .await;
+    call(&mut session, &["blpop", "block:tx", "0"]).await;
+    assert_eq!(
+        call(&mut session, &["exec"]).await,
+        b"*3\r\n*2\r\n$8\r\nblock:tx\r\n$1\r\na\r\n\
+          *2\r\n$8\r\nblock:tx\r\n*2\r\n$1\r\nc\r\n$1\r\nb\r\n\
+          *-1\r\n"
+    );
+}
+
+#[tokio::test]
+async fn a_blocked_pop_is_served_by_a_later_push() {
+    let blocked = tokio::spawn(async {
+        let mut session = Session::new();
+        session.call(&["blpop", "block:wake", "5"]).await
+    });
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    let mut pusher = Session::new();
+    assert_eq!(
+        call(&mut pusher, &["rpush", "block:wake", "x"]).await,
+        b":1\r\n"
+    );
+    let reply = timeout(Duration::from_secs(1), blocked)
+        .await
+        .expect("the push didn't wake the pop")
+        .unwrap();
+    assert_eq!(reply, b"*2\r\n$10\r\nblock:wake\r\n$1\r\nx\r\n");
+    assert_eq!(
+        call(&mut pusher, &["type", "block:wake"]).await,
+        b"+none\r\n"
+    );
+}
+
+#[tokio::test]
+async fn a_blocked_zset_pop_is_served_by_a_later_zadd() {
+    let blocked = tokio::spawn(async {
+        let mut session = Session::new();
+        session
+            .call(&["bzmpop", "5", "1", "block:zwake", "MAX"])
+            .await
+    });
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    let mut adder = Session::new();
+    call(&mut adder, &["zadd", "block:zwake", "1", "a", "2", "b"]).await;
+    let reply = timeout(Duration::from_secs(1), blocked)
+        .await
+        .expect("the zadd didn't wake the pop")
+        .unwrap();
+    assert_eq!(
+        reply,
+        b"*2\r\n$11\r\nblock:zwake\r\n*1\r\n*2\r\n$1\r\nb\r\n$1\r\n2\r\n"
+    );
+}
+
+#[tokio::test]
+async fn a_blocked_pop_times_out_with_nil() {
+    let mut session = Session::new();
+    let started = std::time::Instant::now();
+    assert_eq!(
+        call(&mut session, &["brpop", "block:timeout", "0.1"]).await,
+        b"*-1\r\n"
+    );
+    assert!(started.elapsed() >= Duration::from_millis(100));
+}
+
+#[tokio::test]
+async fn a_blocked_move_is_served_once_per_pushed_element() {
+    let blocked: Vec<_> = (0..2)
+        .map(|_| {
+            tokio::spawn(async {
+                let mut session = Session::new();
+                session
+                    .call(&["blmove", "block:src", "block:dst", "RIGHT", "LEFT", "5"])
+                    .await
+            })
+        })
+        .collect();
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    let mut pusher = Session::new();
+    call(&mut pusher, &["rpush", "block:src", "x"]).await;
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    // One waiter got the element, the other found the source drained and waits on
+    let finished: Vec<bool> = blocked.iter().map(|task| task.is_finished()).collect();
+    assert_eq!(finished.iter().filter(|&&done| done).count(), 1);
+    call(&mut pusher, &["rpush", "block:src", "y"]).await;
+
+    let mut replies = Vec::new();
+    for task in blocked {
+        let reply = timeout(Duration::from_secs(1), task)
+            .await
+            .expect("the push didn't wake the move")
+            .unwrap();
+        replies.push(reply);
+    }
+    replies.sort();
+    assert_eq!(replies, [b"$1\r\nx\r\n", b"$1\r\ny\r\n"]);
+    assert_eq!(
+        call(&mut pusher, &["lrange", "block:dst", "0", "-1"]).await,
+        b"*2\r\n$1\r\ny\r\n$1\r\nx\r\n"
+    );
+    assert_eq!(
+        call(&mut pusher, &["type", "block:src"]).await,
+        b"+none\r\n"
+    );
+
+    assert_eq!(
+        call(
+            &mut pusher,
+            &["brpoplpush", "block:src", "block:dst", "0.1"]
+        )
+        .await,
+        b"$-1\r\n"
+    );
+}
+
+#[test]
+fn a_client_that_disconnects_while_blocked_takes_nothing() {
+    let server = Server::start(&[]);
+    let mut client = server.client();
+    for command in [
+        &["blpop", "gone", "0"][..],
+        &["blmpop", "0", "1", "gone", "LEFT"],
+        &["blmove", "gone", "elsewhere", "LEFT", "RIGHT", "0"],
+    ] {
+        let mut blocked = server.client();
+        blocked.send(command);
+        eventually("the command to block", || {
+            info_field(&mut client, "clients", "connected_clients") == "2"
+        });
+        drop(blocked);
+        eventually("the blocked client to be gone", || {
+            info_field(&mut client, "clients", "connected_clients") == "1"
+        });
+
+        assert_eq!(client.call(&["rpush", "gone", "v"]), Frame::Integer(1));
+        assert_eq!(
+            client.call(&["lrange", "gone", "0", "-1"]),
+            Frame::Array(Some(vec![bulk("v")])),
+            "{:?}",
+            command
+        );
+        assert_eq!(client.call(&["del", "gone"]), Frame::Integer(1));
+    }
+}
@@ -0,0 +1,107 @@
+//! Command introspection, from the command registry, through a socketless session
+use codecrafters_redis::Session;
+
+#[tokio::test]
+async fn command_docs_maps_each_known_command_to_its_docs() {
+    let mut session = Session::new();
+    let summary = "Returns the string value of a key.";
+    let get_docs = [
+        "$3\r\nget\r\n",
+        "*6\r\n",
+        "$7\r\nsummary\r\n",
+        &format!("${}\r\n{}\r\n", summary.len(), summary),
+        "$5\r\nsince\r\n$5\r\n1.0.0\r\n",
+        "$5\r\ngroup\r\n$6\r\nstring\r\n",
+    ]
+    .concat();
+    // Unknown commands are left out
+    assert_eq!(
+        session.call(&["command", "docs", "get", "nosuch"]).await,
+        format!("*2\r\n{}", get_docs).into_bytes()
+    );
+
+    session.call(&["hello", "3"]).await;
+    assert_eq!(
+        session.call(&["command", "docs", "GET"]).await,
+        format!("%1\r\n{}", get_docs.replacen("*6", "%3", 1)).into_bytes()
+    );
+    assert_eq!(
+        session.call(&["command", "docs", "nosuch"]).await,
+        b"%0\r\n"
+    );
+    let all = session.call(&["command", "docs"]).await;
+    let count = session.call(&["command", "count"]).await;
+    let count = std::str::from_utf8(&count[1..count.len() - 2]).unwrap();
+    assert!(all.starts_with(format!("%{}\r\n", count).as_bytes()));
+}
+
+fn elements(items: &[&str]) -> Vec<u8> {
+    let mut reply = format!("*{}\r\n", items.len()).into_bytes();
+    for item in items {
+        reply.extend(format!("${}\r\n{}\r\n", item.len(), item).into_bytes());
+    }
+    reply
+}
+
+#[tokio::test]
+async fn command_getkeys_picks_the_key_arguments() {
+    let mut session = Session::new();
+    for (command, keys) in [
+        (&["set", "foo", "bar"][..], &["foo"][..]),
+        (&["SET", "foo", "bar", "EX", "10"], &["foo"]),
+        (&["get", "foo"], &["foo"]),
+        (&["mset", "a", "1", "b", "2"], &["a", "b"]),
+        (&["mget", "a", "b", "c"], &["a", "b", "c"]),
+        (&["sintercard", "2", "a", "b", "LIMIT", "1"], &["a", "b"]),
+        (&["lmove", "src", "dst", "LEFT", "RIGHT"], &["src", "dst"]),
+    ] {
+        let mut args = vec!["command", "getkeys"];
+        args.extend_from_slice(command);
+        assert_eq!(session.call(&args).await, elements(keys), "{:?}", command);
+    }
+
+    assert_eq!(
+        session.call(&["command", "getkeys", "nosuch", "a"]).await,
+        b"-ERR Invalid command specified\r\n"
+    );
+    assert_eq!(
+        session.call(&["command", "getkeys", "ping"]).await,
+        b"-ERR The command has no key arguments\r\n"
+    );
+    assert_eq!(
+        session.call(&["command", "getkeys", "get"]).await,
+        b"-ERR Invalid number of arguments specified for command\r\n"
+    );
+    assert_eq!(
+        session
+            .call(&["command", "getkeys", "sintercard", "3", "a"])
+            .await,
+        b"-ERR Invalid arguments specified for command\r\n"
+    );
+}
+
+#[tokio::test]
+async fn command_info_gives_the_spec_of_each_named_command() {
+    let mut session = Session::new();
+    assert_eq!(
+        session
+            .call(&["command", "info", "get", "nosuch", "SET"])
+            .await,
+        [
+            &b"*3\r\n"[..],
+            b"*6\r\n$3\r\nget\r\n:2\r\n*2\r\n+readonly\r\n+fast\r\n:1\r\n:1\r\n:1\r\n",
+            b"*-1\r\n",
+            b"*6\r\n$3\r\nset\r\n:-3\r\n*2\r\n+write\r\n+denyoom\r\n:1\r\n:1\r\n:1\r\n",
+        ]
+        .concat()
+    );
+    assert_eq!(
+        session.call(&["command", "info", "ping"]).await,
+        b"*1\r\n*6\r\n$4\r\nping\r\n:-1\r\n*1\r\n+fast\r\n:0\r\n:0\r\n:0\r\n"
+    );
+    // With no names, every command there is
+    let all = session.call(&["command", "info"]).await;
+    let count = session.call(&["command", "count"]).await;
+    let count = std::str::from_utf8(&count[1..count.len() - 2]).unwrap();
+    assert!(all.starts_with(format!("*{}\r\n", count).as_bytes()));
+}
@@ -0,0 +1,185 @@
+//! Running the server binary, for tests that need a process of their own: restarts,
+//! replication, and anything listening on a port
+#![allow(dead_code)] // each test binary uses its own part of this
+use codecrafters_redis::resp::parser::FrameParser;
+use codecrafters_redis::resp::Frame;
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::time::{Duration, Instant};
+
+/// A server process, killed when dropped
+pub struct Server {
+    child: Child,
+    pub port: u16,
+}
+
+impl Server {
+    /// Start the server on a free port with extra command line arguments, and wait
+    /// until it accepts connections
+    pub fn start(args: &[&str]) -> Server {
+        let port = TcpListener::bind("127.0.0.1:0")
+            .and_then(|listener| listener.local_addr())
+            .unwrap()
+            .port();
+        Server::start_on(port, args)
+    }
+
+    pub fn start_on(port: u16, args: &[&str]) -> Server {
+        Server::spawn(port, args, Stdio::null())
+    }
+
+    /// Like start, keeping what the server logs to stdout for `logged`
+    pub fn start_logging(args: &[&str]) -> Server {
+        let port = TcpListener::bind("127.0.0.1:0")
+            .and_then(|listener| listener.local_addr())
+            .unwrap()
+            .port();
+        Server::spawn(port, args, Stdio::piped())
+    }
+
+    fn spawn(port: u16, args: &[&str], stdout: Stdio) -> Server {
+        // The arguments go first, where a config file has to be
+        let child = Command::new(env!("CARGO_BIN_EXE_codecrafters-redis"))
+            .args(args)
+            .arg("--port")
+            .arg(port.to_string())
+            .stdout(stdout)
+            .stderr(Stdio::null())
+            .spawn()
+            .unwrap();
+        let server = Server { child, port };
+        let started = Instant::now();
+        while TcpStream::connect(("127.0.0.1", port)).is_err() {
+            assert!(
+                started.elapsed() < Duration::from_secs(10),
+                "server didn't start"
+            );
+            std::thread::sleep(Duration::from_millis(20));
+        }
+        server
+    }
+
+    pub fn client(&self) -> Client {
+        Client::connect(self.port)
+    }
+
+    /// Stop a server started with start_logging and return everything it logged
+    pub fn logged(mut self) -> String {
+        let _ = self.child.kill();
+        let mut out = String::new();
+        if let Some(mut stdout) = self.child.stdout.take() {
+            stdout.read_to_string(&mut out).unwrap();
+        }
+        out
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+/// A connection that sends commands and reads back one reply for each
+pub struct Client {
+    stream: TcpStream,
+    parser: FrameParser,
+}
+
+impl Client {
+    pub fn connect(port: u16) -> Client {
+        let stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        Client {
+            stream,
+            parser: FrameParser::new(),
+        }
+    }
+
+    pub fn call(&mut self, args: &[&str]) -> Frame {
+        self.send(args);
+        self.read()
+    }
+
+    pub fn send(&mut self, args: &[&str]) {
+        let command = Frame::Array(Some(
+            args.iter()
+                .map(|arg| Frame::BulkString(Some(arg.as_bytes().to_vec())))
+                .collect(),
+        ));
+        self.stream.write_all(&command.encode()).unwrap();
+    }
+
+    /// Send raw bytes, such as a malformed command
+    pub fn write(&mut self, bytes: &[u8]) {
+        self.stream.write_all(bytes).unwrap();
+    }
+
+    /// Whether the server closes the connection before the read timeout, sending nothing
+    pub fn closed(&mut self) -> bool {
+        let mut buf = [0u8; 1];
+        match self.stream.read(&mut buf) {
+            Ok(n) => n == 0,
+            Err(e) => e.kind() == std::io::ErrorKind::ConnectionReset,
+        }
+    }
+
+    /// Read the next frame the server sends
+    pub fn read(&mut self) -> Frame {
+        let mut buf = [0u8; 4096];
+        loop {
+            if let Some(frame) = self.parser.parse().unwrap() {
+                return frame;
+            }
+            let n = self.stream.read(&mut buf).expect("no reply in time");
+            assert!(n > 0, "connection closed");
+            self.parser.feed(&buf[..n]);
+        }
+    }
+}
+
+/// A field of an INFO section, or an empty string if it isn't there
+pub fn info_field(client: &mut Client, section: &str, name: &str) -> String {
+    let Frame::BulkString(Some(info)) = client.call(&["info", section]) else {
+        panic!("INFO didn't reply with a bulk string");
+    };
+    let prefix = format!("{}:", name);
+    String::from_utf8_lossy(&info)
+        .lines()
+        .find_map(|line| line.strip_prefix(&prefix).map(str::to_string))
+        .unwrap_or_default()
+}
+
+pub fn bulk(value: &str) -> Frame {
+    Frame::BulkString(Some(value.as_bytes().to_vec()))
+}
+
+pub fn ok() -> Frame {
+    Frame::SimpleString("OK".into())
+}
+
+/// A fresh, empty directory for a test's files
+pub fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("redis-rust-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+pub fn path_arg(path: &Path) -> &str {
+    path.to_str().unwrap()
+}
+
+/// Poll until `done` holds, failing the test after a few seconds
+pub fn eventually(what: &str, mut done: impl FnMut() -> bool) {
+    let started = Instant::now();
+    while !done() {
+        assert!(started.elapsed() < Duration::from_secs(5), "{}", what);
+        std::thread::sleep(Duration::from_millis(20));
+    }
+}
@@ -0,0 +1,321 @@
+//! Per-connection commands and limits: through a socketless session where the state
+//! is the connection's own, and through a server process where a socket is involved
+mod common;
+
+use codecrafters_redis::resp::Frame;
+use codecrafters_redis::Session;
+
+#[test]
+fn a_subscriber_that_never_reads_is_closed_at_its_output_limit() {
+    let server = common::Server::start(&[]);
+    let mut publisher = server.client();
+    assert_eq!(
+        publisher.call(&[
+            "config",
+            "set",
+            "client-output-buffer-limit",
+            "pubsub 1mb 0 0"
+        ]),
+        common::ok()
+    );
+    let mut subscriber = server.client();
+    subscriber.call(&["subscribe", "flood"]);
+
+    // Far more than the socket buffers and the limit together hold
+    let message = "x".repeat(64 * 1024);
+    let mut delivered = 0;
+    for _ in 0..1_000 {
+        match publisher.call(&["publish", "flood", &message]) {
+            Frame::Integer(1) => delivered += 1,
+            Frame::Integer(0) => break,
+            other => panic!("PUBLISH replied {:?}", other),
+        }
+    }
+    assert!(delivered < 1_000, "the subscriber was never closed");
+    common::eventually("the subscriber to be disconnected", || {
+        common::info_field(&mut publisher, "clients", "connected_clients") == "1"
+    });
+    // The server is still serving
+    assert_eq!(
+        publisher.call(&["ping"]),
+        Frame::SimpleString("PONG".into())
+    );
+    drop(subscriber);
+}
+
+#[test]
+fn acl_reports_the_default_user_and_its_password() {
+    let server = common::Server::start(&[]);
+    let mut client = server.client();
+    let bulk = common::bulk;
+    assert_eq!(client.call(&["acl", "whoami"]), bulk("default"));
+    assert_eq!(
+        client.call(&["acl", "list"]),
+        Frame::Array(Some(vec![bulk("user default on nopass ~* &* +@all")]))
+    );
+    assert_eq!(
+        client.call(&["acl", "users"]),
+        Frame::Array(Some(vec![bulk("default")]))
+    );
+    assert_eq!(
+        client.call(&["acl", "getuser", "nobody"]),
+        Frame::BulkString(None)
+    );
+    let Frame::Array(Some(categories)) = client.call(&["acl", "cat"]) else {
+        panic!("ACL CAT didn't reply with an array");
+    };
+    assert!(categories.contains(&bulk("keyspace")), "{:?}", categories);
+    let Frame::Array(Some(fast)) = client.call(&["acl", "cat", "fast"]) else {
+        panic!("ACL CAT fast didn't reply with an array");
+    };
+    assert!(fast.contains(&bulk("get")) && !fast.contains(&bulk("keys")));
+
+    // With requirepass set, the rule has the password's SHA-256 instead of nopass
+    client.call(&["config", "set", "requirepass", "secret"]);
+    client.call(&["auth", "secret"]);
+    let hash = "2bb80d537b1da3e38bd30361aa855686bde0eacd7162fef6a25fe97bf527a25b";
+    assert_eq!(
+        client.call(&["acl", "list"]),
+        Frame::Array(Some(vec![bulk(&format!(
+            "user default on #{} ~* &* +@all",
+            hash
+        ))]))
+    );
+    assert_eq!(
+        client.call(&["acl", "getuser", "default"]),
+        Frame::Array(Some(vec![
+            bulk("flags"),
+            Frame::Array(Some(vec![bulk("on")])),
+            bulk("passwords"),
+            Frame::Array(Some(vec![bulk(hash)])),
+            bulk("commands"),
+            bulk("+@all"),
+            bulk("keys"),
+            bulk("~*"),
+            bulk("channels"),
+            bulk("&*"),
+            bulk("selectors"),
+            Frame::Array(Some(Vec::new())),
+        ]))
+    );
+}
+
+#[test]
+fn a_10mb_value_arrives_whole() {
+    let server = common::Server::start(&[]);
+    let mut client = server.client();
+    let value: String = (0..10 * 1024 * 1024)
+        .map(|i| (b'a' + (i % 26) as u8) as char)
+        .collect();
+    assert_eq!(client.call(&["set", "big", &value]), common::ok());
+    assert_eq!(
+        client.call(&["strlen", "big"]),
+        Frame::Integer(value.len() as i64)
+    );
+    assert_eq!(client.call(&["get", "big"]), common::bulk(&value));
+}
+
+#[tokio::test]
+async fn ping_replies_pong_or_echoes_its_message() {
+    let mut session = Session::new();
+    assert_eq!(session.call(&["ping"]).await, b"+PONG\r\n");
+    assert_eq!(session.call(&["ping", "hello"]).await, b"$5\r\nhello\r\n");
+    assert_eq!(
+        session.call(&["ping", "a", "b"]).await,
+        b"-ERR wrong number of arguments for 'ping'\r\n"
+    );
+
+    // A RESP2 subscriber gets a pong message instead
+    session.call(&["subscribe", "conn:ping"]).await;
+    assert_eq!(
+        session.call(&["ping"]).await,
+        b"*2\r\n$4\r\npong\r\n$0\r\n\r\n"
+    );
+    assert_eq!(
+        session.call(&["ping", "hi"]).await,
+        b"*2\r\n$4\r\npong\r\n$2\r\nhi\r\n"
+    );
+
+    // Over RESP3 replies and pushes can't be confused, so PING is answered as usual
+    let mut session = Session::new();
+    session.call(&["hello", "3"]).await;
+    session.call(&["subscribe", "conn:ping"]).await;
+    assert_eq!(session.call(&["ping"]).await, b"+PONG\r\n");
+}
+
+#[tokio::test]
+async fn reset_returns_the_connection_to_its_initial_state() {
+    let mut session = Session::new();
+    assert_eq!(session.call(&["multi"]).await, b"+OK\r\n");
+    session.call(&["set", "conn:reset", "queued"]).await;
+    assert_eq!(session.call(&["reset"]).await, b"+RESET\r\n");
+    assert_eq!(
+        session.call(&["exec"]).await,
+        b"-ERR EXEC without MULTI\r\n"
+    );
+    assert_eq!(session.call(&["get", "conn:reset"]).await, b"$-1\r\n");
+
+    session.call(&["select", "2"]).await;
+    session.call(&["set", "conn:reset:db", "two"]).await;
+    session.call(&["client", "setname", "pooled"]).await;
+    session.call(&["subscribe", "conn:reset"]).await;
+    session.call(&["reset"]).await;
+    // Out of subscriber mode, in database 0, nameless
+    assert_eq!(session.call(&["ping"]).await, b"+PONG\r\n");
+    assert_eq!(session.call(&["get", "conn:reset:db"]).await, b"$-1\r\n");
+    assert_eq!(session.call(&["client", "getname"]).await, b"$-1\r\n");
+    assert_eq!(
+        session.call(&["reset", "now"]).await,
+        b"-ERR wrong number of arguments for 'reset'\r\n"
+    );
+}
+
+#[test]
+fn reset_deauthenticates_when_a_password_is_set() {
+    let server = common::Server::start(&[]);
+    let mut client = server.client();
+    assert_eq!(
+        client.call(&["config", "set", "requirepass", "secret"]),
+        common::ok()
+    );
+    assert_eq!(client.call(&["auth", "secret"]), common::ok());
+    assert_eq!(client.call(&["get", "k"]), Frame::BulkString(None));
+    assert_eq!(client.call(&["reset"]), Frame::SimpleString("RESET".into()));
+    assert_eq!(
+        client.call(&["get", "k"]),
+        Frame::Error("NOAUTH Authentication required.".into())
+    );
+}
+
+#[test]
+fn idle_clients_are_closed_after_the_timeout() {
+    let server = common::Server::start(&[]);
+    let mut admin = server.client();
+    assert_eq!(admin.call(&["config", "set", "timeout", "1"]), common::ok());
+    let mut idle = server.client();
+    let mut active = server.client();
+    assert_eq!(idle.call(&["ping"]), Frame::SimpleString("PONG".into()));
+    // The active client keeps talking for longer than the timeout
+    for _ in 0..8 {
+        assert_eq!(active.call(&["ping"]), Frame::SimpleString("PONG".into()));
+        std::thread::sleep(std::time::Duration::from_millis(250));
+    }
+    assert!(idle.closed(), "the idle client was left connected");
+    assert_eq!(active.call(&["ping"]), Frame::SimpleString("PONG".into()));
+
+    // With the timeout off, nobody is closed
+    assert_eq!(
+        active.call(&["config", "set", "timeout", "0"]),
+        common::ok()
+    );
+    let mut patient = server.client();
+    patient.call(&["ping"]);
+    std::thread::sleep(std::time::Duration::from_millis(1500));
+    assert_eq!(patient.call(&["ping"]), Frame::SimpleString("PONG".into()));
+}
+
+#[test]
+fn clients_past_maxclients_are_refused() {
+    let server = common::Server::start(&[]);
+    let mut admin = server.client();
+    assert_eq!(
+        admin.call(&["config", "set", "maxclients", "2"]),
+        common::ok()
+    );
+    let mut second = server.client();
+    assert_eq!(second.call(&["ping"]), Frame::SimpleString("PONG".into()));
+
+    let mut third = server.client();
+    assert_eq!(
+        third.read(),
+        Frame::Error("ERR max number of clients reached".into())
+    );
+    assert!(third.closed());
+
+    // A disconnect frees its slot
+    drop(second);
+    common::eventually("the freed slot was never reused", || {
+        let mut again = server.client();
+        again.send(&["ping"]);
+        again.read() == Frame::SimpleString("PONG".into())
+    });
+    assert_eq!(
+        admin.call(&["config", "get", "tcp-keepalive"]),
+        Frame::Array(Some(vec![
+            common::bulk("tcp-keepalive"),
+            common::bulk("300")
+        ]))
+    );
+}
+
+#[test]
+fn a_monitor_sees_the_commands_of_other_clients() {
+    let server = common::Server::start(&[]);
+    let mut monitor = server.client();
+    let mut client = server.client();
+    assert_eq!(monitor.call(&["monitor"]), common::ok());
+    assert_eq!(
+        common::info_field(&mut client, "clients", "monitor_clients"),
+        "1"
+    );
+
+    let Frame::SimpleString(line) = monitor.read() else {
+        panic!("MONITOR didn't send a status line");
+    };
+    assert!(line.ends_with("] \"info\" \"clients\""), "{}", line);
+
+    client.call(&["select", "1"]);
+    client.call(&["set", "k", "two words\n"]);
+    let Frame::SimpleString(line) = monitor.read() else {
+        panic!("MONITOR didn't send a status line");
+    };
+    let (time, rest) = line.split_once(' ').unwrap();
+    let (secs, micros) = time.split_once('.').unwrap();
+    assert!(secs.parse::<u64>().is_ok() && micros.len() == 6, "{}", line);
+    assert!(rest.starts_with("[0 127.0.0.1:"), "{}", line);
+    assert!(rest.ends_with("] \"select\" \"1\""), "{}", line);
+    let Frame::SimpleString(line) = monitor.read() else {
+        panic!("MONITOR didn't send a status line");
+    };
+    assert!(line.contains(" [1 127.0.0.1:"), "{}", line);
+    assert!(
+        line.ends_with("] \"set\" \"k\" \"two words\\n\""),
+        "{}",
+        line
+    );
+
+    drop(monitor);
+    common::eventually("the monitor to be forgotten on disconnect", || {
+        common::info_field(&mut client, "clients", "monitor_clients") == "0"
+    });
+}
+
+#[tokio::test]
+async fn subcommand_routers_answer_help_with_usage_lines() {
+    let mut session = Session::new();
+    for command in [
+        "config", "object", "client", "debug", "command", "slowlog", "pubsub", "xinfo",
+    ] {
+        let reply = String::from_utf8(session.call(&[command, "help"]).await).unwrap();
+        let lines: Vec<&str> = reply.split("\r\n").collect();
+        let header = format!(
+            "+{} <subcommand> [<arg> [value] [opt] ...]. Subcommands are:",
+            command.to_uppercase()
+        );
+        assert_eq!(lines[1], header, "{}", reply);
+        // Usage and description lines come in pairs, ending with HELP itself
+        let count: usize = lines[0].trim_start_matches('*').parse().unwrap();
+        assert!(count >= 5 && count % 2 == 1, "{}", reply);
+        assert_eq!(
+            &lines[count - 1..=count],
+            ["+HELP", "+    Print this help."]
+        );
+    }
+    assert!(String::from_utf8(session.call(&["config", "help"]).await)
+        .unwrap()
+        .contains("+GET <pattern>\r\n"));
+    assert_eq!(
+        session.call(&["config", "help", "extra"]).await,
+        b"-ERR wrong number of arguments for 'config help'\r\n"
+    );
+}
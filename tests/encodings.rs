@@ -0,0 +1,105 @@
+//! OBJECT ENCODING under the listpack thresholds, read from the live configuration.
+//! The thresholds are global, so this binary has a single test.
+use codecrafters_redis::Session;
+
+const THRESHOLDS: &[&str] = &[
+    "hash-max-listpack-entries",
+    "hash-max-listpack-value",
+    "set-max-intset-entries",
+    "set-max-listpack-entries",
+    "set-max-listpack-value",
+    "zset-max-listpack-entries",
+    "zset-max-listpack-value",
+    "list-max-listpack-size",
+];
+
+async fn encoding(session: &mut Session, key: &str) -> Vec<u8> {
+    session.call(&["object", "encoding", key]).await
+}
+
+fn bulk(value: &str) -> Vec<u8> {
+    format!("${}\r\n{}\r\n", value.len(), value).into_bytes()
+}
+
+/// Set the thresholds back to the CONFIG GET replies taken before changing them
+async fn restore(session: &mut Session, defaults: &[Vec<u8>]) {
+    for (name, default) in THRESHOLDS.iter().zip(defaults) {
+        let default = String::from_utf8_lossy(default);
+        let value = default.lines().nth(4).unwrap();
+        session.call(&["config", "set", name, value]).await;
+    }
+}
+
+#[tokio::test]
+async fn thresholds_of_zero_give_the_large_encodings() {
+    let mut session = Session::new();
+    let mut defaults = Vec::new();
+    for name in THRESHOLDS {
+        defaults.push(session.call(&["config", "get", name]).await);
+    }
+    session.call(&["hset", "enc:hash", "f", "v"]).await;
+    session.call(&["sadd", "enc:intset", "1"]).await;
+    session.call(&["sadd", "enc:set", "m"]).await;
+    session.call(&["zadd", "enc:zset", "1", "m"]).await;
+    session.call(&["rpush", "enc:list", "a"]).await;
+    assert_eq!(encoding(&mut session, "enc:hash").await, bulk("listpack"));
+    assert_eq!(encoding(&mut session, "enc:intset").await, bulk("intset"));
+    assert_eq!(encoding(&mut session, "enc:set").await, bulk("listpack"));
+    assert_eq!(encoding(&mut session, "enc:zset").await, bulk("listpack"));
+    assert_eq!(encoding(&mut session, "enc:list").await, bulk("listpack"));
+
+    // Each entry threshold on its own
+    for (name, key, large) in [
+        ("hash-max-listpack-entries", "enc:hash", "hashtable"),
+        ("set-max-intset-entries", "enc:intset", "listpack"),
+        ("set-max-listpack-entries", "enc:set", "hashtable"),
+        ("zset-max-listpack-entries", "enc:zset", "skiplist"),
+        ("list-max-listpack-size", "enc:list", "quicklist"),
+    ] {
+        assert_eq!(
+            session.call(&["config", "set", name, "0"]).await,
+            b"+OK\r\n"
+        );
+        assert_eq!(encoding(&mut session, key).await, bulk(large), "{}", name);
+    }
+    restore(&mut session, &defaults).await;
+
+    // And each value threshold
+    for (name, key, large) in [
+        ("hash-max-listpack-value", "enc:hash", "hashtable"),
+        ("set-max-listpack-value", "enc:set", "hashtable"),
+        ("zset-max-listpack-value", "enc:zset", "skiplist"),
+    ] {
+        assert_eq!(
+            session.call(&["config", "set", name, "0"]).await,
+            b"+OK\r\n"
+        );
+        assert_eq!(encoding(&mut session, key).await, bulk(large), "{}", name);
+    }
+
+    // New collections are created large too
+    session
+        .call(&["config", "set", "hash-max-listpack-entries", "0"])
+        .await;
+    session
+        .call(&["hset", "enc:hash:new", "field", "value"])
+        .await;
+    assert_eq!(
+        encoding(&mut session, "enc:hash:new").await,
+        bulk("hashtable")
+    );
+    session
+        .call(&["config", "set", "list-max-listpack-size", "0"])
+        .await;
+    session.call(&["lpush", "enc:list:new", "x"]).await;
+    assert_eq!(
+        encoding(&mut session, "enc:list:new").await,
+        bulk("quicklist")
+    );
+
+    restore(&mut session, &defaults).await;
+    assert_eq!(
+        encoding(&mut session, "enc:hash:new").await,
+        bulk("listpack")
+    );
+}
@@ -0,0 +1,122 @@
+//! Geo commands, stored in sorted sets, run through a socketless session
+use codecrafters_redis::resp::parser::FrameParser;
+use codecrafters_redis::resp::Frame;
+use codecrafters_redis::Session;
+
+fn bulk(value: &str) -> Vec<u8> {
+    format!("${}\r\n{}\r\n", value.len(), value).into_bytes()
+}
+
+/// The coordinates of each member in a GEOPOS reply, None for missing ones
+fn positions(reply: &[u8]) -> Vec<Option<(f64, f64)>> {
+    let mut parser = FrameParser::new();
+    parser.feed(reply);
+    let number = |frame: &Frame| match frame {
+        Frame::BulkString(Some(bytes)) => std::str::from_utf8(bytes).unwrap().parse().unwrap(),
+        other => panic!("expected a coordinate, got {:?}", other),
+    };
+    match parser.parse() {
+        Ok(Some(Frame::Array(Some(items)))) => items
+            .iter()
+            .map(|item| match item {
+                Frame::Array(Some(pair)) => Some((number(&pair[0]), number(&pair[1]))),
+                Frame::Array(None) => None,
+                other => panic!("expected a position, got {:?}", other),
+            })
+            .collect(),
+        other => panic!("expected an array, got {:?}", other),
+    }
+}
+
+#[tokio::test]
+async fn geodist_between_known_cities_matches_redis() {
+    let mut session = Session::new();
+    assert_eq!(
+        session
+            .call(&[
+                "geoadd",
+                "geo:sicily",
+                "13.361389",
+                "38.115556",
+                "Palermo",
+                "15.087269",
+                "37.502669",
+                "Catania",
+            ])
+            .await,
+        b":2\r\n"
+    );
+    // The 52-bit interleaved geohash Redis stores as the score
+    assert_eq!(
+        session.call(&["zscore", "geo:sicily", "Palermo"]).await,
+        bulk("3479099956230698")
+    );
+    for (unit, distance) in [
+        (None, "166274.1516"),
+        (Some("km"), "166.2742"),
+        (Some("MI"), "103.3182"),
+        (Some("ft"), "545518.8700"),
+    ] {
+        let mut command = vec!["geodist", "geo:sicily", "Palermo", "Catania"];
+        command.extend(unit);
+        assert_eq!(session.call(&command).await, bulk(distance), "{:?}", unit);
+    }
+    assert_eq!(
+        session
+            .call(&["geodist", "geo:sicily", "Palermo", "Rome"])
+            .await,
+        b"$-1\r\n"
+    );
+    assert_eq!(
+        session
+            .call(&["geodist", "geo:sicily", "Palermo", "Catania", "yd"])
+            .await,
+        b"-ERR unsupported unit provided. please use M, KM, FT, MI\r\n"
+    );
+
+    let found = positions(
+        &session
+            .call(&["geopos", "geo:sicily", "Palermo", "Rome", "Catania"])
+            .await,
+    );
+    let close = |(lon, lat): (f64, f64), (x, y): (f64, f64)| {
+        (lon - x).abs() < 1e-5 && (lat - y).abs() < 1e-5
+    };
+    assert!(
+        close(found[0].unwrap(), (13.361389, 38.115556)),
+        "{:?}",
+        found
+    );
+    assert_eq!(found[1], None);
+    assert!(
+        close(found[2].unwrap(), (15.087269, 37.502669)),
+        "{:?}",
+        found
+    );
+}
+
+#[tokio::test]
+async fn geoadd_refuses_coordinates_out_of_range() {
+    let mut session = Session::new();
+    assert_eq!(
+        session
+            .call(&["geoadd", "geo:bad", "200", "100", "nowhere"])
+            .await,
+        b"-ERR invalid longitude,latitude pair 200.000000,100.000000\r\n"
+    );
+    // Latitudes are limited to what the web mercator projection covers
+    assert_eq!(
+        session
+            .call(&["geoadd", "geo:bad", "0", "86", "pole"])
+            .await,
+        b"-ERR invalid longitude,latitude pair 0.000000,86.000000\r\n"
+    );
+    assert_eq!(session.call(&["type", "geo:bad"]).await, b"+none\r\n");
+    assert_eq!(
+        session
+            .call(&["geoadd", "geo:bad", "180", "85.05112878", "edge"])
+            .await,
+        b":1\r\n"
+    );
+    assert_eq!(session.call(&["type", "geo:bad"]).await, b"+zset\r\n");
+}
@@ -0,0 +1,372 @@
+//! Hash commands run through a socketless session
+use codecrafters_redis::resp::parser::FrameParser;
+use codecrafters_redis::resp::Frame;
+use codecrafters_redis::Session;
+
+#[tokio::test]
+async fn hgetdel_returns_the_fields_it_deletes_and_the_emptied_hash_goes() {
+    let mut session = Session::new();
+    session
+        .call(&["hset", "hash:getdel", "a", "1", "b", "2"])
+        .await;
+    assert_eq!(
+        session
+            .call(&["hgetdel", "hash:getdel", "FIELDS", "2", "a", "missing"])
+            .await,
+        b"*2\r\n$1\r\n1\r\n$-1\r\n"
+    );
+    assert_eq!(
+        session.call(&["hgetall", "hash:getdel"]).await,
+        b"*2\r\n$1\r\nb\r\n$1\r\n2\r\n"
+    );
+    assert_eq!(
+        session
+            .call(&["hgetdel", "hash:getdel", "FIELDS", "1", "b"])
+            .await,
+        b"*1\r\n$1\r\n2\r\n"
+    );
+    assert_eq!(session.call(&["type", "hash:getdel"]).await, b"+none\r\n");
+    assert_eq!(
+        session
+            .call(&["hgetdel", "hash:getdel", "FIELDS", "1", "b"])
+            .await,
+        b"*1\r\n$-1\r\n"
+    );
+    assert_eq!(
+        session
+            .call(&["hgetdel", "hash:getdel", "FIELDS", "2", "b"])
+            .await,
+        b"-ERR The `numfields` parameter must match the number of arguments\r\n"
+    );
+}
+
+#[tokio::test]
+async fn hgetex_sets_and_clears_field_ttls() {
+    let mut session = Session::new();
+    session
+        .call(&["hset", "hash:getex", "a", "1", "b", "2"])
+        .await;
+    assert_eq!(
+        session
+            .call(&[
+                "hgetex",
+                "hash:getex",
+                "EX",
+                "100",
+                "FIELDS",
+                "2",
+                "a",
+                "missing"
+            ])
+            .await,
+        b"*2\r\n$1\r\n1\r\n$-1\r\n"
+    );
+    assert_eq!(
+        session
+            .call(&["httl", "hash:getex", "FIELDS", "2", "a", "b"])
+            .await,
+        b"*2\r\n:100\r\n:-1\r\n"
+    );
+    // Without an option the TTLs are left as they are
+    session
+        .call(&["hgetex", "hash:getex", "FIELDS", "1", "a"])
+        .await;
+    assert_eq!(
+        session
+            .call(&["httl", "hash:getex", "FIELDS", "1", "a"])
+            .await,
+        b"*1\r\n:100\r\n"
+    );
+    session
+        .call(&["hgetex", "hash:getex", "PERSIST", "FIELDS", "1", "a"])
+        .await;
+    assert_eq!(
+        session
+            .call(&["httl", "hash:getex", "FIELDS", "1", "a"])
+            .await,
+        b"*1\r\n:-1\r\n"
+    );
+    // A time in the past deletes the fields, and the key once it empties
+    assert_eq!(
+        session
+            .call(&["hgetex", "hash:getex", "PXAT", "1", "FIELDS", "2", "a", "b"])
+            .await,
+        b"*2\r\n$1\r\n1\r\n$1\r\n2\r\n"
+    );
+    assert_eq!(session.call(&["type", "hash:getex"]).await, b"+none\r\n");
+}
+
+#[tokio::test]
+async fn a_hash_past_either_listpack_threshold_is_a_hashtable() {
+    let mut session = Session::new();
+    // Values of up to 64 bytes, and up to 128 fields, by default
+    session
+        .call(&["hset", "hash:enc:value", "f", &"v".repeat(64)])
+        .await;
+    assert_eq!(
+        session
+            .call(&["object", "encoding", "hash:enc:value"])
+            .await,
+        b"$8\r\nlistpack\r\n"
+    );
+    session
+        .call(&["hset", "hash:enc:value", "g", &"v".repeat(65)])
+        .await;
+    assert_eq!(
+        session
+            .call(&["object", "encoding", "hash:enc:value"])
+            .await,
+        b"$9\r\nhashtable\r\n"
+    );
+    // Field names count as well as values
+    session
+        .call(&["hset", "hash:enc:field", &"f".repeat(65), "v"])
+        .await;
+    assert_eq!(
+        session
+            .call(&["object", "encoding", "hash:enc:field"])
+            .await,
+        b"$9\r\nhashtable\r\n"
+    );
+
+    for i in 0..128 {
+        session
+            .call(&["hset", "hash:enc:entries", &i.to_string(), "v"])
+            .await;
+    }
+    assert_eq!(
+        session
+            .call(&["object", "encoding", "hash:enc:entries"])
+            .await,
+        b"$8\r\nlistpack\r\n"
+    );
+    session
+        .call(&["hset", "hash:enc:entries", "128", "v"])
+        .await;
+    assert_eq!(
+        session
+            .call(&["object", "encoding", "hash:enc:entries"])
+            .await,
+        b"$9\r\nhashtable\r\n"
+    );
+}
+
+/// The cursor and elements of a SCAN family reply
+fn scan_elements(reply: &[u8]) -> (Vec<u8>, Vec<Vec<u8>>) {
+    let mut parser = FrameParser::new();
+    parser.feed(reply);
+    let Ok(Some(Frame::Array(Some(parts)))) = parser.parse() else {
+        panic!("not a scan reply: {:?}", String::from_utf8_lossy(reply));
+    };
+    let [Frame::BulkString(Some(cursor)), Frame::Array(Some(items))] = parts.as_slice() else {
+        panic!("not a scan reply: {:?}", String::from_utf8_lossy(reply));
+    };
+    let items = items
+        .iter()
+        .map(|item| match item {
+            Frame::BulkString(Some(bytes)) => bytes.clone(),
+            other => panic!("not an element: {:?}", other),
+        })
+        .collect();
+    (cursor.clone(), items)
+}
+
+#[tokio::test]
+async fn hscan_novalues_returns_just_the_fields() {
+    let mut session = Session::new();
+    session
+        .call(&["hset", "hash:scan", "a", "1", "b", "2", "c", "3"])
+        .await;
+    let (cursor, pairs) = scan_elements(&session.call(&["hscan", "hash:scan", "0"]).await);
+    assert_eq!(cursor, b"0");
+    assert_eq!(pairs.len(), 6);
+    let (cursor, fields) =
+        scan_elements(&session.call(&["hscan", "hash:scan", "0", "NOVALUES"]).await);
+    assert_eq!(cursor, b"0");
+    assert_eq!(fields, pairs.into_iter().step_by(2).collect::<Vec<_>>());
+
+    // It goes with MATCH and COUNT, in any order, but only HSCAN takes it
+    let (_, fields) = scan_elements(
+        &session
+            .call(&[
+                "hscan",
+                "hash:scan",
+                "0",
+                "NOVALUES",
+                "MATCH",
+                "b",
+                "COUNT",
+                "5",
+            ])
+            .await,
+    );
+    assert_eq!(fields, [b"b"]);
+    for command in ["sscan", "zscan"] {
+        assert_eq!(
+            session
+                .call(&[command, "hash:missing", "0", "NOVALUES"])
+                .await,
+            b"-ERR syntax error\r\n"
+        );
+    }
+}
+
+const WRONGTYPE: &[u8] = b"-WRONGTYPE Operation against a key holding the wrong kind of value\r\n";
+
+#[tokio::test]
+async fn get_refuses_a_hash_that_hgetall_returns() {
+    let mut session = Session::new();
+    session.call(&["hset", "hash:all", "f", "v"]).await;
+    assert_eq!(session.call(&["get", "hash:all"]).await, WRONGTYPE);
+    assert_eq!(
+        session.call(&["hgetall", "hash:all"]).await,
+        b"*2\r\n$1\r\nf\r\n$1\r\nv\r\n"
+    );
+    // RESP3 gets a map
+    session.call(&["hello", "3"]).await;
+    assert_eq!(
+        session.call(&["hgetall", "hash:all"]).await,
+        b"%1\r\n$1\r\nf\r\n$1\r\nv\r\n"
+    );
+    assert_eq!(session.call(&["get", "hash:all"]).await, WRONGTYPE);
+    assert_eq!(session.call(&["hgetall", "hash:missing"]).await, b"%0\r\n");
+
+    session.call(&["set", "hash:string", "v"]).await;
+    assert_eq!(session.call(&["hgetall", "hash:string"]).await, WRONGTYPE);
+}
+
+#[tokio::test]
+async fn hsetnx_never_overwrites_and_hmset_replies_ok() {
+    let mut session = Session::new();
+    assert_eq!(
+        session.call(&["hsetnx", "hash:nx", "f", "first"]).await,
+        b":1\r\n"
+    );
+    assert_eq!(
+        session.call(&["hsetnx", "hash:nx", "f", "second"]).await,
+        b":0\r\n"
+    );
+    assert_eq!(
+        session.call(&["hget", "hash:nx", "f"]).await,
+        b"$5\r\nfirst\r\n"
+    );
+    // A new field of an existing hash leaves the key's TTL alone
+    session.call(&["expire", "hash:nx", "1000"]).await;
+    let expires = session.call(&["expiretime", "hash:nx"]).await;
+    assert_eq!(
+        session.call(&["hsetnx", "hash:nx", "g", "v"]).await,
+        b":1\r\n"
+    );
+    assert_eq!(session.call(&["expiretime", "hash:nx"]).await, expires);
+
+    assert_eq!(
+        session.call(&["hmset", "hash:m", "a", "1", "b", "2"]).await,
+        b"+OK\r\n"
+    );
+    assert_eq!(
+        session.call(&["hmset", "hash:m", "a", "3"]).await,
+        b"+OK\r\n"
+    );
+    assert_eq!(
+        session.call(&["hgetall", "hash:m"]).await.len(),
+        b"*4\r\n$1\r\na\r\n$1\r\n3\r\n$1\r\nb\r\n$1\r\n2\r\n".len()
+    );
+    assert_eq!(session.call(&["hget", "hash:m", "a"]).await, b"$1\r\n3\r\n");
+    assert_eq!(
+        session.call(&["hmset", "hash:m", "a", "1", "b"]).await,
+        b"-ERR wrong number of arguments for 'hmset'\r\n"
+    );
+
+    session.call(&["set", "hash:string", "v"]).await;
+    assert_eq!(
+        session.call(&["hsetnx", "hash:string", "f", "v"]).await,
+        WRONGTYPE
+    );
+    assert_eq!(
+        session.call(&["hmset", "hash:string", "f", "v"]).await,
+        WRONGTYPE
+    );
+}
+
+#[tokio::test]
+async fn hexpire_reports_a_status_per_field_and_httl_reads_them_back() {
+    let mut session = Session::new();
+    session
+        .call(&["hset", "hash:ttl", "a", "1", "b", "2", "c", "3"])
+        .await;
+    assert_eq!(
+        session
+            .call(&["hexpire", "hash:ttl", "100", "FIELDS", "3", "a", "missing", "b"])
+            .await,
+        b"*3\r\n:1\r\n:-2\r\n:1\r\n"
+    );
+    // NX leaves the fields that already have a TTL, and a time of 0 deletes the field
+    assert_eq!(
+        session
+            .call(&["hexpire", "hash:ttl", "200", "NX", "FIELDS", "2", "a", "c"])
+            .await,
+        b"*2\r\n:0\r\n:1\r\n"
+    );
+    assert_eq!(
+        session
+            .call(&["hexpire", "hash:ttl", "0", "FIELDS", "1", "b"])
+            .await,
+        b"*1\r\n:2\r\n"
+    );
+    assert_eq!(
+        session
+            .call(&["httl", "hash:ttl", "FIELDS", "4", "a", "b", "c", "missing"])
+            .await,
+        b"*4\r\n:100\r\n:-2\r\n:200\r\n:-2\r\n"
+    );
+    assert_eq!(
+        session
+            .call(&["httl", "hash:none", "FIELDS", "2", "a", "b"])
+            .await,
+        b"*2\r\n:-2\r\n:-2\r\n"
+    );
+    assert_eq!(
+        session
+            .call(&["hexpire", "hash:none", "10", "FIELDS", "1", "a"])
+            .await,
+        b"*1\r\n:-2\r\n"
+    );
+    assert_eq!(
+        session
+            .call(&["hexpire", "hash:ttl", "10", "FIELDS", "2", "a"])
+            .await,
+        b"-ERR The `numfields` parameter must match the number of arguments\r\n"
+    );
+}
+
+#[tokio::test]
+async fn expired_fields_are_gone_and_take_the_empty_hash_with_them() {
+    let mut session = Session::new();
+    session
+        .call(&["hset", "hash:lazy", "short", "1", "kept", "2"])
+        .await;
+    session
+        .call(&["hpexpire", "hash:lazy", "50", "FIELDS", "1", "short"])
+        .await;
+    assert_eq!(
+        session
+            .call(&["httl", "hash:lazy", "FIELDS", "2", "short", "kept"])
+            .await,
+        b"*2\r\n:1\r\n:-1\r\n"
+    );
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+    assert_eq!(
+        session.call(&["hget", "hash:lazy", "short"]).await,
+        b"$-1\r\n"
+    );
+    assert_eq!(
+        session.call(&["hgetall", "hash:lazy"]).await,
+        b"*2\r\n$4\r\nkept\r\n$1\r\n2\r\n"
+    );
+
+    session
+        .call(&["hpexpire", "hash:lazy", "50", "FIELDS", "1", "kept"])
+        .await;
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+    assert_eq!(session.call(&["type", "hash:lazy"]).await, b"+none\r\n");
+}
@@ -0,0 +1,83 @@
+//! HyperLogLog commands run through a socketless session
+use codecrafters_redis::Session;
+
+fn integer(reply: &[u8]) -> i64 {
+    std::str::from_utf8(&reply[1..reply.len() - 2])
+        .unwrap()
+        .parse()
+        .unwrap()
+}
+
+/// PFADD the elements in batches, as clients usually do
+async fn add(session: &mut Session, key: &str, elements: impl Iterator<Item = String>) {
+    let elements: Vec<String> = elements.collect();
+    for batch in elements.chunks(100) {
+        let mut command = vec!["pfadd".to_string(), key.to_string()];
+        command.extend_from_slice(batch);
+        session.call(&command).await;
+    }
+}
+
+#[tokio::test]
+async fn pfcount_estimates_within_a_few_percent() {
+    let mut session = Session::new();
+    add(&mut session, "hll:a", (0..1000).map(|i| format!("a{}", i))).await;
+    let count = integer(&session.call(&["pfcount", "hll:a"]).await);
+    assert!((970..=1030).contains(&count), "{}", count);
+
+    // Adding them again changes nothing
+    assert_eq!(
+        session.call(&["pfadd", "hll:a", "a1", "a2"]).await,
+        b":0\r\n"
+    );
+    assert_eq!(integer(&session.call(&["pfcount", "hll:a"]).await), count);
+
+    // Stored in Redis's dense layout: the HYLL header and 16384 six-bit registers
+    let dump = session.call(&["get", "hll:a"]).await;
+    assert!(dump.starts_with(b"$12304\r\nHYLL\x00"), "{:?}", &dump[..12]);
+
+    add(
+        &mut session,
+        "hll:b",
+        (500..1500).map(|i| format!("a{}", i)),
+    )
+    .await;
+    let union = integer(&session.call(&["pfcount", "hll:a", "hll:b"]).await);
+    assert!((1455..=1545).contains(&union), "{}", union);
+    assert_eq!(
+        session
+            .call(&["pfmerge", "hll:merged", "hll:a", "hll:b"])
+            .await,
+        b"+OK\r\n"
+    );
+    assert_eq!(
+        integer(&session.call(&["pfcount", "hll:merged"]).await),
+        union
+    );
+}
+
+#[tokio::test]
+async fn pfadd_reports_changes_and_refuses_other_strings() {
+    let mut session = Session::new();
+    assert_eq!(session.call(&["pfadd", "hll:new"]).await, b":1\r\n");
+    assert_eq!(session.call(&["pfadd", "hll:new"]).await, b":0\r\n");
+    assert_eq!(session.call(&["pfcount", "hll:new"]).await, b":0\r\n");
+    assert_eq!(session.call(&["pfadd", "hll:new", "x"]).await, b":1\r\n");
+    assert_eq!(session.call(&["pfadd", "hll:new", "x"]).await, b":0\r\n");
+    assert_eq!(session.call(&["pfcount", "hll:new"]).await, b":1\r\n");
+    assert_eq!(session.call(&["pfcount", "hll:missing"]).await, b":0\r\n");
+
+    let wrongtype = b"-WRONGTYPE Key is not a valid HyperLogLog string value.\r\n";
+    session.call(&["set", "hll:string", "not an hll"]).await;
+    assert_eq!(session.call(&["pfadd", "hll:string", "x"]).await, wrongtype);
+    assert_eq!(session.call(&["pfcount", "hll:string"]).await, wrongtype);
+    assert_eq!(
+        session.call(&["pfmerge", "hll:dest", "hll:string"]).await,
+        wrongtype
+    );
+    session.call(&["sadd", "hll:set", "x"]).await;
+    assert_eq!(
+        session.call(&["pfcount", "hll:set"]).await,
+        b"-WRONGTYPE Operation against a key holding the wrong kind of value\r\n"
+    );
+}
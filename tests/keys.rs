@@ -0,0 +1,729 @@
+//! Keyspace commands run through a socketless session, and through a server process
+//! for the ones that depend on global settings
+mod common;
+
+use codecrafters_redis::resp::Frame;
+use codecrafters_redis::Session;
+use std::time::Duration;
+
+#[tokio::test]
+async fn expire_times_out_of_range_are_refused() {
+    let mut session = Session::new();
+    session.call(&["set", "keys:exp", "v"]).await;
+    for (command, name) in [
+        (&["expire", "keys:exp", "9999999999999999"][..], "expire"),
+        (&["pexpire", "keys:exp", "9223372036854775807"], "pexpire"),
+        (&["expireat", "keys:exp", "9223372036854775807"], "expireat"),
+        (&["set", "keys:exp", "v", "EX", "0"], "set"),
+        (&["set", "keys:exp", "v", "PX", "-5"], "set"),
+        (&["set", "keys:exp", "v", "EX", "9999999999999999"], "set"),
+        (&["setex", "keys:exp", "0", "v"], "setex"),
+        (&["psetex", "keys:exp", "-1", "v"], "psetex"),
+        (&["getex", "keys:exp", "EX", "0"], "getex"),
+        (&["getex", "keys:exp", "PX", "9223372036854775807"], "getex"),
+    ] {
+        assert_eq!(
+            session.call(command).await,
+            format!("-ERR invalid expire time in '{}' command\r\n", name).into_bytes(),
+            "{:?}",
+            command
+        );
+    }
+    // None of them touched the key
+    assert_eq!(session.call(&["get", "keys:exp"]).await, b"$1\r\nv\r\n");
+    assert_eq!(session.call(&["expiretime", "keys:exp"]).await, b":-1\r\n");
+    // EXPIRE does take a time in the past, which deletes the key
+    assert_eq!(session.call(&["expire", "keys:exp", "-1"]).await, b":1\r\n");
+    assert_eq!(session.call(&["type", "keys:exp"]).await, b"+none\r\n");
+}
+
+fn integer(reply: &[u8]) -> i64 {
+    std::str::from_utf8(&reply[1..reply.len() - 2])
+        .unwrap()
+        .parse()
+        .unwrap()
+}
+
+#[tokio::test]
+async fn expire_options_combine_unless_they_contradict() {
+    let mut session = Session::new();
+    session.call(&["set", "keys:expopt", "v"]).await;
+    // XX GT and XX LT both need a TTL to be there already
+    for options in [&["XX", "GT"][..], &["xx", "lt"], &["LT", "XX"]] {
+        let command = [&["expire", "keys:expopt", "100"][..], options].concat();
+        assert_eq!(session.call(&command).await, b":0\r\n", "{:?}", options);
+    }
+    assert_eq!(
+        session.call(&["expiretime", "keys:expopt"]).await,
+        b":-1\r\n"
+    );
+
+    session.call(&["expire", "keys:expopt", "100"]).await;
+    let at = integer(&session.call(&["expiretime", "keys:expopt"]).await);
+    assert_eq!(
+        session
+            .call(&["expire", "keys:expopt", "200", "XX", "GT"])
+            .await,
+        b":1\r\n"
+    );
+    assert!(integer(&session.call(&["expiretime", "keys:expopt"]).await) >= at + 100);
+    assert_eq!(
+        session
+            .call(&["expire", "keys:expopt", "150", "XX", "GT"])
+            .await,
+        b":0\r\n"
+    );
+    assert_eq!(
+        session
+            .call(&["expire", "keys:expopt", "50", "XX", "LT"])
+            .await,
+        b":1\r\n"
+    );
+    assert_eq!(
+        session
+            .call(&["expire", "keys:expopt", "60", "GT", "GT"])
+            .await,
+        b":1\r\n"
+    );
+    let at = session.call(&["expiretime", "keys:expopt"]).await;
+
+    for (options, error) in [
+        (&["NX", "XX"][..], "NX and XX, GT or LT"),
+        (&["GT", "NX"], "NX and XX, GT or LT"),
+        (&["NX", "LT"], "NX and XX, GT or LT"),
+        (&["GT", "LT"], "GT and LT"),
+        (&["XX", "LT", "GT"], "GT and LT"),
+    ] {
+        let command = [&["expire", "keys:expopt", "10"][..], options].concat();
+        assert_eq!(
+            session.call(&command).await,
+            format!(
+                "-ERR {} options at the same time are not compatible\r\n",
+                error
+            )
+            .into_bytes(),
+            "{:?}",
+            options
+        );
+    }
+    assert_eq!(session.call(&["expiretime", "keys:expopt"]).await, at);
+}
+
+/// The keys KEYS returns for a pattern, sorted
+async fn matching_keys(session: &mut Session, pattern: &str) -> Vec<String> {
+    let reply = session.call(&["keys", pattern]).await;
+    let mut parser = codecrafters_redis::resp::parser::FrameParser::new();
+    parser.feed(&reply);
+    let Ok(Some(Frame::Array(Some(items)))) = parser.parse() else {
+        panic!("KEYS {} didn't reply with an array", pattern);
+    };
+    let mut keys: Vec<String> = items
+        .iter()
+        .map(|item| match item {
+            Frame::BulkString(Some(key)) => String::from_utf8_lossy(key).into_owned(),
+            other => panic!("expected a bulk string, got {:?}", other),
+        })
+        .collect();
+    keys.sort();
+    keys
+}
+
+#[tokio::test]
+async fn keys_returns_every_matching_key() {
+    let mut session = Session::new();
+    for key in [
+        "keys:match:hello",
+        "keys:match:hallo",
+        "keys:match:hxllo",
+        "keys:match:h*llo",
+        "keys:match:a/b/c",
+        "keys:other",
+    ] {
+        session.call(&["set", key, "v"]).await;
+    }
+    assert_eq!(
+        matching_keys(&mut session, "keys:match:h[ae]llo").await,
+        ["keys:match:hallo", "keys:match:hello"]
+    );
+    assert_eq!(
+        matching_keys(&mut session, "keys:match:h\\*llo").await,
+        ["keys:match:h*llo"]
+    );
+    assert_eq!(
+        matching_keys(&mut session, "keys:match:*/c").await,
+        ["keys:match:a/b/c"]
+    );
+    assert_eq!(matching_keys(&mut session, "keys:match:*").await.len(), 5);
+    assert!(matching_keys(&mut session, "keys:nomatch:*")
+        .await
+        .is_empty());
+}
+
+#[tokio::test]
+async fn memory_usage_grows_with_the_value() {
+    let mut session = Session::new();
+    session
+        .call(&["set", "keys:mem:1k", &"x".repeat(1000)])
+        .await;
+    session
+        .call(&["set", "keys:mem:2k", &"x".repeat(2000)])
+        .await;
+    let small = integer(&session.call(&["memory", "usage", "keys:mem:1k"]).await);
+    let large = integer(&session.call(&["memory", "usage", "keys:mem:2k"]).await);
+    assert!((1000..1200).contains(&small), "{}", small);
+    assert_eq!(large - small, 1000);
+
+    // Sampling extrapolates, so elements of one size give the same estimate
+    let mut push = vec!["rpush", "keys:mem:list"];
+    push.extend(std::iter::repeat_n("0123456789", 100));
+    session.call(&push).await;
+    let sampled = session.call(&["memory", "usage", "keys:mem:list"]).await;
+    assert_eq!(
+        session
+            .call(&["memory", "usage", "keys:mem:list", "SAMPLES", "0"])
+            .await,
+        sampled
+    );
+    assert!(integer(&sampled) > 100 * 10);
+
+    assert_eq!(
+        session.call(&["memory", "usage", "keys:mem:missing"]).await,
+        b"$-1\r\n"
+    );
+    assert_eq!(
+        session
+            .call(&["memory", "usage", "keys:mem:1k", "SAMPLES", "-1"])
+            .await,
+        b"-ERR syntax error\r\n"
+    );
+    assert!(session.call(&["memory", "doctor"]).await.starts_with(b"$"));
+}
+
+#[test]
+fn swapdb_exchanges_the_datasets_under_connected_clients() {
+    let server = common::Server::start(&[]);
+    let mut first = server.client();
+    let mut second = server.client();
+    first.call(&["set", "owner", "db0"]);
+    first.call(&["set", "volatile", "v", "EX", "1000"]);
+    second.call(&["select", "1"]);
+    second.call(&["set", "owner", "db1"]);
+    let mut blocked = server.client();
+    blocked.send(&["blpop", "queue", "5"]);
+    std::thread::sleep(Duration::from_millis(50));
+
+    assert_eq!(first.call(&["swapdb", "0", "1"]), common::ok());
+    // Clients keep their index, so each now sees the other dataset
+    assert_eq!(first.call(&["get", "owner"]), common::bulk("db1"));
+    assert_eq!(first.call(&["dbsize"]), Frame::Integer(1));
+    assert_eq!(second.call(&["get", "owner"]), common::bulk("db0"));
+    let Frame::Integer(expires) = second.call(&["expiretime", "volatile"]) else {
+        panic!("EXPIRETIME didn't reply with an integer");
+    };
+    assert!(expires > 0);
+
+    // A client blocked in database 0 is served by pushes to what is there now
+    first.call(&["rpush", "queue", "x"]);
+    assert_eq!(
+        blocked.read(),
+        Frame::Array(Some(vec![common::bulk("queue"), common::bulk("x")]))
+    );
+
+    assert_eq!(
+        first.call(&["swapdb", "0", "16"]),
+        Frame::Error("ERR DB index is out of range".into())
+    );
+    assert_eq!(
+        first.call(&["swapdb", "-1", "0"]),
+        Frame::Error("ERR DB index is out of range".into())
+    );
+}
+
+#[tokio::test]
+async fn object_idletime_grows_until_the_key_is_read() {
+    let mut session = Session::new();
+    session.call(&["set", "keys:idle", "v"]).await;
+    assert_eq!(
+        session.call(&["object", "idletime", "keys:idle"]).await,
+        b":0\r\n"
+    );
+    tokio::time::sleep(Duration::from_millis(1100)).await;
+    assert_eq!(
+        session.call(&["object", "idletime", "keys:idle"]).await,
+        b":1\r\n"
+    );
+    // OBJECT itself doesn't count as an access, but a read does
+    session.call(&["get", "keys:idle"]).await;
+    assert_eq!(
+        session.call(&["object", "idletime", "keys:idle"]).await,
+        b":0\r\n"
+    );
+    assert_eq!(
+        session.call(&["object", "idletime", "keys:missing"]).await,
+        b"-ERR no such key\r\n"
+    );
+}
+
+#[tokio::test]
+async fn object_refcount_reports_shared_small_integers() {
+    let mut session = Session::new();
+    session.call(&["set", "keys:shared", "42"]).await;
+    session.call(&["set", "keys:big", "10000"]).await;
+    session.call(&["set", "keys:text", "text"]).await;
+    assert_eq!(
+        session.call(&["object", "refcount", "keys:shared"]).await,
+        b":2147483647\r\n"
+    );
+    assert_eq!(
+        session.call(&["object", "refcount", "keys:big"]).await,
+        b":1\r\n"
+    );
+    assert_eq!(
+        session.call(&["object", "refcount", "keys:text"]).await,
+        b":1\r\n"
+    );
+    assert_eq!(
+        session.call(&["object", "refcount", "keys:missing"]).await,
+        b"-ERR no such key\r\n"
+    );
+}
+
+#[test]
+fn object_freq_needs_an_lfu_policy() {
+    let server = common::Server::start(&[]);
+    let mut client = server.client();
+    client.call(&["set", "k", "v"]);
+    let Frame::Error(e) = client.call(&["object", "freq", "k"]) else {
+        panic!("OBJECT FREQ worked without an LFU policy");
+    };
+    assert!(
+        e.starts_with("ERR An LFU maxmemory policy is not selected"),
+        "{}",
+        e
+    );
+
+    client.call(&["config", "set", "maxmemory-policy", "allkeys-lfu"]);
+    client.call(&["set", "fresh", "v"]);
+    // New keys start at LFU_INIT_VAL, so they aren't evicted straight away
+    assert_eq!(client.call(&["object", "freq", "fresh"]), Frame::Integer(5));
+    for _ in 0..200 {
+        client.call(&["get", "fresh"]);
+    }
+    let Frame::Integer(freq) = client.call(&["object", "freq", "fresh"]) else {
+        panic!("OBJECT FREQ didn't reply with an integer");
+    };
+    assert!(freq > 5, "200 reads left the counter at {}", freq);
+    let Frame::Error(e) = client.call(&["object", "idletime", "fresh"]) else {
+        panic!("OBJECT IDLETIME worked under an LFU policy");
+    };
+    assert!(
+        e.starts_with("ERR An LFU maxmemory policy is selected"),
+        "{}",
+        e
+    );
+}
+
+/// The cursor and elements of a SCAN-family reply
+fn scan_page(reply: &[u8]) -> (String, Vec<String>) {
+    let mut parser = codecrafters_redis::resp::parser::FrameParser::new();
+    parser.feed(reply);
+    let Ok(Some(Frame::Array(Some(page)))) = parser.parse() else {
+        panic!("not a scan reply: {:?}", String::from_utf8_lossy(reply));
+    };
+    let text = |frame: &Frame| match frame {
+        Frame::BulkString(Some(bytes)) => String::from_utf8_lossy(bytes).into_owned(),
+        other => panic!("expected a bulk string, got {:?}", other),
+    };
+    let Frame::Array(Some(elements)) = &page[1] else {
+        panic!("no elements in the scan reply");
+    };
+    (text(&page[0]), elements.iter().map(text).collect())
+}
+
+/// Every element a SCAN-family command returns, following the cursor back to 0
+async fn scan_all(session: &mut Session, command: &[&str]) -> Vec<String> {
+    let (mut cursor, mut found) = ("0".to_string(), Vec::new());
+    loop {
+        let mut args = command.to_vec();
+        args.insert(if command[0] == "scan" { 1 } else { 2 }, &cursor);
+        let (next, elements) = scan_page(&session.call(&args).await);
+        found.extend(elements);
+        if next == "0" {
+            break;
+        }
+        cursor = next;
+    }
+    found
+}
+
+#[tokio::test]
+async fn scan_filters_by_pattern_then_by_type() {
+    let mut session = Session::new();
+    for i in 0..30 {
+        session
+            .call(&["hset", &format!("scan:hash:{}", i), "f", "v"])
+            .await;
+        session
+            .call(&["set", &format!("scan:string:{}", i), "v"])
+            .await;
+    }
+    session.call(&["hset", "other:hash", "f", "v"]).await;
+
+    let mut hashes = scan_all(
+        &mut session,
+        &["scan", "MATCH", "scan:*", "TYPE", "hash", "COUNT", "7"],
+    )
+    .await;
+    hashes.sort();
+    let mut expected: Vec<String> = (0..30).map(|i| format!("scan:hash:{}", i)).collect();
+    expected.sort();
+    assert_eq!(hashes, expected);
+    assert_eq!(
+        scan_all(&mut session, &["scan", "MATCH", "scan:*", "TYPE", "zset"]).await,
+        Vec::<String>::new()
+    );
+    assert_eq!(
+        session.call(&["scan", "0", "COUNT", "0"]).await,
+        b"-ERR syntax error\r\n"
+    );
+}
+
+#[tokio::test]
+async fn element_scans_walk_their_own_key() {
+    let mut session = Session::new();
+    session
+        .call(&["hset", "elements:h", "a1", "1", "a2", "2", "b1", "3"])
+        .await;
+    session
+        .call(&["sadd", "elements:s", "a1", "a2", "b1"])
+        .await;
+    session
+        .call(&["zadd", "elements:z", "1", "a1", "2", "a2", "3", "b1"])
+        .await;
+
+    let mut fields = scan_all(&mut session, &["hscan", "elements:h", "MATCH", "a*"]).await;
+    fields.sort();
+    assert_eq!(fields, ["1", "2", "a1", "a2"]);
+    let mut members = scan_all(&mut session, &["sscan", "elements:s", "MATCH", "a*"]).await;
+    members.sort();
+    assert_eq!(members, ["a1", "a2"]);
+    let mut scored = scan_all(&mut session, &["zscan", "elements:z", "MATCH", "b*"]).await;
+    scored.sort();
+    assert_eq!(scored, ["3", "b1"]);
+    assert_eq!(
+        scan_all(&mut session, &["sscan", "elements:none"]).await,
+        Vec::<String>::new()
+    );
+    assert_eq!(
+        session.call(&["sscan", "elements:h", "0"]).await,
+        b"-WRONGTYPE Operation against a key holding the wrong kind of value\r\n"
+    );
+}
+
+#[tokio::test]
+async fn move_takes_the_key_and_its_ttl_to_another_database() {
+    let mut session = Session::new();
+    session.call(&["select", "3"]).await;
+    session.call(&["del", "keys:move"]).await;
+    session.call(&["select", "0"]).await;
+    session.call(&["set", "keys:move", "v", "EX", "1000"]).await;
+    let expires = session.call(&["expiretime", "keys:move"]).await;
+    assert_eq!(session.call(&["move", "keys:move", "3"]).await, b":1\r\n");
+    assert_eq!(session.call(&["type", "keys:move"]).await, b"+none\r\n");
+
+    session.call(&["select", "3"]).await;
+    assert_eq!(session.call(&["get", "keys:move"]).await, b"$1\r\nv\r\n");
+    assert_eq!(session.call(&["expiretime", "keys:move"]).await, expires);
+
+    // Nothing moves onto a key that exists, or from one that doesn't
+    session.call(&["select", "0"]).await;
+    session.call(&["set", "keys:move", "other"]).await;
+    assert_eq!(session.call(&["move", "keys:move", "3"]).await, b":0\r\n");
+    assert_eq!(
+        session.call(&["get", "keys:move"]).await,
+        b"$5\r\nother\r\n"
+    );
+    assert_eq!(
+        session.call(&["move", "keys:nothing", "3"]).await,
+        b":0\r\n"
+    );
+
+    assert_eq!(
+        session.call(&["move", "keys:move", "0"]).await,
+        b"-ERR source and destination objects are the same\r\n"
+    );
+    for index in ["16", "-1"] {
+        assert_eq!(
+            session.call(&["move", "keys:move", index]).await,
+            b"-ERR DB index is out of range\r\n"
+        );
+    }
+    assert_eq!(
+        session.call(&["move", "keys:move", "x"]).await,
+        b"-ERR value is not an integer or out of range\r\n"
+    );
+    session.call(&["select", "3"]).await;
+    session.call(&["del", "keys:move"]).await;
+}
+
+#[tokio::test]
+async fn expiretime_gives_the_unix_time_a_key_expires_at() {
+    let mut session = Session::new();
+    assert_eq!(
+        session.call(&["expiretime", "keys:exp:missing"]).await,
+        b":-2\r\n"
+    );
+    assert_eq!(
+        session.call(&["pexpiretime", "keys:exp:missing"]).await,
+        b":-2\r\n"
+    );
+    session.call(&["set", "keys:exp:forever", "v"]).await;
+    assert_eq!(
+        session.call(&["expiretime", "keys:exp:forever"]).await,
+        b":-1\r\n"
+    );
+    assert_eq!(
+        session.call(&["pexpiretime", "keys:exp:forever"]).await,
+        b":-1\r\n"
+    );
+
+    let now_ms = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_millis() as i64;
+    session
+        .call(&["set", "keys:exp:soon", "v", "PX", "100000"])
+        .await;
+    let at_ms = integer(&session.call(&["pexpiretime", "keys:exp:soon"]).await);
+    assert!(
+        (at_ms - (now_ms + 100_000)).abs() < 1_000,
+        "{} is not about {}",
+        at_ms,
+        now_ms + 100_000
+    );
+    let at = integer(&session.call(&["expiretime", "keys:exp:soon"]).await);
+    assert!((at - at_ms / 1000).abs() <= 1);
+
+    // An absolute time set with PXAT comes back as it was given
+    let pxat = (now_ms + 500_000).to_string();
+    session.call(&["pexpireat", "keys:exp:soon", &pxat]).await;
+    let at_ms = integer(&session.call(&["pexpiretime", "keys:exp:soon"]).await);
+    assert!((at_ms - (now_ms + 500_000)).abs() <= 1, "{}", at_ms);
+}
+
+/// A field of the DEBUG OBJECT description of a key
+async fn debug_object_field(session: &mut Session, key: &str, name: &str) -> String {
+    let reply = String::from_utf8(session.call(&["debug", "object", key]).await).unwrap();
+    let prefix = format!("{}:", name);
+    reply
+        .trim_end()
+        .split(' ')
+        .find_map(|field| field.strip_prefix(&prefix).map(str::to_string))
+        .unwrap_or_else(|| panic!("no {} in {}", name, reply))
+}
+
+#[tokio::test]
+async fn debug_object_gives_the_rdb_serialized_length() {
+    let mut session = Session::new();
+    // A length byte and the ten bytes, or an integer in as few bytes as it fits
+    for (value, length) in [("abcdefghij", "11"), ("-1", "2"), ("12345", "3")] {
+        session.call(&["set", "keys:ser", value]).await;
+        assert_eq!(
+            debug_object_field(&mut session, "keys:ser", "serializedlength").await,
+            length,
+            "{}",
+            value
+        );
+    }
+
+    // Every type takes what its DUMP payload holds after the type byte, before the
+    // version and checksum
+    for (key, command) in [
+        ("keys:ser:s", &["set", "keys:ser:s", &"x".repeat(1000)][..]),
+        ("keys:ser:l", &["rpush", "keys:ser:l", "a", "bb", "300"]),
+        ("keys:ser:i", &["sadd", "keys:ser:i", "1", "2", "3"]),
+        ("keys:ser:h", &["hset", "keys:ser:h", "f", "v"]),
+        ("keys:ser:z", &["zadd", "keys:ser:z", "1.5", "m"]),
+        ("keys:ser:x", &["xadd", "keys:ser:x", "1-1", "f", "v"]),
+    ] {
+        session.call(command).await;
+        let dump = session.call(&["dump", key]).await;
+        let header = dump.iter().position(|&b| b == b'\n').unwrap() + 1;
+        let payload = dump.len() - header - 2;
+        assert_eq!(
+            debug_object_field(&mut session, key, "serializedlength").await,
+            (payload - 1 - 10).to_string(),
+            "{}",
+            key
+        );
+    }
+    assert_eq!(
+        session.call(&["debug", "object", "keys:ser:missing"]).await,
+        b"-ERR no such key\r\n"
+    );
+}
+
+#[tokio::test]
+async fn scan_returns_every_key_present_for_the_whole_scan() {
+    let mut session = Session::new();
+    let stable: Vec<String> = (0..200)
+        .map(|i| format!("guarantee:stable:{}", i))
+        .collect();
+    for key in &stable {
+        session.call(&["set", key, "v"]).await;
+    }
+
+    // Keys come and go between every page
+    let mut cursor = "0".to_string();
+    let mut seen = std::collections::HashSet::new();
+    let mut round = 0;
+    loop {
+        let reply = session
+            .call(&["scan", &cursor, "MATCH", "guarantee:*", "COUNT", "10"])
+            .await;
+        let (next, keys) = scan_page(&reply);
+        seen.extend(keys);
+        for i in 0..20 {
+            session
+                .call(&["set", &format!("guarantee:churn:{}:{}", round, i), "v"])
+                .await;
+        }
+        if round > 0 {
+            for i in 0..20 {
+                session
+                    .call(&["del", &format!("guarantee:churn:{}:{}", round - 1, i)])
+                    .await;
+            }
+        }
+        round += 1;
+        if next == "0" {
+            break;
+        }
+        assert!(round < 1000, "the scan never finished");
+        cursor = next;
+    }
+    let missed: Vec<&String> = stable.iter().filter(|key| !seen.contains(*key)).collect();
+    assert!(missed.is_empty(), "missed {:?}", missed);
+}
+
+#[test]
+fn keys_wait_for_a_read_while_active_expire_is_off() {
+    let server = common::Server::start(&[]);
+    let mut client = server.client();
+    assert_eq!(
+        client.call(&["debug", "set-active-expire", "0"]),
+        common::ok()
+    );
+    client.call(&["set", "lazy", "v", "PX", "50"]);
+    client.call(&["set", "kept", "v"]);
+    std::thread::sleep(Duration::from_millis(300));
+    assert_eq!(client.call(&["dbsize"]), Frame::Integer(2));
+    assert_eq!(client.call(&["get", "lazy"]), Frame::BulkString(None));
+    assert_eq!(client.call(&["dbsize"]), Frame::Integer(1));
+
+    assert_eq!(
+        client.call(&["debug", "set-active-expire", "1"]),
+        common::ok()
+    );
+    assert!(matches!(
+        client.call(&["debug", "set-active-expire", "yes"]),
+        Frame::Error(_)
+    ));
+}
+
+#[tokio::test]
+async fn reads_with_client_no_touch_leave_the_idle_time_alone() {
+    let mut session = Session::new();
+    session.call(&["set", "keys:untouched", "v"]).await;
+    assert_eq!(
+        session.call(&["client", "no-touch", "on"]).await,
+        b"+OK\r\n"
+    );
+    tokio::time::sleep(Duration::from_millis(1100)).await;
+    assert_eq!(
+        session.call(&["get", "keys:untouched"]).await,
+        b"$1\r\nv\r\n"
+    );
+    assert_eq!(
+        session
+            .call(&["object", "idletime", "keys:untouched"])
+            .await,
+        b":1\r\n"
+    );
+
+    assert_eq!(
+        session.call(&["client", "no-touch", "off"]).await,
+        b"+OK\r\n"
+    );
+    session.call(&["get", "keys:untouched"]).await;
+    assert_eq!(
+        session
+            .call(&["object", "idletime", "keys:untouched"])
+            .await,
+        b":0\r\n"
+    );
+
+    // NO-EVICT is only remembered, but takes the same arguments
+    assert_eq!(
+        session.call(&["client", "no-evict", "on"]).await,
+        b"+OK\r\n"
+    );
+    assert_eq!(
+        session.call(&["client", "no-evict", "off"]).await,
+        b"+OK\r\n"
+    );
+    for flag in ["no-touch", "no-evict"] {
+        assert_eq!(
+            session.call(&["client", flag, "maybe"]).await,
+            b"-ERR syntax error\r\n"
+        );
+    }
+}
+
+#[tokio::test]
+async fn a_deleted_key_leaves_no_ttl_behind() {
+    let mut session = Session::new();
+    for delete in ["del", "unlink"] {
+        session.call(&["set", "keys:gone", "v", "EX", "100"]).await;
+        assert_eq!(session.call(&[delete, "keys:gone"]).await, b":1\r\n");
+        // Written again without a TTL, the key mustn't pick the old one back up
+        session.call(&["rpush", "keys:gone", "v"]).await;
+        assert_eq!(session.call(&["expiretime", "keys:gone"]).await, b":-1\r\n");
+        session.call(&["del", "keys:gone"]).await;
+    }
+
+    session.call(&["hset", "keys:fields", "f", "v"]).await;
+    session
+        .call(&["hexpire", "keys:fields", "100", "FIELDS", "1", "f"])
+        .await;
+    session.call(&["del", "keys:fields"]).await;
+    session.call(&["hset", "keys:fields", "f", "v"]).await;
+    assert_eq!(
+        session
+            .call(&["httl", "keys:fields", "FIELDS", "1", "f"])
+            .await,
+        b"*1\r\n:-1\r\n"
+    );
+}
+
+#[tokio::test]
+async fn debug_stringmatch_len_uses_the_keys_matcher() {
+    let mut session = Session::new();
+    for (pattern, string, reply) in [
+        ("h[a-c]llo", "hbllo", b":1\r\n"),
+        ("h[a-c]llo", "hello", b":0\r\n"),
+        ("h\\*llo", "h*llo", b":1\r\n"),
+        ("h\\*llo", "hello", b":0\r\n"),
+        ("users:*/profile", "users:1/2/profile", b":1\r\n"),
+    ] {
+        assert_eq!(
+            session
+                .call(&["debug", "stringmatch-len", pattern, string])
+                .await,
+            reply,
+            "{} against {}",
+            pattern,
+            string
+        );
+    }
+}
@@ -0,0 +1,141 @@
+//! Keyspace notifications, in a process of their own since notify-keyspace-events is
+//! a server-wide setting
+mod common;
+
+use codecrafters_redis::resp::Frame;
+use common::{bulk, ok, Server};
+use std::time::Duration;
+
+#[test]
+fn a_key_found_expired_by_a_read_is_announced_once() {
+    let server = Server::start(&[]);
+    let mut client = server.client();
+    let mut subscriber = server.client();
+    assert_eq!(
+        client.call(&["config", "set", "notify-keyspace-events", "Ex"]),
+        ok()
+    );
+    subscriber.call(&["subscribe", "__keyevent@0__:expired"]);
+
+    client.call(&["set", "short", "v", "PX", "50"]);
+    std::thread::sleep(Duration::from_millis(100));
+    assert_eq!(client.call(&["get", "short"]), Frame::BulkString(None));
+    assert_eq!(client.call(&["get", "short"]), Frame::BulkString(None));
+    assert_eq!(
+        subscriber.read(),
+        Frame::Array(Some(vec![
+            bulk("message"),
+            bulk("__keyevent@0__:expired"),
+            bulk("short")
+        ]))
+    );
+    // Nothing else was queued before the reply to this PING
+    assert_eq!(
+        subscriber.call(&["ping"]),
+        Frame::Array(Some(vec![bulk("pong"), bulk("")]))
+    );
+
+    // Without the x flag expiries go unannounced
+    client.call(&["config", "set", "notify-keyspace-events", "Eg"]);
+    client.call(&["set", "quiet", "v", "PX", "50"]);
+    std::thread::sleep(Duration::from_millis(100));
+    assert_eq!(client.call(&["get", "quiet"]), Frame::BulkString(None));
+    assert_eq!(
+        subscriber.call(&["ping"]),
+        Frame::Array(Some(vec![bulk("pong"), bulk("")]))
+    );
+}
+
+/// The events published to the __keyevent@0__ channels until the subscriber's PING reply,
+/// as (event, key) pairs
+fn events(subscriber: &mut common::Client) -> Vec<(String, String)> {
+    let text = |frame: &Frame| match frame {
+        Frame::BulkString(Some(bytes)) => String::from_utf8_lossy(bytes).into_owned(),
+        other => panic!("expected a bulk string, got {:?}", other),
+    };
+    subscriber.send(&["ping"]);
+    let mut events = Vec::new();
+    loop {
+        match subscriber.read() {
+            Frame::Array(Some(items)) if items.len() == 4 => {
+                let event = text(&items[2])
+                    .trim_start_matches("__keyevent@0__:")
+                    .to_string();
+                events.push((event, text(&items[3])));
+            }
+            // The PING reply: everything published before it has been read
+            _ => return events,
+        }
+    }
+}
+
+fn pairs(events: &[(&str, &str)]) -> Vec<(String, String)> {
+    events
+        .iter()
+        .map(|(event, key)| (event.to_string(), key.to_string()))
+        .collect()
+}
+
+#[test]
+fn writes_announce_the_event_of_their_type() {
+    let server = Server::start(&[]);
+    let mut client = server.client();
+    let mut subscriber = server.client();
+    assert_eq!(
+        client.call(&["config", "set", "notify-keyspace-events", "EA"]),
+        ok()
+    );
+    subscriber.call(&["psubscribe", "__keyevent@0__:*"]);
+
+    client.call(&["set", "s", "v", "EX", "100"]);
+    client.call(&["mset", "s", "w"]);
+    client.call(&["rpush", "l", "a"]);
+    client.call(&["lpush", "l", "b"]);
+    client.call(&["hset", "h", "f", "v"]);
+    client.call(&["sadd", "set", "m"]);
+    // Adding a member that is already there changes nothing, so it goes unannounced
+    client.call(&["sadd", "set", "m"]);
+    client.call(&["zadd", "z", "1", "m"]);
+    client.call(&["zadd", "z", "1", "m"]);
+    client.call(&["zadd", "z", "INCR", "1", "m"]);
+    client.call(&["xadd", "x", "*", "f", "v"]);
+    let expected = [
+        ("set", "s"),
+        ("expire", "s"),
+        ("set", "s"),
+        ("rpush", "l"),
+        ("lpush", "l"),
+        ("hset", "h"),
+        ("sadd", "set"),
+        ("zadd", "z"),
+        ("zincr", "z"),
+        ("xadd", "x"),
+    ];
+    assert_eq!(events(&mut subscriber), pairs(&expected));
+
+    // Only the classes enabled are announced
+    client.call(&["config", "set", "notify-keyspace-events", "El"]);
+    client.call(&["set", "s", "v"]);
+    client.call(&["rpush", "l", "c"]);
+    assert_eq!(events(&mut subscriber), pairs(&[("rpush", "l")]));
+}
+
+#[test]
+fn new_keys_and_missed_reads_are_announced_when_asked_for() {
+    let server = Server::start(&[]);
+    let mut client = server.client();
+    let mut subscriber = server.client();
+    assert_eq!(
+        client.call(&["config", "set", "notify-keyspace-events", "Enm"]),
+        ok()
+    );
+    subscriber.call(&["psubscribe", "__keyevent@0__:*"]);
+
+    client.call(&["set", "k", "v"]);
+    client.call(&["set", "k", "w"]);
+    client.call(&["get", "k"]);
+    client.call(&["get", "missing"]);
+    client.call(&["lrange", "nolist", "0", "-1"]);
+    let expected = [("new", "k"), ("keymiss", "missing"), ("keymiss", "nolist")];
+    assert_eq!(events(&mut subscriber), pairs(&expected));
+}
@@ -0,0 +1,456 @@
+//! List commands run through a socketless session, and through a server process
+//! where a setting is changed
+mod common;
+
+use codecrafters_redis::resp::Frame;
+use codecrafters_redis::Session;
+use common::Server;
+use common::{bulk, ok};
+
+#[tokio::test]
+async fn pushes_refuse_keys_of_other_types() {
+    const WRONGTYPE: &[u8] =
+        b"-WRONGTYPE Operation against a key holding the wrong kind of value\r\n";
+    let mut session = Session::new();
+    session.call(&["set", "list:string", "text"]).await;
+    session.call(&["set", "list:int", "12345"]).await;
+    session.call(&["sadd", "list:set", "m"]).await;
+    session.call(&["hset", "list:hash", "f", "v"]).await;
+    for key in ["list:string", "list:int", "list:set", "list:hash"] {
+        for push in ["lpush", "rpush"] {
+            assert_eq!(
+                session.call(&[push, key, "a", "b"]).await,
+                WRONGTYPE,
+                "{} {}",
+                push,
+                key
+            );
+        }
+    }
+    // And leave them as they were
+    assert_eq!(session.call(&["get", "list:int"]).await, b"$5\r\n12345\r\n");
+    assert_eq!(
+        session.call(&["object", "encoding", "list:int"]).await,
+        b"$3\r\nint\r\n"
+    );
+    assert_eq!(session.call(&["type", "list:set"]).await, b"+set\r\n");
+}
+
+/// A field of the DEBUG OBJECT line
+fn debug_object_field(client: &mut common::Client, key: &str, name: &str) -> String {
+    let Frame::SimpleString(line) = client.call(&["debug", "object", key]) else {
+        panic!("DEBUG OBJECT didn't reply with a status line");
+    };
+    let prefix = format!("{}:", name);
+    line.split(' ')
+        .find_map(|field| field.strip_prefix(&prefix).map(str::to_string))
+        .unwrap_or_else(|| panic!("no {} in {}", name, line))
+}
+
+#[test]
+fn debug_object_describes_the_quicklist_nodes() {
+    let server = Server::start(&[]);
+    let mut client = server.client();
+    let item = "v".repeat(100);
+    let mut push = vec!["rpush", "bytes"];
+    push.extend(std::iter::repeat_n(item.as_str(), 200));
+    client.call(&push);
+    // 80 entries fill each 8kb node
+    assert_eq!(debug_object_field(&mut client, "bytes", "ql_nodes"), "3");
+    assert_eq!(
+        debug_object_field(&mut client, "bytes", "ql_avg_node"),
+        "66.67"
+    );
+    assert_eq!(
+        debug_object_field(&mut client, "bytes", "ql_listpack_max"),
+        "-2"
+    );
+
+    client.call(&["config", "set", "list-max-listpack-size", "3"]);
+    client.call(&[
+        "rpush", "entries", "a", "b", "c", "d", "e", "f", "g", "h", "i", "j",
+    ]);
+    assert_eq!(debug_object_field(&mut client, "entries", "ql_nodes"), "4");
+    assert_eq!(
+        debug_object_field(&mut client, "entries", "ql_avg_node"),
+        "2.50"
+    );
+    assert_eq!(
+        debug_object_field(&mut client, "entries", "ql_listpack_max"),
+        "3"
+    );
+    // Lists still in a single listpack have no nodes to describe
+    client.call(&["rpush", "small", "a"]);
+    let Frame::SimpleString(line) = client.call(&["debug", "object", "small"]) else {
+        panic!("DEBUG OBJECT didn't reply with a status line");
+    };
+    assert!(!line.contains("ql_nodes"), "{}", line);
+}
+
+/// The encoded reply of LRANGE key 0 -1 for these elements
+fn elements(items: &[&str]) -> Vec<u8> {
+    let mut reply = format!("*{}\r\n", items.len()).into_bytes();
+    for item in items {
+        reply.extend(format!("${}\r\n{}\r\n", item.len(), item).into_bytes());
+    }
+    reply
+}
+
+#[tokio::test]
+async fn variadic_pushes_keep_redis_order() {
+    let mut session = Session::new();
+    assert_eq!(
+        session.call(&["lpush", "list:l", "a", "b", "c"]).await,
+        b":3\r\n"
+    );
+    assert_eq!(
+        session.call(&["lrange", "list:l", "0", "-1"]).await,
+        elements(&["c", "b", "a"])
+    );
+
+    assert_eq!(
+        session.call(&["rpush", "list:r", "a", "b", "c"]).await,
+        b":3\r\n"
+    );
+    assert_eq!(
+        session.call(&["lrange", "list:r", "0", "-1"]).await,
+        elements(&["a", "b", "c"])
+    );
+
+    // Interleaved on the same key, each push replies with the length after all its values
+    assert_eq!(
+        session.call(&["lpush", "list:both", "1", "2"]).await,
+        b":2\r\n"
+    );
+    assert_eq!(
+        session.call(&["rpush", "list:both", "3", "4"]).await,
+        b":4\r\n"
+    );
+    assert_eq!(
+        session.call(&["lpush", "list:both", "5", "6"]).await,
+        b":6\r\n"
+    );
+    assert_eq!(
+        session.call(&["lrange", "list:both", "0", "-1"]).await,
+        elements(&["6", "5", "2", "1", "3", "4"])
+    );
+}
+
+#[tokio::test]
+async fn debug_quicklist_only_describes_quicklists() {
+    let mut session = Session::new();
+    assert_eq!(
+        session
+            .call(&["debug", "quicklist", "list:ql:missing"])
+            .await,
+        b"-ERR no such key\r\n"
+    );
+    session.call(&["rpush", "list:ql:small", "a", "b"]).await;
+    assert_eq!(
+        session.call(&["debug", "quicklist", "list:ql:small"]).await,
+        b"-ERR Not a quicklist encoded object.\r\n"
+    );
+
+    // Past the default 8kb listpack
+    let big = "x".repeat(1000);
+    let mut push = vec!["rpush", "list:ql:big"];
+    push.extend(std::iter::repeat_n(big.as_str(), 20));
+    session.call(&push).await;
+    assert_eq!(
+        session.call(&["object", "encoding", "list:ql:big"]).await,
+        b"$9\r\nquicklist\r\n"
+    );
+    for level in [
+        &["debug", "quicklist", "list:ql:big"][..],
+        &["debug", "quicklist", "list:ql:big", "1"],
+    ] {
+        assert_eq!(
+            session.call(level).await,
+            b"+Quicklist structure printed on stdout\r\n"
+        );
+    }
+    assert_eq!(
+        session
+            .call(&["debug", "quicklist", "list:ql:big", "1", "2"])
+            .await,
+        b"-ERR wrong number of arguments for 'debug quicklist'\r\n"
+    );
+}
+
+#[tokio::test]
+async fn lmpop_pops_from_the_first_non_empty_list() {
+    let mut session = Session::new();
+    session.call(&["rpush", "list:mpop:b", "1", "2", "3"]).await;
+    session.call(&["rpush", "list:mpop:c", "x"]).await;
+    assert_eq!(
+        session
+            .call(&[
+                "lmpop",
+                "3",
+                "list:mpop:a",
+                "list:mpop:b",
+                "list:mpop:c",
+                "LEFT"
+            ])
+            .await,
+        b"*2\r\n$11\r\nlist:mpop:b\r\n*1\r\n$1\r\n1\r\n"
+    );
+    // COUNT takes as many as there are, and the emptied key goes
+    assert_eq!(
+        session
+            .call(&[
+                "lmpop",
+                "2",
+                "list:mpop:b",
+                "list:mpop:c",
+                "RIGHT",
+                "COUNT",
+                "5"
+            ])
+            .await,
+        b"*2\r\n$11\r\nlist:mpop:b\r\n*2\r\n$1\r\n3\r\n$1\r\n2\r\n"
+    );
+    assert_eq!(session.call(&["type", "list:mpop:b"]).await, b"+none\r\n");
+    assert_eq!(
+        session.call(&["lmpop", "1", "list:mpop:a", "LEFT"]).await,
+        b"*-1\r\n"
+    );
+
+    for (command, error) in [
+        (
+            &["lmpop", "0", "list:mpop:c", "LEFT"][..],
+            "ERR numkeys should be greater than 0",
+        ),
+        (&["lmpop", "2", "list:mpop:c", "LEFT"], "ERR syntax error"),
+        (&["lmpop", "1", "list:mpop:c", "UP"], "ERR syntax error"),
+        (
+            &["lmpop", "1", "list:mpop:c", "LEFT", "COUNT", "0"],
+            "ERR count should be greater than 0",
+        ),
+    ] {
+        assert_eq!(
+            session.call(command).await,
+            format!("-{}\r\n", error).into_bytes(),
+            "{:?}",
+            command
+        );
+    }
+    assert_eq!(
+        session.call(&["lrange", "list:mpop:c", "0", "-1"]).await,
+        elements(&["x"])
+    );
+}
+
+#[tokio::test]
+async fn lrem_counts_from_either_end() {
+    let mut session = Session::new();
+    let fill = ["rpush", "list:rem", "a", "x", "b", "x", "c", "x"];
+    session.call(&fill).await;
+    assert_eq!(
+        session.call(&["lrem", "list:rem", "2", "x"]).await,
+        b":2\r\n"
+    );
+    assert_eq!(
+        session.call(&["lrange", "list:rem", "0", "-1"]).await,
+        elements(&["a", "b", "c", "x"])
+    );
+
+    session.call(&["del", "list:rem"]).await;
+    session.call(&fill).await;
+    assert_eq!(
+        session.call(&["lrem", "list:rem", "-2", "x"]).await,
+        b":2\r\n"
+    );
+    assert_eq!(
+        session.call(&["lrange", "list:rem", "0", "-1"]).await,
+        elements(&["a", "x", "b", "c"])
+    );
+    assert_eq!(
+        session.call(&["lrem", "list:rem", "0", "nope"]).await,
+        b":0\r\n"
+    );
+    assert_eq!(
+        session.call(&["lrem", "list:rem", "0", "x"]).await,
+        b":1\r\n"
+    );
+
+    // Removing the last elements removes the key
+    session.call(&["rpush", "list:rem:one", "x", "x"]).await;
+    assert_eq!(
+        session.call(&["lrem", "list:rem:one", "0", "x"]).await,
+        b":2\r\n"
+    );
+    assert_eq!(session.call(&["type", "list:rem:one"]).await, b"+none\r\n");
+}
+
+#[tokio::test]
+async fn ltrim_keeps_an_inclusive_range() {
+    let mut session = Session::new();
+    session
+        .call(&["rpush", "list:trim", "a", "b", "c", "d", "e"])
+        .await;
+    assert_eq!(
+        session.call(&["ltrim", "list:trim", "1", "-2"]).await,
+        b"+OK\r\n"
+    );
+    assert_eq!(
+        session.call(&["lrange", "list:trim", "0", "-1"]).await,
+        elements(&["b", "c", "d"])
+    );
+    assert_eq!(
+        session.call(&["ltrim", "list:trim", "-100", "1"]).await,
+        b"+OK\r\n"
+    );
+    assert_eq!(
+        session.call(&["lrange", "list:trim", "0", "-1"]).await,
+        elements(&["b", "c"])
+    );
+    // An empty range removes the key
+    assert_eq!(
+        session.call(&["ltrim", "list:trim", "5", "10"]).await,
+        b"+OK\r\n"
+    );
+    assert_eq!(session.call(&["type", "list:trim"]).await, b"+none\r\n");
+    assert_eq!(
+        session.call(&["ltrim", "list:trim", "0", "1"]).await,
+        b"+OK\r\n"
+    );
+}
+
+#[tokio::test]
+async fn linsert_places_values_around_the_first_pivot() {
+    let mut session = Session::new();
+    session
+        .call(&["rpush", "list:ins", "a", "p", "b", "p"])
+        .await;
+    assert_eq!(
+        session
+            .call(&["linsert", "list:ins", "BEFORE", "p", "x"])
+            .await,
+        b":5\r\n"
+    );
+    assert_eq!(
+        session
+            .call(&["linsert", "list:ins", "after", "p", "y"])
+            .await,
+        b":6\r\n"
+    );
+    assert_eq!(
+        session.call(&["lrange", "list:ins", "0", "-1"]).await,
+        elements(&["a", "x", "p", "y", "b", "p"])
+    );
+    assert_eq!(
+        session
+            .call(&["linsert", "list:ins", "before", "nope", "z"])
+            .await,
+        b":-1\r\n"
+    );
+    assert_eq!(
+        session
+            .call(&["linsert", "list:missing", "before", "p", "z"])
+            .await,
+        b":0\r\n"
+    );
+    assert_eq!(
+        session
+            .call(&["linsert", "list:ins", "around", "p", "z"])
+            .await,
+        b"-ERR syntax error\r\n"
+    );
+}
+
+#[tokio::test]
+async fn lpos_finds_matches_by_rank_count_and_maxlen() {
+    let mut session = Session::new();
+    session
+        .call(&["rpush", "list:pos", "a", "b", "c", "1", "2", "3", "c", "c"])
+        .await;
+    for (options, reply) in [
+        (&[][..], ":2\r\n"),
+        (&["RANK", "2"], ":6\r\n"),
+        (&["RANK", "-1"], ":7\r\n"),
+        (&["RANK", "4"], "$-1\r\n"),
+        (&["COUNT", "2"], "*2\r\n:2\r\n:6\r\n"),
+        (&["RANK", "-1", "COUNT", "2"], "*2\r\n:7\r\n:6\r\n"),
+        // COUNT 0 asks for every match
+        (&["COUNT", "0"], "*3\r\n:2\r\n:6\r\n:7\r\n"),
+        (&["RANK", "-2", "COUNT", "0"], "*2\r\n:6\r\n:2\r\n"),
+        // MAXLEN only looks at that many entries from where the scan starts
+        (&["MAXLEN", "2"], "$-1\r\n"),
+        (&["COUNT", "0", "MAXLEN", "7"], "*2\r\n:2\r\n:6\r\n"),
+        (
+            &["RANK", "-1", "COUNT", "0", "MAXLEN", "2"],
+            "*2\r\n:7\r\n:6\r\n",
+        ),
+    ] {
+        let mut command = vec!["lpos", "list:pos", "c"];
+        command.extend_from_slice(options);
+        assert_eq!(
+            session.call(&command).await,
+            reply.as_bytes(),
+            "{:?}",
+            options
+        );
+    }
+    assert_eq!(session.call(&["lpos", "list:pos", "x"]).await, b"$-1\r\n");
+    assert_eq!(
+        session.call(&["lpos", "list:pos", "x", "COUNT", "0"]).await,
+        b"*0\r\n"
+    );
+    assert_eq!(
+        session
+            .call(&["lpos", "list:missing", "x", "COUNT", "1"])
+            .await,
+        b"*0\r\n"
+    );
+    assert_eq!(
+        session
+            .call(&["lpos", "list:pos", "c", "COUNT", "-1"])
+            .await,
+        b"-ERR COUNT can't be negative\r\n"
+    );
+    assert!(session
+        .call(&["lpos", "list:pos", "c", "RANK", "0"])
+        .await
+        .starts_with(b"-ERR RANK can't be zero"));
+}
+
+#[tokio::test]
+async fn a_list_past_8kb_is_a_quicklist_by_default() {
+    let mut session = Session::new();
+    let item = "v".repeat(100);
+    // 80 entries of 102 bytes, with the header, just fit in 8kb
+    for _ in 0..80 {
+        session.call(&["rpush", "list:enc", &item]).await;
+    }
+    assert_eq!(
+        session.call(&["object", "encoding", "list:enc"]).await,
+        b"$8\r\nlistpack\r\n"
+    );
+    session.call(&["rpush", "list:enc", &item]).await;
+    assert_eq!(
+        session.call(&["object", "encoding", "list:enc"]).await,
+        b"$9\r\nquicklist\r\n"
+    );
+    session
+        .call(&["rpush", "list:huge", &"v".repeat(9000)])
+        .await;
+    assert_eq!(
+        session.call(&["object", "encoding", "list:huge"]).await,
+        b"$9\r\nquicklist\r\n"
+    );
+}
+
+#[test]
+fn list_max_listpack_size_can_count_entries_instead() {
+    let server = Server::start(&[]);
+    let mut client = server.client();
+    assert_eq!(
+        client.call(&["config", "set", "list-max-listpack-size", "3"]),
+        ok()
+    );
+    client.call(&["rpush", "l", "a", "b", "c"]);
+    assert_eq!(client.call(&["object", "encoding", "l"]), bulk("listpack"));
+    client.call(&["rpush", "l", "d"]);
+    assert_eq!(client.call(&["object", "encoding", "l"]), bulk("quicklist"));
+}
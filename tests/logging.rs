@@ -0,0 +1,43 @@
+//! What a server process logs at each loglevel
+mod common;
+
+use codecrafters_redis::resp::Frame;
+use common::{bulk, ok, Server};
+
+#[test]
+fn verbose_lines_are_left_out_at_the_notice_level() {
+    let server = Server::start_logging(&[]);
+    server.client().call(&["ping"]);
+    let logged = server.logged();
+    assert!(logged.contains("* Listening on"), "{}", logged);
+    assert!(!logged.contains("New client"), "{}", logged);
+
+    let server = Server::start_logging(&["--loglevel", "verbose"]);
+    server.client().call(&["ping"]);
+    let logged = server.logged();
+    assert!(logged.contains("- New client"), "{}", logged);
+
+    // Only below the level that is set
+    let server = Server::start_logging(&["--loglevel", "warning"]);
+    server.client().call(&["ping"]);
+    let logged = server.logged();
+    assert!(!logged.contains("Listening on"), "{}", logged);
+}
+
+#[test]
+fn config_set_loglevel_applies_to_what_comes_next() {
+    let server = Server::start_logging(&[]);
+    let mut client = server.client();
+    assert_eq!(client.call(&["config", "set", "loglevel", "debug"]), ok());
+    assert_eq!(
+        client.call(&["config", "get", "loglevel"]),
+        Frame::Array(Some(vec![bulk("loglevel"), bulk("debug")]))
+    );
+    server.client().call(&["ping"]);
+    assert!(matches!(
+        client.call(&["config", "set", "loglevel", "loud"]),
+        Frame::Error(_)
+    ));
+    let logged = server.logged();
+    assert_eq!(logged.matches("- New client").count(), 1, "{}", logged);
+}
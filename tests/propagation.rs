@@ -0,0 +1,545 @@
+//! What writes append to the AOF, which is what replicas are sent too: the effect a
+//! command had rather than the command, whenever replaying the command could differ
+use codecrafters_redis::resp::parser::FrameParser;
+use codecrafters_redis::resp::Frame;
+use codecrafters_redis::Session;
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::sync::Mutex;
+
+/// The AOF settings are global, so tests that log take turns
+static LOGGING: Mutex<()> = Mutex::const_new(());
+
+fn aof_dir() -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("redis-rust-propagation-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// The commands in a log, as strings, leaving out the SELECTs
+fn commands(log: &[u8]) -> Vec<Vec<String>> {
+    let mut parser = FrameParser::new();
+    parser.feed(log);
+    let mut commands = Vec::new();
+    while let Some(frame) = parser.parse().unwrap() {
+        let Frame::Array(Some(args)) = frame else {
+            panic!("logged {:?}", frame);
+        };
+        let args: Vec<String> = args
+            .into_iter()
+            .map(|arg| match arg {
+                Frame::BulkString(Some(arg)) => String::from_utf8_lossy(&arg).into_owned(),
+                arg => panic!("logged argument {:?}", arg),
+            })
+            .collect();
+        if args[0] != "SELECT" {
+            commands.push(args);
+        }
+    }
+    assert_eq!(parser.buffered(), 0, "truncated log");
+    commands
+}
+
+fn frames(reply: &[u8]) -> Vec<String> {
+    let mut parser = FrameParser::new();
+    parser.feed(reply);
+    match parser.parse().unwrap() {
+        Some(Frame::Array(Some(items))) => items
+            .into_iter()
+            .map(|item| match item {
+                Frame::BulkString(Some(item)) => String::from_utf8(item).unwrap(),
+                item => panic!("expected a bulk string, got {:?}", item),
+            })
+            .collect(),
+        other => panic!("expected an array, got {:?}", other),
+    }
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn concurrent_writes_are_logged_in_the_order_they_were_applied() {
+    let _turn = LOGGING.lock().await;
+    let mut session = Session::new();
+    let dir = aof_dir();
+    let _ = std::fs::remove_file(dir.join("order.aof"));
+    let dir_arg = dir.to_string_lossy().into_owned();
+    session.call(&["config", "set", "dir", &dir_arg]).await;
+    session
+        .call(&["config", "set", "appendfilename", "order.aof"])
+        .await;
+    session.call(&["config", "set", "appendonly", "yes"]).await;
+    let preamble = std::fs::metadata(dir.join("order.aof")).unwrap().len() as usize;
+
+    let writers: Vec<_> = (0..8)
+        .map(|writer| {
+            tokio::spawn(async move {
+                let mut session = Session::new();
+                for i in 0..200 {
+                    let element = format!("{}-{}", writer, i);
+                    session.call(&["rpush", "prop:order", &element]).await;
+                    // An LMOVE, whose element depends on what was applied before it
+                    if i % 10 == 0 {
+                        let args = ["lmove", "prop:order", "prop:order", "LEFT", "RIGHT"];
+                        session.call(&args).await;
+                    }
+                }
+            })
+        })
+        .collect();
+    for writer in writers {
+        writer.await.unwrap();
+    }
+    session.call(&["waitaof", "1", "0", "0"]).await;
+    session.call(&["config", "set", "appendonly", "no"]).await;
+    let list = frames(&session.call(&["lrange", "prop:order", "0", "-1"]).await);
+
+    // Replaying the log must build the same list
+    let log = std::fs::read(dir.join("order.aof")).unwrap();
+    let mut replayed: Vec<String> = Vec::new();
+    for command in commands(&log[preamble..]) {
+        match command[0].as_str() {
+            "rpush" => replayed.push(command[2].clone()),
+            "lmove" => replayed.rotate_left(1),
+            other => panic!("logged {}", other),
+        }
+    }
+    assert_eq!(replayed, list);
+}
+
+fn now_ms() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_millis() as i64
+}
+
+/// Run commands with appendonly on, logging to a file of their own, and return
+/// the replies and the raw bytes appended
+async fn logged(name: &str, commands: &[&[&str]]) -> (Vec<Vec<u8>>, Vec<u8>) {
+    let commands: Vec<Vec<&[u8]>> = commands
+        .iter()
+        .map(|command| command.iter().map(|arg| arg.as_bytes()).collect())
+        .collect();
+    logged_bytes(name, &commands).await
+}
+
+async fn logged_bytes(name: &str, commands: &[Vec<&[u8]>]) -> (Vec<Vec<u8>>, Vec<u8>) {
+    let _turn = LOGGING.lock().await;
+    let mut session = Session::new();
+    let dir = aof_dir();
+    let filename = format!("{}.aof", name);
+    let _ = std::fs::remove_file(dir.join(&filename));
+    let dir_arg = dir.to_string_lossy().into_owned();
+    for (param, value) in [("dir", dir_arg.as_str()), ("appendfilename", &filename)] {
+        assert_eq!(
+            session.call(&["config", "set", param, value]).await,
+            b"+OK\r\n"
+        );
+    }
+    session.call(&["config", "set", "appendonly", "yes"]).await;
+    // The file starts with an image of the dataset, which the writes are appended to
+    let preamble = std::fs::metadata(dir.join(&filename)).unwrap().len() as usize;
+    let mut replies = Vec::new();
+    for command in commands {
+        replies.push(session.call(command).await);
+    }
+    assert_eq!(
+        session.call(&["waitaof", "1", "0", "0"]).await,
+        b"*2\r\n:1\r\n:0\r\n"
+    );
+    session.call(&["config", "set", "appendonly", "no"]).await;
+    let log = std::fs::read(dir.join(&filename)).unwrap();
+    (replies, log[preamble..].to_vec())
+}
+
+fn bulk(reply: &[u8]) -> String {
+    let mut parser = FrameParser::new();
+    parser.feed(reply);
+    match parser.parse().unwrap() {
+        Some(Frame::BulkString(Some(bytes))) => String::from_utf8(bytes).unwrap(),
+        other => panic!("expected a bulk string, got {:?}", other),
+    }
+}
+
+fn integer(reply: &[u8]) -> i64 {
+    let reply = std::str::from_utf8(reply).unwrap();
+    reply.trim_start_matches(':').trim_end().parse().unwrap()
+}
+
+/// Assert a logged time lies `offset_ms` after the window the command ran in
+fn assert_time(logged: &str, before: i64, after: i64, offset_ms: i64) {
+    let at: i64 = logged.parse().unwrap();
+    assert!(
+        (before + offset_ms..=after + offset_ms).contains(&at),
+        "{} is not within {}..={}",
+        at,
+        before + offset_ms,
+        after + offset_ms
+    );
+}
+
+#[tokio::test]
+async fn spop_is_logged_as_srem_of_the_members_removed() {
+    let (replies, log) = logged(
+        "spop",
+        &[
+            &["sadd", "prop:set", "a", "b", "c", "d", "e"],
+            &["spop", "prop:set", "2"],
+            &["spop", "prop:set"],
+            &["spop", "prop:missing"],
+            &["spop", "prop:missing", "3"],
+        ],
+    )
+    .await;
+    let popped = frames(&replies[1]);
+    assert_eq!(popped.len(), 2);
+    let single = bulk(&replies[2]);
+    let log = commands(&log);
+    assert_eq!(log.len(), 3);
+    assert_eq!(log[0], ["sadd", "prop:set", "a", "b", "c", "d", "e"]);
+    assert_eq!(
+        log[1],
+        [&["SREM".to_string(), "prop:set".into()][..], &popped].concat()
+    );
+    assert_eq!(log[2], ["SREM", "prop:set", &single]);
+}
+
+#[tokio::test]
+async fn reads_and_writes_that_change_nothing_are_not_logged() {
+    let (_, log) = logged(
+        "noop",
+        &[
+            &["get", "prop:noop"],
+            &["set", "prop:noop", "v"],
+            &["get", "prop:noop"],
+            &["getex", "prop:noop"],
+            &["getex", "prop:missing", "EX", "10"],
+            &["expire", "prop:missing", "10"],
+            &["expire", "prop:noop", "10", "XX"],
+            &["set", "prop:noop", "w", "NX", "EX", "10"],
+            &["hset", "prop:noop:h", "f", "v"],
+            &["hexpire", "prop:noop:h", "10", "FIELDS", "1", "missing"],
+            &["hgetex", "prop:noop:h", "FIELDS", "1", "f"],
+            &[
+                "hgetex",
+                "prop:noop:h",
+                "EX",
+                "10",
+                "FIELDS",
+                "1",
+                "missing",
+            ],
+            &["hsetnx", "prop:noop:h", "f", "w"],
+            &["hgetdel", "prop:noop:h", "FIELDS", "1", "missing"],
+            &["srem", "prop:noop:s", "a"],
+            &["del", "prop:missing"],
+            &["setrange", "prop:noop", "0", ""],
+            &["move", "prop:missing", "1"],
+            &["zadd", "prop:noop:z", "XX", "1", "m"],
+            &["ltrim", "prop:missing", "0", "1"],
+            &["linsert", "prop:missing", "BEFORE", "a", "b"],
+            &["lrem", "prop:missing", "0", "a"],
+            &["lpop", "prop:missing"],
+            &["lmove", "prop:missing", "prop:dest", "LEFT", "LEFT"],
+            &["blmpop", "0.01", "1", "prop:missing", "LEFT"],
+            &["blpop", "prop:missing", "0.01"],
+            &[
+                "blmove",
+                "prop:missing",
+                "prop:dest",
+                "LEFT",
+                "LEFT",
+                "0.01",
+            ],
+        ],
+    )
+    .await;
+    assert_eq!(
+        commands(&log),
+        vec![
+            vec!["set", "prop:noop", "v"],
+            vec!["hset", "prop:noop:h", "f", "v"],
+        ]
+    );
+}
+
+#[tokio::test]
+async fn writes_are_logged_when_they_change_something_not_by_what_they_reply() {
+    let (replies, log) = logged(
+        "changed",
+        &[
+            &["zadd", "prop:changed", "1", "m"],
+            &["zadd", "prop:changed", "2", "m"],
+            &["zadd", "prop:changed", "2", "m"],
+            &["sadd", "prop:changed:s", "a"],
+            &["sadd", "prop:changed:s", "a"],
+        ],
+    )
+    .await;
+    assert_eq!(replies[1], b":0\r\n");
+    assert_eq!(
+        commands(&log),
+        vec![
+            vec!["zadd", "prop:changed", "1", "m"],
+            vec!["zadd", "prop:changed", "2", "m"],
+            vec!["sadd", "prop:changed:s", "a"],
+        ]
+    );
+}
+
+#[tokio::test]
+async fn relative_ttls_are_logged_as_the_time_they_came_to() {
+    let before = now_ms();
+    let (replies, log) = logged(
+        "ttl",
+        &[
+            &["set", "prop:ttl:set", "v", "EX", "100", "NX"],
+            &["setex", "prop:ttl:setex", "200", "v"],
+            &["psetex", "prop:ttl:psetex", "3000", "v"],
+            &["set", "prop:ttl:expire", "v"],
+            &["expire", "prop:ttl:expire", "400"],
+            &["pexpire", "prop:ttl:expire", "5000", "LT"],
+            &["getex", "prop:ttl:expire", "EX", "600"],
+            &["hset", "prop:ttl:hash", "a", "1", "b", "2"],
+            &[
+                "hexpire",
+                "prop:ttl:hash",
+                "700",
+                "FIELDS",
+                "2",
+                "a",
+                "missing",
+            ],
+            &["hpexpire", "prop:ttl:hash", "8000", "FIELDS", "1", "b"],
+            &[
+                "hgetex",
+                "prop:ttl:hash",
+                "EX",
+                "900",
+                "FIELDS",
+                "2",
+                "a",
+                "b",
+            ],
+            &["dump", "prop:ttl:psetex"],
+        ],
+    )
+    .await;
+    let after = now_ms();
+    let log = commands(&log);
+    let expected: &[(&[&str], i64)] = &[
+        (&["SET", "prop:ttl:set", "v", "PXAT", "", "NX"], 100_000),
+        (&["SET", "prop:ttl:setex", "v", "PXAT", ""], 200_000),
+        (&["SET", "prop:ttl:psetex", "v", "PXAT", ""], 3_000),
+        (&["set", "prop:ttl:expire", "v"], 0),
+        (&["PEXPIREAT", "prop:ttl:expire", ""], 400_000),
+        (&["PEXPIREAT", "prop:ttl:expire", ""], 5_000),
+        (&["PEXPIREAT", "prop:ttl:expire", ""], 600_000),
+        (&["hset", "prop:ttl:hash", "a", "1", "b", "2"], 0),
+        (
+            &["HPEXPIREAT", "prop:ttl:hash", "", "FIELDS", "1", "a"],
+            700_000,
+        ),
+        (
+            &["HPEXPIREAT", "prop:ttl:hash", "", "FIELDS", "1", "b"],
+            8_000,
+        ),
+        (
+            &[
+                "HGETEX",
+                "prop:ttl:hash",
+                "PXAT",
+                "",
+                "FIELDS",
+                "2",
+                "a",
+                "b",
+            ],
+            900_000,
+        ),
+    ];
+    assert_eq!(log.len(), expected.len(), "{:?}", log);
+    for (logged, (expected, offset)) in log.iter().zip(expected) {
+        assert_eq!(logged.len(), expected.len(), "{:?}", logged);
+        for (arg, expected) in logged.iter().zip(expected.iter()) {
+            if expected.is_empty() {
+                assert_time(arg, before, after, *offset);
+            } else {
+                assert_eq!(arg, expected, "{:?}", logged);
+            }
+        }
+    }
+
+    // RESTORE with a relative TTL is logged with an absolute one
+    let reply = &replies[11];
+    let payload = &reply[reply.iter().position(|&b| b == b'\n').unwrap() + 1..reply.len() - 2];
+    let restore: Vec<&[u8]> = vec![
+        b"restore",
+        b"prop:ttl:restore",
+        b"10000",
+        payload,
+        b"REPLACE",
+    ];
+    let before = now_ms();
+    let (replies, log) = logged_bytes("restore", &[restore]).await;
+    assert_eq!(replies[0], b"+OK\r\n");
+    let after = now_ms();
+    let log = commands(&log);
+    assert_eq!(log.len(), 1);
+    assert_eq!(log[0][..2], ["RESTORE", "prop:ttl:restore"]);
+    assert_time(&log[0][2], before, after, 10_000);
+    assert_eq!(log[0][4..], ["REPLACE", "ABSTTL"]);
+}
+
+#[tokio::test]
+async fn restore_past_its_deadline_only_deletes_what_it_replaces() {
+    let mut session = Session::new();
+    session.call(&["set", "prop:restore:src", "v"]).await;
+    let dump = session.call(&["dump", "prop:restore:src"]).await;
+    let payload = &dump[dump.iter().position(|&b| b == b'\n').unwrap() + 1..dump.len() - 2];
+    let passed = (now_ms() - 1000).to_string();
+    let restore = |key: &'static str, replace: bool| {
+        let mut command: Vec<&[u8]> = vec![b"restore", key.as_bytes(), passed.as_bytes()];
+        command.extend([payload, b"ABSTTL"]);
+        if replace {
+            command.push(b"REPLACE");
+        }
+        command
+    };
+    session.call(&["set", "prop:restore:busy", "old"]).await;
+    session.call(&["set", "prop:restore:replaced", "old"]).await;
+    let (replies, log) = logged_bytes(
+        "restore-passed",
+        &[
+            restore("prop:restore:busy", false),
+            restore("prop:restore:replaced", true),
+            restore("prop:restore:missing", true),
+        ],
+    )
+    .await;
+    assert_eq!(
+        replies,
+        [
+            &b"-BUSYKEY Target key name already exists.\r\n"[..],
+            b"+OK\r\n",
+            b"+OK\r\n"
+        ]
+    );
+    assert_eq!(commands(&log), [["DEL", "prop:restore:replaced"]]);
+    assert_eq!(
+        session.call(&["get", "prop:restore:busy"]).await,
+        b"$3\r\nold\r\n"
+    );
+    for key in ["prop:restore:replaced", "prop:restore:missing"] {
+        assert_eq!(session.call(&["type", key]).await, b"+none\r\n");
+    }
+}
+
+#[tokio::test]
+async fn replaying_the_log_keeps_the_deadlines() {
+    let (_, log) = logged(
+        "replay",
+        &[
+            &["set", "prop:replay:a", "v", "PX", "100000"],
+            &["setex", "prop:replay:b", "100", "v"],
+            &["set", "prop:replay:c", "v"],
+            &["pexpire", "prop:replay:c", "50000"],
+        ],
+    )
+    .await;
+    let keys = ["prop:replay:a", "prop:replay:b", "prop:replay:c"];
+    let mut session = Session::new();
+    let mut deadlines = Vec::new();
+    for key in keys {
+        deadlines.push(integer(&session.call(&["pexpiretime", key]).await));
+    }
+    // Late enough that a replayed relative TTL would end later, while the deadlines,
+    // kept as instants, may round a millisecond either way
+    tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+    session.call(&["del", keys[0], keys[1], keys[2]]).await;
+    let replies = session.feed(&log).await;
+    assert!(!replies.starts_with(b"-"), "{:?}", replies);
+    for (key, deadline) in keys.iter().zip(deadlines) {
+        let replayed = integer(&session.call(&["pexpiretime", key]).await);
+        assert!(
+            (replayed - deadline).abs() <= 2,
+            "{} moved to {}",
+            deadline,
+            replayed
+        );
+    }
+}
+
+#[tokio::test]
+async fn blocking_pops_are_logged_as_the_pop_that_served_them() {
+    let (_, log) = logged(
+        "blocking",
+        &[
+            &["rpush", "prop:list", "a", "b", "c", "d", "e"],
+            &["blpop", "prop:missing", "prop:list", "0"],
+            &["brpop", "prop:list", "0"],
+            &["blmove", "prop:list", "prop:dest", "LEFT", "RIGHT", "0"],
+            &["brpoplpush", "prop:list", "prop:dest", "0"],
+            &[
+                "blmpop",
+                "0",
+                "2",
+                "prop:missing",
+                "prop:dest",
+                "RIGHT",
+                "COUNT",
+                "5",
+            ],
+            &["zadd", "prop:zset", "1", "m", "2", "n"],
+            &["bzmpop", "0", "1", "prop:zset", "MIN"],
+            &["lmpop", "1", "prop:list", "LEFT"],
+        ],
+    )
+    .await;
+    assert_eq!(
+        commands(&log),
+        vec![
+            vec!["rpush", "prop:list", "a", "b", "c", "d", "e"],
+            vec!["LPOP", "prop:list"],
+            vec!["RPOP", "prop:list"],
+            vec!["LMOVE", "prop:list", "prop:dest", "LEFT", "RIGHT"],
+            vec!["RPOPLPUSH", "prop:list", "prop:dest"],
+            vec!["LMPOP", "1", "prop:dest", "RIGHT", "COUNT", "2"],
+            vec!["zadd", "prop:zset", "1", "m", "2", "n"],
+            vec!["ZMPOP", "1", "prop:zset", "MIN", "COUNT", "1"],
+            vec!["LMPOP", "1", "prop:list", "LEFT", "COUNT", "1"],
+        ]
+    );
+}
+
+#[tokio::test]
+async fn generated_values_are_logged_as_what_was_stored() {
+    let (replies, log) = logged(
+        "generated",
+        &[
+            &["xadd", "prop:stream", "*", "f", "v"],
+            &["set", "prop:float", "1.5"],
+            &["incrbyfloat", "prop:float", "0.25"],
+        ],
+    )
+    .await;
+    let id = bulk(&replies[0]);
+    assert_eq!(
+        commands(&log),
+        vec![
+            vec![
+                "XADD".to_string(),
+                "prop:stream".into(),
+                id,
+                "f".into(),
+                "v".into()
+            ],
+            vec!["set".into(), "prop:float".into(), "1.5".into()],
+            vec![
+                "SET".to_string(),
+                "prop:float".into(),
+                "1.75".into(),
+                "KEEPTTL".into()
+            ],
+        ]
+    );
+}
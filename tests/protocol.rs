@@ -0,0 +1,78 @@
+//! Raw protocol bytes fed through a socketless session, as a connection reads them,
+//! and written to a server process where a setting is changed
+mod common;
+
+use codecrafters_redis::resp::Frame;
+use codecrafters_redis::Session;
+use common::{bulk, ok, Server};
+
+#[tokio::test]
+async fn nothing_after_a_malformed_multibulk_is_run() {
+    let mut session = Session::new();
+    assert_eq!(
+        session
+            .feed(b"*2\r\n$4\r\nECHO\r\n$abc\r\n*2\r\n$4\r\nECHO\r\n$2\r\nok\r\n")
+            .await,
+        b"-ERR Protocol error: invalid bulk length\r\n"
+    );
+    assert!(session.feed(b"PING\r\n").await.is_empty());
+}
+
+#[test]
+fn a_bulk_over_the_limit_closes_the_connection_before_its_payload_runs() {
+    let server = Server::start(&[]);
+    let mut client = server.client();
+    assert_eq!(
+        client.call(&["config", "set", "proto-max-bulk-len", "1k"]),
+        ok()
+    );
+    assert_eq!(client.call(&["set", "proto:victim", "v"]), ok());
+
+    // A payload that would run as inline commands if parsing resumed inside it
+    let mut payload = b"\r\nDEL proto:victim\r\n".to_vec();
+    payload.resize(2048, b'x');
+    let mut command =
+        format!("*3\r\n$3\r\nSET\r\n$3\r\nbig\r\n${}\r\n", payload.len()).into_bytes();
+    command.extend_from_slice(&payload);
+    command.extend_from_slice(b"\r\n");
+    client.write(&command);
+    assert_eq!(
+        client.read(),
+        Frame::Error("ERR Protocol error: invalid bulk length".into())
+    );
+    assert!(client.closed());
+    assert_eq!(server.client().call(&["get", "proto:victim"]), bulk("v"));
+}
+
+#[tokio::test]
+async fn an_over_long_inline_request_is_skipped_to_its_end() {
+    let mut session = Session::new();
+    let mut written = Vec::new();
+    for _ in 0..32 {
+        written.extend(session.feed(&[b'x'; 4096]).await);
+    }
+    assert_eq!(written, b"-ERR Protocol error: too big inline request\r\n");
+    assert_eq!(session.feed(b"xxx\r\nPING\r\n").await, b"+PONG\r\n");
+}
+
+#[tokio::test]
+async fn commands_split_across_reads_run_once_complete() {
+    let mut session = Session::new();
+    let input = b"*3\r\n$3\r\nSET\r\n$11\r\nproto:split\r\n$5\r\nvalue\r\nGET proto:split\n";
+    let mut written = Vec::new();
+    for byte in input {
+        written.extend(session.feed(&[*byte]).await);
+    }
+    assert_eq!(written, b"+OK\r\n$5\r\nvalue\r\n");
+}
+
+#[tokio::test]
+async fn an_error_quoting_a_line_break_stays_on_one_line() {
+    let mut session = Session::new();
+    assert_eq!(
+        session
+            .call(&["expire", "proto:key", "10", "bad\r\n*1"])
+            .await,
+        b"-ERR Unsupported option bad  *1\r\n"
+    );
+}
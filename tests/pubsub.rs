@@ -0,0 +1,109 @@
+//! Publish/subscribe through socketless sessions, each its own subscriber
+use codecrafters_redis::Session;
+
+#[tokio::test]
+async fn a_resp2_subscriber_may_only_manage_its_subscriptions() {
+    let mut session = Session::new();
+    session.call(&["subscribe", "pubsub:gate"]).await;
+    assert_eq!(
+        session.call(&["get", "k"]).await,
+        b"-ERR Can't execute 'get': only (P)SUBSCRIBE / (P)UNSUBSCRIBE / PING / QUIT / RESET are allowed in this context\r\n"
+    );
+    // Patterns are left to the NUMPAT test, which counts every one there is
+    assert_eq!(
+        session.call(&["subscribe", "pubsub:gate:2"]).await,
+        b"*3\r\n$9\r\nsubscribe\r\n$13\r\npubsub:gate:2\r\n:2\r\n"
+    );
+    // Once out of subscriber mode, anything goes again
+    session.call(&["unsubscribe"]).await;
+    assert_eq!(session.call(&["get", "pubsub:gate:k"]).await, b"$-1\r\n");
+
+    let mut resp3 = Session::new();
+    resp3.call(&["hello", "3"]).await;
+    resp3.call(&["subscribe", "pubsub:gate"]).await;
+    assert_eq!(resp3.call(&["get", "pubsub:gate:k"]).await, b"$-1\r\n");
+}
+
+#[tokio::test]
+async fn pubsub_introspection_reflects_subscriptions() {
+    let mut first = Session::new();
+    let mut second = Session::new();
+    first
+        .call(&["subscribe", "pubsub:intro:a", "pubsub:intro:b"])
+        .await;
+    second.call(&["subscribe", "pubsub:intro:a"]).await;
+    let mut observer = Session::new();
+
+    let channels = observer
+        .call(&["pubsub", "channels", "pubsub:intro:*"])
+        .await;
+    assert!(
+        channels == b"*2\r\n$14\r\npubsub:intro:a\r\n$14\r\npubsub:intro:b\r\n"
+            || channels == b"*2\r\n$14\r\npubsub:intro:b\r\n$14\r\npubsub:intro:a\r\n",
+        "{}",
+        String::from_utf8_lossy(&channels)
+    );
+    assert_eq!(
+        observer
+            .call(&["pubsub", "numsub", "pubsub:intro:a", "pubsub:intro:b", "pubsub:intro:c"])
+            .await,
+        b"*6\r\n$14\r\npubsub:intro:a\r\n:2\r\n$14\r\npubsub:intro:b\r\n:1\r\n$14\r\npubsub:intro:c\r\n:0\r\n"
+    );
+
+    let numpat = |reply: Vec<u8>| -> i64 {
+        String::from_utf8_lossy(&reply[1..reply.len() - 2])
+            .parse()
+            .unwrap()
+    };
+    let before = numpat(observer.call(&["pubsub", "numpat"]).await);
+    second.call(&["psubscribe", "pubsub:intro:*"]).await;
+    assert_eq!(
+        numpat(observer.call(&["pubsub", "numpat"]).await),
+        before + 1
+    );
+    second.call(&["punsubscribe", "pubsub:intro:*"]).await;
+    assert_eq!(numpat(observer.call(&["pubsub", "numpat"]).await), before);
+
+    // A channel whose last subscriber leaves isn't active any more
+    first.call(&["unsubscribe", "pubsub:intro:b"]).await;
+    assert_eq!(
+        observer
+            .call(&["pubsub", "channels", "pubsub:intro:b"])
+            .await,
+        b"*0\r\n"
+    );
+}
+
+#[tokio::test]
+async fn messages_are_push_frames_over_resp3_and_arrays_over_resp2() {
+    let mut resp2 = Session::new();
+    let mut resp3 = Session::new();
+    resp3.call(&["hello", "3"]).await;
+    assert_eq!(
+        resp2.call(&["subscribe", "pubsub:proto"]).await,
+        b"*3\r\n$9\r\nsubscribe\r\n$12\r\npubsub:proto\r\n:1\r\n"
+    );
+    assert_eq!(
+        resp3.call(&["subscribe", "pubsub:proto"]).await,
+        b">3\r\n$9\r\nsubscribe\r\n$12\r\npubsub:proto\r\n:1\r\n"
+    );
+
+    let mut publisher = Session::new();
+    assert_eq!(
+        publisher.call(&["publish", "pubsub:proto", "hi"]).await,
+        b":2\r\n"
+    );
+    // Pushes arrive ahead of the reply to whatever the subscriber runs next
+    assert_eq!(
+        resp2.call(&["ping"]).await,
+        b"*3\r\n$7\r\nmessage\r\n$12\r\npubsub:proto\r\n$2\r\nhi\r\n*2\r\n$4\r\npong\r\n$0\r\n\r\n"
+    );
+    assert_eq!(
+        resp3.call(&["ping"]).await,
+        b">3\r\n$7\r\nmessage\r\n$12\r\npubsub:proto\r\n$2\r\nhi\r\n+PONG\r\n"
+    );
+    assert_eq!(
+        resp3.call(&["unsubscribe", "pubsub:proto"]).await,
+        b">3\r\n$11\r\nunsubscribe\r\n$12\r\npubsub:proto\r\n:0\r\n"
+    );
+}
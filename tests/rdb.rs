@@ -0,0 +1,145 @@
+//! The RDB file of a server process: what SAVE writes is what a restart loads
+mod common;
+
+use codecrafters_redis::resp::Frame;
+use common::{bulk, eventually, info_field, ok, path_arg, temp_dir, Server};
+
+#[test]
+fn a_restart_loads_the_saved_dataset() {
+    let dir = temp_dir("rdb-restart");
+    let args = ["--dir", path_arg(&dir), "--save", ""];
+    let server = Server::start(&args);
+    let mut client = server.client();
+    let large = "x".repeat(100_000);
+    for command in [
+        &["set", "string", "v"][..],
+        &["set", "large", &large],
+        &["set", "number", "12345"],
+        &["rpush", "list", "a", "b", "c"],
+        &["sadd", "set", "1", "2", "3"],
+        &["hset", "hash", "f", "v"],
+        &["zadd", "zset", "1.5", "m", "-inf", "n"],
+        &["set", "volatile", "v", "EX", "1000"],
+        &["select", "2"],
+        &["set", "string", "in db 2"],
+    ] {
+        assert!(
+            !matches!(client.call(command), Frame::Error(_)),
+            "{:?}",
+            command
+        );
+    }
+    client.call(&["select", "0"]);
+    let expires = client.call(&["expiretime", "volatile"]);
+    assert_eq!(client.call(&["save"]), ok());
+    let port = server.port;
+    drop(server);
+
+    let server = Server::start_on(port, &args);
+    let mut client = server.client();
+    assert_eq!(client.call(&["get", "string"]), bulk("v"));
+    assert_eq!(client.call(&["get", "large"]), bulk(&large));
+    assert_eq!(client.call(&["get", "number"]), bulk("12345"));
+    assert_eq!(
+        client.call(&["lrange", "list", "0", "-1"]),
+        Frame::Array(Some(vec![bulk("a"), bulk("b"), bulk("c")]))
+    );
+    assert_eq!(client.call(&["sintercard", "1", "set"]), Frame::Integer(3));
+    assert_eq!(client.call(&["hget", "hash", "f"]), bulk("v"));
+    assert_eq!(client.call(&["zscore", "zset", "m"]), bulk("1.5"));
+    assert_eq!(client.call(&["zscore", "zset", "n"]), bulk("-inf"));
+    assert_eq!(client.call(&["expiretime", "volatile"]), expires);
+    client.call(&["select", "2"]);
+    assert_eq!(client.call(&["get", "string"]), bulk("in db 2"));
+}
+
+#[test]
+fn debug_reload_round_trips_every_type() {
+    let dir = temp_dir("rdb-debug-reload");
+    let server = Server::start(&["--dir", path_arg(&dir), "--save", ""]);
+    let mut client = server.client();
+    for command in [
+        &["set", "string", "v\"quoted\""][..],
+        &["set", "number", "12345"],
+        &["rpush", "list", "a", "b", "c"],
+        &["sadd", "set", "1"],
+        &["hset", "hash", "f", "v"],
+        &["zadd", "zset", "1.5", "m", "-inf", "n"],
+        &["set", "volatile", "v", "EX", "1000"],
+    ] {
+        assert!(
+            !matches!(client.call(command), Frame::Error(_)),
+            "{:?}",
+            command
+        );
+    }
+    let expected = concat!(
+        r#"{"hash":{"type":"hash","value":{"f":"v"}},"#,
+        r#""list":{"type":"list","value":["a","b","c"]},"#,
+        r#""number":{"type":"string","value":"12345"},"#,
+        r#""set":{"type":"set","value":["1"]},"#,
+        r#""string":{"type":"string","value":"v\"quoted\""},"#,
+        r#""volatile":{"type":"string","value":"v"},"#,
+        r#""zset":{"type":"zset","value":[["n","-inf"],["m",1.5]]}}"#,
+    );
+    assert_eq!(client.call(&["debug", "dumpkeys"]), bulk(expected));
+    let expires = client.call(&["expiretime", "volatile"]);
+
+    assert_eq!(client.call(&["debug", "reload"]), ok());
+    assert_eq!(client.call(&["debug", "dumpkeys"]), bulk(expected));
+    assert_eq!(client.call(&["expiretime", "volatile"]), expires);
+    assert!(dir.join("dump.rdb").exists());
+}
+
+#[test]
+fn a_save_rule_snapshots_after_enough_changes() {
+    let dir = temp_dir("rdb-save-rule");
+    let args = ["--dir", path_arg(&dir), "--save", "1 1"];
+    let server = Server::start(&args);
+    let mut client = server.client();
+    assert!(!dir.join("dump.rdb").exists());
+    assert_eq!(client.call(&["set", "saved", "by the rule"]), ok());
+    eventually("a snapshot after one change and a second", || {
+        dir.join("dump.rdb").exists()
+            && info_field(&mut client, "persistence", "rdb_changes_since_last_save") == "0"
+    });
+    let port = server.port;
+    drop(server);
+
+    let server = Server::start_on(port, &args);
+    let mut client = server.client();
+    assert_eq!(client.call(&["get", "saved"]), bulk("by the rule"));
+
+    // An empty save disables the rules
+    assert_eq!(client.call(&["config", "set", "save", ""]), ok());
+    assert_eq!(client.call(&["set", "unsaved", "v"]), ok());
+    // A write that changes nothing isn't a change
+    assert_eq!(client.call(&["del", "missing"]), Frame::Integer(0));
+    std::thread::sleep(std::time::Duration::from_millis(2_500));
+    assert_eq!(
+        info_field(&mut client, "persistence", "rdb_changes_since_last_save"),
+        "1"
+    );
+}
+
+#[test]
+fn save_rules_are_read_from_the_config_file() {
+    let dir = temp_dir("rdb-config-file");
+    let conf = dir.join("redis.conf");
+    std::fs::write(
+        &conf,
+        "# Only the last two rules count\nsave 900 1\nsave \"\"\nsave 1 1\nsave 60 100\n\n\
+         dbfilename \"from-config.rdb\"\n",
+    )
+    .unwrap();
+    let server = Server::start(&[path_arg(&conf), "--dir", path_arg(&dir)]);
+    let mut client = server.client();
+    assert_eq!(
+        client.call(&["config", "get", "save"]),
+        Frame::Array(Some(vec![bulk("save"), bulk("1 1 60 100")]))
+    );
+    assert_eq!(client.call(&["set", "saved", "by the rule"]), ok());
+    eventually("a snapshot after one change and a second", || {
+        dir.join("from-config.rdb").exists()
+    });
+}
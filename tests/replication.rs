@@ -0,0 +1,152 @@
+//! A replica process syncing from a master process
+mod common;
+
+use codecrafters_redis::resp::Frame;
+use common::{bulk, eventually, info_field, ok, path_arg, temp_dir, Server};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+fn start_pair(name: &str) -> (Server, Server) {
+    let master = Server::start(&["--dir", path_arg(&temp_dir(&format!("{}-master", name)))]);
+    let replica = Server::start(&["--dir", path_arg(&temp_dir(&format!("{}-replica", name)))]);
+    (master, replica)
+}
+
+#[test]
+fn a_new_replica_gets_the_dataset_and_later_writes() {
+    let (master, replica) = start_pair("repl-sync");
+    let mut writer = master.client();
+    assert_eq!(writer.call(&["set", "before", "1"]), ok());
+    writer.call(&["rpush", "list", "a", "b"]);
+
+    let mut reader = replica.client();
+    let port = master.port.to_string();
+    assert_eq!(
+        reader.call(&["replicaof", "127.0.0.1", &port]),
+        Frame::SimpleString("OK Background replication started".into())
+    );
+    eventually("the replica never got the dataset", || {
+        reader.call(&["get", "before"]) == bulk("1")
+    });
+    assert_eq!(
+        reader.call(&["lrange", "list", "0", "-1"]),
+        Frame::Array(Some(vec![bulk("a"), bulk("b")]))
+    );
+
+    assert_eq!(writer.call(&["set", "after", "2"]), ok());
+    writer.call(&["select", "3"]);
+    writer.call(&["set", "elsewhere", "3"]);
+    eventually("the replica never got the later SET", || {
+        reader.call(&["get", "after"]) == bulk("2")
+    });
+    reader.call(&["select", "3"]);
+    eventually("the replica never got the SET in db 3", || {
+        reader.call(&["get", "elsewhere"]) == bulk("3")
+    });
+}
+
+#[test]
+fn writes_during_the_sync_are_not_lost() {
+    let (master, replica) = start_pair("repl-race");
+    let port = master.port;
+    // A big list in a later database keeps the snapshot going after it has read the
+    // counter's database
+    let mut loader = master.client();
+    loader.call(&["select", "5"]);
+    let elements: Vec<String> = (0..1000).map(|i| i.to_string()).collect();
+    let mut push = vec!["rpush", "big"];
+    push.extend(elements.iter().map(String::as_str));
+    for _ in 0..300 {
+        loader.call(&push);
+    }
+    // Keep writing until the replica has synced, so some writes land as it is snapshotted
+    let stop = Arc::new(AtomicBool::new(false));
+    let writing = {
+        let stop = stop.clone();
+        std::thread::spawn(move || {
+            let mut writer = common::Client::connect(port);
+            while !stop.load(Ordering::Relaxed) {
+                writer.call(&["incr", "counter"]);
+            }
+            writer.call(&["get", "counter"])
+        })
+    };
+    let mut reader = replica.client();
+    reader.call(&["replicaof", "127.0.0.1", &port.to_string()]);
+    reader.call(&["select", "5"]);
+    eventually("the replica never synced", || {
+        reader.call(&["type", "big"]) == Frame::SimpleString("list".into())
+    });
+    stop.store(true, Ordering::Relaxed);
+    let written = writing.join().unwrap();
+    reader.call(&["select", "0"]);
+    eventually("the replica lost writes made during its sync", || {
+        reader.call(&["get", "counter"]) == written
+    });
+}
+
+fn error(message: &str) -> Frame {
+    Frame::Error(message.to_string())
+}
+
+#[test]
+fn a_standalone_server_reports_itself_a_master_with_a_replid() {
+    let server = Server::start(&[]);
+    let mut client = server.client();
+    assert_eq!(info_field(&mut client, "replication", "role"), "master");
+    assert_eq!(
+        info_field(&mut client, "replication", "connected_slaves"),
+        "0"
+    );
+    let replid = info_field(&mut client, "replication", "master_replid");
+    assert_eq!(replid.len(), 40, "{}", replid);
+    assert!(replid
+        .bytes()
+        .all(|b| b.is_ascii_hexdigit() && !b.is_ascii_uppercase()));
+    let offset: u64 = info_field(&mut client, "replication", "master_repl_offset")
+        .parse()
+        .unwrap();
+
+    assert_eq!(client.call(&["set", "k", "v"]), ok());
+    let later: u64 = info_field(&mut client, "replication", "master_repl_offset")
+        .parse()
+        .unwrap();
+    assert!(later > offset, "{} after {}", later, offset);
+
+    assert_eq!(client.call(&["debug", "change-repl-id"]), ok());
+    let changed = info_field(&mut client, "replication", "master_replid");
+    assert_eq!(changed.len(), 40);
+    assert_ne!(changed, replid);
+}
+
+#[test]
+fn replicaof_no_one_turns_a_replica_back_into_a_master() {
+    let (master, replica) = start_pair("repl-no-one");
+    let mut writer = master.client();
+    writer.call(&["set", "k", "from master"]);
+    let mut reader = replica.client();
+    let port = master.port.to_string();
+    reader.call(&["slaveof", "127.0.0.1", &port]);
+    eventually("the replica never synced", || {
+        reader.call(&["get", "k"]) == bulk("from master")
+    });
+    assert_eq!(info_field(&mut reader, "replication", "role"), "slave");
+    assert_eq!(
+        reader.call(&["set", "k", "v"]),
+        error("READONLY You can't write against a read only replica.")
+    );
+    assert_eq!(
+        reader.call(&["replicaof", "127.0.0.1", "x"]),
+        error("ERR Invalid master port")
+    );
+
+    assert_eq!(reader.call(&["replicaof", "no", "one"]), ok());
+    assert_eq!(info_field(&mut reader, "replication", "role"), "master");
+    // The data it had is kept, and later writes on the old master no longer arrive
+    assert_eq!(reader.call(&["get", "k"]), bulk("from master"));
+    assert_eq!(reader.call(&["set", "k", "own"]), ok());
+    writer.call(&["set", "later", "1"]);
+    std::thread::sleep(std::time::Duration::from_millis(200));
+    assert_eq!(reader.call(&["get", "later"]), Frame::BulkString(None));
+    assert_eq!(reader.call(&["get", "k"]), bulk("own"));
+}
@@ -0,0 +1,197 @@
+//! Commands about the server itself, run through a socketless session, and through a
+//! server process for its startup settings and for settings that change how it runs
+mod common;
+
+use codecrafters_redis::resp::Frame;
+use codecrafters_redis::Session;
+use common::{bulk, ok, Server};
+use std::time::{Duration, Instant};
+
+#[test]
+fn debug_sleep_holds_up_other_clients_only_when_emulating_a_single_thread() {
+    let server = Server::start(&[]);
+    let mut sleeper = server.client();
+    let mut other = server.client();
+    for (emulate, held_up) in [("yes", true), ("no", false)] {
+        assert_eq!(
+            sleeper.call(&["config", "set", "emulate-single-thread", emulate]),
+            ok()
+        );
+        sleeper.send(&["debug", "sleep", "1"]);
+        // Give the sleep time to start before pinging
+        std::thread::sleep(Duration::from_millis(200));
+        let started = Instant::now();
+        assert_eq!(other.call(&["ping"]), Frame::SimpleString("PONG".into()));
+        let waited = started.elapsed();
+        assert_eq!(sleeper.read(), ok());
+        if held_up {
+            assert!(waited >= Duration::from_millis(600), "{:?}", waited);
+        } else {
+            assert!(waited < Duration::from_millis(500), "{:?}", waited);
+        }
+    }
+}
+
+#[test]
+fn debug_sleep_in_a_transaction_holds_up_other_clients_when_exec_runs_it() {
+    let server = Server::start(&[]);
+    let mut sleeper = server.client();
+    let mut other = server.client();
+    assert_eq!(
+        sleeper.call(&["config", "set", "emulate-single-thread", "yes"]),
+        ok()
+    );
+    assert_eq!(sleeper.call(&["multi"]), ok());
+    assert_eq!(
+        sleeper.call(&["debug", "sleep", "1"]),
+        Frame::SimpleString("QUEUED".into())
+    );
+    sleeper.send(&["exec"]);
+    std::thread::sleep(Duration::from_millis(200));
+    let started = Instant::now();
+    assert_eq!(other.call(&["ping"]), Frame::SimpleString("PONG".into()));
+    let waited = started.elapsed();
+    assert_eq!(sleeper.read(), Frame::Array(Some(vec![ok()])));
+    assert!(waited >= Duration::from_millis(600), "{:?}", waited);
+}
+
+#[test]
+fn config_get_gives_parameters_in_their_canonical_form() {
+    let server = Server::start(&["--databases", "4", "--dir", "."]);
+    let mut client = server.client();
+    let get = |client: &mut common::Client, name: &str| -> Frame {
+        let Frame::Array(Some(mut pair)) = client.call(&["config", "get", name]) else {
+            panic!("CONFIG GET {} didn't reply with an array", name);
+        };
+        assert_eq!(pair.len(), 2, "{:?}", pair);
+        assert_eq!(pair[0], bulk(name));
+        pair.pop().unwrap()
+    };
+    // Unset parameters have their defaults
+    assert_eq!(get(&mut client, "maxmemory"), bulk("0"));
+    assert_eq!(get(&mut client, "databases"), bulk("4"));
+    // Memory sizes are replied in bytes, whatever unit they were set in
+    for (value, bytes) in [
+        ("100mb", "104857600"),
+        ("1gb", "1073741824"),
+        ("2k", "2000"),
+    ] {
+        assert_eq!(client.call(&["config", "set", "maxmemory", value]), ok());
+        assert_eq!(get(&mut client, "maxmemory"), bulk(bytes), "{}", value);
+    }
+    // A relative dir is resolved against the working directory
+    let cwd = std::env::current_dir().unwrap().canonicalize().unwrap();
+    assert_eq!(get(&mut client, "dir"), bulk(cwd.to_str().unwrap()));
+}
+
+#[test]
+fn config_get_matches_glob_patterns_and_config_set_refuses_unknown_parameters() {
+    let server = Server::start(&[]);
+    let mut client = server.client();
+    let names = |reply: Frame| -> Vec<String> {
+        let Frame::Array(Some(pairs)) = reply else {
+            panic!("CONFIG GET replied with {:?}", reply);
+        };
+        pairs
+            .chunks(2)
+            .map(|pair| match &pair[0] {
+                Frame::BulkString(Some(name)) => String::from_utf8_lossy(name).into_owned(),
+                other => panic!("CONFIG GET replied with the name {:?}", other),
+            })
+            .collect()
+    };
+
+    let max = names(client.call(&["config", "get", "*max*"]));
+    assert_eq!(max.len(), 20, "{:?}", max);
+    assert!(max.iter().all(|name| name.contains("max")), "{:?}", max);
+    for name in [
+        "maxmemory",
+        "maxclients",
+        "proto-max-bulk-len",
+        "slowlog-max-len",
+    ] {
+        assert!(max.iter().any(|n| n == name), "{} is missing", name);
+    }
+    // Several patterns, each parameter once
+    assert_eq!(
+        names(client.call(&["config", "get", "maxmemory", "MAXMEMORY*", "dbfil?name"])),
+        ["maxmemory", "maxmemory-policy", "dbfilename"]
+    );
+    assert_eq!(
+        client.call(&["config", "get", "no-such-*"]),
+        Frame::Array(Some(vec![]))
+    );
+
+    assert_eq!(
+        client.call(&["config", "set", "no-such-parameter", "1"]),
+        Frame::Error(
+            "ERR Unknown option or number of arguments for CONFIG SET - 'no-such-parameter'".into()
+        )
+    );
+}
+
+#[test]
+fn config_set_dir_takes_only_an_existing_directory_and_canonicalizes_it() {
+    let dir = common::temp_dir("config-set-dir");
+    std::fs::create_dir(dir.join("sub")).unwrap();
+    std::fs::write(dir.join("file"), "").unwrap();
+    let server = Server::start(&["--dir", common::path_arg(&dir), "--save", ""]);
+    let mut client = server.client();
+    let dir = dir.canonicalize().unwrap();
+    let get_dir = |client: &mut common::Client| client.call(&["config", "get", "dir"]);
+    let before = get_dir(&mut client);
+
+    for (value, reason) in [
+        ("missing", "No such file or directory"),
+        ("file", "Not a directory"),
+    ] {
+        let path = dir.join(value);
+        assert_eq!(
+            client.call(&["config", "set", "dir", path.to_str().unwrap()]),
+            Frame::Error(format!(
+                "ERR CONFIG SET failed (possibly related to argument 'dir') - {}",
+                reason
+            )),
+        );
+        assert_eq!(get_dir(&mut client), before);
+    }
+
+    let roundabout = dir.join("sub").join("..").join("sub");
+    assert_eq!(
+        client.call(&["config", "set", "dir", roundabout.to_str().unwrap()]),
+        ok()
+    );
+    let sub = dir.join("sub");
+    assert_eq!(
+        get_dir(&mut client),
+        Frame::Array(Some(vec![bulk("dir"), bulk(sub.to_str().unwrap())]))
+    );
+    assert_eq!(client.call(&["save"]), ok());
+    assert!(sub.join("dump.rdb").exists());
+}
+
+fn text(reply: Vec<u8>) -> String {
+    String::from_utf8(reply).unwrap()
+}
+
+#[tokio::test]
+async fn cluster_reports_a_standalone_server() {
+    let mut session = Session::new();
+    let info = text(session.call(&["cluster", "info"]).await);
+    assert!(info.starts_with('$'), "{}", info);
+    assert!(info.contains("\r\ncluster_state:ok\r\n"), "{}", info);
+    assert!(info.contains("\r\ncluster_enabled:0\r\n"), "{}", info);
+    assert_eq!(session.call(&["cluster", "slots"]).await, b"*0\r\n");
+    assert_eq!(session.call(&["cluster", "nodes"]).await, b"$0\r\n\r\n");
+
+    // Its node id is the run id, the same for the life of the server
+    let myid = text(session.call(&["cluster", "myid"]).await);
+    let id = myid.strip_prefix("$40\r\n").unwrap().trim_end();
+    assert!(id.bytes().all(|b| b.is_ascii_hexdigit()), "{}", myid);
+    assert_eq!(text(session.call(&["cluster", "myid"]).await), myid);
+
+    assert_eq!(
+        session.call(&["cluster", "info", "extra"]).await,
+        b"-ERR wrong number of arguments for 'cluster info'\r\n"
+    );
+}
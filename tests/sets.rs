@@ -0,0 +1,215 @@
+//! Set commands run through a socketless session
+use codecrafters_redis::resp::parser::FrameParser;
+use codecrafters_redis::resp::Frame;
+use codecrafters_redis::Session;
+
+/// The members of an array reply, in the order given
+fn members(reply: &[u8]) -> Vec<Vec<u8>> {
+    let mut parser = FrameParser::new();
+    parser.feed(reply);
+    match parser.parse() {
+        Ok(Some(Frame::Array(Some(items)))) => items
+            .into_iter()
+            .map(|item| match item {
+                Frame::BulkString(Some(member)) => member,
+                other => panic!("expected a member, got {:?}", other),
+            })
+            .collect(),
+        other => panic!("expected an array, got {:?}", other),
+    }
+}
+
+#[tokio::test]
+async fn spop_deletes_the_set_it_empties() {
+    let mut session = Session::new();
+    session.call(&["sadd", "sets:pop", "a", "b", "c"]).await;
+    let mut popped = members(&session.call(&["spop", "sets:pop", "2"]).await);
+    assert_eq!(popped.len(), 2);
+    assert_eq!(
+        session.call(&["sintercard", "1", "sets:pop"]).await,
+        b":1\r\n"
+    );
+    let last = session.call(&["spop", "sets:pop"]).await;
+    popped.push(last[4..last.len() - 2].to_vec());
+    popped.sort();
+    assert_eq!(popped, [b"a", b"b", b"c"]);
+    assert_eq!(session.call(&["type", "sets:pop"]).await, b"+none\r\n");
+    assert_eq!(session.call(&["spop", "sets:pop"]).await, b"$-1\r\n");
+    assert_eq!(session.call(&["spop", "sets:pop", "3"]).await, b"*0\r\n");
+
+    // A count past the size takes everything
+    session.call(&["sadd", "sets:pop", "x", "y"]).await;
+    assert_eq!(
+        members(&session.call(&["spop", "sets:pop", "10"]).await).len(),
+        2
+    );
+    assert_eq!(session.call(&["type", "sets:pop"]).await, b"+none\r\n");
+}
+
+#[tokio::test]
+async fn srandmember_counts_choose_distinct_or_repeated_members() {
+    let mut session = Session::new();
+    session.call(&["sadd", "sets:rand", "a", "b", "c"]).await;
+
+    // A positive count gives distinct members, at most the whole set
+    let mut distinct = members(&session.call(&["srandmember", "sets:rand", "2"]).await);
+    distinct.sort();
+    distinct.dedup();
+    assert_eq!(distinct.len(), 2);
+    let mut all = members(&session.call(&["srandmember", "sets:rand", "10"]).await);
+    all.sort();
+    assert_eq!(all, [b"a", b"b", b"c"]);
+
+    // A negative one gives exactly that many, repeating members
+    let repeated = members(&session.call(&["srandmember", "sets:rand", "-20"]).await);
+    assert_eq!(repeated.len(), 20);
+    assert!(repeated
+        .iter()
+        .all(|m| [&b"a"[..], b"b", b"c"].contains(&m.as_slice())));
+
+    // Nothing is removed
+    assert_eq!(
+        session.call(&["sintercard", "1", "sets:rand"]).await,
+        b":3\r\n"
+    );
+    assert_eq!(
+        session.call(&["srandmember", "sets:none"]).await,
+        b"$-1\r\n"
+    );
+    assert_eq!(
+        session.call(&["srandmember", "sets:none", "-5"]).await,
+        b"*0\r\n"
+    );
+}
+
+#[tokio::test]
+async fn intercard_counts_up_to_the_limit() {
+    let mut session = Session::new();
+    session
+        .call(&["sadd", "sets:card:a", "1", "2", "3", "4", "x"])
+        .await;
+    session
+        .call(&["sadd", "sets:card:b", "2", "3", "4", "x", "y"])
+        .await;
+    session
+        .call(&["zadd", "sets:card:z1", "1", "a", "2", "b", "3", "c"])
+        .await;
+    session
+        .call(&["zadd", "sets:card:z2", "9", "b", "8", "c", "7", "d"])
+        .await;
+
+    for (command, a, b) in [
+        ("sintercard", "sets:card:a", "sets:card:b"),
+        ("zintercard", "sets:card:z1", "sets:card:z2"),
+    ] {
+        let full = if command == "sintercard" { 4 } else { 2 };
+        let count = |reply: Vec<u8>| String::from_utf8(reply).unwrap();
+        assert_eq!(
+            count(session.call(&[command, "2", a, b]).await),
+            format!(":{}\r\n", full)
+        );
+        assert_eq!(
+            session.call(&[command, "2", a, b, "LIMIT", "1"]).await,
+            b":1\r\n"
+        );
+        // LIMIT 0 means no limit, and a limit past the count changes nothing
+        for limit in ["0", "100"] {
+            assert_eq!(
+                count(session.call(&[command, "2", a, b, "LIMIT", limit]).await),
+                format!(":{}\r\n", full)
+            );
+        }
+        assert_eq!(
+            session.call(&[command, "2", a, "sets:card:missing"]).await,
+            b":0\r\n"
+        );
+
+        assert_eq!(
+            session.call(&[command, "0", a]).await,
+            b"-ERR numkeys should be greater than 0\r\n"
+        );
+        assert_eq!(
+            session.call(&[command, "3", a, b]).await,
+            b"-ERR Number of keys can't be greater than number of args\r\n"
+        );
+        assert_eq!(
+            session.call(&[command, "1", a, b]).await,
+            b"-ERR syntax error\r\n"
+        );
+        assert_eq!(
+            session.call(&[command, "2", a, b, "LIMIT", "-1"]).await,
+            b"-ERR LIMIT can't be negative\r\n"
+        );
+    }
+
+    session.call(&["set", "sets:card:string", "v"]).await;
+    let wrongtype = b"-WRONGTYPE Operation against a key holding the wrong kind of value\r\n";
+    assert_eq!(
+        session
+            .call(&["sintercard", "2", "sets:card:a", "sets:card:string"])
+            .await,
+        wrongtype
+    );
+    assert_eq!(
+        session
+            .call(&["zintercard", "2", "sets:card:z1", "sets:card:string"])
+            .await,
+        wrongtype
+    );
+    assert_eq!(
+        session
+            .call(&["sintercard", "2", "sets:card:a", "sets:card:z1"])
+            .await,
+        wrongtype
+    );
+}
+
+#[tokio::test]
+async fn a_non_integer_member_moves_a_set_off_intset() {
+    let mut session = Session::new();
+    session.call(&["sadd", "sets:enc", "1", "2", "-3"]).await;
+    assert_eq!(
+        session.call(&["object", "encoding", "sets:enc"]).await,
+        b"$6\r\nintset\r\n"
+    );
+    // Integers past set-max-listpack-entries still fit an intset
+    let many: Vec<String> = (0..200).map(|i| i.to_string()).collect();
+    let mut command = vec!["sadd".to_string(), "sets:enc".to_string()];
+    command.extend(many);
+    session.call(&command).await;
+    assert_eq!(
+        session.call(&["object", "encoding", "sets:enc"]).await,
+        b"$6\r\nintset\r\n"
+    );
+
+    // Members that only look like integers don't
+    for member in ["x", "01", "1.5", "99999999999999999999"] {
+        session.call(&["sadd", "sets:small", "1", member]).await;
+        assert_eq!(
+            session.call(&["object", "encoding", "sets:small"]).await,
+            b"$8\r\nlistpack\r\n",
+            "{}",
+            member
+        );
+        session.call(&["del", "sets:small"]).await;
+    }
+
+    // Past 128 members, or with one over 64 bytes, a listpack set becomes a hashtable
+    session.call(&["sadd", "sets:enc", "x"]).await;
+    assert_eq!(
+        session.call(&["object", "encoding", "sets:enc"]).await,
+        b"$9\r\nhashtable\r\n"
+    );
+    session
+        .call(&["sadd", "sets:long", "a", &"v".repeat(64)])
+        .await;
+    assert_eq!(
+        session.call(&["object", "encoding", "sets:long"]).await,
+        b"$8\r\nlistpack\r\n"
+    );
+    session.call(&["sadd", "sets:long", &"v".repeat(65)]).await;
+    assert_eq!(
+        session.call(&["object", "encoding", "sets:long"]).await,
+        b"$9\r\nhashtable\r\n"
+    );
+}
@@ -0,0 +1,133 @@
+//! The slow log of a server process, whose thresholds are global settings
+mod common;
+
+use codecrafters_redis::resp::Frame;
+use common::{bulk, ok, Server};
+
+#[test]
+fn resp3_clients_get_the_max_len_as_an_attribute() {
+    let server = Server::start(&[]);
+    let mut resp2 = server.client();
+    let mut resp3 = server.client();
+    resp3.call(&["hello", "3"]);
+    assert_eq!(resp2.call(&["config", "set", "slowlog-max-len", "5"]), ok());
+    resp2.call(&["slowlog", "reset"]);
+
+    assert_eq!(
+        resp3.call(&["slowlog", "get"]),
+        Frame::Attribute(Some(vec![(bulk("slowlog-max-len"), Frame::Integer(5))]))
+    );
+    assert_eq!(resp3.read(), Frame::Array(Some(Vec::new())));
+    // RESP2 has no attributes, so the entries come alone
+    assert_eq!(
+        resp2.call(&["slowlog", "get"]),
+        Frame::Array(Some(Vec::new()))
+    );
+    assert_eq!(resp2.call(&["ping"]), Frame::SimpleString("PONG".into()));
+}
+
+/// The entries of a SLOWLOG GET reply
+fn entries(reply: Frame) -> Vec<Vec<Frame>> {
+    let Frame::Array(Some(entries)) = reply else {
+        panic!("expected an array, got {:?}", reply);
+    };
+    entries
+        .into_iter()
+        .map(|entry| match entry {
+            Frame::Array(Some(fields)) => fields,
+            other => panic!("expected an entry, got {:?}", other),
+        })
+        .collect()
+}
+
+#[test]
+fn commands_over_the_threshold_are_logged_newest_first() {
+    let server = Server::start(&[]);
+    let mut client = server.client();
+    assert_eq!(
+        client.call(&["config", "set", "slowlog-log-slower-than", "50000"]),
+        ok()
+    );
+    client.call(&["slowlog", "reset"]);
+    assert_eq!(client.call(&["client", "setname", "sleepy"]), ok());
+    client.call(&["set", "fast", "v"]);
+    assert_eq!(client.call(&["debug", "sleep", "0.1"]), ok());
+    client.call(&["get", "fast"]);
+    assert_eq!(client.call(&["slowlog", "len"]), Frame::Integer(1));
+
+    let logged = entries(client.call(&["slowlog", "get"]));
+    assert_eq!(logged.len(), 1);
+    let entry = &logged[0];
+    assert!(matches!(entry[1], Frame::Integer(t) if t > 1_600_000_000));
+    assert!(
+        matches!(entry[2], Frame::Integer(us) if us >= 100_000),
+        "{:?}",
+        entry
+    );
+    assert_eq!(
+        entry[3],
+        Frame::Array(Some(vec![bulk("debug"), bulk("sleep"), bulk("0.1")]))
+    );
+    assert!(matches!(&entry[4], Frame::BulkString(Some(addr)) if addr.starts_with(b"127.0.0.1:")));
+    assert_eq!(entry[5], bulk("sleepy"));
+
+    // With a threshold of 0 everything is logged, up to slowlog-max-len entries
+    client.call(&["config", "set", "slowlog-log-slower-than", "0"]);
+    client.call(&["config", "set", "slowlog-max-len", "3"]);
+    for value in ["1", "2", "3", "4"] {
+        client.call(&["set", "k", value]);
+    }
+    assert_eq!(client.call(&["slowlog", "len"]), Frame::Integer(3));
+    let logged = entries(client.call(&["slowlog", "get", "2"]));
+    assert_eq!(logged.len(), 2);
+    assert_eq!(
+        logged[0][3],
+        Frame::Array(Some(vec![bulk("slowlog"), bulk("len")]))
+    );
+    assert_eq!(
+        logged[1][3],
+        Frame::Array(Some(vec![bulk("set"), bulk("k"), bulk("4")]))
+    );
+    let (Frame::Integer(newer), Frame::Integer(older)) = (&logged[0][0], &logged[1][0]) else {
+        panic!("expected ids");
+    };
+    assert_eq!(*newer, older + 1);
+
+    assert_eq!(client.call(&["slowlog", "reset"]), ok());
+    client.call(&["config", "set", "slowlog-log-slower-than", "-1"]);
+    client.call(&["slowlog", "reset"]);
+    client.call(&["debug", "sleep", "0.05"]);
+    assert_eq!(client.call(&["slowlog", "len"]), Frame::Integer(0));
+    assert!(matches!(
+        client.call(&["slowlog", "get", "-2"]),
+        Frame::Error(_)
+    ));
+}
+
+#[test]
+fn long_commands_are_logged_truncated() {
+    let server = Server::start(&[]);
+    let mut client = server.client();
+    client.call(&["config", "set", "slowlog-log-slower-than", "0"]);
+    client.call(&["slowlog", "reset"]);
+    let long = "v".repeat(200);
+    client.call(&["set", "k", &long]);
+    let keys: Vec<String> = (0..40).map(|i| format!("k{}", i)).collect();
+    let mut del = vec!["del"];
+    del.extend(keys.iter().map(String::as_str));
+    client.call(&del);
+
+    let logged = entries(client.call(&["slowlog", "get", "-1"]));
+    let Frame::Array(Some(args)) = &logged[0][3] else {
+        panic!("expected arguments");
+    };
+    assert_eq!(args.len(), 32);
+    assert_eq!(args[31], bulk("... (10 more arguments)"));
+    let Frame::Array(Some(args)) = &logged[1][3] else {
+        panic!("expected arguments");
+    };
+    assert_eq!(
+        args[2],
+        bulk(&format!("{}... (72 more bytes)", "v".repeat(128)))
+    );
+}
@@ -0,0 +1,411 @@
+//! Sorted set commands run through a socketless session
+use codecrafters_redis::Session;
+
+fn bulk(value: &str) -> Vec<u8> {
+    format!("${}\r\n{}\r\n", value.len(), value).into_bytes()
+}
+
+#[tokio::test]
+async fn scores_are_doubles_over_resp3_and_bulk_strings_over_resp2() {
+    let mut session = Session::new();
+    session
+        .call(&["zadd", "zsets:double", "1.5", "a", "inf", "b", "-inf", "c"])
+        .await;
+    session.call(&["set", "zsets:float", "10.5"]).await;
+    assert_eq!(
+        session.call(&["zscore", "zsets:double", "a"]).await,
+        bulk("1.5")
+    );
+    assert_eq!(
+        session.call(&["incrbyfloat", "zsets:float", "0.25"]).await,
+        bulk("10.75")
+    );
+
+    session.call(&["hello", "3"]).await;
+    for (member, reply) in [
+        ("a", &b",1.5\r\n"[..]),
+        ("b", b",inf\r\n"),
+        ("c", b",-inf\r\n"),
+        ("missing", b"$-1\r\n"),
+    ] {
+        assert_eq!(
+            session.call(&["zscore", "zsets:double", member]).await,
+            reply,
+            "{}",
+            member
+        );
+    }
+    assert_eq!(
+        session.call(&["zincrby", "zsets:double", "1", "a"]).await,
+        b",2.5\r\n"
+    );
+    assert_eq!(
+        session
+            .call(&["zmscore", "zsets:double", "a", "missing"])
+            .await,
+        b"*2\r\n,2.5\r\n$-1\r\n"
+    );
+    // INCRBYFLOAT replies with the string it stored, as Redis does
+    assert_eq!(
+        session.call(&["incrbyfloat", "zsets:float", "0.25"]).await,
+        bulk("11")
+    );
+}
+
+#[tokio::test]
+async fn scores_far_from_one_are_written_with_an_exponent() {
+    let mut session = Session::new();
+    session
+        .call(&[
+            "zadd",
+            "zsets:exp",
+            "1e300",
+            "big",
+            "-2.5e-10",
+            "small",
+            "1e16",
+            "whole",
+            "0.1",
+            "tenth",
+        ])
+        .await;
+    for (member, score) in [
+        ("big", "1e+300"),
+        ("small", "-2.5e-10"),
+        ("whole", "10000000000000000"),
+        ("tenth", "0.1"),
+    ] {
+        assert_eq!(
+            session.call(&["zscore", "zsets:exp", member]).await,
+            bulk(score),
+            "{}",
+            member
+        );
+    }
+    assert_eq!(
+        session
+            .call(&["zincrby", "zsets:exp", "1e300", "big"])
+            .await,
+        bulk("2e+300")
+    );
+    session.call(&["hello", "3"]).await;
+    assert_eq!(
+        session.call(&["zscore", "zsets:exp", "big"]).await,
+        b",2e+300\r\n"
+    );
+}
+
+#[tokio::test]
+async fn zmpop_pops_scores_from_the_first_non_empty_set() {
+    let mut session = Session::new();
+    session
+        .call(&["zadd", "zsets:mpop:b", "1", "one", "2", "two", "3", "three"])
+        .await;
+    assert_eq!(
+        session
+            .call(&["zmpop", "2", "zsets:mpop:a", "zsets:mpop:b", "MAX"])
+            .await,
+        b"*2\r\n$12\r\nzsets:mpop:b\r\n*1\r\n*2\r\n$5\r\nthree\r\n$1\r\n3\r\n"
+    );
+    assert_eq!(
+        session
+            .call(&["zmpop", "1", "zsets:mpop:b", "MIN", "COUNT", "10"])
+            .await,
+        b"*2\r\n$12\r\nzsets:mpop:b\r\n*2\r\n*2\r\n$3\r\none\r\n$1\r\n1\r\n*2\r\n$3\r\ntwo\r\n$1\r\n2\r\n"
+    );
+    assert_eq!(session.call(&["type", "zsets:mpop:b"]).await, b"+none\r\n");
+    assert_eq!(
+        session
+            .call(&["zmpop", "2", "zsets:mpop:a", "zsets:mpop:b", "MIN"])
+            .await,
+        b"*-1\r\n"
+    );
+    assert_eq!(
+        session.call(&["zmpop", "1", "zsets:mpop:a", "LEFT"]).await,
+        b"-ERR syntax error\r\n"
+    );
+}
+
+#[tokio::test]
+async fn zadd_gt_lt_only_move_scores_one_way_and_ch_counts_changes() {
+    let mut session = Session::new();
+    session
+        .call(&["zadd", "zsets:gt", "5", "a", "5", "b"])
+        .await;
+
+    // GT raises a, leaves b, and still adds c
+    assert_eq!(
+        session
+            .call(&["zadd", "zsets:gt", "GT", "CH", "7", "a", "3", "b", "1", "c"])
+            .await,
+        b":2\r\n"
+    );
+    assert_eq!(session.call(&["zscore", "zsets:gt", "a"]).await, bulk("7"));
+    assert_eq!(session.call(&["zscore", "zsets:gt", "b"]).await, bulk("5"));
+    assert_eq!(session.call(&["zscore", "zsets:gt", "c"]).await, bulk("1"));
+
+    assert_eq!(
+        session
+            .call(&["zadd", "zsets:gt", "LT", "CH", "8", "a", "3", "b"])
+            .await,
+        b":1\r\n"
+    );
+    assert_eq!(session.call(&["zscore", "zsets:gt", "a"]).await, bulk("7"));
+    assert_eq!(session.call(&["zscore", "zsets:gt", "b"]).await, bulk("3"));
+
+    // Without CH only new members count, and an unchanged score isn't a change
+    assert_eq!(
+        session
+            .call(&["zadd", "zsets:gt", "9", "a", "2", "d"])
+            .await,
+        b":1\r\n"
+    );
+    assert_eq!(
+        session.call(&["zadd", "zsets:gt", "CH", "9", "a"]).await,
+        b":0\r\n"
+    );
+
+    // NX only adds, XX only updates
+    assert_eq!(
+        session
+            .call(&["zadd", "zsets:gt", "NX", "0", "a", "4", "e"])
+            .await,
+        b":1\r\n"
+    );
+    assert_eq!(session.call(&["zscore", "zsets:gt", "a"]).await, bulk("9"));
+    assert_eq!(
+        session
+            .call(&["zadd", "zsets:gt", "XX", "CH", "1", "a", "4", "f"])
+            .await,
+        b":1\r\n"
+    );
+    assert_eq!(session.call(&["zscore", "zsets:gt", "f"]).await, b"$-1\r\n");
+    assert_eq!(
+        session
+            .call(&["zadd", "zsets:nothing", "XX", "1", "a"])
+            .await,
+        b":0\r\n"
+    );
+    assert_eq!(session.call(&["type", "zsets:nothing"]).await, b"+none\r\n");
+}
+
+#[tokio::test]
+async fn zadd_incr_replies_with_the_new_score_or_nil() {
+    let mut session = Session::new();
+    assert_eq!(
+        session
+            .call(&["zadd", "zsets:incr", "INCR", "2.5", "a"])
+            .await,
+        bulk("2.5")
+    );
+    assert_eq!(
+        session
+            .call(&["zadd", "zsets:incr", "INCR", "-1", "a"])
+            .await,
+        bulk("1.5")
+    );
+    assert_eq!(
+        session
+            .call(&["zadd", "zsets:incr", "NX", "INCR", "1", "a"])
+            .await,
+        b"$-1\r\n"
+    );
+    assert_eq!(
+        session
+            .call(&["zadd", "zsets:incr", "XX", "INCR", "1", "b"])
+            .await,
+        b"$-1\r\n"
+    );
+    assert_eq!(
+        session
+            .call(&["zadd", "zsets:incr", "GT", "INCR", "-1", "a"])
+            .await,
+        b"$-1\r\n"
+    );
+    assert_eq!(
+        session.call(&["zscore", "zsets:incr", "a"]).await,
+        bulk("1.5")
+    );
+
+    for (options, error) in [
+        (
+            &["NX", "XX"][..],
+            "ERR XX and NX options at the same time are not compatible",
+        ),
+        (
+            &["NX", "GT"],
+            "ERR GT, LT, and/or NX options at the same time are not compatible",
+        ),
+        (
+            &["GT", "LT"],
+            "ERR GT, LT, and/or NX options at the same time are not compatible",
+        ),
+    ] {
+        let mut command = vec!["zadd", "zsets:incr"];
+        command.extend_from_slice(options);
+        command.extend_from_slice(&["1", "a"]);
+        assert_eq!(
+            session.call(&command).await,
+            format!("-{}\r\n", error).into_bytes()
+        );
+    }
+    assert_eq!(
+        session
+            .call(&["zadd", "zsets:incr", "INCR", "1", "a", "2", "b"])
+            .await,
+        b"-ERR INCR option supports a single increment-element pair\r\n"
+    );
+}
+
+#[tokio::test]
+async fn scores_take_decimals_exponents_and_infinities() {
+    let mut session = Session::new();
+    assert_eq!(
+        session
+            .call(&[
+                "zadd",
+                "zsets:fmt",
+                "3.0e3",
+                "exp",
+                "+inf",
+                "top",
+                "-inf",
+                "bottom",
+                "0.25",
+                "frac",
+                "7",
+                "int",
+            ])
+            .await,
+        b":5\r\n"
+    );
+    assert_eq!(
+        session.call(&["zscore", "zsets:fmt", "exp"]).await,
+        bulk("3000")
+    );
+    assert_eq!(
+        session.call(&["zscore", "zsets:fmt", "top"]).await,
+        bulk("inf")
+    );
+    assert_eq!(
+        session.call(&["zscore", "zsets:fmt", "frac"]).await,
+        bulk("0.25")
+    );
+
+    // The infinities sit at either end of the order
+    let mut all = b"*5\r\n".to_vec();
+    for member in ["bottom", "frac", "int", "exp", "top"] {
+        all.extend(bulk(member));
+    }
+    assert_eq!(
+        session
+            .call(&["zrangebyscore", "zsets:fmt", "-inf", "+inf"])
+            .await,
+        all
+    );
+    let mut finite = b"*3\r\n".to_vec();
+    for member in ["frac", "int", "exp"] {
+        finite.extend(bulk(member));
+    }
+    assert_eq!(
+        session
+            .call(&["zrangebyscore", "zsets:fmt", "(-inf", "(inf"])
+            .await,
+        finite
+    );
+    assert_eq!(
+        session
+            .call(&["zrangebyscore", "zsets:fmt", "1e1", "5E3"])
+            .await,
+        [&b"*1\r\n"[..], &bulk("exp")].concat()
+    );
+
+    for score in ["nan", "", "1.5.1"] {
+        assert_eq!(
+            session.call(&["zadd", "zsets:fmt", score, "bad"]).await,
+            b"-ERR value is not a valid float\r\n",
+            "{:?}",
+            score
+        );
+        assert_eq!(
+            session.call(&["zincrby", "zsets:fmt", score, "int"]).await,
+            b"-ERR value is not a valid float\r\n"
+        );
+    }
+    assert_eq!(
+        session
+            .call(&["zrangebyscore", "zsets:fmt", "nan", "1"])
+            .await,
+        b"-ERR min or max is not a float\r\n"
+    );
+    assert_eq!(
+        session
+            .call(&["zincrby", "zsets:fmt", "2.5e-1", "int"])
+            .await,
+        bulk("7.25")
+    );
+}
+
+#[tokio::test]
+async fn zincrby_refuses_nan_and_zmscore_keeps_argument_order() {
+    let mut session = Session::new();
+    assert_eq!(
+        session.call(&["zincrby", "zsets:incrby", "2", "a"]).await,
+        bulk("2")
+    );
+    assert_eq!(
+        session
+            .call(&["zincrby", "zsets:incrby", "-0.5", "a"])
+            .await,
+        bulk("1.5")
+    );
+    session.call(&["zincrby", "zsets:incrby", "1", "b"]).await;
+    // Re-sorted by the new score
+    assert_eq!(
+        session
+            .call(&["zrangebyscore", "zsets:incrby", "-inf", "+inf"])
+            .await,
+        [&b"*2\r\n"[..], &bulk("b"), &bulk("a")].concat()
+    );
+
+    session.call(&["zincrby", "zsets:incrby", "inf", "c"]).await;
+    assert_eq!(
+        session
+            .call(&["zincrby", "zsets:incrby", "-inf", "c"])
+            .await,
+        b"-ERR resulting score is not a number (NaN)\r\n"
+    );
+    assert_eq!(
+        session.call(&["zscore", "zsets:incrby", "c"]).await,
+        bulk("inf")
+    );
+
+    assert_eq!(
+        session
+            .call(&["zmscore", "zsets:incrby", "c", "missing", "a", "a"])
+            .await,
+        [
+            &b"*4\r\n"[..],
+            &bulk("inf"),
+            b"$-1\r\n",
+            &bulk("1.5"),
+            &bulk("1.5"),
+        ]
+        .concat()
+    );
+    assert_eq!(
+        session.call(&["zmscore", "zsets:none", "a", "b"]).await,
+        b"*2\r\n$-1\r\n$-1\r\n"
+    );
+
+    session.call(&["set", "zsets:string", "v"]).await;
+    let wrongtype = b"-WRONGTYPE Operation against a key holding the wrong kind of value\r\n";
+    assert_eq!(
+        session.call(&["zincrby", "zsets:string", "1", "a"]).await,
+        wrongtype
+    );
+    assert_eq!(
+        session.call(&["zmscore", "zsets:string", "a"]).await,
+        wrongtype
+    );
+}
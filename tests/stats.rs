@@ -0,0 +1,95 @@
+//! The INFO stats counters and CONFIG RESETSTAT, in a process of their own so other
+//! tests' commands don't count
+mod common;
+
+use codecrafters_redis::resp::Frame;
+use common::{bulk, info_field, ok, Server};
+use std::time::Duration;
+
+#[test]
+fn published_messages_and_subscriptions_are_counted() {
+    let server = Server::start(&[]);
+    let mut subscriber = server.client();
+    let mut client = server.client();
+    assert_eq!(info_field(&mut client, "stats", "pubsub_channels"), "0");
+    assert_eq!(
+        info_field(&mut client, "stats", "total_pub_sub_messages"),
+        "0"
+    );
+
+    subscriber.call(&["subscribe", "news"]);
+    subscriber.call(&["psubscribe", "n*"]);
+    assert_eq!(info_field(&mut client, "stats", "pubsub_channels"), "1");
+    assert_eq!(info_field(&mut client, "stats", "pubsub_patterns"), "1");
+
+    // Every delivery counts, the channel's and the pattern's
+    assert_eq!(client.call(&["publish", "news", "hi"]), Frame::Integer(2));
+    assert_eq!(
+        subscriber.read(),
+        Frame::Array(Some(vec![bulk("message"), bulk("news"), bulk("hi")]))
+    );
+    assert_eq!(
+        subscriber.read(),
+        Frame::Array(Some(vec![
+            bulk("pmessage"),
+            bulk("n*"),
+            bulk("news"),
+            bulk("hi")
+        ]))
+    );
+    assert_eq!(
+        info_field(&mut client, "stats", "total_pub_sub_messages"),
+        "2"
+    );
+    // A message nobody receives doesn't
+    assert_eq!(client.call(&["publish", "other", "hi"]), Frame::Integer(0));
+    assert_eq!(
+        info_field(&mut client, "stats", "total_pub_sub_messages"),
+        "2"
+    );
+
+    subscriber.call(&["unsubscribe", "news"]);
+    assert_eq!(info_field(&mut client, "stats", "pubsub_channels"), "0");
+    assert_eq!(info_field(&mut client, "stats", "pubsub_patterns"), "1");
+}
+
+#[test]
+fn counters_follow_commands_and_resetstat_clears_them() {
+    let server = Server::start(&[]);
+    let mut client = server.client();
+    let stat = |client: &mut common::Client, name: &str| -> u64 {
+        info_field(client, "stats", name).parse().unwrap()
+    };
+    assert_eq!(stat(&mut client, "keyspace_misses"), 0);
+
+    client.call(&["get", "missing"]);
+    assert_eq!(stat(&mut client, "keyspace_misses"), 1);
+    assert_eq!(stat(&mut client, "keyspace_hits"), 0);
+    client.call(&["set", "present", "1"]);
+    client.call(&["get", "present"]);
+    assert_eq!(stat(&mut client, "keyspace_hits"), 1);
+
+    // A key found expired when read is counted as expired and as a miss
+    client.call(&["set", "short", "1", "PX", "1"]);
+    std::thread::sleep(Duration::from_millis(20));
+    client.call(&["get", "short"]);
+    assert_eq!(stat(&mut client, "expired_keys"), 1);
+    assert_eq!(stat(&mut client, "keyspace_misses"), 2);
+
+    let _other = server.client();
+    assert!(stat(&mut client, "total_connections_received") >= 2);
+    let processed = stat(&mut client, "total_commands_processed");
+    assert!(processed >= 10, "only {} commands counted", processed);
+
+    assert_eq!(client.call(&["config", "resetstat"]), ok());
+    for name in [
+        "keyspace_hits",
+        "keyspace_misses",
+        "expired_keys",
+        "total_connections_received",
+    ] {
+        assert_eq!(stat(&mut client, name), 0, "{}", name);
+    }
+    // Commands are counted as they start, so the five INFOs since include this one
+    assert_eq!(stat(&mut client, "total_commands_processed"), 5);
+}
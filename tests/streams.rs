@@ -0,0 +1,195 @@
+//! XADD, XLEN, XRANGE and XINFO through a socketless session, and streams through DUMP/RESTORE
+use codecrafters_redis::resp::parser::FrameParser;
+use codecrafters_redis::resp::Frame;
+use codecrafters_redis::Session;
+
+fn frame(reply: &[u8]) -> Frame {
+    let mut parser = FrameParser::new();
+    parser.feed(reply);
+    parser.parse().unwrap().expect("a whole reply")
+}
+
+fn bulk(reply: &[u8]) -> Vec<u8> {
+    match frame(reply) {
+        Frame::BulkString(Some(bytes)) => bytes,
+        other => panic!("expected a bulk string, got {:?}", other),
+    }
+}
+
+fn parse_id(id: &[u8]) -> (u64, u64) {
+    let id = std::str::from_utf8(id).unwrap();
+    let (ms, seq) = id.split_once('-').unwrap();
+    (ms.parse().unwrap(), seq.parse().unwrap())
+}
+
+/// The IDs of the entries in an XRANGE reply
+fn ids(reply: &[u8]) -> Vec<String> {
+    let Frame::Array(Some(entries)) = frame(reply) else {
+        panic!("expected an array");
+    };
+    entries
+        .into_iter()
+        .map(|entry| match entry {
+            Frame::Array(Some(parts)) => match &parts[0] {
+                Frame::BulkString(Some(id)) => String::from_utf8(id.clone()).unwrap(),
+                other => panic!("expected an ID, got {:?}", other),
+            },
+            other => panic!("expected an entry, got {:?}", other),
+        })
+        .collect()
+}
+
+#[tokio::test]
+async fn generated_ids_keep_increasing() {
+    let mut session = Session::new();
+    let mut last = (0, 0);
+    for i in 0..200 {
+        let id = bulk(
+            &session
+                .call(&["xadd", "streams:auto", "*", "n", &i.to_string()])
+                .await,
+        );
+        let id = parse_id(&id);
+        assert!(id > last, "{:?} after {:?}", id, last);
+        last = id;
+    }
+    // One past the last entry's time, even when it is in the future
+    session
+        .call(&["xadd", "streams:future", "99999999999999-5", "f", "v"])
+        .await;
+    let id = bulk(
+        &session
+            .call(&["xadd", "streams:future", "*", "f", "v"])
+            .await,
+    );
+    assert_eq!(id, b"99999999999999-6");
+    let id = bulk(
+        &session
+            .call(&["xadd", "streams:future", "99999999999999-*", "f", "v"])
+            .await,
+    );
+    assert_eq!(id, b"99999999999999-7");
+    assert_eq!(
+        session
+            .call(&["xadd", "streams:future", "5-1", "f", "v"])
+            .await,
+        b"-ERR The ID specified in XADD is equal or smaller than the target stream top item\r\n"
+    );
+    assert_eq!(session.call(&["xlen", "streams:future"]).await, b":3\r\n");
+    assert_eq!(session.call(&["xlen", "streams:missing"]).await, b":0\r\n");
+}
+
+#[tokio::test]
+async fn xrange_slices_by_id_and_count() {
+    let mut session = Session::new();
+    for id in ["1-1", "1-2", "2-0", "3-5", "10-0"] {
+        session.call(&["xadd", "streams:range", id, "f", id]).await;
+    }
+    let range = |start: &'static str, end: &'static str| ["xrange", "streams:range", start, end];
+    let mut xrange = async |args: &[&str]| ids(&session.call(args).await);
+    assert_eq!(
+        xrange(&range("-", "+")).await,
+        ["1-1", "1-2", "2-0", "3-5", "10-0"]
+    );
+    // An ID without a sequence covers the whole millisecond
+    assert_eq!(xrange(&range("1", "2")).await, ["1-1", "1-2", "2-0"]);
+    assert_eq!(xrange(&range("1-2", "3-5")).await, ["1-2", "2-0", "3-5"]);
+    assert_eq!(xrange(&range("4", "9")).await, Vec::<String>::new());
+    assert_eq!(xrange(&range("5", "1")).await, Vec::<String>::new());
+    assert_eq!(
+        xrange(&["xrange", "streams:range", "-", "+", "COUNT", "2"]).await,
+        ["1-1", "1-2"]
+    );
+    assert_eq!(
+        xrange(&["xrange", "streams:range", "2", "+", "COUNT", "0"]).await,
+        Vec::<String>::new()
+    );
+
+    // Entries carry their fields and values in order
+    assert_eq!(
+        frame(
+            &session
+                .call(&["xrange", "streams:range", "3-5", "3-5"])
+                .await
+        ),
+        Frame::Array(Some(vec![Frame::Array(Some(vec![
+            Frame::BulkString(Some(b"3-5".to_vec())),
+            Frame::Array(Some(vec![
+                Frame::BulkString(Some(b"f".to_vec())),
+                Frame::BulkString(Some(b"3-5".to_vec())),
+            ])),
+        ]))]))
+    );
+}
+
+#[tokio::test]
+async fn streams_survive_dump_and_restore() {
+    let mut session = Session::new();
+    for i in 0..150 {
+        let fields: &[&str] = if i % 2 == 0 {
+            &["a", "1", "b", "2"]
+        } else {
+            &["only", "x"]
+        };
+        let id = format!("{}-{}", 1000 + i / 4, i % 4);
+        let mut command = vec!["xadd", "streams:dump", &id];
+        command.extend_from_slice(fields);
+        session.call(&command).await;
+    }
+    session
+        .call(&["xadd", "streams:dump", "2000-0", "last", "one"])
+        .await;
+    let before = session.call(&["xrange", "streams:dump", "-", "+"]).await;
+
+    let dump = bulk(&session.call(&["dump", "streams:dump"]).await);
+    // The type byte of Redis 7.2's stream listpacks
+    assert_eq!(dump[0], 21);
+    assert_eq!(
+        session
+            .call(&[&b"restore"[..], b"streams:copy", b"0", &dump])
+            .await,
+        b"+OK\r\n"
+    );
+    assert_eq!(
+        session.call(&["xrange", "streams:copy", "-", "+"]).await,
+        before
+    );
+    assert_eq!(session.call(&["xlen", "streams:copy"]).await, b":151\r\n");
+    // The last ID comes along, so new entries still go after it
+    let id = bulk(
+        &session
+            .call(&["xadd", "streams:copy", "2000-*", "f", "v"])
+            .await,
+    );
+    assert_eq!(id, b"2000-1");
+}
+
+#[tokio::test]
+async fn xinfo_stream_reports_the_length_and_both_ends() {
+    let mut session = Session::new();
+    session
+        .call(&["xadd", "stream:info", "1-1", "a", "1"])
+        .await;
+    session
+        .call(&["xadd", "stream:info", "2-5", "b", "2"])
+        .await;
+    assert_eq!(session.call(&["type", "stream:info"]).await, b"+stream\r\n");
+    assert_eq!(
+        session.call(&["object", "encoding", "stream:info"]).await,
+        b"$6\r\nstream\r\n"
+    );
+    assert_eq!(
+        session.call(&["xinfo", "stream", "stream:info"]).await,
+        [
+            &b"*8\r\n$6\r\nlength\r\n:2\r\n"[..],
+            b"$17\r\nlast-generated-id\r\n$3\r\n2-5\r\n",
+            b"$11\r\nfirst-entry\r\n*2\r\n$3\r\n1-1\r\n*2\r\n$1\r\na\r\n$1\r\n1\r\n",
+            b"$10\r\nlast-entry\r\n*2\r\n$3\r\n2-5\r\n*2\r\n$1\r\nb\r\n$1\r\n2\r\n",
+        ]
+        .concat()
+    );
+    assert_eq!(
+        session.call(&["xinfo", "stream", "stream:missing"]).await,
+        b"-ERR no such key\r\n"
+    );
+}
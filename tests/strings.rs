@@ -0,0 +1,545 @@
+//! String commands run through a socketless session, and through a server process
+//! where a setting is changed
+mod common;
+
+use codecrafters_redis::resp::Frame;
+use codecrafters_redis::Session;
+
+#[tokio::test]
+async fn get_replies_with_a_bulk_string_for_int_encoded_values() {
+    let mut session = Session::new();
+    assert_eq!(session.call(&["set", "str:int", "123"]).await, b"+OK\r\n");
+    assert_eq!(
+        session.call(&["object", "encoding", "str:int"]).await,
+        b"$3\r\nint\r\n"
+    );
+    assert_eq!(session.call(&["get", "str:int"]).await, b"$3\r\n123\r\n");
+
+    // INCR replies with an integer, but the value it leaves is still read as a string
+    assert_eq!(session.call(&["incr", "str:int"]).await, b":124\r\n");
+    assert_eq!(session.call(&["get", "str:int"]).await, b"$3\r\n124\r\n");
+
+    // As is one restored from an int-encoded DUMP payload
+    let dump = session.call(&["dump", "str:int"]).await;
+    let payload = &dump[dump.iter().position(|&b| b == b'\n').unwrap() + 1..dump.len() - 2];
+    assert_eq!(
+        session
+            .call(&[&b"restore"[..], b"str:restored", b"0", payload])
+            .await,
+        b"+OK\r\n"
+    );
+    assert_eq!(
+        session.call(&["get", "str:restored"]).await,
+        b"$3\r\n124\r\n"
+    );
+}
+
+#[tokio::test]
+async fn string_encodings_follow_the_value_and_how_it_was_written() {
+    let mut session = Session::new();
+    let long = "x".repeat(45);
+    for (value, encoding) in [
+        ("12345", "int"),
+        ("-12345", "int"),
+        ("9223372036854775807", "int"),
+        // Not a canonical i64
+        ("9223372036854775808", "embstr"),
+        ("007", "embstr"),
+        ("+1", "embstr"),
+        ("1 ", "embstr"),
+        ("abcdefghij", "embstr"),
+        (&long[..44], "embstr"),
+        (&long, "raw"),
+    ] {
+        session.call(&["set", "str:enc", value]).await;
+        assert_eq!(
+            session.call(&["object", "encoding", "str:enc"]).await,
+            format!("${}\r\n{}\r\n", encoding.len(), encoding).into_bytes(),
+            "{}",
+            value
+        );
+    }
+
+    // Strings changed in place are raw however short they are
+    session.call(&["set", "str:enc", "ab"]).await;
+    session.call(&["append", "str:enc", "c"]).await;
+    assert_eq!(
+        session.call(&["object", "encoding", "str:enc"]).await,
+        b"$3\r\nraw\r\n"
+    );
+    session.call(&["set", "str:enc", "99"]).await;
+    session.call(&["setrange", "str:enc", "0", "1"]).await;
+    assert_eq!(
+        session.call(&["object", "encoding", "str:enc"]).await,
+        b"$3\r\nraw\r\n"
+    );
+}
+
+#[tokio::test]
+async fn substr_is_getrange_by_its_old_name() {
+    let mut session = Session::new();
+    session.call(&["set", "str:substr", "Hello, world"]).await;
+    for (start, end, expected) in [
+        ("0", "4", &b"$5\r\nHello\r\n"[..]),
+        ("-5", "-1", b"$5\r\nworld\r\n"),
+        ("7", "100", b"$5\r\nworld\r\n"),
+        ("5", "2", b"$0\r\n\r\n"),
+    ] {
+        assert_eq!(
+            session.call(&["substr", "str:substr", start, end]).await,
+            expected
+        );
+        assert_eq!(
+            session.call(&["getrange", "str:substr", start, end]).await,
+            expected
+        );
+    }
+    assert_eq!(
+        session.call(&["substr", "str:missing", "0", "-1"]).await,
+        b"$0\r\n\r\n"
+    );
+    assert_eq!(
+        session.call(&["substr", "str:substr", "0"]).await,
+        b"-ERR wrong number of arguments for 'substr'\r\n"
+    );
+}
+
+#[tokio::test]
+async fn set_nx_and_xx_treat_an_expired_key_as_missing() {
+    let mut session = Session::new();
+    for key in ["strings:nx", "strings:xx", "strings:keepttl"] {
+        assert_eq!(
+            session.call(&["set", key, "old", "PX", "1"]).await,
+            b"+OK\r\n"
+        );
+    }
+    tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+
+    assert_eq!(
+        session.call(&["set", "strings:nx", "new", "NX"]).await,
+        b"+OK\r\n"
+    );
+    assert_eq!(session.call(&["get", "strings:nx"]).await, b"$3\r\nnew\r\n");
+    assert_eq!(
+        session.call(&["expiretime", "strings:nx"]).await,
+        b":-1\r\n"
+    );
+
+    assert_eq!(
+        session.call(&["set", "strings:xx", "new", "XX"]).await,
+        b"$-1\r\n"
+    );
+    assert_eq!(
+        session
+            .call(&["set", "strings:keepttl", "new", "XX", "KEEPTTL"])
+            .await,
+        b"$-1\r\n"
+    );
+    for key in ["strings:xx", "strings:keepttl"] {
+        assert_eq!(session.call(&["type", key]).await, b"+none\r\n");
+    }
+}
+
+#[tokio::test]
+async fn mget_replies_nil_for_anything_but_a_live_string() {
+    let mut session = Session::new();
+    session.call(&["set", "str:mget:a", "1"]).await;
+    session.call(&["rpush", "str:mget:list", "x"]).await;
+    session
+        .call(&["set", "str:mget:gone", "v", "PX", "1"])
+        .await;
+    session.call(&["set", "str:mget:b", "two"]).await;
+    tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+    assert_eq!(
+        session
+            .call(&[
+                "mget",
+                "str:mget:a",
+                "str:mget:list",
+                "str:mget:missing",
+                "str:mget:gone",
+                "str:mget:b",
+            ])
+            .await,
+        b"*5\r\n$1\r\n1\r\n$-1\r\n$-1\r\n$-1\r\n$3\r\ntwo\r\n"
+    );
+    assert_eq!(session.call(&["type", "str:mget:list"]).await, b"+list\r\n");
+}
+
+#[tokio::test]
+async fn setrange_past_the_bulk_limit_fails_before_allocating() {
+    let mut session = Session::new();
+    let too_big = b"-ERR string exceeds maximum allowed size (proto-max-bulk-len)\r\n";
+    session.call(&["set", "str:huge", "v"]).await;
+    // 512mb by default, so the byte just past it
+    for offset in ["536870912", "9223372036854775807"] {
+        assert_eq!(
+            session.call(&["setrange", "str:huge", offset, "x"]).await,
+            too_big,
+            "{}",
+            offset
+        );
+        assert_eq!(
+            session
+                .call(&["setrange", "str:huge:new", offset, "x"])
+                .await,
+            too_big,
+            "{}",
+            offset
+        );
+    }
+    assert_eq!(
+        session.call(&["setrange", "str:huge", "-1", "x"]).await,
+        b"-ERR offset is out of range\r\n"
+    );
+    assert_eq!(session.call(&["get", "str:huge"]).await, b"$1\r\nv\r\n");
+    assert_eq!(session.call(&["type", "str:huge:new"]).await, b"+none\r\n");
+}
+
+#[test]
+fn append_refuses_to_grow_a_string_past_proto_max_bulk_len() {
+    let server = common::Server::start(&[]);
+    let mut client = server.client();
+    assert_eq!(
+        client.call(&["config", "set", "proto-max-bulk-len", "10"]),
+        common::ok()
+    );
+    assert_eq!(
+        client.call(&["append", "s", "0123456789"]),
+        Frame::Integer(10)
+    );
+    assert_eq!(
+        client.call(&["append", "s", "x"]),
+        Frame::Error("ERR string exceeds maximum allowed size (proto-max-bulk-len)".into())
+    );
+    assert!(matches!(
+        client.call(&["setrange", "s", "9", "ab"]),
+        Frame::Error(e) if e.starts_with("ERR string exceeds maximum allowed size")
+    ));
+    assert_eq!(
+        client.call(&["setrange", "s", "8", "ab"]),
+        Frame::Integer(10)
+    );
+    assert_eq!(client.call(&["get", "s"]), common::bulk("01234567ab"));
+}
+
+#[tokio::test]
+async fn int_encoded_values_are_read_and_changed_as_their_digits() {
+    let mut session = Session::new();
+    session.call(&["set", "str:digits", "100"]).await;
+    assert_eq!(
+        session.call(&["object", "encoding", "str:digits"]).await,
+        b"$3\r\nint\r\n"
+    );
+    assert_eq!(session.call(&["strlen", "str:digits"]).await, b":3\r\n");
+    assert_eq!(
+        session.call(&["getrange", "str:digits", "1", "-1"]).await,
+        b"$2\r\n00\r\n"
+    );
+    assert_eq!(
+        session.call(&["append", "str:digits", "x"]).await,
+        b":4\r\n"
+    );
+    assert_eq!(
+        session.call(&["get", "str:digits"]).await,
+        b"$4\r\n100x\r\n"
+    );
+    assert_eq!(
+        session.call(&["object", "encoding", "str:digits"]).await,
+        b"$3\r\nraw\r\n"
+    );
+
+    session.call(&["set", "str:digits", "-42"]).await;
+    assert_eq!(
+        session.call(&["setrange", "str:digits", "1", "7"]).await,
+        b":3\r\n"
+    );
+    assert_eq!(session.call(&["get", "str:digits"]).await, b"$3\r\n-72\r\n");
+    // Still a number, for INCR, but no longer int-encoded
+    assert_eq!(
+        session.call(&["object", "encoding", "str:digits"]).await,
+        b"$3\r\nraw\r\n"
+    );
+    assert_eq!(session.call(&["incr", "str:digits"]).await, b":-71\r\n");
+}
+
+#[tokio::test]
+async fn incrbyfloat_writes_sums_as_redis_does() {
+    let mut session = Session::new();
+    session.call(&["set", "str:float", "10.50"]).await;
+    assert_eq!(
+        session.call(&["incrbyfloat", "str:float", "0.1"]).await,
+        b"$4\r\n10.6\r\n"
+    );
+    assert_eq!(session.call(&["get", "str:float"]).await, b"$4\r\n10.6\r\n");
+    assert_eq!(
+        session.call(&["incrbyfloat", "str:float", "2.0e2"]).await,
+        b"$5\r\n210.6\r\n"
+    );
+    assert_eq!(
+        session.call(&["incrbyfloat", "str:float:new", "-5"]).await,
+        b"$2\r\n-5\r\n"
+    );
+    // In fixed point, never with an exponent, and to 17 decimals at most
+    assert_eq!(
+        session
+            .call(&["incrbyfloat", "str:float:big", "1e20"])
+            .await,
+        b"$21\r\n100000000000000000000\r\n"
+    );
+    assert_eq!(
+        session
+            .call(&["incrbyfloat", "str:float:tiny", "-1e-20"])
+            .await,
+        b"$1\r\n0\r\n"
+    );
+    assert_eq!(
+        session.call(&["incrbyfloat", "str:float", "inf"]).await,
+        b"-ERR increment would produce NaN or Infinity\r\n"
+    );
+    session.call(&["set", "str:float:text", "abc"]).await;
+    assert_eq!(
+        session.call(&["incrbyfloat", "str:float:text", "1"]).await,
+        b"-ERR value is not a valid float\r\n"
+    );
+}
+
+#[tokio::test]
+async fn setbit_grows_the_string_and_getbit_reads_zero_past_it() {
+    let mut session = Session::new();
+    assert_eq!(
+        session.call(&["setbit", "str:bits", "100", "1"]).await,
+        b":0\r\n"
+    );
+    // Bit 100 is in the 13th byte, and everything before it is zeroed
+    assert_eq!(session.call(&["strlen", "str:bits"]).await, b":13\r\n");
+    assert_eq!(
+        session.call(&["getrange", "str:bits", "0", "-1"]).await,
+        b"$13\r\n\0\0\0\0\0\0\0\0\0\0\0\0\x08\r\n"
+    );
+    assert_eq!(
+        session.call(&["getbit", "str:bits", "100"]).await,
+        b":1\r\n"
+    );
+    assert_eq!(session.call(&["getbit", "str:bits", "99"]).await, b":0\r\n");
+    assert_eq!(
+        session.call(&["getbit", "str:bits", "10000"]).await,
+        b":0\r\n"
+    );
+    assert_eq!(
+        session.call(&["setbit", "str:bits", "100", "0"]).await,
+        b":1\r\n"
+    );
+    assert_eq!(
+        session.call(&["getbit", "str:missing", "0"]).await,
+        b":0\r\n"
+    );
+
+    // Offsets are bounded by proto-max-bulk-len, 512MB or 2^32 bits
+    let out_of_range = b"-ERR bit offset is not an integer or out of range\r\n";
+    for offset in ["4294967296", "-1", "x"] {
+        assert_eq!(
+            session.call(&["setbit", "str:bits", offset, "1"]).await,
+            out_of_range
+        );
+    }
+    assert_eq!(
+        session.call(&["setbit", "str:bits", "0", "2"]).await,
+        b"-ERR bit is not an integer or out of range\r\n"
+    );
+    assert_eq!(session.call(&["strlen", "str:bits"]).await, b":13\r\n");
+
+    session.call(&["rpush", "str:list", "a"]).await;
+    let wrongtype = b"-WRONGTYPE Operation against a key holding the wrong kind of value\r\n";
+    assert_eq!(
+        session.call(&["setbit", "str:list", "0", "1"]).await,
+        wrongtype
+    );
+    assert_eq!(session.call(&["getbit", "str:list", "0"]).await, wrongtype);
+    assert_eq!(session.call(&["bitcount", "str:list"]).await, wrongtype);
+}
+
+#[tokio::test]
+async fn bitcount_counts_over_byte_or_bit_ranges() {
+    let mut session = Session::new();
+    session.call(&["set", "str:foobar", "foobar"]).await;
+    for (range, count) in [
+        (&[][..], ":26\r\n"),
+        (&["0", "0"], ":4\r\n"),
+        (&["1", "1"], ":6\r\n"),
+        (&["1", "1", "BYTE"], ":6\r\n"),
+        (&["-2", "-1"], ":7\r\n"),
+        (&["5", "30", "BIT"], ":17\r\n"),
+        (&["2", "1"], ":0\r\n"),
+    ] {
+        let mut command = vec!["bitcount", "str:foobar"];
+        command.extend_from_slice(range);
+        assert_eq!(
+            session.call(&command).await,
+            count.as_bytes(),
+            "{:?}",
+            range
+        );
+    }
+    assert_eq!(session.call(&["bitcount", "str:missing"]).await, b":0\r\n");
+    assert_eq!(
+        session.call(&["bitcount", "str:foobar", "0"]).await,
+        b"-ERR syntax error\r\n"
+    );
+    assert_eq!(
+        session
+            .call(&["bitcount", "str:foobar", "0", "1", "WORD"])
+            .await,
+        b"-ERR syntax error\r\n"
+    );
+}
+
+#[tokio::test]
+async fn time_replies_with_seconds_and_microseconds() {
+    let mut session = Session::new();
+    let reply = String::from_utf8(session.call(&["time"]).await).unwrap();
+    let parts: Vec<&str> = reply.split("\r\n").collect();
+    assert_eq!((parts[0], parts.len()), ("*2", 6), "{}", reply);
+    let seconds: u64 = parts[2].parse().unwrap();
+    let micros: u64 = parts[4].parse().unwrap();
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+    assert!(
+        seconds.abs_diff(now) <= 1,
+        "{} is not about {}",
+        seconds,
+        now
+    );
+    assert!(micros < 1_000_000);
+    assert_eq!(
+        session.call(&["time", "now"]).await,
+        b"-ERR wrong number of arguments for 'time'\r\n"
+    );
+}
+
+#[tokio::test]
+async fn bitop_zero_extends_shorter_operands() {
+    let mut session = Session::new();
+    session
+        .call(&["set", "str:op:a", "\u{7f}\u{7f}\u{7f}"])
+        .await;
+    session.call(&["set", "str:op:b", "a"]).await;
+
+    // AND with the missing bytes of b taken as zeros
+    assert_eq!(
+        session
+            .call(&["bitop", "AND", "str:op:dest", "str:op:a", "str:op:b"])
+            .await,
+        b":3\r\n"
+    );
+    assert_eq!(
+        session.call(&["get", "str:op:dest"]).await,
+        b"$3\r\na\0\0\r\n"
+    );
+    session
+        .call(&["bitop", "OR", "str:op:dest", "str:op:b", "str:op:a"])
+        .await;
+    assert_eq!(
+        session.call(&["get", "str:op:dest"]).await,
+        b"$3\r\n\x7f\x7f\x7f\r\n"
+    );
+    session
+        .call(&[
+            "bitop",
+            "XOR",
+            "str:op:dest",
+            "str:op:a",
+            "str:op:b",
+            "str:op:missing",
+        ])
+        .await;
+    assert_eq!(
+        session.call(&["get", "str:op:dest"]).await,
+        b"$3\r\n\x1e\x7f\x7f\r\n"
+    );
+    assert_eq!(
+        session
+            .call(&["bitop", "NOT", "str:op:dest", "str:op:b"])
+            .await,
+        b":1\r\n"
+    );
+    assert_eq!(
+        session.call(&["get", "str:op:dest"]).await,
+        b"$1\r\n\x9e\r\n"
+    );
+
+    // An empty result deletes the destination, TTL and all
+    session.call(&["expire", "str:op:dest", "100"]).await;
+    assert_eq!(
+        session
+            .call(&["bitop", "AND", "str:op:dest", "str:op:missing"])
+            .await,
+        b":0\r\n"
+    );
+    assert_eq!(session.call(&["type", "str:op:dest"]).await, b"+none\r\n");
+
+    assert_eq!(
+        session
+            .call(&["bitop", "NOT", "str:op:dest", "str:op:a", "str:op:b"])
+            .await,
+        b"-ERR BITOP NOT must be called with a single source key.\r\n"
+    );
+    assert_eq!(
+        session
+            .call(&["bitop", "NAND", "str:op:dest", "str:op:a"])
+            .await,
+        b"-ERR syntax error\r\n"
+    );
+    session.call(&["sadd", "str:op:set", "m"]).await;
+    assert_eq!(
+        session
+            .call(&["bitop", "OR", "str:op:dest", "str:op:a", "str:op:set"])
+            .await,
+        b"-WRONGTYPE Operation against a key holding the wrong kind of value\r\n"
+    );
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn concurrent_read_modify_writes_are_atomic() {
+    let tasks: Vec<_> = (0..16)
+        .map(|_| {
+            tokio::spawn(async {
+                let mut session = Session::new();
+                for _ in 0..500 {
+                    session.call(&["incr", "str:racing"]).await;
+                }
+            })
+        })
+        .collect();
+    for task in tasks {
+        task.await.unwrap();
+    }
+    let mut session = Session::new();
+    assert_eq!(
+        session.call(&["get", "str:racing"]).await,
+        b"$4\r\n8000\r\n"
+    );
+
+    // Of many GETDELs racing for one value, exactly one gets it
+    for round in 0..50 {
+        let value = round.to_string();
+        session.call(&["set", "str:taken", &value]).await;
+        let tasks: Vec<_> = (0..8)
+            .map(|_| {
+                tokio::spawn(async {
+                    let mut session = Session::new();
+                    session.call(&["getdel", "str:taken"]).await
+                })
+            })
+            .collect();
+        let mut winners = 0;
+        for task in tasks {
+            if task.await.unwrap() != b"$-1\r\n" {
+                winners += 1;
+            }
+        }
+        assert_eq!(winners, 1, "round {}", round);
+    }
+}
@@ -0,0 +1,165 @@
+#![cfg(feature = "tls")]
+mod common;
+
+use common::{bulk, ok, Server};
+use std::net::TcpListener;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio_rustls::client::TlsStream;
+use tokio_rustls::rustls::crypto::ring;
+use tokio_rustls::rustls::pki_types::pem::PemObject;
+use tokio_rustls::rustls::pki_types::{CertificateDer, ServerName};
+use tokio_rustls::rustls::{ClientConfig, RootCertStore};
+use tokio_rustls::TlsConnector;
+
+fn fixture(name: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/fixtures/tls")
+        .join(name)
+}
+
+fn free_port() -> u16 {
+    TcpListener::bind("127.0.0.1:0")
+        .and_then(|listener| listener.local_addr())
+        .unwrap()
+        .port()
+}
+
+/// A client that trusts the fixture certificate, and nothing else
+fn connector() -> TlsConnector {
+    let mut roots = RootCertStore::empty();
+    roots
+        .add(CertificateDer::from_pem_file(fixture("cert.pem")).unwrap())
+        .unwrap();
+    let config = ClientConfig::builder_with_provider(Arc::new(ring::default_provider()))
+        .with_safe_default_protocol_versions()
+        .unwrap()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    TlsConnector::from(Arc::new(config))
+}
+
+/// Connect to the TLS port once it is listening, which can be just after the plain one
+async fn connect_tls(port: u16) -> TlsStream<TcpStream> {
+    let started = Instant::now();
+    let socket = loop {
+        match TcpStream::connect(("127.0.0.1", port)).await {
+            Ok(socket) => break socket,
+            Err(e) => {
+                assert!(started.elapsed() < Duration::from_secs(10), "{}", e);
+                tokio::time::sleep(Duration::from_millis(20)).await;
+            }
+        }
+    };
+    let name = ServerName::try_from("localhost").unwrap();
+    connector().connect(name, socket).await.unwrap()
+}
+
+async fn call(stream: &mut TlsStream<TcpStream>, command: &[u8], reply_len: usize) -> Vec<u8> {
+    stream.write_all(command).await.unwrap();
+    stream.flush().await.unwrap();
+    let mut reply = vec![0u8; reply_len];
+    tokio::time::timeout(Duration::from_secs(5), stream.read_exact(&mut reply))
+        .await
+        .expect("no reply in time")
+        .unwrap();
+    reply
+}
+
+#[tokio::test]
+async fn clients_are_served_over_tls_and_plain_tcp_at_once() {
+    let tls_port = free_port();
+    let cert = fixture("cert.pem");
+    let key = fixture("key.pem");
+    let server = Server::start(&[
+        "--tls-port",
+        &tls_port.to_string(),
+        "--tls-cert-file",
+        cert.to_str().unwrap(),
+        "--tls-key-file",
+        key.to_str().unwrap(),
+    ]);
+
+    let mut secure = connect_tls(tls_port).await;
+    assert_eq!(
+        call(&mut secure, b"*1\r\n$4\r\nPING\r\n", 7).await,
+        b"+PONG\r\n"
+    );
+    assert_eq!(
+        call(
+            &mut secure,
+            b"*3\r\n$3\r\nSET\r\n$1\r\nk\r\n$3\r\nsec\r\n",
+            5
+        )
+        .await,
+        b"+OK\r\n"
+    );
+
+    // Plain clients share the dataset
+    let mut plain = server.client();
+    assert_eq!(plain.call(&["get", "k"]), bulk("sec"));
+    assert_eq!(plain.call(&["set", "k", "plain"]), ok());
+    assert_eq!(
+        call(&mut secure, b"*2\r\n$3\r\nGET\r\n$1\r\nk\r\n", 11).await,
+        b"$5\r\nplain\r\n"
+    );
+    assert_eq!(
+        plain.call(&["config", "get", "tls-port"]),
+        codecrafters_redis::resp::Frame::Array(Some(vec![
+            bulk("tls-port"),
+            bulk(&tls_port.to_string())
+        ]))
+    );
+
+    // A plaintext client on the TLS port fails the handshake without taking the
+    // listener down
+    let mut wrong = TcpStream::connect(("127.0.0.1", tls_port)).await.unwrap();
+    wrong.write_all(b"*1\r\n$4\r\nPING\r\n").await.unwrap();
+    let mut rest = Vec::new();
+    let _ = tokio::time::timeout(Duration::from_secs(5), wrong.read_to_end(&mut rest)).await;
+    assert!(!rest.starts_with(b"+PONG"));
+    let mut again = connect_tls(tls_port).await;
+    assert_eq!(
+        call(&mut again, b"*1\r\n$4\r\nPING\r\n", 7).await,
+        b"+PONG\r\n"
+    );
+}
+
+#[tokio::test]
+async fn clients_stuck_in_the_handshake_take_no_slot_and_time_out() {
+    let tls_port = free_port();
+    let cert = fixture("cert.pem");
+    let key = fixture("key.pem");
+    let server = Server::start(&[
+        "--tls-port",
+        &tls_port.to_string(),
+        "--tls-cert-file",
+        cert.to_str().unwrap(),
+        "--tls-key-file",
+        key.to_str().unwrap(),
+    ]);
+    let mut plain = server.client();
+    assert_eq!(plain.call(&["config", "set", "maxclients", "2"]), ok());
+
+    // Connections that never start a handshake
+    let mut idle = Vec::new();
+    for _ in 0..3 {
+        idle.push(TcpStream::connect(("127.0.0.1", tls_port)).await.unwrap());
+    }
+    let mut secure = connect_tls(tls_port).await;
+    assert_eq!(
+        call(&mut secure, b"*1\r\n$4\r\nPING\r\n", 7).await,
+        b"+PONG\r\n"
+    );
+
+    for mut socket in idle {
+        let mut rest = Vec::new();
+        let read = tokio::time::timeout(Duration::from_secs(15), socket.read_to_end(&mut rest))
+            .await
+            .expect("the handshake didn't time out");
+        assert!(read.is_err() || rest.is_empty());
+    }
+}